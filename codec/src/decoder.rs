@@ -64,11 +64,31 @@ impl Context {
 
     /// Sends to the decoder a packet to be decoded.
     pub fn send_packet(&mut self, pkt: &Packet) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "decode",
+            stream = pkt.stream_index as i64,
+            pts = pkt.t.get_pts().map(|ts| ts.get_value()),
+            dts = pkt.t.get_dts().map(|ts| ts.get_value()),
+            size = pkt.data.len(),
+        )
+        .entered();
+
         self.dec.send_packet(pkt)
     }
     /// Returns a decoded frame.
     pub fn receive_frame(&mut self) -> Result<ArcFrame> {
-        self.dec.receive_frame()
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("receive_frame", pts = tracing::field::Empty).entered();
+
+        let res = self.dec.receive_frame();
+
+        #[cfg(feature = "tracing")]
+        if let Ok(ref frame) = res {
+            span.record("pts", frame.t.get_pts().map(|ts| ts.get_value()));
+        }
+
+        res
     }
     /// Configures the decoder.
     pub fn configure(&mut self) -> Result<()> {
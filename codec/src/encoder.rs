@@ -84,12 +84,33 @@ impl Context {
     }
     /// Sends to the encoder a frame to be encoded.
     pub fn send_frame(&mut self, frame: &ArcFrame) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("encode", pts = frame.t.get_pts().map(|ts| ts.get_value()))
+                .entered();
+
         self.enc.send_frame(frame)
     }
     /// Returns an encoded packet.
     // TODO: Return an Event?
     pub fn receive_packet(&mut self) -> Result<Packet> {
-        self.enc.receive_packet()
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "receive_packet",
+            pts = tracing::field::Empty,
+            dts = tracing::field::Empty,
+        )
+        .entered();
+
+        let res = self.enc.receive_packet();
+
+        #[cfg(feature = "tracing")]
+        if let Ok(ref pkt) = res {
+            span.record("pts", pkt.t.get_pts().map(|ts| ts.get_value()));
+            span.record("dts", pkt.t.get_dts().map(|ts| ts.get_value()));
+        }
+
+        res
     }
 
     /// Tells encoder to clear its internal state.
@@ -5,3 +5,6 @@ pub mod common;
 pub mod decoder;
 pub mod encoder;
 pub mod error;
+
+#[cfg(feature = "nvenc")]
+pub mod nvenc;
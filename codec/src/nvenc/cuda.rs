@@ -0,0 +1,229 @@
+//!
+//! CUDA devices and frames, as used by the NVENC encoders.
+//!
+
+use std::any::Any;
+use std::fmt;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::sync::Arc;
+
+use libloading::Library;
+
+use crate::data::frame::{FrameBuffer, FrameError};
+use crate::data::hwdevice::*;
+
+#[cfg(windows)]
+const LIBRARY: &str = "nvcuda.dll";
+#[cfg(not(windows))]
+const LIBRARY: &str = "libcuda.so.1";
+
+type CuResult = c_int;
+type CuContext = *mut c_void;
+
+struct Api {
+    ctx_destroy: unsafe extern "system" fn(CuContext) -> CuResult,
+    _lib: Library,
+}
+
+fn failure(what: &str, res: CuResult) -> HwDeviceError {
+    HwDeviceError::Creation(format!("{} failing with CUDA error {}", what, res))
+}
+
+impl Api {
+    fn open(ordinal: c_int) -> Result<(Api, CuContext)> {
+        unsafe {
+            let lib = Library::new(LIBRARY).map_err(|e| HwDeviceError::Creation(e.to_string()))?;
+            let symbol_error = |e: libloading::Error| HwDeviceError::Creation(e.to_string());
+
+            let init = *lib
+                .get::<unsafe extern "system" fn(c_uint) -> CuResult>(b"cuInit\0")
+                .map_err(symbol_error)?;
+            let device_get = *lib
+                .get::<unsafe extern "system" fn(*mut c_int, c_int) -> CuResult>(b"cuDeviceGet\0")
+                .map_err(symbol_error)?;
+            let ctx_create = *lib
+                .get::<unsafe extern "system" fn(*mut CuContext, c_uint, c_int) -> CuResult>(
+                    b"cuCtxCreate_v2\0",
+                )
+                .map_err(symbol_error)?;
+            let ctx_destroy = *lib
+                .get::<unsafe extern "system" fn(CuContext) -> CuResult>(b"cuCtxDestroy_v2\0")
+                .map_err(symbol_error)?;
+
+            let res = init(0);
+            if res != 0 {
+                return Err(failure("cuInit", res));
+            }
+            let mut device = 0;
+            let res = device_get(&mut device, ordinal);
+            if res != 0 {
+                return Err(failure("cuDeviceGet", res));
+            }
+            let mut ctx = std::ptr::null_mut();
+            let res = ctx_create(&mut ctx, 0, device);
+            if res != 0 {
+                return Err(failure("cuCtxCreate", res));
+            }
+
+            Ok((
+                Api {
+                    ctx_destroy,
+                    _lib: lib,
+                },
+                ctx,
+            ))
+        }
+    }
+}
+
+/// A CUDA context.
+pub struct CudaContext {
+    ctx: CuContext,
+    api: Arc<Api>,
+}
+
+// A CUDA context can be made current on any thread.
+unsafe impl Send for CudaContext {}
+unsafe impl Sync for CudaContext {}
+
+impl CudaContext {
+    /// Returns the `CUcontext` handle.
+    pub fn get_handle(&self) -> *mut c_void {
+        self.ctx
+    }
+}
+
+impl fmt::Debug for CudaContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CudaContext")
+            .field("ctx", &self.ctx)
+            .finish()
+    }
+}
+
+impl Drop for CudaContext {
+    fn drop(&mut self) {
+        unsafe {
+            (self.api.ctx_destroy)(self.ctx);
+        }
+    }
+}
+
+impl HwDeviceContext for CudaContext {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Cuda
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Opens CUDA devices through the driver library, loaded at run time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CudaDeviceBackend;
+
+impl HwDeviceBackend for CudaDeviceBackend {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Cuda
+    }
+
+    fn create(&self, selector: &DeviceSelector) -> Result<Box<dyn HwDeviceContext>> {
+        let ordinal = match *selector {
+            DeviceSelector::Default => 0,
+            DeviceSelector::AdapterIndex(idx) => idx as c_int,
+            DeviceSelector::DrmNode(_) => {
+                return Err(HwDeviceError::Unsupported("DRM node for CUDA".to_owned()))
+            }
+        };
+        let (api, ctx) = Api::open(ordinal)?;
+
+        Ok(Box::new(CudaContext {
+            ctx,
+            api: Arc::new(api),
+        }))
+    }
+}
+
+/// Layouts of the `CudaFrame`s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CudaFormat {
+    /// 8-bit 4:2:0, a luma plane followed by a plane of interleaved Cb and
+    /// Cr samples.
+    Nv12,
+    /// 4:2:0 laid out as `Nv12` with 16-bit little-endian samples, the
+    /// significant bits in the high bits.
+    P010,
+}
+
+/// A frame in the memory of a CUDA device.
+///
+/// The memory is owned by the producer of the frame, which has to keep it
+/// alive while the frame is in use.
+#[derive(Clone, Debug)]
+pub struct CudaFrame {
+    device: HwDevice,
+    ptr: u64,
+    pitch: usize,
+    format: CudaFormat,
+}
+
+impl CudaFrame {
+    /// Wraps a `CUdeviceptr` allocated in the context of `device`, the
+    /// chroma plane following the luma plane.
+    ///
+    /// # Safety
+    ///
+    /// The allocation must hold a frame of the size of the `VideoInfo` of
+    /// the frame using it and outlive it.
+    pub unsafe fn new(device: HwDevice, ptr: u64, pitch: usize, format: CudaFormat) -> Self {
+        CudaFrame {
+            device,
+            ptr,
+            pitch,
+            format,
+        }
+    }
+
+    /// Returns the `CUdeviceptr` of the frame.
+    pub fn get_ptr(&self) -> u64 {
+        self.ptr
+    }
+
+    /// Returns the distance in bytes between two lines.
+    pub fn get_pitch(&self) -> usize {
+        self.pitch
+    }
+
+    /// Returns the layout of the frame.
+    pub fn get_format(&self) -> CudaFormat {
+        self.format
+    }
+}
+
+impl HwFrame for CudaFrame {
+    fn get_device(&self) -> &HwDevice {
+        &self.device
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The planes of a `CudaFrame` are not accessible from the CPU.
+impl FrameBuffer for CudaFrame {
+    fn linesize(&self, _: usize) -> ::std::result::Result<usize, FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn count(&self) -> usize {
+        2
+    }
+    fn as_slice_inner(&self, _: usize) -> ::std::result::Result<&[u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_mut_slice_inner(&mut self, _: usize) -> ::std::result::Result<&mut [u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+        Some(self)
+    }
+}
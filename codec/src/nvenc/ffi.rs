@@ -0,0 +1,424 @@
+//!
+//! Bindings to the NVENC runtime, loaded at run time.
+//!
+//! The structures of the API are large and padded with reserved fields
+//! growing with each release, only their leading fields being used here.
+//! They are kept in zeroed `Block`s larger than any of them and accessed
+//! through the offsets of `nvEncodeAPI.h` from the Video Codec SDK 12.0.
+//!
+
+#![allow(non_snake_case)]
+
+use std::os::raw::c_void;
+
+use libloading::Library;
+
+use crate::error::*;
+
+/// API version requested, 12.0.
+pub const API_MAJOR: u32 = 12;
+pub const API_MINOR: u32 = 0;
+pub const API_VERSION: u32 = API_MAJOR | (API_MINOR << 24);
+
+const fn struct_version(ver: u32) -> u32 {
+    API_VERSION | (ver << 16) | (0x7 << 28)
+}
+
+pub const FUNCTION_LIST_VER: u32 = struct_version(2);
+pub const OPEN_SESSION_VER: u32 = struct_version(1);
+pub const PRESET_CONFIG_VER: u32 = struct_version(4) | (1 << 31);
+pub const CONFIG_VER: u32 = struct_version(8) | (1 << 31);
+pub const RC_PARAMS_VER: u32 = struct_version(1);
+pub const INITIALIZE_PARAMS_VER: u32 = struct_version(5) | (1 << 31);
+pub const CREATE_INPUT_BUFFER_VER: u32 = struct_version(1);
+pub const CREATE_BITSTREAM_BUFFER_VER: u32 = struct_version(1);
+pub const LOCK_INPUT_BUFFER_VER: u32 = struct_version(1);
+pub const LOCK_BITSTREAM_VER: u32 = struct_version(1);
+pub const PIC_PARAMS_VER: u32 = struct_version(6) | (1 << 31);
+pub const REGISTER_RESOURCE_VER: u32 = struct_version(4);
+pub const MAP_INPUT_RESOURCE_VER: u32 = struct_version(4);
+pub const SEQUENCE_PARAM_PAYLOAD_VER: u32 = struct_version(1);
+
+pub const DEVICE_TYPE_CUDA: u32 = 1;
+pub const BUFFER_FORMAT_NV12: u32 = 0x1;
+pub const BUFFER_FORMAT_P010: u32 = 0x10000;
+pub const RC_CONSTQP: u32 = 0;
+pub const RC_VBR: u32 = 1;
+pub const RC_CBR: u32 = 2;
+pub const TUNING_HIGH_QUALITY: u32 = 1;
+pub const PIC_STRUCT_FRAME: u32 = 1;
+pub const PIC_FLAG_FORCEIDR: u32 = 0x2;
+pub const PIC_FLAG_EOS: u32 = 0x8;
+pub const PIC_TYPE_I: u32 = 2;
+pub const PIC_TYPE_IDR: u32 = 3;
+pub const RESOURCE_TYPE_CUDADEVICEPTR: u32 = 1;
+pub const BUFFER_USAGE_INPUT_IMAGE: u32 = 0;
+
+pub const SUCCESS: i32 = 0;
+pub const ERR_NEED_MORE_INPUT: i32 = 17;
+
+/// Offsets of the fields of `NV_ENC_CONFIG`.
+pub mod config {
+    pub const VERSION: usize = 0;
+    pub const GOP_LENGTH: usize = 20;
+    pub const FRAME_INTERVAL_P: usize = 24;
+    pub const RC_VERSION: usize = 40;
+    pub const RC_MODE: usize = 44;
+    pub const RC_CONST_QP: usize = 48;
+    pub const RC_AVERAGE_BIT_RATE: usize = 60;
+    pub const RC_MAX_BIT_RATE: usize = 64;
+}
+
+/// Offset of `presetCfg` in `NV_ENC_PRESET_CONFIG`.
+pub const PRESET_CONFIG: usize = 8;
+
+/// Offsets of the fields of `NV_ENC_INITIALIZE_PARAMS`.
+pub mod init {
+    pub const ENCODE_GUID: usize = 4;
+    pub const PRESET_GUID: usize = 20;
+    pub const WIDTH: usize = 36;
+    pub const HEIGHT: usize = 40;
+    pub const DAR_WIDTH: usize = 44;
+    pub const DAR_HEIGHT: usize = 48;
+    pub const FRAME_RATE_NUM: usize = 52;
+    pub const FRAME_RATE_DEN: usize = 56;
+    pub const ENABLE_PTD: usize = 64;
+    pub const ENCODE_CONFIG: usize = 88;
+    pub const MAX_WIDTH: usize = 96;
+    pub const MAX_HEIGHT: usize = 100;
+    pub const TUNING_INFO: usize = 136;
+}
+
+/// Offsets of the fields of `NV_ENC_CREATE_INPUT_BUFFER`.
+pub mod input_buffer {
+    pub const WIDTH: usize = 4;
+    pub const HEIGHT: usize = 8;
+    pub const FORMAT: usize = 16;
+    pub const BUFFER: usize = 24;
+}
+
+/// Offset of `bitstreamBuffer` in `NV_ENC_CREATE_BITSTREAM_BUFFER`.
+pub const BITSTREAM_BUFFER: usize = 16;
+
+/// Offsets of the fields of `NV_ENC_LOCK_INPUT_BUFFER`.
+pub mod lock_input {
+    pub const BUFFER: usize = 8;
+    pub const DATA: usize = 16;
+    pub const PITCH: usize = 24;
+}
+
+/// Offsets of the fields of `NV_ENC_PIC_PARAMS`.
+pub mod pic {
+    pub const WIDTH: usize = 4;
+    pub const HEIGHT: usize = 8;
+    pub const PITCH: usize = 12;
+    pub const FLAGS: usize = 16;
+    pub const FRAME_IDX: usize = 20;
+    pub const TIMESTAMP: usize = 24;
+    pub const INPUT: usize = 40;
+    pub const OUTPUT: usize = 48;
+    pub const FORMAT: usize = 64;
+    pub const STRUCT: usize = 68;
+}
+
+/// Offsets of the fields of `NV_ENC_LOCK_BITSTREAM`.
+pub mod lock_bitstream {
+    pub const OUTPUT: usize = 8;
+    pub const SIZE: usize = 36;
+    pub const TIMESTAMP: usize = 40;
+    pub const DATA: usize = 56;
+    pub const PICTURE_TYPE: usize = 64;
+}
+
+/// Offsets of the fields of `NV_ENC_REGISTER_RESOURCE`.
+pub mod register {
+    pub const TYPE: usize = 4;
+    pub const WIDTH: usize = 8;
+    pub const HEIGHT: usize = 12;
+    pub const PITCH: usize = 16;
+    pub const RESOURCE: usize = 24;
+    pub const REGISTERED: usize = 32;
+    pub const FORMAT: usize = 40;
+    pub const USAGE: usize = 44;
+}
+
+/// Offsets of the fields of `NV_ENC_MAP_INPUT_RESOURCE`.
+pub mod map {
+    pub const REGISTERED: usize = 16;
+    pub const MAPPED: usize = 24;
+}
+
+/// Offsets of the fields of `NV_ENC_SEQUENCE_PARAM_PAYLOAD`.
+pub mod sequence {
+    pub const SIZE: usize = 4;
+    pub const BUFFER: usize = 16;
+    pub const OUT_SIZE: usize = 24;
+}
+
+/// Globally unique identifier of a codec or preset.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+const fn guid(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Guid {
+    Guid {
+        data1,
+        data2,
+        data3,
+        data4,
+    }
+}
+
+pub const CODEC_H264: Guid = guid(
+    0x6bc8_2762,
+    0x4e63,
+    0x4ca4,
+    [0xaa, 0x85, 0x1e, 0x50, 0xf3, 0x21, 0xf6, 0xbf],
+);
+pub const CODEC_HEVC: Guid = guid(
+    0x790c_dc88,
+    0x4522,
+    0x4d7b,
+    [0x94, 0x25, 0xbd, 0xa9, 0x97, 0x5f, 0x76, 0x03],
+);
+
+/// Presets P1 (fastest) to P7 (slowest).
+pub const PRESETS: [Guid; 7] = [
+    guid(
+        0xfc0a_8d3e,
+        0x45f8,
+        0x4cf8,
+        [0x80, 0xc7, 0x29, 0x88, 0x71, 0x59, 0x0e, 0xbf],
+    ),
+    guid(
+        0xf581_cfb8,
+        0x88d6,
+        0x4381,
+        [0x93, 0xf0, 0xdf, 0x13, 0xf9, 0xc2, 0x7d, 0xab],
+    ),
+    guid(
+        0x3685_0110,
+        0x3a07,
+        0x441f,
+        [0x94, 0xd5, 0x36, 0x70, 0x63, 0x1f, 0x91, 0xf6],
+    ),
+    guid(
+        0x90a7_b826,
+        0xdf06,
+        0x4862,
+        [0xb9, 0xd2, 0xcd, 0x6d, 0x73, 0xa0, 0x86, 0x81],
+    ),
+    guid(
+        0x21c6_e6b4,
+        0x297a,
+        0x4cba,
+        [0x99, 0x8f, 0xb6, 0xcb, 0xde, 0x72, 0xad, 0xe3],
+    ),
+    guid(
+        0x8e75_c279,
+        0x6299,
+        0x4ab6,
+        [0x83, 0x02, 0x0b, 0x21, 0x5a, 0x33, 0x5c, 0xf5],
+    ),
+    guid(
+        0x8484_8c12,
+        0x6f71,
+        0x4c13,
+        [0x93, 0x1b, 0x53, 0xe2, 0x83, 0xf5, 0x79, 0x74],
+    ),
+];
+
+const BLOCK_SIZE: usize = 16384;
+
+/// Zeroed storage for a structure of the API.
+#[repr(C, align(8))]
+pub struct Block([u8; BLOCK_SIZE]);
+
+impl Block {
+    /// Returns a zeroed block with the structure version set.
+    pub fn new(version: u32) -> Box<Block> {
+        let mut block = Box::new(Block([0; BLOCK_SIZE]));
+        block.put_u32(0, version);
+        block
+    }
+
+    pub fn put_u32(&mut self, offset: usize, v: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&v.to_ne_bytes());
+    }
+
+    pub fn get_u32(&self, offset: usize) -> u32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&self.0[offset..offset + 4]);
+        u32::from_ne_bytes(b)
+    }
+
+    pub fn put_u64(&mut self, offset: usize, v: u64) {
+        self.0[offset..offset + 8].copy_from_slice(&v.to_ne_bytes());
+    }
+
+    pub fn get_u64(&self, offset: usize) -> u64 {
+        let mut b = [0; 8];
+        b.copy_from_slice(&self.0[offset..offset + 8]);
+        u64::from_ne_bytes(b)
+    }
+
+    pub fn put_ptr(&mut self, offset: usize, p: *mut c_void) {
+        self.put_u64(offset, p as usize as u64);
+    }
+
+    pub fn get_ptr(&self, offset: usize) -> *mut c_void {
+        self.get_u64(offset) as usize as *mut c_void
+    }
+
+    pub fn put_guid(&mut self, offset: usize, guid: &Guid) {
+        self.put_u32(offset, guid.data1);
+        self.0[offset + 4..offset + 6].copy_from_slice(&guid.data2.to_ne_bytes());
+        self.0[offset + 6..offset + 8].copy_from_slice(&guid.data3.to_ne_bytes());
+        self.0[offset + 8..offset + 16].copy_from_slice(&guid.data4);
+    }
+
+    /// Copies the structure at `offset` in `src`.
+    pub fn copy_from(&mut self, src: &Block, offset: usize) {
+        let len = BLOCK_SIZE - offset;
+        self.0[..len].copy_from_slice(&src.0[offset..]);
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.0.as_mut_ptr() as *mut c_void
+    }
+}
+
+pub type Handle = *mut c_void;
+type Status = i32;
+type Unused = *const c_void;
+
+/// `NV_ENCODE_API_FUNCTION_LIST`.
+#[repr(C)]
+pub struct FunctionList {
+    pub version: u32,
+    reserved: u32,
+    nvEncOpenEncodeSession: Unused,
+    nvEncGetEncodeGUIDCount: Unused,
+    nvEncGetEncodeProfileGUIDCount: Unused,
+    nvEncGetEncodeProfileGUIDs: Unused,
+    nvEncGetEncodeGUIDs: Unused,
+    nvEncGetInputFormatCount: Unused,
+    nvEncGetInputFormats: Unused,
+    nvEncGetEncodeCaps: Unused,
+    nvEncGetEncodePresetCount: Unused,
+    nvEncGetEncodePresetGUIDs: Unused,
+    nvEncGetEncodePresetConfig: Unused,
+    pub nvEncInitializeEncoder: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncCreateInputBuffer: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncDestroyInputBuffer: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    pub nvEncCreateBitstreamBuffer:
+        Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncDestroyBitstreamBuffer: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    pub nvEncEncodePicture: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncLockBitstream: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncUnlockBitstream: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    pub nvEncLockInputBuffer: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncUnlockInputBuffer: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    nvEncGetEncodeStats: Unused,
+    pub nvEncGetSequenceParams: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    nvEncRegisterAsyncEvent: Unused,
+    nvEncUnregisterAsyncEvent: Unused,
+    pub nvEncMapInputResource: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncUnmapInputResource: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    pub nvEncDestroyEncoder: Option<unsafe extern "system" fn(Handle) -> Status>,
+    nvEncInvalidateRefFrames: Unused,
+    pub nvEncOpenEncodeSessionEx:
+        Option<unsafe extern "system" fn(*mut c_void, *mut Handle) -> Status>,
+    pub nvEncRegisterResource: Option<unsafe extern "system" fn(Handle, *mut c_void) -> Status>,
+    pub nvEncUnregisterResource: Option<unsafe extern "system" fn(Handle, Handle) -> Status>,
+    nvEncReconfigureEncoder: Unused,
+    reserved1: Unused,
+    nvEncCreateMVBuffer: Unused,
+    nvEncDestroyMVBuffer: Unused,
+    nvEncRunMotionEstimationOnly: Unused,
+    nvEncGetLastErrorString: Unused,
+    nvEncSetIOCudaStreams: Unused,
+    pub nvEncGetEncodePresetConfigEx:
+        Option<unsafe extern "system" fn(Handle, Guid, Guid, u32, *mut c_void) -> Status>,
+    nvEncGetSequenceParamEx: Unused,
+    reserved2: [Unused; 277],
+}
+
+#[cfg(windows)]
+const LIBRARY: &str = "nvEncodeAPI64.dll";
+#[cfg(not(windows))]
+const LIBRARY: &str = "libnvidia-encode.so.1";
+
+/// The NVENC runtime library and its entry points.
+pub struct Api {
+    pub functions: Box<FunctionList>,
+    _lib: Library,
+}
+
+// The entry points of the runtime can be called from any thread.
+unsafe impl Send for Api {}
+unsafe impl Sync for Api {}
+
+fn unavailable(what: &str) -> Error {
+    Error::Unsupported(format!("NVENC {}", what))
+}
+
+/// Returns an error if an API call failed.
+pub fn check(status: Status) -> Result<()> {
+    if status == SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::Unsupported(format!(
+            "NVENC call failing with status {}",
+            status
+        )))
+    }
+}
+
+/// Returns an entry point of the function list.
+pub fn entry<T>(f: Option<T>) -> Result<T> {
+    f.ok_or_else(|| unavailable("entry point"))
+}
+
+impl Api {
+    /// Loads the runtime library, checking it supports the API version.
+    pub fn load() -> Result<Api> {
+        unsafe {
+            let lib = Library::new(LIBRARY).map_err(|e| unavailable(&e.to_string()))?;
+
+            let max_version = *lib
+                .get::<unsafe extern "system" fn(*mut u32) -> Status>(
+                    b"NvEncodeAPIGetMaxSupportedVersion\0",
+                )
+                .map_err(|e| unavailable(&e.to_string()))?;
+            let mut version = 0;
+            check(max_version(&mut version))?;
+            if version < (API_MAJOR << 4 | API_MINOR) {
+                return Err(unavailable(&format!(
+                    "driver supporting API {}.{}",
+                    version >> 4,
+                    version & 0xf
+                )));
+            }
+
+            let create = *lib
+                .get::<unsafe extern "system" fn(*mut FunctionList) -> Status>(
+                    b"NvEncodeAPICreateInstance\0",
+                )
+                .map_err(|e| unavailable(&e.to_string()))?;
+            let mut functions: Box<FunctionList> = Box::new(std::mem::zeroed());
+            functions.version = FUNCTION_LIST_VER;
+            check(create(&mut *functions))?;
+
+            Ok(Api {
+                functions,
+                _lib: lib,
+            })
+        }
+    }
+}
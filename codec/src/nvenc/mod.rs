@@ -0,0 +1,1034 @@
+//!
+//! NVIDIA NVENC hardware encoders.
+//!
+//! The NVENC and CUDA libraries are loaded when an encoder is configured,
+//! so that the crate builds and runs on systems without them.
+//!
+//! Frames in system memory are copied to the input buffers of the
+//! encoder, `CudaFrame`s on the device of the encoder are encoded without
+//! copies.
+//!
+
+mod cuda;
+mod ffi;
+
+pub use self::cuda::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use crate::data::frame::{ArcFrame, Frame, MediaKind as FrameKind};
+use crate::data::hwdevice::{DeviceSelector, HwDevice, HwDeviceType};
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::pixel::{ColorModel, Formaton, TrichromaticEncodingSystem};
+use crate::data::timeinfo::TimeInfo;
+use crate::data::value::Value;
+
+use crate::encoder::*;
+use crate::error::*;
+
+use self::ffi::{Block, Handle};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+    H264,
+    Hevc,
+}
+
+impl Codec {
+    fn get_guid(self) -> ffi::Guid {
+        match self {
+            Codec::H264 => ffi::CODEC_H264,
+            Codec::Hevc => ffi::CODEC_HEVC,
+        }
+    }
+
+    fn get_name(self) -> &'static str {
+        match self {
+            Codec::H264 => "h264",
+            Codec::Hevc => "hevc",
+        }
+    }
+}
+
+/// Rate control modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant quantizer, set by the `qp` option.
+    ConstQp,
+    /// Variable bitrate, averaging the `bitrate` option and peaking at the
+    /// `max_bitrate` option.
+    Vbr,
+    /// Constant bitrate, set by the `bitrate` option.
+    Cbr,
+}
+
+#[derive(Clone, Debug)]
+struct Settings {
+    preset: usize,
+    rc: RateControl,
+    bitrate: u32,
+    max_bitrate: u32,
+    gop: Option<u32>,
+    bframes: u32,
+    qp: u32,
+    framerate: (u32, u32),
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            preset: 4,
+            rc: RateControl::Vbr,
+            bitrate: 0,
+            max_bitrate: 0,
+            gop: None,
+            bframes: 0,
+            qp: 23,
+            framerate: (30, 1),
+        }
+    }
+}
+
+fn to_u32(v: u64) -> Result<u32> {
+    u32::try_from(v).map_err(|_| Error::ConfigurationInvalid)
+}
+
+impl Settings {
+    fn set_option(&mut self, key: &str, val: Value) -> Result<()> {
+        match (key, val) {
+            ("preset", Value::U64(v)) if (1..=7).contains(&v) => self.preset = v as usize,
+            ("preset", Value::Str(s)) => {
+                self.preset = match s {
+                    "p1" => 1,
+                    "p2" => 2,
+                    "p3" => 3,
+                    "p4" => 4,
+                    "p5" => 5,
+                    "p6" => 6,
+                    "p7" => 7,
+                    _ => return Err(Error::ConfigurationInvalid),
+                }
+            }
+            ("rc", Value::Str(s)) => {
+                self.rc = match s {
+                    "cqp" => RateControl::ConstQp,
+                    "vbr" => RateControl::Vbr,
+                    "cbr" => RateControl::Cbr,
+                    _ => return Err(Error::ConfigurationInvalid),
+                }
+            }
+            ("bitrate", Value::U64(v)) => self.bitrate = to_u32(v)?,
+            ("max_bitrate", Value::U64(v)) => self.max_bitrate = to_u32(v)?,
+            ("gop", Value::U64(v)) if v > 0 => self.gop = Some(to_u32(v)?),
+            ("bframes", Value::U64(v)) if v <= 4 => self.bframes = v as u32,
+            ("qp", Value::U64(v)) if v <= 51 => self.qp = v as u32,
+            ("framerate", Value::Pair(num, den)) if num > 0 && den > 0 => {
+                self.framerate = (to_u32(num as u64)?, to_u32(den as u64)?)
+            }
+            ("preset", _)
+            | ("rc", _)
+            | ("gop", _)
+            | ("bframes", _)
+            | ("qp", _)
+            | ("framerate", _)
+            | ("bitrate", _)
+            | ("max_bitrate", _) => return Err(Error::ConfigurationInvalid),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+
+    /// Overrides the preset configuration with the settings.
+    fn apply_config(&self, config: &mut Block) {
+        use self::ffi::config::*;
+
+        if let Some(gop) = self.gop {
+            config.put_u32(GOP_LENGTH, gop);
+        }
+        config.put_u32(FRAME_INTERVAL_P, self.bframes + 1);
+        match self.rc {
+            RateControl::ConstQp => {
+                config.put_u32(RC_MODE, ffi::RC_CONSTQP);
+                for i in 0..3 {
+                    config.put_u32(RC_CONST_QP + i * 4, self.qp);
+                }
+            }
+            RateControl::Vbr | RateControl::Cbr => {
+                let mode = if self.rc == RateControl::Vbr {
+                    ffi::RC_VBR
+                } else {
+                    ffi::RC_CBR
+                };
+                config.put_u32(RC_MODE, mode);
+                if self.bitrate > 0 {
+                    config.put_u32(RC_AVERAGE_BIT_RATE, self.bitrate);
+                }
+                if self.max_bitrate > 0 {
+                    config.put_u32(RC_MAX_BIT_RATE, self.max_bitrate);
+                }
+            }
+        }
+    }
+
+    /// Fills the initialization parameters of an encoder.
+    fn init_params(&self, codec: Codec, width: u32, height: u32, config: &mut Block) -> Box<Block> {
+        use self::ffi::init::*;
+
+        let mut init = Block::new(ffi::INITIALIZE_PARAMS_VER);
+        init.put_guid(ENCODE_GUID, &codec.get_guid());
+        init.put_guid(PRESET_GUID, &ffi::PRESETS[self.preset - 1]);
+        init.put_u32(WIDTH, width);
+        init.put_u32(HEIGHT, height);
+        init.put_u32(DAR_WIDTH, width);
+        init.put_u32(DAR_HEIGHT, height);
+        init.put_u32(FRAME_RATE_NUM, self.framerate.0);
+        init.put_u32(FRAME_RATE_DEN, self.framerate.1);
+        init.put_u32(ENABLE_PTD, 1);
+        init.put_ptr(ENCODE_CONFIG, config.as_mut_ptr());
+        init.put_u32(MAX_WIDTH, width);
+        init.put_u32(MAX_HEIGHT, height);
+        init.put_u32(TUNING_INFO, ffi::TUNING_HIGH_QUALITY);
+        init
+    }
+}
+
+/// Returns the layout of the encoder input matching a pixel format.
+fn get_layout(fmt: &Formaton) -> Option<CudaFormat> {
+    if let ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(_)) = fmt.get_model() {
+    } else {
+        return None;
+    }
+    if fmt.get_num_comp() != 3 || fmt.is_be() {
+        return None;
+    }
+    let chromas = [fmt.get_chromaton(1)?, fmt.get_chromaton(2)?];
+    if chromas.iter().any(|c| c.get_subsampling() != (1, 1)) {
+        return None;
+    }
+
+    match fmt.get_chromaton(0)?.get_depth() {
+        8 => Some(CudaFormat::Nv12),
+        10 => Some(CudaFormat::P010),
+        _ => None,
+    }
+}
+
+fn get_buffer_format(layout: CudaFormat) -> u32 {
+    match layout {
+        CudaFormat::Nv12 => ffi::BUFFER_FORMAT_NV12,
+        CudaFormat::P010 => ffi::BUFFER_FORMAT_P010,
+    }
+}
+
+/// Copies a planar 4:2:0 frame to a buffer of the encoder.
+fn pack(
+    frame: &Frame,
+    layout: CudaFormat,
+    width: usize,
+    height: usize,
+    dst: &mut [u8],
+    pitch: usize,
+) -> Result<()> {
+    let wide = layout == CudaFormat::P010;
+    let size = if wide { 2 } else { 1 };
+    let sample = |row: &[u8], x: usize| {
+        if wide {
+            u16::from_le_bytes([row[2 * x], row[2 * x + 1]]) << 6
+        } else {
+            u16::from(row[x])
+        }
+    };
+    let put = |row: &mut [u8], x: usize, v: u16| {
+        if wide {
+            row[2 * x..2 * x + 2].copy_from_slice(&v.to_le_bytes());
+        } else {
+            row[x] = v as u8;
+        }
+    };
+
+    let mut planes = Vec::with_capacity(3);
+    for i in 0..3 {
+        let data = frame
+            .buf
+            .as_slice_inner(i)
+            .map_err(|_| Error::InvalidData)?;
+        let stride = frame.buf.linesize(i).map_err(|_| Error::InvalidData)?;
+        planes.push((data, stride));
+    }
+
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    if dst.len() < pitch * (height + ch)
+        || pitch < width * size
+        || planes[0].0.len() < planes[0].1 * (height - 1) + width * size
+        || planes[1..]
+            .iter()
+            .any(|p| p.0.len() < p.1 * (ch - 1) + cw * size)
+    {
+        return Err(Error::InvalidData);
+    }
+
+    let (luma, chroma) = dst.split_at_mut(pitch * height);
+    for (y, row) in luma.chunks_mut(pitch).enumerate() {
+        let src = &planes[0].0[y * planes[0].1..];
+        for x in 0..width {
+            put(row, x, sample(src, x));
+        }
+    }
+    for (y, row) in chroma.chunks_mut(pitch).take(ch).enumerate() {
+        let cb = &planes[1].0[y * planes[1].1..];
+        let cr = &planes[2].0[y * planes[2].1..];
+        for x in 0..cw {
+            put(row, 2 * x, sample(cb, x));
+            put(row, 2 * x + 1, sample(cr, x));
+        }
+    }
+
+    Ok(())
+}
+
+struct InFlight {
+    output: Handle,
+    mapped: Option<Handle>,
+    _frame: Option<ArcFrame>,
+}
+
+/// An initialized encoder and its buffers.
+struct Session {
+    api: ffi::Api,
+    encoder: Handle,
+    device: HwDevice,
+    layout: CudaFormat,
+    width: usize,
+    height: usize,
+    inputs: Vec<Handle>,
+    outputs: Vec<Handle>,
+    next: usize,
+    registered: HashMap<u64, Handle>,
+    in_flight: VecDeque<InFlight>,
+}
+
+// The handles of a session are only used through `&mut self`.
+unsafe impl Send for Session {}
+
+impl Session {
+    fn open(
+        api: ffi::Api,
+        device: HwDevice,
+        codec: Codec,
+        settings: &Settings,
+        layout: CudaFormat,
+        width: usize,
+        height: usize,
+    ) -> Result<Session> {
+        let ctx = device
+            .downcast_ref::<CudaContext>()
+            .ok_or_else(|| Error::Unsupported("device other than CUDA".to_owned()))?
+            .get_handle();
+
+        let mut params = Block::new(ffi::OPEN_SESSION_VER);
+        params.put_u32(4, ffi::DEVICE_TYPE_CUDA);
+        params.put_ptr(8, ctx);
+        params.put_u32(24, ffi::API_VERSION);
+        let mut encoder = std::ptr::null_mut();
+        unsafe {
+            ffi::check(ffi::entry(api.functions.nvEncOpenEncodeSessionEx)?(
+                params.as_mut_ptr(),
+                &mut encoder,
+            ))?;
+        }
+
+        let mut session = Session {
+            api,
+            encoder,
+            device,
+            layout,
+            width,
+            height,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            next: 0,
+            registered: HashMap::new(),
+            in_flight: VecDeque::new(),
+        };
+        session.initialize(codec, settings)?;
+
+        Ok(session)
+    }
+
+    fn initialize(&mut self, codec: Codec, settings: &Settings) -> Result<()> {
+        let f = &self.api.functions;
+        let (width, height) = (to_u32(self.width as u64)?, to_u32(self.height as u64)?);
+        let format = get_buffer_format(self.layout);
+
+        let mut preset = Block::new(ffi::PRESET_CONFIG_VER);
+        preset.put_u32(ffi::PRESET_CONFIG, ffi::CONFIG_VER);
+        preset.put_u32(
+            ffi::PRESET_CONFIG + ffi::config::RC_VERSION,
+            ffi::RC_PARAMS_VER,
+        );
+        let mut config = Block::new(0);
+        unsafe {
+            ffi::check(ffi::entry(f.nvEncGetEncodePresetConfigEx)?(
+                self.encoder,
+                codec.get_guid(),
+                ffi::PRESETS[settings.preset - 1],
+                ffi::TUNING_HIGH_QUALITY,
+                preset.as_mut_ptr(),
+            ))?;
+            config.copy_from(&preset, ffi::PRESET_CONFIG);
+            config.put_u32(ffi::config::VERSION, ffi::CONFIG_VER);
+            settings.apply_config(&mut config);
+
+            let mut init = settings.init_params(codec, width, height, &mut config);
+            ffi::check(ffi::entry(f.nvEncInitializeEncoder)?(
+                self.encoder,
+                init.as_mut_ptr(),
+            ))?;
+
+            // The encoder holds up to `bframes` frames waiting for their
+            // reference.
+            for _ in 0..settings.bframes + 4 {
+                let mut input = Block::new(ffi::CREATE_INPUT_BUFFER_VER);
+                input.put_u32(ffi::input_buffer::WIDTH, width);
+                input.put_u32(ffi::input_buffer::HEIGHT, height);
+                input.put_u32(ffi::input_buffer::FORMAT, format);
+                ffi::check(ffi::entry(f.nvEncCreateInputBuffer)?(
+                    self.encoder,
+                    input.as_mut_ptr(),
+                ))?;
+                self.inputs.push(input.get_ptr(ffi::input_buffer::BUFFER));
+
+                let mut output = Block::new(ffi::CREATE_BITSTREAM_BUFFER_VER);
+                ffi::check(ffi::entry(f.nvEncCreateBitstreamBuffer)?(
+                    self.encoder,
+                    output.as_mut_ptr(),
+                ))?;
+                self.outputs.push(output.get_ptr(ffi::BITSTREAM_BUFFER));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_sequence_params(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; 1024];
+        let mut size = 0u32;
+        let mut payload = Block::new(ffi::SEQUENCE_PARAM_PAYLOAD_VER);
+        payload.put_u32(ffi::sequence::SIZE, buf.len() as u32);
+        payload.put_ptr(ffi::sequence::BUFFER, buf.as_mut_ptr() as *mut _);
+        payload.put_ptr(ffi::sequence::OUT_SIZE, &mut size as *mut u32 as *mut _);
+        unsafe {
+            ffi::check(ffi::entry(self.api.functions.nvEncGetSequenceParams)?(
+                self.encoder,
+                payload.as_mut_ptr(),
+            ))?;
+        }
+        buf.truncate(size as usize);
+
+        Ok(buf)
+    }
+
+    /// Returns the mapped input resource of a CUDA frame.
+    fn map(&mut self, frame: &CudaFrame) -> Result<Handle> {
+        let f = &self.api.functions;
+        let registered = match self.registered.get(&frame.get_ptr()) {
+            Some(&registered) => registered,
+            None => {
+                use self::ffi::register::*;
+
+                let mut reg = Block::new(ffi::REGISTER_RESOURCE_VER);
+                reg.put_u32(TYPE, ffi::RESOURCE_TYPE_CUDADEVICEPTR);
+                reg.put_u32(WIDTH, self.width as u32);
+                reg.put_u32(HEIGHT, self.height as u32);
+                reg.put_u32(PITCH, to_u32(frame.get_pitch() as u64)?);
+                reg.put_u64(RESOURCE, frame.get_ptr());
+                reg.put_u32(FORMAT, get_buffer_format(self.layout));
+                reg.put_u32(USAGE, ffi::BUFFER_USAGE_INPUT_IMAGE);
+                unsafe {
+                    ffi::check(ffi::entry(f.nvEncRegisterResource)?(
+                        self.encoder,
+                        reg.as_mut_ptr(),
+                    ))?;
+                }
+                let registered = reg.get_ptr(REGISTERED);
+                self.registered.insert(frame.get_ptr(), registered);
+                registered
+            }
+        };
+
+        let mut map = Block::new(ffi::MAP_INPUT_RESOURCE_VER);
+        map.put_ptr(ffi::map::REGISTERED, registered);
+        unsafe {
+            ffi::check(ffi::entry(f.nvEncMapInputResource)?(
+                self.encoder,
+                map.as_mut_ptr(),
+            ))?;
+        }
+
+        Ok(map.get_ptr(ffi::map::MAPPED))
+    }
+
+    /// Copies a frame in system memory to an input buffer.
+    fn upload(&mut self, frame: &Frame, input: Handle) -> Result<usize> {
+        use self::ffi::lock_input::*;
+
+        let f = &self.api.functions;
+        let mut lock = Block::new(ffi::LOCK_INPUT_BUFFER_VER);
+        lock.put_ptr(BUFFER, input);
+        unsafe {
+            ffi::check(ffi::entry(f.nvEncLockInputBuffer)?(
+                self.encoder,
+                lock.as_mut_ptr(),
+            ))?;
+            let pitch = lock.get_u32(PITCH) as usize;
+            let len = pitch * (self.height + self.height.div_ceil(2));
+            let dst = std::slice::from_raw_parts_mut(lock.get_ptr(DATA) as *mut u8, len);
+            let res = pack(frame, self.layout, self.width, self.height, dst, pitch);
+            ffi::check(ffi::entry(f.nvEncUnlockInputBuffer)?(self.encoder, input))?;
+            res.map(|_| pitch)
+        }
+    }
+
+    fn encode(&mut self, frame: &ArcFrame, idx: u64) -> Result<bool> {
+        let slot = self.next;
+        self.next = (self.next + 1) % self.inputs.len();
+
+        let (input, pitch, mapped, keep) = match frame.buf.as_hw_frame() {
+            Some(hw) => {
+                let cuda = hw.as_any().downcast_ref::<CudaFrame>().ok_or_else(|| {
+                    Error::Unsupported("hardware frames other than CUDA".to_owned())
+                })?;
+                if !hw.get_device().ptr_eq(&self.device) {
+                    return Err(Error::Unsupported(
+                        "CUDA frames of another device".to_owned(),
+                    ));
+                }
+                if cuda.get_format() != self.layout {
+                    return Err(Error::InvalidData);
+                }
+                let mapped = self.map(cuda)?;
+                (mapped, cuda.get_pitch(), Some(mapped), Some(frame.clone()))
+            }
+            None => {
+                let input = self.inputs[slot];
+                let pitch = self.upload(frame, input)?;
+                (input, pitch, None, None)
+            }
+        };
+
+        let mut pic = Block::new(ffi::PIC_PARAMS_VER);
+        pic.put_u32(ffi::pic::WIDTH, self.width as u32);
+        pic.put_u32(ffi::pic::HEIGHT, self.height as u32);
+        pic.put_u32(ffi::pic::PITCH, pitch as u32);
+        pic.put_u32(ffi::pic::FRAME_IDX, idx as u32);
+        pic.put_u64(ffi::pic::TIMESTAMP, idx);
+        pic.put_ptr(ffi::pic::INPUT, input);
+        pic.put_ptr(ffi::pic::OUTPUT, self.outputs[slot]);
+        pic.put_u32(ffi::pic::FORMAT, get_buffer_format(self.layout));
+        pic.put_u32(ffi::pic::STRUCT, ffi::PIC_STRUCT_FRAME);
+        if idx == 0 {
+            pic.put_u32(ffi::pic::FLAGS, ffi::PIC_FLAG_FORCEIDR);
+        }
+        self.in_flight.push_back(InFlight {
+            output: self.outputs[slot],
+            mapped,
+            _frame: keep,
+        });
+
+        self.submit(&mut pic)
+    }
+
+    /// Submits a picture, telling if the pictures in flight are encoded.
+    fn submit(&mut self, pic: &mut Block) -> Result<bool> {
+        let status = unsafe {
+            ffi::entry(self.api.functions.nvEncEncodePicture)?(self.encoder, pic.as_mut_ptr())
+        };
+        match status {
+            ffi::ERR_NEED_MORE_INPUT => Ok(false),
+            status => ffi::check(status).map(|_| true),
+        }
+    }
+
+    fn end_of_stream(&mut self) -> Result<bool> {
+        let mut pic = Block::new(ffi::PIC_PARAMS_VER);
+        pic.put_u32(ffi::pic::FLAGS, ffi::PIC_FLAG_EOS);
+        self.submit(&mut pic)
+    }
+
+    /// Reads the encoded pictures in flight, as the data and the timestamp
+    /// of their input.
+    fn drain(&mut self) -> Result<Vec<(Vec<u8>, u64, bool)>> {
+        use self::ffi::lock_bitstream::*;
+
+        let f = &self.api.functions;
+        let mut out = Vec::with_capacity(self.in_flight.len());
+        while let Some(pic) = self.in_flight.pop_front() {
+            let mut lock = Block::new(ffi::LOCK_BITSTREAM_VER);
+            lock.put_ptr(OUTPUT, pic.output);
+            unsafe {
+                ffi::check(ffi::entry(f.nvEncLockBitstream)?(
+                    self.encoder,
+                    lock.as_mut_ptr(),
+                ))?;
+                let data = std::slice::from_raw_parts(
+                    lock.get_ptr(DATA) as *const u8,
+                    lock.get_u32(SIZE) as usize,
+                )
+                .to_vec();
+                let ty = lock.get_u32(PICTURE_TYPE);
+                out.push((
+                    data,
+                    lock.get_u64(TIMESTAMP),
+                    ty == ffi::PIC_TYPE_IDR || ty == ffi::PIC_TYPE_I,
+                ));
+                ffi::check(ffi::entry(f.nvEncUnlockBitstream)?(
+                    self.encoder,
+                    pic.output,
+                ))?;
+                if let Some(mapped) = pic.mapped {
+                    ffi::check(ffi::entry(f.nvEncUnmapInputResource)?(self.encoder, mapped))?;
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let f = &self.api.functions;
+        unsafe {
+            for pic in self.in_flight.drain(..) {
+                if let (Some(mapped), Some(unmap)) = (pic.mapped, f.nvEncUnmapInputResource) {
+                    unmap(self.encoder, mapped);
+                }
+            }
+            if let Some(unregister) = f.nvEncUnregisterResource {
+                for (_, registered) in self.registered.drain() {
+                    unregister(self.encoder, registered);
+                }
+            }
+            if let Some(destroy) = f.nvEncDestroyInputBuffer {
+                for input in self.inputs.drain(..) {
+                    destroy(self.encoder, input);
+                }
+            }
+            if let Some(destroy) = f.nvEncDestroyBitstreamBuffer {
+                for output in self.outputs.drain(..) {
+                    destroy(self.encoder, output);
+                }
+            }
+            if let Some(destroy) = f.nvEncDestroyEncoder {
+                destroy(self.encoder);
+            }
+        }
+    }
+}
+
+/// NVENC encoder.
+///
+/// Supported options:
+///
+/// - `preset`: `U64` from 1 (fastest) to 7 (slowest) or `Str` from `p1`
+///   to `p7`.
+/// - `rc`: `Str`, one of `cqp`, `vbr` and `cbr`.
+/// - `bitrate`, `max_bitrate`: `U64`, in bits per second.
+/// - `gop`: `U64`, the distance between two intra frames.
+/// - `bframes`: `U64`, the number of consecutive B-frames, up to 4.
+/// - `qp`: `U64`, the quantizer of the `cqp` rate control, up to 51.
+/// - `framerate`: `Pair`, the numerator and denominator of the frame rate.
+pub struct NvencEncoder {
+    codec: Codec,
+    settings: Settings,
+    width: Option<usize>,
+    height: Option<usize>,
+    format: Option<Arc<Formaton>>,
+    device: Option<HwDevice>,
+    session: Option<Session>,
+    extradata: Option<Vec<u8>>,
+    frame_idx: u64,
+    times: HashMap<u64, TimeInfo>,
+    packets: VecDeque<Packet>,
+}
+
+impl NvencEncoder {
+    fn new(codec: Codec) -> Self {
+        NvencEncoder {
+            codec,
+            settings: Settings::default(),
+            width: None,
+            height: None,
+            format: None,
+            device: None,
+            session: None,
+            extradata: None,
+            frame_idx: 0,
+            times: HashMap::new(),
+            packets: VecDeque::new(),
+        }
+    }
+
+    /// Creates an H.264 encoder.
+    pub fn h264() -> Self {
+        Self::new(Codec::H264)
+    }
+
+    /// Creates an HEVC encoder.
+    pub fn hevc() -> Self {
+        Self::new(Codec::Hevc)
+    }
+
+    /// Sets the device used by the encoder, a CUDA device being derived
+    /// from it if needed.
+    ///
+    /// Without a device, the default CUDA device is opened on
+    /// configuration.
+    pub fn set_hw_device(&mut self, device: &HwDevice) -> Result<()> {
+        self.device = Some(
+            device
+                .derive(HwDeviceType::Cuda)
+                .map_err(|e| Error::Unsupported(e.to_string()))?,
+        );
+        Ok(())
+    }
+
+    fn queue(&mut self, encoded: Vec<(Vec<u8>, u64, bool)>) {
+        for (data, ts, is_key) in encoded {
+            let mut pkt = Packet::new();
+            pkt.data = data;
+            pkt.is_key = is_key;
+            pkt.t = self.times.remove(&ts).unwrap_or_default();
+            if self.settings.bframes == 0 {
+                let pts = pkt.t.get_pts();
+                // The timebase of the frame is kept, the rescaling cannot fail.
+                let _ = pkt.t.set_dts(pts);
+            }
+            self.packets.push_back(pkt);
+        }
+    }
+}
+
+impl Encoder for NvencEncoder {
+    fn get_extradata(&self) -> Option<Vec<u8>> {
+        self.extradata.clone()
+    }
+
+    fn send_frame(&mut self, frame: &ArcFrame) -> Result<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or(Error::ConfigurationIncomplete)?;
+        match frame.kind {
+            FrameKind::Video(ref info)
+                if info.width == session.width && info.height == session.height => {}
+            _ => return Err(Error::InvalidData),
+        }
+
+        let idx = self.frame_idx;
+        self.times.insert(idx, frame.t.clone());
+        self.frame_idx += 1;
+        let encoded = match session.encode(frame, idx) {
+            Ok(true) => session.drain()?,
+            Ok(false) => Vec::new(),
+            Err(e) => {
+                self.times.remove(&idx);
+                return Err(e);
+            }
+        };
+        self.queue(encoded);
+
+        Ok(())
+    }
+
+    fn receive_packet(&mut self) -> Result<Packet> {
+        self.packets.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(session) = self.session.as_mut() {
+            if session.end_of_stream()? {
+                let encoded = session.drain()?;
+                self.queue(encoded);
+            }
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self) -> Result<()> {
+        let (width, height) = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            _ => return Err(Error::ConfigurationIncomplete),
+        };
+        let format = self.format.as_ref().ok_or(Error::ConfigurationIncomplete)?;
+        let layout =
+            get_layout(format).ok_or_else(|| Error::Unsupported("pixel format".to_owned()))?;
+        if self.codec == Codec::H264 && layout == CudaFormat::P010 {
+            return Err(Error::Unsupported("10-bit H.264".to_owned()));
+        }
+
+        self.session = None;
+        let api = ffi::Api::load()?;
+        let device = match self.device {
+            Some(ref device) => device.clone(),
+            None => HwDevice::create(&CudaDeviceBackend, DeviceSelector::Default)
+                .map_err(|e| Error::Unsupported(e.to_string()))?,
+        };
+        let mut session = Session::open(
+            api,
+            device,
+            self.codec,
+            &self.settings,
+            layout,
+            width,
+            height,
+        )?;
+        self.extradata = Some(session.get_sequence_params()?);
+        self.session = Some(session);
+        self.frame_idx = 0;
+        self.times.clear();
+        self.packets.clear();
+
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("w", Value::U64(v)) => self.width = Some(v as usize),
+            ("h", Value::U64(v)) => self.height = Some(v as usize),
+            ("format", Value::Formaton(f)) => self.format = Some(f),
+            (key, val) => return self.settings.set_option(key, val),
+        }
+
+        Ok(())
+    }
+
+    fn set_params(&mut self, params: &CodecParams) -> Result<()> {
+        if let Some(MediaKind::Video(ref info)) = params.kind {
+            self.width = Some(info.width);
+            self.height = Some(info.height);
+            self.format = info.format.clone();
+        }
+        if params.bit_rate > 0 {
+            self.settings.bitrate = to_u32(params.bit_rate as u64)?;
+        }
+        Ok(())
+    }
+
+    fn get_params(&self) -> Result<CodecParams> {
+        let (width, height) = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            _ => return Err(Error::ConfigurationIncomplete),
+        };
+
+        Ok(CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width,
+                height,
+                format: self.format.clone(),
+            })),
+            codec_id: Some(self.codec.get_name().to_owned()),
+            extradata: self.get_extradata(),
+            bit_rate: self.settings.bitrate as usize,
+            convergence_window: 0,
+            delay: self.settings.bframes as usize,
+        })
+    }
+}
+
+/// Descriptor of an NVENC encoder.
+pub struct Des {
+    codec: Codec,
+    descr: Descr,
+}
+
+impl Descriptor for Des {
+    fn create(&self) -> Box<dyn Encoder> {
+        Box::new(NvencEncoder::new(self.codec))
+    }
+    fn describe(&self) -> &Descr {
+        &self.descr
+    }
+}
+
+/// NVENC H.264 encoder descriptor.
+pub const H264_DESCR: &Des = &Des {
+    codec: Codec::H264,
+    descr: Descr {
+        codec: "h264",
+        name: "h264_nvenc",
+        desc: "NVIDIA NVENC H.264 encoder",
+        mime: "video/avc",
+    },
+};
+
+/// NVENC HEVC encoder descriptor.
+pub const HEVC_DESCR: &Des = &Des {
+    codec: Codec::Hevc,
+    descr: Descr {
+        codec: "hevc",
+        name: "hevc_nvenc",
+        desc: "NVIDIA NVENC HEVC encoder",
+        mime: "video/hevc",
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{FrameType, VideoInfo as FrameInfo};
+    use crate::data::pixel::formats::{RGB24, YUV420, YUV420_10, YUV444};
+
+    #[test]
+    fn options() {
+        let mut enc = NvencEncoder::hevc();
+
+        enc.set_option("preset", Value::Str("p7")).unwrap();
+        enc.set_option("rc", Value::Str("cbr")).unwrap();
+        enc.set_option("bitrate", Value::U64(4_000_000)).unwrap();
+        enc.set_option("gop", Value::U64(120)).unwrap();
+        enc.set_option("bframes", Value::U64(2)).unwrap();
+        enc.set_option("framerate", Value::Pair(30000, 1001))
+            .unwrap();
+        assert_eq!(enc.settings.preset, 7);
+        assert_eq!(enc.settings.rc, RateControl::Cbr);
+        assert_eq!(enc.settings.framerate, (30000, 1001));
+
+        assert!(enc.set_option("preset", Value::U64(8)).is_err());
+        assert!(enc.set_option("rc", Value::Str("abr")).is_err());
+        assert!(enc.set_option("bitrate", Value::U64(1 << 40)).is_err());
+        assert!(enc.set_option("gop", Value::Bool(true)).is_err());
+        assert!(matches!(
+            enc.set_option("tune", Value::Str("hq")),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn apply() {
+        use self::ffi::config::*;
+
+        let mut settings = Settings::default();
+        settings.set_option("gop", Value::U64(60)).unwrap();
+        settings.set_option("bframes", Value::U64(3)).unwrap();
+        settings
+            .set_option("bitrate", Value::U64(5_000_000))
+            .unwrap();
+        settings
+            .set_option("max_bitrate", Value::U64(8_000_000))
+            .unwrap();
+
+        let mut config = Block::new(ffi::CONFIG_VER);
+        settings.apply_config(&mut config);
+        assert_eq!(config.get_u32(GOP_LENGTH), 60);
+        assert_eq!(config.get_u32(FRAME_INTERVAL_P), 4);
+        assert_eq!(config.get_u32(RC_MODE), ffi::RC_VBR);
+        assert_eq!(config.get_u32(RC_AVERAGE_BIT_RATE), 5_000_000);
+        assert_eq!(config.get_u32(RC_MAX_BIT_RATE), 8_000_000);
+
+        settings.set_option("rc", Value::Str("cqp")).unwrap();
+        settings.set_option("qp", Value::U64(30)).unwrap();
+        settings.apply_config(&mut config);
+        assert_eq!(config.get_u32(RC_MODE), ffi::RC_CONSTQP);
+        assert_eq!(config.get_u32(RC_CONST_QP + 8), 30);
+
+        let init = settings.init_params(Codec::H264, 1920, 1080, &mut config);
+        assert_eq!(init.get_u32(0), ffi::INITIALIZE_PARAMS_VER);
+        assert_eq!(init.get_u32(ffi::init::ENCODE_GUID), ffi::CODEC_H264.data1);
+        assert_eq!(init.get_u32(ffi::init::PRESET_GUID), ffi::PRESETS[3].data1);
+        assert_eq!(init.get_u32(ffi::init::WIDTH), 1920);
+        assert_eq!(init.get_u32(ffi::init::HEIGHT), 1080);
+        assert_eq!(init.get_u32(ffi::init::FRAME_RATE_NUM), 30);
+        assert_eq!(init.get_ptr(ffi::init::ENCODE_CONFIG), config.as_mut_ptr());
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(get_layout(YUV420), Some(CudaFormat::Nv12));
+        assert_eq!(get_layout(YUV420_10), Some(CudaFormat::P010));
+        assert_eq!(get_layout(YUV444), None);
+        assert_eq!(get_layout(RGB24), None);
+    }
+
+    fn test_frame(fmt: &Formaton, w: usize, h: usize) -> Frame {
+        let info = FrameInfo::new(w, h, false, FrameType::I, Arc::new(*fmt));
+        let mut frame = Frame::new_default_frame(info, None);
+        let wide = fmt.get_chromaton(0).unwrap().get_depth() > 8;
+        for i in 0..3 {
+            let data = frame.buf.as_mut_slice_inner(i).unwrap();
+            for (j, b) in data.iter_mut().enumerate() {
+                *b = if wide && j % 2 == 1 {
+                    i as u8
+                } else {
+                    (j as u8).wrapping_add(i as u8 * 64)
+                };
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn pack_nv12() {
+        let frame = test_frame(YUV420, 6, 4);
+        let pitch = 8;
+        let mut dst = vec![0; pitch * 6];
+        pack(&frame, CudaFormat::Nv12, 6, 4, &mut dst, pitch).unwrap();
+
+        let luma = frame.buf.as_slice_inner(0).unwrap();
+        let stride = frame.buf.linesize(0).unwrap();
+        assert_eq!(&dst[pitch..pitch + 6], &luma[stride..stride + 6]);
+
+        let cb = frame.buf.as_slice_inner(1).unwrap();
+        let cr = frame.buf.as_slice_inner(2).unwrap();
+        let stride = frame.buf.linesize(1).unwrap();
+        let row = &dst[pitch * 5..pitch * 5 + 6];
+        assert_eq!(row[0], cb[stride]);
+        assert_eq!(row[1], cr[stride]);
+        assert_eq!(row[4], cb[stride + 2]);
+        assert_eq!(row[5], cr[stride + 2]);
+
+        assert!(pack(&frame, CudaFormat::Nv12, 6, 4, &mut dst[..40], pitch).is_err());
+    }
+
+    #[test]
+    fn pack_p010() {
+        let frame = test_frame(YUV420_10, 4, 2);
+        let pitch = 8;
+        let mut dst = vec![0; pitch * 3];
+        pack(&frame, CudaFormat::P010, 4, 2, &mut dst, pitch).unwrap();
+
+        let luma = frame.buf.as_slice_inner(0).unwrap();
+        let v = u16::from_le_bytes([luma[2], luma[3]]) << 6;
+        assert_eq!(&dst[2..4], &v.to_le_bytes());
+
+        let cr = frame.buf.as_slice_inner(2).unwrap();
+        let v = u16::from_le_bytes([cr[2], cr[3]]) << 6;
+        assert_eq!(&dst[pitch * 2 + 6..pitch * 2 + 8], &v.to_le_bytes());
+    }
+
+    #[test]
+    fn configure() {
+        let mut enc = NvencEncoder::h264();
+        assert!(matches!(
+            enc.configure(),
+            Err(Error::ConfigurationIncomplete)
+        ));
+
+        enc.set_option("w", Value::U64(64)).unwrap();
+        enc.set_option("h", Value::U64(64)).unwrap();
+        enc.set_option("format", Value::Formaton(Arc::new(*YUV420_10)))
+            .unwrap();
+        assert!(matches!(enc.configure(), Err(Error::Unsupported(_))));
+
+        // Without the runtime library the configuration fails gracefully.
+        enc.set_option("format", Value::Formaton(Arc::new(*YUV420)))
+            .unwrap();
+        if ffi::Api::load().is_err() {
+            assert!(enc.configure().is_err());
+        }
+    }
+}
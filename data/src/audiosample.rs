@@ -77,7 +77,7 @@ impl fmt::Display for Soniton {
 }
 
 /// Known audio channel types.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChannelType {
     C,
     L,
@@ -107,6 +107,8 @@ pub enum ChannelType {
     Rt,
     Lo,
     Ro,
+    /// Channel of unknown position.
+    Unknown,
 }
 
 impl ChannelType {
@@ -191,6 +193,7 @@ impl fmt::Display for ChannelType {
             ChannelType::Rt => "Rt".to_string(),
             ChannelType::Lo => "Lo".to_string(),
             ChannelType::Ro => "Ro".to_string(),
+            ChannelType::Unknown => "Unknown".to_string(),
         };
         write!(f, "{}", name)
     }
@@ -252,19 +255,197 @@ impl ChannelMap {
     /// When `count` is 1 --> the channel map is composed by a single centered
     /// channel.
     ///
-    /// When `count` is 2 --> the channel map is composed by a right and a left
+    /// When `count` is 2 --> the channel map is composed by a left and a right
     /// channel respectively.
     ///
-    /// For other `count` values, no other implementations are given for now.
+    /// For `count` values up to 8, the channel map is the one of the default
+    /// `ChannelLayout`.
+    ///
+    /// For other `count` values, the channels are of unknown positions.
     pub fn default_map(count: usize) -> Self {
-        use self::ChannelType::*;
-        let ids = match count {
-            1 => vec![C],
-            2 => vec![R, L],
-            _ => unimplemented!(),
+        ChannelLayout::default_for(count)
+            .to_map()
+            .unwrap_or_else(|| ChannelMap {
+                ids: vec![ChannelType::Unknown; count],
+            })
+    }
+}
+
+/// Channel positions of the channel masks, by bit.
+///
+/// They follow the order of the `WAVE_FORMAT_EXTENSIBLE` speaker positions,
+/// shared by the CAF channel bitmaps.
+pub const MASK_POSITIONS: [ChannelType; 18] = {
+    use self::ChannelType::*;
+    [
+        L, R, C, LFE, Ls, Rs, Lc, Rc, Cs, Lss, Rss, Ov, Lh, Ch, Rh, Lhs, Chs, Rhs,
+    ]
+};
+
+impl ChannelType {
+    /// Returns the bit of the channel in the channel masks, if any.
+    pub fn get_mask_bit(self) -> Option<u32> {
+        MASK_POSITIONS
+            .iter()
+            .position(|&ch| ch == self)
+            .map(|bit| bit as u32)
+    }
+}
+
+/// Layout of the channels of an audio stream.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelLayout {
+    /// Channels of unknown positions.
+    Unspecified(usize),
+    /// Channels of the positions set in a mask, in the order of their
+    /// `MASK_POSITIONS` bits.
+    Mask(u32),
+    /// Channels of named positions, in any order.
+    Custom(ChannelMap),
+    /// Ambisonic channels in ACN order, followed by the non-diegetic
+    /// channels of a mask.
+    Ambisonic {
+        /// Ambisonic order, the channels being `(order + 1)²`.
+        order: u32,
+        /// Mask of the non-diegetic channels.
+        mask: u32,
+    },
+}
+
+impl ChannelLayout {
+    /// Mono layout.
+    pub const MONO: ChannelLayout = ChannelLayout::Mask(0x4);
+    /// Stereo layout.
+    pub const STEREO: ChannelLayout = ChannelLayout::Mask(0x3);
+    /// 5.1 layout, with rear surround channels.
+    pub const SURROUND_5_1: ChannelLayout = ChannelLayout::Mask(0x3f);
+    /// 7.1 layout.
+    pub const SURROUND_7_1: ChannelLayout = ChannelLayout::Mask(0x63f);
+
+    /// Returns the default layout of a number of channels, unspecified
+    /// beyond 8 channels.
+    pub fn default_for(count: usize) -> Self {
+        let mask = match count {
+            1 => 0x4,
+            2 => 0x3,
+            3 => 0x7,
+            4 => 0x107,
+            5 => 0x37,
+            6 => 0x3f,
+            7 => 0x70f,
+            8 => 0x63f,
+            _ => return ChannelLayout::Unspecified(count),
         };
+        ChannelLayout::Mask(mask)
+    }
+
+    /// Returns the layout of a `WAVE_FORMAT_EXTENSIBLE` channel mask.
+    ///
+    /// The layout is unspecified when the mask does not describe every
+    /// channel, as the format allows.
+    pub fn from_wav_mask(channels: usize, mask: u32) -> Self {
+        if mask >> MASK_POSITIONS.len() == 0 && mask.count_ones() as usize == channels {
+            ChannelLayout::Mask(mask)
+        } else {
+            ChannelLayout::Unspecified(channels)
+        }
+    }
+
+    /// Returns the layout of a channel map, a mask one if its channels
+    /// follow the mask order.
+    pub fn from_map(map: &ChannelMap) -> Self {
+        if map.ids.contains(&ChannelType::Unknown) {
+            return ChannelLayout::Unspecified(map.len());
+        }
+        let mut mask = 0u32;
+        for &ch in &map.ids {
+            match ch.get_mask_bit() {
+                Some(bit) if mask >> bit == 0 => mask |= 1 << bit,
+                _ => return ChannelLayout::Custom(map.clone()),
+            }
+        }
+        ChannelLayout::Mask(mask)
+    }
+
+    /// Returns the number of channels.
+    pub fn count(&self) -> usize {
+        match self {
+            ChannelLayout::Unspecified(count) => *count,
+            ChannelLayout::Mask(mask) => mask.count_ones() as usize,
+            ChannelLayout::Custom(map) => map.len(),
+            ChannelLayout::Ambisonic { order, mask } => {
+                let side = *order as usize + 1;
+                side * side + mask.count_ones() as usize
+            }
+        }
+    }
+
+    /// Returns the channel mask, if the channels follow the mask order.
+    pub fn get_mask(&self) -> Option<u32> {
+        match self {
+            ChannelLayout::Mask(mask) => Some(*mask),
+            ChannelLayout::Custom(map) => match ChannelLayout::from_map(map) {
+                ChannelLayout::Mask(mask) => Some(mask),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the ambisonic order, if any.
+    pub fn get_ambisonic_order(&self) -> Option<u32> {
+        match self {
+            ChannelLayout::Ambisonic { order, .. } => Some(*order),
+            _ => None,
+        }
+    }
 
-        ChannelMap { ids }
+    /// Returns the sequence of channels, if all of them are named.
+    pub fn to_map(&self) -> Option<ChannelMap> {
+        match self {
+            ChannelLayout::Mask(mask) => {
+                let mut map = ChannelMap::new();
+                for (bit, &ch) in MASK_POSITIONS.iter().enumerate() {
+                    if mask & (1 << bit) != 0 {
+                        map.add_channel(ch);
+                    }
+                }
+                Some(map)
+            }
+            ChannelLayout::Custom(map) => Some(map.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl From<&ChannelMap> for ChannelLayout {
+    fn from(map: &ChannelMap) -> Self {
+        ChannelLayout::from_map(map)
+    }
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelLayout::Unspecified(count) => write!(f, "{} channels", count),
+            ChannelLayout::Ambisonic { order, mask } => {
+                write!(f, "ambisonic {}", order)?;
+                if *mask != 0 {
+                    write!(f, "+{}", ChannelLayout::Mask(*mask))?;
+                }
+                Ok(())
+            }
+            _ => {
+                let map = self.to_map().unwrap_or_default();
+                for (i, ch) in map.ids.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", ch)?;
+                }
+                Ok(())
+            }
+        }
     }
 }
 
@@ -333,4 +514,45 @@ mod test {
         println!("{}", formats::U8);
         println!("{}", formats::F32);
     }
+
+    #[test]
+    fn layouts() {
+        use self::ChannelType::*;
+
+        let layout = ChannelLayout::default_for(6);
+        assert_eq!(layout, ChannelLayout::SURROUND_5_1);
+        let map = layout.to_map().unwrap();
+        assert_eq!(map.ids, [L, R, C, LFE, Ls, Rs]);
+        assert_eq!(ChannelLayout::from_map(&map), layout);
+        assert_eq!(format!("{}", layout), "L R C LFE Ls Rs");
+        assert_eq!(ChannelMap::default_map(8).len(), 8);
+        assert_eq!(ChannelMap::default_map(2).ids, [L, R]);
+        let unknown = ChannelMap::default_map(10);
+        assert_eq!(unknown.ids, [Unknown; 10]);
+        assert_eq!(
+            ChannelLayout::from_map(&unknown),
+            ChannelLayout::Unspecified(10)
+        );
+
+        let mut swapped = ChannelMap::new();
+        swapped.add_channels(&[R, L]);
+        let custom = ChannelLayout::from_map(&swapped);
+        assert_eq!(custom, ChannelLayout::Custom(swapped));
+        assert_eq!((custom.count(), custom.get_mask()), (2, None));
+
+        assert_eq!(ChannelLayout::from_wav_mask(2, 0x3), ChannelLayout::STEREO);
+        assert_eq!(
+            ChannelLayout::from_wav_mask(4, 0x3),
+            ChannelLayout::Unspecified(4)
+        );
+        assert_eq!(ChannelLayout::default_for(9).to_map(), None);
+
+        let ambisonic = ChannelLayout::Ambisonic {
+            order: 1,
+            mask: 0x3,
+        };
+        assert_eq!(ambisonic.count(), 6);
+        assert_eq!(ambisonic.get_ambisonic_order(), Some(1));
+        assert_eq!(format!("{}", ambisonic), "ambisonic 1+L R");
+    }
 }
@@ -0,0 +1,293 @@
+//!
+//! Memory accounting.
+//!
+//! A `Budget` is a memory ceiling shared by the components holding frames
+//! and packets, such as queues, pools and demuxer buffers. Each of them
+//! reserves the bytes it holds and gets a `Reservation` giving them back
+//! once dropped.
+//!
+//! Reserving fails when the budget is exceeded, or blocks until enough
+//! bytes are given back, so a producer waits for its consumer. Only the
+//! components handing their data to other threads should block.
+//!
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+/// Memory budget errors.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum BudgetError {
+    /// Reserving the bytes would exceed the budget.
+    #[error("{requested} bytes requested, {available} available")]
+    Exceeded {
+        /// Bytes requested.
+        requested: usize,
+        /// Bytes available when requested.
+        available: usize,
+    },
+    /// The bytes were not given back in time.
+    #[error("Timed out")]
+    TimedOut,
+}
+
+/// A specialised `Result` type for memory budget operations.
+pub type Result<T> = ::std::result::Result<T, BudgetError>;
+
+struct Inner {
+    limit: usize,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// Memory budget, its clones sharing the same bytes.
+#[derive(Clone)]
+pub struct Budget(Arc<Inner>);
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit", &self.get_limit())
+            .field("used", &self.get_used())
+            .finish()
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::unbounded()
+    }
+}
+
+impl Budget {
+    /// Creates a budget of `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        Budget(Arc::new(Inner {
+            limit,
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }))
+    }
+
+    /// Creates a budget only accounting for the bytes.
+    pub fn unbounded() -> Self {
+        Budget::new(usize::MAX)
+    }
+
+    /// Returns the size of the budget.
+    pub fn get_limit(&self) -> usize {
+        self.0.limit
+    }
+
+    /// Returns the bytes reserved.
+    pub fn get_used(&self) -> usize {
+        *self.0.used.lock().unwrap()
+    }
+
+    /// Returns the bytes left.
+    pub fn get_available(&self) -> usize {
+        self.0.limit - self.get_used()
+    }
+
+    fn reservation(&self, size: usize) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            size,
+        }
+    }
+
+    fn try_take(&self, size: usize) -> Result<()> {
+        let mut used = self.0.used.lock().unwrap();
+        let available = self.0.limit - *used;
+        if size > available {
+            return Err(BudgetError::Exceeded {
+                requested: size,
+                available,
+            });
+        }
+        *used += size;
+        Ok(())
+    }
+
+    /// Reserves `size` bytes, failing if the budget would be exceeded.
+    pub fn try_reserve(&self, size: usize) -> Result<Reservation> {
+        self.try_take(size)?;
+        Ok(self.reservation(size))
+    }
+
+    /// Reserves `size` bytes, waiting for them to be given back if needed.
+    ///
+    /// Fails at once if `size` exceeds the whole budget, and once the
+    /// timeout expires if any.
+    pub fn reserve(&self, size: usize, timeout: Option<Duration>) -> Result<Reservation> {
+        if size > self.0.limit {
+            return Err(BudgetError::Exceeded {
+                requested: size,
+                available: self.get_available(),
+            });
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut used = self.0.used.lock().unwrap();
+        while size > self.0.limit - *used {
+            used = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(BudgetError::TimedOut);
+                    }
+                    self.0.freed.wait_timeout(used, deadline - now).unwrap().0
+                }
+                None => self.0.freed.wait(used).unwrap(),
+            };
+        }
+        *used += size;
+        Ok(self.reservation(size))
+    }
+
+    /// Reserves the bytes of a value, failing if the budget would be
+    /// exceeded.
+    pub fn try_hold<T>(&self, value: T, size: usize) -> Result<Held<T>> {
+        Ok(Held {
+            value,
+            reservation: self.try_reserve(size)?,
+        })
+    }
+
+    /// Reserves the bytes of a value, waiting for them as `reserve` does.
+    pub fn hold<T>(&self, value: T, size: usize, timeout: Option<Duration>) -> Result<Held<T>> {
+        Ok(Held {
+            value,
+            reservation: self.reserve(size, timeout)?,
+        })
+    }
+
+    fn release(&self, size: usize) {
+        *self.0.used.lock().unwrap() -= size;
+        self.0.freed.notify_all();
+    }
+}
+
+/// Bytes reserved from a budget, given back when dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    budget: Budget,
+    size: usize,
+}
+
+impl Reservation {
+    /// Returns the bytes reserved.
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    /// Resizes the reservation, failing if the budget would be exceeded.
+    pub fn try_resize(&mut self, size: usize) -> Result<()> {
+        if size > self.size {
+            self.budget.try_take(size - self.size)?;
+        } else {
+            self.budget.release(self.size - size);
+        }
+        self.size = size;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.size);
+    }
+}
+
+/// A value along with the bytes reserved for it, such as a queued frame or
+/// packet.
+#[derive(Debug)]
+pub struct Held<T> {
+    value: T,
+    reservation: Reservation,
+}
+
+impl<T> Held<T> {
+    /// Returns the bytes reserved for the value.
+    pub fn get_size(&self) -> usize {
+        self.reservation.get_size()
+    }
+
+    /// Returns the value, giving its bytes back.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Held<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Held<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn reserve() {
+        let budget = Budget::new(100);
+        let a = budget.try_reserve(60).unwrap();
+        assert_eq!(budget.get_used(), 60);
+        assert_eq!(
+            budget.try_reserve(50).unwrap_err(),
+            BudgetError::Exceeded {
+                requested: 50,
+                available: 40
+            }
+        );
+
+        let mut b = budget.try_reserve(40).unwrap();
+        assert!(b.try_resize(41).is_err());
+        b.try_resize(10).unwrap();
+        assert_eq!(budget.get_available(), 30);
+        drop(a);
+        b.try_resize(90).unwrap();
+        assert_eq!(budget.get_used(), 90);
+        drop(b);
+        assert_eq!(budget.get_used(), 0);
+
+        let held = budget.try_hold(vec![0u8; 70], 70).unwrap();
+        assert_eq!((held.len(), budget.get_used()), (70, 70));
+        let data = held.into_inner();
+        assert_eq!((data.len(), budget.get_used()), (70, 0));
+    }
+
+    #[test]
+    fn wait() {
+        let budget = Budget::new(100);
+        let full = budget.try_reserve(100).unwrap();
+        assert_eq!(
+            budget
+                .reserve(10, Some(Duration::from_millis(10)))
+                .unwrap_err(),
+            BudgetError::TimedOut
+        );
+        assert!(budget.reserve(101, None).is_err());
+
+        let waiting = {
+            let budget = budget.clone();
+            thread::spawn(move || budget.reserve(10, None).map(|r| r.get_size()))
+        };
+        thread::sleep(Duration::from_millis(10));
+        drop(full);
+        assert_eq!(waiting.join().unwrap(), Ok(10));
+        assert_eq!(budget.get_used(), 0);
+    }
+}
@@ -3,7 +3,6 @@
 use std::alloc::{alloc, Layout};
 use std::convert::From;
 use std::fmt;
-use std::ptr::copy_nonoverlapping;
 use std::sync::Arc;
 
 use byte_slice_cast::*;
@@ -11,8 +10,12 @@ use bytes::BytesMut;
 use thiserror::Error;
 
 use crate::audiosample::*;
+use crate::hdr::HdrMetadata;
+use crate::hwdevice::HwFrame;
+use crate::imgutils;
+use crate::metadata::Metadata;
 use crate::pixel::*;
-use crate::timeinfo::*;
+use crate::timeinfo::TimeInfo;
 
 use self::FrameError::*;
 
@@ -58,6 +61,147 @@ impl fmt::Display for FrameType {
     }
 }
 
+/// A field of an interlaced video frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// The field made of the even lines.
+    Top,
+    /// The field made of the odd lines.
+    Bottom,
+}
+
+/// How the lines of the two fields are stored in the frame planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldLayout {
+    /// Lines of the two fields are interleaved.
+    Interleaved,
+    /// All the lines of the top field are followed by all the lines
+    /// of the bottom field.
+    Separate,
+}
+
+/// Field structure of a video frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldInfo {
+    /// The two fields are sampled at different instants.
+    pub interlaced: bool,
+    /// The field displayed first.
+    pub first_field: Field,
+    /// The first field is displayed again after the second one
+    /// (e.g. 3:2 pulldown).
+    pub repeat_first_field: bool,
+    /// Number of additional frame periods the frame is displayed for
+    /// (e.g. frame doubling or tripling).
+    pub repeat_frame: u8,
+    /// Storage layout of the fields.
+    pub layout: FieldLayout,
+    /// The picture holds this field only, the other field of the frame
+    /// being coded in another picture.
+    pub single_field: Option<Field>,
+}
+
+impl Default for FieldInfo {
+    fn default() -> Self {
+        FieldInfo {
+            interlaced: false,
+            first_field: Field::Top,
+            repeat_first_field: false,
+            repeat_frame: 0,
+            layout: FieldLayout::Interleaved,
+            single_field: None,
+        }
+    }
+}
+
+impl FieldInfo {
+    /// Creates the field information of a picture holding a single field.
+    pub fn single(field: Field) -> Self {
+        FieldInfo {
+            interlaced: true,
+            first_field: field,
+            single_field: Some(field),
+            ..Default::default()
+        }
+    }
+
+    /// Creates the field information matching an H.264 `pic_struct` value
+    /// (Table D-1 of ITU-T H.264).
+    ///
+    /// The value is the one of the picture timing SEI message.
+    ///
+    /// Returns `None` for reserved values.
+    pub fn from_h264_pic_struct(pic_struct: u8) -> Option<Self> {
+        let interlaced = |first_field, repeat_first_field| FieldInfo {
+            interlaced: true,
+            first_field,
+            repeat_first_field,
+            ..Default::default()
+        };
+        let info = match pic_struct {
+            0 => FieldInfo::default(),
+            1 => FieldInfo::single(Field::Top),
+            2 => FieldInfo::single(Field::Bottom),
+            3 => interlaced(Field::Top, false),
+            4 => interlaced(Field::Bottom, false),
+            5 => interlaced(Field::Top, true),
+            6 => interlaced(Field::Bottom, true),
+            7 | 8 => FieldInfo {
+                repeat_frame: pic_struct - 6,
+                ..Default::default()
+            },
+            _ => return None,
+        };
+
+        Some(info)
+    }
+
+    /// Creates the field information from the MPEG-2 sequence extension and
+    /// picture coding extension flags (ISO/IEC 13818-2 6.3.10).
+    ///
+    /// `field` is the field of a field picture, `None` for frame pictures.
+    pub fn from_mpeg2(
+        progressive_sequence: bool,
+        field: Option<Field>,
+        progressive_frame: bool,
+        top_field_first: bool,
+        repeat_first_field: bool,
+    ) -> Self {
+        if let Some(field) = field {
+            FieldInfo::single(field)
+        } else if progressive_sequence {
+            // In progressive sequences the two flags encode frame repetition.
+            let repeat_frame = match (repeat_first_field, top_field_first) {
+                (false, _) => 0,
+                (true, false) => 1,
+                (true, true) => 2,
+            };
+            FieldInfo {
+                repeat_frame,
+                ..Default::default()
+            }
+        } else {
+            FieldInfo {
+                interlaced: !progressive_frame,
+                first_field: if top_field_first {
+                    Field::Top
+                } else {
+                    Field::Bottom
+                },
+                repeat_first_field,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Returns the number of field periods the frame is displayed for.
+    pub fn get_display_fields(&self) -> usize {
+        if self.single_field.is_some() {
+            return 1;
+        }
+        2 + self.repeat_first_field as usize + 2 * self.repeat_frame as usize
+    }
+}
+
 /// Video stream information.
 #[derive(Clone, Debug)]
 pub struct VideoInfo {
@@ -73,6 +217,10 @@ pub struct VideoInfo {
     pub format: Arc<Formaton>,
     /// Declared bits per sample.
     pub bits: u8,
+    /// Field structure of the frame.
+    pub fields: FieldInfo,
+    /// High dynamic range metadata of the frame.
+    pub hdr: HdrMetadata,
 }
 
 impl VideoInfo {
@@ -92,6 +240,8 @@ impl VideoInfo {
             frame_type,
             format,
             bits,
+            fields: FieldInfo::default(),
+            hdr: HdrMetadata::default(),
         }
     }
 
@@ -125,15 +275,27 @@ impl VideoInfo {
         self.height = height;
     }
 
+    /// Returns frame field structure.
+    pub fn get_field_info(&self) -> &FieldInfo {
+        &self.fields
+    }
+    /// Sets new frame field structure.
+    pub fn set_field_info(&mut self, fields: FieldInfo) {
+        self.fields = fields;
+    }
+
+    /// Returns frame high dynamic range metadata.
+    pub fn get_hdr_metadata(&self) -> &HdrMetadata {
+        &self.hdr
+    }
+    /// Sets new frame high dynamic range metadata.
+    pub fn set_hdr_metadata(&mut self, hdr: HdrMetadata) {
+        self.hdr = hdr;
+    }
+
     /// Returns video stream size with the specified alignment.
     pub fn size(&self, align: usize) -> usize {
-        let mut size = 0;
-        for &component in self.format.into_iter() {
-            if let Some(c) = component {
-                size += c.get_data_size(self.width, self.height, align);
-            }
-        }
-        size
+        imgutils::get_buffer_size(&self.format, self.width, self.height, align)
     }
 }
 
@@ -293,6 +455,11 @@ pub trait FrameBuffer: Send + Sync {
     fn count(&self) -> usize;
     fn as_slice_inner(&self, idx: usize) -> Result<&[u8], FrameError>;
     fn as_mut_slice_inner(&mut self, idx: usize) -> Result<&mut [u8], FrameError>;
+    /// Returns the hardware frame holding the planes, if they are not in
+    /// system memory.
+    fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+        None
+    }
 }
 
 mod private {
@@ -392,17 +559,17 @@ impl DefaultFrameBuffer {
                 let buf = BytesMut::from(unsafe { &Vec::from_raw_parts(data, size, size)[..] });
                 let mut buffer = DefaultFrameBuffer {
                     buf,
-                    planes: Vec::with_capacity(video.format.get_num_comp()),
+                    planes: Vec::with_capacity(imgutils::get_num_planes(&video.format)),
                 };
-                for &component in video.format.iter() {
-                    if let Some(c) = component {
-                        let planesize = c.get_data_size(video.width, video.height, ALIGNMENT);
-                        let linesize = c.get_linesize(video.width, ALIGNMENT);
-                        buffer.planes.push(Plane {
-                            buf: buffer.buf.split_to(planesize),
-                            linesize,
-                        });
-                    }
+                for plane in 0..imgutils::get_num_planes(&video.format) {
+                    let fmt = &video.format;
+                    let planesize =
+                        imgutils::get_plane_size(fmt, plane, video.width, video.height, ALIGNMENT);
+                    let linesize = imgutils::get_plane_linesize(fmt, plane, video.width, ALIGNMENT);
+                    buffer.planes.push(Plane {
+                        buf: buffer.buf.split_to(planesize),
+                        linesize,
+                    });
                 }
                 buffer
             }
@@ -447,6 +614,9 @@ pub struct Frame {
     pub buf: Box<dyn FrameBuffer>,
     /// Timestamp information associated to a frame.
     pub t: TimeInfo,
+    /// Additional information attached to a frame
+    /// (e.g. the results of an analysis filter).
+    pub metadata: Metadata,
 }
 
 impl Frame {
@@ -462,19 +632,79 @@ impl Frame {
             kind: k,
             buf: Box::new(buf),
             t: t.unwrap_or_default(),
+            metadata: Metadata::new(),
         }
     }
+
+    fn field_plane_geometry(
+        &self,
+        idx: usize,
+        field: Field,
+    ) -> Result<(usize, usize, usize), FrameError> {
+        let video = match self.kind {
+            MediaKind::Video(ref video) => video,
+            MediaKind::Audio(_) => return Err(InvalidIndex),
+        };
+        let linesize = self.buf.linesize(idx)?;
+        let height = imgutils::get_plane_height(&video.format, idx, video.height);
+        let top_lines = height.div_ceil(2);
+        let lines = match field {
+            Field::Top => top_lines,
+            Field::Bottom => height / 2,
+        };
+
+        let geometry = match (video.fields.layout, field) {
+            (FieldLayout::Interleaved, Field::Top) => (0, 2 * linesize, lines),
+            (FieldLayout::Interleaved, Field::Bottom) => (linesize, 2 * linesize, lines),
+            (FieldLayout::Separate, Field::Top) => (0, linesize, lines),
+            (FieldLayout::Separate, Field::Bottom) => (top_lines * linesize, linesize, lines),
+        };
+
+        Ok(geometry)
+    }
+
+    /// Returns the data of a single field of a video plane, together with
+    /// the distance in bytes between its lines and its number of lines.
+    pub fn field_plane(
+        &self,
+        idx: usize,
+        field: Field,
+    ) -> Result<(&[u8], usize, usize), FrameError> {
+        let (offset, stride, lines) = self.field_plane_geometry(idx, field)?;
+        let data = self.buf.as_slice_inner(idx)?;
+
+        Ok((&data[offset.min(data.len())..], stride, lines))
+    }
+
+    /// Returns the mutable data of a single field of a video plane, together
+    /// with the distance in bytes between its lines and its number of lines.
+    pub fn field_plane_mut(
+        &mut self,
+        idx: usize,
+        field: Field,
+    ) -> Result<(&mut [u8], usize, usize), FrameError> {
+        let (offset, stride, lines) = self.field_plane_geometry(idx, field)?;
+        let data = self.buf.as_mut_slice_inner(idx)?;
+        let offset = offset.min(data.len());
+
+        Ok((&mut data[offset..], stride, lines))
+    }
 }
 
 impl FrameBufferCopy for Frame {
     fn copy_plane_to_buffer(&self, plane_index: usize, dst: &mut [u8], dst_linesize: usize) {
         if let MediaKind::Video(ref fmt) = self.kind {
-            let width = fmt.width;
-            let height = fmt.height;
             let src = self.buf.as_slice_inner(plane_index).unwrap();
             let src_linesize = self.buf.linesize(plane_index).unwrap();
 
-            copy_plane(dst, dst_linesize, src, src_linesize, width, height);
+            imgutils::copy_plane(
+                dst,
+                dst_linesize,
+                src,
+                src_linesize,
+                imgutils::get_plane_bytewidth(&fmt.format, plane_index, fmt.width),
+                imgutils::get_plane_height(&fmt.format, plane_index, fmt.height),
+            );
         } else {
             unimplemented!();
         }
@@ -486,19 +716,17 @@ impl FrameBufferCopy for Frame {
         IU: Iterator<Item = usize>,
     {
         if let MediaKind::Video(ref fmt) = self.kind {
-            let width = fmt.width;
-            let height = fmt.height;
             let dst_iter = dst.zip(dst_linesizes);
-            let iter = dst_iter.zip(0..self.buf.count()).zip(fmt.format.iter());
+            let iter = dst_iter.zip(0..self.buf.count());
 
-            for (((d, d_linesize), plane_index), c) in iter {
-                copy_plane(
+            for ((d, d_linesize), plane_index) in iter {
+                imgutils::copy_plane(
                     d,
                     d_linesize,
                     self.buf.as_slice_inner(plane_index).unwrap(),
                     self.buf.linesize(plane_index).unwrap(),
-                    c.unwrap().get_width(width),
-                    c.unwrap().get_height(height),
+                    imgutils::get_plane_bytewidth(&fmt.format, plane_index, fmt.width),
+                    imgutils::get_plane_height(&fmt.format, plane_index, fmt.height),
                 );
             }
         } else {
@@ -514,22 +742,18 @@ impl FrameBufferCopy for Frame {
         IU: Iterator<Item = usize>,
     {
         if let MediaKind::Video(ref fmt) = self.kind {
-            let mut f_iter = fmt.format.iter();
-            let width = fmt.width;
-            let height = fmt.height;
             for i in 0..self.buf.count() {
                 let d_linesize = self.buf.linesize(i).unwrap();
                 let s_linesize = src_linesize.next().unwrap();
                 let data = self.buf.as_mut_slice(i).unwrap();
                 let ss = src.next().unwrap();
-                let cc = f_iter.next().unwrap();
-                copy_plane(
+                imgutils::copy_plane(
                     data,
                     d_linesize,
                     ss,
                     s_linesize,
-                    cc.unwrap().get_width(width),
-                    cc.unwrap().get_height(height),
+                    imgutils::get_plane_bytewidth(&fmt.format, i, fmt.width),
+                    imgutils::get_plane_height(&fmt.format, i, fmt.height),
                 );
             }
         } else {
@@ -538,24 +762,6 @@ impl FrameBufferCopy for Frame {
     }
 }
 
-fn copy_plane(
-    dst: &mut [u8],
-    dst_linesize: usize,
-    src: &[u8],
-    src_linesize: usize,
-    width: usize,
-    height: usize,
-) {
-    let dst_chunks = dst.chunks_mut(dst_linesize);
-    let src_chunks = src.chunks(src_linesize);
-
-    for (d, s) in dst_chunks.zip(src_chunks).take(height) {
-        unsafe {
-            copy_nonoverlapping(s.as_ptr(), d.as_mut_ptr(), width);
-        }
-    }
-}
-
 /// A specialised type for reference-counted `Frame`
 pub type ArcFrame = Arc<Frame>;
 
@@ -613,6 +819,79 @@ mod test {
         assert_eq!(info1 == info2, false);
     }
 
+    #[test]
+    fn test_frame_planes() {
+        use crate::pixel::formats::{RGB24, YUV420_10};
+
+        let fm = Arc::new(*RGB24);
+        let video_info = VideoInfo::new(42, 42, false, FrameType::I, fm);
+        let frame = Frame::new_default_frame(video_info, None);
+
+        assert_eq!(frame.buf.count(), 1);
+        assert_eq!(frame.buf.linesize(0).unwrap(), 128);
+
+        let fm = Arc::new(*YUV420_10);
+        let video_info = VideoInfo::new(42, 42, false, FrameType::I, fm);
+        let frame = Frame::new_default_frame(video_info, None);
+
+        assert_eq!(frame.buf.count(), 3);
+        assert_eq!(frame.buf.linesize(0).unwrap(), 96);
+        assert_eq!(frame.buf.linesize(1).unwrap(), 64);
+        assert_eq!(frame.buf.as_slice_inner(1).unwrap().len(), 64 * 21);
+    }
+
+    #[test]
+    fn test_field_info() {
+        let info = FieldInfo::from_h264_pic_struct(0).unwrap();
+        assert_eq!(info, FieldInfo::default());
+        assert_eq!(info.get_display_fields(), 2);
+
+        let info = FieldInfo::from_h264_pic_struct(6).unwrap();
+        assert!(info.interlaced && info.repeat_first_field);
+        assert_eq!(info.first_field, Field::Bottom);
+        assert_eq!(info.get_display_fields(), 3);
+
+        let info = FieldInfo::from_h264_pic_struct(8).unwrap();
+        assert_eq!(info.get_display_fields(), 6);
+
+        assert!(FieldInfo::from_h264_pic_struct(9).is_none());
+
+        let info = FieldInfo::from_h264_pic_struct(2).unwrap();
+        assert_eq!(info.single_field, Some(Field::Bottom));
+        assert_eq!(info.get_display_fields(), 1);
+
+        let info = FieldInfo::from_mpeg2(false, None, true, false, true);
+        assert!(!info.interlaced && info.repeat_first_field);
+        assert_eq!(info.first_field, Field::Bottom);
+
+        let info = FieldInfo::from_mpeg2(true, None, true, true, true);
+        assert_eq!(info.repeat_frame, 2);
+        assert!(!info.repeat_first_field);
+
+        let info = FieldInfo::from_mpeg2(false, Some(Field::Top), false, true, false);
+        assert_eq!(info, FieldInfo::single(Field::Top));
+    }
+
+    #[test]
+    fn test_field_plane() {
+        use crate::pixel::formats::YUV420;
+
+        let fm = Arc::new(*YUV420);
+        let mut video_info = VideoInfo::new(32, 5, false, FrameType::I, fm);
+        let mut frame = Frame::new_default_frame(video_info.clone(), None);
+
+        let linesize = frame.buf.linesize(0).unwrap();
+        let (top, stride, lines) = frame.field_plane(0, Field::Top).unwrap();
+        assert_eq!((top.len(), stride, lines), (5 * linesize, 2 * linesize, 3));
+        let (bottom, _, lines) = frame.field_plane_mut(0, Field::Bottom).unwrap();
+        assert_eq!((bottom.len(), lines), (4 * linesize, 2));
+
+        video_info.fields.layout = FieldLayout::Separate;
+        frame.kind = MediaKind::Video(video_info);
+        let (bottom, stride, lines) = frame.field_plane(0, Field::Bottom).unwrap();
+        assert_eq!((bottom.len(), stride, lines), (2 * linesize, linesize, 2));
+    }
+
     #[test]
     #[should_panic]
     // FIXME: On Windows this test does not work
@@ -0,0 +1,120 @@
+//!
+//! High dynamic range metadata.
+//!
+//! Values are stored with the same units used by the HEVC/AV1 SEI and
+//! metadata OBUs, so they can be carried around losslessly.
+//!
+
+/// Chromaticity coordinates in increments of 0.00002.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Chromaticity {
+    /// Normalized x chromaticity coordinate.
+    pub x: u16,
+    /// Normalized y chromaticity coordinate.
+    pub y: u16,
+}
+
+impl Chromaticity {
+    /// Returns the (x, y) chromaticity coordinates.
+    pub fn get_xy(&self) -> (f64, f64) {
+        (f64::from(self.x) * 0.00002, f64::from(self.y) * 0.00002)
+    }
+}
+
+/// Mastering display colour volume (SMPTE ST 2086).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    /// Display primaries in red, green, blue order.
+    pub primaries: [Chromaticity; 3],
+    /// Display white point.
+    pub white_point: Chromaticity,
+    /// Maximum display luminance in increments of 0.0001 cd/m².
+    pub max_luminance: u32,
+    /// Minimum display luminance in increments of 0.0001 cd/m².
+    pub min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    /// Returns the maximum display luminance in cd/m².
+    pub fn get_max_luminance(&self) -> f64 {
+        f64::from(self.max_luminance) * 0.0001
+    }
+    /// Returns the minimum display luminance in cd/m².
+    pub fn get_min_luminance(&self) -> f64 {
+        f64::from(self.min_luminance) * 0.0001
+    }
+}
+
+/// Content light level information (CTA-861.3).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    /// Maximum content light level in cd/m².
+    pub max_cll: u16,
+    /// Maximum frame-average light level in cd/m².
+    pub max_fall: u16,
+}
+
+/// High dynamic range metadata associated to a video frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HdrMetadata {
+    /// Mastering display colour volume, if known.
+    pub mastering_display: Option<MasteringDisplay>,
+    /// Content light level, if known.
+    pub content_light: Option<ContentLightLevel>,
+}
+
+impl HdrMetadata {
+    /// Returns the peak luminance of the content in cd/m², if known.
+    ///
+    /// The content light level is preferred over the mastering display
+    /// luminance, since it describes the actual content.
+    pub fn get_peak_luminance(&self) -> Option<f64> {
+        self.content_light
+            .filter(|cll| cll.max_cll > 0)
+            .map(|cll| f64::from(cll.max_cll))
+            .or_else(|| {
+                self.mastering_display
+                    .filter(|md| md.max_luminance > 0)
+                    .map(|md| md.get_max_luminance())
+            })
+    }
+
+    /// Tells whether no metadata is present.
+    pub fn is_empty(&self) -> bool {
+        self.mastering_display.is_none() && self.content_light.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn peak_luminance() {
+        let mut hdr = HdrMetadata::default();
+        assert!(hdr.is_empty());
+        assert_eq!(hdr.get_peak_luminance(), None);
+
+        hdr.mastering_display = Some(MasteringDisplay {
+            primaries: [
+                Chromaticity { x: 34000, y: 16000 },
+                Chromaticity { x: 13250, y: 34500 },
+                Chromaticity { x: 7500, y: 3000 },
+            ],
+            white_point: Chromaticity { x: 15635, y: 16450 },
+            max_luminance: 10_000_000,
+            min_luminance: 50,
+        });
+        assert_eq!(hdr.get_peak_luminance(), Some(1000.0));
+        assert_eq!(
+            hdr.mastering_display.unwrap().white_point.get_xy(),
+            (15635.0 * 0.00002, 16450.0 * 0.00002)
+        );
+
+        hdr.content_light = Some(ContentLightLevel {
+            max_cll: 800,
+            max_fall: 400,
+        });
+        assert_eq!(hdr.get_peak_luminance(), Some(800.0));
+    }
+}
@@ -0,0 +1,362 @@
+//!
+//! Hardware acceleration devices.
+//!
+//! A `HwDevice` is a reference counted handle to a device context of a
+//! hardware acceleration API, meant to be created once by the application
+//! and shared by the decoders, filters and encoders using the device.
+//!
+//! Backends implement `HwDeviceBackend` to open devices and
+//! `HwDeviceContext` to expose them, possibly allowing the derivation of
+//! contexts of other APIs sharing the same physical device.
+//!
+//! Frames kept in the memory of a device expose it through `HwFrame`, so
+//! that the components sharing the device can use them without copies.
+//!
+
+use std::any::Any;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+
+use thiserror::Error;
+
+/// Hardware device errors.
+#[derive(Debug, Error)]
+pub enum HwDeviceError {
+    /// The requested device or derivation is not supported.
+    #[error("Unsupported {0}")]
+    Unsupported(String),
+    /// The device could not be opened.
+    #[error("Device creation failed: {0}")]
+    Creation(String),
+}
+
+/// A specialised `Result` type for hardware device operations.
+pub type Result<T> = ::std::result::Result<T, HwDeviceError>;
+
+/// Hardware acceleration APIs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HwDeviceType {
+    /// Video Acceleration API.
+    Vaapi,
+    /// Video Decode and Presentation API for Unix.
+    Vdpau,
+    /// NVIDIA CUDA.
+    Cuda,
+    /// Direct3D 11 Video API.
+    D3d11va,
+    /// Apple VideoToolbox.
+    VideoToolbox,
+    /// Intel oneVPL/Quick Sync Video.
+    Qsv,
+    /// Vulkan.
+    Vulkan,
+    /// wgpu.
+    Wgpu,
+}
+
+impl fmt::Display for HwDeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            HwDeviceType::Vaapi => "VAAPI",
+            HwDeviceType::Vdpau => "VDPAU",
+            HwDeviceType::Cuda => "CUDA",
+            HwDeviceType::D3d11va => "D3D11VA",
+            HwDeviceType::VideoToolbox => "VideoToolbox",
+            HwDeviceType::Qsv => "QSV",
+            HwDeviceType::Vulkan => "Vulkan",
+            HwDeviceType::Wgpu => "wgpu",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Selects the physical device to open.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The default device of the API.
+    Default,
+    /// A DRM render node (e.g. `/dev/dri/renderD128`).
+    DrmNode(PathBuf),
+    /// The index of an adapter as enumerated by the API.
+    AdapterIndex(u32),
+}
+
+/// Used to implement a device context of a hardware acceleration API.
+pub trait HwDeviceContext: Send + Sync + fmt::Debug {
+    /// Returns the API of the context.
+    fn device_type(&self) -> HwDeviceType;
+    /// Creates a context of another API sharing the same physical device.
+    ///
+    /// Returns `None` if the derivation is not supported.
+    fn derive(&self, ty: HwDeviceType) -> Option<Box<dyn HwDeviceContext>> {
+        let _ = ty;
+        None
+    }
+    /// Returns the context as `Any`, to access the API handles.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Used to open device contexts of a hardware acceleration API.
+pub trait HwDeviceBackend {
+    /// Returns the API of the contexts created.
+    fn device_type(&self) -> HwDeviceType;
+    /// Opens a device context.
+    fn create(&self, selector: &DeviceSelector) -> Result<Box<dyn HwDeviceContext>>;
+}
+
+/// Used to implement a frame stored in the memory of a hardware device.
+///
+/// A frame buffer returns its hardware frame through
+/// `FrameBuffer::as_hw_frame`, its planes not being accessible as slices.
+pub trait HwFrame: Send + Sync + fmt::Debug {
+    /// Returns the device holding the frame.
+    fn get_device(&self) -> &HwDevice;
+    /// Returns the frame as `Any`, to access the API handles.
+    fn as_any(&self) -> &dyn Any;
+}
+
+#[derive(Debug)]
+struct Inner {
+    ctx: Box<dyn HwDeviceContext>,
+    selector: DeviceSelector,
+    source: Option<HwDevice>,
+    derived: Mutex<Vec<Weak<Inner>>>,
+}
+
+/// Reference counted hardware device.
+///
+/// Cloning a `HwDevice` returns another handle to the same device.
+#[derive(Clone, Debug)]
+pub struct HwDevice {
+    inner: Arc<Inner>,
+}
+
+impl HwDevice {
+    /// Opens a device through a backend.
+    pub fn create(backend: &dyn HwDeviceBackend, selector: DeviceSelector) -> Result<Self> {
+        let ctx = backend.create(&selector)?;
+        if ctx.device_type() != backend.device_type() {
+            return Err(HwDeviceError::Creation(format!(
+                "{} backend returned a {} context",
+                backend.device_type(),
+                ctx.device_type()
+            )));
+        }
+
+        Ok(Self::new(ctx, selector, None))
+    }
+
+    /// Wraps an already opened device context.
+    pub fn from_context(ctx: Box<dyn HwDeviceContext>) -> Self {
+        Self::new(ctx, DeviceSelector::Default, None)
+    }
+
+    fn new(
+        ctx: Box<dyn HwDeviceContext>,
+        selector: DeviceSelector,
+        source: Option<HwDevice>,
+    ) -> Self {
+        HwDevice {
+            inner: Arc::new(Inner {
+                ctx,
+                selector,
+                source,
+                derived: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the API of the device.
+    pub fn get_type(&self) -> HwDeviceType {
+        self.inner.ctx.device_type()
+    }
+
+    /// Returns the selector the device was opened with.
+    pub fn get_selector(&self) -> &DeviceSelector {
+        &self.inner.selector
+    }
+
+    /// Returns the device this one was derived from, if any.
+    pub fn get_source(&self) -> Option<&HwDevice> {
+        self.inner.source.as_ref()
+    }
+
+    /// Returns the device context.
+    pub fn get_context(&self) -> &dyn HwDeviceContext {
+        &*self.inner.ctx
+    }
+
+    /// Returns the device context as its concrete type, if it matches.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.inner.ctx.as_any().downcast_ref()
+    }
+
+    /// Returns a device of the requested API sharing the same physical
+    /// device.
+    ///
+    /// The device itself is returned if it already has the requested API,
+    /// a source device or a live derived device is reused if possible.
+    pub fn derive(&self, ty: HwDeviceType) -> Result<HwDevice> {
+        if self.get_type() == ty {
+            return Ok(self.clone());
+        }
+
+        let mut source = self.get_source();
+        while let Some(dev) = source {
+            if dev.get_type() == ty {
+                return Ok(dev.clone());
+            }
+            source = dev.get_source();
+        }
+
+        let mut derived = self.inner.derived.lock().unwrap();
+        derived.retain(|d| d.strong_count() > 0);
+        let existing = derived
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|d| d.ctx.device_type() == ty);
+        if let Some(inner) = existing {
+            return Ok(HwDevice { inner });
+        }
+
+        let ctx = self.inner.ctx.derive(ty).ok_or_else(|| {
+            HwDeviceError::Unsupported(format!("derivation from {} to {}", self.get_type(), ty))
+        })?;
+        let dev = Self::new(ctx, self.inner.selector.clone(), Some(self.clone()));
+        derived.push(Arc::downgrade(&dev.inner));
+
+        Ok(dev)
+    }
+
+    /// Tells if two handles refer to the same device.
+    pub fn ptr_eq(&self, other: &HwDevice) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::{Frame, FrameBuffer, FrameError, FrameType, VideoInfo};
+    use crate::pixel::formats::YUV420;
+
+    #[derive(Debug)]
+    struct Mock {
+        ty: HwDeviceType,
+        node: Option<PathBuf>,
+    }
+
+    impl HwDeviceContext for Mock {
+        fn device_type(&self) -> HwDeviceType {
+            self.ty
+        }
+        fn derive(&self, ty: HwDeviceType) -> Option<Box<dyn HwDeviceContext>> {
+            match (self.ty, ty) {
+                (HwDeviceType::Vaapi, HwDeviceType::Vulkan) => Some(Box::new(Mock {
+                    ty,
+                    node: self.node.clone(),
+                })),
+                _ => None,
+            }
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    struct MockBackend;
+
+    impl HwDeviceBackend for MockBackend {
+        fn device_type(&self) -> HwDeviceType {
+            HwDeviceType::Vaapi
+        }
+        fn create(&self, selector: &DeviceSelector) -> Result<Box<dyn HwDeviceContext>> {
+            match selector {
+                DeviceSelector::DrmNode(node) => Ok(Box::new(Mock {
+                    ty: HwDeviceType::Vaapi,
+                    node: Some(node.clone()),
+                })),
+                _ => Err(HwDeviceError::Creation("no render node".to_owned())),
+            }
+        }
+    }
+
+    #[test]
+    fn create() {
+        let node = PathBuf::from("/dev/dri/renderD128");
+        let dev = HwDevice::create(&MockBackend, DeviceSelector::DrmNode(node.clone())).unwrap();
+
+        assert_eq!(dev.get_type(), HwDeviceType::Vaapi);
+        assert_eq!(dev.downcast_ref::<Mock>().unwrap().node, Some(node));
+        assert!(dev.clone().ptr_eq(&dev));
+        assert!(HwDevice::create(&MockBackend, DeviceSelector::AdapterIndex(0)).is_err());
+    }
+
+    #[test]
+    fn derive() {
+        let node = PathBuf::from("/dev/dri/renderD128");
+        let dev = HwDevice::create(&MockBackend, DeviceSelector::DrmNode(node)).unwrap();
+
+        assert!(dev.derive(HwDeviceType::Vaapi).unwrap().ptr_eq(&dev));
+        assert!(dev.derive(HwDeviceType::Cuda).is_err());
+
+        let vk = dev.derive(HwDeviceType::Vulkan).unwrap();
+        assert_eq!(vk.get_type(), HwDeviceType::Vulkan);
+        assert!(vk.get_source().unwrap().ptr_eq(&dev));
+        assert!(dev.derive(HwDeviceType::Vulkan).unwrap().ptr_eq(&vk));
+        assert!(vk.derive(HwDeviceType::Vaapi).unwrap().ptr_eq(&dev));
+
+        drop(vk);
+        let vk = dev.derive(HwDeviceType::Vulkan).unwrap();
+        assert_eq!(vk.get_selector(), dev.get_selector());
+    }
+
+    #[derive(Debug)]
+    struct Surface {
+        device: HwDevice,
+    }
+
+    impl HwFrame for Surface {
+        fn get_device(&self) -> &HwDevice {
+            &self.device
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl FrameBuffer for Surface {
+        fn linesize(&self, _: usize) -> ::std::result::Result<usize, FrameError> {
+            Err(FrameError::InvalidIndex)
+        }
+        fn count(&self) -> usize {
+            3
+        }
+        fn as_slice_inner(&self, _: usize) -> ::std::result::Result<&[u8], FrameError> {
+            Err(FrameError::InvalidIndex)
+        }
+        fn as_mut_slice_inner(&mut self, _: usize) -> ::std::result::Result<&mut [u8], FrameError> {
+            Err(FrameError::InvalidIndex)
+        }
+        fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+            Some(self)
+        }
+    }
+
+    #[test]
+    fn hw_frames() {
+        let node = PathBuf::from("/dev/dri/renderD128");
+        let dev = HwDevice::create(&MockBackend, DeviceSelector::DrmNode(node)).unwrap();
+        let info = VideoInfo::new(16, 16, false, FrameType::I, Arc::new(*YUV420));
+
+        let mut frame = Frame::new_default_frame(info, None);
+        assert!(frame.buf.as_hw_frame().is_none());
+        frame.buf = Box::new(Surface {
+            device: dev.clone(),
+        });
+        let hw = frame.buf.as_hw_frame().unwrap();
+        assert!(hw.get_device().ptr_eq(&dev));
+        assert!(hw.as_any().downcast_ref::<Surface>().is_some());
+    }
+}
@@ -0,0 +1,556 @@
+//!
+//! Image manipulation helpers.
+//!
+//! Describe how images of a determined `Formaton` are laid out in memory
+//! and provide the basic operations needed to manipulate them: copy
+//! between buffers with different strides, solid fills and edge padding.
+//!
+//! Images are stored as a set of planes:
+//!
+//! - planar formats use one plane per component, components deeper than
+//!   8 bits are stored in 16-bit samples using the format endianness.
+//! - packed formats use a single plane of `elem_size`-bytes samples.
+//! - paletted formats use a plane of 8-bit indices followed by a
+//!   256-entries palette plane.
+//!
+
+use crate::pixel::*;
+
+/// Number of entries of the palette plane of paletted formats.
+pub const PALETTE_SIZE: usize = 256;
+
+fn align(v: usize, a: usize) -> usize {
+    (v + a - 1) & !(a - 1)
+}
+
+fn is_packed(fmt: &Formaton) -> bool {
+    fmt.get_chromaton(0).is_some_and(|c| c.is_packed())
+}
+
+fn planar_chromaton(fmt: &Formaton, plane: usize) -> Chromaton {
+    fmt.get_chromaton(plane).expect("Invalid plane index")
+}
+
+fn check_plane(fmt: &Formaton, plane: usize) {
+    assert!(plane < get_num_planes(fmt), "Invalid plane index");
+}
+
+/// Returns the number of planes used to store an image.
+pub fn get_num_planes(fmt: &Formaton) -> usize {
+    if fmt.is_paletted() {
+        2
+    } else if is_packed(fmt) {
+        1
+    } else {
+        fmt.get_num_comp()
+    }
+}
+
+/// Returns the size in bytes of a single sample of a plane.
+///
+/// # Panics
+///
+/// Panics if `plane` is not a valid plane index for the format.
+pub fn get_plane_sample_size(fmt: &Formaton, plane: usize) -> usize {
+    check_plane(fmt, plane);
+    if fmt.is_paletted() {
+        if plane == 0 {
+            1
+        } else {
+            fmt.get_elem_size() as usize
+        }
+    } else if is_packed(fmt) {
+        fmt.get_elem_size() as usize
+    } else {
+        let depth = planar_chromaton(fmt, plane).get_depth() as usize;
+        (depth + 7) >> 3
+    }
+}
+
+/// Returns the width in samples of a plane for an image of the given width.
+///
+/// # Panics
+///
+/// Panics if `plane` is not a valid plane index for the format.
+pub fn get_plane_width(fmt: &Formaton, plane: usize, width: usize) -> usize {
+    check_plane(fmt, plane);
+    if fmt.is_paletted() {
+        if plane == 0 {
+            width
+        } else {
+            PALETTE_SIZE
+        }
+    } else if is_packed(fmt) {
+        width
+    } else {
+        planar_chromaton(fmt, plane).get_width(width)
+    }
+}
+
+/// Returns the height in lines of a plane for an image of the given height.
+///
+/// # Panics
+///
+/// Panics if `plane` is not a valid plane index for the format.
+pub fn get_plane_height(fmt: &Formaton, plane: usize, height: usize) -> usize {
+    check_plane(fmt, plane);
+    if fmt.is_paletted() {
+        if plane == 0 {
+            height
+        } else {
+            1
+        }
+    } else if is_packed(fmt) {
+        height
+    } else {
+        planar_chromaton(fmt, plane).get_height(height)
+    }
+}
+
+/// Returns the number of bytes of meaningful data in a line of a plane.
+pub fn get_plane_bytewidth(fmt: &Formaton, plane: usize, width: usize) -> usize {
+    get_plane_width(fmt, plane, width) * get_plane_sample_size(fmt, plane)
+}
+
+/// Returns the minimal stride of a plane with the specified alignment.
+pub fn get_plane_linesize(fmt: &Formaton, plane: usize, width: usize, alignment: usize) -> usize {
+    align(get_plane_bytewidth(fmt, plane, width), alignment)
+}
+
+/// Returns the size in bytes of a plane with the specified alignment.
+pub fn get_plane_size(
+    fmt: &Formaton,
+    plane: usize,
+    width: usize,
+    height: usize,
+    alignment: usize,
+) -> usize {
+    get_plane_linesize(fmt, plane, width, alignment) * get_plane_height(fmt, plane, height)
+}
+
+/// Returns the size in bytes of a whole image with the specified alignment.
+pub fn get_buffer_size(fmt: &Formaton, width: usize, height: usize, alignment: usize) -> usize {
+    (0..get_num_planes(fmt))
+        .map(|plane| get_plane_size(fmt, plane, width, height, alignment))
+        .sum()
+}
+
+/// Copies `height` lines of `bytewidth` bytes between two buffers
+/// with different strides.
+///
+/// # Panics
+///
+/// Panics if any of the buffers is too small.
+pub fn copy_plane(
+    dst: &mut [u8],
+    dst_linesize: usize,
+    src: &[u8],
+    src_linesize: usize,
+    bytewidth: usize,
+    height: usize,
+) {
+    if height == 0 || bytewidth == 0 {
+        return;
+    }
+
+    assert!(dst_linesize >= bytewidth && src_linesize >= bytewidth);
+    assert!(dst.len() >= dst_linesize * (height - 1) + bytewidth);
+    assert!(src.len() >= src_linesize * (height - 1) + bytewidth);
+
+    for (d, s) in dst
+        .chunks_mut(dst_linesize)
+        .zip(src.chunks(src_linesize))
+        .take(height)
+    {
+        d[..bytewidth].copy_from_slice(&s[..bytewidth]);
+    }
+}
+
+/// Copies a whole image between two sets of planes with different strides.
+///
+/// # Panics
+///
+/// Panics if fewer planes than the format needs are provided or if any of
+/// them is too small.
+pub fn copy_image(
+    fmt: &Formaton,
+    dst: &mut [&mut [u8]],
+    dst_linesizes: &[usize],
+    src: &[&[u8]],
+    src_linesizes: &[usize],
+    width: usize,
+    height: usize,
+) {
+    for plane in 0..get_num_planes(fmt) {
+        copy_plane(
+            dst[plane],
+            dst_linesizes[plane],
+            src[plane],
+            src_linesizes[plane],
+            get_plane_bytewidth(fmt, plane, width),
+            get_plane_height(fmt, plane, height),
+        );
+    }
+}
+
+/// Fills `height` lines of a plane repeating the sample `value`
+/// `width` times per line.
+///
+/// # Panics
+///
+/// Panics if the buffer is too small.
+pub fn fill_plane(dst: &mut [u8], linesize: usize, value: &[u8], width: usize, height: usize) {
+    let bytewidth = value.len() * width;
+
+    if height == 0 || bytewidth == 0 {
+        return;
+    }
+
+    assert!(linesize >= bytewidth);
+    assert!(dst.len() >= linesize * (height - 1) + bytewidth);
+
+    for line in dst.chunks_mut(linesize).take(height) {
+        for sample in line[..bytewidth].chunks_mut(value.len()) {
+            sample.copy_from_slice(value);
+        }
+    }
+}
+
+fn put_sample(buf: &mut [u8], value: u64, be: bool) {
+    let len = buf.len();
+    for (i, b) in buf.iter_mut().enumerate() {
+        let shift = if be { (len - 1 - i) * 8 } else { i * 8 };
+        *b = (value >> shift) as u8;
+    }
+}
+
+/// Returns the bytes representing a sample of a plane set to the given
+/// component values.
+///
+/// `color` holds at least one value per component, in the format component
+/// order.
+fn get_plane_sample(fmt: &Formaton, plane: usize, color: &[u16]) -> Vec<u8> {
+    let size = get_plane_sample_size(fmt, plane);
+    let mut sample = vec![0; size];
+
+    if fmt.is_paletted() {
+        // Every palette entry is set to the color, indices are left to 0.
+        if plane == 1 {
+            for (i, c) in fmt.iter().flatten().enumerate() {
+                sample[c.get_offset() as usize] = color[i] as u8;
+            }
+        }
+    } else if is_packed(fmt) {
+        let mut word = 0u64;
+        for (i, c) in fmt.iter().flatten().enumerate() {
+            let v = u64::from(color[i]) & ((1 << c.get_depth()) - 1);
+            if c.get_depth() == 8 && c.get_shift() == 0 {
+                sample[c.get_offset() as usize] = v as u8;
+            } else {
+                word |= v << c.get_shift();
+            }
+        }
+        if word != 0 {
+            let mut packed = vec![0; size];
+            put_sample(&mut packed, word, fmt.is_be());
+            for (s, p) in sample.iter_mut().zip(packed) {
+                *s |= p;
+            }
+        }
+    } else {
+        put_sample(&mut sample, color[plane].into(), fmt.is_be());
+    }
+
+    sample
+}
+
+/// Returns the component values representing black for the given format.
+///
+/// Limited range YUV formats use the nominal black level, the alpha
+/// component, if any, is fully opaque.
+pub fn get_black_color(fmt: &Formaton) -> Vec<u16> {
+    let limited = fmt.get_model()
+        == ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(YUVSystem::YCbCr(
+            YUVRange::Limited,
+        )));
+    let yuv = matches!(
+        fmt.get_model(),
+        ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(_))
+    );
+    let num_comp = fmt.get_num_comp();
+
+    fmt.iter()
+        .flatten()
+        .enumerate()
+        .map(|(i, c)| {
+            let depth = c.get_depth() as u32;
+            if fmt.has_alpha() && i == num_comp - 1 {
+                ((1u32 << depth) - 1) as u16
+            } else if yuv && i > 0 {
+                (1u32 << (depth - 1)) as u16
+            } else if limited && depth >= 8 {
+                (16u32 << (depth - 8)) as u16
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// Fills a whole image with a solid color.
+///
+/// `color` holds one value per component, in the format component order.
+/// Paletted images are filled with the index 0, which is set to the color.
+///
+/// # Panics
+///
+/// Panics if `color` holds fewer values than the format has components, if
+/// fewer planes than the format needs are provided or if any of them is too
+/// small.
+pub fn fill_image(
+    fmt: &Formaton,
+    dst: &mut [&mut [u8]],
+    linesizes: &[usize],
+    width: usize,
+    height: usize,
+    color: &[u16],
+) {
+    assert!(color.len() >= fmt.get_num_comp());
+
+    for plane in 0..get_num_planes(fmt) {
+        let sample = get_plane_sample(fmt, plane, color);
+        fill_plane(
+            dst[plane],
+            linesizes[plane],
+            &sample,
+            get_plane_width(fmt, plane, width),
+            get_plane_height(fmt, plane, height),
+        );
+    }
+}
+
+/// Fills a whole image with black.
+pub fn fill_black(
+    fmt: &Formaton,
+    dst: &mut [&mut [u8]],
+    linesizes: &[usize],
+    width: usize,
+    height: usize,
+) {
+    fill_image(fmt, dst, linesizes, width, height, &get_black_color(fmt));
+}
+
+/// Amount of padding around an image, in samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Padding {
+    /// Samples on the left of each line.
+    pub left: usize,
+    /// Samples on the right of each line.
+    pub right: usize,
+    /// Lines above the image.
+    pub top: usize,
+    /// Lines below the image.
+    pub bottom: usize,
+}
+
+/// Fills the padding area of a plane replicating the image edges.
+///
+/// The `width`x`height` image is stored at `pad.left`, `pad.top` within
+/// `buf`, the plane is `pad.left + width + pad.right` samples wide.
+///
+/// # Panics
+///
+/// Panics if the buffer is too small.
+pub fn pad_plane(
+    buf: &mut [u8],
+    linesize: usize,
+    sample_size: usize,
+    width: usize,
+    height: usize,
+    pad: &Padding,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let total_width = (pad.left + width + pad.right) * sample_size;
+    let total_height = pad.top + height + pad.bottom;
+
+    assert!(linesize >= total_width);
+    assert!(buf.len() >= linesize * (total_height - 1) + total_width);
+
+    let start = pad.left * sample_size;
+    let end = start + width * sample_size;
+
+    for line in buf
+        .chunks_mut(linesize)
+        .skip(pad.top)
+        .take(height)
+        .map(|l| &mut l[..total_width])
+    {
+        let (left, rest) = line.split_at_mut(start);
+        let (image, right) = rest.split_at_mut(end - start);
+
+        let first = &image[..sample_size];
+        for s in left.chunks_mut(sample_size) {
+            s.copy_from_slice(first);
+        }
+        let last = &image[image.len() - sample_size..];
+        for s in right.chunks_mut(sample_size) {
+            s.copy_from_slice(last);
+        }
+    }
+
+    let first = pad.top * linesize;
+    for i in 0..pad.top {
+        buf.copy_within(first..first + total_width, i * linesize);
+    }
+    let last = (pad.top + height - 1) * linesize;
+    for i in pad.top + height..total_height {
+        buf.copy_within(last..last + total_width, i * linesize);
+    }
+}
+
+/// Fills the padding area of all the planes of an image replicating
+/// the image edges.
+///
+/// `pad` refers to the full resolution plane, subsampled planes are
+/// padded accordingly.
+pub fn pad_image(
+    fmt: &Formaton,
+    dst: &mut [&mut [u8]],
+    linesizes: &[usize],
+    width: usize,
+    height: usize,
+    pad: &Padding,
+) {
+    for plane in 0..get_num_planes(fmt) {
+        if fmt.is_paletted() && plane == 1 {
+            continue;
+        }
+        let plane_pad = Padding {
+            left: get_plane_width(fmt, plane, pad.left),
+            right: get_plane_width(fmt, plane, pad.right),
+            top: get_plane_height(fmt, plane, pad.top),
+            bottom: get_plane_height(fmt, plane, pad.bottom),
+        };
+        pad_plane(
+            dst[plane],
+            linesizes[plane],
+            get_plane_sample_size(fmt, plane),
+            get_plane_width(fmt, plane, width),
+            get_plane_height(fmt, plane, height),
+            &plane_pad,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pixel::formats::*;
+
+    #[test]
+    fn plane_layout() {
+        assert_eq!(get_num_planes(YUV420), 3);
+        assert_eq!(get_num_planes(RGB24), 1);
+        assert_eq!(get_num_planes(PAL8), 2);
+
+        assert_eq!(get_plane_linesize(YUV420, 0, 33, 1), 33);
+        assert_eq!(get_plane_linesize(YUV420, 1, 33, 1), 17);
+        assert_eq!(get_plane_height(YUV420, 1, 33), 17);
+        assert_eq!(get_plane_linesize(YUV420, 0, 33, 32), 64);
+
+        assert_eq!(get_plane_linesize(YUV422, 1, 32, 1), 16);
+        assert_eq!(get_plane_height(YUV422, 1, 32), 32);
+
+        assert_eq!(get_plane_linesize(YUV420_10, 0, 32, 1), 64);
+        assert_eq!(get_plane_linesize(YUV420_10, 2, 32, 1), 32);
+
+        assert_eq!(get_plane_linesize(RGB24, 0, 10, 1), 30);
+        assert_eq!(get_plane_linesize(RGBA, 0, 10, 1), 40);
+        assert_eq!(get_plane_linesize(RGB565, 0, 10, 1), 20);
+
+        assert_eq!(get_plane_size(PAL8, 1, 10, 10, 1), 768);
+        assert_eq!(get_buffer_size(YUV420, 16, 16, 1), 16 * 16 + 2 * 8 * 8);
+    }
+
+    #[test]
+    fn copy() {
+        let src: Vec<u8> = (0..24).collect();
+        let mut dst = vec![0; 8];
+
+        copy_plane(&mut dst, 4, &src, 6, 3, 2);
+        assert_eq!(dst, [0, 1, 2, 0, 6, 7, 8, 0]);
+    }
+
+    #[test]
+    fn fill() {
+        let mut y = vec![0; 4 * 2];
+        let mut u = vec![0; 2];
+        let mut v = vec![0; 2];
+
+        fill_black(YUV420, &mut [&mut y, &mut u, &mut v], &[4, 2, 2], 3, 2);
+        assert_eq!(y, [16, 16, 16, 0, 16, 16, 16, 0]);
+        assert_eq!(u, [128, 128]);
+        assert_eq!(v, [128, 128]);
+
+        let mut y = vec![0; 8];
+        let mut u = vec![0; 2];
+        let mut v = vec![0; 2];
+        fill_black(YUV420_10, &mut [&mut y, &mut u, &mut v], &[4, 2, 2], 2, 2);
+        assert_eq!(y, [64, 0, 64, 0, 64, 0, 64, 0]);
+        assert_eq!(u, [0, 2]);
+
+        let mut rgba = vec![0; 8];
+        fill_black(RGBA, &mut [&mut rgba], &[8], 2, 1);
+        assert_eq!(rgba, [255, 0, 0, 0, 255, 0, 0, 0]);
+
+        let mut rgb = vec![0; 4];
+        fill_image(RGB565, &mut [&mut rgb], &[4], 2, 1, &[31, 0, 31]);
+        assert_eq!(rgb, [0x1f, 0xf8, 0x1f, 0xf8]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn fill_missing_component() {
+        let mut y = vec![0; 4];
+        let mut u = vec![0; 1];
+        let mut v = vec![0; 1];
+        fill_image(
+            YUV420,
+            &mut [&mut y, &mut u, &mut v],
+            &[2, 1, 1],
+            2,
+            2,
+            &[16, 128],
+        );
+    }
+
+    #[test]
+    fn padding() {
+        #[rustfmt::skip]
+        let mut buf = vec![
+            0, 0, 0, 0, 0,
+            0, 1, 2, 0, 0,
+            0, 3, 4, 0, 0,
+            0, 0, 0, 0, 0,
+        ];
+        let pad = Padding {
+            left: 1,
+            right: 2,
+            top: 1,
+            bottom: 1,
+        };
+
+        pad_plane(&mut buf, 5, 1, 2, 2, &pad);
+
+        #[rustfmt::skip]
+        assert_eq!(buf, [
+            1, 1, 2, 2, 2,
+            1, 1, 2, 2, 2,
+            3, 3, 4, 4, 4,
+            3, 3, 4, 4, 4,
+        ]);
+    }
+}
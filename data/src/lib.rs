@@ -1,11 +1,14 @@
-pub mod rational {
-    pub use num_rational::*;
-}
-
 pub mod audiosample;
+pub mod budget;
 pub mod frame;
+pub mod hdr;
+pub mod hwdevice;
+pub mod imgutils;
+pub mod metadata;
+pub mod options;
 pub mod packet;
 pub mod params;
 pub mod pixel;
+pub mod rational;
 pub mod timeinfo;
 pub mod value;
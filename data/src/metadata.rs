@@ -0,0 +1,304 @@
+//!
+//! Key/value metadata dictionary.
+//!
+//! Used to attach free-form information (e.g. analysis results produced
+//! by a filter) to a frame without defining a dedicated data structure
+//! for each of them.
+//!
+//! Keys are free-form strings, the convention is to prefix them with
+//! the name of the component producing them (e.g. `scdet.score`).
+//!
+
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::convert::From;
+use std::fmt;
+
+/// Metadata entry value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetaValue {
+    /// Signed integer value.
+    I64(i64),
+    /// Unsigned integer value.
+    U64(u64),
+    /// Floating point value.
+    F64(f64),
+    /// Boolean value.
+    Bool(bool),
+    /// Pair of signed integer values.
+    Pair(i64, i64),
+    /// Unicode string value.
+    Str(String),
+}
+
+impl MetaValue {
+    /// Returns the value as a signed integer, if it is an integer
+    /// representable as such.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            MetaValue::I64(v) => Some(v),
+            MetaValue::U64(v) if v <= i64::MAX as u64 => Some(v as i64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an unsigned integer, if it is an integer
+    /// representable as such.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            MetaValue::U64(v) => Some(v),
+            MetaValue::I64(v) if v >= 0 => Some(v as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a floating point number, if it is numeric.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            MetaValue::F64(v) => Some(v),
+            MetaValue::I64(v) => Some(v as f64),
+            MetaValue::U64(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a boolean, if it is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            MetaValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a pair of signed integers, if it is one.
+    pub fn as_pair(&self) -> Option<(i64, i64)> {
+        match *self {
+            MetaValue::Pair(a, b) => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string slice, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            MetaValue::Str(ref v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MetaValue::I64(v) => write!(f, "{}", v),
+            MetaValue::U64(v) => write!(f, "{}", v),
+            MetaValue::F64(v) => write!(f, "{}", v),
+            MetaValue::Bool(v) => write!(f, "{}", v),
+            MetaValue::Pair(a, b) => write!(f, "{}:{}", a, b),
+            MetaValue::Str(ref v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<i64> for MetaValue {
+    fn from(v: i64) -> Self {
+        MetaValue::I64(v)
+    }
+}
+
+impl From<u64> for MetaValue {
+    fn from(v: u64) -> Self {
+        MetaValue::U64(v)
+    }
+}
+
+impl From<f64> for MetaValue {
+    fn from(v: f64) -> Self {
+        MetaValue::F64(v)
+    }
+}
+
+impl From<bool> for MetaValue {
+    fn from(v: bool) -> Self {
+        MetaValue::Bool(v)
+    }
+}
+
+impl From<(i64, i64)> for MetaValue {
+    fn from(v: (i64, i64)) -> Self {
+        MetaValue::Pair(v.0, v.1)
+    }
+}
+
+impl<'a> From<&'a str> for MetaValue {
+    fn from(v: &'a str) -> Self {
+        MetaValue::Str(v.to_owned())
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(v: String) -> Self {
+        MetaValue::Str(v)
+    }
+}
+
+/// Dictionary of metadata entries, ordered by key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    entries: BTreeMap<String, MetaValue>,
+}
+
+impl Metadata {
+    /// Creates a new empty dictionary.
+    pub fn new() -> Self {
+        Metadata {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Sets an entry, returning the value previously associated to the key.
+    pub fn insert<K, V>(&mut self, key: K, val: V) -> Option<MetaValue>
+    where
+        K: Into<String>,
+        V: Into<MetaValue>,
+    {
+        self.entries.insert(key.into(), val.into())
+    }
+
+    /// Returns the value associated to a key.
+    pub fn get(&self, key: &str) -> Option<&MetaValue> {
+        self.entries.get(key)
+    }
+
+    /// Returns the value associated to a key as a signed integer.
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get(key).and_then(MetaValue::as_i64)
+    }
+
+    /// Returns the value associated to a key as an unsigned integer.
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(MetaValue::as_u64)
+    }
+
+    /// Returns the value associated to a key as a floating point number.
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.get(key).and_then(MetaValue::as_f64)
+    }
+
+    /// Returns the value associated to a key as a boolean.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).and_then(MetaValue::as_bool)
+    }
+
+    /// Returns the value associated to a key as a string slice.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(MetaValue::as_str)
+    }
+
+    /// Removes an entry, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<MetaValue> {
+        self.entries.remove(key)
+    }
+
+    /// Tells whether the dictionary contains a key.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Tells whether the dictionary is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Copies all the entries of another dictionary, overwriting the
+    /// entries with the same key.
+    pub fn merge(&mut self, other: &Metadata) {
+        for (k, v) in other.iter() {
+            self.entries.insert(k.to_owned(), v.clone());
+        }
+    }
+
+    /// Returns an iterator over the entries, ordered by key.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.entries.iter(),
+        }
+    }
+}
+
+/// Iterator over the entries of a `Metadata` dictionary.
+pub struct Iter<'a> {
+    inner: btree_map::Iter<'a, String, MetaValue>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a MetaValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+impl<'a> IntoIterator for &'a Metadata {
+    type Item = (&'a str, &'a MetaValue);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get() {
+        let mut m = Metadata::new();
+
+        assert!(m.insert("scdet.score", 0.5).is_none());
+        m.insert("scdet.cut", true);
+        m.insert("ebur128.lufs", -23i64);
+        m.insert("source", "camera");
+
+        assert_eq!(m.len(), 4);
+        assert_eq!(m.get_f64("scdet.score"), Some(0.5));
+        assert_eq!(m.get_bool("scdet.cut"), Some(true));
+        assert_eq!(m.get_f64("ebur128.lufs"), Some(-23.0));
+        assert_eq!(m.get_u64("ebur128.lufs"), None);
+        assert_eq!(m.get_str("source"), Some("camera"));
+        assert_eq!(m.get_i64("source"), None);
+
+        let old = m.insert("scdet.score", 0.7);
+        assert_eq!(old, Some(MetaValue::F64(0.5)));
+        assert_eq!(m.len(), 4);
+    }
+
+    #[test]
+    fn merge_iter() {
+        let mut a = Metadata::new();
+        a.insert("a", 1u64);
+        a.insert("b", 2u64);
+
+        let mut b = Metadata::new();
+        b.insert("b", 3u64);
+        b.insert("c", (4i64, 5i64));
+
+        a.merge(&b);
+
+        let keys: Vec<_> = a.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(a.get_u64("b"), Some(3));
+        assert_eq!(a.get("c").and_then(MetaValue::as_pair), Some((4, 5)));
+    }
+}
@@ -0,0 +1,611 @@
+//!
+//! Options parsed from strings.
+//!
+//! Two syntaxes are supported, sharing the same keys:
+//!
+//! - the command line one, `-key value`, one argument each;
+//! - the dictionary one, `key=value:key=value`, `&` being accepted as
+//!   separator too, so URL queries can be parsed as well. `\` escapes
+//!   the character following it.
+//!
+//! A key may be followed by a stream specifier restricting the option to
+//! some streams, as in `b:a:0=128000`:
+//!
+//! - `v` and `a` match all the video or audio streams;
+//! - `v:1` and `a:0` match the second video stream and the first audio
+//!   one;
+//! - `2` matches the stream of index 2.
+//!
+//! The values are stored as strings and typed when applied: booleans,
+//! integers and pairs (`16/9`, `1920x1080`) are recognized, anything else
+//! is passed as a string.
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::params::{CodecParams, MediaKind};
+use crate::value::Value;
+
+/// Options parsing errors.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum OptionsError {
+    /// An option has no key.
+    #[error("Missing key")]
+    MissingKey,
+    /// An option has no value.
+    #[error("Missing value for {0}")]
+    MissingValue(String),
+    /// A command line argument is not an option.
+    #[error("Invalid argument {0}")]
+    InvalidArgument(String),
+    /// A stream specifier cannot be parsed.
+    #[error("Invalid stream specifier {0}")]
+    InvalidSpecifier(String),
+}
+
+/// Kind of the streams selected by a specifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamKind {
+    /// Video streams, `v`.
+    Video,
+    /// Audio streams, `a`.
+    Audio,
+}
+
+impl StreamKind {
+    fn matches(self, params: &CodecParams) -> bool {
+        matches!(
+            (self, &params.kind),
+            (StreamKind::Video, Some(MediaKind::Video(_)))
+                | (StreamKind::Audio, Some(MediaKind::Audio(_)))
+        )
+    }
+}
+
+/// Streams an option applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamSpecifier {
+    /// Stream of a given index.
+    Index(usize),
+    /// Streams of a given kind, or the n-th one of them.
+    Kind(StreamKind, Option<usize>),
+}
+
+impl StreamSpecifier {
+    /// Tells if the stream of index `index` of `streams` is selected.
+    pub fn matches(&self, streams: &[CodecParams], index: usize) -> bool {
+        match *self {
+            StreamSpecifier::Index(i) => i == index && index < streams.len(),
+            StreamSpecifier::Kind(kind, n) => match streams.get(index) {
+                Some(params) if kind.matches(params) => n.is_none_or(|n| {
+                    streams[..index].iter().filter(|p| kind.matches(p)).count() == n
+                }),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl FromStr for StreamSpecifier {
+    type Err = OptionsError;
+
+    fn from_str(s: &str) -> Result<Self, OptionsError> {
+        let invalid = || OptionsError::InvalidSpecifier(s.to_owned());
+        let mut parts = s.splitn(2, ':');
+        let kind = match parts.next() {
+            Some("v") => StreamKind::Video,
+            Some("a") => StreamKind::Audio,
+            Some(index) => {
+                return index
+                    .parse()
+                    .map(StreamSpecifier::Index)
+                    .map_err(|_| invalid())
+            }
+            None => return Err(invalid()),
+        };
+        let n = match parts.next() {
+            Some(n) => Some(n.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(StreamSpecifier::Kind(kind, n))
+    }
+}
+
+impl fmt::Display for StreamSpecifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamSpecifier::Index(i) => write!(f, "{}", i),
+            StreamSpecifier::Kind(kind, n) => {
+                let kind = match kind {
+                    StreamKind::Video => "v",
+                    StreamKind::Audio => "a",
+                };
+                match n {
+                    Some(n) => write!(f, "{}:{}", kind, n),
+                    None => write!(f, "{}", kind),
+                }
+            }
+        }
+    }
+}
+
+/// Single parsed option.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opt {
+    /// Option key.
+    pub key: String,
+    /// Streams the option applies to, all of them if `None`.
+    pub stream: Option<StreamSpecifier>,
+    /// Option value, as written.
+    pub value: String,
+}
+
+impl Opt {
+    /// Returns the value typed after its content.
+    pub fn get_value(&self) -> Value<'_> {
+        let v = self.value.as_str();
+        if let Ok(b) = v.parse() {
+            return Value::Bool(b);
+        }
+        if let Ok(u) = v.parse() {
+            return Value::U64(u);
+        }
+        if let Ok(i) = v.parse() {
+            return Value::I64(i);
+        }
+        for sep in &['/', 'x'] {
+            let mut parts = v.splitn(2, *sep);
+            if let (Some(a), Some(b)) = (parts.next(), parts.next()) {
+                if let (Ok(a), Ok(b)) = (a.parse(), b.parse()) {
+                    return Value::Pair(a, b);
+                }
+            }
+        }
+        Value::Str(v)
+    }
+
+    /// Passes the option to a setter, such as a `set_option` method.
+    ///
+    /// If the typed value is refused, the value is passed again as a string.
+    pub fn apply<E, F>(&self, mut set: F) -> Result<(), E>
+    where
+        F: FnMut(&str, Value<'_>) -> Result<(), E>,
+    {
+        let val = self.get_value();
+        if let Value::Str(_) = val {
+            return set(&self.key, val);
+        }
+        match set(&self.key, val) {
+            Err(err) => set(&self.key, Value::Str(&self.value)).map_err(|_| err),
+            res => res,
+        }
+    }
+}
+
+fn escape(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    for c in s.chars() {
+        if matches!(c, ':' | '&' | '=' | '\\') {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Opt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        escape(f, &self.key)?;
+        if let Some(stream) = self.stream {
+            write!(f, ":{}", stream)?;
+        }
+        write!(f, "=")?;
+        escape(f, &self.value)
+    }
+}
+
+/// Splits a key from its stream specifier.
+fn split_key(key: &str) -> Result<(String, Option<StreamSpecifier>), OptionsError> {
+    let mut parts = key.splitn(2, ':');
+    let name = parts.next().unwrap_or_default();
+    if name.is_empty() {
+        return Err(OptionsError::MissingKey);
+    }
+    let stream = match parts.next() {
+        Some(spec) => Some(spec.parse()?),
+        None => None,
+    };
+    Ok((name.to_owned(), stream))
+}
+
+/// Ordered list of options, later ones overriding the former ones with
+/// the same key and stream specifier.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    opts: Vec<Opt>,
+}
+
+impl Options {
+    /// Creates a new empty list.
+    pub fn new() -> Self {
+        Options { opts: Vec::new() }
+    }
+
+    /// Parses command line arguments, as in `-key value`.
+    pub fn parse_args<I, S>(args: I) -> Result<Self, OptionsError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut opts = Options::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let key = match arg.strip_prefix('-') {
+                Some(key) => key,
+                None => return Err(OptionsError::InvalidArgument(arg.to_owned())),
+            };
+            let (key, stream) = split_key(key)?;
+            let value = args
+                .next()
+                .ok_or_else(|| OptionsError::MissingValue(arg.to_owned()))?;
+            opts.set(key, stream, value.as_ref());
+        }
+        Ok(opts)
+    }
+
+    /// Parses a `key=value:key=value` dictionary.
+    pub fn parse_str(s: &str) -> Result<Self, OptionsError> {
+        let mut opts = Options::new();
+        // Pieces without `=` are stream specifiers of the next key.
+        let mut prefix = String::new();
+        let mut piece = String::new();
+        let mut eq = None;
+        let mut chars = s.chars();
+        loop {
+            let c = chars.next();
+            match c {
+                Some('\\') => piece.extend(chars.next()),
+                Some('=') if eq.is_none() => {
+                    eq = Some(piece.len());
+                    piece.push('=');
+                }
+                Some(':') | Some('&') | None => {
+                    match eq.take() {
+                        Some(pos) => {
+                            let key = if prefix.is_empty() {
+                                piece[..pos].to_owned()
+                            } else {
+                                format!("{}:{}", prefix, &piece[..pos])
+                            };
+                            let (key, stream) = split_key(&key)?;
+                            opts.set(key, stream, &piece[pos + 1..]);
+                            prefix.clear();
+                        }
+                        None if piece.is_empty() => {}
+                        None => {
+                            if !prefix.is_empty() {
+                                prefix.push(':');
+                            }
+                            prefix.push_str(&piece);
+                        }
+                    }
+                    piece.clear();
+                    if c.is_none() {
+                        break;
+                    }
+                }
+                Some(c) => piece.push(c),
+            }
+        }
+        if !prefix.is_empty() {
+            return Err(OptionsError::MissingValue(prefix));
+        }
+        Ok(opts)
+    }
+
+    /// Sets an option, replacing the one with the same key and stream
+    /// specifier.
+    pub fn set<K, V>(&mut self, key: K, stream: Option<StreamSpecifier>, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        self.opts
+            .retain(|opt| !(opt.key == key && opt.stream == stream));
+        self.opts.push(Opt {
+            key,
+            stream,
+            value: value.into(),
+        });
+    }
+
+    /// Returns the value of an option applying to all the streams.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.opts
+            .iter()
+            .find(|opt| opt.key == key && opt.stream.is_none())
+            .map(|opt| opt.value.as_str())
+    }
+
+    /// Removes the options with a key, returning the value of the one
+    /// applying to all the streams.
+    ///
+    /// Meant to take out the options interpreted by the caller, such as
+    /// `format`.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let global = self
+            .opts
+            .iter()
+            .position(|opt| opt.key == key && opt.stream.is_none())
+            .map(|pos| self.opts.remove(pos).value);
+        self.opts.retain(|opt| opt.key != key);
+        global
+    }
+
+    /// Copies the options of another list, overriding the ones with the
+    /// same key and stream specifier.
+    pub fn merge(&mut self, other: &Options) {
+        for opt in other.iter() {
+            self.set(opt.key.as_str(), opt.stream, opt.value.as_str());
+        }
+    }
+
+    /// Returns an iterator over the options, in order.
+    pub fn iter(&self) -> std::slice::Iter<'_, Opt> {
+        self.opts.iter()
+    }
+
+    /// Returns the number of options.
+    pub fn len(&self) -> usize {
+        self.opts.len()
+    }
+
+    /// Tells whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.opts.is_empty()
+    }
+
+    /// Returns an iterator over the options without stream specifier.
+    pub fn global(&self) -> impl Iterator<Item = &Opt> {
+        self.opts.iter().filter(|opt| opt.stream.is_none())
+    }
+
+    /// Returns an iterator over the options applying to a stream, the
+    /// ones without stream specifier first.
+    pub fn for_stream<'a>(
+        &'a self,
+        streams: &'a [CodecParams],
+        index: usize,
+    ) -> impl Iterator<Item = &'a Opt> {
+        self.global().chain(
+            self.opts
+                .iter()
+                .filter(move |opt| opt.stream.is_some_and(|s| s.matches(streams, index))),
+        )
+    }
+
+    /// Passes the options without stream specifier to a setter.
+    pub fn apply<E, F>(&self, mut set: F) -> Result<(), E>
+    where
+        F: FnMut(&str, Value<'_>) -> Result<(), E>,
+    {
+        self.global().try_for_each(|opt| opt.apply(&mut set))
+    }
+
+    /// Passes the options applying to a stream to a setter.
+    pub fn apply_stream<E, F>(
+        &self,
+        streams: &[CodecParams],
+        index: usize,
+        mut set: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(&str, Value<'_>) -> Result<(), E>,
+    {
+        self.for_stream(streams, index)
+            .try_for_each(|opt| opt.apply(&mut set))
+    }
+}
+
+impl FromStr for Options {
+    type Err = OptionsError;
+
+    fn from_str(s: &str) -> Result<Self, OptionsError> {
+        Options::parse_str(s)
+    }
+}
+
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, opt) in self.opts.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{}", opt)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a Options {
+    type Item = &'a Opt;
+    type IntoIter = std::slice::Iter<'a, Opt>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::params::{AudioInfo, VideoInfo};
+
+    fn params(kind: MediaKind) -> CodecParams {
+        CodecParams {
+            kind: Some(kind),
+            codec_id: None,
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        }
+    }
+
+    fn streams() -> Vec<CodecParams> {
+        let video = || {
+            params(MediaKind::Video(VideoInfo {
+                width: 0,
+                height: 0,
+                format: None,
+            }))
+        };
+        let audio = || {
+            params(MediaKind::Audio(AudioInfo {
+                rate: 0,
+                map: None,
+                format: None,
+            }))
+        };
+        vec![video(), audio(), audio(), video()]
+    }
+
+    fn pairs<'a>(opts: impl Iterator<Item = &'a Opt>) -> Vec<(&'a str, &'a str)> {
+        opts.map(|opt| (opt.key.as_str(), opt.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn specifiers() {
+        let streams = streams();
+        let matching = |s: &str| -> Vec<usize> {
+            let spec: StreamSpecifier = s.parse().unwrap();
+            assert_eq!(spec.to_string(), s);
+            (0..streams.len())
+                .filter(|&i| spec.matches(&streams, i))
+                .collect()
+        };
+        assert_eq!(matching("v"), [0, 3]);
+        assert_eq!(matching("a"), [1, 2]);
+        assert_eq!(matching("a:1"), [2]);
+        assert_eq!(matching("v:1"), [3]);
+        assert_eq!(matching("2"), [2]);
+        assert!(matching("v:2").is_empty());
+        assert!(matching("4").is_empty());
+
+        for spec in &["", "s", "v:", "a:x", "-1"] {
+            assert!(spec.parse::<StreamSpecifier>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn args() {
+        let opts = Options::parse_args(["-format", "mp4", "-b:a:0", "128000", "-b", "-1"]).unwrap();
+        assert_eq!(opts.get("format"), Some("mp4"));
+        assert_eq!(opts.get("b"), Some("-1"));
+        assert_eq!(
+            opts.iter().nth(1).unwrap().stream,
+            Some(StreamSpecifier::Kind(StreamKind::Audio, Some(0)))
+        );
+
+        assert_eq!(
+            Options::parse_args(["-format"]),
+            Err(OptionsError::MissingValue("-format".to_owned()))
+        );
+        assert!(Options::parse_args(["format", "mp4"]).is_err());
+        assert!(Options::parse_args(["-", "mp4"]).is_err());
+    }
+
+    #[test]
+    fn dictionary() {
+        let mut opts: Options = "format=mp4:movflags=faststart:b:a:1=64000&title=a\\:b=c"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            pairs(opts.iter()),
+            [
+                ("format", "mp4"),
+                ("movflags", "faststart"),
+                ("b", "64000"),
+                ("title", "a:b=c"),
+            ]
+        );
+        assert_eq!(
+            opts.to_string(),
+            "format=mp4:movflags=faststart:b:a:1=64000:title=a\\:b\\=c"
+        );
+        assert_eq!(opts.to_string().parse::<Options>().unwrap(), opts);
+
+        assert_eq!(opts.remove("format").as_deref(), Some("mp4"));
+        assert_eq!(opts.get("format"), None);
+
+        let streams = streams();
+        assert_eq!(
+            pairs(opts.for_stream(&streams, 2)),
+            [
+                ("movflags", "faststart"),
+                ("title", "a:b=c"),
+                ("b", "64000")
+            ]
+        );
+        assert_eq!(opts.for_stream(&streams, 1).count(), 2);
+
+        assert!(Options::parse_str("").unwrap().is_empty());
+        assert_eq!(
+            Options::parse_str("format=mp4:b:a"),
+            Err(OptionsError::MissingValue("b:a".to_owned()))
+        );
+        assert_eq!(Options::parse_str("=mp4"), Err(OptionsError::MissingKey));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut opts = Options::parse_str("b=1:b:v=2").unwrap();
+        opts.merge(&Options::parse_args(["-b", "3", "-g", "4"]).unwrap());
+        assert_eq!(pairs(opts.iter()), [("b", "2"), ("b", "3"), ("g", "4")]);
+        assert_eq!(opts.len(), 3);
+    }
+
+    #[test]
+    fn values() {
+        let opts = Options::parse_str("a=true:b=12:c=-3:d=16/9:e=1920x1080:f=2.5:g=").unwrap();
+        let values: Vec<_> = opts
+            .iter()
+            .map(|opt| format!("{:?}", opt.get_value()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "Bool(true)",
+                "U64(12)",
+                "I64(-3)",
+                "Pair(16, 9)",
+                "Pair(1920, 1080)",
+                "Str(\"2.5\")",
+                "Str(\"\")",
+            ]
+        );
+
+        // A string option given an integer.
+        let mut set = Vec::new();
+        Options::parse_str("vendor=123:page_duration=48000")
+            .unwrap()
+            .apply(|key, val| match (key, val) {
+                ("vendor", Value::Str(s)) => {
+                    set.push(s.to_owned());
+                    Ok(())
+                }
+                ("page_duration", Value::U64(v)) => {
+                    set.push(v.to_string());
+                    Ok(())
+                }
+                _ => Err(()),
+            })
+            .unwrap();
+        assert_eq!(set, ["123", "48000"]);
+    }
+}
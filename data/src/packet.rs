@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use crate::frame::FieldInfo;
 use crate::timeinfo::TimeInfo;
 use std::io::{Read, Result, Write};
 
@@ -21,6 +22,9 @@ pub struct Packet {
     pub is_key: bool,
     /// Tells whether a packet is corrupted.
     pub is_corrupted: bool,
+    /// Field structure of the video picture, if given by the stream
+    /// headers.
+    pub fields: Option<FieldInfo>,
 }
 
 impl Packet {
@@ -33,6 +37,7 @@ impl Packet {
             stream_index: -1,
             is_key: false,
             is_corrupted: false,
+            fields: None,
         }
     }
 
@@ -45,6 +50,7 @@ impl Packet {
             stream_index: -1,
             is_key: false,
             is_corrupted: false,
+            fields: None,
         }
     }
 
@@ -24,6 +24,15 @@ pub struct AudioInfo {
     pub format: Option<Arc<Soniton>>,
 }
 
+/// Timed data stream information.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataInfo {
+    /// MIME type of the data, if known.
+    pub mime: Option<String>,
+    /// Content encoding of the data (e.g. `gzip`), if any.
+    pub encoding: Option<String>,
+}
+
 /// Possible stream information types.
 #[derive(Clone, Debug, PartialEq)]
 pub enum MediaKind {
@@ -31,6 +40,8 @@ pub enum MediaKind {
     Video(VideoInfo),
     /// Audio codec information.
     Audio(AudioInfo),
+    /// Timed data information (e.g. camera telemetry).
+    Data(DataInfo),
 }
 
 /// Possible codec parameters.
@@ -385,8 +385,7 @@ impl Chromaton {
     /// Calculates the required image size in pixels for a component
     /// from general image width.
     pub fn get_data_size(self, width: usize, height: usize, align: usize) -> usize {
-        let nh = (height + ((1 << self.v_ss) - 1)) >> self.v_ss;
-        self.get_linesize(width, align) * nh
+        self.get_linesize(width, align) * self.get_height(height)
     }
 }
 
@@ -621,6 +620,135 @@ impl fmt::Display for Formaton {
     }
 }
 
+/// Information lost when converting an image from a format to another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatLoss {
+    /// Components are stored with fewer bits.
+    pub depth: bool,
+    /// Chroma is subsampled further or color components are dropped.
+    pub chroma: bool,
+    /// The alpha component is dropped.
+    pub alpha: bool,
+    /// A color model conversion is needed.
+    pub colorspace: bool,
+    /// Colors are quantized to a palette.
+    pub palette: bool,
+}
+
+impl FormatLoss {
+    /// Tells whether the conversion preserves all the information.
+    pub fn is_lossless(&self) -> bool {
+        *self == FormatLoss::default()
+    }
+}
+
+/// Summary of the color (non-alpha) components of a format.
+struct ColorLayout {
+    count: usize,
+    min_depth: u8,
+    max_depth: u8,
+    h_ss: u8,
+    v_ss: u8,
+}
+
+impl ColorLayout {
+    fn new(fmt: &Formaton) -> Self {
+        let count = fmt.get_num_comp() - fmt.has_alpha() as usize;
+        let mut layout = ColorLayout {
+            count,
+            min_depth: u8::MAX,
+            max_depth: 0,
+            h_ss: 0,
+            v_ss: 0,
+        };
+
+        for c in fmt.iter().take(count).flatten() {
+            layout.min_depth = layout.min_depth.min(c.depth);
+            layout.max_depth = layout.max_depth.max(c.depth);
+            layout.h_ss = layout.h_ss.max(c.h_ss);
+            layout.v_ss = layout.v_ss.max(c.v_ss);
+        }
+
+        if count == 0 {
+            layout.min_depth = 0;
+        }
+
+        layout
+    }
+}
+
+/// Returns the information lost converting an image from `src` to `dst`.
+pub fn get_format_loss(src: &Formaton, dst: &Formaton) -> FormatLoss {
+    let s = ColorLayout::new(src);
+    let d = ColorLayout::new(dst);
+
+    FormatLoss {
+        depth: d.max_depth < s.max_depth || d.min_depth < s.min_depth,
+        chroma: d.h_ss > s.h_ss || d.v_ss > s.v_ss || d.count < s.count,
+        alpha: src.has_alpha() && !dst.has_alpha(),
+        colorspace: src.get_model() != dst.get_model(),
+        palette: dst.is_paletted() && !src.is_paletted(),
+    }
+}
+
+/// Returns a penalty for converting from `src` to `dst`, the lower the better.
+///
+/// The information lost dominates the score, in order of severity:
+/// alpha, palette quantization, depth, colorspace conversion and chroma
+/// subsampling. Formats wasting memory (more depth or less subsampling than
+/// needed) get a small penalty so the closest lossless format wins.
+fn get_format_penalty(src: &Formaton, dst: &Formaton) -> u64 {
+    let s = ColorLayout::new(src);
+    let d = ColorLayout::new(dst);
+    let diff = |a: u8, b: u8| u64::from(a.saturating_sub(b));
+
+    let mut penalty = 0;
+
+    if src.has_alpha() && !dst.has_alpha() {
+        penalty += 1 << 24;
+    }
+    if dst.is_paletted() && !src.is_paletted() {
+        penalty += 1 << 22;
+    }
+    penalty += (diff(s.max_depth, d.max_depth) + diff(s.min_depth, d.min_depth)) << 16;
+    if src.get_model() != dst.get_model() {
+        penalty += 1 << 14;
+    }
+    penalty += (diff(d.h_ss, s.h_ss) + diff(d.v_ss, s.v_ss)) << 12;
+    if d.count < s.count {
+        penalty += 1 << 13;
+    }
+
+    penalty += diff(d.max_depth, s.max_depth) << 4;
+    penalty += (diff(s.h_ss, d.h_ss) + diff(s.v_ss, d.v_ss)) << 2;
+    if dst.has_alpha() && !src.has_alpha() {
+        penalty += 2;
+    }
+    if dst.is_be() != src.is_be() {
+        penalty += 1;
+    }
+
+    penalty
+}
+
+/// Picks among the `supported` formats the best one to convert a `src`
+/// image to.
+///
+/// Useful to connect components supporting different sets of formats
+/// (e.g. a decoder to an encoder), it prefers the format losing the least
+/// information and, among lossless candidates, the one closest to `src`.
+///
+/// Returns `None` if `supported` is empty.
+pub fn find_best_pixel_format<'a>(
+    supported: &[&'a Formaton],
+    src: &Formaton,
+) -> Option<&'a Formaton> {
+    supported
+        .iter()
+        .min_by_key(|dst| get_format_penalty(src, dst))
+        .copied()
+}
+
 pub mod formats {
     //!
     //! Ready-to-use formaton
@@ -747,8 +875,8 @@ pub mod formats {
         components: 3,
         comp_info: [
             Some(Chromaton::new(0, 0, false, 10, 0, 0, 1)),
-            Some(Chromaton::yuvhb(0, 0, 1, 10)),
-            Some(Chromaton::yuvhb(0, 0, 2, 10)),
+            Some(Chromaton::yuvhb(0, 0, 10, 1)),
+            Some(Chromaton::yuvhb(0, 0, 10, 2)),
             None,
             None,
         ],
@@ -768,8 +896,8 @@ pub mod formats {
         components: 3,
         comp_info: [
             Some(Chromaton::new(0, 0, false, 10, 0, 0, 1)),
-            Some(Chromaton::yuvhb(0, 1, 1, 10)),
-            Some(Chromaton::yuvhb(0, 1, 2, 10)),
+            Some(Chromaton::yuvhb(0, 1, 10, 1)),
+            Some(Chromaton::yuvhb(0, 1, 10, 2)),
             None,
             None,
         ],
@@ -789,8 +917,8 @@ pub mod formats {
         components: 3,
         comp_info: [
             Some(Chromaton::new(0, 0, false, 10, 0, 0, 1)),
-            Some(Chromaton::yuvhb(1, 1, 1, 10)),
-            Some(Chromaton::yuvhb(1, 1, 2, 10)),
+            Some(Chromaton::yuvhb(1, 1, 10, 1)),
+            Some(Chromaton::yuvhb(1, 1, 10, 2)),
             None,
             None,
         ],
@@ -810,8 +938,8 @@ pub mod formats {
         components: 3,
         comp_info: [
             Some(Chromaton::new(0, 0, false, 10, 0, 0, 1)),
-            Some(Chromaton::yuvhb(2, 0, 1, 10)),
-            Some(Chromaton::yuvhb(2, 0, 2, 10)),
+            Some(Chromaton::yuvhb(2, 0, 10, 1)),
+            Some(Chromaton::yuvhb(2, 0, 10, 2)),
             None,
             None,
         ],
@@ -831,8 +959,8 @@ pub mod formats {
         components: 3,
         comp_info: [
             Some(Chromaton::new(0, 0, false, 10, 0, 0, 1)),
-            Some(Chromaton::yuvhb(2, 1, 1, 10)),
-            Some(Chromaton::yuvhb(2, 1, 2, 10)),
+            Some(Chromaton::yuvhb(2, 1, 10, 1)),
+            Some(Chromaton::yuvhb(2, 1, 10, 2)),
             None,
             None,
         ],
@@ -953,5 +1081,66 @@ mod test {
                 panic!("rcf");
             }
         }
+
+        #[test]
+        fn high_depth() {
+            use self::formats::*;
+
+            for fmt in &[YUV444_10, YUV422_10, YUV420_10, YUV411_10, YUV410_10] {
+                let comps: Vec<_> = (0..3)
+                    .map(|i| fmt.get_chromaton(i).unwrap())
+                    .map(|c| (c.get_depth(), c.get_offset()))
+                    .collect();
+                assert_eq!(comps, [(10, 0), (10, 1), (10, 2)], "{}", fmt);
+                assert_eq!(fmt.get_total_depth(), 30);
+            }
+        }
+    }
+
+    mod negotiation {
+        use super::super::formats::*;
+        use super::super::*;
+
+        #[test]
+        fn loss() {
+            assert!(get_format_loss(YUV420, YUV420).is_lossless());
+
+            let loss = get_format_loss(YUV420_10, YUV420);
+            assert!(loss.depth && !loss.chroma && !loss.colorspace);
+
+            let loss = get_format_loss(YUV444, YUV420);
+            assert!(loss.chroma && !loss.depth);
+
+            let loss = get_format_loss(RGBA, YUV420);
+            assert!(loss.alpha && loss.colorspace && loss.chroma);
+
+            let loss = get_format_loss(RGB24, PAL8);
+            assert!(loss.palette && !loss.colorspace);
+
+            assert!(get_format_loss(YUV420, YUV444_10).is_lossless());
+        }
+
+        #[test]
+        fn best_format() {
+            assert_eq!(find_best_pixel_format(&[], YUV420), None);
+
+            let best = find_best_pixel_format(&[RGB24, YUV444, YUV420], YUV420);
+            assert_eq!(best, Some(YUV420));
+
+            let best = find_best_pixel_format(&[YUV420_10, YUV420], YUV420);
+            assert_eq!(best, Some(YUV420));
+
+            let best = find_best_pixel_format(&[YUV420, YUV444_10], YUV420_10);
+            assert_eq!(best, Some(YUV444_10));
+
+            let best = find_best_pixel_format(&[YUV420, RGB24], RGBA);
+            assert_eq!(best, Some(RGB24));
+
+            let best = find_best_pixel_format(&[RGB565, RGBA], RGB24);
+            assert_eq!(best, Some(RGBA));
+
+            let best = find_best_pixel_format(&[PAL8, YUV420, RGB565], RGB24);
+            assert_eq!(best, Some(YUV420));
+        }
     }
 }
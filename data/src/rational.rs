@@ -0,0 +1,202 @@
+//!
+//! Rational numbers and timebase arithmetic.
+//!
+//! Multiplying a timestamp by `Rational64` timebases overflows, panicking
+//! or wrapping, once the timestamp and the timebase terms get large. The
+//! functions below compute in 128 bits and report the overflows instead.
+//!
+
+pub use num_rational::*;
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+/// Rounding of the rescaled timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Toward negative infinity.
+    Down,
+    /// Toward positive infinity.
+    Up,
+    /// To the nearest integer, half-way cases away from zero as
+    /// `Ratio::round` does.
+    Nearest,
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a.abs()
+}
+
+/// Divides, `d` being positive.
+fn div(n: i128, d: i128, rounding: Rounding) -> i128 {
+    let (q, r) = (n.div_euclid(d), n.rem_euclid(d));
+    let up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => r != 0,
+        Rounding::Nearest => r > d - r || (r == d - r && n >= 0),
+    };
+    if up {
+        q + 1
+    } else {
+        q
+    }
+}
+
+/// Converts a timestamp from a timebase to another, rounding to the
+/// nearest integer.
+///
+/// Returns `None` if a timebase is invalid or on overflow.
+pub fn rescale(ts: i64, from: Rational64, to: Rational64) -> Option<i64> {
+    rescale_rnd(ts, from, to, Rounding::Nearest)
+}
+
+/// Converts a timestamp from a timebase to another.
+///
+/// Returns `None` if a timebase is invalid or on overflow.
+pub fn rescale_rnd(ts: i64, from: Rational64, to: Rational64, rounding: Rounding) -> Option<i64> {
+    let mut n = i128::from(*from.numer()) * i128::from(*to.denom());
+    let mut d = i128::from(*from.denom()) * i128::from(*to.numer());
+    if d == 0 || *to.denom() == 0 {
+        return None;
+    }
+    if d < 0 {
+        n = -n;
+        d = -d;
+    }
+    let g = gcd(n, d);
+    if g > 1 {
+        n /= g;
+        d /= g;
+    }
+
+    let ts = i128::from(ts).checked_mul(n)?;
+    i64::try_from(div(ts, d, rounding)).ok()
+}
+
+/// Returns a timestamp as a fraction of a positive denominator.
+fn fraction(ts: i64, tb: Rational64) -> Option<(i128, i128)> {
+    let n = i128::from(ts) * i128::from(*tb.numer());
+    match tb.denom().cmp(&0) {
+        Ordering::Greater => Some((n, i128::from(*tb.denom()))),
+        Ordering::Less => Some((-n, -i128::from(*tb.denom()))),
+        Ordering::Equal => None,
+    }
+}
+
+/// Compares two timestamps of different timebases, exactly.
+///
+/// Returns `None` if a timebase is invalid.
+pub fn compare(a: i64, tb_a: Rational64, b: i64, tb_b: Rational64) -> Option<Ordering> {
+    let (a, da) = fraction(a, tb_a)?;
+    let (b, db) = fraction(b, tb_b)?;
+    let ord = a.div_euclid(da).cmp(&b.div_euclid(db));
+    if ord != Ordering::Equal {
+        return Some(ord);
+    }
+    // The remainders are below the 64 bit denominators.
+    Some((a.rem_euclid(da) * db).cmp(&(b.rem_euclid(db) * da)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn timebase() -> impl Strategy<Value = Rational64> {
+        (1i64..=1001, 1i64..=1 << 30).prop_map(|(n, d)| Rational64::new(n, d))
+    }
+
+    /// Timestamps for which the `Ratio` arithmetic does not overflow.
+    fn small() -> impl Strategy<Value = i64> {
+        -(1i64 << 20)..1 << 20
+    }
+
+    #[test]
+    fn cases() {
+        let ms = Rational64::new(1, 1000);
+        let ticks = Rational64::new(1, 90000);
+        assert_eq!(rescale(1001, ms, ticks), Some(90090));
+        assert_eq!(rescale(1, ticks, ms), Some(0));
+        assert_eq!(rescale_rnd(1, ticks, ms, Rounding::Up), Some(1));
+        assert_eq!(rescale(45, ticks, ms), Some(1));
+        assert_eq!(rescale(-45, ticks, ms), Some(-1));
+        assert_eq!(rescale_rnd(-45, ticks, ms, Rounding::Down), Some(-1));
+        assert_eq!(rescale_rnd(-45, ticks, ms, Rounding::Up), Some(0));
+
+        // Overflows of the num-rational arithmetic.
+        let ns = Rational64::new(1, 1_000_000_000);
+        let pts = 1 << 50;
+        assert_eq!(rescale(pts, ms, ns), None);
+        assert_eq!(rescale(pts, ns, ms), Some((pts + 500_000) / 1_000_000));
+        let odd = Rational64::new(1_000_000_007, 1_000_000_009);
+        assert_eq!(rescale(pts, odd, odd), Some(pts));
+        assert_eq!(rescale(i64::MAX, ms, Rational64::new(1, 2000)), None);
+        assert_eq!(rescale(i64::MIN, ms, ms), Some(i64::MIN));
+
+        assert_eq!(rescale(1, ms, Rational64::new(0, 1)), None);
+        assert_eq!(compare(1, ms, 89, ticks), Some(Ordering::Greater));
+        assert_eq!(compare(1, ms, 90, ticks), Some(Ordering::Equal));
+        assert_eq!(compare(i64::MAX, ns, i64::MAX, odd), Some(Ordering::Less));
+    }
+
+    proptest! {
+        #[test]
+        fn matches_ratio(ts in small(), from in timebase(), to in timebase()) {
+            let expected = (Rational64::from_integer(ts) * from / to).round().to_integer();
+            prop_assert_eq!(rescale(ts, from, to), Some(expected));
+        }
+
+        #[test]
+        fn roundtrip(ts in any::<i64>(), from in timebase(), to in timebase()) {
+            // Back from a finer timebase, the error is below one half.
+            let (coarse, fine) = if from < to { (to, from) } else { (from, to) };
+            if let Some(rescaled) = rescale(ts, coarse, fine) {
+                prop_assert_eq!(rescale(rescaled, fine, coarse), Some(ts));
+            }
+        }
+
+        #[test]
+        fn rounding(ts in any::<i64>(), from in timebase(), to in timebase()) {
+            let down = rescale_rnd(ts, from, to, Rounding::Down);
+            let up = rescale_rnd(ts, from, to, Rounding::Up);
+            let nearest = rescale(ts, from, to);
+            if let (Some(down), Some(up), Some(nearest)) = (down, up, nearest) {
+                prop_assert!(down <= nearest && nearest <= up);
+                prop_assert!(up - down <= 1);
+                prop_assert_eq!(
+                    compare(down, to, ts, from) == Some(Ordering::Equal),
+                    down == up
+                );
+            }
+        }
+
+        #[test]
+        fn monotonic(a in any::<i64>(), b in any::<i64>(), from in timebase(), to in timebase()) {
+            let (a, b) = (a.min(b), a.max(b));
+            if let (Some(a), Some(b)) = (rescale(a, from, to), rescale(b, from, to)) {
+                prop_assert!(a <= b);
+            }
+        }
+
+        #[test]
+        fn comparison(a in any::<i64>(), b in any::<i64>(), tb_a in timebase(), tb_b in timebase()) {
+            let ord = compare(a, tb_a, b, tb_b).unwrap();
+            prop_assert_eq!(compare(b, tb_b, a, tb_a), Some(ord.reverse()));
+            prop_assert_eq!(compare(a, tb_a, a, tb_a), Some(Ordering::Equal));
+            if let Some(down) = rescale_rnd(a, tb_a, tb_b, Rounding::Down) {
+                prop_assert_ne!(compare(down, tb_b, a, tb_a), Some(Ordering::Greater));
+            }
+        }
+
+        #[test]
+        fn comparison_matches_ratio(a in small(), b in small(), tb_a in timebase(), tb_b in timebase()) {
+            let expected = (Rational64::from_integer(a) * tb_a).cmp(&(Rational64::from_integer(b) * tb_b));
+            prop_assert_eq!(compare(a, tb_a, b, tb_b), Some(expected));
+        }
+    }
+}
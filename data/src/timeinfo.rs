@@ -1,18 +1,370 @@
-use crate::rational::Rational64;
+//!
+//! Timestamps and durations.
+//!
+//! `Timestamp` and `Duration` carry their timebase along, so that adding,
+//! subtracting or comparing values of different timebases rescales them,
+//! and report the overflows and the missing timestamps instead of wrapping.
+//! `TimeInfo` stores them as bare integers in units of its timebase, read
+//! and written through its accessors.
+//!
+
+use crate::rational::{compare, rescale, Rational64};
 use std::any::Any;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::sync::Arc;
 
+use thiserror::Error;
+
+/// Timestamp arithmetic errors.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum TimeError {
+    /// The timestamp or its timebase is not set.
+    #[error("No timestamp")]
+    NoTimestamp,
+    /// The result does not fit, or a timebase is invalid.
+    #[error("Timestamp overflow")]
+    Overflow,
+    /// The resulting duration would be negative.
+    #[error("Negative duration")]
+    Negative,
+}
+
+/// A specialised `Result` type for timestamp arithmetic.
+pub type Result<T> = ::std::result::Result<T, TimeError>;
+
+/// A point in time, in units of a timebase.
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    value: i64,
+    timebase: Rational64,
+}
+
+impl Timestamp {
+    /// Creates a timestamp of `value` units of `timebase`.
+    pub fn new(value: i64, timebase: Rational64) -> Self {
+        Timestamp { value, timebase }
+    }
+
+    /// Returns the timestamp in units of its timebase.
+    pub fn get_value(self) -> i64 {
+        self.value
+    }
+
+    /// Returns the timebase of the timestamp.
+    pub fn get_timebase(self) -> Rational64 {
+        self.timebase
+    }
+
+    /// Converts the timestamp to another timebase, rounding to the nearest
+    /// unit.
+    pub fn rescale(self, timebase: Rational64) -> Result<Self> {
+        rescale(self.value, self.timebase, timebase)
+            .map(|value| Timestamp::new(value, timebase))
+            .ok_or(TimeError::Overflow)
+    }
+
+    /// Adds a duration, rescaled to the timebase of the timestamp.
+    pub fn checked_add(self, duration: Duration) -> Result<Self> {
+        let units = duration.units(self.timebase)?;
+        self.value
+            .checked_add(units)
+            .map(|value| Timestamp::new(value, self.timebase))
+            .ok_or(TimeError::Overflow)
+    }
+
+    /// Subtracts a duration, rescaled to the timebase of the timestamp.
+    pub fn checked_sub(self, duration: Duration) -> Result<Self> {
+        let units = duration.units(self.timebase)?;
+        self.value
+            .checked_sub(units)
+            .map(|value| Timestamp::new(value, self.timebase))
+            .ok_or(TimeError::Overflow)
+    }
+
+    /// Returns the duration elapsed since an earlier timestamp, in the
+    /// timebase of this one.
+    pub fn duration_since(self, earlier: Timestamp) -> Result<Duration> {
+        let earlier = earlier.rescale(self.timebase)?;
+        let units = self
+            .value
+            .checked_sub(earlier.value)
+            .ok_or(TimeError::Overflow)?;
+        u64::try_from(units)
+            .map(|value| Duration::new(value, self.timebase))
+            .map_err(|_| TimeError::Negative)
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Compares exactly, across timebases; `None` if a timebase is invalid.
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        compare(self.value, self.timebase, other.value, other.timebase)
+    }
+}
+
+/// A span of time, in units of a timebase.
+#[derive(Clone, Copy, Debug)]
+pub struct Duration {
+    value: u64,
+    timebase: Rational64,
+}
+
+impl Duration {
+    /// Creates a duration of `value` units of `timebase`.
+    pub fn new(value: u64, timebase: Rational64) -> Self {
+        Duration { value, timebase }
+    }
+
+    /// Returns the duration in units of its timebase.
+    pub fn get_value(self) -> u64 {
+        self.value
+    }
+
+    /// Returns the timebase of the duration.
+    pub fn get_timebase(self) -> Rational64 {
+        self.timebase
+    }
+
+    /// Returns the duration in units of `timebase`, rounded to the nearest.
+    fn units(self, timebase: Rational64) -> Result<i64> {
+        i64::try_from(self.value)
+            .ok()
+            .and_then(|value| rescale(value, self.timebase, timebase))
+            .ok_or(TimeError::Overflow)
+    }
+
+    /// Converts the duration to another timebase, rounding to the nearest
+    /// unit.
+    pub fn rescale(self, timebase: Rational64) -> Result<Self> {
+        let units = self.units(timebase)?;
+        u64::try_from(units)
+            .map(|value| Duration::new(value, timebase))
+            .map_err(|_| TimeError::Negative)
+    }
+
+    /// Adds a duration, rescaled to the timebase of this one.
+    pub fn checked_add(self, other: Duration) -> Result<Self> {
+        let other = other.rescale(self.timebase)?;
+        self.value
+            .checked_add(other.value)
+            .map(|value| Duration::new(value, self.timebase))
+            .ok_or(TimeError::Overflow)
+    }
+
+    /// Subtracts a duration, rescaled to the timebase of this one.
+    pub fn checked_sub(self, other: Duration) -> Result<Self> {
+        let other = other.rescale(self.timebase)?;
+        self.value
+            .checked_sub(other.value)
+            .map(|value| Duration::new(value, self.timebase))
+            .ok_or(TimeError::Negative)
+    }
+}
+
+impl PartialEq for Duration {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Compares exactly, across timebases; `None` if a timebase is invalid or
+/// a duration exceeds `i64::MAX` units.
+impl PartialOrd for Duration {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let a = i64::try_from(self.value).ok()?;
+        let b = i64::try_from(other.value).ok()?;
+        compare(a, self.timebase, b, other.timebase)
+    }
+}
+
 /// Timestamp information.
 #[derive(Debug, Clone, Default)]
 pub struct TimeInfo {
     /// Presentation timestamp.
+    #[deprecated(note = "Use get_pts and set_pts")]
     pub pts: Option<i64>,
     /// Decode timestamp.
+    #[deprecated(note = "Use get_dts and set_dts")]
     pub dts: Option<i64>,
     /// Duration (in timebase units).
+    #[deprecated(note = "Use get_duration and set_duration")]
     pub duration: Option<u64>,
     /// Timebase numerator/denominator.
     pub timebase: Option<Rational64>,
     /// Timebase user private data.
     pub user_private: Option<Arc<dyn Any + Send + Sync>>,
 }
+
+#[allow(deprecated)]
+impl TimeInfo {
+    /// Returns a copy of the information using `timebase` if it has none,
+    /// as the packets of a stream do.
+    pub fn with_default_timebase(&self, timebase: Rational64) -> TimeInfo {
+        TimeInfo {
+            timebase: self.timebase.or(Some(timebase)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the presentation timestamp, `None` if it or the timebase is
+    /// not set.
+    pub fn get_pts(&self) -> Option<Timestamp> {
+        Some(Timestamp::new(self.pts?, self.timebase?))
+    }
+
+    /// Returns the decode timestamp, `None` if it or the timebase is not
+    /// set.
+    pub fn get_dts(&self) -> Option<Timestamp> {
+        Some(Timestamp::new(self.dts?, self.timebase?))
+    }
+
+    /// Returns the duration, `None` if it or the timebase is not set.
+    pub fn get_duration(&self) -> Option<Duration> {
+        Some(Duration::new(self.duration?, self.timebase?))
+    }
+
+    /// Rescales a value to the timebase of the information, setting it to
+    /// `timebase` if none is set and the rescaling succeeds.
+    fn rescale<T>(
+        &mut self,
+        timebase: Rational64,
+        rescale: impl FnOnce(Rational64) -> Result<T>,
+    ) -> Result<T> {
+        let value = rescale(self.timebase.unwrap_or(timebase))?;
+        self.timebase.get_or_insert(timebase);
+        Ok(value)
+    }
+
+    /// Sets the presentation timestamp, rescaled to the timebase of the
+    /// information, which is the one of the timestamp if not set.
+    pub fn set_pts(&mut self, pts: Option<Timestamp>) -> Result<()> {
+        self.pts = match pts {
+            Some(pts) => Some(self.rescale(pts.timebase, |tb| pts.rescale(tb))?.value),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Sets the decode timestamp as `set_pts` does.
+    pub fn set_dts(&mut self, dts: Option<Timestamp>) -> Result<()> {
+        self.dts = match dts {
+            Some(dts) => Some(self.rescale(dts.timebase, |tb| dts.rescale(tb))?.value),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Sets the duration as `set_pts` does.
+    pub fn set_duration(&mut self, duration: Option<Duration>) -> Result<()> {
+        self.duration = match duration {
+            Some(duration) => Some(
+                self.rescale(duration.timebase, |tb| duration.rescale(tb))?
+                    .value,
+            ),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Returns the end of the presentation, the presentation timestamp
+    /// plus the duration.
+    pub fn get_end(&self) -> Result<Timestamp> {
+        let pts = self.get_pts().ok_or(TimeError::NoTimestamp)?;
+        let duration = self.get_duration().ok_or(TimeError::NoTimestamp)?;
+        pts.checked_add(duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let ms = Rational64::new(1, 1000);
+        let ticks = Rational64::new(1, 90000);
+        let pts = Timestamp::new(1000, ms);
+
+        let later = pts.checked_add(Duration::new(3600, ticks)).unwrap();
+        assert_eq!((later.get_value(), later.get_timebase()), (1040, ms));
+        assert_eq!(later, Timestamp::new(93600, ticks));
+        assert!(later > Timestamp::new(93599, ticks));
+        assert_eq!(
+            later.duration_since(pts).unwrap(),
+            Duration::new(3600, ticks)
+        );
+        assert_eq!(pts.duration_since(later), Err(TimeError::Negative));
+        assert_eq!(
+            pts.checked_sub(Duration::new(2, ms)).unwrap().get_value(),
+            998
+        );
+
+        let big = Timestamp::new(i64::MAX - 1, ticks);
+        assert_eq!(
+            big.checked_add(Duration::new(2, ticks)),
+            Err(TimeError::Overflow)
+        );
+        assert_eq!(
+            big.rescale(Rational64::new(1, 1 << 40)),
+            Err(TimeError::Overflow)
+        );
+        assert_eq!(
+            pts.checked_add(Duration::new(u64::MAX, ms)),
+            Err(TimeError::Overflow)
+        );
+
+        let d = Duration::new(40, ms);
+        assert_eq!(
+            d.checked_add(Duration::new(900, ticks))
+                .unwrap()
+                .get_value(),
+            50
+        );
+        assert_eq!(
+            d.checked_sub(Duration::new(41, ms)),
+            Err(TimeError::Negative)
+        );
+        assert!(d < Duration::new(3601, ticks));
+    }
+
+    #[test]
+    fn info() {
+        let ms = Rational64::new(1, 1000);
+        let ticks = Rational64::new(1, 90000);
+        let mut t = TimeInfo::default();
+        assert_eq!(t.get_pts(), None);
+        assert_eq!(t.get_end(), Err(TimeError::NoTimestamp));
+
+        t.set_pts(Some(Timestamp::new(90000, ticks))).unwrap();
+        assert_eq!(t.timebase, Some(ticks));
+        t.set_dts(Some(Timestamp::new(500, ms))).unwrap();
+        assert_eq!(t.get_dts().map(Timestamp::get_value), Some(45000));
+        assert_eq!(t.get_end(), Err(TimeError::NoTimestamp));
+        t.set_duration(Some(Duration::new(40, ms))).unwrap();
+        assert_eq!(t.get_duration().map(Duration::get_value), Some(3600));
+        assert_eq!(t.get_end().unwrap(), Timestamp::new(1040, ms));
+
+        // A failed rescaling leaves the timebase unset.
+        let mut u = TimeInfo::default();
+        let invalid = Timestamp::new(1, Rational64::new_raw(1, 0));
+        assert_eq!(u.set_pts(Some(invalid)), Err(TimeError::Overflow));
+        assert_eq!(u.timebase, None);
+
+        t.set_pts(None).unwrap();
+        assert_eq!(t.get_pts(), None);
+        // A timestamp without timebase is not usable, unless given one.
+        t.timebase = None;
+        assert_eq!(t.get_dts(), None);
+        let v = t.with_default_timebase(ms);
+        assert_eq!(v.get_dts(), Some(Timestamp::new(45000, ms)));
+        let v = v.with_default_timebase(ticks);
+        assert_eq!(v.timebase, Some(ms));
+    }
+}
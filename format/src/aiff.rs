@@ -0,0 +1,595 @@
+//!
+//! AIFF and AIFF-C demuxer and muxer.
+//!
+//! The muxer writes uncompressed AIFF, or AIFF-C when the sample format
+//! is only representable there (little-endian and floating point samples).
+//!
+//! Since the chunk sizes precede the sound data, the muxer streams the
+//! packets out only if the stream duration is known, otherwise they are
+//! kept in memory until the trailer is written.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::{SeekFrom, Write};
+use std::sync::Arc;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::audiosample::Soniton;
+use crate::data::packet::Packet;
+use crate::data::params::MediaKind;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::data::value::Value;
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::muxer::{self, Muxer};
+use crate::pcm;
+use crate::rational::{rescale, Rational64};
+use crate::stream::Stream;
+
+/// Timestamp of the AIFF-C version 1 specification.
+const AIFC_VERSION: u32 = 0xa280_5140;
+
+/// Reads an IEEE 754 80-bit extended precision number.
+fn get_f80(buf: &[u8]) -> f64 {
+    let exp = i32::from(get_u16b(buf) & 0x7fff);
+    let mantissa = get_u64b(&buf[2..]);
+    if exp == 0 && mantissa == 0 {
+        0.0
+    } else {
+        mantissa as f64 * 2f64.powi(exp - 16383 - 63)
+    }
+}
+
+/// Writes an integer as an IEEE 754 80-bit extended precision number.
+fn put_f80(out: &mut Vec<u8>, v: u64) {
+    if v == 0 {
+        out.extend_from_slice(&[0; 10]);
+        return;
+    }
+    let shift = v.leading_zeros();
+    let exp = 16383 + 63 - shift as u16;
+    out.extend_from_slice(&exp.to_be_bytes());
+    out.extend_from_slice(&(v << shift).to_be_bytes());
+}
+
+/// Returns the codec name and sample format of the sound data.
+fn sound_format(compression: &[u8], bits: usize) -> Result<(String, Option<Soniton>)> {
+    let bits = (bits.div_ceil(8) * 8) as u8;
+    let fmt = match compression {
+        b"NONE" | b"twos" => Soniton::new(bits, true, false, false, false, true),
+        b"sowt" => Soniton::new(bits, false, false, false, false, true),
+        b"raw " => Soniton::new(8, false, false, false, false, false),
+        b"fl32" | b"FL32" => Soniton::new(32, true, false, false, true, true),
+        b"fl64" | b"FL64" => Soniton::new(64, true, false, false, true, true),
+        b"ulaw" | b"ULAW" => return Ok(("pcm_mulaw".to_owned(), None)),
+        b"alaw" | b"ALAW" => return Ok(("pcm_alaw".to_owned(), None)),
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "AIFF-C compression {}",
+                String::from_utf8_lossy(compression)
+            )))
+        }
+    };
+    if bits == 0 || bits > 32 && !fmt.float {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((pcm::codec_id(&fmt), Some(fmt)))
+}
+
+/// Common chunk contents.
+struct Comm {
+    channels: usize,
+    frames: u64,
+    rate: usize,
+    codec_id: String,
+    format: Option<Soniton>,
+    block_align: usize,
+}
+
+impl Comm {
+    fn parse(data: &[u8], aifc: bool) -> Result<Self> {
+        if data.len() < 18 || aifc && data.len() < 22 {
+            return Err(Error::InvalidData);
+        }
+        let channels = get_u16b(data) as usize;
+        let frames = u64::from(get_u32b(&data[2..]));
+        let bits = get_u16b(&data[6..]) as usize;
+        let rate = get_f80(&data[8..]).round();
+        let compression = if aifc { &data[18..22] } else { b"NONE" };
+
+        let (codec_id, format) = sound_format(compression, bits)?;
+        let sample_size = format.map_or(1, |fmt| fmt.bits as usize / 8);
+        if channels == 0 || !(1.0..=f64::from(u32::MAX)).contains(&rate) {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Comm {
+            channels,
+            frames,
+            rate: rate as usize,
+            codec_id,
+            format,
+            block_align: channels * sample_size,
+        })
+    }
+}
+
+/// AIFF demuxer.
+pub struct AiffDemuxer {
+    block_align: usize,
+    timebase: Rational64,
+    remaining: u64,
+    frames: u64,
+}
+
+impl Default for AiffDemuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AiffDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        AiffDemuxer {
+            block_align: 0,
+            timebase: Rational64::from_integer(1),
+            remaining: 0,
+            frames: 0,
+        }
+    }
+
+    fn add_stream(
+        &mut self,
+        comm: Comm,
+        sound_size: u64,
+        start: usize,
+        info: &mut GlobalInfo,
+    ) -> Result<SeekFrom> {
+        let map = pcm::channel_map(comm.channels)?;
+        let params = pcm::audio_params(comm.codec_id, comm.rate, map, comm.format);
+        let timebase = Rational64::new(1, comm.rate as i64);
+        let mut st = Stream::from_params(&params, timebase);
+        st.duration = Some(comm.frames);
+        info.add_stream(st);
+
+        self.block_align = comm.block_align;
+        self.timebase = timebase;
+        self.remaining = sound_size.min(comm.frames * comm.block_align as u64);
+        self.frames = 0;
+
+        Ok(SeekFrom::Current(start as i64))
+    }
+}
+
+impl Demuxer for AiffDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, 12)?;
+        let aifc = match (&data[..4], &data[8..12]) {
+            (b"FORM", b"AIFF") => false,
+            (b"FORM", b"AIFC") => true,
+            _ => return Err(Error::InvalidData),
+        };
+
+        let mut comm = None;
+        let mut pos = 12;
+        loop {
+            need(data, pos + 8)?;
+            let id = &data[pos..pos + 4];
+            let size = u64::from(get_u32b(&data[pos + 4..]));
+
+            if id == b"SSND" {
+                need(data, pos + 16)?;
+                let comm = comm.ok_or_else(|| {
+                    Error::Unsupported("sound data before the common chunk".to_owned())
+                })?;
+                let offset = u64::from(get_u32b(&data[pos + 8..]));
+                let start = pos + 16 + offset as usize;
+                need(data, start)?;
+                let sound_size = size.checked_sub(8 + offset).ok_or(Error::InvalidData)?;
+                return self.add_stream(comm, sound_size, start, info);
+            }
+
+            let end = pos + 8 + size as usize + (size & 1) as usize;
+            need(data, end)?;
+            if id == b"COMM" {
+                comm = Some(Comm::parse(&data[pos + 8..pos + 8 + size as usize], aifc)?);
+            }
+            pos = end;
+        }
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if self.remaining == 0 {
+            return Ok((SeekFrom::Current(0), Event::Eof));
+        }
+
+        let max = (pcm::FRAMES_PER_PACKET * self.block_align) as u64;
+        let size = self.remaining.min(max) as usize;
+        let data = buf.data();
+        need(data, size)?;
+
+        let duration = (size / self.block_align) as u64;
+        let mut pkt = Packet::with_capacity(size);
+        pkt.data.extend_from_slice(&data[..size]);
+        pkt.stream_index = 0;
+        pkt.is_key = true;
+        let ts = Timestamp::new(self.frames as i64, self.timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t
+            .set_duration(Some(Duration::new(duration, self.timebase)))?;
+
+        self.remaining -= size as u64;
+        self.frames += duration;
+
+        Ok((SeekFrom::Current(size as i64), Event::NewPacket(pkt)))
+    }
+}
+
+struct AiffDemuxerDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for AiffDemuxerDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(AiffDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if data.len() >= 12 && &data[..4] == b"FORM" {
+            match &data[8..12] {
+                b"AIFF" | b"AIFC" => 100,
+                _ => 0,
+            }
+        } else {
+            0
+        }
+    }
+}
+
+/// AIFF demuxer descriptor.
+pub const AIFF_DEMUXER_DESCR: &dyn demuxer::Descriptor = &AiffDemuxerDescr {
+    d: demuxer::Descr {
+        name: "aiff",
+        demuxer: "aiff",
+        description: "Audio Interchange File Format",
+        extensions: &["aiff", "aif", "aifc"],
+        mime: &["audio/aiff", "audio/x-aiff"],
+    },
+};
+
+/// Sound data layout of the file being written.
+struct Layout {
+    compression: Option<&'static [u8; 4]>,
+    bits: u16,
+    channels: usize,
+    rate: usize,
+    block_align: usize,
+}
+
+/// AIFF muxer.
+#[derive(Default)]
+pub struct AiffMuxer {
+    info: Option<GlobalInfo>,
+    layout: Option<Layout>,
+    expected: Option<u64>,
+    written: u64,
+    pending: Vec<u8>,
+}
+
+impl AiffMuxer {
+    /// Creates a new muxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write_chunks(&self, out: &mut dyn Write, frames: u64) -> Result<()> {
+        let layout = self.layout.as_ref().ok_or(Error::InvalidData)?;
+        let data_size = frames * layout.block_align as u64;
+        // AIFF-C common chunks end with an empty compression name.
+        let (comm_size, fver_size) = match layout.compression {
+            Some(_) => (24, 12),
+            None => (18, 0),
+        };
+        let form_size = 4 + 8 + comm_size + fver_size + 16 + data_size + (data_size & 1);
+        if form_size > u64::from(u32::MAX) || frames > u64::from(u32::MAX) {
+            return Err(Error::InvalidData);
+        }
+
+        let mut head = Vec::with_capacity(12 + 8 + comm_size as usize + fver_size as usize + 16);
+        head.extend_from_slice(b"FORM");
+        head.extend_from_slice(&(form_size as u32).to_be_bytes());
+        head.extend_from_slice(match layout.compression {
+            Some(_) => b"AIFC",
+            None => b"AIFF",
+        });
+        if fver_size > 0 {
+            head.extend_from_slice(b"FVER");
+            head.extend_from_slice(&4u32.to_be_bytes());
+            head.extend_from_slice(&AIFC_VERSION.to_be_bytes());
+        }
+        head.extend_from_slice(b"COMM");
+        head.extend_from_slice(&(comm_size as u32).to_be_bytes());
+        head.extend_from_slice(&(layout.channels as u16).to_be_bytes());
+        head.extend_from_slice(&(frames as u32).to_be_bytes());
+        head.extend_from_slice(&layout.bits.to_be_bytes());
+        put_f80(&mut head, layout.rate as u64);
+        if let Some(compression) = layout.compression {
+            head.extend_from_slice(compression);
+            head.extend_from_slice(&[0, 0]);
+        }
+        head.extend_from_slice(b"SSND");
+        head.extend_from_slice(&((data_size + 8) as u32).to_be_bytes());
+        head.extend_from_slice(&[0; 8]);
+
+        out.write_all(&head)?;
+        Ok(())
+    }
+}
+
+impl Muxer for AiffMuxer {
+    fn configure(&mut self) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+        let st = match info.streams.as_slice() {
+            [st] => st,
+            _ => return Err(Error::Unsupported("multiple streams".to_owned())),
+        };
+        let audio = match st.params.kind {
+            Some(MediaKind::Audio(ref audio)) => audio,
+            _ => return Err(Error::InvalidData),
+        };
+        let codec_id = st.params.codec_id.as_deref().unwrap_or("");
+        let fmt = pcm::sample_format(codec_id)
+            .ok_or_else(|| Error::Unsupported(format!("codec {}", codec_id)))?;
+        let compression: Option<&'static [u8; 4]> = match fmt {
+            Soniton { float: false, .. } if !fmt.signed => {
+                return Err(Error::Unsupported(format!("codec {}", codec_id)))
+            }
+            Soniton { float: false, .. } if fmt.be || fmt.bits == 8 => None,
+            Soniton { float: false, .. } => Some(b"sowt"),
+            Soniton { float: true, .. } if !fmt.be => {
+                return Err(Error::Unsupported(format!("codec {}", codec_id)))
+            }
+            Soniton { bits: 32, .. } => Some(b"fl32"),
+            _ => Some(b"fl64"),
+        };
+        let channels = audio.map.as_ref().map_or(0, |map| map.len());
+        if channels == 0 || audio.rate == 0 {
+            return Err(Error::InvalidData);
+        }
+
+        self.expected = st.duration.and_then(|duration| {
+            let frames = rescale(
+                duration as i64,
+                st.timebase,
+                Rational64::new(1, audio.rate as i64),
+            )?;
+            Some(frames.max(0) as u64)
+        });
+        self.layout = Some(Layout {
+            compression,
+            bits: u16::from(fmt.bits),
+            channels,
+            rate: audio.rate,
+            block_align: channels * fmt.bits as usize / 8,
+        });
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, out: &mut dyn Write) -> Result<()> {
+        match self.expected {
+            Some(frames) => self.write_chunks(out, frames),
+            None => Ok(()),
+        }
+    }
+
+    fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+        if pkt.stream_index != 0 {
+            return Err(Error::InvalidData);
+        }
+        let block_align = self.layout.as_ref().ok_or(Error::InvalidData)?.block_align;
+
+        match self.expected {
+            Some(frames) => {
+                let size = self.written + pkt.data.len() as u64;
+                if size > frames * block_align as u64 {
+                    return Err(Error::InvalidData);
+                }
+                out.write_all(&pkt.data)?;
+                self.written = size;
+            }
+            None => self.pending.extend_from_slice(&pkt.data),
+        }
+
+        Ok(())
+    }
+
+    fn write_trailer(&mut self, out: &mut dyn Write) -> Result<()> {
+        let block_align = self.layout.as_ref().ok_or(Error::InvalidData)?.block_align as u64;
+
+        let size = match self.expected {
+            Some(frames) => {
+                // Pad the sound data to the duration declared in the header.
+                let size = frames * block_align;
+                let padding = vec![0; (size - self.written) as usize];
+                out.write_all(&padding)?;
+                size
+            }
+            None => {
+                let frames = self.pending.len() as u64 / block_align;
+                let size = frames * block_align;
+                self.write_chunks(out, frames)?;
+                out.write_all(&self.pending[..size as usize])?;
+                size
+            }
+        };
+        if size & 1 == 1 {
+            out.write_all(&[0])?;
+        }
+
+        Ok(())
+    }
+
+    fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, _val: Value<'a>) -> Result<()> {
+        Err(Error::Unsupported(format!("{} key", key)))
+    }
+}
+
+struct AiffMuxerDescr {
+    d: muxer::Descr,
+    caps: muxer::Capabilities,
+}
+
+impl muxer::Descriptor for AiffMuxerDescr {
+    fn create(&self) -> Box<dyn Muxer> {
+        Box::new(AiffMuxer::new())
+    }
+    fn describe(&self) -> &muxer::Descr {
+        &self.d
+    }
+    fn capabilities(&self) -> &muxer::Capabilities {
+        &self.caps
+    }
+}
+
+/// AIFF muxer descriptor.
+pub const AIFF_MUXER_DESCR: &dyn muxer::Descriptor = &AiffMuxerDescr {
+    d: muxer::Descr {
+        name: "aiff",
+        demuxer: "aiff",
+        description: "Audio Interchange File Format",
+        extensions: &["aiff", "aif", "aifc"],
+        mime: &["audio/aiff", "audio/x-aiff"],
+    },
+    caps: muxer::Capabilities {
+        codecs: Some(&[
+            "pcm_s8",
+            "pcm_s16be",
+            "pcm_s16le",
+            "pcm_s24be",
+            "pcm_s24le",
+            "pcm_s32be",
+            "pcm_s32le",
+            "pcm_f32be",
+            "pcm_f64be",
+        ]),
+        max_streams: Some(1),
+        parameter_sets: muxer::ParameterSets::Any,
+        variable_frame_rate: false,
+        seekable_output: false,
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::metadata::Metadata;
+    use crate::demuxer::demux_file;
+
+    fn mux(codec_id: &str, duration: Option<u64>, data: &[u8]) -> Vec<u8> {
+        let params = pcm::audio_params(
+            codec_id.to_owned(),
+            44100,
+            pcm::channel_map(2).unwrap(),
+            pcm::sample_format(codec_id),
+        );
+        let mut st = Stream::from_params(&params, Rational64::new(1, 44100));
+        st.duration = duration;
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        info.add_stream(st);
+
+        let mut out = Vec::new();
+        let mut mux = AiffMuxer::new();
+        mux.set_global_info(info).unwrap();
+        mux.configure().unwrap();
+        mux.write_header(&mut out).unwrap();
+        for chunk in data.chunks(400) {
+            let mut pkt = Packet::new();
+            pkt.data = chunk.to_vec();
+            pkt.stream_index = 0;
+            mux.write_packet(&mut out, Arc::new(pkt)).unwrap();
+        }
+        mux.write_trailer(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn extended() {
+        let mut buf = Vec::new();
+        put_f80(&mut buf, 44100);
+        assert_eq!(buf, [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(get_f80(&buf), 44100.0);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..4 * 3000).map(|i| i as u8).collect();
+        let file = mux("pcm_s16be", None, &data);
+        assert_eq!(&file[8..12], b"AIFF");
+        assert_eq!(file, mux("pcm_s16be", Some(3000), &data));
+        assert_eq!(AIFF_DEMUXER_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(AIFF_DEMUXER_DESCR, file, 64);
+        let info = c.info;
+        let params = &info.streams[0].params;
+        assert_eq!(params.codec_id.as_deref(), Some("pcm_s16be"));
+        assert_eq!(info.streams[0].duration, Some(3000));
+        match params.kind {
+            Some(MediaKind::Audio(ref audio)) => {
+                assert_eq!(audio.rate, 44100);
+                assert_eq!(audio.map.as_ref().unwrap().len(), 2);
+            }
+            _ => panic!("not an audio stream"),
+        }
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[2].t.get_pts().map(Timestamp::get_value), Some(2048));
+        assert_eq!(
+            packets[2].t.get_duration().map(Duration::get_value),
+            Some(3000 - 2048)
+        );
+        let demuxed: Vec<u8> = packets.iter().flat_map(|p| p.data.clone()).collect();
+        assert_eq!(demuxed, data);
+    }
+
+    #[test]
+    fn aifc() {
+        let data: Vec<u8> = (0..4 * 10).map(|i| i as u8).collect();
+        let file = mux("pcm_s16le", None, &data);
+        assert_eq!(&file[8..12], b"AIFC");
+
+        let (c, packets) = demux_file(AIFF_DEMUXER_DESCR, file, 64);
+        let info = c.info;
+        let params = &info.streams[0].params;
+        assert_eq!(params.codec_id.as_deref(), Some("pcm_s16le"));
+        assert_eq!(packets[0].data, data);
+
+        let file = mux("pcm_f32be", Some(5), &data[..16]);
+        let (c, packets) = demux_file(AIFF_DEMUXER_DESCR, file, 64);
+        let info = c.info;
+        let params = &info.streams[0].params;
+        assert_eq!(params.codec_id.as_deref(), Some("pcm_f32be"));
+        assert_eq!(&packets[0].data[..16], &data[..16]);
+        assert_eq!(&packets[0].data[16..], &[0; 24][..]);
+    }
+}
@@ -0,0 +1,661 @@
+//!
+//! Animated PNG demuxer.
+//!
+//! The frames are decoded and composited, each packet holds the whole
+//! picture as `rawvideo` RGBA. A PNG without animation control chunk is
+//! exposed as a single frame. Interlaced pictures are not supported.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::canvas::{Canvas, Dispose, Rect, MAX_PIXELS};
+use crate::common::*;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::inflate;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Frame delays are expressed in this timebase.
+const TIMEBASE_DEN: u64 = 100_000;
+
+/// Chunk types.
+const IHDR: &[u8; 4] = b"IHDR";
+const PLTE: &[u8; 4] = b"PLTE";
+const TRNS: &[u8; 4] = b"tRNS";
+const IDAT: &[u8; 4] = b"IDAT";
+const IEND: &[u8; 4] = b"IEND";
+const ACTL: &[u8; 4] = b"acTL";
+const FCTL: &[u8; 4] = b"fcTL";
+const FDAT: &[u8; 4] = b"fdAT";
+
+/// Computes the CRC-32 of the chunks, as defined by ISO 3309.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Reads the chunk starting at `pos`, returning its type, its data and
+/// the position following it.
+fn read_chunk(data: &[u8], pos: usize) -> Result<([u8; 4], &[u8], usize)> {
+    need(data, pos + 8)?;
+    let len = get_u32b(&data[pos..]) as usize;
+    if len > 0x7fff_ffff {
+        return Err(Error::InvalidData);
+    }
+    let end = pos + 12 + len;
+    need(data, end)?;
+    if crc32(&data[pos + 4..end - 4]) != get_u32b(&data[end - 4..]) {
+        return Err(Error::InvalidData);
+    }
+    let kind = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+
+    Ok((kind, &data[pos + 8..end - 4], end))
+}
+
+/// Image header.
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() != 13 {
+            return Err(Error::InvalidData);
+        }
+        let header = Header {
+            width: get_u32b(data) as usize,
+            height: get_u32b(&data[4..]) as usize,
+            depth: data[8],
+            color: data[9],
+        };
+        let depths: &[u8] = match header.color {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => &[],
+        };
+        if header.width == 0
+            || header.height == 0
+            || !depths.contains(&header.depth)
+            || data[10] != 0
+            || data[11] != 0
+        {
+            return Err(Error::InvalidData);
+        }
+        if data[12] != 0 {
+            return Err(Error::Unsupported("interlaced PNG".to_owned()));
+        }
+        if header.width * header.height > MAX_PIXELS {
+            return Err(Error::Unsupported(format!(
+                "{}x{} picture",
+                header.width, header.height
+            )));
+        }
+
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+}
+
+/// Frame control chunk.
+#[derive(Clone, Copy, Debug)]
+struct FrameControl {
+    rect: Rect,
+    /// Delay, in `1/TIMEBASE_DEN` units.
+    delay: Option<u64>,
+    dispose: Dispose,
+    /// Whether the frame is composited over the canvas or replaces it.
+    blend: bool,
+}
+
+impl FrameControl {
+    fn parse(data: &[u8], header: &Header) -> Result<Self> {
+        if data.len() != 26 {
+            return Err(Error::InvalidData);
+        }
+        let rect = Rect {
+            width: get_u32b(&data[4..]) as usize,
+            height: get_u32b(&data[8..]) as usize,
+            x: get_u32b(&data[12..]) as usize,
+            y: get_u32b(&data[16..]) as usize,
+        };
+        if rect.width == 0
+            || rect.height == 0
+            || rect.x + rect.width > header.width
+            || rect.y + rect.height > header.height
+        {
+            return Err(Error::InvalidData);
+        }
+        let num = u64::from(get_u16b(&data[20..]));
+        let den = match get_u16b(&data[22..]) {
+            0 => 100,
+            den => u64::from(den),
+        };
+        let dispose = match data[24] {
+            0 => Dispose::None,
+            1 => Dispose::Background,
+            2 => Dispose::Previous,
+            _ => return Err(Error::InvalidData),
+        };
+
+        Ok(FrameControl {
+            rect,
+            delay: Some(num * TIMEBASE_DEN / den),
+            dispose,
+            blend: data[25] == 1,
+        })
+    }
+}
+
+/// Reads the sample `index` of a row.
+fn sample(line: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => get_u16b(&line[index * 2..]),
+        8 => u16::from(line[index]),
+        _ => {
+            let bit = index * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            u16::from(line[bit / 8] >> shift) & ((1 << depth) - 1)
+        }
+    }
+}
+
+/// Scales a sample to 8 bits.
+fn scale(v: u16, depth: u8) -> u8 {
+    match depth {
+        16 => (v >> 8) as u8,
+        8 => v as u8,
+        _ => (u32::from(v) * 255 / ((1 << depth) - 1)) as u8,
+    }
+}
+
+/// Reverses the filter of a row, given the previous one.
+fn unfilter(filter: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> Result<()> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in bpp..line.len() {
+                line[i] = line[i].wrapping_add(line[i - bpp]);
+            }
+        }
+        2 => {
+            for (v, &up) in line.iter_mut().zip(prev) {
+                *v = v.wrapping_add(up);
+            }
+        }
+        3 => {
+            for i in 0..line.len() {
+                let left = if i >= bpp { line[i - bpp] } else { 0 };
+                let avg = (u16::from(left) + u16::from(prev[i])) / 2;
+                line[i] = line[i].wrapping_add(avg as u8);
+            }
+        }
+        4 => {
+            for i in 0..line.len() {
+                let (a, c) = if i >= bpp {
+                    (line[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                let b = prev[i];
+                let p = i16::from(a) + i16::from(b) - i16::from(c);
+                let (pa, pb, pc) = (
+                    (p - i16::from(a)).abs(),
+                    (p - i16::from(b)).abs(),
+                    (p - i16::from(c)).abs(),
+                );
+                let pred = if pa <= pb && pa <= pc {
+                    a
+                } else if pb <= pc {
+                    b
+                } else {
+                    c
+                };
+                line[i] = line[i].wrapping_add(pred);
+            }
+        }
+        _ => return Err(Error::InvalidData),
+    }
+    Ok(())
+}
+
+/// Palette and transparency of an image.
+#[derive(Default)]
+struct Colors {
+    palette: Vec<[u8; 3]>,
+    trns: Vec<u8>,
+}
+
+impl Colors {
+    /// Converts the pixel `x` of a row to RGBA.
+    fn rgba(&self, header: &Header, line: &[u8], x: usize) -> Result<[u8; 4]> {
+        let depth = header.depth;
+        let channels = header.channels();
+        let s = |k: usize| sample(line, x * channels + k, depth);
+        let key = |k: usize| get_u16b(&self.trns[k * 2..]);
+
+        let rgba = match header.color {
+            0 => {
+                let g = s(0);
+                let transparent = self.trns.len() >= 2 && key(0) == g;
+                let g = scale(g, depth);
+                [g, g, g, if transparent { 0 } else { 255 }]
+            }
+            2 => {
+                let (r, g, b) = (s(0), s(1), s(2));
+                let transparent = self.trns.len() >= 6 && key(0) == r && key(1) == g && key(2) == b;
+                [
+                    scale(r, depth),
+                    scale(g, depth),
+                    scale(b, depth),
+                    if transparent { 0 } else { 255 },
+                ]
+            }
+            3 => {
+                let index = s(0) as usize;
+                let [r, g, b] = *self.palette.get(index).ok_or(Error::InvalidData)?;
+                [r, g, b, self.trns.get(index).copied().unwrap_or(255)]
+            }
+            4 => {
+                let g = scale(s(0), depth);
+                [g, g, g, scale(s(1), depth)]
+            }
+            _ => [
+                scale(s(0), depth),
+                scale(s(1), depth),
+                scale(s(2), depth),
+                scale(s(3), depth),
+            ],
+        };
+
+        Ok(rgba)
+    }
+}
+
+/// Decodes the compressed data of a frame, returning its RGBA rows.
+fn decode(header: &Header, colors: &Colors, rect: &Rect, zdata: &[u8]) -> Result<Vec<[u8; 4]>> {
+    let bits = header.channels() * header.depth as usize;
+    let stride = (rect.width * bits).div_ceil(8);
+    let bpp = (bits / 8).max(1);
+    let size = rect.height * (stride + 1);
+    let mut raw = inflate::uncompress(zdata, size)?;
+    if raw.len() != size {
+        return Err(Error::InvalidData);
+    }
+
+    let mut pixels = Vec::with_capacity(rect.width * rect.height);
+    let mut prev = vec![0; stride];
+    for row in raw.chunks_mut(stride + 1) {
+        let (&mut filter, line) = row.split_first_mut().ok_or(Error::InvalidData)?;
+        unfilter(filter, line, &prev, bpp)?;
+        for x in 0..rect.width {
+            pixels.push(colors.rgba(header, line, x)?);
+        }
+        prev.copy_from_slice(line);
+    }
+
+    Ok(pixels)
+}
+
+/// APNG demuxer.
+#[derive(Default)]
+pub struct ApngDemuxer {
+    header: Option<Header>,
+    colors: Colors,
+    animated: bool,
+    canvas: Option<Canvas>,
+    /// Frame whose data is being gathered.
+    frame: Option<(FrameControl, Vec<u8>)>,
+    pts: i64,
+}
+
+impl ApngDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn finish_frame(&mut self) -> Result<Event> {
+        let (control, zdata) = self.frame.take().ok_or(Error::InvalidData)?;
+        let header = self.header.as_ref().ok_or(Error::InvalidData)?;
+        let canvas = self.canvas.as_mut().ok_or(Error::InvalidData)?;
+        let pixels = decode(header, &self.colors, &control.rect, &zdata)?;
+
+        let rect = control.rect;
+        canvas.start_frame(rect, control.dispose);
+        for (i, &rgba) in pixels.iter().enumerate() {
+            let (x, y) = (rect.x + i % rect.width, rect.y + i / rect.width);
+            if control.blend {
+                canvas.blend(x, y, rgba);
+            } else {
+                canvas.set(x, y, rgba);
+            }
+        }
+
+        let mut pkt = canvas.packet();
+        pkt.stream_index = 0;
+        let timebase = Rational64::new(1, TIMEBASE_DEN as i64);
+        let ts = Timestamp::new(self.pts, timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.t
+            .set_duration(control.delay.map(|d| Duration::new(d, timebase)))?;
+        self.pts += control.delay.unwrap_or(0) as i64;
+
+        Ok(Event::NewPacket(pkt))
+    }
+}
+
+impl Demuxer for ApngDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, SIGNATURE.len())?;
+        if data[..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::InvalidData);
+        }
+
+        let (kind, chunk, mut pos) = read_chunk(data, SIGNATURE.len())?;
+        if &kind != IHDR {
+            return Err(Error::InvalidData);
+        }
+        let header = Header::parse(chunk)?;
+
+        let mut colors = Colors::default();
+        let mut animated = false;
+        loop {
+            let (kind, chunk, end) = read_chunk(data, pos)?;
+            match &kind {
+                IDAT | FCTL => break,
+                IEND => return Err(Error::InvalidData),
+                PLTE => {
+                    colors.palette = chunk.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+                }
+                TRNS => colors.trns = chunk.to_vec(),
+                ACTL if chunk.len() == 8 => {
+                    animated = true;
+                    info.metadata
+                        .insert("loop_count", u64::from(get_u32b(&chunk[4..])));
+                }
+                _ => {}
+            }
+            pos = end;
+        }
+        if header.color == 3 && colors.palette.is_empty() {
+            return Err(Error::InvalidData);
+        }
+
+        let params = Canvas::params(header.width, header.height);
+        let timebase = Rational64::new(1, TIMEBASE_DEN as i64);
+        info.add_stream(Stream::from_params(&params, timebase));
+
+        self.canvas = Some(Canvas::new(header.width, header.height));
+        self.header = Some(header);
+        self.colors = colors;
+        self.animated = animated;
+        self.frame = None;
+        self.pts = 0;
+
+        Ok(SeekFrom::Current(pos as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let data = buf.data();
+        let (kind, chunk, end) = read_chunk(data, 0)?;
+
+        // A frame is complete once a chunk not carrying its data follows.
+        let gathered = self.frame.as_ref().is_some_and(|(_, z)| !z.is_empty());
+        if gathered && &kind != IDAT && &kind != FDAT {
+            return Ok((SeekFrom::Current(0), self.finish_frame()?));
+        }
+
+        match &kind {
+            FCTL => {
+                let header = self.header.as_ref().ok_or(Error::InvalidData)?;
+                self.frame = Some((FrameControl::parse(chunk, header)?, Vec::new()));
+            }
+            IDAT => {
+                // The default image is not part of the animation unless a
+                // frame control chunk precedes it.
+                if self.frame.is_none() && !self.animated {
+                    let header = self.header.as_ref().ok_or(Error::InvalidData)?;
+                    let control = FrameControl {
+                        rect: Rect {
+                            x: 0,
+                            y: 0,
+                            width: header.width,
+                            height: header.height,
+                        },
+                        delay: None,
+                        dispose: Dispose::None,
+                        blend: false,
+                    };
+                    self.frame = Some((control, Vec::new()));
+                }
+                if let Some((_, zdata)) = self.frame.as_mut() {
+                    zdata.extend_from_slice(chunk);
+                }
+            }
+            FDAT => {
+                let (_, zdata) = self.frame.as_mut().ok_or(Error::InvalidData)?;
+                if chunk.len() < 4 {
+                    return Err(Error::InvalidData);
+                }
+                zdata.extend_from_slice(&chunk[4..]);
+            }
+            IEND => return Ok((SeekFrom::Current(0), Event::Eof)),
+            _ => {}
+        }
+
+        Ok((SeekFrom::Current(end as i64), Event::Continue))
+    }
+}
+
+struct ApngDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for ApngDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(ApngDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if data.starts_with(&SIGNATURE) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// APNG demuxer descriptor.
+pub const APNG_DESCR: &dyn demuxer::Descriptor = &ApngDescr {
+    d: demuxer::Descr {
+        name: "apng",
+        demuxer: "apng",
+        description: "Animated Portable Network Graphics",
+        extensions: &["apng", "png"],
+        mime: &["image/apng", "image/png"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::demuxer::{demux_file, Context};
+    use std::io::Cursor;
+
+    fn chunk(file: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        let start = file.len();
+        file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        file.extend_from_slice(kind);
+        file.extend_from_slice(data);
+        let crc = crc32(&file[start + 4..]);
+        file.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    /// Compresses as a single stored deflate block.
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01, 0x01];
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(data.len() as u16)).to_le_bytes());
+        out.extend_from_slice(data);
+        let (mut a, mut b) = (1u32, 0u32);
+        for &v in data {
+            a = (a + u32::from(v)) % 65521;
+            b = (b + a) % 65521;
+        }
+        out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        out
+    }
+
+    fn ihdr(file: &mut Vec<u8>, width: u32, height: u32, depth: u8, color: u8) {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[depth, color, 0, 0, 0]);
+        chunk(file, IHDR, &data);
+    }
+
+    fn fctl(file: &mut Vec<u8>, seq: u32, rect: [u32; 4], delay: [u16; 2], ops: [u8; 2]) {
+        let mut data = seq.to_be_bytes().to_vec();
+        for v in &rect {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        for v in &delay {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&ops);
+        chunk(file, FCTL, &data);
+    }
+
+    /// Lays out RGBA pixels as the RGBA format does, alpha first.
+    fn pixels(rgba: &[[u8; 4]]) -> Vec<u8> {
+        rgba.iter()
+            .flat_map(|p| vec![p[3], p[2], p[1], p[0]])
+            .collect()
+    }
+
+    #[test]
+    fn filters() {
+        let mut file = SIGNATURE.to_vec();
+        ihdr(&mut file, 2, 3, 8, 2);
+        let raw = [
+            1, 10, 20, 30, 5, 5, 5, // Sub
+            4, 1, 1, 1, 0, 0, 0, // Paeth
+            3, 0, 0, 0, 0, 0, 0, // Average
+        ];
+        chunk(&mut file, IDAT, &zlib(&raw));
+        chunk(&mut file, IEND, &[]);
+        assert_eq!(APNG_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(APNG_DESCR, file, 16);
+        assert_eq!(
+            c.info.streams[0].params.codec_id.as_deref(),
+            Some("rawvideo")
+        );
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].t.get_pts().map(Timestamp::get_value), Some(0));
+        assert_eq!(packets[0].t.get_duration().map(Duration::get_value), None);
+        assert_eq!(
+            packets[0].data,
+            pixels(&[
+                [10, 20, 30, 255],
+                [15, 25, 35, 255],
+                [11, 21, 31, 255],
+                [15, 25, 35, 255],
+                [5, 10, 15, 255],
+                [10, 17, 25, 255],
+            ])
+        );
+    }
+
+    #[test]
+    fn animation() {
+        let mut file = SIGNATURE.to_vec();
+        ihdr(&mut file, 3, 2, 2, 3);
+        chunk(&mut file, ACTL, &[0, 0, 0, 2, 0, 0, 0, 0]);
+        chunk(&mut file, PLTE, &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        chunk(&mut file, TRNS, &[255, 255, 0]);
+        fctl(&mut file, 0, [3, 2, 0, 0], [1, 10], [0, 0]);
+        chunk(&mut file, IDAT, &zlib(&[0, 0x18, 0, 0x50]));
+        fctl(&mut file, 1, [2, 1, 1, 0], [0, 0], [0, 1]);
+        let mut fdat = 2u32.to_be_bytes().to_vec();
+        fdat.extend_from_slice(&zlib(&[0, 0x80]));
+        chunk(&mut file, FDAT, &fdat);
+        chunk(&mut file, IEND, &[]);
+
+        let (c, packets) = demux_file(APNG_DESCR, file, 16);
+        assert_eq!(c.info.streams[0].timebase, Rational64::new(1, 100_000));
+        assert_eq!(c.info.metadata.get_u64("loop_count"), Some(0));
+        assert_eq!(packets.len(), 2);
+        assert_eq!(
+            packets[0].t.get_duration().map(Duration::get_value),
+            Some(10_000)
+        );
+        assert_eq!(
+            packets[1].t.get_pts().map(Timestamp::get_value),
+            Some(10_000)
+        );
+        assert_eq!(
+            packets[1].t.get_duration().map(Duration::get_value),
+            Some(0)
+        );
+
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        let clear = [0, 0, 255, 0];
+        assert_eq!(
+            packets[0].data,
+            pixels(&[red, green, clear, green, green, red])
+        );
+        // The transparent pixel blended over leaves the green one.
+        assert_eq!(
+            packets[1].data,
+            pixels(&[red, green, red, green, green, red])
+        );
+    }
+
+    #[test]
+    fn corrupt_chunk() {
+        let mut file = SIGNATURE.to_vec();
+        ihdr(&mut file, 1, 1, 8, 0);
+        file[20] ^= 1;
+        let r = AccReader::with_capacity(16, Cursor::new(file));
+        let mut c = Context::new(APNG_DESCR.create(), Box::new(r));
+        assert!(c.read_headers().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//!
+//! H.264 and HEVC access unit handling.
+//!
+//! The packets of some containers hold arbitrary fragments of an Annex B
+//! byte stream, such as the PES payloads of the MPEG transport streams.
+//! A `Merger` rechunks them into whole access units, as the decoders and
+//! the MP4 muxing expect, the Annex B parsers telling where they end.
+//!
+//! The access units can be split back into NAL units and converted from
+//! and to the length prefixed NAL units of MP4.
+//!
+
+use std::collections::VecDeque;
+
+use crate::data::packet::Packet;
+use crate::data::timeinfo::TimeInfo;
+use crate::error::*;
+use crate::raw::annexb::{nal_units, H264Parser, HevcParser};
+use crate::raw::Parser;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Packet given to a merger, by its position in the merged data.
+struct Input {
+    end: usize,
+    stream_index: isize,
+    t: TimeInfo,
+    /// Tells if an access unit starting in the packet took its timestamps.
+    used: bool,
+}
+
+/// Merges packets holding fragments of an Annex B byte stream into packets
+/// of whole access units.
+///
+/// An access unit takes the timestamps of the packet it starts in, if no
+/// previous access unit starting in the same packet took them, as in the
+/// PES packets.
+pub struct Merger {
+    parser: Box<dyn Parser>,
+    data: Vec<u8>,
+    inputs: VecDeque<Input>,
+    /// Position of `data` in the merged data.
+    pos: usize,
+}
+
+impl Merger {
+    /// Creates a new merger, its parser delimiting the access units.
+    pub fn new(parser: Box<dyn Parser>) -> Self {
+        Merger {
+            parser,
+            data: Vec::new(),
+            inputs: VecDeque::new(),
+            pos: 0,
+        }
+    }
+
+    /// Creates a new merger for the `h264` or `hevc` codec.
+    pub fn from_codec(codec_id: &str) -> Option<Self> {
+        let parser: Box<dyn Parser> = match codec_id {
+            "h264" => Box::<H264Parser>::default(),
+            "hevc" => Box::new(HevcParser),
+            _ => return None,
+        };
+        Some(Merger::new(parser))
+    }
+
+    /// Gives a packet to the merger.
+    pub fn push(&mut self, pkt: &Packet) {
+        self.data.extend_from_slice(&pkt.data);
+        self.inputs.push_back(Input {
+            end: self.pos + self.data.len(),
+            stream_index: pkt.stream_index,
+            t: pkt.t.clone(),
+            used: false,
+        });
+    }
+
+    /// Returns the next whole access unit, `eof` telling that no packet
+    /// follows so the data left is one.
+    ///
+    /// Returns `None` if more packets are needed.
+    pub fn pull(&mut self, eof: bool) -> Result<Option<Packet>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let frame = match self.parser.frame(&self.data, eof) {
+            Ok(frame) => frame,
+            Err(Error::MoreDataNeeded(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let size = frame.size.clamp(1, self.data.len());
+
+        let mut pkt = Packet::new();
+        pkt.data = self.data.drain(..size).collect();
+        pkt.is_key = frame.is_key;
+        let start = self.pos;
+        self.pos += size;
+        if let Some(input) = self.inputs.iter_mut().find(|input| input.end > start) {
+            pkt.stream_index = input.stream_index;
+            if !input.used {
+                input.used = true;
+                pkt.t = input.t.clone();
+            } else {
+                pkt.t.timebase = input.t.timebase;
+            }
+        }
+        while let Some(input) = self.inputs.front() {
+            if input.end > self.pos {
+                break;
+            }
+            self.inputs.pop_front();
+        }
+
+        Ok(Some(pkt))
+    }
+}
+
+/// Splits an Annex B packet into packets of one NAL unit each.
+///
+/// The first one keeps the timestamps and the key flag of the packet.
+pub fn split(pkt: &Packet) -> Vec<Packet> {
+    nal_units(&pkt.data)
+        .enumerate()
+        .map(|(i, (_, nal))| {
+            let mut unit = Packet::with_capacity(nal.len() + START_CODE.len());
+            unit.data.extend_from_slice(&START_CODE);
+            unit.data.extend_from_slice(nal);
+            unit.stream_index = pkt.stream_index;
+            unit.pos = pkt.pos;
+            if i == 0 {
+                unit.t = pkt.t.clone();
+                unit.is_key = pkt.is_key;
+            } else {
+                unit.t.timebase = pkt.t.timebase;
+            }
+            unit
+        })
+        .collect()
+}
+
+/// Converts Annex B data to NAL units prefixed by their 32 bit size.
+pub fn to_length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (_, nal) in nal_units(data) {
+        out.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+/// Converts NAL units prefixed by their size, on `length_size` bytes as
+/// told by the decoder configuration record, to Annex B data.
+pub fn from_length_prefixed(mut data: &[u8], length_size: usize) -> Result<Vec<u8>> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(Error::InvalidData);
+    }
+    let mut out = Vec::with_capacity(data.len() + data.len() / 4);
+    while !data.is_empty() {
+        let size = data
+            .get(..length_size)
+            .ok_or(Error::InvalidData)?
+            .iter()
+            .fold(0, |size, &b| size << 8 | usize::from(b));
+        let nal = data
+            .get(length_size..length_size + size)
+            .ok_or(Error::InvalidData)?;
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nal);
+        data = &data[length_size + size..];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::timeinfo::Timestamp;
+    use crate::rational::Rational64;
+
+    const AUD: [u8; 6] = [0, 0, 0, 1, 0x09, 0xf0];
+    const IDR: [u8; 6] = [0, 0, 0, 1, 0x65, 0x88];
+    const SLICE: [u8; 6] = [0, 0, 0, 1, 0x41, 0x9a];
+
+    fn packet(data: &[u8], pts: Option<i64>) -> Packet {
+        let mut pkt = Packet::new();
+        pkt.data = data.to_vec();
+        pkt.stream_index = 1;
+        let timebase = Rational64::new(1, 90000);
+        pkt.t
+            .set_pts(pts.map(|pts| Timestamp::new(pts, timebase)))
+            .unwrap();
+        pkt.t.timebase = Some(timebase);
+        pkt
+    }
+
+    #[test]
+    fn merge() {
+        let stream = [&AUD[..], &IDR, &AUD, &SLICE, &AUD, &SLICE].concat();
+        let mut merger = Merger::from_codec("h264").unwrap();
+        // The second access unit starts in the first packet, the third in
+        // the second one.
+        merger.push(&packet(&stream[..18], Some(0)));
+        assert_eq!(
+            merger.pull(false).unwrap().map(|pkt| pkt.data.len()),
+            Some(12)
+        );
+        assert!(merger.pull(false).unwrap().is_none());
+        merger.push(&packet(&stream[18..], Some(3600)));
+
+        let au = merger.pull(false).unwrap().unwrap();
+        assert_eq!(au.data, [&AUD[..], &SLICE].concat());
+        assert_eq!(
+            (
+                au.t.get_pts().map(Timestamp::get_value),
+                au.is_key,
+                au.stream_index
+            ),
+            (None, false, 1)
+        );
+        assert!(merger.pull(false).unwrap().is_none());
+        let au = merger.pull(true).unwrap().unwrap();
+        assert_eq!(au.t.get_pts().map(Timestamp::get_value), Some(3600));
+        assert_eq!(au.t.timebase, Some(Rational64::new(1, 90000)));
+        assert!(merger.pull(true).unwrap().is_none());
+
+        assert!(Merger::from_codec("av1").is_none());
+    }
+
+    #[test]
+    fn merge_timestamps() {
+        let mut merger = Merger::from_codec("h264").unwrap();
+        merger.push(&packet(&AUD, Some(0)));
+        merger.push(&packet(&IDR, None));
+        merger.push(&packet(&[&AUD[..], &SLICE].concat(), Some(3600)));
+        let au = merger.pull(false).unwrap().unwrap();
+        assert_eq!(
+            (
+                au.data.len(),
+                au.t.get_pts().map(Timestamp::get_value),
+                au.is_key
+            ),
+            (12, Some(0), true)
+        );
+        let au = merger.pull(true).unwrap().unwrap();
+        assert_eq!(
+            (au.data.len(), au.t.get_pts().map(Timestamp::get_value)),
+            (12, Some(3600))
+        );
+    }
+
+    #[test]
+    fn split_and_convert() {
+        let mut pkt = packet(&[&AUD[..], &[0, 0, 1, 0x65, 0x88]].concat(), Some(7));
+        pkt.is_key = true;
+        let units = split(&pkt);
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].data, AUD);
+        assert_eq!(
+            (
+                units[0].t.get_pts().map(Timestamp::get_value),
+                units[0].is_key
+            ),
+            (Some(7), true)
+        );
+        assert_eq!(units[1].data, IDR);
+        assert_eq!(
+            (
+                units[1].t.get_pts().map(Timestamp::get_value),
+                units[1].is_key
+            ),
+            (None, false)
+        );
+
+        let prefixed = to_length_prefixed(&pkt.data);
+        assert_eq!(prefixed, [0, 0, 0, 2, 0x09, 0xf0, 0, 0, 0, 2, 0x65, 0x88]);
+        let annexb = [&AUD[..], &IDR].concat();
+        assert_eq!(from_length_prefixed(&prefixed, 4).unwrap(), annexb);
+        assert_eq!(
+            from_length_prefixed(&[2, 0x09, 0xf0, 2, 0x65, 0x88], 1).unwrap(),
+            annexb
+        );
+        assert!(from_length_prefixed(&prefixed[..11], 4).is_err());
+        assert!(from_length_prefixed(&prefixed, 3).is_err());
+    }
+}
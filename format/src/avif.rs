@@ -0,0 +1,645 @@
+//!
+//! AVIF still image demuxer and muxer.
+//!
+//! The primary item of the file is exposed as a single frame video
+//! stream, its `av1C` configuration as extradata. Image sequences and
+//! grid images are not supported.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::{SeekFrom, Write};
+use std::sync::Arc;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::Timestamp;
+use crate::data::value::Value;
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::isobmff::*;
+use crate::muxer::{self, Muxer};
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Brands written in the file type box.
+const BRANDS: [&[u8; 4]; 3] = [b"avif", b"mif1", b"miaf"];
+
+/// Identifier of the item written by the muxer.
+const ITEM_ID: u32 = 1;
+
+/// Tells if the data starts with a file type box listing the AVIF brand.
+fn is_avif(data: &[u8]) -> bool {
+    match read_box(data, 0) {
+        Ok((kind, payload, _)) if &kind == b"ftyp" => {
+            payload.chunks_exact(4).any(|brand| brand == b"avif")
+        }
+        _ => false,
+    }
+}
+
+/// Item location, as described by the `iloc` box.
+#[derive(Clone, Debug, Default)]
+struct Location {
+    /// Whether the offsets point in the `idat` box instead of the file.
+    in_idat: bool,
+    /// Offset and length of each extent.
+    extents: Vec<(u64, u64)>,
+}
+
+fn parse_iloc(data: &[u8], item: u32) -> Result<Option<Location>> {
+    let (version, _, mut data) = full_box(data)?;
+    if version > 2 || data.len() < 2 {
+        return Err(Error::InvalidData);
+    }
+    let offset_size = (data[0] >> 4) as usize;
+    let length_size = (data[0] & 15) as usize;
+    let base_offset_size = (data[1] >> 4) as usize;
+    let index_size = if version > 0 {
+        (data[1] & 15) as usize
+    } else {
+        0
+    };
+    data = &data[2..];
+    let count = get_sized(&mut data, if version < 2 { 2 } else { 4 })?;
+
+    for _ in 0..count {
+        let id = get_sized(&mut data, if version < 2 { 2 } else { 4 })?;
+        let method = if version > 0 {
+            get_sized(&mut data, 2)? & 15
+        } else {
+            0
+        };
+        get_sized(&mut data, 2)?;
+        let base = get_sized(&mut data, base_offset_size)?;
+        let extent_count = get_sized(&mut data, 2)?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            get_sized(&mut data, index_size)?;
+            let offset = get_sized(&mut data, offset_size)?;
+            let length = get_sized(&mut data, length_size)?;
+            extents.push((base + offset, length));
+        }
+        if id != u64::from(item) {
+            continue;
+        }
+        if method > 1 {
+            return Err(Error::Unsupported("AVIF item references".to_owned()));
+        }
+        if extents.iter().any(|&(_, length)| length == 0) {
+            return Err(Error::Unsupported(
+                "AVIF extents extending to the end of file".to_owned(),
+            ));
+        }
+        return Ok(Some(Location {
+            in_idat: method == 1,
+            extents,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Returns the type of an item from the `iinf` box.
+fn parse_iinf(data: &[u8], item: u32) -> Result<Option<[u8; 4]>> {
+    let (version, _, data) = full_box(data)?;
+    let skip = if version == 0 { 2 } else { 4 };
+    if data.len() < skip {
+        return Err(Error::InvalidData);
+    }
+    for (kind, infe) in children(&data[skip..])? {
+        if &kind != b"infe" {
+            continue;
+        }
+        let (version, _, infe) = full_box(infe)?;
+        if version < 2 {
+            continue;
+        }
+        let mut infe = infe;
+        let id = get_sized(&mut infe, if version == 2 { 2 } else { 4 })?;
+        get_sized(&mut infe, 2)?;
+        if id == u64::from(item) && infe.len() >= 4 {
+            return Ok(Some([infe[0], infe[1], infe[2], infe[3]]));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the properties associated to an item from the `iprp` box.
+fn parse_iprp(data: &[u8], item: u32) -> Result<Vec<([u8; 4], &[u8])>> {
+    let boxes = children(data)?;
+    let ipco = match boxes.iter().find(|(kind, _)| kind == b"ipco") {
+        Some((_, ipco)) => children(ipco)?,
+        None => return Err(Error::InvalidData),
+    };
+
+    let mut properties = Vec::new();
+    for (_, ipma) in boxes.iter().filter(|(kind, _)| kind == b"ipma") {
+        let (version, flags, mut ipma) = full_box(ipma)?;
+        let count = get_sized(&mut ipma, 4)?;
+        for _ in 0..count {
+            let id = get_sized(&mut ipma, if version < 1 { 2 } else { 4 })?;
+            let associations = get_sized(&mut ipma, 1)?;
+            for _ in 0..associations {
+                let index = if flags & 1 != 0 {
+                    get_sized(&mut ipma, 2)? & 0x7fff
+                } else {
+                    get_sized(&mut ipma, 1)? & 0x7f
+                } as usize;
+                if id != u64::from(item) || index == 0 {
+                    continue;
+                }
+                let property = ipco.get(index - 1).ok_or(Error::InvalidData)?;
+                properties.push(*property);
+            }
+        }
+    }
+
+    Ok(properties)
+}
+
+/// Primary image, as described by the `meta` box.
+struct Image {
+    location: Location,
+    idat: Vec<u8>,
+    width: usize,
+    height: usize,
+    config: Vec<u8>,
+}
+
+fn parse_meta(data: &[u8]) -> Result<Image> {
+    let (_, _, data) = full_box(data)?;
+    let boxes = children(data)?;
+    let find = |name: &[u8; 4]| {
+        boxes
+            .iter()
+            .find(|(kind, _)| kind == name)
+            .map(|(_, payload)| *payload)
+    };
+
+    match find(b"hdlr").map(full_box) {
+        Some(Ok((_, _, hdlr))) if hdlr.len() >= 8 && &hdlr[4..8] == b"pict" => {}
+        _ => return Err(Error::InvalidData),
+    }
+    let item = match find(b"pitm").map(full_box) {
+        Some(Ok((0, _, pitm))) if pitm.len() >= 2 => u32::from(get_u16b(pitm)),
+        Some(Ok((_, _, pitm))) if pitm.len() >= 4 => get_u32b(pitm),
+        _ => return Err(Error::InvalidData),
+    };
+
+    match parse_iinf(find(b"iinf").ok_or(Error::InvalidData)?, item)? {
+        Some(kind) if &kind == b"av01" => {}
+        Some(kind) => {
+            return Err(Error::Unsupported(format!(
+                "AVIF {} items",
+                String::from_utf8_lossy(&kind)
+            )))
+        }
+        None => return Err(Error::InvalidData),
+    }
+    let location =
+        parse_iloc(find(b"iloc").ok_or(Error::InvalidData)?, item)?.ok_or(Error::InvalidData)?;
+
+    let mut image = Image {
+        location,
+        idat: find(b"idat").unwrap_or(&[]).to_vec(),
+        width: 0,
+        height: 0,
+        config: Vec::new(),
+    };
+    for (kind, property) in parse_iprp(find(b"iprp").ok_or(Error::InvalidData)?, item)? {
+        match &kind {
+            b"ispe" => {
+                let (_, _, ispe) = full_box(property)?;
+                if ispe.len() < 8 {
+                    return Err(Error::InvalidData);
+                }
+                image.width = get_u32b(ispe) as usize;
+                image.height = get_u32b(&ispe[4..]) as usize;
+            }
+            b"av1C" => image.config = property.to_vec(),
+            _ => {}
+        }
+    }
+    if image.config.is_empty() {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(image)
+}
+
+fn params(width: usize, height: usize, config: Vec<u8>) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Video(VideoInfo {
+            width,
+            height,
+            format: None,
+        })),
+        codec_id: Some("av1".to_owned()),
+        extradata: Some(config),
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// AVIF demuxer.
+#[derive(Default)]
+pub struct AvifDemuxer {
+    /// Extents of the image left to read, as file offsets.
+    extents: Vec<(u64, u64)>,
+    /// Image data read so far.
+    data: Vec<u8>,
+    /// File offset of the buffered data.
+    pos: u64,
+    done: bool,
+}
+
+impl AvifDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Demuxer for AvifDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        read_box(data, 0)?;
+        if !is_avif(data) {
+            return Err(Error::InvalidData);
+        }
+
+        // The image data is read later on, it may precede the metadata.
+        let mut pos = 0;
+        let image = loop {
+            let (kind, payload, end) = read_box(data, pos)?;
+            if &kind == b"meta" {
+                break parse_meta(payload)?;
+            }
+            pos = end;
+        };
+
+        let mut extents = Vec::new();
+        let mut image_data = Vec::new();
+        for &(offset, length) in &image.location.extents {
+            if image.location.in_idat {
+                let range = offset as usize..(offset + length) as usize;
+                image_data.extend_from_slice(image.idat.get(range).ok_or(Error::InvalidData)?);
+            } else {
+                extents.push((offset, length));
+            }
+        }
+        if extents.windows(2).any(|w| w[1].0 < w[0].0 + w[0].1) {
+            return Err(Error::Unsupported("unordered AVIF extents".to_owned()));
+        }
+
+        let params = params(image.width, image.height, image.config);
+        info.add_stream(Stream::from_params(&params, Rational64::new(1, 1)));
+
+        self.extents = extents;
+        self.data = image_data;
+        self.pos = 0;
+        self.done = false;
+
+        Ok(SeekFrom::Current(0))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if self.done {
+            return Ok((SeekFrom::Current(0), Event::Eof));
+        }
+        let (offset, length) = match self.extents.first() {
+            Some(&extent) => extent,
+            None => {
+                let mut pkt = Packet::with_capacity(self.data.len());
+                pkt.data.append(&mut self.data);
+                pkt.stream_index = 0;
+                pkt.is_key = true;
+                let ts = Timestamp::new(0, Rational64::new(1, 1));
+                pkt.t.set_pts(Some(ts))?;
+                pkt.t.set_dts(Some(ts))?;
+                self.done = true;
+                return Ok((SeekFrom::Current(0), Event::NewPacket(pkt)));
+            }
+        };
+
+        let data = buf.data();
+        if offset > self.pos {
+            need(data, 1)?;
+            let skip = (offset - self.pos).min(data.len() as u64);
+            self.pos += skip;
+            return Ok((SeekFrom::Current(skip as i64), Event::Continue));
+        }
+        need(data, length as usize)?;
+        self.data.extend_from_slice(&data[..length as usize]);
+        self.pos += length;
+        self.extents.remove(0);
+
+        Ok((SeekFrom::Current(length as i64), Event::Continue))
+    }
+}
+
+struct AvifDemuxerDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for AvifDemuxerDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(AvifDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if is_avif(data) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// AVIF demuxer descriptor.
+pub const AVIF_DEMUXER_DESCR: &dyn demuxer::Descriptor = &AvifDemuxerDescr {
+    d: demuxer::Descr {
+        name: "avif",
+        demuxer: "avif",
+        description: "AV1 Image File Format",
+        extensions: &["avif"],
+        mime: &["image/avif"],
+    },
+};
+
+/// AVIF muxer.
+///
+/// The first packet is written as the image, along with the metadata
+/// pointing at it.
+#[derive(Default)]
+pub struct AvifMuxer {
+    info: Option<GlobalInfo>,
+    written: bool,
+}
+
+impl AvifMuxer {
+    /// Creates a new muxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn meta(&self, offset: u32, length: u32) -> Result<Vec<u8>> {
+        let st = &self.info.as_ref().ok_or(Error::InvalidData)?.streams[0];
+        let (width, height) = match st.params.kind {
+            Some(MediaKind::Video(ref video)) => (video.width as u32, video.height as u32),
+            _ => return Err(Error::InvalidData),
+        };
+
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(b"pict");
+        hdlr.extend_from_slice(&[0; 13]);
+
+        let mut iloc = vec![0x44, 0x00, 0, 1];
+        iloc.extend_from_slice(&(ITEM_ID as u16).to_be_bytes());
+        iloc.extend_from_slice(&[0, 0, 0, 1]);
+        iloc.extend_from_slice(&offset.to_be_bytes());
+        iloc.extend_from_slice(&length.to_be_bytes());
+
+        let mut infe = Vec::new();
+        let mut entry = (ITEM_ID as u16).to_be_bytes().to_vec();
+        entry.extend_from_slice(&[0, 0]);
+        entry.extend_from_slice(b"av01");
+        entry.push(0);
+        put_full_box(&mut infe, b"infe", 2, 0, &entry);
+        let mut iinf = 1u16.to_be_bytes().to_vec();
+        iinf.extend_from_slice(&infe);
+
+        let mut ipco = Vec::new();
+        let mut ispe = width.to_be_bytes().to_vec();
+        ispe.extend_from_slice(&height.to_be_bytes());
+        put_full_box(&mut ipco, b"ispe", 0, 0, &ispe);
+        put_box(&mut ipco, b"av1C", st.get_extradata().unwrap_or(&[]));
+        let mut ipma = 1u32.to_be_bytes().to_vec();
+        ipma.extend_from_slice(&(ITEM_ID as u16).to_be_bytes());
+        // The spatial extents, then the essential codec configuration.
+        ipma.extend_from_slice(&[2, 0x01, 0x82]);
+        let mut iprp = Vec::new();
+        put_box(&mut iprp, b"ipco", &ipco);
+        put_full_box(&mut iprp, b"ipma", 0, 0, &ipma);
+
+        let mut meta = Vec::new();
+        put_full_box(&mut meta, b"hdlr", 0, 0, &hdlr);
+        put_full_box(&mut meta, b"pitm", 0, 0, &(ITEM_ID as u16).to_be_bytes());
+        put_full_box(&mut meta, b"iloc", 0, 0, &iloc);
+        put_full_box(&mut meta, b"iinf", 0, 0, &iinf);
+        put_box(&mut meta, b"iprp", &iprp);
+
+        let mut out = Vec::new();
+        put_full_box(&mut out, b"meta", 0, 0, &meta);
+        Ok(out)
+    }
+}
+
+impl Muxer for AvifMuxer {
+    fn configure(&mut self) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+        match info.streams.as_slice() {
+            [st] if st.params.codec_id.as_deref() == Some("av1") => {
+                if st.get_extradata().is_none_or(|config| config.is_empty()) {
+                    return Err(Error::Unsupported(
+                        "AV1 stream without av1C configuration".to_owned(),
+                    ));
+                }
+                Ok(())
+            }
+            [st] => Err(Error::Unsupported(format!(
+                "codec {:?} in AVIF",
+                st.params.codec_id
+            ))),
+            _ => Err(Error::Unsupported("AVIF with several streams".to_owned())),
+        }
+    }
+
+    fn write_header(&mut self, _out: &mut dyn Write) -> Result<()> {
+        self.written = false;
+        Ok(())
+    }
+
+    fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+        if self.written {
+            return Err(Error::Unsupported("more than one AVIF image".to_owned()));
+        }
+
+        let mut ftyp = BRANDS[0].to_vec();
+        ftyp.extend_from_slice(&[0; 4]);
+        for brand in &BRANDS {
+            ftyp.extend_from_slice(*brand);
+        }
+        let mut file = Vec::new();
+        put_box(&mut file, b"ftyp", &ftyp);
+
+        // The offsets are fixed size, the metadata size does not depend on
+        // them.
+        let length = pkt.data.len() as u32;
+        let offset = file.len() + self.meta(0, length)?.len() + BOX_HEADER_SIZE;
+        file.extend(self.meta(offset as u32, length)?);
+        put_box(&mut file, b"mdat", &pkt.data);
+
+        out.write_all(&file)?;
+        self.written = true;
+        Ok(())
+    }
+
+    fn write_trailer(&mut self, _out: &mut dyn Write) -> Result<()> {
+        if self.written {
+            Ok(())
+        } else {
+            Err(Error::InvalidData)
+        }
+    }
+
+    fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, _val: Value<'a>) -> Result<()> {
+        Err(Error::Unsupported(format!("{} key", key)))
+    }
+}
+
+struct AvifMuxerDescr {
+    d: muxer::Descr,
+    caps: muxer::Capabilities,
+}
+
+impl muxer::Descriptor for AvifMuxerDescr {
+    fn create(&self) -> Box<dyn Muxer> {
+        Box::new(AvifMuxer::new())
+    }
+    fn describe(&self) -> &muxer::Descr {
+        &self.d
+    }
+    fn capabilities(&self) -> &muxer::Capabilities {
+        &self.caps
+    }
+}
+
+/// AVIF muxer descriptor.
+pub const AVIF_MUXER_DESCR: &dyn muxer::Descriptor = &AvifMuxerDescr {
+    d: muxer::Descr {
+        name: "avif",
+        demuxer: "avif",
+        description: "AV1 Image File Format",
+        extensions: &["avif"],
+        mime: &["image/avif"],
+    },
+    caps: muxer::Capabilities {
+        codecs: Some(&["av1"]),
+        max_streams: Some(1),
+        parameter_sets: muxer::ParameterSets::Global,
+        variable_frame_rate: false,
+        seekable_output: false,
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::metadata::Metadata;
+    use crate::demuxer::demux_file;
+
+    /// av1C of a 8-bit 4:2:0 main profile stream.
+    const CONFIG: [u8; 4] = [0x81, 0x00, 0x0c, 0x00];
+
+    fn info(codec_id: &str) -> GlobalInfo {
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        let mut params = params(64, 32, CONFIG.to_vec());
+        params.codec_id = Some(codec_id.to_owned());
+        info.add_stream(Stream::from_params(&params, Rational64::new(1, 1)));
+        info
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut muxer = AvifMuxer::new();
+        muxer.set_global_info(info("av1")).unwrap();
+        muxer.configure().unwrap();
+
+        let mut pkt = Packet::with_capacity(300);
+        pkt.data.extend((0..300).map(|i| i as u8));
+        let pkt = Arc::new(pkt);
+        let mut file = Vec::new();
+        muxer.write_header(&mut file).unwrap();
+        muxer.write_packet(&mut file, pkt.clone()).unwrap();
+        assert!(muxer.write_packet(&mut file, pkt.clone()).is_err());
+        muxer.write_trailer(&mut file).unwrap();
+        assert_eq!(AVIF_DEMUXER_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(AVIF_DEMUXER_DESCR, file, 32);
+        assert_eq!(c.info.streams.len(), 1);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("av1"));
+        assert_eq!(st.get_extradata(), Some(&CONFIG[..]));
+        match st.params.kind {
+            Some(MediaKind::Video(ref video)) => assert_eq!((video.width, video.height), (64, 32)),
+            _ => panic!("Not a video stream"),
+        }
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, pkt.data);
+        assert!(packets[0].is_key);
+    }
+
+    #[test]
+    fn idat_extents() {
+        // Two extents in the idat box, with 8 byte offsets in a version 1
+        // iloc box.
+        let mut iloc = vec![0x88, 0x00, 0, 1, 0, 1, 0, 1, 0, 0, 0, 2];
+        for v in &[4u64, 2, 0, 3] {
+            iloc.extend_from_slice(&v.to_be_bytes());
+        }
+        let mut meta = Vec::new();
+        put_full_box(&mut meta, b"hdlr", 0, 0, b"\0\0\0\0pict\0");
+        put_full_box(&mut meta, b"pitm", 0, 0, &[0, 1]);
+        put_full_box(&mut meta, b"iloc", 1, 0, &iloc);
+        let mut infe = Vec::new();
+        put_full_box(&mut infe, b"infe", 2, 0, b"\0\x01\0\0av01\0");
+        put_full_box(&mut meta, b"iinf", 0, 0, &[&[0, 1][..], &infe].concat());
+        let mut ipco = Vec::new();
+        put_box(&mut ipco, b"av1C", &CONFIG);
+        let mut iprp = Vec::new();
+        put_box(&mut iprp, b"ipco", &ipco);
+        put_full_box(&mut iprp, b"ipma", 0, 0, &[0, 0, 0, 1, 0, 1, 1, 0x81]);
+        put_box(&mut meta, b"iprp", &iprp);
+        put_box(&mut meta, b"idat", &[10, 11, 12, 13, 14, 15]);
+
+        let mut file = Vec::new();
+        put_box(&mut file, b"ftyp", b"avif\0\0\0\0mif1avif");
+        put_full_box(&mut file, b"meta", 0, 0, &meta);
+
+        let (_, packets) = demux_file(AVIF_DEMUXER_DESCR, file, 32);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, [14, 15, 10, 11, 12]);
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut muxer = AvifMuxer::new();
+        muxer.set_global_info(info("vp9")).unwrap();
+        assert!(muxer.configure().is_err());
+
+        let mut file = Vec::new();
+        put_box(&mut file, b"ftyp", b"mif1\0\0\0\0mif1");
+        assert_eq!(AVIF_DEMUXER_DESCR.probe(&file), 0);
+    }
+}
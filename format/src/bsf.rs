@@ -0,0 +1,241 @@
+//!
+//! Bitstream filters moving the codec configuration between the extradata
+//! and the packets.
+//!
+//! Only the parameter sets of the H.264 and HEVC Annex B streams are
+//! handled, the packets of the other codecs are left untouched.
+//!
+
+use av_bitstream::byteread::get_u16b;
+
+use crate::data::packet::Packet;
+use crate::error::*;
+use crate::raw::annexb::nal_units;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Tells if a NAL unit is a parameter set.
+fn is_parameter_set(codec_id: &str, nal: &[u8]) -> bool {
+    match (codec_id, nal.first()) {
+        ("h264", Some(&b)) => matches!(b & 0x1f, 7 | 8 | 13),
+        ("hevc", Some(&b)) => matches!((b >> 1) & 0x3f, 32..=34),
+        _ => false,
+    }
+}
+
+/// Tells if the parameter sets of a codec can be moved by the filters.
+pub fn supports_codec(codec_id: &str) -> bool {
+    matches!(codec_id, "h264" | "hevc")
+}
+
+/// Returns the parameter sets of an Annex B packet, as Annex B extradata.
+pub fn extract_extradata(codec_id: &str, data: &[u8]) -> Option<Vec<u8>> {
+    let mut extradata = Vec::new();
+    for (_, nal) in nal_units(data).filter(|(_, nal)| is_parameter_set(codec_id, nal)) {
+        extradata.extend_from_slice(&START_CODE);
+        extradata.extend_from_slice(nal);
+    }
+    if extradata.is_empty() {
+        None
+    } else {
+        Some(extradata)
+    }
+}
+
+/// Removes the parameter sets of an Annex B packet.
+pub fn strip_parameter_sets(codec_id: &str, data: &[u8]) -> Vec<u8> {
+    if !nal_units(data).any(|(_, nal)| is_parameter_set(codec_id, nal)) {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    for (_, nal) in nal_units(data).filter(|(_, nal)| !is_parameter_set(codec_id, nal)) {
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+/// Reads NAL units prefixed by their 16 bit size.
+fn sized_units(data: &mut &[u8], count: usize, out: &mut Vec<u8>) -> Result<()> {
+    for _ in 0..count {
+        if data.len() < 2 {
+            return Err(Error::InvalidData);
+        }
+        let size = get_u16b(data) as usize;
+        let nal = data.get(2..2 + size).ok_or(Error::InvalidData)?;
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nal);
+        *data = &data[2 + size..];
+    }
+    Ok(())
+}
+
+/// Converts extradata to Annex B parameter sets, from an `avcC` or `hvcC`
+/// decoder configuration record if needed.
+pub fn annexb_extradata(codec_id: &str, extradata: &[u8]) -> Result<Vec<u8>> {
+    if extradata.starts_with(&[0, 0, 1]) || extradata.starts_with(&START_CODE) {
+        return Ok(extradata.to_vec());
+    }
+    if extradata.first() != Some(&1) {
+        return Err(Error::InvalidData);
+    }
+
+    let mut out = Vec::new();
+    match codec_id {
+        "h264" => {
+            let mut data = extradata.get(5..).ok_or(Error::InvalidData)?;
+            let count = (data[0] & 0x1f) as usize;
+            data = &data[1..];
+            sized_units(&mut data, count, &mut out)?;
+            let count = *data.first().ok_or(Error::InvalidData)? as usize;
+            data = &data[1..];
+            sized_units(&mut data, count, &mut out)?;
+        }
+        "hevc" => {
+            let mut data = extradata.get(22..).ok_or(Error::InvalidData)?;
+            let arrays = data[0];
+            data = &data[1..];
+            for _ in 0..arrays {
+                if data.len() < 3 {
+                    return Err(Error::InvalidData);
+                }
+                let count = get_u16b(&data[1..]) as usize;
+                data = &data[3..];
+                sized_units(&mut data, count, &mut out)?;
+            }
+        }
+        _ => return Err(Error::Unsupported(format!("{} extradata", codec_id))),
+    }
+    Ok(out)
+}
+
+/// Used to rewrite the packets of a stream.
+pub trait Filter: Send {
+    /// Filters a packet in place.
+    fn filter(&mut self, pkt: &mut Packet) -> Result<()>;
+}
+
+/// Moves the in-band parameter sets to the extradata.
+///
+/// The extradata is taken from the first packet carrying parameter sets,
+/// unless given upfront.
+pub struct ExtractExtradata {
+    codec_id: String,
+    extradata: Option<Vec<u8>>,
+}
+
+impl ExtractExtradata {
+    /// Creates a new filter, for a stream with or without extradata.
+    pub fn new(codec_id: &str, extradata: Option<Vec<u8>>) -> Self {
+        ExtractExtradata {
+            codec_id: codec_id.to_owned(),
+            extradata,
+        }
+    }
+
+    /// Returns the extradata, once known.
+    pub fn get_extradata(&self) -> Option<&[u8]> {
+        self.extradata.as_deref()
+    }
+}
+
+impl Filter for ExtractExtradata {
+    fn filter(&mut self, pkt: &mut Packet) -> Result<()> {
+        if self.extradata.is_none() {
+            self.extradata = extract_extradata(&self.codec_id, &pkt.data);
+        }
+        pkt.data = strip_parameter_sets(&self.codec_id, &pkt.data);
+        Ok(())
+    }
+}
+
+/// Repeats the parameter sets of the extradata in-band, before the key
+/// packets lacking them.
+pub struct DumpExtradata {
+    codec_id: String,
+    parameter_sets: Vec<u8>,
+}
+
+impl DumpExtradata {
+    /// Creates a new filter, from the extradata of the stream.
+    pub fn new(codec_id: &str, extradata: &[u8]) -> Result<Self> {
+        Ok(DumpExtradata {
+            codec_id: codec_id.to_owned(),
+            parameter_sets: annexb_extradata(codec_id, extradata)?,
+        })
+    }
+}
+
+impl Filter for DumpExtradata {
+    fn filter(&mut self, pkt: &mut Packet) -> Result<()> {
+        let codec_id = &self.codec_id;
+        if pkt.is_key && !nal_units(&pkt.data).any(|(_, nal)| is_parameter_set(codec_id, nal)) {
+            let mut data = self.parameter_sets.clone();
+            data.extend_from_slice(&pkt.data);
+            pkt.data = data;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPS: [u8; 4] = [0x67, 0x42, 0x00, 0x1e];
+    const PPS: [u8; 3] = [0x68, 0xce, 0x38];
+    const IDR: [u8; 3] = [0x65, 0x88, 0x84];
+
+    fn annexb(nals: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for nal in nals {
+            data.extend_from_slice(&START_CODE);
+            data.extend_from_slice(nal);
+        }
+        data
+    }
+
+    fn packet(data: Vec<u8>, is_key: bool) -> Packet {
+        let mut pkt = Packet::new();
+        pkt.data = data;
+        pkt.is_key = is_key;
+        pkt
+    }
+
+    #[test]
+    fn extract() {
+        let mut filter = ExtractExtradata::new("h264", None);
+        let mut pkt = packet(annexb(&[&SPS, &PPS, &IDR]), true);
+        filter.filter(&mut pkt).unwrap();
+        assert_eq!(pkt.data, annexb(&[&IDR]));
+        assert_eq!(filter.get_extradata(), Some(&annexb(&[&SPS, &PPS])[..]));
+
+        let mut pkt = packet(annexb(&[&IDR]), true);
+        filter.filter(&mut pkt).unwrap();
+        assert_eq!(pkt.data, annexb(&[&IDR]));
+    }
+
+    #[test]
+    fn dump() {
+        // avcC with one SPS and one PPS.
+        let mut avcc = vec![1, 0x42, 0, 0x1e, 0xff, 0xe1, 0, 4];
+        avcc.extend_from_slice(&SPS);
+        avcc.extend_from_slice(&[1, 0, 3]);
+        avcc.extend_from_slice(&PPS);
+
+        let mut filter = DumpExtradata::new("h264", &avcc).unwrap();
+        let mut pkt = packet(annexb(&[&IDR]), true);
+        filter.filter(&mut pkt).unwrap();
+        assert_eq!(pkt.data, annexb(&[&SPS, &PPS, &IDR]));
+
+        let mut pkt = packet(annexb(&[&SPS, &PPS, &IDR]), true);
+        filter.filter(&mut pkt).unwrap();
+        assert_eq!(pkt.data, annexb(&[&SPS, &PPS, &IDR]));
+
+        let mut pkt = packet(annexb(&[&[0x41, 0x9a]]), false);
+        filter.filter(&mut pkt).unwrap();
+        assert_eq!(pkt.data, annexb(&[&[0x41, 0x9a]]));
+
+        assert!(DumpExtradata::new("h264", &avcc[..10]).is_err());
+    }
+}
@@ -32,7 +32,7 @@ impl<R: Read + Seek> AccReader<R> {
     pub fn with_capacity(cap: usize, inner: R) -> AccReader<R> {
         AccReader {
             inner,
-            buf: iter::repeat(0).take(cap).collect::<Vec<_>>(),
+            buf: iter::repeat_n(0, cap).collect::<Vec<_>>(),
             pos: 0,
             end: 0,
             index: 0,
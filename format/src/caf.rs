@@ -0,0 +1,532 @@
+//!
+//! Core Audio Format demuxer.
+//!
+//! Variable bitrate streams need their packet table to precede the audio
+//! data, as the table is read along with the other headers.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::audiosample::{ChannelLayout, ChannelMap, Soniton, MASK_POSITIONS};
+use crate::data::packet::Packet;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::limits::Limits;
+use crate::pcm;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Linear PCM samples are floating point.
+const LPCM_FLOAT: u32 = 1;
+/// Linear PCM samples are little-endian.
+const LPCM_LITTLE_ENDIAN: u32 = 2;
+
+/// The channel layout is described by the channel bitmap.
+const LAYOUT_USE_BITMAP: u32 = 0x10000;
+
+/// Size of the audio data chunk when it extends to the end of the file.
+const SIZE_UNKNOWN: i64 = -1;
+
+/// Audio description chunk contents.
+#[derive(Debug)]
+struct Desc {
+    rate: f64,
+    format_id: [u8; 4],
+    flags: u32,
+    bytes_per_packet: u32,
+    frames_per_packet: u32,
+    channels: u32,
+    bits: u32,
+}
+
+impl Desc {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 32 {
+            return Err(Error::InvalidData);
+        }
+        let mut format_id = [0; 4];
+        format_id.copy_from_slice(&data[8..12]);
+        let desc = Desc {
+            rate: get_f64b(data),
+            format_id,
+            flags: get_u32b(&data[12..]),
+            bytes_per_packet: get_u32b(&data[16..]),
+            frames_per_packet: get_u32b(&data[20..]),
+            channels: get_u32b(&data[24..]),
+            bits: get_u32b(&data[28..]),
+        };
+        if desc.channels == 0 || !(1.0..=f64::from(u32::MAX)).contains(&desc.rate) {
+            return Err(Error::InvalidData);
+        }
+        Ok(desc)
+    }
+
+    fn is_lpcm(&self) -> bool {
+        &self.format_id == b"lpcm"
+    }
+
+    /// Returns the codec name and sample format of the audio data.
+    fn codec(&self) -> Result<(String, Option<Soniton>)> {
+        let name = match &self.format_id {
+            b"lpcm" => {
+                let float = self.flags & LPCM_FLOAT != 0;
+                let be = self.flags & LPCM_LITTLE_ENDIAN == 0;
+                let bits = self.bits.div_ceil(8) * 8;
+                match (bits, float) {
+                    (8, false)
+                    | (16, false)
+                    | (24, false)
+                    | (32, false)
+                    | (32, true)
+                    | (64, true) => {}
+                    _ => return Err(Error::Unsupported(format!("{}-bit LPCM", self.bits))),
+                }
+                let fmt = Soniton::new(bits as u8, be, false, false, float, true);
+                return Ok((pcm::codec_id(&fmt), Some(fmt)));
+            }
+            b"ulaw" => "pcm_mulaw",
+            b"alaw" => "pcm_alaw",
+            b"ima4" => "adpcm_ima_qt",
+            b"aac " => "aac",
+            b"alac" => "alac",
+            b"flac" => "flac",
+            b"opus" => "opus",
+            b".mp3" => "mp3",
+            b"samr" => "amr_nb",
+            id => {
+                return Err(Error::Unsupported(format!(
+                    "CAF format {}",
+                    String::from_utf8_lossy(id)
+                )))
+            }
+        };
+        Ok((name.to_owned(), None))
+    }
+}
+
+/// Reads a variable length integer of the packet table, returning it
+/// along with its size.
+fn get_varint(data: &[u8]) -> Result<(u64, usize)> {
+    let mut v = 0u64;
+    for (i, &b) in data.iter().enumerate().take(9) {
+        v = (v << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(Error::InvalidData)
+}
+
+/// Packet table chunk contents.
+#[derive(Debug, Default)]
+struct Pakt {
+    valid_frames: u64,
+    priming: u64,
+    packets: Vec<(usize, u64)>,
+}
+
+impl Pakt {
+    fn parse(data: &[u8], desc: &Desc, limits: &Limits) -> Result<Self> {
+        if data.len() < 24 {
+            return Err(Error::InvalidData);
+        }
+        let count = get_i64b(data);
+        let valid_frames = get_i64b(&data[8..]);
+        let priming = get_i32b(&data[16..]);
+        if count < 0 || valid_frames < 0 || priming < 0 {
+            return Err(Error::InvalidData);
+        }
+        limits.check_index_entries(count.min(usize::MAX as i64) as usize)?;
+
+        let mut table = &data[24..];
+        let mut packets = Vec::new();
+        for _ in 0..count {
+            let size = match desc.bytes_per_packet {
+                0 => {
+                    let (size, len) = get_varint(table)?;
+                    table = &table[len..];
+                    size as usize
+                }
+                size => size as usize,
+            };
+            let frames = match desc.frames_per_packet {
+                0 => {
+                    let (frames, len) = get_varint(table)?;
+                    table = &table[len..];
+                    frames
+                }
+                frames => u64::from(frames),
+            };
+            packets.push((size, frames));
+        }
+
+        Ok(Pakt {
+            valid_frames: valid_frames as u64,
+            priming: priming as u64,
+            packets,
+        })
+    }
+}
+
+/// Returns the channel map described by a channel layout chunk.
+///
+/// The bits of the channel bitmaps are the ones of the channel masks.
+fn channel_layout(data: &[u8], channels: usize) -> Option<ChannelMap> {
+    if data.len() < 8 || get_u32b(data) != LAYOUT_USE_BITMAP {
+        return None;
+    }
+    let layout = ChannelLayout::Mask(get_u32b(&data[4..]) & ((1 << MASK_POSITIONS.len()) - 1));
+
+    if layout.count() == channels {
+        layout.to_map()
+    } else {
+        None
+    }
+}
+
+/// How the audio data is split into packets.
+#[derive(Debug)]
+enum Packets {
+    /// Packets of constant size and duration.
+    Constant { size: usize, frames: u64 },
+    /// Packet sizes and durations listed by the packet table.
+    Table(Vec<(usize, u64)>),
+}
+
+/// CAF demuxer.
+pub struct CafDemuxer {
+    packets: Packets,
+    timebase: Rational64,
+    remaining: Option<u64>,
+    index: usize,
+    frames: u64,
+    limits: Limits,
+}
+
+impl Default for CafDemuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CafDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        CafDemuxer {
+            packets: Packets::Table(Vec::new()),
+            timebase: Rational64::from_integer(1),
+            remaining: None,
+            index: 0,
+            frames: 0,
+            limits: Limits::default(),
+        }
+    }
+
+    fn add_stream(
+        &mut self,
+        desc: &Desc,
+        pakt: Option<Pakt>,
+        map: Option<ChannelMap>,
+        extradata: Option<Vec<u8>>,
+        data_size: Option<u64>,
+        info: &mut GlobalInfo,
+    ) -> Result<()> {
+        let (codec_id, format) = desc.codec()?;
+        let rate = desc.rate.round() as usize;
+        let map = match map {
+            Some(map) => map,
+            None => pcm::channel_map(desc.channels as usize)?,
+        };
+        let mut params = pcm::audio_params(codec_id, rate, map, format);
+        params.extradata = extradata;
+
+        let timebase = Rational64::new(1, rate as i64);
+        let mut st = Stream::from_params(&params, timebase);
+
+        self.packets = match (desc.bytes_per_packet, desc.frames_per_packet, pakt) {
+            (_, _, Some(pakt)) if !desc.is_lpcm() => {
+                st.params.delay = pakt.priming as usize;
+                st.duration = Some(pakt.valid_frames);
+                Packets::Table(pakt.packets)
+            }
+            (0, _, _) | (_, 0, _) => {
+                return Err(Error::Unsupported(
+                    "packet table after the audio data".to_owned(),
+                ))
+            }
+            (size, frames, _) => {
+                let size = size as usize;
+                let frames = u64::from(frames);
+                st.duration = data_size.map(|data_size| data_size / size as u64 * frames);
+                if desc.is_lpcm() {
+                    let group = (pcm::FRAMES_PER_PACKET as u64 / frames).max(1);
+                    Packets::Constant {
+                        size: size * group as usize,
+                        frames: frames * group,
+                    }
+                } else {
+                    Packets::Constant { size, frames }
+                }
+            }
+        };
+        info.add_stream(st);
+
+        self.timebase = timebase;
+        self.remaining = data_size;
+        self.index = 0;
+        self.frames = 0;
+
+        Ok(())
+    }
+
+    /// Returns the size and duration of the next packet.
+    fn next_packet(&self) -> Option<(usize, u64)> {
+        let (size, frames) = match self.packets {
+            Packets::Constant { size, frames } => (size, frames),
+            Packets::Table(ref table) => *table.get(self.index)?,
+        };
+
+        match self.remaining {
+            Some(0) => None,
+            // A truncated constant size packet is kept, trimming its duration.
+            Some(remaining) if remaining < size as u64 => {
+                let frames = frames * remaining / size as u64;
+                Some((remaining as usize, frames))
+            }
+            _ => Some((size, frames)),
+        }
+    }
+}
+
+impl Demuxer for CafDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, 8)?;
+        if &data[..4] != b"caff" || get_u16b(&data[4..]) != 1 {
+            return Err(Error::InvalidData);
+        }
+
+        let mut desc = None;
+        let mut pakt = None;
+        let mut map = None;
+        let mut extradata = None;
+        let mut pos = 8;
+        loop {
+            need(data, pos + 12)?;
+            let id = &data[pos..pos + 4];
+            let size = get_i64b(&data[pos + 4..]);
+            let start = pos + 12;
+
+            if id == b"data" {
+                let desc = desc.as_ref().ok_or(Error::InvalidData)?;
+                // Skip the edit count.
+                need(data, start + 4)?;
+                let data_size = match size {
+                    SIZE_UNKNOWN => None,
+                    size if size >= 4 => Some(size as u64 - 4),
+                    _ => return Err(Error::InvalidData),
+                };
+                self.add_stream(desc, pakt, map, extradata, data_size, info)?;
+                return Ok(SeekFrom::Current(start as i64 + 4));
+            }
+
+            if size < 0 {
+                return Err(Error::InvalidData);
+            }
+            let end = start + size as usize;
+            need(data, end)?;
+            let chunk = &data[start..end];
+            match id {
+                b"desc" => desc = Some(Desc::parse(chunk)?),
+                b"pakt" => {
+                    let desc = desc.as_ref().ok_or(Error::InvalidData)?;
+                    pakt = Some(Pakt::parse(chunk, desc, &self.limits)?);
+                }
+                b"chan" => {
+                    let channels = desc.as_ref().map_or(0, |desc| desc.channels as usize);
+                    map = channel_layout(chunk, channels);
+                }
+                b"kuki" => extradata = Some(chunk.to_vec()),
+                _ => {}
+            }
+            pos = end;
+        }
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let (size, duration) = match self.next_packet() {
+            Some(next) => next,
+            None => return Ok((SeekFrom::Current(0), Event::Eof)),
+        };
+        let data = buf.data();
+        need(data, size)?;
+
+        let mut pkt = Packet::with_capacity(size);
+        pkt.data.extend_from_slice(&data[..size]);
+        pkt.stream_index = 0;
+        pkt.is_key = true;
+        let ts = Timestamp::new(self.frames as i64, self.timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t
+            .set_duration(Some(Duration::new(duration, self.timebase)))?;
+
+        self.index += 1;
+        self.frames += duration;
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining -= size as u64;
+        }
+
+        Ok((SeekFrom::Current(size as i64), Event::NewPacket(pkt)))
+    }
+
+    fn set_limits(&mut self, limits: &Limits) {
+        self.limits = *limits;
+    }
+}
+
+struct CafDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for CafDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(CafDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if data.len() >= 8 && &data[..4] == b"caff" && get_u16b(&data[4..]) == 1 {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// CAF demuxer descriptor.
+pub const CAF_DESCR: &dyn demuxer::Descriptor = &CafDescr {
+    d: demuxer::Descr {
+        name: "caf",
+        demuxer: "caf",
+        description: "Apple Core Audio Format",
+        extensions: &["caf"],
+        mime: &["audio/x-caf"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::audiosample::ChannelType;
+    use crate::data::params::MediaKind;
+    use crate::demuxer::{demux_file, Context};
+    use std::io::Cursor;
+
+    fn chunk(file: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+        file.extend_from_slice(id);
+        file.extend_from_slice(&(data.len() as i64).to_be_bytes());
+        file.extend_from_slice(data);
+    }
+
+    fn desc(format_id: &[u8], flags: u32, bpp: u32, fpp: u32, channels: u32, bits: u32) -> Vec<u8> {
+        let mut desc = 48000f64.to_be_bytes().to_vec();
+        desc.extend_from_slice(format_id);
+        for v in &[flags, bpp, fpp, channels, bits] {
+            desc.extend_from_slice(&v.to_be_bytes());
+        }
+        desc
+    }
+
+    #[test]
+    fn lpcm() {
+        let samples: Vec<u8> = (0..4 * 1500).map(|i| i as u8).collect();
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        chunk(&mut file, b"desc", &desc(b"lpcm", 2, 4, 1, 2, 16));
+        chunk(&mut file, b"chan", &[0, 1, 0, 0, 0, 0, 0, 0x30, 0, 0, 0, 0]);
+        chunk(&mut file, b"data", &[&[0; 4], &samples[..]].concat());
+        assert_eq!(CAF_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(CAF_DESCR, file, 64);
+        let info = c.info;
+        let st = &info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("pcm_s16le"));
+        assert_eq!(st.duration, Some(1500));
+        match st.params.kind {
+            Some(MediaKind::Audio(ref audio)) => {
+                let map = audio.map.as_ref().unwrap();
+                assert_eq!(audio.rate, 48000);
+                assert_eq!(map.get_channel(0), ChannelType::Ls);
+                assert_eq!(map.get_channel(1), ChannelType::Rs);
+            }
+            _ => panic!("not an audio stream"),
+        }
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[1].t.get_pts().map(Timestamp::get_value), Some(1024));
+        assert_eq!(
+            packets[1].t.get_duration().map(Duration::get_value),
+            Some(1500 - 1024)
+        );
+        let demuxed: Vec<u8> = packets.iter().flat_map(|p| p.data.clone()).collect();
+        assert_eq!(demuxed, samples);
+    }
+
+    #[test]
+    fn packet_table() {
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        chunk(&mut file, b"desc", &desc(b"aac ", 0, 0, 1024, 2, 0));
+        chunk(&mut file, b"kuki", &[0x12, 0x10]);
+        let mut pakt = Vec::new();
+        pakt.extend_from_slice(&3i64.to_be_bytes());
+        pakt.extend_from_slice(&2000i64.to_be_bytes());
+        pakt.extend_from_slice(&1024i32.to_be_bytes());
+        pakt.extend_from_slice(&1120i32.to_be_bytes());
+        pakt.extend_from_slice(&[10, 0x81, 0x00, 20]);
+        chunk(&mut file, b"pakt", &pakt);
+        let payload: Vec<u8> = (0..158).map(|i| i as u8).collect();
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&SIZE_UNKNOWN.to_be_bytes());
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&payload);
+
+        let (c, packets) = demux_file(CAF_DESCR, file.clone(), 64);
+        let info = c.info;
+        let st = &info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("aac"));
+        assert_eq!(st.params.extradata.as_deref(), Some(&[0x12, 0x10][..]));
+        assert_eq!(st.params.delay, 1024);
+        assert_eq!(st.duration, Some(2000));
+
+        let sizes: Vec<_> = packets.iter().map(|p| p.data.len()).collect();
+        assert_eq!(sizes, vec![10, 128, 20]);
+        assert_eq!(packets[2].t.get_pts().map(Timestamp::get_value), Some(2048));
+        assert_eq!(&packets[2].data[..], &payload[138..]);
+
+        let r = AccReader::with_capacity(file.len(), Cursor::new(file));
+        let mut c = Context::new(CAF_DESCR.create(), Box::new(r));
+        c.set_limits(Limits {
+            max_index_entries: 2,
+            ..Limits::default()
+        });
+        assert!(matches!(
+            c.read_headers(),
+            Err(Error::LimitExceeded("index entries"))
+        ));
+    }
+
+    #[test]
+    fn varint() {
+        assert_eq!(get_varint(&[0x05]).unwrap(), (5, 1));
+        assert_eq!(get_varint(&[0x81, 0x80, 0x00]).unwrap(), (1 << 14, 3));
+        assert!(get_varint(&[0x80]).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//!
+//! Cancellation of blocking operations.
+//!
+//! A `CancelToken` is shared between the thread running an operation and
+//! the ones willing to abort it. The demuxing `Context` checks it between
+//! reads and arms its deadline when an operation timeout is set.
+//!
+//! A read blocked in the source cannot be aborted from the outside, the
+//! sources that may stall, such as network ones, should be given a read
+//! timeout and wrapped in an `Interruptible` reader, which retries the
+//! timed out reads until the token is cancelled or its deadline is
+//! reached.
+//!
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::error::*;
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+}
+
+/// Shared flag aborting the operations checking it.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    /// Creates a new token, not cancelled and without deadline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the operations checking the token, now and later.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Tells if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns the deadline of the current operation.
+    pub fn get_deadline(&self) -> Option<Instant> {
+        *self.inner.deadline.lock().unwrap()
+    }
+
+    /// Sets the deadline of the current operation, `None` removing it.
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.inner.deadline.lock().unwrap() = deadline;
+    }
+
+    /// Returns `Error::Cancelled` if the token was cancelled and
+    /// `Error::TimedOut` if its deadline is reached.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        match self.get_deadline() {
+            Some(deadline) if Instant::now() >= deadline => Err(Error::TimedOut),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_io(&self) -> io::Result<()> {
+        self.check().map_err(|err| {
+            let kind = match err {
+                Error::TimedOut => io::ErrorKind::TimedOut,
+                _ => io::ErrorKind::Other,
+            };
+            io::Error::new(kind, err)
+        })
+    }
+}
+
+/// Reader checking a `CancelToken` before each read.
+///
+/// The reads failing with `WouldBlock`, `TimedOut` or `Interrupted` are
+/// retried as long as the token allows it. The errors raised by the token
+/// are turned back into `Error::Cancelled` and `Error::TimedOut` when
+/// converted to `Error`.
+pub struct Interruptible<R> {
+    inner: R,
+    token: CancelToken,
+}
+
+impl<R> Interruptible<R> {
+    /// Creates a new reader checking a token.
+    pub fn new(inner: R, token: CancelToken) -> Self {
+        Interruptible { inner, token }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwraps the `Interruptible`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.token.check_io()?;
+            match self.inner.read(buf) {
+                Err(ref err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<R: Seek> Seek for Interruptible<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.token.check_io()?;
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Source timing out `stalls` times before each read.
+    pub(crate) struct Stalling {
+        pub(crate) data: Cursor<Vec<u8>>,
+        pub(crate) stalls: usize,
+        pub(crate) left: usize,
+    }
+
+    impl Read for Stalling {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left > 0 {
+                self.left -= 1;
+                thread::sleep(Duration::from_millis(1));
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.left = self.stalls;
+            self.data.read(buf)
+        }
+    }
+
+    impl Seek for Stalling {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    fn stalling(stalls: usize) -> Stalling {
+        Stalling {
+            data: Cursor::new(vec![1, 2, 3]),
+            stalls,
+            left: stalls,
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let mut r = Interruptible::new(stalling(3), CancelToken::new());
+        let mut buf = [0; 2];
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(r.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 3);
+    }
+
+    #[test]
+    fn cancel() {
+        let token = CancelToken::new();
+        let mut r = Interruptible::new(stalling(usize::MAX), token.clone());
+        let reader = thread::spawn(move || r.read(&mut [0; 2]));
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+
+        let err: Error = reader.join().unwrap().unwrap_err().into();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(matches!(token.check(), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn deadline() {
+        let token = CancelToken::new();
+        token.set_deadline(Some(Instant::now() + Duration::from_millis(10)));
+        let mut r = Interruptible::new(stalling(usize::MAX), token.clone());
+        let err: Error = r.read(&mut [0; 2]).unwrap_err().into();
+        assert!(matches!(err, Error::TimedOut));
+
+        token.set_deadline(None);
+        token.check().unwrap();
+    }
+}
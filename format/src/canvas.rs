@@ -0,0 +1,218 @@
+//!
+//! Compositing of the frames of animated images.
+//!
+//! The frames of GIF and APNG files update a region of the picture shown
+//! so far, the canvas keeps that picture and exposes it as RGBA packets.
+//!
+
+use std::sync::Arc;
+
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::pixel::formats;
+
+/// Largest picture accepted, in pixels.
+pub(crate) const MAX_PIXELS: usize = 1 << 26;
+
+/// Region of the canvas updated by a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// What to do with the region of a frame once it is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Dispose {
+    /// Leave the region as it is.
+    None,
+    /// Clear the region to transparent black.
+    Background,
+    /// Restore the region to what it was before the frame.
+    Previous,
+}
+
+/// Picture built from the frames of an animation.
+pub(crate) struct Canvas {
+    width: usize,
+    height: usize,
+    /// Pixels, as red, green, blue and alpha bytes.
+    pixels: Vec<u8>,
+    /// Region and disposal of the last frame drawn.
+    pending: Option<(Rect, Dispose)>,
+    /// Region saved for `Dispose::Previous`.
+    saved: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+            pending: None,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Codec parameters of the packets produced.
+    pub fn params(width: usize, height: usize) -> CodecParams {
+        CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width,
+                height,
+                format: Some(Arc::new(*formats::RGBA)),
+            })),
+            codec_id: Some("rawvideo".to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        }
+    }
+
+    /// Restricts a region to the canvas.
+    pub fn clip(&self, rect: Rect) -> Rect {
+        let x = rect.x.min(self.width);
+        let y = rect.y.min(self.height);
+        Rect {
+            x,
+            y,
+            width: rect.width.min(self.width - x),
+            height: rect.height.min(self.height - y),
+        }
+    }
+
+    fn rows(&self, rect: Rect) -> impl Iterator<Item = std::ops::Range<usize>> {
+        let (width, rect) = (self.width, self.clip(rect));
+        (rect.y..rect.y + rect.height).map(move |y| {
+            let start = (y * width + rect.x) * 4;
+            start..start + rect.width * 4
+        })
+    }
+
+    /// Disposes of the previous frame and prepares drawing a new one over
+    /// `rect`, to be disposed of as `dispose` once shown.
+    pub fn start_frame(&mut self, rect: Rect, dispose: Dispose) {
+        match self.pending.take() {
+            Some((prev, Dispose::Background)) => {
+                for row in self.rows(prev).collect::<Vec<_>>() {
+                    self.pixels[row].iter_mut().for_each(|b| *b = 0);
+                }
+            }
+            Some((prev, Dispose::Previous)) => {
+                let mut saved = self.saved.chunks(prev.width * 4);
+                for row in self.rows(prev).collect::<Vec<_>>() {
+                    if let Some(src) = saved.next() {
+                        self.pixels[row].copy_from_slice(src);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let rect = self.clip(rect);
+        if dispose == Dispose::Previous {
+            let mut saved = Vec::with_capacity(rect.width * rect.height * 4);
+            for row in self.rows(rect) {
+                saved.extend_from_slice(&self.pixels[row]);
+            }
+            self.saved = saved;
+        }
+        self.pending = Some((rect, dispose));
+    }
+
+    /// Replaces a pixel, ignoring the ones out of the canvas.
+    pub fn set(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        if x < self.width && y < self.height {
+            let off = (y * self.width + x) * 4;
+            self.pixels[off..off + 4].copy_from_slice(&rgba);
+        }
+    }
+
+    /// Composites a pixel over the canvas, ignoring the ones out of it.
+    pub fn blend(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let off = (y * self.width + x) * 4;
+        let dst = &mut self.pixels[off..off + 4];
+        let src_a = u32::from(rgba[3]);
+        if src_a == 255 || dst[3] == 0 {
+            dst.copy_from_slice(&rgba);
+            return;
+        }
+        if src_a == 0 {
+            return;
+        }
+
+        // Alpha over, with non premultiplied components.
+        let dst_a = u32::from(dst[3]) * (255 - src_a) / 255;
+        let out_a = src_a + dst_a;
+        for c in 0..3 {
+            dst[c] = ((u32::from(rgba[c]) * src_a + u32::from(dst[c]) * dst_a) / out_a) as u8;
+        }
+        dst[3] = out_a as u8;
+    }
+
+    /// Produces a packet holding the current picture.
+    pub fn packet(&self) -> Packet {
+        let fmt = formats::RGBA;
+        let mut offsets = [0; 4];
+        for (c, off) in offsets.iter_mut().enumerate() {
+            *off = fmt
+                .get_chromaton(c)
+                .map_or(c, |chr| chr.get_offset() as usize);
+        }
+
+        let mut pkt = Packet::with_capacity(self.pixels.len());
+        pkt.data.resize(self.pixels.len(), 0);
+        for (dst, src) in pkt.data.chunks_mut(4).zip(self.pixels.chunks(4)) {
+            for c in 0..4 {
+                dst[offsets[c]] = src[c];
+            }
+        }
+        pkt.is_key = true;
+        pkt
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dispose() {
+        let mut canvas = Canvas::new(2, 2);
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        let corner = Rect {
+            width: 1,
+            height: 1,
+            ..full
+        };
+
+        canvas.start_frame(full, Dispose::None);
+        for i in 0..4 {
+            canvas.set(i % 2, i / 2, [10, 20, 30, 255]);
+        }
+        canvas.start_frame(corner, Dispose::Previous);
+        canvas.set(0, 0, [1, 2, 3, 255]);
+        assert_eq!(&canvas.pixels[..4], &[1, 2, 3, 255]);
+        canvas.start_frame(corner, Dispose::Background);
+        assert_eq!(&canvas.pixels[..4], &[10, 20, 30, 255]);
+        canvas.start_frame(full, Dispose::None);
+        assert_eq!(&canvas.pixels[..8], &[0, 0, 0, 0, 10, 20, 30, 255]);
+
+        canvas.blend(1, 0, [0, 0, 0, 0]);
+        assert_eq!(&canvas.pixels[4..8], &[10, 20, 30, 255]);
+        canvas.blend(1, 0, [255, 255, 255, 255]);
+        assert_eq!(&canvas.pixels[4..8], &[255, 255, 255, 255]);
+    }
+}
@@ -1,5 +1,6 @@
+use crate::data::metadata::Metadata;
 use crate::data::rational::Rational64;
-use crate::stream::Stream;
+use crate::stream::{Stream, StreamGroup};
 
 /// Global media file information.
 #[derive(Debug, Clone)]
@@ -14,6 +15,10 @@ pub struct GlobalInfo {
     pub timebase: Option<Rational64>,
     /// List of streams present in a media file.
     pub streams: Vec<Stream>,
+    /// Descriptive metadata of a media file (e.g. its title).
+    pub metadata: Metadata,
+    /// Relationships between the streams (e.g. stereoscopic pairs).
+    pub groups: Vec<StreamGroup>,
 }
 
 impl GlobalInfo {
@@ -30,4 +35,17 @@ impl GlobalInfo {
 
         idx
     }
+
+    /// Adds a group of streams, returning its position.
+    pub fn add_group(&mut self, group: StreamGroup) -> usize {
+        self.groups.push(group);
+        self.groups.len() - 1
+    }
+
+    /// Returns the groups including a stream.
+    pub fn groups_of(&self, index: usize) -> impl Iterator<Item = &StreamGroup> + '_ {
+        self.groups
+            .iter()
+            .filter(move |group| group.streams.contains(&index))
+    }
 }
@@ -3,13 +3,22 @@
 use crate::error::*;
 
 use crate::buffer::Buffered;
+use crate::cancel::CancelToken;
+use crate::limits::Limits;
 use std::any::Any;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::io::SeekFrom;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::common::*;
 
+use crate::data::budget::{Budget, Reservation};
+use crate::data::metadata::Metadata;
 use crate::data::packet::Packet;
+use crate::data::timeinfo::Timestamp;
+use crate::rational::Rational64;
 use crate::stream::Stream;
 
 /// Events processed by a demuxer analyzing a source.
@@ -41,6 +50,70 @@ pub trait Demuxer: Send {
     fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom>;
     /// Reads an event from a data structure implementing the `Buffered` trait.
     fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)>;
+    /// Reads an event once the data source is exhausted, the buffer holding
+    /// the data left.
+    ///
+    /// Used by the demuxers which only know the end of their last packet
+    /// when the data ends.
+    fn read_eof(&mut self, _buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        Ok((SeekFrom::Current(0), Event::Eof))
+    }
+    /// Applies the limits only the demuxer can enforce, such as the index
+    /// and nesting ones.
+    fn set_limits(&mut self, _limits: &Limits) {}
+    /// Applies the discard modes of the streams, by stream index, so the
+    /// demuxer may skip the packets dropped without parsing or copying
+    /// them.
+    ///
+    /// The context drops them anyway.
+    fn set_discard(&mut self, _discard: &[Discard]) {}
+    /// Returns the media duration read after the headers to find the
+    /// streams by default, for the formats announcing them late.
+    fn analyze_duration(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Packets of a stream dropped by the demuxing context.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Discard {
+    /// No packet is dropped.
+    #[default]
+    Default,
+    /// The packets which are not key ones are dropped.
+    NonKey,
+    /// Every packet is dropped.
+    All,
+}
+
+impl Discard {
+    /// Tells if a packet is dropped, given whether it is a key one.
+    pub fn drops(self, is_key: bool) -> bool {
+        match self {
+            Discard::Default => false,
+            Discard::NonKey => !is_key,
+            Discard::All => true,
+        }
+    }
+}
+
+/// Default maximum size of the packets read to find the streams.
+pub const ANALYZE_DATA: usize = 5 << 20;
+
+/// Limits of the data read to find the format and the streams of a
+/// source, trading startup delay for accuracy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProbeOptions {
+    /// Maximum size of the data probed to find the format, and of the
+    /// packets read to find the streams.
+    ///
+    /// `None` uses the sizes of the formats.
+    pub probe_size: Option<usize>,
+    /// Media duration read after the headers to find the streams.
+    ///
+    /// `None` uses the duration of the demuxer, `Duration::ZERO`
+    /// returning once the headers are read.
+    pub analyze_duration: Option<Duration>,
 }
 
 /// Auxiliary structure to encapsulate a demuxer object and
@@ -48,6 +121,16 @@ pub trait Demuxer: Send {
 pub struct Context {
     demuxer: Box<dyn Demuxer>,
     reader: Box<dyn Buffered>,
+    cancel: CancelToken,
+    timeout: Option<Duration>,
+    limits: Limits,
+    probe: ProbeOptions,
+    /// Discard modes, by stream index.
+    discard: Vec<Discard>,
+    /// Events read while analyzing the streams.
+    queued: VecDeque<Event>,
+    /// Bytes of the buffer growth.
+    buffer: Reservation,
     /// Global media file information.
     pub info: GlobalInfo,
     /// User private data.
@@ -62,15 +145,123 @@ impl Context {
         Context {
             demuxer,
             reader,
+            cancel: CancelToken::new(),
+            timeout: None,
+            limits: Limits::default(),
+            probe: ProbeOptions::default(),
+            discard: Vec::new(),
+            queued: VecDeque::new(),
+            buffer: Budget::unbounded().try_reserve(0).unwrap(),
             info: GlobalInfo {
                 duration: None,
                 timebase: None,
                 streams: Vec::with_capacity(2),
+                metadata: Metadata::new(),
+                groups: Vec::new(),
             },
             user_private: None,
         }
     }
 
+    /// Returns the token cancelling the operations of the context.
+    ///
+    /// The same token should be given to the `Interruptible` sources.
+    pub fn get_cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Sets the token cancelling the operations of the context.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
+
+    /// Returns the maximum duration of `read_headers` and `read_event`.
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets the maximum duration of `read_headers` and `read_event`,
+    /// `None` letting them wait forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the resource limits of the context.
+    pub fn get_limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Sets the resource limits of the context, to be called before
+    /// `read_headers`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.demuxer.set_limits(&limits);
+        self.limits = limits;
+    }
+
+    /// Returns the probing options of the context.
+    pub fn get_probe_options(&self) -> &ProbeOptions {
+        &self.probe
+    }
+
+    /// Sets the probing options of the context, to be called before
+    /// `read_headers`.
+    pub fn set_probe_options(&mut self, options: ProbeOptions) {
+        self.probe = options;
+    }
+
+    /// Returns the discard mode of a stream.
+    pub fn get_discard(&self, index: usize) -> Discard {
+        self.discard.get(index).copied().unwrap_or_default()
+    }
+
+    /// Sets the discard mode of a stream, dropping its packets from the
+    /// next events read.
+    ///
+    /// The events already read while analyzing the streams are dropped as
+    /// well.
+    pub fn set_discard(&mut self, index: usize, discard: Discard) {
+        if self.discard.len() <= index {
+            self.discard.resize(index + 1, Discard::Default);
+        }
+        self.discard[index] = discard;
+        self.demuxer.set_discard(&self.discard);
+
+        let discards = &self.discard;
+        self.queued.retain(|event| match event {
+            Event::NewPacket(pkt) => !dropped(discards, pkt),
+            _ => true,
+        });
+    }
+
+    /// Sets the memory budget of the buffer, to be called before
+    /// `read_headers`.
+    ///
+    /// Reading fails if the buffer would grow past the budget.
+    pub fn set_budget(&mut self, budget: &Budget) -> Result<()> {
+        self.buffer = budget.try_reserve(self.buffer.get_size())?;
+        Ok(())
+    }
+
+    /// Grows the buffer to hold `needed` bytes, within the limits and the
+    /// budget.
+    fn grow(&mut self, needed: usize) -> Result<()> {
+        self.limits.check_buffer_size(needed)?;
+        if needed > self.buffer.get_size() {
+            self.buffer.try_resize(needed)?;
+        }
+        self.reader.grow(needed);
+        Ok(())
+    }
+
+    /// Runs an operation, the deadline of the token armed after the timeout.
+    fn timed<T>(&mut self, op: fn(&mut Self) -> Result<T>) -> Result<T> {
+        self.cancel
+            .set_deadline(self.timeout.map(|timeout| Instant::now() + timeout));
+        let res = op(self);
+        self.cancel.set_deadline(None);
+        res
+    }
+
     fn read_headers_internal(&mut self) -> Result<()> {
         let demux = &mut self.demuxer;
 
@@ -87,49 +278,103 @@ impl Context {
     }
 
     /// Reads stream headers and global information from a data source.
+    ///
+    /// The events of the analyzed media duration are read as well, so the
+    /// streams they announce are known, and returned by `read_event`.
     pub fn read_headers(&mut self) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!("open").entered();
+
+        self.timed(Self::read_headers_loop)?;
+        self.timed(Self::analyze)
+    }
+
+    /// Reads events until the analyzed duration of a stream, the size of
+    /// their packets or the end of the data is reached.
+    fn analyze(&mut self) -> Result<()> {
+        let duration = self
+            .probe
+            .analyze_duration
+            .unwrap_or_else(|| self.demuxer.analyze_duration());
+        if duration == Duration::ZERO {
+            return Ok(());
+        }
+        let duration = duration.as_micros().min(i64::MAX as u128) as i64;
+        let max_size = self.probe.probe_size.unwrap_or(ANALYZE_DATA);
+        let micros = Rational64::new(1, 1_000_000);
+
+        let mut starts: Vec<(isize, i64)> = Vec::new();
+        let mut size = 0;
+        loop {
+            let event = self.read_event_loop()?;
+            let done = match event {
+                Event::NewPacket(ref pkt) => {
+                    size += pkt.data.len();
+                    let time = pkt
+                        .t
+                        .get_pts()
+                        .or_else(|| pkt.t.get_dts())
+                        .and_then(|ts| ts.rescale(micros).ok())
+                        .map(Timestamp::get_value);
+                    let elapsed = time.map_or(0, |time| {
+                        match starts.iter().find(|(index, _)| *index == pkt.stream_index) {
+                            Some(&(_, start)) => time.saturating_sub(start),
+                            None => {
+                                starts.push((pkt.stream_index, time));
+                                0
+                            }
+                        }
+                    });
+                    elapsed >= duration || size >= max_size
+                }
+                Event::Eof => true,
+                _ => false,
+            };
+            self.queued.push_back(event);
+            if done {
+                debug!("analyzed {} events", self.queued.len());
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_headers_loop(&mut self) -> Result<()> {
         loop {
+            self.cancel.check()?;
             // TODO: wrap fill_buf() with a check for Eof
             self.reader.fill_buf()?;
             match self.read_headers_internal() {
                 Err(e) => match e {
-                    Error::MoreDataNeeded(needed) => {
-                        self.reader.grow(needed);
-                    }
+                    Error::MoreDataNeeded(needed) => self.grow(needed)?,
                     _ => return Err(e),
                 },
-                Ok(_) => return Ok(()),
+                Ok(_) => {
+                    debug!("found {} streams", self.info.streams.len());
+                    self.limits.check_streams(self.info.streams.len())?;
+                    self.limits.check_metadata(&self.info.metadata)?;
+                    return Ok(());
+                }
             }
         }
     }
 
-    fn read_event_internal(&mut self) -> Result<Event> {
+    fn read_event_internal(&mut self, eof: bool) -> Result<Event> {
         let demux = &mut self.demuxer;
 
-        let res = demux.read_event(&self.reader);
+        let res = if eof {
+            demux.read_eof(&self.reader)
+        } else {
+            demux.read_event(&self.reader)
+        };
         match res {
             Err(e) => Err(e),
             Ok((seek, mut event)) => {
                 //TODO: handle seeking here
                 let _ = self.reader.seek(seek)?;
-                if let Event::NewStream(ref st) = event {
-                    self.info.streams.push(st.clone());
-                }
                 if let Event::MoreDataNeeded(size) = event {
                     return Err(Error::MoreDataNeeded(size));
                 }
-                if let Event::NewPacket(ref mut pkt) = event {
-                    if pkt.t.timebase.is_none() {
-                        if let Some(ref st) = self
-                            .info
-                            .streams
-                            .iter()
-                            .find(|s| s.index as isize == pkt.stream_index)
-                        {
-                            pkt.t.timebase = Some(st.timebase);
-                        }
-                    }
-                }
+                track_event(&mut self.info, &self.limits, &mut event)?;
                 Ok(event)
             }
         }
@@ -137,9 +382,40 @@ impl Context {
 
     /// Reads an event from a data source.
     pub fn read_event(&mut self) -> Result<Event> {
+        #[cfg(feature = "tracing")]
+        let span = debug_span!(
+            "read_packet",
+            stream = tracing::field::Empty,
+            pts = tracing::field::Empty,
+            dts = tracing::field::Empty,
+        )
+        .entered();
+
+        let res = match self.queued.pop_front() {
+            Some(event) => Ok(event),
+            None => self.timed(Self::read_event_loop),
+        };
+
+        #[cfg(feature = "tracing")]
+        match res {
+            Ok(Event::NewPacket(ref pkt)) => {
+                span.record("stream", pkt.stream_index as i64);
+                span.record("pts", pkt.t.get_pts().map(Timestamp::get_value));
+                span.record("dts", pkt.t.get_dts().map(Timestamp::get_value));
+                trace!(size = pkt.data.len(), is_key = pkt.is_key, "packet");
+            }
+            Ok(ref event) => trace!(?event, "event"),
+            Err(ref err) => debug!(%err, "read failed"),
+        }
+
+        res
+    }
+
+    fn read_event_loop(&mut self) -> Result<Event> {
         // TODO: guard against infiniloops and maybe factor the loop.
         loop {
-            match self.read_event_internal() {
+            self.cancel.check()?;
+            match self.read_event_internal(false) {
                 Err(e) => match e {
                     Error::MoreDataNeeded(needed) => {
                         let len = self.reader.data().len();
@@ -148,20 +424,67 @@ impl Context {
                         if len >= needed {
                             continue;
                         }
-                        self.reader.grow(needed);
+                        self.grow(needed)?;
                         self.reader.fill_buf()?;
                         if self.reader.data().len() <= len {
-                            return Ok(Event::Eof);
+                            match self.read_event_internal(true)? {
+                                Event::NewPacket(ref pkt) if dropped(&self.discard, pkt) => {}
+                                ev => return Ok(ev),
+                            }
                         }
                     }
                     _ => return Err(e),
                 },
+                Ok(Event::NewPacket(ref pkt)) if dropped(&self.discard, pkt) => {}
                 Ok(ev) => return Ok(ev),
             }
         }
     }
 }
 
+/// Tells if a packet is dropped by the discard mode of its stream.
+fn dropped(discard: &[Discard], pkt: &Packet) -> bool {
+    usize::try_from(pkt.stream_index)
+        .ok()
+        .and_then(|index| discard.get(index))
+        .is_some_and(|discard| discard.drops(pkt.is_key))
+}
+
+/// Records the streams announced by an event and completes its packet,
+/// checking both against the limits.
+pub(crate) fn track_event(info: &mut GlobalInfo, limits: &Limits, event: &mut Event) -> Result<()> {
+    match *event {
+        Event::NewStream(ref st) => {
+            limits.check_streams(info.streams.len() + 1)?;
+            info.streams.push(st.clone());
+        }
+        Event::NewPacket(ref mut pkt) => {
+            limits.check_packet_size(pkt.data.len())?;
+            if pkt.t.timebase.is_none() {
+                if let Some(st) = info
+                    .streams
+                    .iter()
+                    .find(|s| s.index as isize == pkt.stream_index)
+                {
+                    pkt.t.timebase = Some(st.timebase);
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns an error asking for `size` buffered bytes if fewer are
+/// available.
+pub(crate) fn need(data: &[u8], size: usize) -> Result<()> {
+    if data.len() < size {
+        Err(Error::MoreDataNeeded(size))
+    } else {
+        Ok(())
+    }
+}
+
 /// Format descriptor.
 ///
 /// Contains information on a format and its own demuxer.
@@ -189,11 +512,18 @@ pub trait Descriptor {
     /// Returns a score which represents how much the input data are associated
     /// to a format.
     fn probe(&self, data: &[u8]) -> u8;
+    /// Returns the data size needed to probe the format by default.
+    fn probe_size(&self) -> usize {
+        PROBE_DATA
+    }
 }
 
 /// Maximum data size to probe a format.
 pub const PROBE_DATA: usize = 4 * 1024;
 
+/// Probe score of the data surely associated to a format.
+pub const PROBE_SCORE_MAX: u8 = 100;
+
 /// Data whose probe score is equal or greater than the value of this constant
 /// surely is associated to the format currently being analyzed.
 pub const PROBE_SCORE_EXTENSION: u8 = 50;
@@ -204,27 +534,102 @@ pub trait Probe {
     fn probe(&self, data: &[u8]) -> Option<&'static dyn Descriptor>;
 }
 
+/// Returns the format of the highest probe score, along with the score.
+fn best(descrs: &[&'static dyn Descriptor], data: &[u8]) -> (Option<&'static dyn Descriptor>, u8) {
+    #[cfg(feature = "tracing")]
+    let _span = debug_span!("probe", size = data.len()).entered();
+
+    let mut max = u8::MIN;
+    let mut candidate: Option<&'static dyn Descriptor> = None;
+    for desc in descrs {
+        let score = desc.probe(data);
+        trace!("{} probe score: {}", desc.describe().name, score);
+
+        if score > max {
+            max = score;
+            candidate = Some(*desc);
+        }
+    }
+
+    (candidate, max)
+}
+
 impl<'a> Probe for [&'static dyn Descriptor] {
     fn probe(&self, data: &[u8]) -> Option<&'static dyn Descriptor> {
-        let mut max = u8::min_value();
-        let mut candidate: Option<&'static dyn Descriptor> = None;
-        for desc in self {
-            let score = desc.probe(data);
-
-            if score > max {
-                max = score;
-                candidate = Some(*desc);
-            }
+        match best(self, data) {
+            (candidate, score) if score > PROBE_SCORE_EXTENSION => candidate,
+            _ => None,
         }
+    }
+}
 
-        if max > PROBE_SCORE_EXTENSION {
-            candidate
-        } else {
-            None
+/// Probes the format of a source, reading up to the probe size of the
+/// options, or the largest one of the formats.
+///
+/// The probing stops early once a format is sure. The data read stays
+/// buffered for the demuxer.
+pub fn probe_source(
+    descrs: &[&'static dyn Descriptor],
+    reader: &mut dyn Buffered,
+    options: &ProbeOptions,
+) -> Result<Option<&'static dyn Descriptor>> {
+    let probe_size = options.probe_size.unwrap_or_else(|| {
+        descrs
+            .iter()
+            .map(|desc| desc.probe_size())
+            .max()
+            .unwrap_or(PROBE_DATA)
+    });
+
+    let mut grown = false;
+    loop {
+        let len = reader.data().len();
+        reader.fill_buf()?;
+        let data = reader.data();
+        let size = data.len().min(probe_size);
+        let (candidate, score) = best(descrs, &data[..size]);
+        let eof = data.len() == len && grown;
+
+        if score >= PROBE_SCORE_MAX || size >= probe_size || eof {
+            return Ok(candidate.filter(|_| score > PROBE_SCORE_EXTENSION));
+        }
+        grown = data.len() == len;
+        if grown {
+            reader.grow(probe_size - size);
         }
     }
 }
 
+/// Reads the events of a context until the end of the file, returning
+/// the packets.
+#[cfg(test)]
+pub(crate) fn read_packets(c: &mut Context) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    loop {
+        match c.read_event().unwrap() {
+            Event::NewPacket(pkt) => packets.push(pkt),
+            Event::Continue => {}
+            Event::Eof => break,
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+    packets
+}
+
+/// Demuxes a whole file, read through a buffer of `capacity` bytes.
+#[cfg(test)]
+pub(crate) fn demux_file(
+    descr: &dyn Descriptor,
+    file: Vec<u8>,
+    capacity: usize,
+) -> (Context, Vec<Packet>) {
+    let r = crate::buffer::AccReader::with_capacity(capacity, std::io::Cursor::new(file));
+    let mut c = Context::new(descr.create(), Box::new(r));
+    c.read_headers().unwrap();
+    let packets = read_packets(&mut c);
+    (c, packets)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -302,6 +707,25 @@ mod test {
     use crate::buffer::*;
     use std::io::Cursor;
 
+    #[test]
+    fn probe_sizes() {
+        let demuxers: &[&'static dyn Descriptor] = &[DUMMY_DES];
+        let probe = |data: &'static [u8], probe_size| {
+            let mut r = AccReader::with_capacity(2, Cursor::new(data));
+            let options = ProbeOptions {
+                probe_size,
+                ..ProbeOptions::default()
+            };
+            let found = probe_source(demuxers, &mut r, &options).unwrap();
+            (found.map(|desc| desc.describe().name), r.data().len())
+        };
+
+        assert_eq!(probe(b"dummy", None), (Some("dummy"), 5));
+        assert_eq!(probe(b"dummy", Some(3)), (None, 3));
+        // The whole source is read without a sure format.
+        assert_eq!(probe(b"dummy header", None), (None, 12));
+    }
+
     #[test]
     fn read_headers() {
         let buf = b"dummy header";
@@ -310,6 +734,29 @@ mod test {
         let mut c = Context::new(d, Box::new(r));
 
         c.read_headers().unwrap();
+
+        let r = AccReader::with_capacity(4, Cursor::new(buf));
+        let mut c = Context::new(DUMMY_DES.create(), Box::new(r));
+        c.set_limits(Limits {
+            max_packet_size: 4,
+            max_metadata_size: 4,
+            ..Limits::default()
+        });
+        assert!(matches!(
+            c.read_headers(),
+            Err(Error::LimitExceeded("buffer size"))
+        ));
+
+        let budget = Budget::new(4);
+        let r = AccReader::with_capacity(4, Cursor::new(buf));
+        let mut c = Context::new(DUMMY_DES.create(), Box::new(r));
+        c.set_budget(&budget).unwrap();
+        assert!(matches!(
+            c.read_headers(),
+            Err(Error::LimitExceeded("memory budget"))
+        ));
+        drop(c);
+        assert_eq!(budget.get_used(), 0);
     }
 
     #[test]
@@ -327,4 +774,31 @@ mod test {
         println!("{:?}", c.read_event());
         println!("{:?}", c.read_event());
     }
+
+    #[test]
+    fn cancel() {
+        use crate::cancel::test::Stalling;
+        use crate::cancel::Interruptible;
+        use std::time::Duration;
+
+        let source = |token| {
+            let stalling = Stalling {
+                data: Cursor::new(b"dummy header p1 p1 ".to_vec()),
+                stalls: usize::MAX,
+                left: 0,
+            };
+            AccReader::with_capacity(4, Interruptible::new(stalling, token))
+        };
+
+        let token = CancelToken::new();
+        let mut c = Context::new(DUMMY_DES.create(), Box::new(source(token.clone())));
+        c.set_cancel_token(token.clone());
+        c.set_timeout(Some(Duration::from_millis(10)));
+        assert!(matches!(c.read_headers(), Err(Error::TimedOut)));
+        assert_eq!(token.get_deadline(), None);
+
+        let mut c = Context::new(DUMMY_DES.create(), Box::new(source(c.get_cancel_token())));
+        c.get_cancel_token().cancel();
+        assert!(matches!(c.read_headers(), Err(Error::Cancelled)));
+    }
 }
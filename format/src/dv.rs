@@ -0,0 +1,359 @@
+//!
+//! DV demuxer (IEC 61834, SMPTE 314M), for the 25 Mbit/s systems.
+//!
+//! Each frame is exposed as a video packet, the 16 bit stereo audio
+//! interleaved in its DIF sequences is extracted as little-endian PCM.
+//! Frames without a usable audio source pack have no audio packet.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::pcm;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Size of a DIF block.
+const BLOCK_SIZE: usize = 80;
+/// Header, subcode and VAUX blocks starting each DIF sequence.
+const SEQUENCE_HEADER: usize = 6 * BLOCK_SIZE;
+/// Audio and video blocks following each audio block.
+const AUDIO_SPACING: usize = 16 * BLOCK_SIZE;
+/// Audio blocks of a DIF sequence.
+const AUDIO_BLOCKS: usize = 9;
+/// Offset of the AAUX source pack in a frame.
+const AAUX_SOURCE: usize = SEQUENCE_HEADER + 3 * AUDIO_SPACING + 3;
+const AAUX_SOURCE_ID: u8 = 0x50;
+
+/// Audio sample rates, by frequency code.
+const RATES: [usize; 3] = [48000, 44100, 32000];
+
+/// Position of the samples of the first channel, by DIF sequence and
+/// audio block.
+const SHUFFLE_525: [[u8; 9]; 5] = [
+    [0, 30, 60, 20, 50, 80, 10, 40, 70],
+    [6, 36, 66, 26, 56, 86, 16, 46, 76],
+    [12, 42, 72, 2, 32, 62, 22, 52, 82],
+    [18, 48, 78, 8, 38, 68, 28, 58, 88],
+    [24, 54, 84, 14, 44, 74, 4, 34, 64],
+];
+const SHUFFLE_625: [[u8; 9]; 6] = [
+    [0, 36, 72, 26, 62, 98, 16, 52, 88],
+    [6, 42, 78, 32, 68, 104, 22, 58, 94],
+    [12, 48, 84, 2, 38, 74, 28, 64, 100],
+    [18, 54, 90, 8, 44, 80, 34, 70, 106],
+    [24, 60, 96, 14, 50, 86, 4, 40, 76],
+    [30, 66, 102, 20, 56, 92, 10, 46, 82],
+];
+
+/// Parameters of a DV system.
+struct System {
+    sequences: usize,
+    height: usize,
+    /// Frame duration.
+    timebase: (i64, i64),
+    /// Distance between the samples of an audio block.
+    stride: usize,
+    shuffle: &'static [[u8; 9]],
+    /// Minimum number of samples per frame, by frequency code.
+    min_samples: [usize; 3],
+}
+
+static SYSTEM_525: System = System {
+    sequences: 10,
+    height: 480,
+    timebase: (1001, 30000),
+    stride: 90,
+    shuffle: &SHUFFLE_525,
+    min_samples: [1580, 1452, 1053],
+};
+static SYSTEM_625: System = System {
+    sequences: 12,
+    height: 576,
+    timebase: (1, 25),
+    stride: 108,
+    shuffle: &SHUFFLE_625,
+    min_samples: [1896, 1742, 1264],
+};
+
+impl System {
+    /// Returns the system of the frame starting the data.
+    fn detect(data: &[u8]) -> Result<&'static System> {
+        need(data, 4)?;
+        let header = get_u32b(data);
+        if header & 0xffff_ff7f != 0x1f07_003f {
+            return Err(Error::InvalidData);
+        }
+        Ok(if header & 0x80 != 0 {
+            &SYSTEM_625
+        } else {
+            &SYSTEM_525
+        })
+    }
+
+    fn frame_size(&self) -> usize {
+        self.sequences * (SEQUENCE_HEADER + AUDIO_BLOCKS * AUDIO_SPACING)
+    }
+
+    /// Reads the AAUX source pack, returning the sample rate and the
+    /// number of samples of the frame.
+    fn audio_source(&self, frame: &[u8]) -> Option<(usize, usize)> {
+        let pack = &frame[AAUX_SOURCE..AAUX_SOURCE + 5];
+        let freq = ((pack[4] >> 3) & 7) as usize;
+        let stereo = pack[3] & 0x1f == 0;
+        let quant = pack[4] & 7;
+        if pack[0] != AAUX_SOURCE_ID || !stereo || quant != 0 || freq >= RATES.len() {
+            return None;
+        }
+        Some((
+            RATES[freq],
+            self.min_samples[freq] + (pack[1] & 0x3f) as usize,
+        ))
+    }
+
+    /// Gathers the audio samples of a frame.
+    fn extract_audio(&self, frame: &[u8], samples: usize) -> Vec<u8> {
+        let mut pcm = vec![0; samples * 4];
+        let half = self.sequences / 2;
+        let mut pos = 0;
+        for i in 0..self.sequences {
+            pos += SEQUENCE_HEADER;
+            let (row, channel) = if i < half { (i, 0) } else { (i - half, 1) };
+            for j in 0..AUDIO_BLOCKS {
+                let block = &frame[pos..pos + BLOCK_SIZE];
+                let first = (self.shuffle[row][j] + channel) as usize;
+                for d in (8..BLOCK_SIZE).step_by(2) {
+                    let of = first + (d - 8) / 2 * self.stride;
+                    if of * 2 >= pcm.len() {
+                        continue;
+                    }
+                    // 0x8000 marks an erroneous sample.
+                    if block[d..d + 2] != [0x80, 0] {
+                        pcm[of * 2] = block[d + 1];
+                        pcm[of * 2 + 1] = block[d];
+                    }
+                }
+                pos += AUDIO_SPACING;
+            }
+        }
+        pcm
+    }
+}
+
+/// DV demuxer.
+#[derive(Default)]
+pub struct DvDemuxer {
+    system: Option<&'static System>,
+    /// Index and sample rate of the audio stream, if any.
+    audio: Option<(usize, usize)>,
+    frames: i64,
+    samples: i64,
+    /// Audio packet of the last video frame.
+    pending: Option<Packet>,
+}
+
+impl DvDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Demuxer for DvDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        let system = System::detect(data)?;
+        need(data, system.frame_size())?;
+
+        let params = CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width: 720,
+                height: system.height,
+                format: None,
+            })),
+            codec_id: Some("dvvideo".to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+        let (num, den) = system.timebase;
+        info.add_stream(Stream::from_params(&params, Rational64::new(num, den)));
+
+        self.audio = None;
+        if let Some((rate, _)) = system.audio_source(data) {
+            let codec_id = "pcm_s16le";
+            let format = pcm::sample_format(codec_id);
+            let params = pcm::audio_params(codec_id.to_owned(), rate, pcm::channel_map(2)?, format);
+            let index = info.add_stream(Stream::from_params(
+                &params,
+                Rational64::new(1, rate as i64),
+            ));
+            self.audio = Some((index, rate));
+        }
+        self.system = Some(system);
+        self.frames = 0;
+        self.samples = 0;
+        self.pending = None;
+
+        Ok(SeekFrom::Current(0))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if let Some(pkt) = self.pending.take() {
+            return Ok((SeekFrom::Current(0), Event::NewPacket(pkt)));
+        }
+
+        let system = self.system.ok_or(Error::InvalidData)?;
+        let size = system.frame_size();
+        let data = buf.data();
+        need(data, size)?;
+        if !std::ptr::eq(System::detect(data)?, system) {
+            return Err(Error::InvalidData);
+        }
+        let frame = &data[..size];
+
+        if let Some((index, rate)) = self.audio {
+            // Frames changing the sample rate are left without audio.
+            if let Some((_, samples)) = system.audio_source(frame).filter(|s| s.0 == rate) {
+                let mut pkt = Packet::new();
+                pkt.data = system.extract_audio(frame, samples);
+                pkt.stream_index = index as isize;
+                let timebase = Rational64::new(1, rate as i64);
+                let ts = Timestamp::new(self.samples, timebase);
+                pkt.t.set_pts(Some(ts))?;
+                pkt.t.set_dts(Some(ts))?;
+                pkt.t
+                    .set_duration(Some(Duration::new(samples as u64, timebase)))?;
+                pkt.is_key = true;
+                self.samples += samples as i64;
+                self.pending = Some(pkt);
+            }
+        }
+
+        let mut pkt = Packet::with_capacity(size);
+        pkt.data.extend_from_slice(frame);
+        pkt.stream_index = 0;
+        let (num, den) = system.timebase;
+        let timebase = Rational64::new(num, den);
+        let ts = Timestamp::new(self.frames, timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.t.set_duration(Some(Duration::new(1, timebase)))?;
+        pkt.is_key = true;
+        self.frames += 1;
+
+        Ok((SeekFrom::Current(size as i64), Event::NewPacket(pkt)))
+    }
+}
+
+struct DvDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for DvDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(DvDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if System::detect(data).is_ok() {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// DV demuxer descriptor.
+pub const DV_DESCR: &dyn demuxer::Descriptor = &DvDescr {
+    d: demuxer::Descr {
+        name: "dv",
+        demuxer: "dv",
+        description: "DV (Digital Video)",
+        extensions: &["dv", "dif"],
+        mime: &["video/dv"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::demuxer::{read_packets, Context};
+    use std::io::Cursor;
+
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![0; SYSTEM_525.frame_size()];
+        frame[..4].copy_from_slice(&[0x1f, 0x07, 0x00, 0x3f]);
+        // 48 kHz, 1600 samples.
+        frame[AAUX_SOURCE..AAUX_SOURCE + 5].copy_from_slice(&[AAUX_SOURCE_ID, 20, 0, 0, 0]);
+        // First sample of the left channel.
+        frame[SEQUENCE_HEADER + 8..SEQUENCE_HEADER + 10].copy_from_slice(&[0x12, 0x34]);
+        // Second sample of the second block of the right channel, erroneous.
+        let pos = 5 * 12000 + SEQUENCE_HEADER + 10;
+        frame[pos..pos + 2].copy_from_slice(&[0x80, 0]);
+        // Second sample of the first block of the right channel.
+        let pos = 5 * 12000 + SEQUENCE_HEADER + AUDIO_SPACING + 10;
+        frame[pos..pos + 2].copy_from_slice(&[0x56, 0x78]);
+        frame
+    }
+
+    #[test]
+    fn demux() {
+        let mut file = frame();
+        file.extend_from_slice(&frame());
+        assert_eq!(DV_DESCR.probe(&file), 100);
+
+        let r = AccReader::with_capacity(4096, Cursor::new(file));
+        let mut c = Context::new(DV_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+        assert_eq!(c.info.streams.len(), 2);
+        assert_eq!(c.info.streams[1].timebase, Rational64::new(1, 48000));
+
+        let packets = read_packets(&mut c);
+
+        let packets: Vec<_> = packets
+            .iter()
+            .map(|pkt| {
+                (
+                    pkt.stream_index,
+                    pkt.t.get_pts().map(Timestamp::get_value),
+                    pkt.data.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            packets,
+            [
+                (0, Some(0), 120000),
+                (1, Some(0), 6400),
+                (0, Some(1), 120000),
+                (1, Some(1600), 6400),
+            ]
+        );
+    }
+
+    #[test]
+    fn audio() {
+        let frame = frame();
+        assert_eq!(SYSTEM_525.audio_source(&frame), Some((48000, 1600)));
+        let pcm = SYSTEM_525.extract_audio(&frame, 1600);
+        assert_eq!(pcm[..2], [0x34, 0x12]);
+        // Sample 91 (right channel, stride 90) from the block at 30.
+        assert_eq!(pcm[31 * 2 + 90 * 2..31 * 2 + 90 * 2 + 2], [0x78, 0x56]);
+        assert_eq!(pcm.iter().filter(|&&b| b != 0).count(), 4);
+    }
+}
@@ -0,0 +1,291 @@
+//!
+//! DASH event messages (ISO/IEC 23009-1 `emsg` boxes).
+//!
+//! Event messages precede the media of the fragmented MP4 segments and
+//! signal timed events such as SCTE-35 splices or ID3 tags. They travel as
+//! packets of `emsg` data streams, each packet holding a whole box timed
+//! by its event: fragmented MP4 demuxers build them with
+//! `EventMessage::to_packet` and muxers parse them back, applications
+//! inserting events by sending such packets.
+//!
+
+use av_bitstream::byteread::*;
+
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, DataInfo, MediaKind};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::error::*;
+use crate::id3;
+use crate::isobmff::{full_box, put_full_box, read_box};
+use crate::rational::Rational64;
+
+/// Codec of the event message streams.
+pub const CODEC_ID: &str = "emsg";
+
+/// Scheme of the SCTE-35 splice information sections.
+pub const SCTE35_SCHEME: &str = "urn:scte:scte35:2013:bin";
+/// Scheme of the ID3 tags.
+pub const ID3_SCHEME: &str = "https://aomedia.org/emsg/ID3";
+
+/// Returns the codec parameters of an event message stream.
+pub fn params() -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Data(DataInfo::default())),
+        codec_id: Some(CODEC_ID.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// Presentation time of an event, in the timescale of the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventTime {
+    /// Delay from the earliest presentation time of the segment, as
+    /// stored by version 0 boxes.
+    Delta(u32),
+    /// Presentation time of the media, as stored by version 1 boxes.
+    Absolute(u64),
+}
+
+/// Event message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventMessage {
+    /// Scheme of the message (e.g. `SCTE35_SCHEME`).
+    pub scheme_id_uri: String,
+    /// Value within the scheme.
+    pub value: String,
+    /// Timescale of the times, in ticks per second.
+    pub timescale: u32,
+    /// Presentation time of the event.
+    pub time: EventTime,
+    /// Duration of the event, `0xffffffff` if unknown.
+    pub duration: u32,
+    /// Identifier of the event, unique within the scheme and value.
+    pub id: u32,
+    /// Message data, as defined by the scheme.
+    pub data: Vec<u8>,
+}
+
+/// Reads a null-terminated string, advancing `data`.
+fn get_cstr(data: &mut &[u8]) -> Result<String> {
+    let len = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(Error::InvalidData)?;
+    let s = String::from_utf8_lossy(&data[..len]).into_owned();
+    *data = &data[len + 1..];
+    Ok(s)
+}
+
+fn get_u32(data: &mut &[u8]) -> Result<u32> {
+    if data.len() < 4 {
+        return Err(Error::InvalidData);
+    }
+    let v = get_u32b(data);
+    *data = &data[4..];
+    Ok(v)
+}
+
+impl EventMessage {
+    /// Parses an `emsg` box, returning the message along with the size of
+    /// the box.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let (kind, payload, end) = read_box(data, 0)?;
+        if &kind != b"emsg" {
+            return Err(Error::InvalidData);
+        }
+        let (version, _, mut data) = full_box(payload)?;
+
+        // The versions store the same fields in different orders.
+        let (scheme_id_uri, value, timescale, time, duration, id) = match version {
+            0 => (
+                get_cstr(&mut data)?,
+                get_cstr(&mut data)?,
+                get_u32(&mut data)?,
+                EventTime::Delta(get_u32(&mut data)?),
+                get_u32(&mut data)?,
+                get_u32(&mut data)?,
+            ),
+            1 => {
+                let timescale = get_u32(&mut data)?;
+                let high = get_u32(&mut data)?;
+                let low = get_u32(&mut data)?;
+                let duration = get_u32(&mut data)?;
+                let id = get_u32(&mut data)?;
+                (
+                    get_cstr(&mut data)?,
+                    get_cstr(&mut data)?,
+                    timescale,
+                    EventTime::Absolute(u64::from(high) << 32 | u64::from(low)),
+                    duration,
+                    id,
+                )
+            }
+            _ => return Err(Error::Unsupported(format!("emsg version {}", version))),
+        };
+        if timescale == 0 {
+            return Err(Error::InvalidData);
+        }
+
+        let msg = EventMessage {
+            scheme_id_uri,
+            value,
+            timescale,
+            time,
+            duration,
+            id,
+            data: data.to_vec(),
+        };
+        Ok((msg, end))
+    }
+
+    /// Returns the message as an `emsg` box, version 0 for the delta times
+    /// and version 1 for the absolute ones.
+    pub fn to_box(&self) -> Vec<u8> {
+        let strings = || {
+            let mut data = self.scheme_id_uri.as_bytes().to_vec();
+            data.push(0);
+            data.extend_from_slice(self.value.as_bytes());
+            data.push(0);
+            data
+        };
+
+        let mut payload = Vec::new();
+        let version = match self.time {
+            EventTime::Delta(delta) => {
+                payload.extend_from_slice(&strings());
+                payload.extend_from_slice(&self.timescale.to_be_bytes());
+                payload.extend_from_slice(&delta.to_be_bytes());
+                payload.extend_from_slice(&self.duration.to_be_bytes());
+                payload.extend_from_slice(&self.id.to_be_bytes());
+                0
+            }
+            EventTime::Absolute(time) => {
+                payload.extend_from_slice(&self.timescale.to_be_bytes());
+                payload.extend_from_slice(&time.to_be_bytes());
+                payload.extend_from_slice(&self.duration.to_be_bytes());
+                payload.extend_from_slice(&self.id.to_be_bytes());
+                payload.extend_from_slice(&strings());
+                1
+            }
+        };
+        payload.extend_from_slice(&self.data);
+
+        let mut out = Vec::new();
+        put_full_box(&mut out, b"emsg", version, 0, &payload);
+        out
+    }
+
+    /// Returns the presentation time of the event, in the timescale of the
+    /// message, from the earliest presentation time of its segment.
+    pub fn get_presentation_time(&self, earliest: u64) -> u64 {
+        match self.time {
+            EventTime::Delta(delta) => earliest + u64::from(delta),
+            EventTime::Absolute(time) => time,
+        }
+    }
+
+    /// Returns the message as a key packet of an event message stream,
+    /// timed by the event.
+    pub fn to_packet(&self, stream_index: isize, earliest: u64) -> Result<Packet> {
+        let mut pkt = Packet::new();
+        pkt.data = self.to_box();
+        pkt.stream_index = stream_index;
+        let timebase = Rational64::new(1, i64::from(self.timescale));
+        let ts = Timestamp::new(self.get_presentation_time(earliest) as i64, timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        if self.duration != u32::MAX {
+            let duration = Duration::new(u64::from(self.duration), timebase);
+            pkt.t.set_duration(Some(duration))?;
+        }
+        pkt.is_key = true;
+        Ok(pkt)
+    }
+
+    /// Returns the ID3 tag carried by the message, if its scheme is the
+    /// ID3 one.
+    pub fn get_id3(&self) -> Option<Result<id3::Tag>> {
+        if self.scheme_id_uri != ID3_SCHEME {
+            return None;
+        }
+        Some(id3::Tag::parse(&self.data).map(|(tag, _)| tag))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(time: EventTime) -> EventMessage {
+        EventMessage {
+            scheme_id_uri: SCTE35_SCHEME.to_owned(),
+            value: String::new(),
+            timescale: 90000,
+            time,
+            duration: 2700000,
+            id: 7,
+            data: vec![0xfc, 0x30, 0x11],
+        }
+    }
+
+    #[test]
+    fn versions() {
+        for &time in &[EventTime::Delta(4500), EventTime::Absolute(1 << 33)] {
+            let msg = message(time);
+            let data = msg.to_box();
+            assert_eq!(data[8], if time == EventTime::Delta(4500) { 0 } else { 1 });
+            let (parsed, size) = EventMessage::parse(&data).unwrap();
+            assert_eq!(size, data.len());
+            assert_eq!(parsed, msg);
+            assert!(parsed.get_id3().is_none());
+        }
+
+        assert_eq!(
+            message(EventTime::Delta(4500)).get_presentation_time(90000),
+            94500
+        );
+        let pkt = message(EventTime::Absolute(1 << 33))
+            .to_packet(2, 0)
+            .unwrap();
+        assert_eq!(
+            (
+                pkt.t.get_pts().map(Timestamp::get_value),
+                pkt.t.get_duration().map(Duration::get_value)
+            ),
+            (Some(1 << 33), Some(2700000))
+        );
+        assert_eq!(pkt.t.timebase, Some(Rational64::new(1, 90000)));
+        assert_eq!(EventMessage::parse(&pkt.data).unwrap().0.id, 7);
+    }
+
+    #[test]
+    fn id3() {
+        let mut msg = message(EventTime::Delta(0));
+        msg.scheme_id_uri = ID3_SCHEME.to_owned();
+        msg.data = id3::test::tag().to_bytes().unwrap();
+        let (parsed, _) = EventMessage::parse(&msg.to_box()).unwrap();
+        let tag = parsed.get_id3().unwrap().unwrap();
+        assert_eq!(tag.get_text(b"TIT2").as_deref(), Some("Live"));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = message(EventTime::Delta(0)).to_box();
+        // Missing string terminator.
+        let len = data.len();
+        data.truncate(len - 3 - 16 - 1);
+        data[3] = data.len() as u8;
+        assert!(EventMessage::parse(&data).is_err());
+
+        let mut data = message(EventTime::Delta(0)).to_box();
+        data[8] = 2;
+        assert!(matches!(
+            EventMessage::parse(&data),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(EventMessage::parse(b"\x00\x00\x00\x08free").is_err());
+    }
+}
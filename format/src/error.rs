@@ -2,6 +2,9 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::data::budget::BudgetError;
+use crate::data::timeinfo::TimeError;
+
 /// General muxing/demuxing errors.
 #[derive(Debug, Error)]
 pub enum Error {
@@ -11,9 +14,43 @@ pub enum Error {
     /// A muxing/demuxing operation needs more data to be completed.
     #[error("{0} more bytes needed")]
     MoreDataNeeded(usize),
+    /// Unsupported requested feature.
+    #[error("Unsupported feature {0}")]
+    Unsupported(String),
     #[error("I/O error")]
     /// A more generic I/O error.
-    Io(#[from] io::Error),
+    Io(#[source] io::Error),
+    /// The operation was cancelled through a `CancelToken`.
+    #[error("Cancelled")]
+    Cancelled,
+    /// The operation did not complete before its deadline.
+    #[error("Timed out")]
+    TimedOut,
+    /// A resource limit of the context is exceeded.
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(&'static str),
+    /// A timestamp is missing or its arithmetic failed.
+    #[error("Timestamp error: {0}")]
+    Time(#[from] TimeError),
+}
+
+impl From<io::Error> for Error {
+    /// Unwraps the errors carried through I/O, such as the cancellation
+    /// raised by an `Interruptible` reader.
+    fn from(err: io::Error) -> Self {
+        match err.get_ref() {
+            Some(inner) if inner.is::<Error>() => {
+                *err.into_inner().unwrap().downcast::<Error>().unwrap()
+            }
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<BudgetError> for Error {
+    fn from(_: BudgetError) -> Self {
+        Error::LimitExceeded("memory budget")
+    }
 }
 
 /// A specialised `Result` type for muxing/demuxing operations.
@@ -34,4 +71,20 @@ mod tests {
             _ => panic!("Error doesn't match"),
         }
     }
+
+    #[test]
+    fn wrapped_error_conversion() {
+        let io_err = io::Error::new(io::ErrorKind::TimedOut, Error::TimedOut);
+
+        let err: Error = io_err.into();
+
+        assert!(matches!(err, Error::TimedOut));
+    }
+
+    #[test]
+    fn time_error_conversion() {
+        let err: Error = TimeError::Overflow.into();
+
+        assert!(matches!(err, Error::Time(TimeError::Overflow)));
+    }
 }
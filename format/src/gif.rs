@@ -0,0 +1,553 @@
+//!
+//! Animated GIF demuxer.
+//!
+//! The frames are decoded and composited, each packet holds the whole
+//! picture as `rawvideo` RGBA, timestamped in hundredths of second as
+//! the frame delays are.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::canvas::{Canvas, Dispose, Rect, MAX_PIXELS};
+use crate::common::*;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Size of the header and of the logical screen descriptor.
+const HEADER_SIZE: usize = 13;
+/// Size of the image descriptor.
+const IMAGE_SIZE: usize = 10;
+
+/// Block introducers.
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+
+/// Extension labels.
+const EXT_GRAPHIC_CONTROL: u8 = 0xf9;
+const EXT_COMMENT: u8 = 0xfe;
+const EXT_APPLICATION: u8 = 0xff;
+
+/// Largest LZW code size.
+const MAX_CODE_SIZE: u32 = 12;
+
+/// Delay of the frames asking for less than 2 hundredths of second.
+///
+/// Browsers slow those frames down, animations are made expecting it.
+const DEFAULT_DELAY: u16 = 10;
+
+fn is_gif(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+/// Reads a color table, if the flags of the descriptor announce one.
+fn color_table(data: &[u8], pos: usize, flags: u8) -> Result<(Vec<[u8; 3]>, usize)> {
+    if flags & 0x80 == 0 {
+        return Ok((Vec::new(), pos));
+    }
+    let end = pos + (3 << ((flags & 7) + 1));
+    need(data, end)?;
+    let table = data[pos..end]
+        .chunks(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect();
+
+    Ok((table, end))
+}
+
+/// Reads the data sub-blocks starting at `pos`, returning their content
+/// and the position following the terminator.
+fn sub_blocks(data: &[u8], mut pos: usize) -> Result<(Vec<u8>, usize)> {
+    let mut content = Vec::new();
+    loop {
+        need(data, pos + 1)?;
+        let len = data[pos] as usize;
+        pos += 1;
+        if len == 0 {
+            return Ok((content, pos));
+        }
+        need(data, pos + len)?;
+        content.extend_from_slice(&data[pos..pos + len]);
+        pos += len;
+    }
+}
+
+/// Decodes the LZW compressed indices of an image holding `count` pixels.
+///
+/// Truncated images are padded with the index 0.
+fn lzw_decode(min_size: u8, data: &[u8], count: usize) -> Result<Vec<u8>> {
+    if !(2..=8).contains(&min_size) {
+        return Err(Error::InvalidData);
+    }
+    let clear = 1u16 << min_size;
+    let end = clear + 1;
+
+    // Each code is a prefix code followed by a byte.
+    let mut prefix = [0u16; 1 << MAX_CODE_SIZE];
+    let mut suffix = [0u8; 1 << MAX_CODE_SIZE];
+    let mut first = [0u8; 1 << MAX_CODE_SIZE];
+    let mut length = [0u16; 1 << MAX_CODE_SIZE];
+    for code in 0..clear {
+        suffix[code as usize] = code as u8;
+        first[code as usize] = code as u8;
+        length[code as usize] = 1;
+    }
+
+    let mut out = Vec::with_capacity(count);
+    let mut size = u32::from(min_size) + 1;
+    let mut next = end + 1;
+    let mut prev: Option<u16> = None;
+    let (mut buf, mut bits) = (0u32, 0u32);
+    let mut bytes = data.iter();
+
+    while out.len() < count {
+        while bits < size {
+            match bytes.next() {
+                Some(&b) => buf |= u32::from(b) << bits,
+                None => break,
+            }
+            bits += 8;
+        }
+        if bits < size {
+            break;
+        }
+        let code = (buf & ((1 << size) - 1)) as u16;
+        buf >>= size;
+        bits -= size;
+
+        if code == clear {
+            size = u32::from(min_size) + 1;
+            next = end + 1;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        if let Some(prev) = prev {
+            if code > next || (code == next && next as usize == prefix.len()) {
+                return Err(Error::InvalidData);
+            }
+            if (next as usize) < prefix.len() {
+                let n = next as usize;
+                prefix[n] = prev;
+                suffix[n] = if code == next {
+                    first[prev as usize]
+                } else {
+                    first[code as usize]
+                };
+                first[n] = first[prev as usize];
+                length[n] = length[prev as usize] + 1;
+                next += 1;
+                if next == 1 << size && size < MAX_CODE_SIZE {
+                    size += 1;
+                }
+            }
+        } else if code >= clear {
+            return Err(Error::InvalidData);
+        }
+
+        let start = out.len();
+        out.resize(start + length[code as usize] as usize, 0);
+        let mut c = code as usize;
+        for i in (start..out.len()).rev() {
+            out[i] = suffix[c];
+            c = prefix[c] as usize;
+        }
+        prev = Some(code);
+    }
+
+    out.resize(count, 0);
+    Ok(out)
+}
+
+/// Returns the order in which the rows of an interlaced image are stored.
+fn interlaced_rows(height: usize) -> Vec<usize> {
+    [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .iter()
+        .flat_map(|&(start, step)| (start..height).step_by(step))
+        .collect()
+}
+
+/// Parameters of the graphic control extension.
+#[derive(Clone, Copy, Debug)]
+struct GraphicControl {
+    dispose: Dispose,
+    delay: u16,
+    transparent: Option<u8>,
+}
+
+impl Default for GraphicControl {
+    fn default() -> Self {
+        GraphicControl {
+            dispose: Dispose::None,
+            delay: 0,
+            transparent: None,
+        }
+    }
+}
+
+impl GraphicControl {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(Error::InvalidData);
+        }
+        // Disposing to the background color is done by clearing the area
+        // to transparent, as browsers do.
+        let dispose = match (data[0] >> 2) & 7 {
+            2 => Dispose::Background,
+            3 => Dispose::Previous,
+            _ => Dispose::None,
+        };
+
+        Ok(GraphicControl {
+            dispose,
+            delay: get_u16l(&data[1..]),
+            transparent: if data[0] & 1 != 0 {
+                Some(data[3])
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// GIF demuxer.
+#[derive(Default)]
+pub struct GifDemuxer {
+    canvas: Option<Canvas>,
+    palette: Vec<[u8; 3]>,
+    control: Option<GraphicControl>,
+    pts: i64,
+}
+
+impl GifDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read_image(&mut self, data: &[u8]) -> Result<(SeekFrom, Event)> {
+        need(data, IMAGE_SIZE)?;
+        let rect = Rect {
+            x: get_u16l(&data[1..]) as usize,
+            y: get_u16l(&data[3..]) as usize,
+            width: get_u16l(&data[5..]) as usize,
+            height: get_u16l(&data[7..]) as usize,
+        };
+        if rect.width * rect.height > MAX_PIXELS {
+            return Err(Error::InvalidData);
+        }
+        let flags = data[9];
+        let (local, pos) = color_table(data, IMAGE_SIZE, flags)?;
+        need(data, pos + 1)?;
+        let (lzw, end) = sub_blocks(data, pos + 1)?;
+
+        let indices = lzw_decode(data[pos], &lzw, rect.width * rect.height)?;
+        let palette = if local.is_empty() {
+            &self.palette
+        } else {
+            &local
+        };
+        let control = self.control.take().unwrap_or_default();
+        let rows = if flags & 0x40 != 0 {
+            interlaced_rows(rect.height)
+        } else {
+            (0..rect.height).collect()
+        };
+
+        let canvas = self.canvas.as_mut().ok_or(Error::InvalidData)?;
+        canvas.start_frame(rect, control.dispose);
+        for (line, &y) in indices.chunks(rect.width.max(1)).zip(rows.iter()) {
+            for (x, &index) in line.iter().enumerate() {
+                if Some(index) == control.transparent {
+                    continue;
+                }
+                if let Some(&[r, g, b]) = palette.get(index as usize) {
+                    canvas.set(rect.x + x, rect.y + y, [r, g, b, 255]);
+                }
+            }
+        }
+
+        let delay = if control.delay < 2 {
+            DEFAULT_DELAY
+        } else {
+            control.delay
+        };
+        let mut pkt = canvas.packet();
+        pkt.stream_index = 0;
+        let timebase = Rational64::new(1, 100);
+        let ts = Timestamp::new(self.pts, timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.t
+            .set_duration(Some(Duration::new(u64::from(delay), timebase)))?;
+        self.pts += i64::from(delay);
+
+        Ok((SeekFrom::Current(end as i64), Event::NewPacket(pkt)))
+    }
+}
+
+impl Demuxer for GifDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, HEADER_SIZE)?;
+        if !is_gif(data) {
+            return Err(Error::InvalidData);
+        }
+        let width = get_u16l(&data[6..]) as usize;
+        let height = get_u16l(&data[8..]) as usize;
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidData);
+        }
+        if width * height > MAX_PIXELS {
+            return Err(Error::Unsupported(format!("{}x{} picture", width, height)));
+        }
+        let (palette, end) = color_table(data, HEADER_SIZE, data[10])?;
+
+        // The looping and the comments are usually found before the first
+        // image, the extensions are left to read_event to skip.
+        let mut pos = end;
+        loop {
+            need(data, pos + 1)?;
+            if data[pos] != EXTENSION {
+                break;
+            }
+            need(data, pos + 2)?;
+            let (content, next) = sub_blocks(data, pos + 2)?;
+            match data[pos + 1] {
+                EXT_APPLICATION if content.len() >= 14 && content.starts_with(b"NETSCAPE2.0") => {
+                    let loops = get_u16l(&content[12..]);
+                    info.metadata.insert("loop_count", u64::from(loops));
+                }
+                EXT_COMMENT => {
+                    let comment = String::from_utf8_lossy(&content).into_owned();
+                    info.metadata.insert("comment", comment);
+                }
+                _ => {}
+            }
+            pos = next;
+        }
+
+        let params = Canvas::params(width, height);
+        info.add_stream(Stream::from_params(&params, Rational64::new(1, 100)));
+
+        self.canvas = Some(Canvas::new(width, height));
+        self.palette = palette;
+        self.control = None;
+        self.pts = 0;
+
+        Ok(SeekFrom::Current(end as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let data = buf.data();
+        need(data, 1)?;
+        match data[0] {
+            TRAILER => Ok((SeekFrom::Current(0), Event::Eof)),
+            EXTENSION => {
+                need(data, 2)?;
+                let (content, end) = sub_blocks(data, 2)?;
+                if data[1] == EXT_GRAPHIC_CONTROL {
+                    self.control = Some(GraphicControl::parse(&content)?);
+                }
+                Ok((SeekFrom::Current(end as i64), Event::Continue))
+            }
+            IMAGE => self.read_image(data),
+            _ => Err(Error::InvalidData),
+        }
+    }
+}
+
+struct GifDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for GifDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(GifDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if is_gif(data) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// GIF demuxer descriptor.
+pub const GIF_DESCR: &dyn demuxer::Descriptor = &GifDescr {
+    d: demuxer::Descr {
+        name: "gif",
+        demuxer: "gif",
+        description: "Graphics Interchange Format",
+        extensions: &["gif"],
+        mime: &["image/gif"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::params::MediaKind;
+    use crate::demuxer::{read_packets, Context};
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn lzw_encode(min_size: u8, indices: &[u8]) -> Vec<u8> {
+        let clear = 1u16 << min_size;
+        let mut out = Vec::new();
+        let (mut buf, mut bits) = (0u32, 0u32);
+        let mut emit = |code: u16, size: u32, out: &mut Vec<u8>| {
+            buf |= u32::from(code) << bits;
+            bits += size;
+            while bits >= 8 {
+                out.push(buf as u8);
+                buf >>= 8;
+                bits -= 8;
+            }
+        };
+
+        let mut dict = HashMap::new();
+        let mut size = u32::from(min_size) + 1;
+        let mut next = clear + 2;
+        emit(clear, size, &mut out);
+        let mut prefix = u16::from(indices[0]);
+        for &index in &indices[1..] {
+            if let Some(&code) = dict.get(&(prefix, index)) {
+                prefix = code;
+                continue;
+            }
+            emit(prefix, size, &mut out);
+            if next < 4096 {
+                dict.insert((prefix, index), next);
+                next += 1;
+                if next > 1 << size && size < MAX_CODE_SIZE {
+                    size += 1;
+                }
+            }
+            prefix = u16::from(index);
+        }
+        emit(prefix, size, &mut out);
+        if next >= 1 << size && size < MAX_CODE_SIZE {
+            size += 1;
+        }
+        emit(clear + 1, size, &mut out);
+        emit(0, 7, &mut out);
+        out
+    }
+
+    fn blocks(file: &mut Vec<u8>, data: &[u8]) {
+        for chunk in data.chunks(255) {
+            file.push(chunk.len() as u8);
+            file.extend_from_slice(chunk);
+        }
+        file.push(0);
+    }
+
+    fn image(file: &mut Vec<u8>, rect: [u16; 4], flags: u8, indices: &[u8]) {
+        file.push(IMAGE);
+        for v in &rect {
+            file.extend_from_slice(&v.to_le_bytes());
+        }
+        file.push(flags);
+        file.push(2);
+        blocks(file, &lzw_encode(2, indices));
+    }
+
+    fn control(file: &mut Vec<u8>, flags: u8, delay: u16, transparent: u8) {
+        file.extend_from_slice(&[EXTENSION, EXT_GRAPHIC_CONTROL, 4, flags]);
+        file.extend_from_slice(&delay.to_le_bytes());
+        file.extend_from_slice(&[transparent, 0]);
+    }
+
+    fn gif_file() -> Vec<u8> {
+        let mut file = b"GIF89a".to_vec();
+        file.extend_from_slice(&[4, 0, 2, 0, 0x81, 0, 0]);
+        // Black, red, green, blue.
+        file.extend_from_slice(&[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        file.extend_from_slice(&[EXTENSION, EXT_APPLICATION, 11]);
+        file.extend_from_slice(b"NETSCAPE2.0");
+        file.extend_from_slice(&[3, 1, 0, 0, 0]);
+
+        control(&mut file, 0, 5, 0);
+        image(&mut file, [0, 0, 4, 2], 0, &[1, 1, 1, 1, 2, 2, 2, 2]);
+        control(&mut file, 1, 0, 0);
+        image(&mut file, [1, 0, 2, 2], 0, &[3, 0, 3, 3]);
+        file.extend_from_slice(&[EXTENSION, EXT_COMMENT]);
+        blocks(&mut file, b"done");
+        file.push(TRAILER);
+        file
+    }
+
+    #[test]
+    fn lzw() {
+        let indices: Vec<u8> = (0..5000u32).map(|i| ((i * i / 7) % 4) as u8).collect();
+        let data = lzw_encode(2, &indices);
+        assert_eq!(lzw_decode(2, &data, indices.len()).unwrap(), indices);
+        assert_eq!(lzw_decode(2, &data[..16], 8).unwrap(), &indices[..8]);
+        assert!(lzw_decode(2, &[0x07], 4).is_err());
+    }
+
+    #[test]
+    fn interlace() {
+        assert_eq!(interlaced_rows(5), [0, 4, 2, 1, 3]);
+    }
+
+    #[test]
+    fn demux() {
+        let file = gif_file();
+        assert_eq!(GIF_DESCR.probe(&file), 100);
+
+        let r = AccReader::with_capacity(16, Cursor::new(file));
+        let mut c = Context::new(GIF_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        assert_eq!(c.info.streams.len(), 1);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("rawvideo"));
+        assert_eq!(st.timebase, Rational64::new(1, 100));
+        match st.params.kind {
+            Some(MediaKind::Video(ref info)) => assert_eq!((info.width, info.height), (4, 2)),
+            _ => panic!("Not a video stream"),
+        }
+        assert_eq!(c.info.metadata.get_u64("loop_count"), Some(0));
+
+        let packets = read_packets(&mut c);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].t.get_pts().map(Timestamp::get_value), Some(0));
+        assert_eq!(
+            packets[0].t.get_duration().map(Duration::get_value),
+            Some(5)
+        );
+        assert_eq!(packets[1].t.get_pts().map(Timestamp::get_value), Some(5));
+        assert_eq!(
+            packets[1].t.get_duration().map(Duration::get_value),
+            Some(u64::from(DEFAULT_DELAY))
+        );
+
+        // The RGBA format stores alpha first.
+        let red = [255, 0, 0, 255];
+        let green = [255, 0, 255, 0];
+        let blue = [255, 255, 0, 0];
+        assert_eq!(&packets[0].data[..4], &red);
+        assert_eq!(&packets[0].data[16..20], &green);
+        // The second frame keeps the first where transparent.
+        let pixels: Vec<_> = packets[1].data.chunks(4).collect();
+        assert_eq!(pixels, [red, blue, red, red, green, blue, blue, green]);
+    }
+}
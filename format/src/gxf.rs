@@ -0,0 +1,525 @@
+//!
+//! General eXchange Format demuxer (SMPTE 360M).
+//!
+//! The streams are described by the map packet starting the file, the
+//! field locator tables and the UMF packets are skipped. Timestamps are
+//! expressed in fields, as the media packets are.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::mxf::{mpeg2_is_intra, Mpeg2Fields};
+use crate::pcm;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Packet types.
+const PKT_MAP: u8 = 0xbc;
+const PKT_MEDIA: u8 = 0xbf;
+const PKT_EOS: u8 = 0xfb;
+
+/// Size of the packet header.
+const HEADER_SIZE: usize = 16;
+/// Size of the preamble of the media packets.
+const PREAMBLE_SIZE: usize = 16;
+
+/// Material and track tags.
+mod tag {
+    pub const MAT_NAME: u8 = 0x40;
+    pub const MAT_FIRST_FIELD: u8 = 0x41;
+    pub const MAT_LAST_FIELD: u8 = 0x42;
+    pub const TRACK_FPS: u8 = 0x50;
+    pub const TRACK_FPF: u8 = 0x52;
+}
+
+/// Frame rates of the track frame rate tag, starting from 1.
+const FRAME_RATES: [(i64, i64); 8] = [
+    (60, 1),
+    (60000, 1001),
+    (50, 1),
+    (30, 1),
+    (30000, 1001),
+    (25, 1),
+    (24, 1),
+    (24000, 1001),
+];
+
+/// Reads a packet header, returning the packet type and the size of its
+/// payload.
+fn read_header(data: &[u8]) -> Result<(u8, usize)> {
+    need(data, HEADER_SIZE)?;
+    if data[..5] != [0, 0, 0, 0, 1] || data[10..16] != [0, 0, 0, 0, 0xe1, 0xe2] {
+        return Err(Error::InvalidData);
+    }
+    let len = get_u32b(&data[6..]) as usize;
+    if len < HEADER_SIZE {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((data[5], len - HEADER_SIZE))
+}
+
+/// Splits a section into its tags.
+fn tags(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut tags = Vec::new();
+    while data.len() >= 2 && data.len() >= 2 + data[1] as usize {
+        let len = data[1] as usize;
+        tags.push((data[0], &data[2..2 + len]));
+        data = &data[2 + len..];
+    }
+    tags
+}
+
+/// Reads a 32-bit tag value.
+fn tag_u32(value: &[u8]) -> Option<u32> {
+    if value.len() == 4 {
+        Some(get_u32b(value))
+    } else {
+        None
+    }
+}
+
+/// Reads a length prefixed section of the map.
+fn section(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if data.len() < 2 {
+        return Err(Error::InvalidData);
+    }
+    let len = get_u16b(data) as usize;
+    let end = 2 + len;
+    if data.len() < end {
+        return Err(Error::InvalidData);
+    }
+    Ok((&data[2..end], &data[end..]))
+}
+
+/// Material description.
+#[derive(Debug, Default)]
+struct Material {
+    name: Option<String>,
+    first_field: Option<u32>,
+    last_field: Option<u32>,
+}
+
+impl Material {
+    fn parse(data: &[u8]) -> Self {
+        let mut material = Material::default();
+        for (tag, value) in tags(data) {
+            match tag {
+                tag::MAT_NAME => {
+                    let name = String::from_utf8_lossy(value);
+                    let name = name.trim_end_matches('\0');
+                    if !name.is_empty() {
+                        material.name = Some(name.to_owned());
+                    }
+                }
+                tag::MAT_FIRST_FIELD => material.first_field = tag_u32(value),
+                tag::MAT_LAST_FIELD => material.last_field = tag_u32(value),
+                _ => {}
+            }
+        }
+        material
+    }
+
+    /// Returns the duration of the material in fields.
+    fn duration(&self) -> Option<u64> {
+        match (self.first_field, self.last_field) {
+            (Some(first), Some(last)) if last >= first => Some(u64::from(last - first)),
+            _ => None,
+        }
+    }
+}
+
+/// Track description.
+#[derive(Debug)]
+struct Track {
+    kind: u8,
+    id: u8,
+    frame_rate: Option<Rational64>,
+    fields_per_frame: u32,
+}
+
+impl Track {
+    fn parse(data: &[u8]) -> Result<(Self, &[u8])> {
+        if data.len() < 4 {
+            return Err(Error::InvalidData);
+        }
+        let (kind, id) = (data[0], data[1]);
+        if kind & 0x80 == 0 || id & 0xc0 != 0xc0 {
+            return Err(Error::InvalidData);
+        }
+        let (desc, rest) = section(&data[2..])?;
+
+        let mut track = Track {
+            kind: kind & 0x7f,
+            id: id & 0x3f,
+            frame_rate: None,
+            fields_per_frame: 2,
+        };
+        for (tag, value) in tags(desc) {
+            match (tag, tag_u32(value)) {
+                (tag::TRACK_FPS, Some(fps)) => {
+                    track.frame_rate = FRAME_RATES
+                        .get((fps as usize).wrapping_sub(1))
+                        .map(|&(num, den)| Rational64::new(num, den));
+                }
+                (tag::TRACK_FPF, Some(fpf @ 1..=2)) => track.fields_per_frame = fpf,
+                _ => {}
+            }
+        }
+
+        Ok((track, rest))
+    }
+}
+
+/// Parses the map packet payload.
+fn parse_map(data: &[u8]) -> Result<(Material, Vec<Track>)> {
+    if data.len() < 2 || data[0] != 0xe0 || data[1] != 0xff {
+        return Err(Error::InvalidData);
+    }
+    let (material, rest) = section(&data[2..])?;
+    let (mut desc, _) = section(rest)?;
+
+    let mut tracks = Vec::new();
+    while !desc.is_empty() {
+        let (track, rest) = Track::parse(desc)?;
+        tracks.push(track);
+        desc = rest;
+    }
+
+    Ok((Material::parse(material), tracks))
+}
+
+/// Media of a track.
+enum Media {
+    Video(&'static str),
+    Audio(&'static str, usize),
+}
+
+/// Returns the media of a track type, timecode and unknown tracks are
+/// ignored.
+fn track_media(kind: u8) -> Option<Media> {
+    match kind {
+        3 | 4 => Some(Media::Video("mjpeg")),
+        11 | 12 | 20 => Some(Media::Video("mpeg2video")),
+        13..=16 | 25 => Some(Media::Video("dvvideo")),
+        22 | 23 => Some(Media::Video("mpeg1video")),
+        9 => Some(Media::Audio("pcm_s24le", 1)),
+        10 => Some(Media::Audio("pcm_s16le", 1)),
+        17 => Some(Media::Audio("ac3", 2)),
+        _ => None,
+    }
+}
+
+/// Track mapped to a stream.
+#[derive(Debug)]
+struct GxfStream {
+    id: u8,
+    index: usize,
+    mpeg: bool,
+    fields: Mpeg2Fields,
+    timebase: Rational64,
+    duration: Option<u64>,
+}
+
+/// GXF demuxer.
+#[derive(Default)]
+pub struct GxfDemuxer {
+    streams: Vec<GxfStream>,
+}
+
+impl GxfDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add_stream(
+        &mut self,
+        track: &Track,
+        timebase: Rational64,
+        info: &mut GlobalInfo,
+    ) -> Result<()> {
+        let (params, duration) = match track_media(track.kind) {
+            Some(Media::Video(codec_id)) => {
+                let params = CodecParams {
+                    kind: Some(MediaKind::Video(VideoInfo {
+                        width: 0,
+                        height: 0,
+                        format: None,
+                    })),
+                    codec_id: Some(codec_id.to_owned()),
+                    extradata: None,
+                    bit_rate: 0,
+                    convergence_window: 0,
+                    delay: 0,
+                };
+                (params, Some(u64::from(track.fields_per_frame)))
+            }
+            Some(Media::Audio(codec_id, channels)) => {
+                let map = pcm::channel_map(channels)?;
+                let format = pcm::sample_format(codec_id);
+                (
+                    pcm::audio_params(codec_id.to_owned(), 48000, map, format),
+                    None,
+                )
+            }
+            None => return Ok(()),
+        };
+
+        let mut st = Stream::from_params(&params, timebase);
+        st.id = track.id as isize;
+        let index = info.add_stream(st);
+        self.streams.push(GxfStream {
+            id: track.id,
+            index,
+            mpeg: params
+                .codec_id
+                .as_deref()
+                .is_some_and(|id| id.starts_with("mpeg")),
+            fields: Mpeg2Fields::default(),
+            timebase,
+            duration,
+        });
+
+        Ok(())
+    }
+}
+
+impl Demuxer for GxfDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        let (kind, len) = read_header(data)?;
+        if kind != PKT_MAP {
+            return Err(Error::InvalidData);
+        }
+        let end = HEADER_SIZE + len;
+        need(data, end)?;
+        let (material, tracks) = parse_map(&data[HEADER_SIZE..end])?;
+
+        // Fields are counted at the rate of the first track declaring one.
+        let timebase = tracks
+            .iter()
+            .find_map(|track| {
+                track
+                    .frame_rate
+                    .map(|rate| rate * i64::from(track.fields_per_frame))
+            })
+            .unwrap_or_else(|| Rational64::from_integer(50))
+            .recip();
+
+        self.streams.clear();
+        for track in &tracks {
+            self.add_stream(track, timebase, info)?;
+        }
+        for st in info.streams.iter_mut() {
+            st.duration = material.duration();
+        }
+        if let Some(name) = material.name {
+            info.metadata.insert("title", name);
+        }
+
+        Ok(SeekFrom::Current(end as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let data = buf.data();
+        let (kind, len) = read_header(data)?;
+        let end = HEADER_SIZE + len;
+        if kind == PKT_EOS {
+            return Ok((SeekFrom::Current(0), Event::Eof));
+        }
+        need(data, end)?;
+        if kind != PKT_MEDIA {
+            return Ok((SeekFrom::Current(end as i64), Event::Continue));
+        }
+        if len < PREAMBLE_SIZE {
+            return Err(Error::InvalidData);
+        }
+
+        let preamble = &data[HEADER_SIZE..HEADER_SIZE + PREAMBLE_SIZE];
+        let id = preamble[1] & 0x3f;
+        let st = match self.streams.iter_mut().find(|st| st.id == id) {
+            Some(st) => st,
+            None => return Ok((SeekFrom::Current(end as i64), Event::Continue)),
+        };
+        let field = i64::from(get_u32b(&preamble[2..]));
+        let payload = &data[HEADER_SIZE + PREAMBLE_SIZE..end];
+
+        let mut pkt = Packet::with_capacity(payload.len());
+        pkt.data.extend_from_slice(payload);
+        pkt.stream_index = st.index as isize;
+        pkt.is_key = !st.mpeg || mpeg2_is_intra(payload);
+        if st.mpeg {
+            pkt.fields = st.fields.parse(payload);
+        }
+        let ts = Timestamp::new(field, st.timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.t
+            .set_duration(st.duration.map(|d| Duration::new(d, st.timebase)))?;
+
+        Ok((SeekFrom::Current(end as i64), Event::NewPacket(pkt)))
+    }
+}
+
+struct GxfDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for GxfDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(GxfDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        match read_header(data) {
+            Ok((PKT_MAP, _)) => 100,
+            _ => 0,
+        }
+    }
+}
+
+/// GXF demuxer descriptor.
+pub const GXF_DESCR: &dyn demuxer::Descriptor = &GxfDescr {
+    d: demuxer::Descr {
+        name: "gxf",
+        demuxer: "gxf",
+        description: "General eXchange Format",
+        extensions: &["gxf"],
+        mime: &[],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::demuxer::{read_packets, Context};
+    use std::io::Cursor;
+
+    fn packet(file: &mut Vec<u8>, kind: u8, payload: &[u8]) {
+        file.extend_from_slice(&[0, 0, 0, 0, 1, kind]);
+        file.extend_from_slice(&((HEADER_SIZE + payload.len()) as u32).to_be_bytes());
+        file.extend_from_slice(&[0, 0, 0, 0, 0xe1, 0xe2]);
+        file.extend_from_slice(payload);
+    }
+
+    fn media(file: &mut Vec<u8>, kind: u8, id: u8, field: u32, payload: &[u8]) {
+        let mut data = vec![0x80 | kind, 0xc0 | id];
+        data.extend_from_slice(&field.to_be_bytes());
+        data.extend_from_slice(&[0; 10]);
+        data.extend_from_slice(payload);
+        packet(file, PKT_MEDIA, &data);
+    }
+
+    fn track(desc: &mut Vec<u8>, kind: u8, id: u8, tags: &[u8]) {
+        desc.extend_from_slice(&[0x80 | kind, 0xc0 | id]);
+        desc.extend_from_slice(&(tags.len() as u16).to_be_bytes());
+        desc.extend_from_slice(tags);
+    }
+
+    fn gxf_file() -> Vec<u8> {
+        let mut material = vec![tag::MAT_NAME, 6];
+        material.extend_from_slice(b"clip\0\0");
+        material.extend_from_slice(&[tag::MAT_FIRST_FIELD, 4, 0, 0, 0, 0]);
+        material.extend_from_slice(&[tag::MAT_LAST_FIELD, 4, 0, 0, 0, 4]);
+
+        let mut desc = Vec::new();
+        track(&mut desc, 7, 0, &[]);
+        track(
+            &mut desc,
+            11,
+            1,
+            &[tag::TRACK_FPS, 4, 0, 0, 0, 6, tag::TRACK_FPF, 4, 0, 0, 0, 2],
+        );
+        track(&mut desc, 9, 2, &[]);
+
+        let mut map = vec![0xe0, 0xff];
+        map.extend_from_slice(&(material.len() as u16).to_be_bytes());
+        map.extend_from_slice(&material);
+        map.extend_from_slice(&(desc.len() as u16).to_be_bytes());
+        map.extend_from_slice(&desc);
+
+        let mut file = Vec::new();
+        packet(&mut file, PKT_MAP, &map);
+        for field in &[0u32, 2] {
+            let coding = if *field == 0 { 0x08 } else { 0x10 };
+            media(&mut file, 7, 0, *field, &[0; 4]);
+            media(&mut file, 11, 1, *field, &[0, 0, 1, 0, 0, coding]);
+            media(&mut file, 9, 2, *field, &[0; 5760]);
+        }
+        packet(&mut file, 0xfc, &[0; 8]);
+        packet(&mut file, PKT_EOS, &[]);
+        file
+    }
+
+    #[test]
+    fn demux() {
+        let file = gxf_file();
+        assert_eq!(GXF_DESCR.probe(&file), 100);
+
+        let r = AccReader::with_capacity(64, Cursor::new(file));
+        let mut c = Context::new(GXF_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        assert_eq!(c.info.streams.len(), 2);
+        let video = &c.info.streams[0];
+        assert_eq!(video.params.codec_id.as_deref(), Some("mpeg2video"));
+        assert_eq!(video.id, 1);
+        assert_eq!(video.timebase, Rational64::new(1, 50));
+        assert_eq!(video.duration, Some(4));
+        let audio = &c.info.streams[1];
+        assert_eq!(audio.params.codec_id.as_deref(), Some("pcm_s24le"));
+        assert_eq!(audio.id, 2);
+        assert_eq!(c.info.metadata.get_str("title"), Some("clip"));
+
+        let packets = read_packets(&mut c);
+        assert_eq!(packets.len(), 4);
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|p| {
+                (
+                    p.stream_index,
+                    p.t.get_pts().map(Timestamp::get_value),
+                    p.is_key,
+                    p.data.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, Some(0), true, 6),
+                (1, Some(0), true, 5760),
+                (0, Some(2), false, 6),
+                (1, Some(2), true, 5760),
+            ]
+        );
+        assert_eq!(
+            packets[0].t.get_duration().map(Duration::get_value),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn invalid_map() {
+        let mut file = Vec::new();
+        packet(&mut file, PKT_MAP, &[0xe0, 0xff, 0, 8, 0, 0]);
+        let r = AccReader::with_capacity(64, Cursor::new(file));
+        let mut c = Context::new(GXF_DESCR.create(), Box::new(r));
+        assert!(c.read_headers().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//!
+//! Timed ID3 metadata.
+//!
+//! ID3v2 tags carried as timed metadata, as HLS does in the private data
+//! streams of its transport streams. The demuxers expose them as packets
+//! of `timed_id3` data streams, each packet holding a whole tag, and the
+//! applications willing to insert tags at given timestamps build them with
+//! `Tag::to_packet`.
+//!
+
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, DataInfo, MediaKind};
+use crate::data::timeinfo::Timestamp;
+use crate::error::*;
+use crate::rational::Rational64;
+
+/// Codec of the timed ID3 streams.
+pub const CODEC_ID: &str = "timed_id3";
+
+/// Size of the tag header.
+const HEADER_SIZE: usize = 10;
+
+/// Flags of the tag header.
+const UNSYNCHRONISATION: u8 = 0x80;
+const EXTENDED_HEADER: u8 = 0x40;
+const FOOTER: u8 = 0x10;
+
+/// Returns the codec parameters of a timed ID3 stream.
+pub fn params() -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Data(DataInfo::default())),
+        codec_id: Some(CODEC_ID.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// Tells if the data starts with an ID3v2 tag header.
+pub fn is_id3(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE
+        && &data[..3] == b"ID3"
+        && data[3] != 0xff
+        && data[4] != 0xff
+        && data[6..10].iter().all(|&b| b < 0x80)
+}
+
+fn get_syncsafe(data: &[u8]) -> usize {
+    data[..4]
+        .iter()
+        .fold(0, |size, &b| size << 7 | usize::from(b & 0x7f))
+}
+
+fn put_syncsafe(out: &mut Vec<u8>, size: usize) -> Result<()> {
+    if size >= 1 << 28 {
+        return Err(Error::InvalidData);
+    }
+    out.extend((0..4).rev().map(|i| (size >> (7 * i)) as u8 & 0x7f));
+    Ok(())
+}
+
+/// Frame of a tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Frame identifier (e.g. `TIT2`).
+    pub id: [u8; 4],
+    /// Frame content.
+    pub data: Vec<u8>,
+}
+
+/// ID3v2 tag.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tag {
+    /// Frames of the tag.
+    pub frames: Vec<Frame>,
+}
+
+impl Tag {
+    /// Creates a new empty tag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a version 2.3 or 2.4 tag, returning it along with its size.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        if !is_id3(data) {
+            return Err(Error::InvalidData);
+        }
+        let version = data[3];
+        let flags = data[5];
+        if !(3..=4).contains(&version) {
+            return Err(Error::Unsupported(format!("ID3v2.{}", version)));
+        }
+        if flags & UNSYNCHRONISATION != 0 {
+            return Err(Error::Unsupported("ID3 unsynchronisation".to_owned()));
+        }
+        let size = HEADER_SIZE + get_syncsafe(&data[6..]);
+        let end = size + if flags & FOOTER != 0 { HEADER_SIZE } else { 0 };
+        let mut body = data.get(HEADER_SIZE..size).ok_or(Error::InvalidData)?;
+
+        if flags & EXTENDED_HEADER != 0 {
+            if body.len() < 4 {
+                return Err(Error::InvalidData);
+            }
+            // The version 2.3 size leaves itself out.
+            let len = match version {
+                3 => 4 + u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize,
+                _ => get_syncsafe(body),
+            };
+            body = body.get(len..).ok_or(Error::InvalidData)?;
+        }
+
+        let mut tag = Tag::new();
+        while body.len() >= HEADER_SIZE && body[0] != 0 {
+            let id = [body[0], body[1], body[2], body[3]];
+            let len = match version {
+                3 => u32::from_be_bytes([body[4], body[5], body[6], body[7]]) as usize,
+                _ => get_syncsafe(&body[4..]),
+            };
+            let data = body
+                .get(HEADER_SIZE..HEADER_SIZE + len)
+                .ok_or(Error::InvalidData)?;
+            tag.frames.push(Frame {
+                id,
+                data: data.to_vec(),
+            });
+            body = &body[HEADER_SIZE + len..];
+        }
+
+        Ok((tag, end))
+    }
+
+    /// Returns the tag as version 2.4, without padding.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        for frame in &self.frames {
+            body.extend_from_slice(&frame.id);
+            put_syncsafe(&mut body, frame.data.len())?;
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&frame.data);
+        }
+
+        let mut data = b"ID3\x04\x00\x00".to_vec();
+        put_syncsafe(&mut data, body.len())?;
+        data.extend_from_slice(&body);
+        Ok(data)
+    }
+
+    /// Returns the tag as a key packet of a timed ID3 stream.
+    pub fn to_packet(&self, stream_index: isize, pts: i64, timebase: Rational64) -> Result<Packet> {
+        let mut pkt = Packet::new();
+        pkt.data = self.to_bytes()?;
+        pkt.stream_index = stream_index;
+        let ts = Timestamp::new(pts, timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.is_key = true;
+        Ok(pkt)
+    }
+
+    /// Returns the first frame of an identifier.
+    pub fn get(&self, id: &[u8; 4]) -> Option<&Frame> {
+        self.frames.iter().find(|frame| &frame.id == id)
+    }
+
+    /// Adds a text frame, UTF-8 encoded.
+    pub fn add_text(&mut self, id: &[u8; 4], text: &str) {
+        let mut data = vec![3];
+        data.extend_from_slice(text.as_bytes());
+        self.frames.push(Frame { id: *id, data });
+    }
+
+    /// Returns the text of the first text frame of an identifier.
+    pub fn get_text(&self, id: &[u8; 4]) -> Option<String> {
+        let (&encoding, text) = self.get(id)?.data.split_first()?;
+        let text = match encoding {
+            0 => text.iter().map(|&b| char::from(b)).collect(),
+            1 | 2 => {
+                let mut units: Vec<u16> = text
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect();
+                match units.first() {
+                    Some(0xfffe) => {
+                        units.remove(0);
+                        units.iter_mut().for_each(|u| *u = u.swap_bytes());
+                    }
+                    Some(0xfeff) => {
+                        units.remove(0);
+                    }
+                    _ => {}
+                }
+                String::from_utf16_lossy(&units)
+            }
+            3 => String::from_utf8_lossy(text).into_owned(),
+            _ => return None,
+        };
+        Some(text.trim_end_matches('\0').to_owned())
+    }
+
+    /// Adds a private frame, its owner identifying the data.
+    pub fn add_private(&mut self, owner: &str, data: &[u8]) {
+        let mut content = owner.as_bytes().to_vec();
+        content.push(0);
+        content.extend_from_slice(data);
+        self.frames.push(Frame {
+            id: *b"PRIV",
+            data: content,
+        });
+    }
+
+    /// Returns the data of the first private frame of an owner.
+    pub fn get_private(&self, owner: &str) -> Option<&[u8]> {
+        self.frames
+            .iter()
+            .filter(|frame| &frame.id == b"PRIV")
+            .find_map(|frame| {
+                let len = frame.data.iter().position(|&b| b == 0)?;
+                if &frame.data[..len] == owner.as_bytes() {
+                    Some(&frame.data[len + 1..])
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Returns a tag with a title and a private frame.
+    pub(crate) fn tag() -> Tag {
+        let mut tag = Tag::new();
+        tag.add_text(b"TIT2", "Live");
+        tag.add_private("com.apple.streaming.transportStreamTimestamp", &[0; 8]);
+        tag
+    }
+
+    #[test]
+    fn roundtrip() {
+        let tag = tag();
+        let mut data = tag.to_bytes().unwrap();
+        let size = data.len();
+        assert!(is_id3(&data));
+        // Padding.
+        data.extend_from_slice(&[0; 20]);
+        data[9] += 20;
+
+        let (parsed, end) = Tag::parse(&data).unwrap();
+        assert_eq!(end, size + 20);
+        assert_eq!(parsed, tag);
+        assert_eq!(parsed.get_text(b"TIT2").as_deref(), Some("Live"));
+        assert_eq!(
+            parsed.get_private("com.apple.streaming.transportStreamTimestamp"),
+            Some(&[0; 8][..])
+        );
+        assert_eq!(parsed.get_private("other"), None);
+
+        let pkt = tag.to_packet(1, 900, Rational64::new(1, 90000)).unwrap();
+        assert_eq!(
+            (pkt.stream_index, pkt.t.get_pts().map(Timestamp::get_value)),
+            (1, Some(900))
+        );
+        assert_eq!(pkt.data, tag.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn version3() {
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x00\x17".to_vec();
+        data.extend_from_slice(b"TPE1\x00\x00\x00\x0d\x00\x00");
+        data.extend_from_slice(&[1, 0xff, 0xfe, b'A', 0, b'r', 0, b't', 0, 0, 0, 0, 0]);
+
+        let (tag, end) = Tag::parse(&data).unwrap();
+        assert_eq!(end, data.len());
+        assert_eq!(tag.get_text(b"TPE1").as_deref(), Some("Art"));
+
+        data[5] = UNSYNCHRONISATION;
+        assert!(matches!(Tag::parse(&data), Err(Error::Unsupported(_))));
+        data[5] = 0;
+        data[9] += 1;
+        assert!(Tag::parse(&data).is_err());
+        assert!(!is_id3(b"ID3\x04\x00\x00\x80\x00\x00\x00"));
+    }
+}
@@ -0,0 +1,317 @@
+//!
+//! Deflate (RFC 1951) and zlib (RFC 1950) decompression.
+//!
+//! The Huffman codes are decoded bit by bit, which is enough for the
+//! small images carried by the formats needing it.
+//!
+
+use av_bitstream::byteread::get_u32b;
+
+use crate::error::*;
+
+/// Maximum length of a Huffman code.
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order of the code length code lengths.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Canonical Huffman code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+/// Reads the bits of a deflate stream, least significant first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn get(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(Error::InvalidData)?;
+            self.pos += 1;
+            self.buf |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let v = self.buf & ((1u64 << n) - 1) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= self.get(1)? as i32;
+            let count = i32::from(h.counts[len]);
+            if code - first < count {
+                return Ok(h.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::InvalidData)
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let nlen = bits.get(5)? as usize + 257;
+    let ndist = bits.get(5)? as usize + 1;
+    let ncode = bits.get(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(Error::InvalidData);
+    }
+
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..ncode] {
+        lengths[i] = bits.get(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = bits.decode(&code)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if i > 0 => (lengths[i - 1], 3 + bits.get(2)?),
+            17 => (0, 3 + bits.get(3)?),
+            18 => (0, 11 + bits.get(7)?),
+            _ => return Err(Error::InvalidData),
+        };
+        let end = i + repeat as usize;
+        if end > lengths.len() {
+            return Err(Error::InvalidData);
+        }
+        lengths[i..end].iter_mut().for_each(|l| *l = len);
+        i = end;
+    }
+    if lengths[256] == 0 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+/// Decompresses a raw deflate stream, producing at most `max_size` bytes.
+pub(crate) fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buf: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.get(1)? == 1;
+        match bits.get(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.pos..bits.pos + 4).ok_or(Error::InvalidData)?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len as u16 != !nlen {
+                    return Err(Error::InvalidData);
+                }
+                let start = bits.pos + 4;
+                let stored = data.get(start..start + len).ok_or(Error::InvalidData)?;
+                if out.len() + len > max_size {
+                    return Err(Error::InvalidData);
+                }
+                out.extend_from_slice(stored);
+                bits.pos = start + len;
+            }
+            kind @ 1..=2 => {
+                let (lencode, distcode) = if kind == 1 {
+                    fixed_codes()?
+                } else {
+                    dynamic_codes(&mut bits)?
+                };
+                loop {
+                    let symbol = bits.decode(&lencode)? as usize;
+                    if symbol < 256 {
+                        if out.len() >= max_size {
+                            return Err(Error::InvalidData);
+                        }
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let symbol = symbol - 257;
+                    if symbol >= LENGTH_BASE.len() {
+                        return Err(Error::InvalidData);
+                    }
+                    let len = LENGTH_BASE[symbol] as usize
+                        + bits.get(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+                    let symbol = bits.decode(&distcode)? as usize;
+                    if symbol >= DIST_BASE.len() {
+                        return Err(Error::InvalidData);
+                    }
+                    let dist = DIST_BASE[symbol] as usize
+                        + bits.get(u32::from(DIST_EXTRA[symbol]))? as usize;
+                    if dist > out.len() || out.len() + len > max_size {
+                        return Err(Error::InvalidData);
+                    }
+                    let start = out.len() - dist;
+                    for i in 0..len {
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+            _ => return Err(Error::InvalidData),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream, producing at most `max_size` bytes.
+pub(crate) fn uncompress(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(Error::InvalidData);
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 || flg & 0x20 != 0 {
+        return Err(Error::InvalidData);
+    }
+    let out = inflate(&data[2..], max_size)?;
+
+    if get_u32b(&data[data.len() - 4..]) != adler32(&out) {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stored() {
+        let data = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 1, 2, 3, 0, 13, 0, 7,
+        ];
+        assert_eq!(uncompress(&data, 16).unwrap(), [1, 2, 3]);
+        assert!(uncompress(&data, 2).is_err());
+    }
+
+    #[test]
+    fn fixed() {
+        // zlib.compress(b"hello hello hello hello", 9)
+        let data = [
+            0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        let out = uncompress(&data, 64).unwrap();
+        assert_eq!(out, b"hello hello hello hello");
+        assert_eq!(adler32(&out), 0x6803_08b1);
+    }
+
+    #[test]
+    fn dynamic() {
+        let data = [
+            0x78, 0xda, 0x3d, 0x8e, 0xc9, 0x11, 0xc0, 0x30, 0x0c, 0x02, 0x6b, 0xe5, 0xe8, 0xbf,
+            0x86, 0x68, 0xf1, 0x4c, 0xfc, 0x11, 0x08, 0x04, 0x96, 0x9b, 0x48, 0x72, 0x6c, 0xeb,
+            0x90, 0x55, 0x58, 0x94, 0xa3, 0x39, 0xca, 0x3b, 0x09, 0x04, 0x9d, 0x4f, 0x03, 0x37,
+            0x3a, 0x25, 0x45, 0xcb, 0x6d, 0xdc, 0x89, 0x0c, 0x57, 0x4b, 0xd0, 0x1c, 0x31, 0x68,
+            0x15, 0x5e, 0xc1, 0xf2, 0xca, 0xe6, 0xcf, 0xee, 0x10, 0xa7, 0x38, 0x83, 0x5e, 0xee,
+            0xbc, 0x2a, 0xf2, 0x26, 0xf5, 0xfd, 0x88, 0xed, 0xcb, 0x63, 0x7c, 0x07, 0x92, 0x4c,
+            0x7a,
+        ];
+        let expected = "abdccaaabcbbbacaabadaabccacbaccabaaaaabbaabaacabacbbbaaabacbaaadbb\
+                        aabcdaacaccbabdbaaabbdbabdabaccaabaabccbcaabbbacabdaabcabaacdbbbab\
+                        aabaacabdbaabadbababbbcaacddcbcabcbaaabdabbababdaaaabbbcbabbacababba";
+        assert_eq!(uncompress(&data, 200).unwrap(), expected.as_bytes());
+        assert!(uncompress(&data, 199).is_err());
+
+        let mut corrupt = data;
+        corrupt[84] ^= 1;
+        assert!(uncompress(&corrupt, 200).is_err());
+    }
+}
@@ -0,0 +1,1158 @@
+//!
+//! ISO base media file format (ISO/IEC 14496-12) helpers.
+//!
+//! Besides the box helpers of the demuxers and muxers of the crate, the
+//! relationships between tracks and the timed data tracks are parsed for
+//! any demuxer of the format.
+//!
+
+use av_bitstream::byteread::*;
+
+use crate::common::GlobalInfo;
+use crate::data::audiosample::{ChannelLayout, ChannelMap, ChannelType};
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, DataInfo, MediaKind};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::need;
+use crate::error::*;
+use crate::limits::Limits;
+use crate::rational::Rational64;
+use crate::stream::{GroupKind, StreamGroup};
+
+/// Size of a box header without large size.
+pub(crate) const BOX_HEADER_SIZE: usize = 8;
+
+/// Reads the box starting at `pos`, returning its type, its payload and
+/// the position following it.
+pub(crate) fn read_box(data: &[u8], pos: usize) -> Result<([u8; 4], &[u8], usize)> {
+    need(data, pos + BOX_HEADER_SIZE)?;
+    let size = get_u32b(&data[pos..]) as u64;
+    let kind = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+    let (header, size) = match size {
+        0 => {
+            return Err(Error::Unsupported(
+                "boxes extending to the end of file".to_owned(),
+            ))
+        }
+        1 => {
+            need(data, pos + 16)?;
+            (16, get_u64b(&data[pos + 8..]))
+        }
+        size => (BOX_HEADER_SIZE, size),
+    };
+    if size < header as u64 || size > usize::MAX as u64 / 2 {
+        return Err(Error::InvalidData);
+    }
+    let end = pos + size as usize;
+    need(data, end)?;
+
+    Ok((kind, &data[pos + header..end], end))
+}
+
+/// Splits the payload of a container box into its children.
+pub(crate) fn children(mut data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        let (kind, payload, end) = read_box(data, 0).map_err(|_| Error::InvalidData)?;
+        boxes.push((kind, payload));
+        data = &data[end..];
+    }
+    Ok(boxes)
+}
+
+/// Splits the payload of a full box into its version, flags and content.
+pub(crate) fn full_box(data: &[u8]) -> Result<(u8, u32, &[u8])> {
+    if data.len() < 4 {
+        return Err(Error::InvalidData);
+    }
+    Ok((data[0], get_u32b(data) & 0x00ff_ffff, &data[4..]))
+}
+
+/// Reads a big-endian unsigned integer of `size` bytes, advancing `data`.
+pub(crate) fn get_sized(data: &mut &[u8], size: usize) -> Result<u64> {
+    if data.len() < size {
+        return Err(Error::InvalidData);
+    }
+    let v = match size {
+        0 => 0,
+        1 => u64::from(data[0]),
+        2 => u64::from(get_u16b(data)),
+        4 => u64::from(get_u32b(data)),
+        8 => get_u64b(data),
+        _ => return Err(Error::InvalidData),
+    };
+    *data = &data[size..];
+    Ok(v)
+}
+
+/// Appends a box.
+pub(crate) fn put_box(out: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(&((BOX_HEADER_SIZE + payload.len()) as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+}
+
+/// Appends a full box.
+pub(crate) fn put_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    payload: &[u8],
+) {
+    let mut data = (u32::from(version) << 24 | flags).to_be_bytes().to_vec();
+    data.extend_from_slice(payload);
+    put_box(out, kind, &data);
+}
+
+/// Scheme of the `kind` boxes telling the purpose of audio tracks, `1`
+/// being audio description.
+const AUDIO_PURPOSE: &[u8] = b"urn:tva:metadata:cs:AudioPurposeCS:2007";
+
+/// Properties of a track relating it to other tracks.
+#[derive(Debug, Default)]
+struct Track {
+    id: u32,
+    alternate_group: u16,
+    audio: bool,
+    description: bool,
+    /// Tile tracks referenced by a tile base track.
+    tiles: Vec<u32>,
+    /// Stereoscopic track group and whether the track is the left view.
+    stereo: Option<(u32, bool)>,
+}
+
+fn find<'a>(boxes: &[([u8; 4], &'a [u8])], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes
+        .iter()
+        .find(|(k, _)| k == kind)
+        .map(|&(_, payload)| payload)
+}
+
+fn parse_trak(data: &[u8]) -> Result<Track> {
+    let boxes = children(data)?;
+    let mut track = Track::default();
+
+    let tkhd = find(&boxes, b"tkhd").ok_or(Error::InvalidData)?;
+    let (version, _, mut tkhd) = full_box(tkhd)?;
+    let time_size = if version == 1 { 8 } else { 4 };
+    get_sized(&mut tkhd, time_size)?;
+    get_sized(&mut tkhd, time_size)?;
+    track.id = get_sized(&mut tkhd, 4)? as u32;
+    get_sized(&mut tkhd, 4)?;
+    get_sized(&mut tkhd, time_size)?;
+    get_sized(&mut tkhd, 8)?;
+    get_sized(&mut tkhd, 2)?;
+    track.alternate_group = get_sized(&mut tkhd, 2)? as u16;
+
+    if let Some(mdia) = find(&boxes, b"mdia") {
+        if let Some(hdlr) = find(&children(mdia)?, b"hdlr") {
+            let (_, _, hdlr) = full_box(hdlr)?;
+            track.audio = hdlr.get(4..8) == Some(&b"soun"[..]);
+        }
+    }
+    if let Some(tref) = find(&boxes, b"tref") {
+        for (kind, ids) in children(tref)? {
+            if &kind == b"sabt" {
+                track.tiles.extend(ids.chunks_exact(4).map(get_u32b));
+            }
+        }
+    }
+    if let Some(trgr) = find(&boxes, b"trgr") {
+        for (kind, group) in children(trgr)? {
+            if &kind == b"ster" {
+                let (_, _, mut group) = full_box(group)?;
+                let id = get_sized(&mut group, 4)? as u32;
+                let left = get_sized(&mut group, 4)? >> 31 == 1;
+                track.stereo = Some((id, left));
+            }
+        }
+    }
+    if let Some(udta) = find(&boxes, b"udta") {
+        for (kind, data) in children(udta)? {
+            if &kind == b"kind" {
+                let (_, _, data) = full_box(data)?;
+                let mut strings = data.split(|&b| b == 0);
+                track.description |=
+                    strings.next() == Some(AUDIO_PURPOSE) && strings.next() == Some(&b"1"[..]);
+            }
+        }
+    }
+
+    Ok(track)
+}
+
+/// Splits tracks sorted by key into the groups of a key, single tracks
+/// left apart.
+fn split(tracks: &[(u32, u32)]) -> Vec<Vec<u32>> {
+    let mut groups: Vec<Vec<u32>> = Vec::new();
+    let mut last = None;
+    for &(key, id) in tracks {
+        match groups.last_mut() {
+            Some(group) if last == Some(key) => group.push(id),
+            _ => groups.push(vec![id]),
+        }
+        last = Some(key);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Returns the relationships between the tracks of a movie box, the
+/// tracks being given by identifier.
+///
+/// Tiles come from the `sabt` references of the tile base tracks,
+/// stereoscopic pairs from the `ster` track groups and alternatives from
+/// the alternate groups of the track headers. Audio descriptions are the
+/// tracks flagged so by a `kind` box, describing the other audio tracks
+/// of their alternate group.
+pub fn track_groups(moov: &[u8]) -> Result<Vec<(GroupKind, Vec<u32>)>> {
+    let tracks = children(moov)?
+        .into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, data)| parse_trak(data))
+        .collect::<Result<Vec<_>>>()?;
+    let mut groups = Vec::new();
+
+    for track in tracks.iter().filter(|track| !track.tiles.is_empty()) {
+        let mut ids = vec![track.id];
+        ids.extend_from_slice(&track.tiles);
+        groups.push((GroupKind::Tiles, ids));
+    }
+
+    let mut stereo: Vec<_> = tracks
+        .iter()
+        .filter_map(|track| track.stereo.map(|(group, left)| (group, !left, track.id)))
+        .collect();
+    stereo.sort_by_key(|&(group, right, _)| (group, right));
+    let stereo: Vec<_> = stereo.iter().map(|&(group, _, id)| (group, id)).collect();
+    groups.extend(
+        split(&stereo)
+            .into_iter()
+            .map(|ids| (GroupKind::Stereo, ids)),
+    );
+
+    let mut alternatives: Vec<_> = tracks
+        .iter()
+        .filter(|track| track.alternate_group != 0)
+        .map(|track| (u32::from(track.alternate_group), track.id))
+        .collect();
+    alternatives.sort_by_key(|&(group, _)| group);
+    groups.extend(
+        split(&alternatives)
+            .into_iter()
+            .map(|ids| (GroupKind::Alternatives, ids)),
+    );
+
+    for track in tracks
+        .iter()
+        .filter(|track| track.audio && track.description)
+    {
+        let mut ids: Vec<_> = tracks
+            .iter()
+            .filter(|main| {
+                main.audio
+                    && !main.description
+                    && main.alternate_group != 0
+                    && main.alternate_group == track.alternate_group
+            })
+            .map(|main| main.id)
+            .collect();
+        ids.push(track.id);
+        groups.push((GroupKind::AudioDescription, ids));
+    }
+
+    Ok(groups)
+}
+
+/// Adds the relationships between the tracks of a movie box to the global
+/// information, the streams having the track identifiers as ids.
+///
+/// The tracks not exposed as streams are left out of the groups.
+pub fn add_track_groups(info: &mut GlobalInfo, moov: &[u8]) -> Result<()> {
+    for (kind, ids) in track_groups(moov)? {
+        let streams: Vec<_> = ids
+            .iter()
+            .filter_map(|&id| info.streams.iter().find(|st| st.id == id as isize))
+            .map(|st| st.index)
+            .collect();
+        if streams.len() > 1 {
+            let id = info.groups.len();
+            info.add_group(StreamGroup { id, kind, streams });
+        }
+    }
+    Ok(())
+}
+
+/// Sample of a track, as described by its sample table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Offset of the sample in the file.
+    pub offset: u64,
+    /// Size of the sample.
+    pub size: u32,
+    /// Decoding timestamp, in the timescale of the track.
+    pub dts: u64,
+    /// Duration, in the timescale of the track.
+    pub duration: u32,
+    /// Roll distance of the sample, from its `roll` sample group: the
+    /// number of samples to decode before it (if negative) or after it for
+    /// it to be presented correctly.
+    pub roll: i16,
+}
+
+/// Reads the entry count of a full box listing entries of `size` bytes,
+/// checking that they all fit, and returns the entries.
+fn entries(data: &[u8], size: usize) -> Result<(usize, &[u8])> {
+    let (_, _, mut data) = full_box(data)?;
+    let count = get_sized(&mut data, 4)? as usize;
+    if count > data.len() / size {
+        return Err(Error::InvalidData);
+    }
+    Ok((count, &data[..count * size]))
+}
+
+/// Parses the sample table of a track, each sample counting as an index
+/// entry.
+///
+/// The composition offsets and the edit lists are not applied.
+pub fn samples(stbl: &[u8], limits: &Limits) -> Result<Vec<Sample>> {
+    let boxes = children(stbl)?;
+    let table = |kind| find(&boxes, kind).ok_or(Error::InvalidData);
+
+    let (_, _, mut stsz) = full_box(table(b"stsz")?)?;
+    let sample_size = get_sized(&mut stsz, 4)? as u32;
+    let count = get_sized(&mut stsz, 4)? as usize;
+    limits.check_index_entries(count)?;
+    if sample_size == 0 && count > stsz.len() / 4 {
+        return Err(Error::InvalidData);
+    }
+    let size = |index: usize| match sample_size {
+        0 => get_u32b(&stsz[index * 4..]),
+        size => size,
+    };
+
+    let (_, mut stts) = entries(table(b"stts")?, 8)?;
+    let (_, mut stsc) = entries(table(b"stsc")?, 12)?;
+    let mut runs = Vec::new();
+    while !stsc.is_empty() {
+        let first_chunk = get_sized(&mut stsc, 4)?;
+        let samples = get_sized(&mut stsc, 4)?;
+        get_sized(&mut stsc, 4)?;
+        runs.push((first_chunk, samples));
+    }
+
+    let offsets: Vec<u64> = match (find(&boxes, b"stco"), find(&boxes, b"co64")) {
+        (Some(stco), _) => {
+            let (count, data) = entries(stco, 4)?;
+            data.chunks_exact(4)
+                .take(count)
+                .map(|d| get_u32b(d).into())
+                .collect()
+        }
+        (None, Some(co64)) => {
+            let (count, data) = entries(co64, 8)?;
+            data.chunks_exact(8).take(count).map(get_u64b).collect()
+        }
+        _ => return Err(Error::InvalidData),
+    };
+
+    let mut samples = Vec::new();
+    let (mut dts, mut delta, mut left) = (0u64, 0, 0);
+    let (mut run, mut per_chunk) = (0, 0);
+    for (chunk, &offset) in offsets.iter().enumerate() {
+        let chunk = chunk as u64 + 1;
+        // The runs of chunks are sorted by their first chunk.
+        while let Some(&(first, samples)) = runs.get(run) {
+            if first > chunk {
+                break;
+            }
+            per_chunk = samples;
+            run += 1;
+        }
+        let mut offset = offset;
+        for _ in 0..per_chunk.min((count - samples.len()) as u64) {
+            while left == 0 && stts.len() >= 8 {
+                left = get_sized(&mut stts, 4)?;
+                delta = get_sized(&mut stts, 4)? as u32;
+            }
+            left = left.saturating_sub(1);
+            let size = size(samples.len());
+            samples.push(Sample {
+                offset,
+                size,
+                dts,
+                duration: delta,
+                roll: 0,
+            });
+            offset += u64::from(size);
+            dts += u64::from(delta);
+        }
+    }
+    if samples.len() < count {
+        return Err(Error::InvalidData);
+    }
+
+    let rolls = sample_rolls(stbl, &roll_groups(stbl)?, count)?;
+    for (sample, roll) in samples.iter_mut().zip(rolls) {
+        sample.roll = roll;
+    }
+
+    Ok(samples)
+}
+
+/// Index of the first group description of a track fragment, the lower
+/// ones pointing in the sample table of the track.
+const FRAGMENT_GROUPS: u32 = 0x10000;
+
+/// Parses the `roll` group descriptions of a sample table or of a track
+/// fragment, returning their roll distances.
+pub fn roll_groups(container: &[u8]) -> Result<Vec<i16>> {
+    let boxes = children(container)?;
+    let sgpd = boxes
+        .iter()
+        .find(|(kind, payload)| kind == b"sgpd" && payload.get(4..8) == Some(&b"roll"[..]));
+    let (version, _, mut data) = match sgpd {
+        Some((_, payload)) => full_box(payload)?,
+        None => return Ok(Vec::new()),
+    };
+    get_sized(&mut data, 4)?;
+    let default_length = match version {
+        1 => get_sized(&mut data, 4)?,
+        0 => 2,
+        _ => {
+            get_sized(&mut data, 4)?;
+            2
+        }
+    };
+    let count = get_sized(&mut data, 4)?;
+
+    let mut rolls = Vec::new();
+    for _ in 0..count {
+        let length = match default_length {
+            0 => get_sized(&mut data, 4)?,
+            length => length,
+        };
+        if length < 2 || data.len() < length as usize {
+            return Err(Error::InvalidData);
+        }
+        rolls.push(get_u16b(data) as i16);
+        data = &data[length as usize..];
+    }
+
+    Ok(rolls)
+}
+
+/// Returns the roll distances of the samples of a sample table or of a
+/// track fragment, from its `roll` sample groups, 0 for the samples out of
+/// any.
+///
+/// `track` holds the roll distances described by the sample table of the
+/// track, for the samples of a fragment pointing in it.
+pub fn sample_rolls(container: &[u8], track: &[i16], count: usize) -> Result<Vec<i16>> {
+    let local = roll_groups(container)?;
+    let boxes = children(container)?;
+    let sbgp = boxes
+        .iter()
+        .find(|(kind, payload)| kind == b"sbgp" && payload.get(4..8) == Some(&b"roll"[..]));
+    let (version, _, mut data) = match sbgp {
+        Some((_, payload)) => full_box(payload)?,
+        None => return Ok(vec![0; count]),
+    };
+    get_sized(&mut data, 4)?;
+    if version == 1 {
+        get_sized(&mut data, 4)?;
+    }
+    let entries = get_sized(&mut data, 4)?;
+    if entries > data.len() as u64 / 8 {
+        return Err(Error::InvalidData);
+    }
+
+    let mut rolls = Vec::with_capacity(count);
+    for _ in 0..entries {
+        let samples = get_sized(&mut data, 4)? as usize;
+        let index = get_sized(&mut data, 4)? as u32;
+        let roll = match index {
+            0 => Some(&0),
+            index if index > FRAGMENT_GROUPS => local.get((index - FRAGMENT_GROUPS - 1) as usize),
+            index => track.get(index as usize - 1),
+        };
+        let roll = *roll.ok_or(Error::InvalidData)?;
+        let left = count - rolls.len();
+        rolls.extend(std::iter::repeat_n(roll, samples.min(left)));
+    }
+    rolls.resize(count, 0);
+
+    Ok(rolls)
+}
+
+/// Returns the index of the sample to start decoding from for a sample to
+/// be presented correctly, as told by its roll distance.
+pub fn preroll_start(samples: &[Sample], index: usize) -> usize {
+    match samples.get(index) {
+        Some(sample) if sample.roll < 0 => {
+            index.saturating_sub(sample.roll.unsigned_abs() as usize)
+        }
+        _ => index,
+    }
+}
+
+/// Returns the roll distance of the audio codecs needing decoder preroll,
+/// given the number of samples of their frames.
+///
+/// AAC needs the previous frame and Opus 80 ms of audio.
+pub fn codec_roll_distance(codec_id: &str, frame_samples: u32) -> Option<i16> {
+    match codec_id {
+        "aac" => Some(-1),
+        "opus" if frame_samples > 0 => Some(-((3840u32.div_ceil(frame_samples)) as i16)),
+        _ => None,
+    }
+}
+
+/// Appends the `sgpd` and `sbgp` boxes giving a roll distance to `count`
+/// samples, to a sample table or a track fragment.
+///
+/// In a track fragment, the description is local to the fragment.
+pub fn put_roll_group(out: &mut Vec<u8>, roll: i16, count: u32, fragment: bool) {
+    let mut sgpd = b"roll".to_vec();
+    sgpd.extend_from_slice(&2u32.to_be_bytes());
+    sgpd.extend_from_slice(&1u32.to_be_bytes());
+    sgpd.extend_from_slice(&roll.to_be_bytes());
+    put_full_box(out, b"sgpd", 1, 0, &sgpd);
+
+    let index = if fragment { FRAGMENT_GROUPS + 1 } else { 1 };
+    let mut sbgp = b"roll".to_vec();
+    sbgp.extend_from_slice(&1u32.to_be_bytes());
+    sbgp.extend_from_slice(&count.to_be_bytes());
+    sbgp.extend_from_slice(&index.to_be_bytes());
+    put_full_box(out, b"sbgp", 0, 0, &sbgp);
+}
+
+/// Channel structured stream of a `chnl` box.
+const CHANNEL_STRUCTURED: u8 = 1;
+/// Object structured stream of a `chnl` box.
+const OBJECT_STRUCTURED: u8 = 2;
+/// Speaker position followed by its azimuth and elevation.
+const EXPLICIT_POSITION: u8 = 126;
+
+/// Returns the channel of an ISO/IEC 23091-3 speaker position.
+fn speaker_position(position: u8) -> Option<ChannelType> {
+    use crate::data::audiosample::ChannelType::*;
+    let ch = match position {
+        0 => L,
+        1 => R,
+        2 => C,
+        3 => LFE,
+        4 => Ls,
+        5 => Rs,
+        6 => Lc,
+        7 => Rc,
+        10 => Cs,
+        13 => Lss,
+        14 => Rss,
+        15 => Lw,
+        16 => Rw,
+        17 => Lh,
+        18 => Rh,
+        19 => Ch,
+        20 => Lhs,
+        21 => Rhs,
+        22 => Chs,
+        25 => Ov,
+        26 => LFE2,
+        _ => return None,
+    };
+    Some(ch)
+}
+
+/// Returns the channels of an ISO/IEC 23091-3 channel configuration.
+fn defined_layout(layout: u8) -> Option<&'static [ChannelType]> {
+    use crate::data::audiosample::ChannelType::*;
+    let chs: &[ChannelType] = match layout {
+        1 => &[C],
+        2 => &[L, R],
+        3 => &[C, L, R],
+        4 => &[C, L, R, Cs],
+        5 => &[C, L, R, Ls, Rs],
+        6 => &[C, L, R, Ls, Rs, LFE],
+        7 => &[C, Lc, Rc, L, R, Ls, Rs, LFE],
+        9 => &[L, R, Cs],
+        10 => &[L, R, Ls, Rs],
+        11 => &[C, L, R, Ls, Rs, Cs, LFE],
+        _ => return None,
+    };
+    Some(chs)
+}
+
+/// Parses the payload of a version 0 `chnl` box, `channels` being the
+/// channel count of its audio sample entry.
+///
+/// The layout is unspecified for the object structured streams and the
+/// positions without a channel type.
+pub fn parse_chnl(data: &[u8], channels: usize) -> Result<ChannelLayout> {
+    let (version, _, mut data) = full_box(data)?;
+    if version != 0 {
+        return Err(Error::Unsupported(format!("chnl version {}", version)));
+    }
+    let structure = get_sized(&mut data, 1)? as u8;
+    if structure & OBJECT_STRUCTURED != 0 || structure & CHANNEL_STRUCTURED == 0 {
+        return Ok(ChannelLayout::Unspecified(channels));
+    }
+
+    let mut map = ChannelMap::new();
+    let mut named = true;
+    match get_sized(&mut data, 1)? as u8 {
+        0 => {
+            for _ in 0..channels {
+                let position = get_sized(&mut data, 1)? as u8;
+                if position == EXPLICIT_POSITION {
+                    get_sized(&mut data, 2)?;
+                    get_sized(&mut data, 1)?;
+                }
+                match speaker_position(position) {
+                    Some(ch) => map.add_channel(ch),
+                    None => named = false,
+                }
+            }
+        }
+        layout => {
+            let omitted = get_sized(&mut data, 8)?;
+            match defined_layout(layout) {
+                Some(chs) => {
+                    for (i, &ch) in chs.iter().enumerate() {
+                        if omitted & (1 << i) == 0 {
+                            map.add_channel(ch);
+                        }
+                    }
+                }
+                None => named = false,
+            }
+        }
+    }
+
+    if !named {
+        return Ok(ChannelLayout::Unspecified(channels));
+    }
+    if map.len() != channels {
+        return Err(Error::InvalidData);
+    }
+    Ok(ChannelLayout::from_map(&map))
+}
+
+/// Parses the payload of a spatial audio `SA3D` box, giving the layout of
+/// ambisonic audio, optionally followed by head-locked stereo.
+pub fn parse_sa3d(mut data: &[u8]) -> Result<ChannelLayout> {
+    let version = get_sized(&mut data, 1)?;
+    if version != 0 {
+        return Err(Error::Unsupported(format!("SA3D version {}", version)));
+    }
+    let kind = get_sized(&mut data, 1)?;
+    let order = get_sized(&mut data, 4)? as u32;
+    let ordering = get_sized(&mut data, 1)?;
+    let _normalization = get_sized(&mut data, 1)?;
+    let channels = get_sized(&mut data, 4)? as usize;
+    if kind != 0 || ordering != 0 {
+        return Err(Error::Unsupported(
+            "non periphonic ACN ambisonics".to_owned(),
+        ));
+    }
+    for i in 0..channels {
+        if get_sized(&mut data, 4)? != i as u64 {
+            return Err(Error::Unsupported("ambisonic channel mapping".to_owned()));
+        }
+    }
+
+    let diegetic = order
+        .checked_add(1)
+        .and_then(|side| side.checked_mul(side))
+        .ok_or(Error::InvalidData)? as usize;
+    let mask = match channels.checked_sub(diegetic) {
+        Some(0) => 0,
+        Some(2) => ChannelLayout::STEREO.get_mask().unwrap_or(0),
+        _ => return Err(Error::InvalidData),
+    };
+    Ok(ChannelLayout::Ambisonic { order, mask })
+}
+
+/// Timed data track, such as camera telemetry.
+///
+/// GoPro metadata (`gpmd`) and text metadata (`mett`) sample entries are
+/// recognized, the codec being named after them.
+#[derive(Clone, Debug)]
+pub struct DataTrack {
+    /// Track identifier.
+    pub id: u32,
+    /// Codec parameters.
+    pub params: CodecParams,
+    /// Timebase of the samples.
+    pub timebase: Rational64,
+    /// Samples of the track.
+    pub samples: Vec<Sample>,
+}
+
+/// Reads a null-terminated string, advancing `data`.
+fn get_cstr(data: &mut &[u8]) -> Result<String> {
+    let len = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(Error::InvalidData)?;
+    let s = String::from_utf8_lossy(&data[..len]).into_owned();
+    *data = &data[len + 1..];
+    Ok(s)
+}
+
+impl DataTrack {
+    /// Parses the payload of a track box, returning `None` if it is not a
+    /// data track.
+    pub fn parse(trak: &[u8], limits: &Limits) -> Result<Option<Self>> {
+        let id = parse_trak(trak)?.id;
+        let boxes = children(trak)?;
+        let mdia = children(find(&boxes, b"mdia").ok_or(Error::InvalidData)?)?;
+        let minf = children(find(&mdia, b"minf").ok_or(Error::InvalidData)?)?;
+        let stbl = find(&minf, b"stbl").ok_or(Error::InvalidData)?;
+
+        let stsd = find(&children(stbl)?, b"stsd").ok_or(Error::InvalidData)?;
+        let (_, _, stsd) = full_box(stsd)?;
+        let (kind, entry, _) = stsd
+            .get(4..)
+            .and_then(|entries| read_box(entries, 0).ok())
+            .ok_or(Error::InvalidData)?;
+        // Reserved bytes and data reference index.
+        let mut entry = entry.get(8..).ok_or(Error::InvalidData)?;
+        let info = match &kind {
+            b"gpmd" => DataInfo::default(),
+            b"mett" => {
+                let encoding = get_cstr(&mut entry)?;
+                let mime = get_cstr(&mut entry)?;
+                DataInfo {
+                    mime: Some(mime).filter(|s| !s.is_empty()),
+                    encoding: Some(encoding).filter(|s| !s.is_empty()),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        let (version, _, mut mdhd) = full_box(find(&mdia, b"mdhd").ok_or(Error::InvalidData)?)?;
+        let time_size = if version == 1 { 8 } else { 4 };
+        get_sized(&mut mdhd, time_size)?;
+        get_sized(&mut mdhd, time_size)?;
+        let timescale = get_sized(&mut mdhd, 4)?;
+        if timescale == 0 {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Some(DataTrack {
+            id,
+            params: CodecParams {
+                kind: Some(MediaKind::Data(info)),
+                codec_id: Some(String::from_utf8_lossy(&kind).into_owned()),
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            },
+            timebase: Rational64::new(1, timescale as i64),
+            samples: samples(stbl, limits)?,
+        }))
+    }
+
+    /// Returns the packet of a sample, from the data of the whole file.
+    pub fn packet(&self, file: &[u8], index: usize) -> Result<Packet> {
+        let sample = self.samples.get(index).ok_or(Error::InvalidData)?;
+        let start = sample.offset as usize;
+        let data = file
+            .get(start..start + sample.size as usize)
+            .ok_or(Error::InvalidData)?;
+
+        let mut pkt = Packet::new();
+        pkt.data = data.to_vec();
+        pkt.pos = Some(start);
+        let ts = Timestamp::new(sample.dts as i64, self.timebase);
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        pkt.t.set_duration(Some(Duration::new(
+            u64::from(sample.duration),
+            self.timebase,
+        )))?;
+        pkt.is_key = true;
+        Ok(pkt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn boxes() {
+        let mut inner = Vec::new();
+        put_full_box(&mut inner, b"pitm", 0, 0, &[0, 1]);
+        put_box(&mut inner, b"free", &[]);
+        let mut data = Vec::new();
+        put_box(&mut data, b"meta", &inner);
+        data.extend_from_slice(&[0, 0, 0, 1, b'm', b'd', b'a', b't']);
+        data.extend_from_slice(&17u64.to_be_bytes());
+        data.push(42);
+
+        let (kind, payload, end) = read_box(&data, 0).unwrap();
+        assert_eq!(&kind, b"meta");
+        let boxes = children(payload).unwrap();
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(&boxes[0].0, b"pitm");
+        assert_eq!(full_box(boxes[0].1).unwrap(), (0, 0, &[0u8, 1][..]));
+        assert_eq!(read_box(&data, end).unwrap().1, [42]);
+
+        assert!(matches!(
+            read_box(&data[..end + 10], end),
+            Err(Error::MoreDataNeeded(_))
+        ));
+        assert!(children(&payload[..payload.len() - 1]).is_err());
+    }
+
+    /// Returns a track box.
+    fn trak(
+        id: u32,
+        version: u8,
+        alternate_group: u16,
+        handler: &[u8; 4],
+        extra: &[u8],
+    ) -> Vec<u8> {
+        let mut tkhd = Vec::new();
+        let time_size = if version == 1 { 8 } else { 4 };
+        tkhd.resize(2 * time_size, 0);
+        tkhd.extend_from_slice(&id.to_be_bytes());
+        tkhd.resize(tkhd.len() + 4 + time_size + 8 + 2, 0);
+        tkhd.extend_from_slice(&alternate_group.to_be_bytes());
+        tkhd.resize(tkhd.len() + 2 + 2 + 36 + 8, 0);
+
+        let mut hdlr = vec![0; 4];
+        hdlr.extend_from_slice(handler);
+        hdlr.resize(hdlr.len() + 12 + 1, 0);
+        let mut mdia = Vec::new();
+        put_full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+
+        let mut data = Vec::new();
+        put_full_box(&mut data, b"tkhd", version, 3, &tkhd);
+        put_box(&mut data, b"mdia", &mdia);
+        data.extend_from_slice(extra);
+        let mut out = Vec::new();
+        put_box(&mut out, b"trak", &data);
+        out
+    }
+
+    fn stereo(group: u32, left: bool) -> Vec<u8> {
+        let mut ster = group.to_be_bytes().to_vec();
+        ster.extend_from_slice(&(u32::from(left) << 31).to_be_bytes());
+        let mut trgr = Vec::new();
+        put_full_box(&mut trgr, b"ster", 0, 0, &ster);
+        let mut out = Vec::new();
+        put_box(&mut out, b"trgr", &trgr);
+        out
+    }
+
+    #[test]
+    fn groups() {
+        let mut sabt = Vec::new();
+        put_box(&mut sabt, b"sabt", &[0, 0, 0, 2, 0, 0, 0, 3]);
+        let mut tref = Vec::new();
+        put_box(&mut tref, b"tref", &sabt);
+
+        let mut kind = AUDIO_PURPOSE.to_vec();
+        kind.extend_from_slice(b"\x001\x00");
+        let mut udta = Vec::new();
+        put_full_box(&mut udta, b"kind", 0, 0, &kind);
+        let mut description = Vec::new();
+        put_box(&mut description, b"udta", &udta);
+
+        let mut moov = Vec::new();
+        put_full_box(&mut moov, b"mvhd", 0, 0, &[0; 96]);
+        for data in &[
+            trak(1, 0, 0, b"vide", &tref),
+            trak(2, 0, 0, b"vide", &[]),
+            trak(3, 0, 0, b"vide", &[]),
+            trak(4, 0, 0, b"vide", &stereo(7, false)),
+            trak(5, 0, 0, b"vide", &stereo(7, true)),
+            trak(6, 0, 1, b"soun", &[]),
+            trak(7, 0, 1, b"soun", &description),
+            trak(8, 1, 1, b"soun", &[]),
+            trak(9, 0, 2, b"soun", &[]),
+        ] {
+            moov.extend_from_slice(data);
+        }
+
+        assert_eq!(
+            track_groups(&moov).unwrap(),
+            vec![
+                (GroupKind::Tiles, vec![1, 2, 3]),
+                (GroupKind::Stereo, vec![5, 4]),
+                (GroupKind::Alternatives, vec![6, 7, 8]),
+                (GroupKind::AudioDescription, vec![6, 8, 7]),
+            ]
+        );
+
+        assert!(track_groups(&trak(1, 0, 0, b"vide", &[])[..20]).is_err());
+    }
+
+    #[test]
+    fn stream_groups() {
+        use crate::data::metadata::Metadata;
+        use crate::data::params::CodecParams;
+        use crate::rational::Rational64;
+        use crate::stream::Stream;
+
+        let mut moov = Vec::new();
+        for data in &[
+            trak(4, 0, 0, b"vide", &stereo(7, false)),
+            trak(5, 0, 0, b"vide", &stereo(7, true)),
+            trak(6, 0, 1, b"soun", &[]),
+            trak(7, 0, 1, b"soun", &[]),
+        ] {
+            moov.extend_from_slice(data);
+        }
+
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        let params = CodecParams {
+            kind: None,
+            codec_id: None,
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+        for &id in &[4, 5, 6] {
+            let mut st = Stream::from_params(&params, Rational64::new(1, 1000));
+            st.id = id;
+            info.add_stream(st);
+        }
+        add_track_groups(&mut info, &moov).unwrap();
+
+        let stereo = StreamGroup {
+            id: 0,
+            kind: GroupKind::Stereo,
+            streams: vec![1, 0],
+        };
+        assert_eq!(info.groups, vec![stereo.clone()]);
+        assert_eq!(info.groups_of(0).collect::<Vec<_>>(), vec![&stereo]);
+        assert_eq!(info.groups_of(2).count(), 0);
+    }
+
+    /// Returns the payload of a data track box with samples of 4, 5 and 6
+    /// bytes in two chunks, at 100 and 200.
+    fn data_trak(entry: &[u8]) -> Vec<u8> {
+        let mut stbl = Vec::new();
+        put_full_box(&mut stbl, b"stsd", 0, 0, &[&[0, 0, 0, 1], entry].concat());
+        put_full_box(
+            &mut stbl,
+            b"stts",
+            0,
+            0,
+            &[0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 3, 0xe9],
+        );
+        put_full_box(
+            &mut stbl,
+            b"stsz",
+            0,
+            0,
+            &[0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 6],
+        );
+        let mut stsc = vec![0, 0, 0, 2];
+        for v in &[1u32, 2, 1, 2, 1, 1] {
+            stsc.extend_from_slice(&v.to_be_bytes());
+        }
+        put_full_box(&mut stbl, b"stsc", 0, 0, &stsc);
+        put_full_box(
+            &mut stbl,
+            b"stco",
+            0,
+            0,
+            &[0, 0, 0, 2, 0, 0, 0, 100, 0, 0, 0, 200],
+        );
+        let mut minf = Vec::new();
+        put_box(&mut minf, b"stbl", &stbl);
+
+        let mut mdhd = vec![0; 8];
+        mdhd.extend_from_slice(&1000u32.to_be_bytes());
+        mdhd.resize(20, 0);
+        let trak = trak(3, 0, 0, b"meta", &[]);
+        let mut data = Vec::new();
+        for (kind, payload) in children(read_box(&trak, 0).unwrap().1).unwrap() {
+            let mut payload = payload.to_vec();
+            if &kind == b"mdia" {
+                put_full_box(&mut payload, b"mdhd", 0, 0, &mdhd);
+                put_box(&mut payload, b"minf", &minf);
+            }
+            put_box(&mut data, &kind, &payload);
+        }
+        data
+    }
+
+    fn sample_entry(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        put_box(
+            &mut entry,
+            kind,
+            &[&[0, 0, 0, 0, 0, 0, 0, 1], payload].concat(),
+        );
+        entry
+    }
+
+    #[test]
+    fn data_tracks() {
+        let limits = Limits::default();
+        let track = DataTrack::parse(&data_trak(&sample_entry(b"gpmd", &[])), &limits)
+            .unwrap()
+            .unwrap();
+        assert_eq!(track.id, 3);
+        assert_eq!(track.params.codec_id.as_deref(), Some("gpmd"));
+        assert_eq!(track.timebase, Rational64::new(1, 1000));
+        let samples: Vec<_> = track
+            .samples
+            .iter()
+            .map(|s| (s.offset, s.size, s.dts, s.duration))
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (100, 4, 0, 1001),
+                (104, 5, 1001, 1001),
+                (200, 6, 2002, 1001)
+            ]
+        );
+
+        let file: Vec<u8> = (0..=255).collect();
+        let pkt = track.packet(&file, 1).unwrap();
+        assert_eq!(pkt.data, [104, 105, 106, 107, 108]);
+        assert_eq!(
+            (
+                pkt.t.get_pts().map(Timestamp::get_value),
+                pkt.t.get_duration().map(Duration::get_value)
+            ),
+            (Some(1001), Some(1001))
+        );
+        assert!(track.packet(&file[..203], 2).is_err());
+
+        let entry = sample_entry(b"mett", b"gzip\0application/json\0");
+        let track = DataTrack::parse(&data_trak(&entry), &limits)
+            .unwrap()
+            .unwrap();
+        let info = DataInfo {
+            mime: Some("application/json".to_owned()),
+            encoding: Some("gzip".to_owned()),
+        };
+        assert_eq!(track.params.kind, Some(MediaKind::Data(info)));
+
+        let entry = sample_entry(b"avc1", &[]);
+        assert!(DataTrack::parse(&data_trak(&entry), &limits)
+            .unwrap()
+            .is_none());
+
+        let limits = Limits {
+            max_index_entries: 2,
+            ..Limits::default()
+        };
+        let entry = sample_entry(b"gpmd", &[]);
+        assert!(matches!(
+            DataTrack::parse(&data_trak(&entry), &limits),
+            Err(Error::LimitExceeded(_))
+        ));
+    }
+    #[test]
+    fn rolls() {
+        let mut stbl = Vec::new();
+        put_roll_group(&mut stbl, -2, 3, false);
+        let track = roll_groups(&stbl).unwrap();
+        assert_eq!(track, [-2]);
+        assert_eq!(sample_rolls(&stbl, &track, 4).unwrap(), [-2, -2, -2, 0]);
+        assert_eq!(sample_rolls(&stbl, &track, 2).unwrap(), [-2, -2]);
+
+        // Descriptions of their own length, local to a fragment.
+        let mut traf = Vec::new();
+        let mut sgpd = b"roll".to_vec();
+        for v in &[0u32, 2, 4, 0xffff_0000, 4, 0xfffc_0000] {
+            sgpd.extend_from_slice(&v.to_be_bytes());
+        }
+        put_full_box(&mut traf, b"sgpd", 1, 0, &sgpd);
+        let mut sbgp = b"roll".to_vec();
+        for v in &[3u32, 1, 0, 1, 1, 2, FRAGMENT_GROUPS + 2] {
+            sbgp.extend_from_slice(&v.to_be_bytes());
+        }
+        put_full_box(&mut traf, b"sbgp", 0, 0, &sbgp);
+        assert_eq!(roll_groups(&traf).unwrap(), [-1, -4]);
+        assert_eq!(sample_rolls(&traf, &track, 5).unwrap(), [0, -2, -4, -4, 0]);
+        assert!(sample_rolls(&traf, &[], 5).is_err());
+        assert_eq!(sample_rolls(&[], &track, 2).unwrap(), [0, 0]);
+
+        let samples: Vec<_> = [0, -1, -1, -3]
+            .iter()
+            .map(|&roll| Sample {
+                offset: 0,
+                size: 1,
+                dts: 0,
+                duration: 1024,
+                roll,
+            })
+            .collect();
+        assert_eq!(preroll_start(&samples, 0), 0);
+        assert_eq!(preroll_start(&samples, 2), 1);
+        assert_eq!(preroll_start(&samples, 3), 0);
+
+        assert_eq!(codec_roll_distance("aac", 1024), Some(-1));
+        assert_eq!(codec_roll_distance("opus", 960), Some(-4));
+        assert_eq!(codec_roll_distance("opus", 1000), Some(-4));
+        assert_eq!(codec_roll_distance("pcm_s16le", 1), None);
+    }
+
+    #[test]
+    fn channel_layouts() {
+        use crate::data::audiosample::ChannelType::*;
+
+        // 5.1 without its LFE channel.
+        let mut chnl = Vec::new();
+        put_full_box(&mut chnl, b"chnl", 0, 0, &[1, 6, 0, 0, 0, 0, 0, 0, 0, 0x20]);
+        let (_, payload, _) = read_box(&chnl, 0).unwrap();
+        let layout = parse_chnl(payload, 5).unwrap();
+        let map = layout.to_map().unwrap();
+        assert_eq!(
+            (0..map.len())
+                .map(|i| map.get_channel(i))
+                .collect::<Vec<_>>(),
+            [C, L, R, Ls, Rs]
+        );
+        assert_eq!(layout.get_mask(), None);
+        assert!(parse_chnl(payload, 6).is_err());
+
+        let positions = [0, 0, 0, 0, 1, 0, 0, 1, 126, 0, 30, 0];
+        let layout = parse_chnl(&positions[..8], 2).unwrap();
+        assert_eq!(layout, ChannelLayout::STEREO);
+        assert_eq!(
+            parse_chnl(&positions, 3).unwrap(),
+            ChannelLayout::Unspecified(3)
+        );
+        assert_eq!(
+            parse_chnl(&[0, 0, 0, 0, 2, 4], 4).unwrap(),
+            ChannelLayout::Unspecified(4)
+        );
+
+        let mut sa3d = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 6];
+        for i in 0..6u32 {
+            sa3d.extend_from_slice(&i.to_be_bytes());
+        }
+        let layout = parse_sa3d(&sa3d).unwrap();
+        assert_eq!(
+            layout,
+            ChannelLayout::Ambisonic {
+                order: 1,
+                mask: 0x3
+            }
+        );
+        assert_eq!(layout.count(), 6);
+        sa3d[11] = 5;
+        assert!(parse_sa3d(&sa3d[..sa3d.len() - 4]).is_err());
+    }
+}
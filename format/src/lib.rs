@@ -1,8 +1,13 @@
 // crates
+#[cfg(not(feature = "tracing"))]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
 
 // local crates
+extern crate av_bitstream;
 extern crate av_data;
 
 mod data {
@@ -11,9 +16,34 @@ mod data {
 
 pub use av_data::rational;
 
+pub mod aiff;
+pub mod apng;
+pub mod au;
+pub mod avif;
+pub mod bsf;
 pub mod buffer;
+pub mod caf;
+pub mod cancel;
+mod canvas;
 pub mod common;
 pub mod demuxer;
+pub mod dv;
+pub mod emsg;
 pub mod error;
+pub mod gif;
+pub mod gxf;
+pub mod id3;
+mod inflate;
+pub mod isobmff;
+pub mod limits;
+pub mod mpegps;
 pub mod muxer;
+pub mod mxf;
+pub mod nut;
+pub mod ogg;
+pub mod parsers;
+mod pcm;
+pub mod raw;
+pub mod replay;
 pub mod stream;
+pub mod webp;
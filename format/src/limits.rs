@@ -0,0 +1,134 @@
+//!
+//! Resource limits for untrusted inputs.
+//!
+//! The demuxing `Context` enforces the limits on the streams, packets and
+//! metadata of every demuxer, the buffer never growing past the largest of
+//! the packet and metadata limits. The index and nesting limits are
+//! enforced by the demuxers keeping index tables or walking nested
+//! structures.
+//!
+//! The default limits are unbounded.
+//!
+
+use crate::data::metadata::{MetaValue, Metadata};
+use crate::error::*;
+
+/// Resource limits of a demuxing context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of streams.
+    pub max_streams: usize,
+    /// Maximum size of a packet, in bytes.
+    pub max_packet_size: usize,
+    /// Maximum number of index entries kept by a demuxer.
+    pub max_index_entries: usize,
+    /// Maximum size of the global metadata, in bytes.
+    pub max_metadata_size: usize,
+    /// Maximum nesting depth of the structures walked by a demuxer.
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_streams: usize::MAX,
+            max_packet_size: usize::MAX,
+            max_index_entries: usize::MAX,
+            max_metadata_size: usize::MAX,
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+fn check(value: usize, max: usize, what: &'static str) -> Result<()> {
+    if value > max {
+        Err(Error::LimitExceeded(what))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the size of metadata, keys included.
+pub fn metadata_size(metadata: &Metadata) -> usize {
+    metadata
+        .iter()
+        .map(|(key, val)| {
+            key.len()
+                + match val {
+                    MetaValue::Str(s) => s.len(),
+                    MetaValue::Pair(..) => 16,
+                    _ => 8,
+                }
+        })
+        .sum()
+}
+
+impl Limits {
+    /// Checks the number of streams.
+    pub fn check_streams(&self, streams: usize) -> Result<()> {
+        check(streams, self.max_streams, "streams")
+    }
+
+    /// Checks the size of a packet.
+    pub fn check_packet_size(&self, size: usize) -> Result<()> {
+        check(size, self.max_packet_size, "packet size")
+    }
+
+    /// Checks the number of index entries.
+    pub fn check_index_entries(&self, entries: usize) -> Result<()> {
+        check(entries, self.max_index_entries, "index entries")
+    }
+
+    /// Checks the size of metadata.
+    pub fn check_metadata(&self, metadata: &Metadata) -> Result<()> {
+        check(
+            metadata_size(metadata),
+            self.max_metadata_size,
+            "metadata size",
+        )
+    }
+
+    /// Checks a nesting depth.
+    pub fn check_depth(&self, depth: usize) -> Result<()> {
+        check(depth, self.max_depth, "nesting depth")
+    }
+
+    /// Checks the size requested for the buffer.
+    pub fn check_buffer_size(&self, size: usize) -> Result<()> {
+        check(
+            size,
+            self.max_packet_size.max(self.max_metadata_size),
+            "buffer size",
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks() {
+        let limits = Limits {
+            max_streams: 2,
+            max_packet_size: 100,
+            max_metadata_size: 10,
+            ..Limits::default()
+        };
+        limits.check_streams(2).unwrap();
+        assert!(matches!(
+            limits.check_streams(3),
+            Err(Error::LimitExceeded("streams"))
+        ));
+        limits.check_buffer_size(100).unwrap();
+        assert!(limits.check_buffer_size(101).is_err());
+        limits.check_index_entries(usize::MAX).unwrap();
+
+        let mut metadata = Metadata::new();
+        metadata.insert("title", "abcd");
+        assert_eq!(metadata_size(&metadata), 9);
+        limits.check_metadata(&metadata).unwrap();
+        metadata.insert("year", 2020u64);
+        assert!(limits.check_metadata(&metadata).is_err());
+    }
+}
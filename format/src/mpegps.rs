@@ -0,0 +1,673 @@
+//!
+//! MPEG program stream demuxer (ISO/IEC 13818-1), as found in VOB and MPG
+//! files.
+//!
+//! The PES payloads are exposed as packets, unparsed. The streams starting
+//! in the data buffered when the headers are read are known upfront, the
+//! later ones are announced by `Event::NewStream`.
+//!
+//! The private stream packets holding an ID3 tag, without substream id,
+//! are exposed as a timed ID3 stream.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+use std::time::Duration;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{AudioInfo, CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::Timestamp;
+use crate::demuxer::{self, need, Demuxer, Discard, Event};
+use crate::error::*;
+use crate::id3;
+use crate::mxf::{mpeg2_is_intra, Mpeg2Fields};
+use crate::pcm;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Start codes.
+const PACK_HEADER: u8 = 0xba;
+const PROGRAM_END: u8 = 0xb9;
+const PRIVATE_STREAM_1: u8 = 0xbd;
+
+/// Sample rates of the DVD LPCM streams.
+const LPCM_RATES: [usize; 4] = [48000, 96000, 44100, 32000];
+
+/// Unit of the program stream.
+enum Unit<'a> {
+    /// Data to skip, pack and system headers included.
+    Skip(usize),
+    /// Program end code.
+    End,
+    Pes(Pes<'a>),
+}
+
+/// Packetized elementary stream packet.
+struct Pes<'a> {
+    stream_id: u8,
+    pts: Option<i64>,
+    dts: Option<i64>,
+    payload: &'a [u8],
+    size: usize,
+}
+
+/// Reads a 33 bit timestamp.
+fn get_ts(data: &[u8]) -> i64 {
+    (i64::from((data[0] >> 1) & 7) << 30)
+        | (i64::from(get_u16b(&data[1..]) >> 1) << 15)
+        | i64::from(get_u16b(&data[3..]) >> 1)
+}
+
+/// Parses the PES packet header following the packet length.
+fn read_pes(data: &[u8], size: usize) -> Result<Pes<'_>> {
+    let stream_id = data[3];
+    let mut pts = None;
+    let mut dts = None;
+
+    let start = if data.get(6).is_some_and(|b| b & 0xc0 == 0x80) {
+        // MPEG-2 header.
+        if size < 9 {
+            return Err(Error::InvalidData);
+        }
+        let flags = data[7];
+        let start = 9 + data[8] as usize;
+        if flags & 0x80 != 0 && start >= 14 {
+            pts = Some(get_ts(&data[9..]));
+            if flags & 0x40 != 0 && start >= 19 {
+                dts = Some(get_ts(&data[14..]));
+            }
+        }
+        start
+    } else {
+        // MPEG-1 header, after its stuffing bytes.
+        let mut p = 6;
+        while p < size && data[p] == 0xff {
+            p += 1;
+        }
+        if p < size && data[p] & 0xc0 == 0x40 {
+            p += 2;
+        }
+        match data.get(p).map(|b| b & 0xf0) {
+            Some(0x20) if p + 5 <= size => {
+                pts = Some(get_ts(&data[p..]));
+                p + 5
+            }
+            Some(0x30) if p + 10 <= size => {
+                pts = Some(get_ts(&data[p..]));
+                dts = Some(get_ts(&data[p + 5..]));
+                p + 10
+            }
+            _ if data.get(p) == Some(&0x0f) => p + 1,
+            _ => return Err(Error::InvalidData),
+        }
+    };
+    if start > size {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(Pes {
+        stream_id,
+        pts,
+        dts,
+        payload: &data[start..size],
+        size,
+    })
+}
+
+/// Reads the unit at the start of `data`.
+fn read_unit(data: &[u8]) -> Result<Unit<'_>> {
+    need(data, 4)?;
+    if data[..3] != [0, 0, 1] {
+        // Resynchronize on the next start code.
+        let skip = data
+            .windows(3)
+            .position(|w| w == [0, 0, 1])
+            .unwrap_or(data.len() - 2);
+        return Ok(Unit::Skip(skip));
+    }
+
+    match data[3] {
+        PACK_HEADER => {
+            need(data, 5)?;
+            if data[4] & 0xc0 == 0x40 {
+                need(data, 14)?;
+                Ok(Unit::Skip(14 + (data[13] & 7) as usize))
+            } else if data[4] & 0xf0 == 0x20 {
+                Ok(Unit::Skip(12))
+            } else {
+                Ok(Unit::Skip(4))
+            }
+        }
+        PROGRAM_END => Ok(Unit::End),
+        0xbb..=0xff => {
+            need(data, 6)?;
+            let size = 6 + get_u16b(&data[4..]) as usize;
+            need(data, size)?;
+            match data[3] {
+                PRIVATE_STREAM_1 | 0xc0..=0xef => Ok(Unit::Pes(read_pes(data, size)?)),
+                _ => Ok(Unit::Skip(size)),
+            }
+        }
+        _ => Ok(Unit::Skip(3)),
+    }
+}
+
+/// Tells if the pack header starting the data is an MPEG-1 one.
+fn is_mpeg1(data: &[u8]) -> bool {
+    data.get(4).is_some_and(|b| b & 0xf0 == 0x20)
+}
+
+/// Elementary stream of a PES packet.
+struct Elementary<'a> {
+    /// Stream id, followed by the substream id for private streams.
+    key: u16,
+    payload: &'a [u8],
+    params: Option<CodecParams>,
+}
+
+fn audio_params(codec_id: &str) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Audio(AudioInfo {
+            rate: 0,
+            map: None,
+            format: None,
+        })),
+        codec_id: Some(codec_id.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// Identifies the elementary stream of a PES packet, the codec parameters
+/// are `None` for the unsupported ones.
+fn elementary<'a>(pes: &Pes<'a>, mpeg1: bool) -> Result<Elementary<'a>> {
+    let id = pes.stream_id;
+    let (key, payload, params) = match id {
+        0xe0..=0xef => {
+            let params = CodecParams {
+                kind: Some(MediaKind::Video(VideoInfo {
+                    width: 0,
+                    height: 0,
+                    format: None,
+                })),
+                codec_id: Some(if mpeg1 { "mpeg1video" } else { "mpeg2video" }.to_owned()),
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            };
+            (u16::from(id), pes.payload, Some(params))
+        }
+        0xc0..=0xdf => (u16::from(id), pes.payload, Some(audio_params("mp2"))),
+        PRIVATE_STREAM_1 if id3::is_id3(pes.payload) => {
+            let key = u16::from(id) << 8 | u16::from(b'I');
+            (key, pes.payload, Some(id3::params()))
+        }
+        _ => {
+            let sub = *pes.payload.first().ok_or(Error::InvalidData)?;
+            let (header, params) = match sub {
+                0x20..=0x3f => {
+                    let params = CodecParams {
+                        kind: None,
+                        codec_id: Some("dvd_subtitle".to_owned()),
+                        extradata: None,
+                        bit_rate: 0,
+                        convergence_window: 0,
+                        delay: 0,
+                    };
+                    (1, Some(params))
+                }
+                0x80..=0x87 => (4, Some(audio_params("ac3"))),
+                0x88..=0x8f => (4, Some(audio_params("dts"))),
+                0xa0..=0xaf if pes.payload.len() >= 7 => {
+                    let b = pes.payload[5];
+                    // Only 16 bit samples are stored as plain PCM.
+                    let params = if b >> 6 == 0 {
+                        let codec_id = "pcm_s16be";
+                        let rate = LPCM_RATES[((b >> 4) & 3) as usize];
+                        let map = pcm::channel_map((b & 7) as usize + 1)?;
+                        let format = pcm::sample_format(codec_id);
+                        Some(pcm::audio_params(codec_id.to_owned(), rate, map, format))
+                    } else {
+                        None
+                    };
+                    (7, params)
+                }
+                _ => (1, None),
+            };
+            let payload = pes.payload.get(header..).ok_or(Error::InvalidData)?;
+            (u16::from(id) << 8 | u16::from(sub), payload, params)
+        }
+    };
+
+    Ok(Elementary {
+        key,
+        payload,
+        params,
+    })
+}
+
+/// MPEG program stream demuxer.
+#[derive(Default)]
+pub struct PsDemuxer {
+    /// Elementary stream keys, by stream index.
+    streams: Vec<u16>,
+    /// Field structure parsers, by stream index.
+    fields: Vec<Mpeg2Fields>,
+    discard: Vec<Discard>,
+    mpeg1: bool,
+}
+
+impl PsDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn new_stream(&mut self, key: u16, params: &CodecParams) -> Stream {
+        let mut st = Stream::from_params(params, Rational64::new(1, 90000));
+        st.id = key as isize;
+        st.index = self.streams.len();
+        self.streams.push(key);
+        self.fields.push(Mpeg2Fields::default());
+        st
+    }
+}
+
+impl Demuxer for PsDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, 5)?;
+        if data[..4] != [0, 0, 1, PACK_HEADER] {
+            return Err(Error::InvalidData);
+        }
+        self.streams.clear();
+        self.mpeg1 = is_mpeg1(data);
+
+        // Look for the streams in the buffered data, the packets are left
+        // to read_event.
+        let mut pos = 0;
+        while let Ok(unit) = read_unit(&data[pos..]) {
+            match unit {
+                Unit::Skip(size) if size > 0 => pos += size,
+                Unit::Pes(pes) => {
+                    pos += pes.size;
+                    let es = match elementary(&pes, self.mpeg1) {
+                        Ok(es) => es,
+                        Err(_) => continue,
+                    };
+                    if let Some(params) = es.params {
+                        if !self.streams.contains(&es.key) {
+                            let st = self.new_stream(es.key, &params);
+                            info.streams.push(st);
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        Ok(SeekFrom::Current(0))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let data = buf.data();
+        let pes = match read_unit(data)? {
+            Unit::Skip(0) => return Err(Error::MoreDataNeeded(data.len() + 1)),
+            Unit::Skip(size) => return Ok((SeekFrom::Current(size as i64), Event::Continue)),
+            Unit::End => return Ok((SeekFrom::Current(4), Event::Eof)),
+            Unit::Pes(pes) => pes,
+        };
+        let skip = Ok((SeekFrom::Current(pes.size as i64), Event::Continue));
+        let es = match elementary(&pes, self.mpeg1) {
+            Ok(es) => es,
+            Err(_) => return skip,
+        };
+        let params = match es.params {
+            Some(params) => params,
+            None => return skip,
+        };
+        let key = es.key;
+        let index = match self.streams.iter().position(|&k| k == key) {
+            Some(index) => index,
+            None => {
+                let st = self.new_stream(key, &params);
+                return Ok((SeekFrom::Current(0), Event::NewStream(st)));
+            }
+        };
+
+        let discard = self.discard.get(index).copied().unwrap_or_default();
+        if discard == Discard::All {
+            return skip;
+        }
+        let is_key = match params.kind {
+            Some(MediaKind::Video(_)) => mpeg2_is_intra(es.payload),
+            _ => true,
+        };
+        if discard.drops(is_key) {
+            return skip;
+        }
+
+        let mut pkt = Packet::with_capacity(es.payload.len());
+        pkt.data.extend_from_slice(es.payload);
+        pkt.stream_index = index as isize;
+        pkt.is_key = is_key;
+        if let Some(MediaKind::Video(_)) = params.kind {
+            pkt.fields = self.fields[index].parse(es.payload);
+        }
+        let timebase = Rational64::new(1, 90000);
+        pkt.t
+            .set_pts(pes.pts.map(|pts| Timestamp::new(pts, timebase)))?;
+        pkt.t
+            .set_dts(pes.dts.or(pes.pts).map(|dts| Timestamp::new(dts, timebase)))?;
+
+        Ok((SeekFrom::Current(pes.size as i64), Event::NewPacket(pkt)))
+    }
+
+    fn set_discard(&mut self, discard: &[Discard]) {
+        self.discard = discard.to_vec();
+    }
+
+    fn analyze_duration(&self) -> Duration {
+        // The streams starting after the buffered data are only found
+        // while reading the packets.
+        Duration::from_secs(1)
+    }
+}
+
+struct PsDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for PsDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(PsDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        let pack = data.len() >= 5 && data[..4] == [0, 0, 1, PACK_HEADER];
+        if pack && (is_mpeg1(data) || data[4] & 0xc0 == 0x40) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// MPEG program stream demuxer descriptor.
+pub const PS_DESCR: &dyn demuxer::Descriptor = &PsDescr {
+    d: demuxer::Descr {
+        name: "mpegps",
+        demuxer: "mpegps",
+        description: "MPEG Program Stream",
+        extensions: &["mpg", "mpeg", "vob"],
+        mime: &["video/mpeg"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::frame::{Field, FieldInfo};
+    use crate::demuxer::{Context, ProbeOptions};
+    use std::io::Cursor;
+
+    fn ts(marker: u8, ts: i64) -> [u8; 5] {
+        [
+            marker << 4 | ((ts >> 29) & 0x0e) as u8 | 1,
+            (ts >> 22) as u8,
+            ((ts >> 14) & 0xfe) as u8 | 1,
+            (ts >> 7) as u8,
+            ((ts << 1) & 0xfe) as u8 | 1,
+        ]
+    }
+
+    fn pack(file: &mut Vec<u8>) {
+        file.extend_from_slice(&[
+            0,
+            0,
+            1,
+            PACK_HEADER,
+            0x44,
+            0,
+            4,
+            0,
+            4,
+            1,
+            1,
+            0x89,
+            0xc3,
+            0xf8,
+        ]);
+    }
+
+    fn pes(file: &mut Vec<u8>, id: u8, pts: Option<i64>, dts: Option<i64>, payload: &[u8]) {
+        let mut header = vec![0x80, 0, 0];
+        if let Some(pts) = pts {
+            header[1] |= 0x80;
+            header.extend_from_slice(&ts(if dts.is_some() { 3 } else { 2 }, pts));
+        }
+        if let Some(dts) = dts {
+            header[1] |= 0x40;
+            header.extend_from_slice(&ts(1, dts));
+        }
+        header[2] = (header.len() - 3) as u8;
+        file.extend_from_slice(&[0, 0, 1, id]);
+        file.extend_from_slice(&((header.len() + payload.len()) as u16).to_be_bytes());
+        file.extend_from_slice(&header);
+        file.extend_from_slice(payload);
+    }
+
+    fn ps_file() -> Vec<u8> {
+        let mut file = Vec::new();
+        pack(&mut file);
+        // System header and padding stream.
+        file.extend_from_slice(&[0, 0, 1, 0xbb, 0, 3, 1, 2, 3]);
+        file.extend_from_slice(&[0, 0, 1, 0xbe, 0, 2, 0xff, 0xff]);
+        let picture = [0, 0, 1, 0, 0, 0x08, 0, 0];
+        pes(&mut file, 0xe0, Some(7200), Some(3600), &picture);
+        pes(&mut file, 0xc0, Some(3600), None, &[0xff, 0xfd, 0, 0]);
+        pes(
+            &mut file,
+            PRIVATE_STREAM_1,
+            Some(3600),
+            None,
+            &[0x80, 1, 0, 1, 0x0b, 0x77],
+        );
+        pes(&mut file, PRIVATE_STREAM_1, None, None, &[0xff, 1, 2]);
+        pack(&mut file);
+        let lpcm = [0xa0, 1, 0, 4, 0, 0x01, 0x80, 0x12, 0x34, 0x56, 0x78];
+        pes(&mut file, PRIVATE_STREAM_1, Some(3600), None, &lpcm);
+        // A bottom field picture.
+        let picture = [
+            0, 0, 1, 0, 0, 0x10, 0, 0, 0, 0, 1, 0xb5, 0x8f, 0xff, 0xf2, 0, 0,
+        ];
+        pes(&mut file, 0xe0, Some(10800), None, &picture);
+        file.extend_from_slice(&[0, 0, 1, PROGRAM_END]);
+        file
+    }
+
+    fn demux(file: Vec<u8>, capacity: usize) -> (Context, Vec<Event>) {
+        let r = AccReader::with_capacity(capacity, Cursor::new(file));
+        let mut c = Context::new(PS_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            match c.read_event().unwrap() {
+                Event::Continue => {}
+                Event::Eof => break,
+                event => events.push(event),
+            }
+        }
+        (c, events)
+    }
+
+    #[test]
+    fn demux_all() {
+        let file = ps_file();
+        assert_eq!(PS_DESCR.probe(&file), 100);
+
+        let (c, events) = demux(file, 4096);
+        let codecs: Vec<_> = c
+            .info
+            .streams
+            .iter()
+            .map(|st| (st.id, st.params.codec_id.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            codecs,
+            [
+                (0xe0, "mpeg2video"),
+                (0xc0, "mp2"),
+                (0xbd80, "ac3"),
+                (0xbda0, "pcm_s16be")
+            ]
+        );
+        match c.info.streams[3].params.kind {
+            Some(MediaKind::Audio(ref audio)) => {
+                assert_eq!(audio.rate, 48000);
+                assert_eq!(audio.map.as_ref().map(|map| map.len()), Some(2));
+            }
+            _ => panic!("Not an audio stream"),
+        }
+
+        let packets: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Event::NewPacket(pkt) => (
+                    pkt.stream_index,
+                    pkt.t.get_pts().map(Timestamp::get_value),
+                    pkt.t.get_dts().map(Timestamp::get_value),
+                    pkt.is_key,
+                ),
+                _ => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            packets,
+            [
+                (0, Some(7200), Some(3600), true),
+                (1, Some(3600), Some(3600), true),
+                (2, Some(3600), Some(3600), true),
+                (3, Some(3600), Some(3600), true),
+                (0, Some(10800), Some(10800), false),
+            ]
+        );
+        match &events[3] {
+            Event::NewPacket(pkt) => assert_eq!(pkt.data, [0x12, 0x34, 0x56, 0x78]),
+            _ => unreachable!(),
+        }
+        match (&events[0], &events[4]) {
+            (Event::NewPacket(first), Event::NewPacket(last)) => {
+                assert_eq!(first.fields, None);
+                assert_eq!(last.fields, Some(FieldInfo::single(Field::Bottom)));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn late_streams() {
+        let (c, events) = demux(ps_file(), 16);
+        assert_eq!(c.info.streams.len(), 4);
+
+        // The analysis finds the streams missing from the buffered data.
+        let r = AccReader::with_capacity(16, Cursor::new(ps_file()));
+        let mut opened = Context::new(PS_DESCR.create(), Box::new(r));
+        opened.read_headers().unwrap();
+        assert_eq!(opened.info.streams.len(), 4);
+        let r = AccReader::with_capacity(16, Cursor::new(ps_file()));
+        let mut opened = Context::new(PS_DESCR.create(), Box::new(r));
+        opened.set_probe_options(ProbeOptions {
+            analyze_duration: Some(Duration::ZERO),
+            ..ProbeOptions::default()
+        });
+        opened.read_headers().unwrap();
+        assert!(opened.info.streams.is_empty());
+
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Event::NewStream(st) => (true, st.index as isize),
+                Event::NewPacket(pkt) => (false, pkt.stream_index),
+                _ => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (true, 0),
+                (false, 0),
+                (true, 1),
+                (false, 1),
+                (true, 2),
+                (false, 2),
+                (true, 3),
+                (false, 3),
+                (false, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn discard() {
+        for &analyze in &[None, Some(Duration::ZERO)] {
+            let r = AccReader::with_capacity(4096, Cursor::new(ps_file()));
+            let mut c = Context::new(PS_DESCR.create(), Box::new(r));
+            c.set_probe_options(ProbeOptions {
+                analyze_duration: analyze,
+                ..ProbeOptions::default()
+            });
+            c.read_headers().unwrap();
+            c.set_discard(0, Discard::NonKey);
+            c.set_discard(1, Discard::All);
+            c.set_discard(2, Discard::All);
+            assert_eq!(c.get_discard(2), Discard::All);
+            assert_eq!(c.get_discard(3), Discard::Default);
+
+            let mut packets = Vec::new();
+            loop {
+                match c.read_event().unwrap() {
+                    Event::NewPacket(pkt) => {
+                        packets.push((pkt.stream_index, pkt.t.get_pts().map(Timestamp::get_value)))
+                    }
+                    Event::Eof => break,
+                    _ => {}
+                }
+            }
+            assert_eq!(packets, [(0, Some(7200)), (3, Some(3600))]);
+        }
+    }
+
+    #[test]
+    fn timed_id3() {
+        let tag = id3::test::tag().to_bytes().unwrap();
+        let mut file = Vec::new();
+        pack(&mut file);
+        pes(&mut file, PRIVATE_STREAM_1, Some(3600), None, &tag);
+        file.extend_from_slice(&[0, 0, 1, PROGRAM_END]);
+
+        let (c, events) = demux(file, 4096);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some(id3::CODEC_ID));
+        match &events[0] {
+            Event::NewPacket(pkt) => {
+                assert_eq!(pkt.t.get_pts().map(Timestamp::get_value), Some(3600));
+                let (parsed, _) = id3::Tag::parse(&pkt.data).unwrap();
+                assert_eq!(parsed.get_text(b"TIT2").as_deref(), Some("Live"));
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+}
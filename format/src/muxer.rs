@@ -1,9 +1,15 @@
+use crate::bsf::{self, DumpExtradata, ExtractExtradata, Filter};
 use crate::common::*;
+use crate::data::budget::{Budget, Held};
+use crate::data::options::Options;
 use crate::data::packet::Packet;
 use crate::data::value::*;
 use std::any::Any;
-use std::io::Write;
-use std::sync::Arc;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
 use crate::error::*;
 
@@ -28,6 +34,134 @@ pub trait Muxer: Send {
     /// This method should be called as many times as the number of options
     /// present in a muxer.
     fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()>;
+
+    /// Returns the serializer of the packets of a configured muxer, if
+    /// their data does not depend on the packets preceding them.
+    fn serializer(&self) -> Option<Arc<dyn Serializer>> {
+        None
+    }
+    /// Writes a packet built by the serializer of a muxer into a data
+    /// structure implementing the `Write` trait.
+    ///
+    /// The packets are written in the order they were given to the context.
+    fn write_serialized(
+        &mut self,
+        _out: &mut dyn Write,
+        _pkt: Arc<Packet>,
+        _data: Vec<u8>,
+    ) -> Result<()> {
+        Err(Error::Unsupported("serialized packets".to_owned()))
+    }
+}
+
+/// Used to build the data of packets apart from their muxer, on worker
+/// threads.
+pub trait Serializer: Send + Sync {
+    /// Builds the data of a packet, headers and checksums included.
+    fn serialize(&self, pkt: &Packet) -> Result<Vec<u8>>;
+}
+
+/// Packets in flight per worker thread.
+const QUEUED_PER_THREAD: usize = 4;
+
+/// Packet along with its serialized data.
+type Serialized = (Arc<Packet>, Result<Vec<u8>>);
+
+/// Worker threads serializing packets, their data committed in order.
+struct Workers {
+    jobs: Option<Sender<(u64, Arc<Packet>)>>,
+    results: Receiver<(u64, Result<Vec<u8>>)>,
+    threads: Vec<JoinHandle<()>>,
+    /// Packets not committed yet, the first one being `next`.
+    queued: VecDeque<Held<Arc<Packet>>>,
+    next: u64,
+    done: BTreeMap<u64, Result<Vec<u8>>>,
+}
+
+impl Workers {
+    fn new(serializer: Arc<dyn Serializer>, threads: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<(u64, Arc<Packet>)>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (sender, results) = mpsc::channel();
+        let threads = (0..threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let sender = sender.clone();
+                let serializer = serializer.clone();
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    let (seq, pkt) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    if sender.send((seq, serializer.serialize(&pkt))).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Workers {
+            jobs: Some(jobs),
+            results,
+            threads,
+            queued: VecDeque::new(),
+            next: 0,
+            done: BTreeMap::new(),
+        }
+    }
+
+    /// Tells if enough packets are in flight to wait for them.
+    fn full(&self) -> bool {
+        self.queued.len() >= self.threads.len() * QUEUED_PER_THREAD
+    }
+
+    fn send(&mut self, pkt: Held<Arc<Packet>>) {
+        let seq = self.next + self.queued.len() as u64;
+        if let Some(ref jobs) = self.jobs {
+            // The workers only stop once the sender is dropped.
+            let _ = jobs.send((seq, (*pkt).clone()));
+        }
+        self.queued.push_back(pkt);
+    }
+
+    /// Returns the next packet to commit along with its data, if it is
+    /// serialized or if `wait` is set.
+    fn next(&mut self, wait: bool) -> Result<Option<Serialized>> {
+        if self.queued.is_empty() {
+            return Ok(None);
+        }
+        loop {
+            if let Some(data) = self.done.remove(&self.next) {
+                self.next += 1;
+                let pkt = self.queued.pop_front().unwrap().into_inner();
+                return Ok(Some((pkt, data)));
+            }
+            let (seq, data) = match self.results.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) if wait => match self.results.recv() {
+                    Ok(result) => result,
+                    Err(_) => return Err(Self::stopped()),
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(Self::stopped()),
+            };
+            self.done.insert(seq, data);
+        }
+    }
+
+    fn stopped() -> Error {
+        Error::Io(io::Error::other("serializing threads stopped"))
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
 }
 
 /// Auxiliary structure to encapsulate a muxer object and
@@ -36,12 +170,34 @@ pub struct Context {
     muxer: Box<dyn Muxer + Send>,
     writer: Box<dyn Write + Send>,
     buf: Vec<u8>,
+    caps: Option<Capabilities>,
+    /// Bitstream filters, by stream index.
+    filters: Vec<Option<StreamFilter>>,
+    /// Global information held until the extradata is extracted.
+    pending: Option<Pending>,
+    threads: usize,
+    workers: Option<Workers>,
+    budget: Budget,
     /// User private data.
     ///
     /// This data cannot be cloned.
     pub user_private: Option<Box<dyn Any + Send + Sync>>,
 }
 
+/// Bitstream filter inserted for the parameter sets handling of a muxer.
+enum StreamFilter {
+    Extract(ExtractExtradata),
+    Dump(DumpExtradata),
+}
+
+/// Muxing operations delayed until the extradata of every stream is known.
+struct Pending {
+    info: GlobalInfo,
+    configure: bool,
+    header: bool,
+    packets: Vec<Held<Arc<Packet>>>,
+}
+
 impl Context {
     /// Creates a new `Context` instance.
     pub fn new<W: Write + 'static + Send>(muxer: Box<dyn Muxer + Send>, writer: Box<W>) -> Self {
@@ -49,19 +205,60 @@ impl Context {
             muxer,
             writer,
             buf: Vec::new(),
+            caps: None,
+            filters: Vec::new(),
+            pending: None,
+            threads: 1,
+            workers: None,
+            budget: Budget::unbounded(),
             user_private: None,
         }
     }
 
+    /// Sets the number of threads serializing the packets, 1 by default.
+    ///
+    /// With more threads, the muxers supporting it build the data of the
+    /// packets on worker threads and only write it in order, so the
+    /// packets are written a few calls after being given.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+        self.workers = None;
+    }
+
+    /// Creates a new `Context` instance for the muxer of a descriptor.
+    ///
+    /// The streams are checked against the muxer capabilities and their
+    /// parameter sets are moved where the muxer expects them.
+    pub fn from_descriptor<W: Write + 'static + Send>(
+        descr: &dyn Descriptor,
+        writer: Box<W>,
+    ) -> Self {
+        let mut ctx = Context::new(descr.create(), writer);
+        ctx.caps = Some(descr.capabilities().clone());
+        ctx
+    }
+
+    /// Sets the memory budget of the packets held by the context, while
+    /// the extradata is unknown or the packets are serialized.
+    ///
+    /// Writing a packet fails if it exceeds the budget once the packets in
+    /// flight are written.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
     /// Configures a muxer.
     pub fn configure(&mut self) -> Result<()> {
-        self.muxer.configure()
+        match self.pending {
+            Some(ref mut pending) => {
+                pending.configure = true;
+                Ok(())
+            }
+            None => self.muxer.configure(),
+        }
     }
 
-    /// Writes a stream header to an internal buffer and returns how many
-    /// bytes were written or an error.
-    pub fn write_header(&mut self) -> Result<usize> {
-        self.muxer.write_header(&mut self.buf)?;
+    fn flush(&mut self) -> Result<usize> {
         //FIXME: we should have proper management of the buffer's index
         match self.writer.write_all(&self.buf) {
             Ok(()) => {
@@ -73,39 +270,206 @@ impl Context {
         }
     }
 
+    /// Writes a stream header to an internal buffer and returns how many
+    /// bytes were written or an error.
+    ///
+    /// Nothing is written while the extradata of a stream is unknown.
+    pub fn write_header(&mut self) -> Result<usize> {
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!("write_header").entered();
+
+        if let Some(ref mut pending) = self.pending {
+            pending.header = true;
+            return Ok(0);
+        }
+        self.muxer.write_header(&mut self.buf)?;
+        self.flush()
+    }
+
+    fn filter(&mut self, pkt: Arc<Packet>) -> Result<Arc<Packet>> {
+        let index = pkt.stream_index;
+        let filter = match self.filters.get_mut(index as usize) {
+            Some(Some(filter)) if index >= 0 => filter,
+            _ => return Ok(pkt),
+        };
+        let mut pkt = (*pkt).clone();
+        match filter {
+            StreamFilter::Extract(f) => {
+                let known = f.get_extradata().is_some();
+                f.filter(&mut pkt)?;
+                if let Some(ref mut pending) = self.pending {
+                    let st = &mut pending.info.streams[index as usize];
+                    if !known {
+                        let extradata = f.get_extradata().ok_or_else(|| {
+                            Error::Unsupported(format!(
+                                "stream {} without parameter sets",
+                                st.index
+                            ))
+                        })?;
+                        st.params.extradata = Some(extradata.to_vec());
+                    }
+                }
+            }
+            StreamFilter::Dump(f) => f.filter(&mut pkt)?,
+        }
+        Ok(Arc::new(pkt))
+    }
+
+    /// Tells if the extradata of every stream is known.
+    fn extradata_known(&self) -> bool {
+        self.filters.iter().all(|filter| match filter {
+            Some(StreamFilter::Extract(f)) => f.get_extradata().is_some(),
+            _ => true,
+        })
+    }
+
     /// Writes a stream packet to an internal buffer and returns how many
     /// bytes were written or an error.
     pub fn write_packet(&mut self, pkt: Arc<Packet>) -> Result<usize> {
-        self.muxer.write_packet(&mut self.buf, pkt)?;
-        //FIXME: we should have proper management of the buffer's index
-        match self.writer.write_all(&self.buf) {
-            Ok(()) => {
-                let len = self.buf.len();
-                self.buf.clear();
-                Ok(len)
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!(
+            "write_packet",
+            stream = pkt.stream_index as i64,
+            pts = pkt.t.get_pts().map(|ts| ts.get_value()),
+            dts = pkt.t.get_dts().map(|ts| ts.get_value()),
+        )
+        .entered();
+
+        let pkt = self.filter(pkt)?;
+
+        if self.pending.is_none() {
+            return self.write_ordered(pkt);
+        }
+        let pkt = self.budget.try_hold(pkt.clone(), pkt.data.len())?;
+        self.pending.as_mut().unwrap().packets.push(pkt);
+        if !self.extradata_known() {
+            return Ok(0);
+        }
+
+        let pending = self.pending.take().unwrap();
+        self.muxer.set_global_info(pending.info)?;
+        if pending.configure {
+            self.muxer.configure()?;
+        }
+        if pending.header {
+            self.muxer.write_header(&mut self.buf)?;
+        }
+        for pkt in pending.packets {
+            self.muxer.write_packet(&mut self.buf, pkt.into_inner())?;
+        }
+        self.flush()
+    }
+
+    /// Writes a packet, through the worker threads if any.
+    fn write_ordered(&mut self, pkt: Arc<Packet>) -> Result<usize> {
+        if self.workers.is_none() && self.threads > 1 {
+            self.workers = self
+                .muxer
+                .serializer()
+                .map(|serializer| Workers::new(serializer, self.threads));
+        }
+        if self.workers.is_none() {
+            self.muxer.write_packet(&mut self.buf, pkt)?;
+            return self.flush();
+        }
+
+        let size = pkt.data.len();
+        let mut written = 0;
+        let pkt = match self.budget.try_hold(pkt.clone(), size) {
+            Ok(pkt) => pkt,
+            // Gives back the bytes of the packets in flight first.
+            Err(_) => {
+                written = self.commit(true)?;
+                self.budget.try_hold(pkt, size)?
             }
-            Err(e) => Err(Error::Io(e)),
+        };
+        self.workers.as_mut().unwrap().send(pkt);
+        Ok(written + self.commit(false)?)
+    }
+
+    /// Writes the serialized packets in order, waiting for the packets in
+    /// flight if there are too many of them or if `all` is set.
+    fn commit(&mut self, all: bool) -> Result<usize> {
+        let workers = match self.workers {
+            Some(ref mut workers) => workers,
+            None => return Ok(0),
+        };
+        while let Some((pkt, data)) = workers.next(all || workers.full())? {
+            self.muxer.write_serialized(&mut self.buf, pkt, data?)?;
         }
+        self.flush()
     }
 
     /// Writes a stream trailer to an internal buffer and returns how many
     /// bytes were written or an error.
+    ///
+    /// The packets still serialized by the worker threads are written
+    /// first.
     pub fn write_trailer(&mut self) -> Result<usize> {
-        self.muxer.write_trailer(&mut self.buf)?;
-        //FIXME: we should have proper management of the buffer's index
-        match self.writer.write_all(&self.buf) {
-            Ok(()) => {
-                let len = self.buf.len();
-                self.buf.clear();
-                Ok(len)
-            }
-            Err(e) => Err(Error::Io(e)),
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!("write_trailer").entered();
+
+        if self.pending.is_some() {
+            return Err(Error::Unsupported(
+                "streams without parameter sets".to_owned(),
+            ));
         }
+        let written = self.commit(true)?;
+        self.workers = None;
+        self.muxer.write_trailer(&mut self.buf)?;
+        Ok(written + self.flush()?)
     }
 
     /// Sets global media file information for a muxer.
-    pub fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
-        self.muxer.set_global_info(info)
+    ///
+    /// If the context was created from a descriptor, the streams are
+    /// checked and the bitstream filters their parameter sets need are
+    /// inserted.
+    pub fn set_global_info(&mut self, mut info: GlobalInfo) -> Result<()> {
+        let caps = match self.caps {
+            Some(ref caps) => caps,
+            None => return self.muxer.set_global_info(info),
+        };
+        caps.check(&info)?;
+
+        self.filters.clear();
+        for st in &mut info.streams {
+            let codec_id = st.params.codec_id.clone().unwrap_or_default();
+            let extradata = st.params.extradata.clone().filter(|data| !data.is_empty());
+            let filter = match caps.parameter_sets {
+                _ if !bsf::supports_codec(&codec_id) => None,
+                ParameterSets::Global => {
+                    if let Some(ref extradata) = extradata {
+                        st.params.extradata = Some(bsf::annexb_extradata(&codec_id, extradata)?);
+                    }
+                    Some(StreamFilter::Extract(ExtractExtradata::new(
+                        &codec_id,
+                        st.params.extradata.clone(),
+                    )))
+                }
+                ParameterSets::InBand => match extradata {
+                    Some(extradata) => Some(StreamFilter::Dump(DumpExtradata::new(
+                        &codec_id, &extradata,
+                    )?)),
+                    None => None,
+                },
+                ParameterSets::Any => None,
+            };
+            self.filters.push(filter);
+        }
+
+        if self.extradata_known() {
+            self.pending = None;
+            self.muxer.set_global_info(info)
+        } else {
+            self.pending = Some(Pending {
+                info,
+                configure: false,
+                header: false,
+                packets: Vec::new(),
+            });
+            Ok(())
+        }
     }
 
     /// Sets a muxer option.
@@ -118,6 +482,11 @@ impl Context {
     {
         self.muxer.set_option(key, val.into())
     }
+
+    /// Sets the muxer options without stream specifier of a parsed list.
+    pub fn set_options(&mut self, opts: &Options) -> Result<()> {
+        opts.apply(|key, val| self.muxer.set_option(key, val))
+    }
 }
 
 /// Format descriptor.
@@ -137,12 +506,92 @@ pub struct Descr {
     pub mime: &'static [&'static str],
 }
 
+/// Where a muxer stores the codec configuration, parameter sets included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterSets {
+    /// In the header, from the stream extradata.
+    Global,
+    /// In the key packets.
+    InBand,
+    /// Either way.
+    Any,
+}
+
+/// Capabilities of a muxer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    /// Codecs the format can store, any if `None`.
+    pub codecs: Option<&'static [&'static str]>,
+    /// Maximum number of streams, unbounded if `None`.
+    pub max_streams: Option<usize>,
+    /// Where the codec configuration is stored.
+    pub parameter_sets: ParameterSets,
+    /// Tells if the video streams can have a variable frame rate.
+    pub variable_frame_rate: bool,
+    /// Tells if the output has to be seekable.
+    pub seekable_output: bool,
+}
+
+impl Capabilities {
+    /// Capabilities of a muxer storing anything, or of unknown ones.
+    pub const ANY: Capabilities = Capabilities {
+        codecs: None,
+        max_streams: None,
+        parameter_sets: ParameterSets::Any,
+        variable_frame_rate: true,
+        seekable_output: false,
+    };
+
+    /// Tells if the format can store a codec.
+    pub fn supports_codec(&self, codec_id: &str) -> bool {
+        self.codecs.is_none_or(|codecs| codecs.contains(&codec_id))
+    }
+
+    /// Checks that the streams can be muxed, before configuring the muxer.
+    pub fn check(&self, info: &GlobalInfo) -> Result<()> {
+        if let Some(max) = self.max_streams {
+            if info.streams.len() > max {
+                return Err(Error::Unsupported(format!(
+                    "{} streams, at most {} allowed",
+                    info.streams.len(),
+                    max
+                )));
+            }
+        }
+        for st in &info.streams {
+            let codec_id = st.params.codec_id.as_deref().unwrap_or("");
+            if !self.supports_codec(codec_id) {
+                return Err(Error::Unsupported(format!(
+                    "codec {} in stream {}",
+                    codec_id, st.index
+                )));
+            }
+            let extradata = st.get_extradata().is_some_and(|data| !data.is_empty());
+            if self.parameter_sets == ParameterSets::Global
+                && !extradata
+                && !bsf::supports_codec(codec_id)
+            {
+                return Err(Error::Unsupported(format!(
+                    "stream {} without extradata",
+                    st.index
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Used to get a format descriptor and create a new muxer.
 pub trait Descriptor {
     /// Creates a new muxer for the requested format.
     fn create(&self) -> Box<dyn Muxer>;
     /// Returns the descriptor of a format.
     fn describe(&self) -> &Descr;
+    /// Returns the capabilities of the muxer, `Capabilities::ANY` if not
+    /// given.
+    fn capabilities(&self) -> &Capabilities {
+        &Capabilities::ANY
+    }
 }
 
 /// Used to look for a specific format.
@@ -156,3 +605,226 @@ impl Lookup for [&'static dyn Descriptor] {
         self.iter().find(|&&d| d.describe().name == name).copied()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::metadata::Metadata;
+    use crate::data::params::CodecParams;
+    use crate::ogg::opus::OPUS_DESCR;
+    use crate::rational::Rational64;
+    use crate::stream::Stream;
+
+    fn info(codecs: &[&str]) -> GlobalInfo {
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        for codec_id in codecs {
+            let params = CodecParams {
+                kind: None,
+                codec_id: Some(codec_id.to_string()),
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            };
+            info.add_stream(Stream::from_params(&params, Rational64::new(1, 1000)));
+        }
+        info
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut caps = Capabilities {
+            codecs: Some(&["av1", "opus"]),
+            max_streams: Some(2),
+            parameter_sets: ParameterSets::Any,
+            variable_frame_rate: true,
+            seekable_output: false,
+        };
+        assert!(caps.supports_codec("opus"));
+        assert!(!caps.supports_codec("h264"));
+
+        caps.check(&info(&["av1", "opus"])).unwrap();
+        assert!(caps.check(&info(&["av1", "h264"])).is_err());
+        assert!(caps.check(&info(&["av1", "opus", "opus"])).is_err());
+
+        caps.parameter_sets = ParameterSets::Global;
+        let mut with_extradata = info(&["av1"]);
+        assert!(caps.check(&with_extradata).is_err());
+        with_extradata.streams[0].params.extradata = Some(vec![0x81]);
+        caps.check(&with_extradata).unwrap();
+
+        Capabilities::ANY
+            .check(&info(&["av1", "h264", "opus"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn options() {
+        let mut ctx = Context::from_descriptor(OPUS_DESCR, Box::new(Output::default()));
+        let opts: Options = "vendor=123:page_duration=960:serial:a=x".parse().unwrap();
+        ctx.set_options(&opts).unwrap();
+        assert!(ctx.set_options(&"serial=x".parse().unwrap()).is_err());
+        assert!(ctx.set_options(&"unknown=1".parse().unwrap()).is_err());
+    }
+
+    /// Writes the extradata of the streams as header and the packet data.
+    struct DummyMuxer {
+        info: Option<GlobalInfo>,
+    }
+
+    impl Muxer for DummyMuxer {
+        fn configure(&mut self) -> Result<()> {
+            self.info.as_ref().map(|_| ()).ok_or(Error::InvalidData)
+        }
+        fn write_header(&mut self, out: &mut dyn Write) -> Result<()> {
+            for st in &self.info.as_ref().unwrap().streams {
+                out.write_all(st.get_extradata().unwrap_or(&[]))?;
+            }
+            Ok(())
+        }
+        fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+            out.write_all(&pkt.data)?;
+            Ok(())
+        }
+        fn write_trailer(&mut self, _out: &mut dyn Write) -> Result<()> {
+            Ok(())
+        }
+        fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+            self.info = Some(info);
+            Ok(())
+        }
+        fn set_option<'a>(&mut self, key: &str, _val: Value<'a>) -> Result<()> {
+            Err(Error::Unsupported(format!("{} key", key)))
+        }
+    }
+
+    struct DummyDescr {
+        d: Descr,
+        caps: Capabilities,
+    }
+
+    impl Descriptor for DummyDescr {
+        fn create(&self) -> Box<dyn Muxer> {
+            Box::new(DummyMuxer { info: None })
+        }
+        fn describe(&self) -> &Descr {
+            &self.d
+        }
+        fn capabilities(&self) -> &Capabilities {
+            &self.caps
+        }
+    }
+
+    fn descr(parameter_sets: ParameterSets) -> DummyDescr {
+        DummyDescr {
+            d: Descr {
+                name: "dummy",
+                demuxer: "dummy",
+                description: "Dummy",
+                extensions: &[],
+                mime: &[],
+            },
+            caps: Capabilities {
+                codecs: None,
+                max_streams: None,
+                parameter_sets,
+                variable_frame_rate: true,
+                seekable_output: false,
+            },
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &[u8], stream_index: isize) -> Arc<Packet> {
+        let mut pkt = Packet::new();
+        pkt.data = data.to_vec();
+        pkt.stream_index = stream_index;
+        pkt.is_key = true;
+        Arc::new(pkt)
+    }
+
+    const PARAMETER_SETS: [u8; 12] = [0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xce];
+    const IDR: [u8; 6] = [0, 0, 0, 1, 0x65, 0x88];
+
+    #[test]
+    fn global_parameter_sets() {
+        let descr = descr(ParameterSets::Global);
+        let out = Output::default();
+        let mut ctx = Context::from_descriptor(&descr, Box::new(out.clone()));
+        let mut streams = info(&["opus", "h264"]);
+        streams.streams[0].params.extradata = Some(b"OpusHead".to_vec());
+        ctx.set_global_info(streams).unwrap();
+        ctx.configure().unwrap();
+        assert_eq!(ctx.write_header().unwrap(), 0);
+        assert_eq!(ctx.write_packet(packet(b"opus", 0)).unwrap(), 0);
+
+        let mut data = PARAMETER_SETS.to_vec();
+        data.extend_from_slice(&IDR);
+        ctx.write_packet(packet(&data, 1)).unwrap();
+        ctx.write_packet(packet(&data, 1)).unwrap();
+        ctx.write_trailer().unwrap();
+
+        let mut expected = b"OpusHead".to_vec();
+        expected.extend_from_slice(&PARAMETER_SETS);
+        expected.extend_from_slice(b"opus");
+        expected.extend_from_slice(&IDR);
+        expected.extend_from_slice(&IDR);
+        assert_eq!(*out.0.lock().unwrap(), expected);
+
+        let mut ctx = Context::from_descriptor(&descr, Box::new(Output::default()));
+        ctx.set_global_info(info(&["h264"])).unwrap();
+        assert!(ctx.write_packet(packet(&IDR, 0)).is_err());
+
+        // The packets held until the extradata is known.
+        let budget = Budget::new(10);
+        let mut ctx = Context::from_descriptor(&descr, Box::new(Output::default()));
+        ctx.set_budget(budget.clone());
+        let mut streams = info(&["opus", "h264"]);
+        streams.streams[0].params.extradata = Some(b"OpusHead".to_vec());
+        ctx.set_global_info(streams).unwrap();
+        ctx.write_packet(packet(b"opus", 0)).unwrap();
+        ctx.write_packet(packet(b"opus", 0)).unwrap();
+        assert_eq!(budget.get_used(), 8);
+        assert!(matches!(
+            ctx.write_packet(packet(&data, 1)),
+            Err(Error::LimitExceeded("memory budget"))
+        ));
+        drop(ctx);
+        assert_eq!(budget.get_used(), 0);
+    }
+
+    #[test]
+    fn in_band_parameter_sets() {
+        let out = Output::default();
+        let mut ctx =
+            Context::from_descriptor(&descr(ParameterSets::InBand), Box::new(out.clone()));
+        let mut streams = info(&["h264"]);
+        streams.streams[0].params.extradata = Some(PARAMETER_SETS.to_vec());
+        ctx.set_global_info(streams).unwrap();
+        ctx.configure().unwrap();
+        ctx.write_header().unwrap();
+        ctx.write_packet(packet(&IDR, 0)).unwrap();
+
+        let mut expected = PARAMETER_SETS.to_vec();
+        expected.extend_from_slice(&PARAMETER_SETS);
+        expected.extend_from_slice(&IDR);
+        assert_eq!(*out.0.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,144 @@
+//!
+//! KLV coding (SMPTE 336M) and the MXF local set encoding.
+//!
+
+use av_bitstream::byteread::*;
+
+use crate::demuxer::need;
+use crate::error::*;
+
+/// SMPTE Universal Label.
+pub type Ul = [u8; 16];
+
+/// Prefix shared by every SMPTE label.
+const UL_PREFIX: [u8; 4] = [0x06, 0x0e, 0x2b, 0x34];
+
+/// Tells if a label starts with `prefix`, ignoring the registry version
+/// byte.
+pub(crate) fn ul_matches(ul: &[u8], prefix: &[u8]) -> bool {
+    ul.len() >= prefix.len()
+        && ul
+            .iter()
+            .zip(prefix)
+            .enumerate()
+            .all(|(i, (a, b))| i == 7 || a == b)
+}
+
+/// Reads the key and the BER coded length of a KLV triplet, returning
+/// them along with the size of the header.
+pub(crate) fn read_header(data: &[u8]) -> Result<(Ul, u64, usize)> {
+    need(data, 17)?;
+    if data[..4] != UL_PREFIX {
+        return Err(Error::InvalidData);
+    }
+    let mut key = [0; 16];
+    key.copy_from_slice(&data[..16]);
+
+    let first = data[16];
+    if first < 0x80 {
+        return Ok((key, u64::from(first), 17));
+    }
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > 8 {
+        return Err(Error::InvalidData);
+    }
+    need(data, 17 + count)?;
+    let len = data[17..17 + count]
+        .iter()
+        .fold(0u64, |len, &b| (len << 8) | u64::from(b));
+
+    Ok((key, len, 17 + count))
+}
+
+/// Iterator over the items of a local set.
+pub(crate) struct LocalItems<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for LocalItems<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < 4 {
+            return None;
+        }
+        let tag = get_u16b(self.data);
+        let len = get_u16b(&self.data[2..]) as usize;
+        let value = self.data.get(4..4 + len)?;
+        self.data = &self.data[4 + len..];
+        Some((tag, value))
+    }
+}
+
+/// Returns the items of a local set with 2-byte tags and lengths.
+pub(crate) fn local_items(data: &[u8]) -> LocalItems<'_> {
+    LocalItems { data }
+}
+
+/// Splits a batch or an array into its elements.
+pub(crate) fn batch(data: &[u8]) -> Vec<&[u8]> {
+    if data.len() < 8 {
+        return Vec::new();
+    }
+    let count = get_u32b(data) as usize;
+    let size = get_u32b(&data[4..]) as usize;
+    if size == 0 {
+        return Vec::new();
+    }
+    data[8..].chunks_exact(size).take(count).collect()
+}
+
+/// Reads a label.
+pub(crate) fn get_ul(data: &[u8]) -> Option<Ul> {
+    let mut ul = [0; 16];
+    ul.copy_from_slice(data.get(..16)?);
+    Some(ul)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: [u8; 16] = [
+        0x06, 0x0e, 0x2b, 0x34, 0x02, 0x05, 0x01, 0x01, 0x0d, 0x01, 0x02, 0x01, 0x01, 0x02, 0x04,
+        0x00,
+    ];
+
+    #[test]
+    fn header() {
+        let mut data = KEY.to_vec();
+        data.push(0x10);
+        assert_eq!(read_header(&data).unwrap(), (KEY, 16, 17));
+
+        data[16] = 0x83;
+        data.extend_from_slice(&[0x01, 0x00]);
+        match read_header(&data) {
+            Err(Error::MoreDataNeeded(20)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        data.push(0x02);
+        assert_eq!(read_header(&data).unwrap(), (KEY, 0x10002, 20));
+
+        data[0] = 0;
+        assert!(read_header(&data).is_err());
+    }
+
+    #[test]
+    fn items() {
+        let data = [0x3c, 0x0a, 0, 2, 1, 2, 0x44, 0x02, 0, 1, 3, 0xff];
+        let items: Vec<_> = local_items(&data).collect();
+        assert_eq!(items, vec![(0x3c0a, &[1, 2][..]), (0x4402, &[3][..])]);
+
+        let data = [0, 0, 0, 2, 0, 0, 0, 2, 1, 2, 3, 4];
+        assert_eq!(batch(&data), vec![&[1, 2][..], &[3, 4][..]]);
+    }
+
+    #[test]
+    fn matches() {
+        let mut key = KEY;
+        key[7] = 0x05;
+        assert!(ul_matches(&key, &KEY[..13]));
+        key[12] = 0;
+        assert!(!ul_matches(&key, &KEY[..13]));
+    }
+}
@@ -0,0 +1,330 @@
+//!
+//! MXF structural and descriptive header metadata.
+//!
+
+use std::collections::HashMap;
+
+use av_bitstream::byteread::*;
+
+use crate::data::metadata::Metadata;
+use crate::error::*;
+use crate::limits::Limits;
+use crate::mxf::klv::{self, Ul};
+use crate::rational::Rational64;
+
+/// Set types, bytes 13 and 14 of the set keys.
+pub(crate) mod kind {
+    pub const IDENTIFICATION: [u8; 2] = [0x01, 0x30];
+    pub const MATERIAL_PACKAGE: [u8; 2] = [0x01, 0x36];
+    pub const SOURCE_PACKAGE: [u8; 2] = [0x01, 0x37];
+    pub const MULTIPLE_DESCRIPTOR: [u8; 2] = [0x01, 0x44];
+    pub const MPEG2_DESCRIPTOR: [u8; 2] = [0x01, 0x51];
+    pub const SOUND_DESCRIPTOR: [u8; 2] = [0x01, 0x42];
+    pub const WAVE_DESCRIPTOR: [u8; 2] = [0x01, 0x48];
+    pub const AES3_DESCRIPTOR: [u8; 2] = [0x01, 0x47];
+}
+
+/// Local tags of the header metadata items.
+mod tag {
+    pub const INSTANCE_UID: u16 = 0x3c0a;
+    pub const COMPANY_NAME: u16 = 0x3c01;
+    pub const PRODUCT_NAME: u16 = 0x3c02;
+    pub const VERSION_STRING: u16 = 0x3c04;
+    pub const MODIFICATION_DATE: u16 = 0x3c06;
+    pub const PACKAGE_UID: u16 = 0x4401;
+    pub const PACKAGE_NAME: u16 = 0x4402;
+    pub const TRACKS: u16 = 0x4403;
+    pub const DESCRIPTOR: u16 = 0x4701;
+    pub const TRACK_ID: u16 = 0x4801;
+    pub const TRACK_NUMBER: u16 = 0x4804;
+    pub const SEQUENCE: u16 = 0x4803;
+    pub const EDIT_RATE: u16 = 0x4b01;
+    pub const DATA_DEFINITION: u16 = 0x0201;
+    pub const DURATION: u16 = 0x0202;
+    pub const SUB_DESCRIPTORS: u16 = 0x3f01;
+    pub const LINKED_TRACK_ID: u16 = 0x3006;
+    pub const SAMPLE_RATE: u16 = 0x3001;
+    pub const ESSENCE_CONTAINER: u16 = 0x3004;
+    pub const STORED_WIDTH: u16 = 0x3203;
+    pub const STORED_HEIGHT: u16 = 0x3202;
+    pub const PICTURE_CODING: u16 = 0x3201;
+    pub const AUDIO_SAMPLING_RATE: u16 = 0x3d03;
+    pub const CHANNEL_COUNT: u16 = 0x3d07;
+    pub const QUANTIZATION_BITS: u16 = 0x3d01;
+    pub const SOUND_COMPRESSION: u16 = 0x3d06;
+    pub const BLOCK_ALIGN: u16 = 0x3d0a;
+}
+
+/// Data definitions of the tracks, bytes 8 to 14 of their labels.
+const PICTURE_DEF: [u8; 6] = [0x01, 0x03, 0x02, 0x02, 0x01, 0x00];
+const SOUND_DEF: [u8; 6] = [0x01, 0x03, 0x02, 0x02, 0x02, 0x00];
+
+/// A header metadata set.
+#[derive(Debug)]
+pub(crate) struct Set {
+    pub kind: [u8; 2],
+    items: HashMap<u16, Vec<u8>>,
+}
+
+impl Set {
+    fn get(&self, tag: u16) -> Option<&[u8]> {
+        self.items.get(&tag).map(|v| v.as_slice())
+    }
+
+    pub fn get_u32(&self, tag: u16) -> Option<u32> {
+        self.get(tag).filter(|v| v.len() >= 4).map(get_u32b)
+    }
+
+    pub fn get_u16(&self, tag: u16) -> Option<u16> {
+        self.get(tag).filter(|v| v.len() >= 2).map(get_u16b)
+    }
+
+    pub fn get_i64(&self, tag: u16) -> Option<i64> {
+        self.get(tag).filter(|v| v.len() >= 8).map(get_i64b)
+    }
+
+    pub fn get_rational(&self, tag: u16) -> Option<Rational64> {
+        let v = self.get(tag).filter(|v| v.len() >= 8)?;
+        let (num, den) = (get_i32b(v), get_i32b(&v[4..]));
+        if num > 0 && den > 0 {
+            Some(Rational64::new(i64::from(num), i64::from(den)))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_ul(&self, tag: u16) -> Option<Ul> {
+        klv::get_ul(self.get(tag)?)
+    }
+
+    pub fn get_refs(&self, tag: u16) -> Vec<Ul> {
+        self.get(tag)
+            .map(klv::batch)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(klv::get_ul)
+            .collect()
+    }
+
+    /// Reads a UTF-16 string.
+    pub fn get_string(&self, tag: u16) -> Option<String> {
+        let v = self.get(tag)?;
+        let units: Vec<u16> = v.chunks_exact(2).map(get_u16b).collect();
+        let s = String::from_utf16_lossy(&units);
+        let s = s.trim_end_matches('\0');
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    }
+
+    /// Reads a timestamp as `YYYY-MM-DD hh:mm:ss`.
+    pub fn get_timestamp(&self, tag: u16) -> Option<String> {
+        let v = self.get(tag).filter(|v| v.len() >= 8)?;
+        if v.iter().all(|&b| b == 0) {
+            return None;
+        }
+        Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            get_u16b(v),
+            v[2],
+            v[3],
+            v[4],
+            v[5],
+            v[6]
+        ))
+    }
+}
+
+/// Essence descriptor of a track.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Descriptor {
+    pub kind: [u8; 2],
+    pub essence_container: Option<Ul>,
+    pub picture_coding: Option<Ul>,
+    pub sound_compression: Option<Ul>,
+    pub width: usize,
+    pub height: usize,
+    pub sample_rate: Option<Rational64>,
+    pub channels: usize,
+    pub bits: usize,
+    pub block_align: usize,
+}
+
+/// Essence track of the file package.
+#[derive(Debug)]
+pub(crate) struct Track {
+    pub number: u32,
+    pub edit_rate: Rational64,
+    pub duration: Option<u64>,
+    pub picture: bool,
+    pub sound: bool,
+    pub descriptor: Descriptor,
+}
+
+/// Header metadata sets, indexed by instance UID.
+#[derive(Debug, Default)]
+pub(crate) struct HeaderMetadata {
+    sets: HashMap<Ul, Set>,
+}
+
+impl HeaderMetadata {
+    /// Adds a set read from a KLV triplet.
+    pub fn add_set(&mut self, key: &Ul, value: &[u8]) {
+        let items: HashMap<u16, Vec<u8>> = klv::local_items(value)
+            .map(|(tag, v)| (tag, v.to_vec()))
+            .collect();
+        let uid = items
+            .get(&tag::INSTANCE_UID)
+            .and_then(|uid| klv::get_ul(uid));
+        if let Some(uid) = uid {
+            let kind = [key[13], key[14]];
+            self.sets.insert(uid, Set { kind, items });
+        }
+    }
+
+    fn get(&self, uid: &Ul) -> Option<&Set> {
+        self.sets.get(uid)
+    }
+
+    fn sets_of(&self, kind: [u8; 2]) -> impl Iterator<Item = &Set> {
+        self.sets.values().filter(move |set| set.kind == kind)
+    }
+
+    fn descriptor(set: &Set) -> Descriptor {
+        Descriptor {
+            kind: set.kind,
+            essence_container: set.get_ul(tag::ESSENCE_CONTAINER),
+            picture_coding: set.get_ul(tag::PICTURE_CODING),
+            sound_compression: set.get_ul(tag::SOUND_COMPRESSION),
+            width: set.get_u32(tag::STORED_WIDTH).unwrap_or(0) as usize,
+            height: set.get_u32(tag::STORED_HEIGHT).unwrap_or(0) as usize,
+            sample_rate: set
+                .get_rational(tag::AUDIO_SAMPLING_RATE)
+                .or_else(|| set.get_rational(tag::SAMPLE_RATE)),
+            channels: set.get_u32(tag::CHANNEL_COUNT).unwrap_or(0) as usize,
+            bits: set.get_u32(tag::QUANTIZATION_BITS).unwrap_or(0) as usize,
+            block_align: set.get_u16(tag::BLOCK_ALIGN).unwrap_or(0) as usize,
+        }
+    }
+
+    /// Returns the essence tracks of the file package.
+    ///
+    /// Source packages describing tapes or other files have no track
+    /// associated to essence elements.
+    pub fn tracks(&self, limits: &Limits) -> Result<Vec<Track>> {
+        for package in self.sets_of(kind::SOURCE_PACKAGE) {
+            let tracks = self.package_tracks(package, limits)?;
+            if !tracks.is_empty() {
+                return Ok(tracks);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Collects the descriptors of a set, walking the nested multiple
+    /// descriptors.
+    fn leaf_descriptors<'a>(
+        &'a self,
+        set: &'a Set,
+        depth: usize,
+        limits: &Limits,
+        out: &mut Vec<&'a Set>,
+    ) -> Result<()> {
+        limits.check_depth(depth)?;
+        // Deeper than the number of sets, the references loop.
+        if depth > self.sets.len() {
+            return Err(Error::InvalidData);
+        }
+        if set.kind != kind::MULTIPLE_DESCRIPTOR {
+            out.push(set);
+            return Ok(());
+        }
+        for uid in set.get_refs(tag::SUB_DESCRIPTORS) {
+            if let Some(sub) = self.get(&uid) {
+                self.leaf_descriptors(sub, depth + 1, limits, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn package_tracks(&self, package: &Set, limits: &Limits) -> Result<Vec<Track>> {
+        let root = package
+            .get_ul(tag::DESCRIPTOR)
+            .and_then(|uid| self.get(&uid));
+        let mut descriptors = Vec::new();
+        match root {
+            Some(set) => self.leaf_descriptors(set, 0, limits, &mut descriptors)?,
+            None => return Ok(Vec::new()),
+        }
+
+        let mut tracks = Vec::new();
+        for set in package
+            .get_refs(tag::TRACKS)
+            .iter()
+            .filter_map(|uid| self.get(uid))
+        {
+            let number = set.get_u32(tag::TRACK_NUMBER).unwrap_or(0);
+            let edit_rate = set.get_rational(tag::EDIT_RATE);
+            let sequence = set.get_ul(tag::SEQUENCE).and_then(|uid| self.get(&uid));
+            let (number, edit_rate, sequence) = match (number, edit_rate, sequence) {
+                (number, Some(edit_rate), Some(sequence)) if number != 0 => {
+                    (number, edit_rate, sequence)
+                }
+                _ => continue,
+            };
+            let def = sequence.get_ul(tag::DATA_DEFINITION).unwrap_or_default();
+            let id = set.get_u32(tag::TRACK_ID);
+            let descriptor = descriptors
+                .iter()
+                .find(|d| d.get_u32(tag::LINKED_TRACK_ID) == id)
+                .or_else(|| descriptors.get(tracks.len()))
+                .map(|d| Self::descriptor(d))
+                .unwrap_or_default();
+
+            tracks.push(Track {
+                number,
+                edit_rate,
+                duration: sequence
+                    .get_i64(tag::DURATION)
+                    .filter(|&d| d >= 0)
+                    .map(|d| d as u64),
+                picture: def[8..14] == PICTURE_DEF,
+                sound: def[8..14] == SOUND_DEF,
+                descriptor,
+            });
+        }
+
+        Ok(tracks)
+    }
+
+    /// Stores the identification and package information.
+    pub fn descriptive(&self, metadata: &mut Metadata) {
+        if let Some(ident) = self.sets_of(kind::IDENTIFICATION).next() {
+            let strings = [
+                ("company_name", tag::COMPANY_NAME),
+                ("product_name", tag::PRODUCT_NAME),
+                ("product_version", tag::VERSION_STRING),
+            ];
+            for &(key, tag) in strings.iter() {
+                if let Some(s) = ident.get_string(tag) {
+                    metadata.insert(key, s);
+                }
+            }
+            if let Some(date) = ident.get_timestamp(tag::MODIFICATION_DATE) {
+                metadata.insert("modification_date", date);
+            }
+        }
+
+        if let Some(package) = self.sets_of(kind::MATERIAL_PACKAGE).next() {
+            if let Some(name) = package.get_string(tag::PACKAGE_NAME) {
+                metadata.insert("title", name);
+            }
+            if let Some(umid) = package.items.get(&tag::PACKAGE_UID) {
+                let umid: String = umid.iter().map(|b| format!("{:02x}", b)).collect();
+                metadata.insert("material_package_umid", umid);
+            }
+        }
+    }
+}
@@ -0,0 +1,1134 @@
+//!
+//! MXF demuxer (SMPTE 377M).
+//!
+//! Files using the OP1a operational pattern are supported, with frame
+//! wrapped MPEG-2 and AVC video and frame or clip wrapped PCM audio.
+//!
+//! The partitions and index table segments met while demuxing are kept
+//! and can be inspected, the index entries also provide the random access
+//! flags of the video frames. The identification and material package
+//! information is stored as descriptive metadata.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+mod klv;
+mod metadata;
+
+use std::io::SeekFrom;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::frame::{Field, FieldInfo};
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::limits::Limits;
+use crate::pcm;
+use crate::rational::{rescale, Rational64};
+use crate::stream::Stream;
+
+use self::klv::{ul_matches, Ul};
+use self::metadata::{kind, Descriptor, HeaderMetadata, Track};
+
+/// Partition packs, the kind of partition follows.
+const PARTITION_PACK: [u8; 13] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x02, 0x05, 0x01, 0x01, 0x0d, 0x01, 0x02, 0x01, 0x01,
+];
+const INDEX_SEGMENT: [u8; 16] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x02, 0x53, 0x01, 0x01, 0x0d, 0x01, 0x02, 0x01, 0x01, 0x10, 0x01, 0x00,
+];
+/// Header metadata sets, the kind of set follows.
+const METADATA_SET: [u8; 13] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x02, 0x53, 0x01, 0x01, 0x0d, 0x01, 0x01, 0x01, 0x01,
+];
+/// Generic container essence elements, the track number follows.
+const ESSENCE_ELEMENT: [u8; 12] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x01, 0x02, 0x01, 0x01, 0x0d, 0x01, 0x03, 0x01,
+];
+/// Operational patterns, the item and package complexities follow.
+const OPERATIONAL_PATTERN: [u8; 12] = [
+    0x06, 0x0e, 0x2b, 0x34, 0x04, 0x01, 0x01, 0x01, 0x0d, 0x01, 0x02, 0x01,
+];
+
+/// Generic container item type of the sound elements.
+const ITEM_SOUND: u8 = 0x16;
+
+/// Index entry flag of the edit units allowing random access.
+pub const RANDOM_ACCESS: u8 = 0x80;
+
+/// Maximum size of the run-in preceding the header partition.
+const MAX_RUN_IN: usize = 65536;
+
+/// Kind of a partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// Header partition.
+    Header,
+    /// Body partition.
+    Body,
+    /// Footer partition.
+    Footer,
+}
+
+/// Partition pack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    /// Kind of partition.
+    pub kind: PartitionKind,
+    /// Tells if the header metadata of the partition is final.
+    pub closed: bool,
+    /// Tells if the header metadata of the partition is complete.
+    pub complete: bool,
+    /// KLV alignment grid size.
+    pub kag_size: u32,
+    /// Offset of the partition.
+    pub this_partition: u64,
+    /// Offset of the previous partition.
+    pub previous_partition: u64,
+    /// Offset of the footer partition, 0 if unknown.
+    pub footer_partition: u64,
+    /// Size of the header metadata of the partition.
+    pub header_byte_count: u64,
+    /// Size of the index table segments of the partition.
+    pub index_byte_count: u64,
+    /// Index table of the partition segments, 0 if there are none.
+    pub index_sid: u32,
+    /// Offset of the partition essence within its essence container.
+    pub body_offset: u64,
+    /// Essence container of the partition, 0 if there is no essence.
+    pub body_sid: u32,
+    /// Operational pattern of the file.
+    pub operational_pattern: Ul,
+    /// Essence containers present in the file.
+    pub essence_containers: Vec<Ul>,
+}
+
+impl Partition {
+    fn parse(key: &Ul, data: &[u8]) -> Result<Self> {
+        if data.len() < 88 {
+            return Err(Error::InvalidData);
+        }
+        let kind = match key[13] {
+            0x02 => PartitionKind::Header,
+            0x03 => PartitionKind::Body,
+            0x04 => PartitionKind::Footer,
+            _ => return Err(Error::InvalidData),
+        };
+
+        Ok(Partition {
+            kind,
+            closed: key[14] == 0x02 || key[14] == 0x04,
+            complete: key[14] >= 0x03,
+            kag_size: get_u32b(&data[4..]),
+            this_partition: get_u64b(&data[8..]),
+            previous_partition: get_u64b(&data[16..]),
+            footer_partition: get_u64b(&data[24..]),
+            header_byte_count: get_u64b(&data[32..]),
+            index_byte_count: get_u64b(&data[40..]),
+            index_sid: get_u32b(&data[48..]),
+            body_offset: get_u64b(&data[52..]),
+            body_sid: get_u32b(&data[60..]),
+            operational_pattern: klv::get_ul(&data[64..]).ok_or(Error::InvalidData)?,
+            essence_containers: klv::batch(&data[80..])
+                .into_iter()
+                .filter_map(klv::get_ul)
+                .collect(),
+        })
+    }
+
+    /// Tells if the file uses the OP1a operational pattern.
+    pub fn is_op1a(&self) -> bool {
+        let op = &self.operational_pattern;
+        ul_matches(op, &OPERATIONAL_PATTERN) && op[12] == 0x01 && op[13] == 0x01
+    }
+}
+
+/// Index table entry of an edit unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Offset of the edit unit in presentation order.
+    pub temporal_offset: i8,
+    /// Offset of the previous random access edit unit.
+    pub key_frame_offset: i8,
+    /// Edit unit flags (e.g. `RANDOM_ACCESS`).
+    pub flags: u8,
+    /// Offset of the edit unit within its essence container.
+    pub stream_offset: u64,
+}
+
+/// Index table segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexSegment {
+    /// Edit rate of the indexed essence.
+    pub edit_rate: Option<Rational64>,
+    /// First edit unit indexed.
+    pub start: i64,
+    /// Number of edit units indexed.
+    pub duration: i64,
+    /// Size of the edit units if constant, 0 otherwise.
+    pub edit_unit_byte_count: u32,
+    /// Index table of the segment.
+    pub index_sid: u32,
+    /// Essence container indexed.
+    pub body_sid: u32,
+    /// Entries of the edit units, empty if their size is constant.
+    pub entries: Vec<IndexEntry>,
+}
+
+impl IndexSegment {
+    fn parse(data: &[u8]) -> Self {
+        let mut segment = IndexSegment {
+            edit_rate: None,
+            start: 0,
+            duration: 0,
+            edit_unit_byte_count: 0,
+            index_sid: 0,
+            body_sid: 0,
+            entries: Vec::new(),
+        };
+
+        for (tag, v) in klv::local_items(data) {
+            match (tag, v.len()) {
+                (0x3f0b, 8..=usize::MAX) => {
+                    let (num, den) = (get_i32b(v), get_i32b(&v[4..]));
+                    if num > 0 && den > 0 {
+                        segment.edit_rate = Some(Rational64::new(i64::from(num), i64::from(den)));
+                    }
+                }
+                (0x3f0c, 8..=usize::MAX) => segment.start = get_i64b(v),
+                (0x3f0d, 8..=usize::MAX) => segment.duration = get_i64b(v),
+                (0x3f05, 4..=usize::MAX) => segment.edit_unit_byte_count = get_u32b(v),
+                (0x3f06, 4..=usize::MAX) => segment.index_sid = get_u32b(v),
+                (0x3f07, 4..=usize::MAX) => segment.body_sid = get_u32b(v),
+                (0x3f0a, _) => {
+                    segment.entries = klv::batch(v)
+                        .into_iter()
+                        .filter(|e| e.len() >= 11)
+                        .map(|e| IndexEntry {
+                            temporal_offset: e[0] as i8,
+                            key_frame_offset: e[1] as i8,
+                            flags: e[2],
+                            stream_offset: get_u64b(&e[3..]),
+                        })
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+
+        segment
+    }
+
+    /// Returns the entry of an edit unit, if indexed by the segment.
+    pub fn get_entry(&self, edit_unit: i64) -> Option<&IndexEntry> {
+        let pos = edit_unit.checked_sub(self.start)?;
+        if pos < 0 {
+            return None;
+        }
+        self.entries.get(pos as usize)
+    }
+}
+
+/// Kind of KLV packet.
+enum Klv {
+    Partition,
+    IndexSegment,
+    MetadataSet,
+    Essence(u32),
+    Other,
+}
+
+impl Klv {
+    fn classify(key: &Ul) -> Self {
+        if ul_matches(key, &PARTITION_PACK) && (0x02..=0x04).contains(&key[13]) {
+            Klv::Partition
+        } else if ul_matches(key, &INDEX_SEGMENT) {
+            Klv::IndexSegment
+        } else if ul_matches(key, &METADATA_SET) {
+            Klv::MetadataSet
+        } else if ul_matches(key, &ESSENCE_ELEMENT) {
+            Klv::Essence(get_u32b(&key[12..]))
+        } else {
+            Klv::Other
+        }
+    }
+}
+
+/// Reads a KLV header at `pos`.
+fn read_klv(data: &[u8], pos: usize) -> Result<(Ul, u64, usize)> {
+    klv::read_header(&data[pos..]).map_err(|e| match e {
+        Error::MoreDataNeeded(size) => Error::MoreDataNeeded(pos + size),
+        e => e,
+    })
+}
+
+/// Returns the end of a KLV packet, requiring it to be available.
+fn klv_end(data: &[u8], pos: usize, len: u64, header: usize) -> Result<usize> {
+    let end = (pos + header)
+        .checked_add(len as usize)
+        .ok_or(Error::InvalidData)?;
+    need(data, end)?;
+    Ok(end)
+}
+
+/// Returns the position of the header partition pack, skipping the run-in.
+fn find_header(data: &[u8]) -> Option<usize> {
+    data.windows(PARTITION_PACK.len() + 1)
+        .take(MAX_RUN_IN)
+        .position(|w| ul_matches(w, &PARTITION_PACK) && w[13] == 0x02)
+}
+
+/// Tells if an MPEG-1 or MPEG-2 video frame starts with an intra coded
+/// picture.
+pub(crate) fn mpeg2_is_intra(data: &[u8]) -> bool {
+    data.windows(6)
+        .find(|w| w[..4] == [0, 0, 1, 0])
+        .is_some_and(|w| (w[5] >> 3) & 7 == 1)
+}
+
+/// Field structure of the MPEG-1 and MPEG-2 video frames of a stream.
+#[derive(Debug, Default)]
+pub(crate) struct Mpeg2Fields {
+    progressive_sequence: bool,
+}
+
+impl Mpeg2Fields {
+    /// Returns the field structure of a video frame from its picture coding
+    /// extensions, `None` if it has none as MPEG-1 frames.
+    ///
+    /// The progressive sequence flag of the last sequence extension is
+    /// kept for the following frames.
+    pub(crate) fn parse(&mut self, data: &[u8]) -> Option<FieldInfo> {
+        let mut first = None;
+        let mut pictures = 0;
+        for w in data.windows(9).filter(|w| w[..4] == [0, 0, 1, 0xb5]) {
+            match w[4] >> 4 {
+                1 => self.progressive_sequence = w[5] & 0x08 != 0,
+                8 => {
+                    first = first.or(Some(w));
+                    pictures += 1;
+                }
+                _ => {}
+            }
+        }
+
+        let w = first?;
+        let field = match w[6] & 3 {
+            1 => Some(Field::Top),
+            2 => Some(Field::Bottom),
+            3 => None,
+            _ => return None,
+        };
+        let info = match field {
+            // Both field pictures of the frame.
+            Some(first_field) if pictures > 1 => FieldInfo {
+                interlaced: true,
+                first_field,
+                ..Default::default()
+            },
+            _ => FieldInfo::from_mpeg2(
+                self.progressive_sequence,
+                field,
+                w[8] & 0x80 != 0,
+                w[7] & 0x80 != 0,
+                w[7] & 0x02 != 0,
+            ),
+        };
+
+        Some(info)
+    }
+}
+
+/// Decoding of an essence track.
+#[derive(Debug)]
+enum Coding {
+    Video {
+        mpeg2: bool,
+        intra: bool,
+        fields: Mpeg2Fields,
+    },
+    Sound {
+        block_align: usize,
+    },
+}
+
+/// Essence track mapped to a stream.
+#[derive(Debug)]
+struct Essence {
+    number: u32,
+    index: usize,
+    timebase: Rational64,
+    coding: Coding,
+    /// Timestamp of the next packet.
+    pts: Timestamp,
+}
+
+/// Returns the codec of a video track and tells if it is intra only.
+fn video_codec(desc: &Descriptor) -> Option<(&'static str, bool)> {
+    if let Some(ul) = desc.picture_coding {
+        if ul[8..13] == [0x04, 0x01, 0x02, 0x02, 0x01] {
+            return match ul[13] {
+                0x01..=0x04 => Some(("mpeg2video", false)),
+                // AVC-Intra profiles are registered as 0x32.
+                0x31 => Some(("h264", false)),
+                0x32 => Some(("h264", true)),
+                _ => None,
+            };
+        }
+    }
+    if desc.kind == kind::MPEG2_DESCRIPTOR {
+        return Some(("mpeg2video", false));
+    }
+    match desc.essence_container.map(|ul| ul[13]) {
+        Some(0x04) => Some(("mpeg2video", false)),
+        Some(0x10) => Some(("h264", false)),
+        _ => None,
+    }
+}
+
+/// Returns the sample size of a PCM track.
+fn sound_sample_size(desc: &Descriptor) -> Option<usize> {
+    let pcm_descriptor = [
+        kind::SOUND_DESCRIPTOR,
+        kind::WAVE_DESCRIPTOR,
+        kind::AES3_DESCRIPTOR,
+    ]
+    .contains(&desc.kind);
+    let uncompressed = desc
+        .sound_compression
+        .is_none_or(|ul| ul[8..12] == [0x04, 0x02, 0x02, 0x01]);
+
+    match desc.bits.div_ceil(8) {
+        size @ 2..=4 if pcm_descriptor && uncompressed => Some(size),
+        _ => None,
+    }
+}
+
+/// MXF demuxer.
+#[derive(Default)]
+pub struct MxfDemuxer {
+    partitions: Vec<Partition>,
+    index: Vec<IndexSegment>,
+    essences: Vec<Essence>,
+    clip: Option<(usize, u64)>,
+    limits: Limits,
+}
+
+impl MxfDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the partitions read so far.
+    pub fn get_partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    /// Returns the index table segments read so far.
+    pub fn get_index_segments(&self) -> &[IndexSegment] {
+        &self.index
+    }
+
+    fn check_index(&self) -> Result<()> {
+        let entries = self.index.iter().map(|segment| segment.entries.len()).sum();
+        self.limits.check_index_entries(entries)
+    }
+
+    fn add_stream(&mut self, track: &Track, info: &mut GlobalInfo) -> Result<()> {
+        let desc = &track.descriptor;
+        let duration = track.duration;
+
+        let (params, timebase, coding, duration) = if track.picture {
+            let codec = video_codec(desc);
+            let params = CodecParams {
+                kind: Some(MediaKind::Video(VideoInfo {
+                    width: desc.width,
+                    height: desc.height,
+                    format: None,
+                })),
+                codec_id: codec.map(|(id, _)| id.to_owned()),
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            };
+            let coding = Coding::Video {
+                mpeg2: codec.is_some_and(|(id, _)| id == "mpeg2video"),
+                intra: codec.is_some_and(|(_, intra)| intra),
+                fields: Mpeg2Fields::default(),
+            };
+            (params, track.edit_rate.recip(), coding, duration)
+        } else if track.sound {
+            let rate = desc
+                .sample_rate
+                .ok_or(Error::InvalidData)?
+                .round()
+                .to_integer();
+            let map = pcm::channel_map(desc.channels)?;
+            let (codec_id, format, block_align) = match sound_sample_size(desc) {
+                Some(size) => {
+                    let fmt = pcm::sample_format(&format!("pcm_s{}le", size * 8));
+                    let codec_id = format!("pcm_s{}le", size * 8);
+                    let block_align = match desc.block_align {
+                        0 => size * desc.channels,
+                        block_align => block_align,
+                    };
+                    (Some(codec_id), fmt, block_align)
+                }
+                None => (None, None, 0),
+            };
+            let mut params = pcm::audio_params(String::new(), rate as usize, map, format);
+            params.codec_id = codec_id;
+
+            // The edit units last the inverse of the edit rate.
+            let edit_unit = Rational64::new_raw(*track.edit_rate.denom(), *track.edit_rate.numer());
+            let duration = duration
+                .and_then(|d| rescale(d as i64, edit_unit, Rational64::new(1, rate)))
+                .map(|samples| samples as u64);
+            (
+                params,
+                Rational64::new(1, rate),
+                Coding::Sound { block_align },
+                duration,
+            )
+        } else {
+            return Ok(());
+        };
+
+        let mut st = Stream::from_params(&params, timebase);
+        st.id = track.number as isize;
+        st.duration = duration;
+        let index = info.add_stream(st);
+
+        self.essences.push(Essence {
+            number: track.number,
+            index,
+            timebase,
+            coding,
+            pts: Timestamp::new(0, timebase),
+        });
+
+        Ok(())
+    }
+
+    fn is_key(&self, essence: &Essence, data: &[u8]) -> bool {
+        match essence.coding {
+            Coding::Video { intra: true, .. } | Coding::Sound { .. } => true,
+            Coding::Video { mpeg2, .. } => {
+                let entry = self
+                    .index
+                    .iter()
+                    .find_map(|segment| segment.get_entry(essence.pts.get_value()));
+                match entry {
+                    Some(entry) => entry.flags & RANDOM_ACCESS != 0,
+                    None if mpeg2 => mpeg2_is_intra(data),
+                    None => true,
+                }
+            }
+        }
+    }
+
+    fn packet(&mut self, idx: usize, data: &[u8]) -> Result<Packet> {
+        let essence = &self.essences[idx];
+        let duration = match essence.coding {
+            Coding::Sound { block_align } if block_align > 0 => (data.len() / block_align) as u64,
+            _ => 1,
+        };
+
+        let mut pkt = Packet::with_capacity(data.len());
+        pkt.data.extend_from_slice(data);
+        pkt.stream_index = essence.index as isize;
+        pkt.is_key = self.is_key(essence, data);
+        pkt.t.set_pts(Some(essence.pts))?;
+        pkt.t
+            .set_duration(Some(Duration::new(duration, essence.timebase)))?;
+
+        let essence = &mut self.essences[idx];
+        if let Coding::Video {
+            mpeg2: true,
+            ref mut fields,
+            ..
+        } = essence.coding
+        {
+            pkt.fields = fields.parse(data);
+        }
+        essence.pts = essence
+            .pts
+            .checked_add(Duration::new(duration, essence.timebase))?;
+        Ok(pkt)
+    }
+
+    /// Reads the next chunk of a clip wrapped sound element.
+    fn read_clip(&mut self, buf: &Box<dyn Buffered>, idx: usize, remaining: u64) -> Result<Event> {
+        let block_align = match self.essences[idx].coding {
+            Coding::Sound { block_align } => block_align.max(1),
+            _ => 1,
+        };
+        let size = remaining.min((pcm::FRAMES_PER_PACKET * block_align) as u64) as usize;
+        let data = buf.data();
+        need(data, size)?;
+
+        let pkt = self.packet(idx, &data[..size])?;
+        let remaining = remaining - size as u64;
+        self.clip = if remaining > 0 {
+            Some((idx, remaining))
+        } else {
+            None
+        };
+
+        Ok(Event::NewPacket(pkt))
+    }
+}
+
+impl Demuxer for MxfDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, 16)?;
+        let mut pos = match find_header(data) {
+            Some(pos) => pos,
+            None if data.len() < MAX_RUN_IN => return Err(Error::MoreDataNeeded(data.len() + 1)),
+            None => return Err(Error::InvalidData),
+        };
+
+        let mut partitions = Vec::new();
+        let mut index = Vec::new();
+        let mut header = HeaderMetadata::default();
+        loop {
+            let (key, len, header_size) = read_klv(data, pos)?;
+            let class = Klv::classify(&key);
+            let footer = matches!(class, Klv::Partition if key[13] == 0x04);
+            if matches!(class, Klv::Essence(_)) || footer {
+                break;
+            }
+
+            let end = klv_end(data, pos, len, header_size)?;
+            let value = &data[pos + header_size..end];
+            match class {
+                Klv::Partition => partitions.push(Partition::parse(&key, value)?),
+                Klv::IndexSegment => index.push(IndexSegment::parse(value)),
+                Klv::MetadataSet => header.add_set(&key, value),
+                _ => {}
+            }
+            pos = end;
+        }
+
+        match partitions.first() {
+            Some(partition) if partition.is_op1a() => {}
+            Some(_) => {
+                return Err(Error::Unsupported(
+                    "operational patterns other than OP1a".to_owned(),
+                ))
+            }
+            None => return Err(Error::InvalidData),
+        }
+
+        self.partitions = partitions;
+        self.index = index;
+        self.check_index()?;
+        self.essences.clear();
+        self.clip = None;
+        for track in header.tracks(&self.limits)? {
+            self.add_stream(&track, info)?;
+        }
+        header.descriptive(&mut info.metadata);
+
+        Ok(SeekFrom::Current(pos as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if let Some((idx, remaining)) = self.clip {
+            let event = self.read_clip(buf, idx, remaining)?;
+            let size = match event {
+                Event::NewPacket(ref pkt) => pkt.data.len(),
+                _ => 0,
+            };
+            return Ok((SeekFrom::Current(size as i64), event));
+        }
+
+        let data = buf.data();
+        let (key, len, header_size) = read_klv(data, 0)?;
+        let class = Klv::classify(&key);
+
+        if let Klv::Essence(number) = class {
+            if let Some(idx) = self.essences.iter().position(|e| e.number == number) {
+                let clip_wrapped = key[12] == ITEM_SOUND && (key[14] == 0x02 || key[14] == 0x04);
+                if clip_wrapped {
+                    self.clip = Some((idx, len)).filter(|&(_, len)| len > 0);
+                    return Ok((SeekFrom::Current(header_size as i64), Event::Continue));
+                }
+
+                let end = klv_end(data, 0, len, header_size)?;
+                let pkt = self.packet(idx, &data[header_size..end])?;
+                return Ok((SeekFrom::Current(end as i64), Event::NewPacket(pkt)));
+            }
+        }
+
+        let end = klv_end(data, 0, len, header_size)?;
+        let value = &data[header_size..end];
+        match class {
+            Klv::Partition => self.partitions.push(Partition::parse(&key, value)?),
+            Klv::IndexSegment => {
+                self.index.push(IndexSegment::parse(value));
+                self.check_index()?;
+            }
+            _ => {}
+        }
+
+        Ok((SeekFrom::Current(end as i64), Event::Continue))
+    }
+
+    fn set_limits(&mut self, limits: &Limits) {
+        self.limits = *limits;
+    }
+}
+
+struct MxfDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for MxfDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(MxfDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if find_header(data).is_some() {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// MXF demuxer descriptor.
+pub const MXF_DESCR: &dyn demuxer::Descriptor = &MxfDescr {
+    d: demuxer::Descr {
+        name: "mxf",
+        demuxer: "mxf",
+        description: "Material eXchange Format",
+        extensions: &["mxf"],
+        mime: &["application/mxf"],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::demuxer::{demux_file, Context};
+    use std::io::{BufRead, Cursor};
+
+    const PICTURE_DEF: [u8; 16] = [
+        0x06, 0x0e, 0x2b, 0x34, 0x04, 0x01, 0x01, 0x01, 0x01, 0x03, 0x02, 0x02, 0x01, 0x00, 0x00,
+        0x00,
+    ];
+    const SOUND_DEF: [u8; 16] = [
+        0x06, 0x0e, 0x2b, 0x34, 0x04, 0x01, 0x01, 0x01, 0x01, 0x03, 0x02, 0x02, 0x02, 0x00, 0x00,
+        0x00,
+    ];
+    const MPEG2_MP_ML: [u8; 16] = [
+        0x06, 0x0e, 0x2b, 0x34, 0x04, 0x01, 0x01, 0x03, 0x04, 0x01, 0x02, 0x02, 0x01, 0x01, 0x11,
+        0x00,
+    ];
+    const VIDEO_TRACK: u32 = 0x1501_0501;
+    const SOUND_TRACK: u32 = 0x1601_0101;
+
+    fn klv(file: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+        file.extend_from_slice(key);
+        file.push(0x83);
+        file.extend_from_slice(&(value.len() as u32).to_be_bytes()[1..]);
+        file.extend_from_slice(value);
+    }
+
+    fn uid(n: u8) -> Vec<u8> {
+        let mut uid = vec![0xaa; 16];
+        uid[15] = n;
+        uid
+    }
+
+    fn refs(uids: &[u8]) -> Vec<u8> {
+        let mut v = (uids.len() as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(&16u32.to_be_bytes());
+        for &n in uids {
+            v.extend_from_slice(&uid(n));
+        }
+        v
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|u| u.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    fn rational(num: i32, den: i32) -> Vec<u8> {
+        [num.to_be_bytes(), den.to_be_bytes()].concat()
+    }
+
+    fn set(file: &mut Vec<u8>, kind: [u8; 2], n: u8, items: &[(u16, Vec<u8>)]) {
+        let mut key = METADATA_SET.to_vec();
+        key.extend_from_slice(&[kind[0], kind[1], 0x00]);
+        let mut value = Vec::new();
+        for (tag, v) in [(0x3c0a, uid(n))].iter().chain(items) {
+            value.extend_from_slice(&tag.to_be_bytes());
+            value.extend_from_slice(&(v.len() as u16).to_be_bytes());
+            value.extend_from_slice(v);
+        }
+        klv(file, &key, &value);
+    }
+
+    fn partition(file: &mut Vec<u8>, kind: u8, body_sid: u32) {
+        let mut key = PARTITION_PACK.to_vec();
+        key.extend_from_slice(&[kind, 0x04, 0x00]);
+        let mut value = vec![0, 1, 0, 3];
+        value.extend_from_slice(&[0; 44]);
+        value.extend_from_slice(&[0, 0, 0, 1]);
+        value.extend_from_slice(&[0; 8]);
+        value.extend_from_slice(&body_sid.to_be_bytes());
+        value.extend_from_slice(&OPERATIONAL_PATTERN);
+        value.extend_from_slice(&[0x01, 0x01, 0x09, 0x00]);
+        value.extend_from_slice(&refs(&[]));
+        klv(file, &key, &value);
+    }
+
+    fn essence(file: &mut Vec<u8>, track: u32, data: &[u8]) {
+        let mut key = ESSENCE_ELEMENT.to_vec();
+        key.extend_from_slice(&track.to_be_bytes());
+        klv(file, &key, data);
+    }
+
+    fn index_segment(file: &mut Vec<u8>, flags: &[u8]) {
+        let mut entries = (flags.len() as u32).to_be_bytes().to_vec();
+        entries.extend_from_slice(&11u32.to_be_bytes());
+        for &f in flags {
+            entries.extend_from_slice(&[0, 0, f]);
+            entries.extend_from_slice(&[0; 8]);
+        }
+        let mut value = Vec::new();
+        for &(tag, ref v) in &[
+            (0x3f0b, rational(25, 1)),
+            (0x3f0c, vec![0; 8]),
+            (0x3f0d, (flags.len() as i64).to_be_bytes().to_vec()),
+            (0x3f0a, entries),
+        ] {
+            value.extend_from_slice(&(tag as u16).to_be_bytes());
+            value.extend_from_slice(&(v.len() as u16).to_be_bytes());
+            value.extend_from_slice(v);
+        }
+        klv(file, &INDEX_SEGMENT, &value);
+    }
+
+    fn op1a_file(clip_wrapped: bool) -> Vec<u8> {
+        // Clip wrapped elements are keyed by a distinct track number.
+        let sound_track = if clip_wrapped {
+            0x1601_0201
+        } else {
+            SOUND_TRACK
+        };
+        let mut file = vec![0xff; 8];
+        partition(&mut file, 0x02, 1);
+        set(
+            &mut file,
+            [0x01, 0x30],
+            1,
+            &[
+                (0x3c01, utf16("rust-av")),
+                (0x3c02, utf16("mxf test")),
+                (0x3c06, vec![0x07, 0xea, 10, 15, 12, 30, 0, 0]),
+            ],
+        );
+        set(
+            &mut file,
+            kind::MATERIAL_PACKAGE,
+            2,
+            &[(0x4401, vec![0x11; 32]), (0x4402, utf16("Evening News"))],
+        );
+        set(
+            &mut file,
+            kind::SOURCE_PACKAGE,
+            3,
+            &[(0x4403, refs(&[10, 11])), (0x4701, uid(20))],
+        );
+        for (n, id, number, rate, sequence, def, duration) in [
+            (
+                10u8,
+                1u32,
+                VIDEO_TRACK,
+                rational(25, 1),
+                12u8,
+                PICTURE_DEF,
+                3i64,
+            ),
+            (11, 2, sound_track, rational(25, 1), 13, SOUND_DEF, 3),
+        ] {
+            set(
+                &mut file,
+                [0x01, 0x3b],
+                n,
+                &[
+                    (0x4801, id.to_be_bytes().to_vec()),
+                    (0x4804, number.to_be_bytes().to_vec()),
+                    (0x4b01, rate),
+                    (0x4803, uid(sequence)),
+                ],
+            );
+            set(
+                &mut file,
+                [0x01, 0x0f],
+                sequence,
+                &[
+                    (0x0201, def.to_vec()),
+                    (0x0202, duration.to_be_bytes().to_vec()),
+                ],
+            );
+        }
+        set(
+            &mut file,
+            kind::MULTIPLE_DESCRIPTOR,
+            20,
+            &[(0x3f01, refs(&[22, 21]))],
+        );
+        set(
+            &mut file,
+            kind::MPEG2_DESCRIPTOR,
+            21,
+            &[
+                (0x3006, 1u32.to_be_bytes().to_vec()),
+                (0x3203, 720u32.to_be_bytes().to_vec()),
+                (0x3202, 288u32.to_be_bytes().to_vec()),
+                (0x3201, MPEG2_MP_ML.to_vec()),
+            ],
+        );
+        set(
+            &mut file,
+            kind::WAVE_DESCRIPTOR,
+            22,
+            &[
+                (0x3006, 2u32.to_be_bytes().to_vec()),
+                (0x3d03, rational(48000, 1)),
+                (0x3d07, 2u32.to_be_bytes().to_vec()),
+                (0x3d01, 24u32.to_be_bytes().to_vec()),
+            ],
+        );
+        index_segment(&mut file, &[RANDOM_ACCESS, 0, 0]);
+
+        let sound: Vec<u8> = (0..6 * 1920).map(|i| i as u8).collect();
+        if clip_wrapped {
+            for t in 0..3 {
+                essence(&mut file, VIDEO_TRACK, &[0, 0, 1, 0, t, 0x08 << t.min(1)]);
+            }
+            essence(
+                &mut file,
+                sound_track,
+                &[&sound[..], &sound[..], &sound[..]].concat(),
+            );
+        } else {
+            for t in 0..3 {
+                essence(&mut file, VIDEO_TRACK, &[0, 0, 1, 0, t, 0x08 << t.min(1)]);
+                essence(&mut file, sound_track, &sound);
+            }
+        }
+        partition(&mut file, 0x04, 0);
+        file
+    }
+
+    #[test]
+    fn op1a() {
+        let file = op1a_file(false);
+        assert_eq!(MXF_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(MXF_DESCR, file, 256);
+        let info = c.info;
+        assert_eq!(info.streams.len(), 2);
+
+        let video = &info.streams[0];
+        assert_eq!(video.params.codec_id.as_deref(), Some("mpeg2video"));
+        assert_eq!(video.id, VIDEO_TRACK as isize);
+        assert_eq!(video.timebase, Rational64::new(1, 25));
+        assert_eq!(video.duration, Some(3));
+        match video.params.kind {
+            Some(MediaKind::Video(ref v)) => assert_eq!((v.width, v.height), (720, 288)),
+            _ => panic!("not a video stream"),
+        }
+
+        let sound = &info.streams[1];
+        assert_eq!(sound.params.codec_id.as_deref(), Some("pcm_s24le"));
+        assert_eq!(sound.timebase, Rational64::new(1, 48000));
+        assert_eq!(sound.duration, Some(5760));
+
+        assert_eq!(info.metadata.get_str("company_name"), Some("rust-av"));
+        assert_eq!(info.metadata.get_str("product_name"), Some("mxf test"));
+        assert_eq!(info.metadata.get_str("title"), Some("Evening News"));
+        assert_eq!(
+            info.metadata.get_str("modification_date"),
+            Some("2026-10-15 12:30:00")
+        );
+
+        assert_eq!(packets.len(), 6);
+        let keys: Vec<_> = packets.iter().map(|p| p.is_key).collect();
+        assert_eq!(keys, vec![true, true, false, true, false, true]);
+        assert_eq!(packets[4].t.get_pts().map(Timestamp::get_value), Some(2));
+        assert_eq!(packets[5].t.get_pts().map(Timestamp::get_value), Some(3840));
+        assert_eq!(
+            packets[5].t.get_duration().map(Duration::get_value),
+            Some(1920)
+        );
+        assert_eq!(packets[5].stream_index, 1);
+    }
+
+    #[test]
+    fn mpeg2_fields() {
+        let sequence = |progressive: bool| {
+            [
+                0,
+                0,
+                1,
+                0xb5,
+                0x14,
+                0x82 | (progressive as u8) << 3,
+                0,
+                1,
+                0,
+                0,
+            ]
+        };
+        let picture = |structure: u8, flags: u8, progressive_frame: bool| {
+            [
+                0,
+                0,
+                1,
+                0xb5,
+                0x8f,
+                0xff,
+                0xf0 | structure,
+                flags,
+                (progressive_frame as u8) << 7,
+            ]
+        };
+        let mut fields = Mpeg2Fields::default();
+
+        // MPEG-1 pictures have no extension.
+        assert_eq!(fields.parse(&[0, 0, 1, 0, 0, 0x08, 0, 0]), None);
+
+        let mut frame = sequence(false).to_vec();
+        frame.extend_from_slice(&picture(3, 0x02, false));
+        let info = fields.parse(&frame).unwrap();
+        assert!(info.interlaced && info.repeat_first_field);
+        assert_eq!((info.first_field, info.single_field), (Field::Bottom, None));
+
+        // Two field pictures, bottom field first.
+        let mut frame = picture(2, 0, false).to_vec();
+        frame.extend_from_slice(&picture(1, 0, false));
+        let info = fields.parse(&frame).unwrap();
+        assert!(info.interlaced);
+        assert_eq!((info.first_field, info.single_field), (Field::Bottom, None));
+        assert_eq!(info.get_display_fields(), 2);
+
+        let info = fields.parse(&picture(1, 0x80, false)).unwrap();
+        assert_eq!(info, FieldInfo::single(Field::Top));
+        assert_eq!(info.get_display_fields(), 1);
+
+        // The progressive sequence flag applies to the following frames.
+        fields.parse(&sequence(true));
+        let info = fields.parse(&picture(3, 0x82, true)).unwrap();
+        assert_eq!((info.interlaced, info.repeat_frame), (false, 2));
+    }
+
+    #[test]
+    fn clip_wrapped() {
+        let (c, packets) = demux_file(MXF_DESCR, op1a_file(true), 256);
+        let info = c.info;
+        assert_eq!(info.streams.len(), 2);
+
+        let sound: Vec<_> = packets.iter().filter(|p| p.stream_index == 1).collect();
+        let durations: Vec<_> = sound
+            .iter()
+            .map(|p| p.t.get_duration().map(Duration::get_value).unwrap())
+            .collect();
+        assert_eq!(durations, vec![1024, 1024, 1024, 1024, 1024, 640]);
+        assert_eq!(sound[5].t.get_pts().map(Timestamp::get_value), Some(5120));
+    }
+
+    #[test]
+    fn partitions_and_index() {
+        let file = op1a_file(false);
+        let r = AccReader::with_capacity(file.len(), Cursor::new(file));
+        let mut buf: Box<dyn Buffered> = Box::new(r);
+        buf.fill_buf().unwrap();
+
+        let mut demuxer = MxfDemuxer::new();
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Default::default(),
+            groups: Vec::new(),
+        };
+        demuxer.read_headers(&buf, &mut info).unwrap();
+
+        let header = &demuxer.get_partitions()[0];
+        assert_eq!(header.kind, PartitionKind::Header);
+        assert!(header.is_op1a() && header.closed && header.complete);
+        assert_eq!(header.body_sid, 1);
+
+        let segment = &demuxer.get_index_segments()[0];
+        assert_eq!(segment.edit_rate, Some(Rational64::new(25, 1)));
+        assert_eq!(segment.get_entry(0).unwrap().flags, RANDOM_ACCESS);
+        assert!(segment.get_entry(3).is_none());
+    }
+
+    #[test]
+    fn not_op1a() {
+        let mut file = op1a_file(false);
+        // Turn the operational pattern into OP2a.
+        let op = file
+            .windows(OPERATIONAL_PATTERN.len())
+            .position(|w| w == OPERATIONAL_PATTERN)
+            .unwrap();
+        file[op + 12] = 0x02;
+
+        let r = AccReader::with_capacity(256, Cursor::new(file));
+        let mut c = Context::new(MXF_DESCR.create(), Box::new(r));
+        match c.read_headers() {
+            Err(Error::Unsupported(_)) => {}
+            r => panic!("unexpected {:?}", r.is_ok()),
+        }
+    }
+    #[test]
+    fn limits() {
+        let demux = |limits| {
+            let r = AccReader::with_capacity(256, Cursor::new(op1a_file(false)));
+            let mut c = Context::new(MXF_DESCR.create(), Box::new(r));
+            c.set_limits(limits);
+            c.read_headers()
+        };
+        let limits = Limits {
+            max_depth: 1,
+            max_index_entries: 3,
+            ..Limits::default()
+        };
+        demux(limits).unwrap();
+        assert!(matches!(
+            demux(Limits {
+                max_depth: 0,
+                ..limits
+            }),
+            Err(Error::LimitExceeded("nesting depth"))
+        ));
+        assert!(matches!(
+            demux(Limits {
+                max_index_entries: 2,
+                ..limits
+            }),
+            Err(Error::LimitExceeded("index entries"))
+        ));
+    }
+}
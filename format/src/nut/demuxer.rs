@@ -0,0 +1,676 @@
+//!
+//! NUT demuxer.
+//!
+//! Frames are read sequentially, index packets and repeated headers are
+//! skipped. Frame timestamps are only coded as presentation timestamps,
+//! decoding timestamps are provided for streams without decode delay.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::SeekFrom;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::Timestamp;
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::ogg::crc32;
+use crate::pcm;
+use crate::rational::{rescale, Rational64};
+use crate::stream::Stream;
+
+use super::*;
+
+/// Frames larger than this cannot use an elision header.
+const MAX_ELISION_FRAME_SIZE: u64 = 4096;
+
+/// Coding shared by the frames using a frame code.
+#[derive(Clone, Copy, Debug)]
+struct FrameCode {
+    flags: u64,
+    stream_id: u64,
+    size_mul: u64,
+    size_lsb: u64,
+    pts_delta: i64,
+    reserved: u64,
+    header_idx: u64,
+}
+
+const INVALID_CODE: FrameCode = FrameCode {
+    flags: flag::INVALID,
+    stream_id: 0,
+    size_mul: 1,
+    size_lsb: 0,
+    pts_delta: 0,
+    reserved: 0,
+    header_idx: 0,
+};
+
+/// Main header contents.
+#[derive(Debug)]
+struct MainHeader {
+    stream_count: u64,
+    time_bases: Vec<Rational64>,
+    frame_codes: Vec<FrameCode>,
+    elision_headers: Vec<Vec<u8>>,
+}
+
+impl MainHeader {
+    fn parse(r: &mut Reader) -> Result<Self> {
+        let version = r.get_v()?;
+        if !(2..=4).contains(&version) {
+            return Err(Error::Unsupported(format!("NUT version {}", version)));
+        }
+        if version > 3 {
+            let _minor_version = r.get_v()?;
+        }
+        let stream_count = r.get_v()?;
+        let _max_distance = r.get_v()?;
+
+        let time_base_count = r.get_v()?;
+        if stream_count == 0 || time_base_count == 0 || time_base_count > 256 {
+            return Err(Error::InvalidData);
+        }
+        let mut time_bases = Vec::new();
+        for _ in 0..time_base_count {
+            let num = r.get_v()?;
+            let den = r.get_v()?;
+            if num == 0 || den == 0 || num > u64::from(u32::MAX) || den > u64::from(u32::MAX) {
+                return Err(Error::InvalidData);
+            }
+            time_bases.push(Rational64::new(num as i64, den as i64));
+        }
+
+        let (mut pts_delta, mut size_mul, mut stream_id, mut header_idx) = (0, 1, 0, 0);
+        let mut frame_codes = Vec::with_capacity(256);
+        while frame_codes.len() < 256 {
+            let flags = r.get_v()?;
+            let fields = r.get_v()?;
+            if fields > 0 {
+                pts_delta = r.get_s()?;
+            }
+            if fields > 1 {
+                size_mul = r.get_v()?;
+            }
+            if fields > 2 {
+                stream_id = r.get_v_below(stream_count)?;
+            }
+            let size_lsb = if fields > 3 { r.get_v()? } else { 0 };
+            let reserved = if fields > 4 { r.get_v()? } else { 0 };
+            let count = if fields > 5 {
+                r.get_v()?
+            } else {
+                size_mul.checked_sub(size_lsb).ok_or(Error::InvalidData)?
+            };
+            if fields > 6 {
+                let _match_time_delta = r.get_s()?;
+            }
+            if fields > 7 {
+                header_idx = r.get_v()?;
+            }
+            for _ in 8..fields {
+                r.get_v()?;
+            }
+            if count == 0 {
+                return Err(Error::InvalidData);
+            }
+
+            let mut j = 0;
+            while j < count && frame_codes.len() < 256 {
+                if frame_codes.len() == STARTCODE_PREFIX as usize {
+                    frame_codes.push(INVALID_CODE);
+                    continue;
+                }
+                frame_codes.push(FrameCode {
+                    flags,
+                    stream_id,
+                    size_mul,
+                    size_lsb: size_lsb + j,
+                    pts_delta,
+                    reserved,
+                    header_idx,
+                });
+                j += 1;
+            }
+        }
+
+        let header_count = r.get_v()? + 1;
+        if header_count > 128 {
+            return Err(Error::InvalidData);
+        }
+        let mut elision_headers = vec![Vec::new()];
+        for _ in 1..header_count {
+            let header = r.get_vb()?;
+            if header.len() >= 256 {
+                return Err(Error::InvalidData);
+            }
+            elision_headers.push(header.to_vec());
+        }
+
+        Ok(MainHeader {
+            stream_count,
+            time_bases,
+            frame_codes,
+            elision_headers,
+        })
+    }
+}
+
+/// Stream header contents.
+#[derive(Debug)]
+struct StreamHeader {
+    id: u64,
+    params: CodecParams,
+    time_base_id: usize,
+    msb_pts_shift: u32,
+    decode_delay: u64,
+}
+
+impl StreamHeader {
+    fn parse(r: &mut Reader, main: &MainHeader) -> Result<Self> {
+        let id = r.get_v_below(main.stream_count)?;
+        let class = r.get_v()?;
+        let codec_id = match *r.get_vb()? {
+            [a, b, c, d] => tag_codec([a, b, c, d]),
+            [a, b] => tag_codec([a, b, 0, 0]),
+            _ => None,
+        };
+        let time_base_id = r.get_v_below(main.time_bases.len() as u64)? as usize;
+        let msb_pts_shift = r.get_v()?;
+        if msb_pts_shift >= 48 {
+            return Err(Error::InvalidData);
+        }
+        let _max_pts_distance = r.get_v()?;
+        let decode_delay = r.get_v()?;
+        let _stream_flags = r.get_v()?;
+        let extradata = r.get_vb()?;
+
+        let mut params = match class {
+            CLASS_VIDEO => {
+                let width = r.get_v()? as usize;
+                let height = r.get_v()? as usize;
+                let _sample_width = r.get_v()?;
+                let _sample_height = r.get_v()?;
+                let _colorspace_type = r.get_v()?;
+                CodecParams {
+                    kind: Some(MediaKind::Video(VideoInfo {
+                        width,
+                        height,
+                        format: None,
+                    })),
+                    codec_id: codec_id.clone(),
+                    extradata: None,
+                    bit_rate: 0,
+                    convergence_window: 0,
+                    delay: 0,
+                }
+            }
+            CLASS_AUDIO => {
+                let num = r.get_v()?;
+                let den = r.get_v()?;
+                let channels = r.get_v()? as usize;
+                if num == 0 || den == 0 {
+                    return Err(Error::InvalidData);
+                }
+                let map = pcm::channel_map(channels)?;
+                let format = codec_id.as_deref().and_then(pcm::sample_format);
+                pcm::audio_params(String::new(), (num / den) as usize, map, format)
+            }
+            _ => CodecParams {
+                kind: None,
+                codec_id: None,
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            },
+        };
+        params.codec_id = codec_id;
+        if !extradata.is_empty() {
+            params.extradata = Some(extradata.to_vec());
+        }
+
+        Ok(StreamHeader {
+            id,
+            params,
+            time_base_id,
+            msb_pts_shift: msb_pts_shift as u32,
+            decode_delay,
+        })
+    }
+}
+
+/// Reads a timestamp coded along with its time base.
+fn get_t(r: &mut Reader, time_bases: &[Rational64]) -> Result<(i64, Rational64)> {
+    let v = r.get_v()?;
+    let count = time_bases.len() as u64;
+    Ok(((v / count) as i64, time_bases[(v % count) as usize]))
+}
+
+/// Demuxing state of a stream.
+#[derive(Debug)]
+struct NutStream {
+    index: usize,
+    timebase: Rational64,
+    msb_pts_shift: u32,
+    decode_delay: u64,
+    last_pts: i64,
+}
+
+/// NUT demuxer.
+#[derive(Default)]
+pub struct NutDemuxer {
+    main: Option<MainHeader>,
+    streams: Vec<NutStream>,
+}
+
+impl NutDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read_syncpoint(&mut self, payload: &[u8]) -> Result<()> {
+        let main = self.main.as_ref().ok_or(Error::InvalidData)?;
+        let (ts, timebase) = parse_payload(payload, |r| get_t(r, &main.time_bases))?;
+        for st in self.streams.iter_mut() {
+            st.last_pts = rescale(ts, timebase, st.timebase).ok_or(Error::InvalidData)?;
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, data: &[u8]) -> Result<(usize, Option<Packet>)> {
+        let main = self.main.as_ref().ok_or(Error::InvalidData)?;
+        let mut r = Reader::new(data, 0);
+        let code = main.frame_codes[r.get_u8()? as usize];
+
+        let mut flags = code.flags;
+        if flags & flag::CODED != 0 {
+            flags ^= r.get_v()?;
+        }
+        if flags & flag::INVALID != 0 {
+            return Err(Error::InvalidData);
+        }
+        let stream_id = if flags & flag::STREAM_ID != 0 {
+            r.get_v()?
+        } else {
+            code.stream_id
+        };
+        let st = self
+            .streams
+            .get_mut(stream_id as usize)
+            .ok_or(Error::InvalidData)?;
+
+        let pts = if flags & flag::CODED_PTS != 0 {
+            let coded = r.get_v()?;
+            let range = 1u64 << st.msb_pts_shift;
+            if coded < range {
+                let mask = range as i64 - 1;
+                let delta = st.last_pts - mask / 2;
+                ((coded as i64 - delta) & mask) + delta
+            } else {
+                (coded - range) as i64
+            }
+        } else {
+            st.last_pts + code.pts_delta
+        };
+        let mut size = code.size_lsb;
+        if flags & flag::SIZE_MSB != 0 {
+            size += r.get_v()? * code.size_mul;
+        }
+        if flags & flag::MATCH_TIME != 0 {
+            let _match_time_delta = r.get_s()?;
+        }
+        let mut header_idx = if flags & flag::HEADER_IDX != 0 {
+            r.get_v()?
+        } else {
+            code.header_idx
+        };
+        let reserved = if flags & flag::RESERVED != 0 {
+            r.get_v()?
+        } else {
+            code.reserved
+        };
+        for _ in 0..reserved {
+            r.get_v()?;
+        }
+        if flags & flag::CHECKSUM != 0 {
+            let header = &data[..r.pos];
+            if r.get_u32()? != crc32(header) {
+                return Err(Error::InvalidData);
+            }
+        }
+
+        if size > MAX_ELISION_FRAME_SIZE {
+            header_idx = 0;
+        }
+        let elided = main
+            .elision_headers
+            .get(header_idx as usize)
+            .ok_or(Error::InvalidData)?;
+        let size = size
+            .checked_sub(elided.len() as u64)
+            .ok_or(Error::InvalidData)?;
+        let mut payload = r.get_bytes(size as usize)?;
+        st.last_pts = pts;
+
+        if flags & flag::EOR != 0 {
+            return Ok((r.pos, None));
+        }
+        if flags & flag::SM_DATA != 0 {
+            let time_base_count = main.time_bases.len() as u64;
+            let skip = parse_payload(payload, |r| {
+                read_items(r, time_base_count)?;
+                read_items(r, time_base_count)?;
+                Ok(r.pos)
+            })?;
+            payload = &payload[skip..];
+        }
+
+        let mut pkt = Packet::with_capacity(elided.len() + payload.len());
+        pkt.data.extend_from_slice(elided);
+        pkt.data.extend_from_slice(payload);
+        pkt.stream_index = st.index as isize;
+        pkt.is_key = flags & flag::KEY != 0;
+        let ts = Timestamp::new(pts, st.timebase);
+        pkt.t.set_pts(Some(ts))?;
+        if st.decode_delay == 0 {
+            pkt.t.set_dts(Some(ts))?;
+        }
+
+        Ok((r.pos, Some(pkt)))
+    }
+}
+
+impl Demuxer for NutDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, FILE_ID.len())?;
+        if !data.starts_with(FILE_ID) {
+            return Err(Error::InvalidData);
+        }
+
+        let mut main: Option<MainHeader> = None;
+        let mut headers: Vec<StreamHeader> = Vec::new();
+        let mut metadata = Vec::new();
+        let mut pos = FILE_ID.len();
+        loop {
+            need(data, pos + 8)?;
+            if data[pos] != STARTCODE_PREFIX {
+                break;
+            }
+            let (startcode, payload, end) = read_packet(data, pos)?;
+            if startcode == SYNCPOINT_STARTCODE {
+                break;
+            }
+            let main = match main {
+                None if startcode == MAIN_STARTCODE => {
+                    main = Some(parse_payload(payload, MainHeader::parse)?);
+                    pos = end;
+                    continue;
+                }
+                None => return Err(Error::InvalidData),
+                Some(ref main) => main,
+            };
+            match startcode {
+                STREAM_STARTCODE => {
+                    let header = parse_payload(payload, |r| StreamHeader::parse(r, main))?;
+                    if headers.iter().all(|h| h.id != header.id) {
+                        headers.push(header);
+                    }
+                }
+                INFO_STARTCODE => {
+                    let time_base_count = main.time_bases.len() as u64;
+                    let (global, items) = parse_payload(payload, |r| {
+                        let stream_id_plus1 = r.get_v()?;
+                        let chapter_id = r.get_s()?;
+                        let _chapter_start = r.get_v()?;
+                        let _chapter_len = r.get_v()?;
+                        let items = read_items(r, time_base_count)?;
+                        Ok((stream_id_plus1 == 0 && chapter_id == 0, items))
+                    })?;
+                    if global {
+                        metadata.extend(items);
+                    }
+                }
+                _ => {}
+            }
+            pos = end;
+        }
+
+        let main = main.ok_or(Error::InvalidData)?;
+        if headers.len() as u64 != main.stream_count {
+            return Err(Error::InvalidData);
+        }
+        headers.sort_by_key(|h| h.id);
+
+        self.streams.clear();
+        for header in headers {
+            let timebase = main.time_bases[header.time_base_id];
+            let mut st = Stream::from_params(&header.params, timebase);
+            st.id = header.id as isize;
+            let index = info.add_stream(st);
+            self.streams.push(NutStream {
+                index,
+                timebase,
+                msb_pts_shift: header.msb_pts_shift,
+                decode_delay: header.decode_delay,
+                last_pts: 0,
+            });
+        }
+        for (key, value) in metadata {
+            info.metadata.insert(key, value);
+        }
+        self.main = Some(main);
+
+        Ok(SeekFrom::Current(pos as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        let data = buf.data();
+        need(data, 1)?;
+        if data[0] == STARTCODE_PREFIX {
+            let (startcode, payload, end) = read_packet(data, 0)?;
+            if startcode == SYNCPOINT_STARTCODE {
+                self.read_syncpoint(payload)?;
+            }
+            return Ok((SeekFrom::Current(end as i64), Event::Continue));
+        }
+
+        match self.read_frame(data)? {
+            (end, Some(pkt)) => Ok((SeekFrom::Current(end as i64), Event::NewPacket(pkt))),
+            (end, None) => Ok((SeekFrom::Current(end as i64), Event::Continue)),
+        }
+    }
+}
+
+struct NutDemuxerDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for NutDemuxerDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(NutDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if data.starts_with(FILE_ID) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// NUT demuxer descriptor.
+pub const NUT_DEMUXER_DESCR: &dyn demuxer::Descriptor = &NutDemuxerDescr {
+    d: demuxer::Descr {
+        name: "nut",
+        demuxer: "nut",
+        description: "NUT",
+        extensions: &["nut"],
+        mime: &[],
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::metadata::MetaValue;
+    use crate::demuxer::{read_packets, Context};
+    use std::io::Cursor;
+
+    /// Frame codes using lsb timestamps, elision headers and side data.
+    fn main_header() -> Vec<u8> {
+        let mut data = Vec::new();
+        for &v in &[3, 1, 32768, 1, 1, 1000] {
+            put_v(&mut data, v);
+        }
+        // 0: invalid.
+        put_v(&mut data, flag::INVALID);
+        put_v(&mut data, 0);
+        // 1: coded pts and size, first elision header.
+        put_v(&mut data, flag::CODED_PTS | flag::SIZE_MSB | flag::KEY);
+        put_v(&mut data, 8);
+        put_s(&mut data, 0);
+        put_v(&mut data, 1);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 1);
+        put_s(&mut data, 0);
+        put_v(&mut data, 1);
+        // 2..: pts delta of 40, side data, no elision.
+        put_v(&mut data, flag::SIZE_MSB | flag::SM_DATA | flag::CODED);
+        put_v(&mut data, 8);
+        put_s(&mut data, 40);
+        put_v(&mut data, 1);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 253);
+        put_s(&mut data, 0);
+        put_v(&mut data, 0);
+        // Elision headers.
+        put_v(&mut data, 1);
+        put_vb(&mut data, &[0, 0, 1]);
+        data
+    }
+
+    fn stream_header() -> Vec<u8> {
+        let mut data = Vec::new();
+        put_v(&mut data, 0);
+        put_v(&mut data, CLASS_VIDEO);
+        put_vb(&mut data, b"H264");
+        for &v in &[0, 4, 40, 0, 0] {
+            put_v(&mut data, v);
+        }
+        put_vb(&mut data, &[1, 2, 3]);
+        for &v in &[320, 240, 1, 1, 0] {
+            put_v(&mut data, v);
+        }
+        data
+    }
+
+    fn nut_file() -> Vec<u8> {
+        let mut file = FILE_ID.to_vec();
+        file.extend(packet(MAIN_STARTCODE, &main_header()));
+        file.extend(packet(STREAM_STARTCODE, &stream_header()));
+        let mut info = Vec::new();
+        for &v in &[0, 0, 0, 0] {
+            put_v(&mut info, v);
+        }
+        put_items(
+            &mut info,
+            [("title", &MetaValue::Str("nut".to_owned()))]
+                .iter()
+                .copied(),
+        );
+        file.extend(packet(INFO_STARTCODE, &info));
+
+        let mut syncpoint = Vec::new();
+        put_v(&mut syncpoint, 2000);
+        put_v(&mut syncpoint, 0);
+        file.extend(packet(SYNCPOINT_STARTCODE, &syncpoint));
+
+        // Key frame at 2005, lsb coded, with elided start code.
+        file.extend_from_slice(&[1, (2005 % 16) as u8, 6, 0x65, 0xaa, 0xbb]);
+        // Frame at 2045, with empty side data.
+        file.extend_from_slice(&[2, 0, 3, 0, 0, 0xcc]);
+        // Frame at 3000, with a checksum and an absolute pts.
+        let mut frame = vec![2];
+        put_v(&mut frame, flag::CHECKSUM | flag::CODED_PTS | flag::SM_DATA);
+        put_v(&mut frame, 3000 + 16);
+        put_v(&mut frame, 1);
+        let crc = crc32(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame.push(0xdd);
+        file.extend(frame);
+        file
+    }
+
+    #[test]
+    fn demux() {
+        let file = nut_file();
+        assert_eq!(NUT_DEMUXER_DESCR.probe(&file), 100);
+
+        let r = AccReader::with_capacity(32, Cursor::new(file));
+        let mut c = Context::new(NUT_DEMUXER_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("h264"));
+        assert_eq!(st.get_extradata(), Some(&[1, 2, 3][..]));
+        assert_eq!(st.timebase, Rational64::new(1, 1000));
+        match st.params.kind {
+            Some(MediaKind::Video(ref v)) => assert_eq!((v.width, v.height), (320, 240)),
+            _ => panic!("not a video stream"),
+        }
+        assert_eq!(c.info.metadata.get_str("title"), Some("nut"));
+
+        let packets = read_packets(&mut c);
+        let summary: Vec<_> = packets
+            .iter()
+            .map(|p| {
+                (
+                    p.t.get_pts().map(Timestamp::get_value).unwrap(),
+                    p.is_key,
+                    p.data.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (2005, true, vec![0, 0, 1, 0x65, 0xaa, 0xbb]),
+                (2045, false, vec![0xcc]),
+                (3000, false, vec![0xdd]),
+            ]
+        );
+    }
+
+    #[test]
+    fn corrupt_frame() {
+        let mut file = nut_file();
+        let last = file.len() - 2;
+        file[last] ^= 1;
+
+        let r = AccReader::with_capacity(32, Cursor::new(file));
+        let mut c = Context::new(NUT_DEMUXER_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+        let mut result = Ok(());
+        for _ in 0..8 {
+            if let Err(e) = c.read_event() {
+                result = Err(e);
+                break;
+            }
+        }
+        assert!(matches!(result, Err(Error::InvalidData)));
+    }
+}
@@ -0,0 +1,397 @@
+//!
+//! NUT container, the FFmpeg interchange format.
+//!
+//! Both the demuxer and the muxer use the codec fourccs of FFmpeg, so
+//! streams can be piped losslessly between rust-av and FFmpeg based tools
+//! with their timestamps preserved.
+//!
+
+pub mod demuxer;
+pub mod muxer;
+
+use crate::data::audiosample::Soniton;
+use crate::data::metadata::MetaValue;
+use crate::error::*;
+use crate::ogg::crc32;
+use crate::pcm;
+
+/// String starting a NUT file.
+const FILE_ID: &[u8] = b"nut/multimedia container\0";
+
+/// Packet startcodes.
+const MAIN_STARTCODE: u64 = 0x4e4d_7a56_1f5f_04ad;
+const STREAM_STARTCODE: u64 = 0x4e53_1140_5bf2_f9db;
+const SYNCPOINT_STARTCODE: u64 = 0x4e4b_e4ad_eeca_4569;
+const INFO_STARTCODE: u64 = 0x4e49_ab68_b596_ba78;
+
+/// First byte of every startcode, never a valid frame code.
+const STARTCODE_PREFIX: u8 = b'N';
+
+/// Packets with a larger forward pointer have a header checksum.
+const HEADER_CHECKSUM_THRESHOLD: usize = 4096;
+
+/// Frame flags.
+mod flag {
+    pub const KEY: u64 = 1;
+    pub const EOR: u64 = 2;
+    pub const CODED_PTS: u64 = 8;
+    pub const STREAM_ID: u64 = 16;
+    pub const SIZE_MSB: u64 = 32;
+    pub const CHECKSUM: u64 = 64;
+    pub const RESERVED: u64 = 128;
+    pub const SM_DATA: u64 = 256;
+    pub const HEADER_IDX: u64 = 1024;
+    pub const MATCH_TIME: u64 = 2048;
+    pub const CODED: u64 = 4096;
+    pub const INVALID: u64 = 8192;
+}
+
+/// Stream classes.
+const CLASS_VIDEO: u64 = 0;
+const CLASS_AUDIO: u64 = 1;
+
+/// Fourccs of the codecs, as FFmpeg maps them.
+const CODEC_TAGS: &[(&str, [u8; 4])] = &[
+    ("h264", *b"H264"),
+    ("hevc", *b"HEVC"),
+    ("av1", *b"av01"),
+    ("vp8", *b"VP80"),
+    ("vp9", *b"VP90"),
+    ("mpeg4", *b"FMP4"),
+    ("mpeg1video", *b"mpg1"),
+    ("mpeg2video", *b"mpg2"),
+    ("mjpeg", *b"MJPG"),
+    ("dvvideo", *b"dvsd"),
+    ("ffv1", *b"FFV1"),
+    ("theora", *b"theo"),
+    // Packed RGB24.
+    ("rawvideo", *b"RGB\x18"),
+    ("mp3", [0x55, 0, 0, 0]),
+    ("ac3", [0x00, 0x20, 0, 0]),
+    ("aac", [0xff, 0, 0, 0]),
+    ("flac", [0xac, 0xf1, 0, 0]),
+    ("opus", [0x4f, 0x70, 0, 0]),
+    ("pcm_alaw", *b"ALAW"),
+    ("pcm_mulaw", *b"ULAW"),
+];
+
+/// Returns the fourcc of a codec.
+///
+/// PCM fourccs spell the sample type and size, reversed for big-endian
+/// samples.
+fn codec_tag(codec_id: &str) -> Option<[u8; 4]> {
+    if let Some(&(_, tag)) = CODEC_TAGS.iter().find(|&&(id, _)| id == codec_id) {
+        return Some(tag);
+    }
+    let fmt = pcm::sample_format(codec_id)?;
+    let kind = match (fmt.float, fmt.signed) {
+        (true, _) => b'F',
+        (false, true) => b'S',
+        (false, false) => b'U',
+    };
+    let tag = [b'P', kind, b'D', fmt.bits];
+    if fmt.be && fmt.bits > 8 {
+        Some([tag[3], tag[2], tag[1], tag[0]])
+    } else {
+        Some(tag)
+    }
+}
+
+/// Returns the codec of a fourcc.
+fn tag_codec(tag: [u8; 4]) -> Option<String> {
+    if let Some(&(id, _)) = CODEC_TAGS.iter().find(|&&(_, t)| t == tag) {
+        return Some(id.to_owned());
+    }
+    let (le, be) = ([b'P', b'D'], [tag[3], tag[1]]);
+    let (kind, bits, is_be) = if [tag[0], tag[2]] == le {
+        (tag[1], tag[3], false)
+    } else if be == le {
+        (tag[2], tag[0], true)
+    } else {
+        return None;
+    };
+    let (float, signed) = match kind {
+        b'F' => (true, true),
+        b'S' => (false, true),
+        b'U' => (false, false),
+        _ => return None,
+    };
+    let fmt = Soniton::new(bits, is_be && bits > 8, false, false, float, signed);
+    let codec_id = pcm::codec_id(&fmt);
+    pcm::sample_format(&codec_id).map(|_| codec_id)
+}
+
+/// Reads the NUT data types from buffered data.
+///
+/// Reading past the end of the data asks for more of it, counted from
+/// the start of the buffer.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::InvalidData)?;
+        if self.data.len() < end {
+            return Err(Error::MoreDataNeeded(end));
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    fn get_u32(&mut self) -> Result<u32> {
+        let b = self.get_bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn get_u64(&mut self) -> Result<u64> {
+        let b = self.get_bytes(8)?;
+        let mut v = [0; 8];
+        v.copy_from_slice(b);
+        Ok(u64::from_be_bytes(v))
+    }
+
+    /// Reads a variable length unsigned value.
+    fn get_v(&mut self) -> Result<u64> {
+        let mut v = 0u64;
+        for _ in 0..10 {
+            let b = self.get_u8()?;
+            v = (v << 7) | u64::from(b & 0x7f);
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(Error::InvalidData)
+    }
+
+    /// Reads a variable length signed value.
+    fn get_s(&mut self) -> Result<i64> {
+        let v = self.get_v()?.wrapping_add(1);
+        if v & 1 != 0 {
+            Ok(-((v >> 1) as i64))
+        } else {
+            Ok((v >> 1) as i64)
+        }
+    }
+
+    /// Reads a length prefixed byte string.
+    fn get_vb(&mut self) -> Result<&'a [u8]> {
+        let len = self.get_v()?;
+        self.get_bytes(len as usize)
+    }
+
+    /// Reads a value below `max`.
+    fn get_v_below(&mut self, max: u64) -> Result<u64> {
+        match self.get_v()? {
+            v if v < max => Ok(v),
+            _ => Err(Error::InvalidData),
+        }
+    }
+}
+
+fn put_v(out: &mut Vec<u8>, v: u64) {
+    let mut shift = 63 / 7 * 7;
+    while shift > 0 && v >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        out.push(0x80 | (v >> shift) as u8);
+        shift -= 7;
+    }
+    out.push((v & 0x7f) as u8);
+}
+
+fn put_s(out: &mut Vec<u8>, v: i64) {
+    if v > 0 {
+        put_v(out, (v as u64) * 2 - 1);
+    } else {
+        put_v(out, v.unsigned_abs() * 2);
+    }
+}
+
+fn put_vb(out: &mut Vec<u8>, data: &[u8]) {
+    put_v(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Builds a packet from its startcode and payload.
+fn packet(startcode: u64, payload: &[u8]) -> Vec<u8> {
+    let mut packet = startcode.to_be_bytes().to_vec();
+    let forward_ptr = payload.len() + 4;
+    put_v(&mut packet, forward_ptr as u64);
+    if forward_ptr > HEADER_CHECKSUM_THRESHOLD {
+        let crc = crc32(&packet);
+        packet.extend_from_slice(&crc.to_be_bytes());
+    }
+    packet.extend_from_slice(payload);
+    packet.extend_from_slice(&crc32(payload).to_be_bytes());
+    packet
+}
+
+/// Reads the packet at `pos`, returning its startcode, its checked payload
+/// and the position following it.
+fn read_packet(data: &[u8], pos: usize) -> Result<(u64, &[u8], usize)> {
+    let mut r = Reader::new(data, pos);
+    let startcode = r.get_u64()?;
+    let forward_ptr = r.get_v()? as usize;
+    if forward_ptr < 4 {
+        return Err(Error::InvalidData);
+    }
+    if forward_ptr > HEADER_CHECKSUM_THRESHOLD {
+        let header = &data[pos..r.pos];
+        if r.get_u32()? != crc32(header) {
+            return Err(Error::InvalidData);
+        }
+    }
+    let payload = r.get_bytes(forward_ptr - 4)?;
+    if r.get_u32()? != crc32(payload) {
+        return Err(Error::InvalidData);
+    }
+    Ok((startcode, payload, r.pos))
+}
+
+/// Parses a packet payload, which is complete.
+fn parse_payload<'a, T>(
+    payload: &'a [u8],
+    f: impl FnOnce(&mut Reader<'a>) -> Result<T>,
+) -> Result<T> {
+    f(&mut Reader::new(payload, 0)).map_err(|e| match e {
+        Error::MoreDataNeeded(_) => Error::InvalidData,
+        e => e,
+    })
+}
+
+/// Reads the items of an info packet or of frame side data, timestamps
+/// being coded with `time_base_count` time bases.
+fn read_items(r: &mut Reader, time_base_count: u64) -> Result<Vec<(String, MetaValue)>> {
+    let count = r.get_v()?;
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let name = String::from_utf8_lossy(r.get_vb()?).into_owned();
+        let value = match r.get_s()? {
+            -1 => MetaValue::Str(String::from_utf8_lossy(r.get_vb()?).into_owned()),
+            -2 => {
+                r.get_vb()?;
+                MetaValue::Str(String::from_utf8_lossy(r.get_vb()?).into_owned())
+            }
+            -3 => MetaValue::I64(r.get_s()?),
+            -4 => MetaValue::I64((r.get_v()? / time_base_count.max(1)) as i64),
+            v if v < -4 => MetaValue::Pair(r.get_s()?, -v - 4),
+            v => MetaValue::I64(v),
+        };
+        items.push((name, value));
+    }
+    Ok(items)
+}
+
+/// Writes a UTF-8 info item value.
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    put_s(out, -1);
+    put_vb(out, s.as_bytes());
+}
+
+/// Writes the items of an info packet.
+fn put_items<'a>(out: &mut Vec<u8>, items: impl Iterator<Item = (&'a str, &'a MetaValue)>) {
+    let mut data = Vec::new();
+    let mut count = 0;
+    for (name, value) in items {
+        put_vb(&mut data, name.as_bytes());
+        match *value {
+            MetaValue::Str(ref s) => put_string(&mut data, s),
+            MetaValue::I64(v) if v >= 0 => put_s(&mut data, v),
+            MetaValue::I64(v) => {
+                put_s(&mut data, -3);
+                put_s(&mut data, v);
+            }
+            MetaValue::U64(v) if v <= i64::MAX as u64 => put_s(&mut data, v as i64),
+            MetaValue::U64(v) => put_string(&mut data, &v.to_string()),
+            MetaValue::F64(v) => put_string(&mut data, &v.to_string()),
+            MetaValue::Bool(v) => put_s(&mut data, i64::from(v)),
+            MetaValue::Pair(num, den) if den > 0 && den < i64::MAX - 4 => {
+                put_s(&mut data, -den - 4);
+                put_s(&mut data, num);
+            }
+            MetaValue::Pair(num, den) => put_string(&mut data, &format!("{}/{}", num, den)),
+        }
+        count += 1;
+    }
+    put_v(out, count);
+    out.extend_from_slice(&data);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        for &v in &[0u64, 1, 127, 128, 16383, 16384, u64::MAX] {
+            let mut data = Vec::new();
+            put_v(&mut data, v);
+            assert_eq!(Reader::new(&data, 0).get_v().unwrap(), v);
+        }
+        let mut data = Vec::new();
+        put_v(&mut data, 300);
+        assert_eq!(data, [0x82, 0x2c]);
+
+        for &v in &[0i64, 1, -1, 64, -64, i64::MAX] {
+            let mut data = Vec::new();
+            put_s(&mut data, v);
+            assert_eq!(Reader::new(&data, 0).get_s().unwrap(), v);
+        }
+
+        match Reader::new(&[0x80, 0x80], 0).get_v() {
+            Err(Error::MoreDataNeeded(3)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(codec_tag("pcm_s16le"), Some(*b"PSD\x10"));
+        assert_eq!(codec_tag("pcm_s24be"), Some(*b"\x18DSP"));
+        assert_eq!(codec_tag("pcm_u8"), Some(*b"PUD\x08"));
+        assert_eq!(codec_tag("h264"), Some(*b"H264"));
+        assert_eq!(codec_tag("unknown"), None);
+
+        for id in &[
+            "pcm_s16le",
+            "pcm_s24be",
+            "pcm_f32le",
+            "pcm_u8",
+            "h264",
+            "flac",
+            "rawvideo",
+        ] {
+            assert_eq!(tag_codec(codec_tag(id).unwrap()).as_deref(), Some(*id));
+        }
+        assert_eq!(tag_codec(*b"XXXX"), None);
+    }
+
+    #[test]
+    fn packets() {
+        for &len in &[0, 16, 5000] {
+            let payload = vec![7; len];
+            let data = packet(INFO_STARTCODE, &payload);
+            let (startcode, read, end) = read_packet(&data, 0).unwrap();
+            assert_eq!(
+                (startcode, read, end),
+                (INFO_STARTCODE, &payload[..], data.len())
+            );
+
+            let mut corrupt = data.clone();
+            *corrupt.last_mut().unwrap() ^= 1;
+            assert!(read_packet(&corrupt, 0).is_err());
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//!
+//! NUT muxer.
+//!
+//! Every frame is written with the same frame code, storing its full
+//! timestamp, its size and a checksum. A syncpoint precedes the first
+//! frame and any frame starting more than `MAX_DISTANCE` bytes after the
+//! previous syncpoint.
+//!
+
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::MediaKind;
+use crate::data::value::Value;
+use crate::error::*;
+use crate::muxer::{self, Muxer, Serializer};
+use crate::ogg::crc32;
+use crate::rational::Rational64;
+
+use super::*;
+
+/// Maximum distance between syncpoints.
+const MAX_DISTANCE: u64 = 32768;
+/// Timestamps are always coded in full, this only sizes their range.
+const MSB_PTS_SHIFT: u32 = 7;
+
+/// Frame code of every frame, 0 being invalid.
+const FRAME_CODE: u8 = 1;
+const FRAME_FLAGS: u64 = flag::CODED | flag::STREAM_ID | flag::CODED_PTS | flag::SIZE_MSB;
+
+/// NUT muxer.
+#[derive(Default)]
+pub struct NutMuxer {
+    info: Option<GlobalInfo>,
+    time_bases: Vec<Rational64>,
+    stream_time_bases: Vec<usize>,
+    frames: Option<Arc<Frames>>,
+    pos: u64,
+    last_syncpoint: Option<u64>,
+}
+
+/// Builds the frames of the configured streams.
+struct Frames {
+    timebases: Vec<Rational64>,
+}
+
+impl Frames {
+    /// Returns the stream index and the timestamp of a packet, in the
+    /// timebase of its stream.
+    fn timestamp(&self, pkt: &Packet) -> Result<(usize, i64)> {
+        let index = pkt.stream_index as usize;
+        let timebase = *self
+            .timebases
+            .get(index)
+            .filter(|_| pkt.stream_index >= 0)
+            .ok_or(Error::InvalidData)?;
+
+        let t = pkt.t.with_default_timebase(timebase);
+        let pts = t
+            .get_pts()
+            .or_else(|| t.get_dts())
+            .ok_or(Error::InvalidData)?
+            .rescale(timebase)?
+            .get_value();
+        if pts < 0 {
+            return Err(Error::Unsupported("negative timestamps in NUT".to_owned()));
+        }
+        Ok((index, pts))
+    }
+}
+
+impl Serializer for Frames {
+    fn serialize(&self, pkt: &Packet) -> Result<Vec<u8>> {
+        let (index, pts) = self.timestamp(pkt)?;
+
+        let mut frame = Vec::with_capacity(pkt.data.len() + 32);
+        frame.push(FRAME_CODE);
+        let key = if pkt.is_key { flag::KEY } else { 0 };
+        put_v(&mut frame, flag::CHECKSUM | key);
+        put_v(&mut frame, index as u64);
+        put_v(&mut frame, pts as u64 + (1 << MSB_PTS_SHIFT));
+        put_v(&mut frame, pkt.data.len() as u64);
+        let crc = crc32(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame.extend_from_slice(&pkt.data);
+        Ok(frame)
+    }
+}
+
+impl NutMuxer {
+    /// Creates a new muxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write(&mut self, out: &mut dyn Write, data: &[u8]) -> Result<()> {
+        out.write_all(data)?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+
+    fn main_header(&self, stream_count: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        put_v(&mut data, 3);
+        put_v(&mut data, stream_count as u64);
+        put_v(&mut data, MAX_DISTANCE);
+        put_v(&mut data, self.time_bases.len() as u64);
+        for tb in &self.time_bases {
+            put_v(&mut data, *tb.numer() as u64);
+            put_v(&mut data, *tb.denom() as u64);
+        }
+
+        put_v(&mut data, flag::INVALID);
+        put_v(&mut data, 0);
+        // The remaining codes, 'N' excluded, are all coded frames.
+        put_v(&mut data, FRAME_FLAGS);
+        put_v(&mut data, 6);
+        put_s(&mut data, 0);
+        put_v(&mut data, 1);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 254);
+
+        // No elision headers.
+        put_v(&mut data, 0);
+        data
+    }
+
+    fn stream_header(&self, index: usize) -> Result<Vec<u8>> {
+        let st = &self.info.as_ref().ok_or(Error::InvalidData)?.streams[index];
+        let codec_id = st.params.codec_id.as_deref().unwrap_or("");
+        let tag = codec_tag(codec_id)
+            .ok_or_else(|| Error::Unsupported(format!("codec {:?} in NUT", codec_id)))?;
+        let tb = st.timebase;
+
+        let mut data = Vec::new();
+        put_v(&mut data, index as u64);
+        let class = match st.params.kind {
+            Some(MediaKind::Video(_)) => CLASS_VIDEO,
+            Some(MediaKind::Audio(_)) => CLASS_AUDIO,
+            Some(MediaKind::Data(_)) => {
+                return Err(Error::Unsupported("data streams in NUT".to_owned()))
+            }
+            None => return Err(Error::InvalidData),
+        };
+        put_v(&mut data, class);
+        put_vb(&mut data, &tag);
+        put_v(&mut data, self.stream_time_bases[index] as u64);
+        put_v(&mut data, u64::from(MSB_PTS_SHIFT));
+        // About a second, frames are checksummed anyway.
+        put_v(&mut data, (*tb.denom() / *tb.numer()).max(1) as u64);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_vb(&mut data, st.get_extradata().unwrap_or(&[]));
+
+        match st.params.kind {
+            Some(MediaKind::Video(ref video)) => {
+                put_v(&mut data, video.width as u64);
+                put_v(&mut data, video.height as u64);
+                put_v(&mut data, 0);
+                put_v(&mut data, 0);
+                put_v(&mut data, 0);
+            }
+            Some(MediaKind::Audio(ref audio)) => {
+                put_v(&mut data, audio.rate as u64);
+                put_v(&mut data, 1);
+                put_v(
+                    &mut data,
+                    audio.map.as_ref().map_or(0, |map| map.len()) as u64,
+                );
+            }
+            _ => {}
+        }
+
+        Ok(data)
+    }
+
+    fn info_packet(info: &GlobalInfo) -> Vec<u8> {
+        let mut data = Vec::new();
+        // Global information: no stream, no chapter.
+        put_v(&mut data, 0);
+        put_s(&mut data, 0);
+        put_v(&mut data, 0);
+        put_v(&mut data, 0);
+        put_items(&mut data, info.metadata.iter());
+        data
+    }
+
+    fn write_syncpoint(&mut self, out: &mut dyn Write, pts: i64, index: usize) -> Result<()> {
+        let back_ptr = self.pos - self.last_syncpoint.unwrap_or(0);
+        let mut data = Vec::new();
+        let count = self.time_bases.len() as u64;
+        put_v(
+            &mut data,
+            pts as u64 * count + self.stream_time_bases[index] as u64,
+        );
+        put_v(&mut data, back_ptr / 16);
+
+        self.last_syncpoint = Some(self.pos);
+        self.write(out, &packet(SYNCPOINT_STARTCODE, &data))
+    }
+}
+
+impl Muxer for NutMuxer {
+    fn configure(&mut self) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+        if info.streams.is_empty() {
+            return Err(Error::InvalidData);
+        }
+
+        self.time_bases.clear();
+        self.stream_time_bases.clear();
+        for st in &info.streams {
+            let tb = st.timebase.reduced();
+            if *tb.numer() <= 0 || *tb.denom() <= 0 {
+                return Err(Error::InvalidData);
+            }
+            let id = match self.time_bases.iter().position(|&t| t == tb) {
+                Some(id) => id,
+                None => {
+                    self.time_bases.push(tb);
+                    self.time_bases.len() - 1
+                }
+            };
+            self.stream_time_bases.push(id);
+        }
+        self.frames = Some(Arc::new(Frames {
+            timebases: info.streams.iter().map(|st| st.timebase).collect(),
+        }));
+        for index in 0..info.streams.len() {
+            self.stream_header(index)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, out: &mut dyn Write) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+
+        let mut data = FILE_ID.to_vec();
+        data.extend(packet(
+            MAIN_STARTCODE,
+            &self.main_header(info.streams.len()),
+        ));
+        for index in 0..info.streams.len() {
+            data.extend(packet(STREAM_STARTCODE, &self.stream_header(index)?));
+        }
+        if !info.metadata.is_empty() {
+            data.extend(packet(INFO_STARTCODE, &Self::info_packet(info)));
+        }
+
+        self.pos = 0;
+        self.last_syncpoint = None;
+        self.write(out, &data)
+    }
+
+    fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+        let frames = self.frames.as_ref().ok_or(Error::InvalidData)?;
+        let data = frames.serialize(&pkt)?;
+        self.write_serialized(out, pkt, data)
+    }
+
+    fn serializer(&self) -> Option<Arc<dyn Serializer>> {
+        self.frames
+            .clone()
+            .map(|frames| frames as Arc<dyn Serializer>)
+    }
+
+    fn write_serialized(
+        &mut self,
+        out: &mut dyn Write,
+        pkt: Arc<Packet>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let frames = self.frames.as_ref().ok_or(Error::InvalidData)?;
+        let (index, pts) = frames.timestamp(&pkt)?;
+
+        let far = self
+            .last_syncpoint
+            .is_none_or(|pos| self.pos - pos > MAX_DISTANCE);
+        if far {
+            self.write_syncpoint(out, pts, index)?;
+        }
+
+        self.write(out, &data)
+    }
+
+    fn write_trailer(&mut self, _out: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, _val: Value<'a>) -> Result<()> {
+        Err(Error::Unsupported(format!("{} key", key)))
+    }
+}
+
+struct NutMuxerDescr {
+    d: muxer::Descr,
+    caps: muxer::Capabilities,
+}
+
+impl muxer::Descriptor for NutMuxerDescr {
+    fn create(&self) -> Box<dyn Muxer> {
+        Box::new(NutMuxer::new())
+    }
+    fn describe(&self) -> &muxer::Descr {
+        &self.d
+    }
+    fn capabilities(&self) -> &muxer::Capabilities {
+        &self.caps
+    }
+}
+
+/// NUT muxer descriptor.
+pub const NUT_MUXER_DESCR: &dyn muxer::Descriptor = &NutMuxerDescr {
+    d: muxer::Descr {
+        name: "nut",
+        demuxer: "nut",
+        description: "NUT",
+        extensions: &["nut"],
+        mime: &[],
+    },
+    caps: muxer::Capabilities {
+        codecs: None,
+        max_streams: None,
+        parameter_sets: muxer::ParameterSets::Any,
+        variable_frame_rate: true,
+        seekable_output: false,
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::audiosample::ChannelMap;
+    use crate::data::budget::Budget;
+    use crate::data::metadata::Metadata;
+    use crate::data::params::{AudioInfo, CodecParams, VideoInfo};
+    use crate::data::timeinfo::Timestamp;
+    use crate::demuxer::{read_packets, Context};
+    use crate::nut::demuxer::NUT_DEMUXER_DESCR;
+    use crate::stream::Stream;
+    use std::io::Cursor;
+
+    fn info() -> GlobalInfo {
+        let video = CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width: 64,
+                height: 48,
+                format: None,
+            })),
+            codec_id: Some("h264".to_owned()),
+            extradata: Some(vec![1, 0x64, 0, 0x1f]),
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+        let audio = CodecParams {
+            kind: Some(MediaKind::Audio(AudioInfo {
+                rate: 48000,
+                map: Some(ChannelMap::default_map(2)),
+                format: None,
+            })),
+            codec_id: Some("pcm_s16le".to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        info.add_stream(Stream::from_params(&video, Rational64::new(1, 90000)));
+        info.add_stream(Stream::from_params(&audio, Rational64::new(1, 48000)));
+        info.metadata.insert("title", "roundtrip".to_owned());
+        info.metadata.insert("track", 3i64);
+        info
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(index: isize, pts: i64, key: bool, size: usize) -> Arc<Packet> {
+        let mut pkt = Packet::with_capacity(size);
+        pkt.data.extend((0..size).map(|i| (i + pts as usize) as u8));
+        pkt.stream_index = index;
+        // Timed in the timebase of the stream, left unset.
+        let timebase = Rational64::new(1, if index == 0 { 90000 } else { 48000 });
+        pkt.t.set_pts(Some(Timestamp::new(pts, timebase))).unwrap();
+        pkt.t.timebase = None;
+        pkt.is_key = key;
+        Arc::new(pkt)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut muxer = NutMuxer::new();
+        muxer.set_global_info(info()).unwrap();
+        muxer.configure().unwrap();
+
+        let packets = vec![
+            packet(0, 0, true, 20000),
+            packet(1, 0, true, 4000),
+            packet(0, 3003, false, 20000),
+            packet(1, 1024, true, 4000),
+            packet(0, 6006, false, 0),
+        ];
+        let mut file = Vec::new();
+        muxer.write_header(&mut file).unwrap();
+        for pkt in &packets {
+            muxer.write_packet(&mut file, pkt.clone()).unwrap();
+        }
+        muxer.write_trailer(&mut file).unwrap();
+
+        let syncpoints = file
+            .windows(8)
+            .filter(|w| w == &SYNCPOINT_STARTCODE.to_be_bytes())
+            .count();
+        assert_eq!(syncpoints, 2);
+
+        let r = AccReader::with_capacity(1024, Cursor::new(file));
+        let mut c = Context::new(NUT_DEMUXER_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        assert_eq!(c.info.streams.len(), 2);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params, info().streams[0].params);
+        assert_eq!(st.timebase, Rational64::new(1, 90000));
+        let st = &c.info.streams[1];
+        assert_eq!(st.params.codec_id.as_deref(), Some("pcm_s16le"));
+        assert_eq!(st.timebase, Rational64::new(1, 48000));
+        assert_eq!(c.info.metadata.get_str("title"), Some("roundtrip"));
+        assert_eq!(c.info.metadata.get_i64("track"), Some(3));
+
+        let demuxed = read_packets(&mut c);
+        assert_eq!(demuxed.len(), packets.len());
+        for (pkt, orig) in demuxed.iter().zip(&packets) {
+            assert_eq!(pkt.stream_index, orig.stream_index);
+            let timebase = c.info.streams[pkt.stream_index as usize].timebase;
+            assert_eq!(
+                pkt.t.get_pts(),
+                orig.t.with_default_timebase(timebase).get_pts()
+            );
+            assert_eq!(pkt.is_key, orig.is_key);
+            assert_eq!(pkt.data, orig.data);
+        }
+    }
+
+    #[test]
+    fn threads() {
+        let packets: Vec<_> = (0..100)
+            .map(|i| packet(i % 2, i as i64 * 1000, i % 10 == 0, 100 * i as usize))
+            .collect();
+        let mux = |threads, budget| {
+            let out = Output::default();
+            let mut ctx = muxer::Context::from_descriptor(NUT_MUXER_DESCR, Box::new(out.clone()));
+            ctx.set_threads(threads);
+            ctx.set_budget(Budget::new(budget));
+            ctx.set_global_info(info()).unwrap();
+            ctx.configure().unwrap();
+            ctx.write_header().unwrap();
+            for pkt in &packets {
+                ctx.write_packet(pkt.clone()).unwrap();
+            }
+            ctx.write_trailer().unwrap();
+            let data = out.0.lock().unwrap().clone();
+            data
+        };
+        let data = mux(1, 0);
+        assert_eq!(mux(4, usize::MAX), data);
+        // Fewer packets in flight.
+        assert_eq!(mux(4, 20000), data);
+
+        let mut ctx = muxer::Context::from_descriptor(NUT_MUXER_DESCR, Box::new(Output::default()));
+        ctx.set_threads(2);
+        ctx.set_global_info(info()).unwrap();
+        ctx.configure().unwrap();
+        ctx.write_header().unwrap();
+        // The error shows once the packet is committed.
+        let written = ctx.write_packet(packet(3, 0, true, 1));
+        assert!(written.and_then(|_| ctx.write_trailer()).is_err());
+    }
+
+    #[test]
+    fn unsupported_codec() {
+        let mut info = info();
+        info.streams[1].params.codec_id = Some("unknown".to_owned());
+        let mut muxer = NutMuxer::new();
+        muxer.set_global_info(info).unwrap();
+        assert!(matches!(muxer.configure(), Err(Error::Unsupported(_))));
+    }
+}
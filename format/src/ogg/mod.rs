@@ -0,0 +1,235 @@
+//!
+//! Ogg encapsulation (RFC 3533).
+//!
+//! `PageWriter` splits the packets of a logical bitstream into pages,
+//! the codec mappings built on top of it live in the submodules.
+//!
+
+pub mod opus;
+
+use std::io::Write;
+
+use crate::error::*;
+
+/// The page continues a packet started in the previous page.
+const FLAG_CONTINUED: u8 = 0x01;
+/// First page of a logical bitstream.
+const FLAG_BOS: u8 = 0x02;
+/// Last page of a logical bitstream.
+const FLAG_EOS: u8 = 0x04;
+
+/// Granule position of pages where no packet ends.
+const NO_GRANULE: u64 = !0;
+
+const MAX_SEGMENTS: usize = 255;
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut r = (i as u32) << 24;
+        let mut j = 0;
+        while j < 8 {
+            r = if r & 0x8000_0000 != 0 {
+                (r << 1) ^ 0x04c1_1db7
+            } else {
+                r << 1
+            };
+            j += 1;
+        }
+        table[i] = r;
+        i += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Computes the page checksum.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &b| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+/// Packs the packets of a logical bitstream into pages.
+#[derive(Debug)]
+pub(crate) struct PageWriter {
+    serial: u32,
+    sequence: u32,
+    started: bool,
+    continued: bool,
+    granule: u64,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl PageWriter {
+    /// Creates a writer for the logical bitstream `serial`.
+    pub fn new(serial: u32) -> Self {
+        PageWriter {
+            serial,
+            sequence: 0,
+            started: false,
+            continued: false,
+            granule: NO_GRANULE,
+            segments: Vec::with_capacity(MAX_SEGMENTS),
+            data: Vec::new(),
+        }
+    }
+
+    /// Tells if some packet data is waiting to be written.
+    pub fn is_pending(&self) -> bool {
+        !self.segments.is_empty()
+    }
+
+    /// Adds a packet ending at `granule`, writing the pages it fills.
+    pub fn add_packet(&mut self, out: &mut dyn Write, data: &[u8], granule: u64) -> Result<()> {
+        let mut rest = data;
+        loop {
+            while self.segments.len() < MAX_SEGMENTS {
+                let len = rest.len().min(255);
+                self.segments.push(len as u8);
+                self.data.extend_from_slice(&rest[..len]);
+                rest = &rest[len..];
+                if len < 255 {
+                    self.granule = granule;
+                    return Ok(());
+                }
+            }
+            self.write_page(out, false)?;
+        }
+    }
+
+    /// Writes the pending data as a page, the last of the bitstream if
+    /// `eos` is set.
+    ///
+    /// An empty page is written if there is no pending data and `eos` is set.
+    pub fn flush(&mut self, out: &mut dyn Write, eos: bool) -> Result<()> {
+        if self.is_pending() || eos {
+            self.write_page(out, eos)?;
+        }
+        Ok(())
+    }
+
+    fn write_page(&mut self, out: &mut dyn Write, eos: bool) -> Result<()> {
+        let mut flags = 0;
+        if self.continued {
+            flags |= FLAG_CONTINUED;
+        }
+        if !self.started {
+            flags |= FLAG_BOS;
+        }
+        if eos {
+            flags |= FLAG_EOS;
+        }
+
+        let mut page = Vec::with_capacity(27 + self.segments.len() + self.data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&self.granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.segments.len() as u8);
+        page.extend_from_slice(&self.segments);
+        page.extend_from_slice(&self.data);
+
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        out.write_all(&page)?;
+
+        self.started = true;
+        self.continued = self.segments.last() == Some(&255);
+        self.sequence += 1;
+        self.granule = NO_GRANULE;
+        self.segments.clear();
+        self.data.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// A parsed page.
+    #[derive(Debug)]
+    pub struct Page {
+        pub flags: u8,
+        pub granule: u64,
+        pub serial: u32,
+        pub sequence: u32,
+        pub segments: Vec<u8>,
+        pub data: Vec<u8>,
+    }
+
+    /// Splits a bitstream into pages, checking their checksum.
+    pub fn parse_pages(mut buf: &[u8]) -> Vec<Page> {
+        let mut pages = Vec::new();
+        while !buf.is_empty() {
+            assert_eq!(&buf[..4], b"OggS");
+            let nsegs = buf[26] as usize;
+            let segments = buf[27..27 + nsegs].to_vec();
+            let size = 27 + nsegs + segments.iter().map(|&s| s as usize).sum::<usize>();
+
+            let mut page = buf[..size].to_vec();
+            page[22..26].copy_from_slice(&[0; 4]);
+            let crc = u32::from_le_bytes([buf[22], buf[23], buf[24], buf[25]]);
+            assert_eq!(crc32(&page), crc);
+
+            pages.push(Page {
+                flags: buf[5],
+                granule: u64::from_le_bytes([
+                    buf[6], buf[7], buf[8], buf[9], buf[10], buf[11], buf[12], buf[13],
+                ]),
+                serial: u32::from_le_bytes([buf[14], buf[15], buf[16], buf[17]]),
+                sequence: u32::from_le_bytes([buf[18], buf[19], buf[20], buf[21]]),
+                data: buf[27 + nsegs..size].to_vec(),
+                segments,
+            });
+            buf = &buf[size..];
+        }
+        pages
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn lacing() {
+        let mut out = Vec::new();
+        let mut w = PageWriter::new(7);
+
+        w.add_packet(&mut out, &[1; 255], 10).unwrap();
+        w.add_packet(&mut out, &[2; 100], 20).unwrap();
+        w.flush(&mut out, false).unwrap();
+
+        let big = vec![3; 255 * 300];
+        w.add_packet(&mut out, &big, 30).unwrap();
+        w.flush(&mut out, true).unwrap();
+
+        let pages = parse_pages(&out);
+        assert_eq!(pages.len(), 3);
+
+        assert_eq!(pages[0].flags, FLAG_BOS);
+        assert_eq!(pages[0].segments, vec![255, 0, 100]);
+        assert_eq!(pages[0].granule, 20);
+
+        assert_eq!(pages[1].flags, 0);
+        assert_eq!(pages[1].segments.len(), 255);
+        assert_eq!(pages[1].granule, NO_GRANULE);
+
+        assert_eq!(pages[2].flags, FLAG_CONTINUED | FLAG_EOS);
+        assert_eq!(pages[2].segments.len(), 46);
+        assert_eq!(pages[2].segments.last(), Some(&0));
+        assert_eq!(pages[2].granule, 30);
+        assert_eq!(pages[2].sequence, 2);
+        assert!(pages.iter().all(|p| p.serial == 7));
+    }
+}
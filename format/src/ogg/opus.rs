@@ -0,0 +1,466 @@
+//!
+//! Ogg Opus muxer (RFC 7845).
+//!
+//! Each stream of the `GlobalInfo` is written as a link of a chained
+//! bitstream: packets of a later stream end the current link and start
+//! the next one with its own headers and the following serial number.
+//!
+//! Granule positions count the decoded 48 kHz samples, pre-skip included.
+//! A packet whose duration is shorter than the one coded in it is taken as
+//! the last of its link and trims the decoder output to that duration.
+//!
+
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind};
+use crate::data::value::Value;
+use crate::error::*;
+use crate::muxer::{Capabilities, Descr, Descriptor, Muxer, ParameterSets};
+use crate::ogg::PageWriter;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Opus granule positions are always expressed at 48 kHz.
+const RATE: i64 = 48_000;
+
+/// Maximum duration of an Opus packet.
+const MAX_PACKET_SAMPLES: u64 = 5760;
+
+/// Returns the number of 48 kHz samples coded in an Opus packet.
+///
+/// See RFC 6716, section 3.1.
+pub fn packet_samples(data: &[u8]) -> Option<u64> {
+    let toc = *data.first()?;
+    let config = toc >> 3;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][(config & 3) as usize],
+        12..=15 => [480, 960][(config & 1) as usize],
+        _ => [120, 240, 480, 960][(config & 3) as usize],
+    };
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => u64::from(*data.get(1)? & 0x3f),
+    };
+    let samples = frame * frames;
+
+    if frames == 0 || samples > MAX_PACKET_SAMPLES {
+        None
+    } else {
+        Some(samples)
+    }
+}
+
+/// Builds the identification header of a stream, returning it along with
+/// its pre-skip.
+///
+/// An `OpusHead` in the extradata is used as is, otherwise a channel
+/// mapping family 0 header is built from the codec parameters.
+fn opus_head(params: &CodecParams) -> Result<(Vec<u8>, u64)> {
+    if let Some(ref extradata) = params.extradata {
+        if extradata.len() >= 19 && extradata.starts_with(b"OpusHead") {
+            let pre_skip = u16::from_le_bytes([extradata[10], extradata[11]]);
+            return Ok((extradata.clone(), u64::from(pre_skip)));
+        }
+    }
+
+    let info = match params.kind {
+        Some(MediaKind::Audio(ref info)) => info,
+        _ => return Err(Error::InvalidData),
+    };
+    let channels = info.map.as_ref().map_or(0, |map| map.len());
+    match channels {
+        1 | 2 => {}
+        0 => return Err(Error::InvalidData),
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "{} channels without an OpusHead",
+                channels
+            )))
+        }
+    }
+    if params.delay > usize::from(u16::MAX) {
+        return Err(Error::InvalidData);
+    }
+
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    head.push(channels as u8);
+    head.extend_from_slice(&(params.delay as u16).to_le_bytes());
+    head.extend_from_slice(&(info.rate as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+
+    Ok((head, params.delay as u64))
+}
+
+/// Builds the comment header.
+fn opus_tags(vendor: &str, comments: &[String]) -> Vec<u8> {
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        tags.extend_from_slice(comment.as_bytes());
+    }
+    tags
+}
+
+/// A link of the chained bitstream being written.
+#[derive(Debug)]
+struct Link {
+    index: usize,
+    timebase: Rational64,
+    pre_skip: u64,
+    writer: PageWriter,
+    granule: u64,
+    page_samples: u64,
+    trimmed: bool,
+}
+
+/// Ogg Opus muxer.
+pub struct OpusMuxer {
+    info: Option<GlobalInfo>,
+    vendor: String,
+    comments: Vec<String>,
+    page_duration: u64,
+    serial: u32,
+    link: Option<Link>,
+}
+
+impl Default for OpusMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpusMuxer {
+    /// Creates a new muxer.
+    pub fn new() -> Self {
+        OpusMuxer {
+            info: None,
+            vendor: "rust-av".to_owned(),
+            comments: Vec::new(),
+            page_duration: 1000,
+            serial: 0,
+            link: None,
+        }
+    }
+
+    fn stream(&self, index: usize) -> Result<&Stream> {
+        self.info
+            .as_ref()
+            .and_then(|info| info.streams.get(index))
+            .ok_or(Error::InvalidData)
+    }
+
+    fn start_link(&mut self, out: &mut dyn Write, index: usize) -> Result<()> {
+        let st = self.stream(index)?;
+        let (head, pre_skip) = opus_head(&st.params)?;
+        let timebase = st.timebase;
+
+        let mut writer = PageWriter::new(self.serial);
+        self.serial = self.serial.wrapping_add(1);
+        writer.add_packet(out, &head, 0)?;
+        writer.flush(out, false)?;
+        writer.add_packet(out, &opus_tags(&self.vendor, &self.comments), 0)?;
+        writer.flush(out, false)?;
+
+        self.link = Some(Link {
+            index,
+            timebase,
+            pre_skip,
+            writer,
+            granule: 0,
+            page_samples: 0,
+            trimmed: false,
+        });
+
+        Ok(())
+    }
+
+    fn end_link(&mut self, out: &mut dyn Write) -> Result<()> {
+        if let Some(mut link) = self.link.take() {
+            link.writer.flush(out, true)?;
+        }
+        Ok(())
+    }
+
+    /// Converts a packet duration to 48 kHz samples.
+    fn duration_samples(pkt: &Packet, timebase: Rational64) -> Option<u64> {
+        let duration = pkt.t.with_default_timebase(timebase).get_duration()?;
+        let samples = duration.rescale(Rational64::new(1, RATE)).ok()?;
+        Some(samples.get_value())
+    }
+}
+
+impl Muxer for OpusMuxer {
+    fn configure(&mut self) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+        if info.streams.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        for st in &info.streams {
+            match st.params.codec_id.as_deref() {
+                Some("opus") => {}
+                _ => return Err(Error::Unsupported("non-Opus streams".to_owned())),
+            }
+            opus_head(&st.params)?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self, out: &mut dyn Write) -> Result<()> {
+        self.start_link(out, 0)
+    }
+
+    fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+        if pkt.stream_index < 0 {
+            return Err(Error::InvalidData);
+        }
+        let index = pkt.stream_index as usize;
+        let current = self.link.as_ref().map_or(0, |link| link.index);
+        if index < current {
+            return Err(Error::InvalidData);
+        }
+        if index > current || self.link.is_none() {
+            self.end_link(out)?;
+            self.start_link(out, index)?;
+        }
+
+        let page_duration = self.page_duration * RATE as u64 / 1000;
+        let link = self.link.as_mut().unwrap();
+        if link.trimmed {
+            return Err(Error::InvalidData);
+        }
+
+        let coded = packet_samples(&pkt.data).ok_or(Error::InvalidData)?;
+        link.granule = match Self::duration_samples(&pkt, link.timebase) {
+            Some(duration) if duration < coded => {
+                // The end granule cannot trim the pre-skip samples away.
+                link.trimmed = true;
+                let pre_skip = link.pre_skip.min(link.granule + coded);
+                (link.granule + duration).max(pre_skip)
+            }
+            _ => link.granule + coded,
+        };
+        link.page_samples += coded;
+        link.writer.add_packet(out, &pkt.data, link.granule)?;
+        if link.page_samples >= page_duration {
+            link.writer.flush(out, false)?;
+            link.page_samples = 0;
+        }
+
+        Ok(())
+    }
+
+    fn write_trailer(&mut self, out: &mut dyn Write) -> Result<()> {
+        self.end_link(out)
+    }
+
+    fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("vendor", Value::Str(s)) => self.vendor = s.to_owned(),
+            ("comment", Value::Str(s)) if s.contains('=') => self.comments.push(s.to_owned()),
+            ("page_duration", Value::U64(v)) if v > 0 => self.page_duration = v,
+            ("page_duration", Value::I64(v)) if v > 0 => self.page_duration = v as u64,
+            ("serial", Value::U64(v)) if v <= u64::from(u32::MAX) => self.serial = v as u32,
+            ("serial", Value::I64(v)) if (0..=i64::from(u32::MAX)).contains(&v) => {
+                self.serial = v as u32
+            }
+            ("vendor", _) | ("comment", _) | ("page_duration", _) | ("serial", _) => {
+                return Err(Error::InvalidData)
+            }
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+}
+
+struct OpusDescr {
+    d: Descr,
+    caps: Capabilities,
+}
+
+impl Descriptor for OpusDescr {
+    fn create(&self) -> Box<dyn Muxer> {
+        Box::new(OpusMuxer::new())
+    }
+    fn describe(&self) -> &Descr {
+        &self.d
+    }
+    fn capabilities(&self) -> &Capabilities {
+        &self.caps
+    }
+}
+
+/// Ogg Opus muxer descriptor.
+pub const OPUS_DESCR: &dyn Descriptor = &OpusDescr {
+    d: Descr {
+        name: "opus",
+        demuxer: "ogg",
+        description: "Ogg Opus",
+        extensions: &["opus", "ogg"],
+        mime: &["audio/ogg", "audio/opus"],
+    },
+    caps: Capabilities {
+        codecs: Some(&["opus"]),
+        max_streams: None,
+        parameter_sets: ParameterSets::Any,
+        variable_frame_rate: false,
+        seekable_output: false,
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::audiosample::ChannelMap;
+    use crate::data::metadata::Metadata;
+    use crate::data::params::AudioInfo;
+    use crate::data::timeinfo::Duration;
+    use crate::ogg::test::parse_pages;
+
+    // CELT fullband, 20 ms, one frame.
+    const TOC_20MS: u8 = 31 << 3;
+
+    fn opus_stream(pre_skip: usize) -> Stream {
+        let params = CodecParams {
+            kind: Some(MediaKind::Audio(AudioInfo {
+                rate: 44100,
+                map: Some(ChannelMap::default_map(2)),
+                format: None,
+            })),
+            codec_id: Some("opus".to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: pre_skip,
+        };
+        Stream::from_params(&params, Rational64::new(1, 48000))
+    }
+
+    fn muxer(streams: Vec<Stream>) -> OpusMuxer {
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        for st in streams {
+            info.add_stream(st);
+        }
+        let mut mux = OpusMuxer::new();
+        mux.set_global_info(info).unwrap();
+        mux.configure().unwrap();
+        mux
+    }
+
+    fn packet(index: isize, duration: Option<u64>) -> Arc<Packet> {
+        let mut pkt = Packet::new();
+        pkt.data = vec![TOC_20MS, 0xaa, 0x55];
+        pkt.stream_index = index;
+        // Timed in the timebase of the stream, left unset.
+        let timebase = Rational64::new(1, 48000);
+        pkt.t
+            .set_duration(duration.map(|d| Duration::new(d, timebase)))
+            .unwrap();
+        pkt.t.timebase = None;
+        Arc::new(pkt)
+    }
+
+    #[test]
+    fn samples() {
+        assert_eq!(packet_samples(&[TOC_20MS]), Some(960));
+        assert_eq!(packet_samples(&[(16 << 3) | 1]), Some(240));
+        assert_eq!(packet_samples(&[(3 << 3) | 3, 2]), Some(5760));
+        assert_eq!(packet_samples(&[(3 << 3) | 3, 3]), None);
+        assert_eq!(packet_samples(&[TOC_20MS | 3]), None);
+        assert_eq!(packet_samples(&[]), None);
+    }
+
+    #[test]
+    fn headers() {
+        let mut out = Vec::new();
+        let mut mux = muxer(vec![opus_stream(312)]);
+        mux.set_option("comment", Value::Str("TITLE=Episode 1"))
+            .unwrap();
+        assert!(mux.set_option("comment", Value::Str("bogus")).is_err());
+        mux.write_header(&mut out).unwrap();
+        mux.write_trailer(&mut out).unwrap();
+
+        let pages = parse_pages(&out);
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].flags, 0x02);
+        assert_eq!(&pages[0].data[..8], b"OpusHead");
+        assert_eq!(pages[0].data[9], 2);
+        assert_eq!(
+            u16::from_le_bytes([pages[0].data[10], pages[0].data[11]]),
+            312
+        );
+        assert_eq!(&pages[0].data[12..16], &44100u32.to_le_bytes());
+        assert_eq!(pages[0].granule, 0);
+
+        assert_eq!(&pages[1].data[..8], b"OpusTags");
+        assert!(pages[1].data.ends_with(b"TITLE=Episode 1"));
+        assert_eq!(pages[2].flags, 0x04);
+    }
+
+    #[test]
+    fn granule() {
+        let mut out = Vec::new();
+        let mut mux = muxer(vec![opus_stream(312)]);
+        mux.set_option("page_duration", Value::U64(40)).unwrap();
+        mux.write_header(&mut out).unwrap();
+        for _ in 0..4 {
+            mux.write_packet(&mut out, packet(0, Some(960))).unwrap();
+        }
+        mux.write_packet(&mut out, packet(0, Some(500))).unwrap();
+        assert!(mux.write_packet(&mut out, packet(0, None)).is_err());
+        mux.write_trailer(&mut out).unwrap();
+
+        let pages = parse_pages(&out);
+        let granules: Vec<_> = pages[2..].iter().map(|p| p.granule).collect();
+        assert_eq!(granules, vec![1920, 3840, 4340]);
+        assert_eq!(pages.last().unwrap().flags, 0x04);
+        assert_eq!(pages.last().unwrap().segments.len(), 1);
+    }
+
+    #[test]
+    fn chained() {
+        let mut out = Vec::new();
+        let mut mux = muxer(vec![opus_stream(312), opus_stream(120)]);
+        mux.write_header(&mut out).unwrap();
+        mux.write_packet(&mut out, packet(0, None)).unwrap();
+        mux.write_packet(&mut out, packet(1, None)).unwrap();
+        mux.write_packet(&mut out, packet(1, None)).unwrap();
+        assert!(mux.write_packet(&mut out, packet(0, None)).is_err());
+        mux.write_trailer(&mut out).unwrap();
+
+        let pages = parse_pages(&out);
+        assert_eq!(pages.len(), 6);
+        assert_eq!(pages[2].flags, 0x04);
+        assert_eq!(pages[2].granule, 960);
+        assert_eq!(pages[3].flags, 0x02);
+        assert_ne!(pages[3].serial, pages[0].serial);
+        assert_eq!(
+            u16::from_le_bytes([pages[3].data[10], pages[3].data[11]]),
+            120
+        );
+        assert_eq!(pages[5].granule, 1920);
+        assert_eq!(pages[5].sequence, 2);
+    }
+}
@@ -0,0 +1,258 @@
+//!
+//! Sans-IO parsers.
+//!
+//! A `Parser` drives the parsing core of any demuxer from byte slices:
+//! the caller owns the data, passes the bytes following the ones consumed
+//! so far and is told how many were consumed, so the data can come from
+//! any source (ring buffers, kernel bypass networking, memory maps).
+//!
+//! The frame parsers of the raw elementary streams work on slices on
+//! their own and are re-exported as well.
+//!
+
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom};
+
+use crate::buffer::Buffered;
+use crate::common::GlobalInfo;
+use crate::data::metadata::Metadata;
+use crate::demuxer::{track_event, Demuxer, Descriptor, Event};
+use crate::error::*;
+use crate::limits::Limits;
+
+pub use crate::raw::{ac3, adts, annexb, av1, Frame, Parser as FrameParser};
+
+/// Buffer exposing a slice to a demuxer.
+struct Slice(Cursor<Vec<u8>>);
+
+impl Read for Slice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl BufRead for Slice {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+impl Seek for Slice {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl Buffered for Slice {
+    fn data(&self) -> &[u8] {
+        let data = self.0.get_ref();
+        &data[(self.0.position() as usize).min(data.len())..]
+    }
+    fn grow(&mut self, _len: usize) {}
+}
+
+/// Outcome of a parsing step.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// The headers are parsed, the streams found are in the global
+    /// information.
+    Headers,
+    /// An event is parsed.
+    Event(Event),
+    /// More data is needed, the next slice should hold at least this
+    /// many bytes.
+    NeedMore(usize),
+}
+
+/// Returns the bytes consumed by a demuxer from the seek it requested.
+fn consumed(seek: SeekFrom, len: usize) -> Result<usize> {
+    match seek {
+        SeekFrom::Current(n) if n >= 0 && n as usize <= len => Ok(n as usize),
+        _ => Err(Error::Unsupported("seeking in a parser".to_owned())),
+    }
+}
+
+/// Demuxer driven by byte slices.
+pub struct Parser {
+    demuxer: Box<dyn Demuxer>,
+    info: GlobalInfo,
+    limits: Limits,
+    headers: bool,
+}
+
+impl Parser {
+    /// Creates a new parser from a demuxer.
+    pub fn new(demuxer: Box<dyn Demuxer>) -> Self {
+        Parser {
+            demuxer,
+            info: GlobalInfo {
+                duration: None,
+                timebase: None,
+                streams: Vec::new(),
+                metadata: Metadata::new(),
+                groups: Vec::new(),
+            },
+            limits: Limits::default(),
+            headers: false,
+        }
+    }
+
+    /// Creates a new parser from the demuxer of a format.
+    pub fn from_descriptor(descr: &dyn Descriptor) -> Self {
+        Self::new(descr.create())
+    }
+
+    /// Returns the global information parsed so far.
+    pub fn get_info(&self) -> &GlobalInfo {
+        &self.info
+    }
+
+    /// Returns the resource limits of the parser.
+    pub fn get_limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Sets the resource limits of the parser.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.demuxer.set_limits(&limits);
+        self.limits = limits;
+    }
+
+    /// Parses the data following the bytes consumed so far, `eof` telling
+    /// that no data follows.
+    ///
+    /// Returns the number of bytes consumed along with the step parsed, the
+    /// headers being parsed first.
+    pub fn parse(&mut self, data: &[u8], eof: bool) -> Result<(usize, Step)> {
+        let need_more = |size: usize| Step::NeedMore(size.max(data.len() + 1));
+        let buf: Box<dyn Buffered> = Box::new(Slice(Cursor::new(data.to_vec())));
+
+        if !self.headers {
+            return match self.demuxer.read_headers(&buf, &mut self.info) {
+                Ok(seek) => {
+                    let size = consumed(seek, data.len())?;
+                    self.limits.check_streams(self.info.streams.len())?;
+                    self.limits.check_metadata(&self.info.metadata)?;
+                    self.headers = true;
+                    Ok((size, Step::Headers))
+                }
+                Err(Error::MoreDataNeeded(_)) if eof => Err(Error::InvalidData),
+                Err(Error::MoreDataNeeded(size)) => Ok((0, need_more(size))),
+                Err(err) => Err(err),
+            };
+        }
+
+        let (size, mut event) = loop {
+            let res = self.demuxer.read_event(&buf).and_then(|(seek, event)| {
+                match (consumed(seek, data.len())?, event) {
+                    (0, Event::MoreDataNeeded(size)) => Err(Error::MoreDataNeeded(size)),
+                    // Skipped data, the caller is to advance.
+                    (n, Event::MoreDataNeeded(_)) => Ok((n, Event::Continue)),
+                    res => Ok(res),
+                }
+            });
+            match res {
+                // The demuxer may ask to be called again.
+                Err(Error::MoreDataNeeded(size)) if size <= data.len() => continue,
+                Err(Error::MoreDataNeeded(_)) if eof => {
+                    let (seek, event) = self.demuxer.read_eof(&buf)?;
+                    break (consumed(seek, data.len())?, event);
+                }
+                Err(Error::MoreDataNeeded(size)) => return Ok((0, need_more(size))),
+                res => break res?,
+            }
+        };
+        track_event(&mut self.info, &self.limits, &mut event)?;
+        Ok((size, Step::Event(event)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::caf::CAF_DESCR;
+    use crate::data::packet::Packet;
+    use crate::data::timeinfo::Timestamp;
+    use crate::demuxer::demux_file;
+    use crate::raw::AAC_DESCR;
+
+    /// Parses a file growing the slice only when asked to.
+    fn parse(descr: &'static dyn Descriptor, file: &[u8]) -> (Parser, Vec<Packet>) {
+        let mut parser = Parser::from_descriptor(descr);
+        let mut packets = Vec::new();
+        let mut pos = 0;
+        let mut len = 1;
+        loop {
+            let end = (pos + len).min(file.len());
+            let (size, step) = parser.parse(&file[pos..end], end == file.len()).unwrap();
+            pos += size;
+            match step {
+                Step::NeedMore(size) => len = size,
+                Step::Event(Event::NewPacket(pkt)) => packets.push(pkt),
+                Step::Event(Event::Eof) => break,
+                _ => {}
+            }
+        }
+        (parser, packets)
+    }
+
+    fn assert_same(parsed: &[Packet], demuxed: &[Packet]) {
+        assert_eq!(parsed.len(), demuxed.len());
+        for (a, b) in parsed.iter().zip(demuxed) {
+            assert_eq!(
+                (
+                    &a.data,
+                    a.t.get_pts().map(Timestamp::get_value),
+                    a.t.timebase
+                ),
+                (
+                    &b.data,
+                    b.t.get_pts().map(Timestamp::get_value),
+                    b.t.timebase
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn aac() {
+        let mut file = Vec::new();
+        for i in 0..3 {
+            file.extend_from_slice(&adts::test::header(10));
+            file.extend_from_slice(&[0x21, 0x10, i]);
+        }
+
+        let (parser, packets) = parse(AAC_DESCR, &file);
+        assert_eq!(parser.get_info().streams.len(), 1);
+        assert_same(&packets, &demux_file(AAC_DESCR, file.clone(), 16).1);
+    }
+
+    #[test]
+    fn caf() {
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&44100f64.to_bits().to_be_bytes());
+        desc.extend_from_slice(b"lpcm");
+        for v in &[2u32, 4, 1, 2, 16] {
+            desc.extend_from_slice(&v.to_be_bytes());
+        }
+        for (id, data) in &[(&b"desc"[..], desc), (b"data", vec![0; 4 + 4 * 40])] {
+            file.extend_from_slice(id);
+            file.extend_from_slice(&(data.len() as i64).to_be_bytes());
+            file.extend_from_slice(data);
+        }
+
+        let (_, packets) = parse(CAF_DESCR, &file);
+        assert_eq!(packets.iter().map(|p| p.data.len()).sum::<usize>(), 160);
+        assert_same(&packets, &demux_file(CAF_DESCR, file.clone(), 16).1);
+
+        let mut parser = Parser::from_descriptor(CAF_DESCR);
+        assert!(matches!(
+            parser.parse(&file[..20], false).unwrap(),
+            (0, Step::NeedMore(_))
+        ));
+        assert!(parser.parse(&file[..20], true).is_err());
+    }
+}
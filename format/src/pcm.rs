@@ -0,0 +1,125 @@
+//!
+//! Helpers shared by the uncompressed audio formats.
+//!
+
+use std::sync::Arc;
+
+use crate::data::audiosample::{ChannelMap, Soniton};
+use crate::data::params::{AudioInfo, CodecParams, MediaKind};
+use crate::error::*;
+
+/// Number of sample frames demuxed per packet.
+pub(crate) const FRAMES_PER_PACKET: usize = 1024;
+
+/// Returns the codec name of an uncompressed audio format,
+/// e.g. `pcm_s16be`.
+pub(crate) fn codec_id(fmt: &Soniton) -> String {
+    let kind = if fmt.float {
+        'f'
+    } else if fmt.signed {
+        's'
+    } else {
+        'u'
+    };
+    let endian = match (fmt.bits > 8, fmt.be) {
+        (false, _) => "",
+        (true, true) => "be",
+        (true, false) => "le",
+    };
+    format!("pcm_{}{}{}", kind, fmt.bits, endian)
+}
+
+/// Returns the sample format named by an uncompressed audio codec name.
+pub(crate) fn sample_format(codec_id: &str) -> Option<Soniton> {
+    let name = codec_id.strip_prefix("pcm_")?;
+    let (float, signed) = match name.chars().next()? {
+        'f' => (true, true),
+        's' => (false, true),
+        'u' => (false, false),
+        _ => return None,
+    };
+    let name = &name[1..];
+    let (bits, be) = if let Some(bits) = name.strip_suffix("be") {
+        (bits, true)
+    } else if let Some(bits) = name.strip_suffix("le") {
+        (bits, false)
+    } else {
+        (name, false)
+    };
+    let bits: u8 = bits.parse().ok()?;
+
+    match (bits, float) {
+        (8, false) | (16, false) | (24, false) | (32, false) | (32, true) | (64, true) => {
+            Some(Soniton::new(bits, be, false, false, float, signed))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the default channel map for a number of channels, the channels
+/// being unnamed beyond 8 channels.
+pub(crate) fn channel_map(channels: usize) -> Result<ChannelMap> {
+    if channels == 0 {
+        return Err(Error::InvalidData);
+    }
+    Ok(ChannelMap::default_map(channels))
+}
+
+/// Returns the codec parameters of an audio stream.
+pub(crate) fn audio_params(
+    codec_id: String,
+    rate: usize,
+    map: ChannelMap,
+    format: Option<Soniton>,
+) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Audio(AudioInfo {
+            rate,
+            map: Some(map),
+            format: format.map(Arc::new),
+        })),
+        codec_id: Some(codec_id),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::audiosample::ChannelLayout;
+
+    #[test]
+    fn names() {
+        let s16be = Soniton::new(16, true, false, false, false, true);
+        assert_eq!(codec_id(&s16be), "pcm_s16be");
+        assert_eq!(sample_format("pcm_s16be"), Some(s16be));
+
+        let u8 = Soniton::new(8, false, false, false, false, false);
+        assert_eq!(codec_id(&u8), "pcm_u8");
+        assert_eq!(sample_format("pcm_u8"), Some(u8));
+
+        let f64le = Soniton::new(64, false, false, false, true, true);
+        assert_eq!(sample_format(&codec_id(&f64le)), Some(f64le));
+
+        assert_eq!(sample_format("pcm_f16le"), None);
+        assert_eq!(sample_format("opus"), None);
+    }
+
+    #[test]
+    fn channels() {
+        assert!(channel_map(0).is_err());
+        assert_eq!(
+            channel_map(2).unwrap(),
+            ChannelLayout::STEREO.to_map().unwrap()
+        );
+        let map = channel_map(12).unwrap();
+        assert_eq!(map.len(), 12);
+        assert_eq!(
+            ChannelLayout::from_map(&map),
+            ChannelLayout::Unspecified(12)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//!
+//! AC-3 and E-AC-3 (ATSC A/52) parser.
+//!
+
+use super::bits::Bits;
+use super::{audio_params, Frame, Parser};
+use crate::data::params::CodecParams;
+use crate::demuxer::need;
+use crate::error::*;
+
+/// Bytes needed to read the header.
+const HEADER_SIZE: usize = 8;
+
+/// Sample rates, by sample rate code.
+const RATES: [usize; 3] = [48000, 44100, 32000];
+/// E-AC-3 reduced sample rates, by second sample rate code.
+const REDUCED_RATES: [usize; 3] = [24000, 22050, 16000];
+/// Bit rates in kbit/s, by half frame size code.
+const BIT_RATES: [usize; 19] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+];
+/// Full bandwidth channels, by audio coding mode.
+const CHANNELS: [usize; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+/// E-AC-3 audio blocks, by block count code.
+const BLOCKS: [u64; 4] = [1, 2, 3, 6];
+
+/// Fields of a syncframe header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    /// Tells if the frame is an E-AC-3 one.
+    pub enhanced: bool,
+    /// Sample rate.
+    pub rate: usize,
+    /// Number of channels, LFE included.
+    pub channels: usize,
+    /// Size of the frame.
+    pub frame_size: usize,
+    /// Number of samples of the frame.
+    pub samples: u64,
+}
+
+/// Reads the header of the syncframe starting the data.
+pub fn read_header(data: &[u8]) -> Result<Header> {
+    need(data, HEADER_SIZE)?;
+    if data[..2] != [0x0b, 0x77] {
+        return Err(Error::InvalidData);
+    }
+    let bsid = data[5] >> 3;
+    let mut bits = Bits::new(&data[2..HEADER_SIZE]);
+
+    let (rate, frame_size, samples, acmod) = match bsid {
+        0..=10 => {
+            bits.skip(16)?;
+            let fscod = bits.get(2)? as usize;
+            let frmsizecod = bits.get(6)? as usize;
+            let rate = *RATES.get(fscod).ok_or(Error::InvalidData)?;
+            let bit_rate = *BIT_RATES.get(frmsizecod / 2).ok_or(Error::InvalidData)?;
+            // The 44.1 kHz frames are padded by a word every other code.
+            let words =
+                bit_rate * 1000 * 1536 / (rate * 16) + if fscod == 1 { frmsizecod & 1 } else { 0 };
+            bits.skip(8)?;
+            let acmod = bits.get(3)? as usize;
+            if acmod & 1 != 0 && acmod != 1 {
+                bits.skip(2)?;
+            }
+            if acmod & 4 != 0 {
+                bits.skip(2)?;
+            }
+            if acmod == 2 {
+                bits.skip(2)?;
+            }
+            (rate, words * 2, 1536, acmod)
+        }
+        11..=16 => {
+            bits.skip(5)?;
+            let frame_size = (bits.get(11)? as usize + 1) * 2;
+            let fscod = bits.get(2)? as usize;
+            let (rate, blocks) = if fscod == 3 {
+                let fscod2 = bits.get(2)? as usize;
+                (*REDUCED_RATES.get(fscod2).ok_or(Error::InvalidData)?, 6)
+            } else {
+                (RATES[fscod], BLOCKS[bits.get(2)? as usize])
+            };
+            let acmod = bits.get(3)? as usize;
+            (rate, frame_size, blocks * 256, acmod)
+        }
+        _ => return Err(Error::InvalidData),
+    };
+    let lfe = bits.get(1)? as usize;
+
+    Ok(Header {
+        enhanced: bsid > 10,
+        rate,
+        channels: CHANNELS[acmod] + lfe,
+        frame_size,
+        samples,
+    })
+}
+
+/// AC-3 and E-AC-3 parser.
+pub struct Ac3Parser;
+
+impl Parser for Ac3Parser {
+    fn probe(&self, data: &[u8]) -> bool {
+        match read_header(data) {
+            Ok(header) => match read_header(&data[header.frame_size.min(data.len())..]) {
+                Ok(next) => next.enhanced == header.enhanced,
+                Err(Error::MoreDataNeeded(_)) => true,
+                Err(_) => false,
+            },
+            Err(_) => false,
+        }
+    }
+    fn params(&self, data: &[u8]) -> Result<CodecParams> {
+        let header = read_header(data)?;
+        let codec_id = if header.enhanced { "eac3" } else { "ac3" };
+        Ok(audio_params(codec_id, header.rate, header.channels))
+    }
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame> {
+        let header = match read_header(data) {
+            Err(Error::MoreDataNeeded(_)) if eof => return Err(Error::InvalidData),
+            res => res?,
+        };
+        if eof && data.len() < header.frame_size {
+            return Err(Error::InvalidData);
+        }
+        need(data, header.frame_size)?;
+        Ok(Frame {
+            size: header.frame_size,
+            is_key: true,
+            samples: Some(header.samples),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        // 44.1 kHz at 192 kbit/s, 3/2 with LFE.
+        let ac3 = [0x0b, 0x77, 0, 0, 0x55, 0x40, 0xeb, 0x20];
+        assert_eq!(
+            read_header(&ac3).unwrap(),
+            Header {
+                enhanced: false,
+                rate: 44100,
+                channels: 6,
+                frame_size: 836,
+                samples: 1536,
+            }
+        );
+
+        // 48 kHz, 6 blocks, stereo.
+        let eac3 = [0x0b, 0x77, 0x01, 0x7f, 0x34, 0x80, 0, 0];
+        assert_eq!(
+            read_header(&eac3).unwrap(),
+            Header {
+                enhanced: true,
+                rate: 48000,
+                channels: 2,
+                frame_size: 768,
+                samples: 1536,
+            }
+        );
+        assert!(Ac3Parser.probe(&eac3));
+        assert!(matches!(
+            Ac3Parser.frame(&eac3, false),
+            Err(Error::MoreDataNeeded(768))
+        ));
+
+        assert!(read_header(&[0x0b, 0x77, 0, 0, 0xc0, 0x40, 0, 0]).is_err());
+    }
+}
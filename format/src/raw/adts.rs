@@ -0,0 +1,139 @@
+//!
+//! AAC Audio Data Transport Stream parser.
+//!
+//! The frames keep their ADTS header.
+//!
+
+use super::{audio_params, Frame, Parser};
+use crate::data::params::CodecParams;
+use crate::demuxer::need;
+use crate::error::*;
+
+/// Size of the header without CRC.
+const HEADER_SIZE: usize = 7;
+
+/// Sample rates, by sampling frequency index.
+const RATES: [usize; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Fields of an ADTS header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    /// Sample rate.
+    pub rate: usize,
+    /// Channel configuration, 0 if given by a program config element.
+    pub channels: usize,
+    /// Size of the frame, header included.
+    pub frame_size: usize,
+    /// Number of samples of the frame.
+    pub samples: u64,
+}
+
+/// Reads the ADTS header starting the data.
+pub fn read_header(data: &[u8]) -> Result<Header> {
+    need(data, HEADER_SIZE)?;
+    if data[0] != 0xff || data[1] & 0xf6 != 0xf0 {
+        return Err(Error::InvalidData);
+    }
+    let rate = *RATES
+        .get(((data[2] >> 2) & 0xf) as usize)
+        .ok_or(Error::InvalidData)?;
+    let channels = (((data[2] & 1) << 2) | (data[3] >> 6)) as usize;
+    let frame_size =
+        ((data[3] as usize & 3) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+    let header_size = if data[1] & 1 == 0 {
+        HEADER_SIZE + 2
+    } else {
+        HEADER_SIZE
+    };
+    if frame_size < header_size {
+        return Err(Error::InvalidData);
+    }
+    let blocks = (data[6] & 3) as u64 + 1;
+
+    Ok(Header {
+        rate,
+        channels,
+        frame_size,
+        samples: blocks * 1024,
+    })
+}
+
+/// ADTS parser.
+pub struct AdtsParser;
+
+impl Parser for AdtsParser {
+    fn probe(&self, data: &[u8]) -> bool {
+        match read_header(data) {
+            Ok(header) => match read_header(&data[header.frame_size.min(data.len())..]) {
+                Ok(next) => next.rate == header.rate,
+                Err(Error::MoreDataNeeded(_)) => true,
+                Err(_) => false,
+            },
+            Err(_) => false,
+        }
+    }
+    fn params(&self, data: &[u8]) -> Result<CodecParams> {
+        let header = read_header(data)?;
+        Ok(audio_params("aac", header.rate, header.channels))
+    }
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame> {
+        let header = match read_header(data) {
+            Err(Error::MoreDataNeeded(_)) if eof => return Err(Error::InvalidData),
+            res => res?,
+        };
+        if eof && data.len() < header.frame_size {
+            return Err(Error::InvalidData);
+        }
+        need(data, header.frame_size)?;
+        Ok(Frame {
+            size: header.frame_size,
+            is_key: true,
+            samples: Some(header.samples),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Returns the header of a stereo 44.1 kHz AAC LC frame, without CRC.
+    pub(crate) fn header(frame_size: usize) -> [u8; 7] {
+        [
+            0xff,
+            0xf1,
+            0x50,
+            0x80 | (frame_size >> 11) as u8,
+            (frame_size >> 3) as u8,
+            ((frame_size & 7) << 5) as u8 | 0x1f,
+            0xfc,
+        ]
+    }
+
+    #[test]
+    fn headers() {
+        let mut data = header(10).to_vec();
+        data.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            read_header(&data).unwrap(),
+            Header {
+                rate: 44100,
+                channels: 2,
+                frame_size: 10,
+                samples: 1024,
+            }
+        );
+        assert!(AdtsParser.probe(&data));
+        assert_eq!(AdtsParser.frame(&data, false).unwrap().size, 10);
+        assert!(matches!(
+            AdtsParser.frame(&data[..9], false),
+            Err(Error::MoreDataNeeded(10))
+        ));
+        assert!(AdtsParser.frame(&data[..9], true).is_err());
+
+        data[2] = 0x7c;
+        assert!(read_header(&data).is_err());
+    }
+}
@@ -0,0 +1,685 @@
+//!
+//! H.264 and HEVC Annex B byte stream parsers.
+//!
+//! An access unit ends before the first slice of the next picture or the
+//! parameter sets, delimiters and SEI messages preceding it.
+//!
+
+use std::collections::HashMap;
+
+use super::bits::Bits;
+use super::{video_params, Frame, Parser};
+use crate::data::frame::FieldInfo;
+use crate::data::params::CodecParams;
+use crate::error::*;
+
+/// Iterator over the NAL units of an Annex B byte stream, as their offset
+/// and their data without start code.
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// Returns the position of the next three bytes start code.
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(3)
+        .position(|w| w == [0, 0, 1])
+        .map(|pos| from + pos)
+}
+
+/// Returns the start of the unit using the start code at `pos`, its
+/// leading zero byte included.
+fn unit_start(data: &[u8], pos: usize) -> usize {
+    if pos > 0 && data[pos - 1] == 0 {
+        pos - 1
+    } else {
+        pos
+    }
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = (usize, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = find_start_code(self.data, self.pos)? + 3;
+        let end = match find_start_code(self.data, start) {
+            Some(pos) => unit_start(self.data, pos),
+            None => self.data.len(),
+        };
+        self.pos = end;
+        let mut nal = &self.data[start..end];
+        while let Some((&0, rest)) = nal.split_last() {
+            nal = rest;
+        }
+        Some((start - 3, nal))
+    }
+}
+
+/// Splits an Annex B byte stream into its NAL units.
+pub fn nal_units(data: &[u8]) -> NalUnits<'_> {
+    NalUnits { data, pos: 0 }
+}
+
+/// NAL unit syntax of a codec.
+trait Syntax {
+    /// Bytes needed to tell how a NAL unit splits the stream.
+    const PEEK_SIZE: usize;
+
+    /// Tells if the NAL unit is a slice.
+    fn is_vcl(nal: &[u8]) -> bool;
+    /// Tells if the NAL unit starts an access unit, once a slice has been
+    /// found.
+    fn starts_au(nal: &[u8]) -> bool;
+    /// Tells if the NAL unit is a slice of a random access picture.
+    fn is_key(nal: &[u8]) -> bool;
+}
+
+fn frame<S: Syntax>(data: &[u8], eof: bool) -> Result<Frame> {
+    let mut pos = 0;
+    let mut vcl = false;
+    let mut is_key = false;
+    while let Some(code) = find_start_code(data, pos) {
+        let nal = &data[code + 3..];
+        if nal.len() < S::PEEK_SIZE {
+            break;
+        }
+        if vcl && S::starts_au(nal) {
+            return Ok(Frame {
+                size: unit_start(data, code),
+                is_key,
+                samples: None,
+            });
+        }
+        if S::is_vcl(nal) {
+            vcl = true;
+            is_key |= S::is_key(nal);
+        }
+        pos = code + 3;
+    }
+
+    if !eof {
+        return Err(Error::MoreDataNeeded(data.len() + 1));
+    }
+    Ok(Frame {
+        size: data.len(),
+        is_key,
+        samples: None,
+    })
+}
+
+/// Tells if the data starts with a start code followed by a NAL unit
+/// accepted by `first`.
+fn probe(data: &[u8], first: impl Fn(&[u8]) -> bool) -> bool {
+    let nal = if data.starts_with(&[0, 0, 1]) {
+        &data[3..]
+    } else if data.starts_with(&[0, 0, 0, 1]) {
+        &data[4..]
+    } else {
+        return false;
+    };
+    nal.len() >= 2 && nal[0] & 0x80 == 0 && first(nal)
+}
+
+/// H.264 Annex B parser.
+///
+/// The parameter sets found in the frames are kept to read the picture
+/// timing SEI messages of the following ones.
+#[derive(Debug, Default)]
+pub struct H264Parser {
+    /// Picture timing syntax of the SPS, by id.
+    sps: HashMap<u32, H264Timing>,
+    /// SPS id of the PPS, by id.
+    pps: HashMap<u32, u32>,
+}
+
+struct H264;
+
+impl Syntax for H264 {
+    const PEEK_SIZE: usize = 2;
+
+    fn is_vcl(nal: &[u8]) -> bool {
+        matches!(nal[0] & 0x1f, 1..=5)
+    }
+    fn starts_au(nal: &[u8]) -> bool {
+        match nal[0] & 0x1f {
+            // A first_mb_in_slice of 0.
+            1 | 2 | 5 => nal[1] & 0x80 != 0,
+            6..=9 | 14..=18 => true,
+            _ => false,
+        }
+    }
+    fn is_key(nal: &[u8]) -> bool {
+        nal[0] & 0x1f == 5
+    }
+}
+
+/// Skips a scaling list of an H.264 SPS.
+fn skip_scaling_list(bits: &mut Bits, size: usize) -> Result<()> {
+    let (mut last, mut next) = (8, 8);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + bits.get_se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Ok(())
+}
+
+/// Picture timing SEI syntax set by an H.264 SPS.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct H264Timing {
+    /// Lengths of `cpb_removal_delay` and `dpb_output_delay`, if the HRD
+    /// parameters are present.
+    delays: Option<(usize, usize)>,
+    pic_struct_present: bool,
+}
+
+/// Fields of an H.264 SPS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct H264Sps {
+    id: u32,
+    /// Cropped dimensions of the picture.
+    width: usize,
+    height: usize,
+    timing: H264Timing,
+}
+
+/// Reads the HRD parameters of an H.264 VUI, returning the lengths of the
+/// picture timing delays.
+fn h264_hrd(bits: &mut Bits) -> Result<(usize, usize)> {
+    let cpb_count = bits.get_ue()? + 1;
+    bits.skip(8)?;
+    for _ in 0..cpb_count {
+        bits.get_ue()?;
+        bits.get_ue()?;
+        bits.skip(1)?;
+    }
+    bits.skip(5)?;
+    let cpb_removal_delay = bits.get(5)? as usize + 1;
+    let dpb_output_delay = bits.get(5)? as usize + 1;
+    bits.skip(5)?;
+    Ok((cpb_removal_delay, dpb_output_delay))
+}
+
+/// Reads the VUI of an H.264 SPS up to the picture structure flag.
+fn h264_vui(bits: &mut Bits) -> Result<H264Timing> {
+    if bits.get_bit()? && bits.get(8)? == 255 {
+        // Extended sample aspect ratio.
+        bits.skip(32)?;
+    }
+    if bits.get_bit()? {
+        bits.skip(1)?;
+    }
+    if bits.get_bit()? {
+        bits.skip(4)?;
+        if bits.get_bit()? {
+            bits.skip(24)?;
+        }
+    }
+    if bits.get_bit()? {
+        bits.get_ue()?;
+        bits.get_ue()?;
+    }
+    if bits.get_bit()? {
+        bits.skip(65)?;
+    }
+    let nal_hrd = if bits.get_bit()? {
+        Some(h264_hrd(bits)?)
+    } else {
+        None
+    };
+    let vcl_hrd = if bits.get_bit()? {
+        Some(h264_hrd(bits)?)
+    } else {
+        None
+    };
+    let delays = nal_hrd.or(vcl_hrd);
+    if delays.is_some() {
+        bits.skip(1)?;
+    }
+    Ok(H264Timing {
+        delays,
+        pic_struct_present: bits.get_bit()?,
+    })
+}
+
+/// Parses an H.264 SPS.
+fn h264_sps(nal: &[u8]) -> Result<H264Sps> {
+    let mut bits = Bits::from_nal(&nal[1..]);
+    let profile = bits.get(8)?;
+    bits.skip(16)?;
+    let id = bits.get_ue()?;
+    let mut chroma_format = 1;
+    if let 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135 = profile {
+        chroma_format = bits.get_ue()?;
+        if chroma_format == 3 && bits.get_bit()? {
+            // Separate colour planes are cropped as monochrome.
+            chroma_format = 0;
+        }
+        bits.get_ue()?;
+        bits.get_ue()?;
+        bits.skip(1)?;
+        if bits.get_bit()? {
+            let lists = if chroma_format == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if bits.get_bit()? {
+                    skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    bits.get_ue()?;
+    match bits.get_ue()? {
+        0 => {
+            bits.get_ue()?;
+        }
+        1 => {
+            bits.skip(1)?;
+            bits.get_se()?;
+            bits.get_se()?;
+            for _ in 0..bits.get_ue()? {
+                bits.get_se()?;
+            }
+        }
+        _ => {}
+    }
+    bits.get_ue()?;
+    bits.skip(1)?;
+    let mut width = (bits.get_ue()? as usize + 1) * 16;
+    let height_units = bits.get_ue()? as usize + 1;
+    let frame_mbs_only = bits.get_bit()?;
+    if !frame_mbs_only {
+        bits.skip(1)?;
+    }
+    bits.skip(1)?;
+    let field_factor = if frame_mbs_only { 1 } else { 2 };
+    let mut height = field_factor * height_units * 16;
+
+    if bits.get_bit()? {
+        let (unit_x, unit_y) = match chroma_format {
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        };
+        let left = bits.get_ue()? as usize;
+        let right = bits.get_ue()? as usize;
+        let top = bits.get_ue()? as usize;
+        let bottom = bits.get_ue()? as usize;
+        let crop_x = unit_x * (left + right);
+        let crop_y = unit_y * (top + bottom);
+        if crop_x >= width || crop_y >= height {
+            return Err(Error::InvalidData);
+        }
+        width -= crop_x;
+        height -= crop_y;
+    }
+    // A truncated SPS still gives the size of the picture.
+    let timing = match bits.get_bit() {
+        Ok(true) => h264_vui(&mut bits)?,
+        _ => H264Timing::default(),
+    };
+
+    Ok(H264Sps {
+        id,
+        width,
+        height,
+        timing,
+    })
+}
+
+/// Returns the id of an H.264 PPS and the one of its SPS.
+fn h264_pps(nal: &[u8]) -> Result<(u32, u32)> {
+    let mut bits = Bits::from_nal(&nal[1..]);
+    Ok((bits.get_ue()?, bits.get_ue()?))
+}
+
+/// Returns the PPS id of an H.264 slice.
+fn h264_slice_pps(nal: &[u8]) -> Result<u32> {
+    let mut bits = Bits::from_nal(&nal[1..]);
+    bits.get_ue()?;
+    bits.get_ue()?;
+    bits.get_ue()
+}
+
+/// Returns the `pic_struct` of the picture timing message of an H.264 SEI,
+/// `None` if it has none.
+fn h264_pic_struct(nal: &[u8], timing: &H264Timing) -> Result<Option<u8>> {
+    let read_value = |bits: &mut Bits| -> Result<usize> {
+        let mut value = 0;
+        loop {
+            let byte = bits.get(8)? as usize;
+            value += byte;
+            if byte != 255 {
+                return Ok(value);
+            }
+        }
+    };
+    let mut bits = Bits::from_nal(&nal[1..]);
+    // Stops at the trailing bits.
+    while bits.left() > 8 {
+        let kind = read_value(&mut bits)?;
+        let size = read_value(&mut bits)?;
+        if kind != 1 {
+            bits.skip(8 * size)?;
+            continue;
+        }
+        if let Some((cpb_removal_delay, dpb_output_delay)) = timing.delays {
+            bits.skip(cpb_removal_delay + dpb_output_delay)?;
+        }
+        return Ok(Some(bits.get(4)? as u8));
+    }
+    Ok(None)
+}
+
+impl H264Parser {
+    /// Returns the SPS of a slice from the parameter sets seen so far.
+    fn slice_sps(&self, nal: &[u8]) -> Option<&H264Timing> {
+        let pps = h264_slice_pps(nal).ok()?;
+        self.sps.get(self.pps.get(&pps)?)
+    }
+}
+
+impl Parser for H264Parser {
+    fn probe(&self, data: &[u8]) -> bool {
+        probe(data, |nal| matches!(nal[0] & 0x1f, 6 | 7 | 9))
+    }
+    fn params(&self, data: &[u8]) -> Result<CodecParams> {
+        let size = nal_units(data)
+            .find(|(_, nal)| nal.first().is_some_and(|b| b & 0x1f == 7))
+            .and_then(|(_, nal)| h264_sps(nal).ok())
+            .map(|sps| (sps.width, sps.height));
+        let (width, height) = size.unwrap_or((0, 0));
+        Ok(video_params("h264", width, height))
+    }
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame> {
+        frame::<H264>(data, eof)
+    }
+    fn fields(&mut self, data: &[u8]) -> Option<FieldInfo> {
+        let mut seis = Vec::new();
+        let mut slice = None;
+        for (_, nal) in nal_units(data) {
+            match nal.first().map(|b| b & 0x1f) {
+                Some(6) => seis.push(nal),
+                Some(7) => {
+                    if let Ok(sps) = h264_sps(nal) {
+                        self.sps.insert(sps.id, sps.timing);
+                    }
+                }
+                Some(8) => {
+                    if let Ok((pps, sps)) = h264_pps(nal) {
+                        self.pps.insert(pps, sps);
+                    }
+                }
+                Some(1 | 2 | 5) if slice.is_none() => slice = Some(nal),
+                _ => {}
+            }
+        }
+
+        let timing = self.slice_sps(slice?)?;
+        if !timing.pic_struct_present {
+            return None;
+        }
+        let pic_struct = seis
+            .into_iter()
+            .find_map(|nal| h264_pic_struct(nal, timing).ok().flatten())?;
+        FieldInfo::from_h264_pic_struct(pic_struct)
+    }
+    fn reorders(&self) -> bool {
+        true
+    }
+}
+
+/// HEVC Annex B parser.
+pub struct HevcParser;
+
+struct Hevc;
+
+impl Hevc {
+    fn nal_type(nal: &[u8]) -> u8 {
+        (nal[0] >> 1) & 0x3f
+    }
+}
+
+impl Syntax for Hevc {
+    const PEEK_SIZE: usize = 3;
+
+    fn is_vcl(nal: &[u8]) -> bool {
+        Hevc::nal_type(nal) < 32
+    }
+    fn starts_au(nal: &[u8]) -> bool {
+        match Hevc::nal_type(nal) {
+            // A first_slice_segment_in_pic_flag set.
+            0..=31 => nal[2] & 0x80 != 0,
+            32..=35 | 39 | 41..=44 | 48..=55 => true,
+            _ => false,
+        }
+    }
+    fn is_key(nal: &[u8]) -> bool {
+        matches!(Hevc::nal_type(nal), 16..=23)
+    }
+}
+
+/// Returns the cropped dimensions of the picture described by an HEVC SPS.
+fn hevc_size(nal: &[u8]) -> Result<(usize, usize)> {
+    let mut bits = Bits::from_nal(&nal[2..]);
+    bits.skip(4)?;
+    let sub_layers = bits.get(3)? as usize;
+    bits.skip(1)?;
+
+    // Profile, tier and level.
+    bits.skip(96)?;
+    let mut present = Vec::with_capacity(sub_layers);
+    for _ in 0..sub_layers {
+        present.push((bits.get_bit()?, bits.get_bit()?));
+    }
+    if sub_layers > 0 {
+        bits.skip(2 * (8 - sub_layers))?;
+    }
+    for (profile, level) in present {
+        if profile {
+            bits.skip(88)?;
+        }
+        if level {
+            bits.skip(8)?;
+        }
+    }
+
+    bits.get_ue()?;
+    let chroma_format = bits.get_ue()?;
+    let separate_planes = chroma_format == 3 && bits.get_bit()?;
+    let width = bits.get_ue()? as usize;
+    let height = bits.get_ue()? as usize;
+    if !bits.get_bit()? {
+        return Ok((width, height));
+    }
+    let (unit_x, unit_y) = match chroma_format {
+        _ if separate_planes => (1, 1),
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    let left = bits.get_ue()? as usize;
+    let right = bits.get_ue()? as usize;
+    let top = bits.get_ue()? as usize;
+    let bottom = bits.get_ue()? as usize;
+    let crop_x = unit_x * (left + right);
+    let crop_y = unit_y * (top + bottom);
+    if crop_x >= width || crop_y >= height {
+        return Err(Error::InvalidData);
+    }
+    Ok((width - crop_x, height - crop_y))
+}
+
+impl Parser for HevcParser {
+    fn probe(&self, data: &[u8]) -> bool {
+        probe(data, |nal| {
+            let layer = (u16::from(nal[0] & 1) << 5) | u16::from(nal[1] >> 3);
+            matches!(Hevc::nal_type(nal), 32 | 35 | 39) && layer == 0 && nal[1] & 7 != 0
+        })
+    }
+    fn params(&self, data: &[u8]) -> Result<CodecParams> {
+        let size = nal_units(data)
+            .find(|(_, nal)| nal.len() > 2 && Hevc::nal_type(nal) == 33)
+            .and_then(|(_, nal)| hevc_size(nal).ok());
+        let (width, height) = size.unwrap_or((0, 0));
+        Ok(video_params("hevc", width, height))
+    }
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame> {
+        frame::<Hevc>(data, eof)
+    }
+    fn reorders(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::data::frame::Field;
+    use crate::data::params::MediaKind;
+
+    /// High profile SPS of a 1920x1080 picture.
+    pub(crate) const H264_SPS: [u8; 16] = [
+        0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5,
+        0x40,
+    ];
+
+    /// Three access units of a 1920x1080 MBAFF stream, with NAL HRD
+    /// parameters and picture timing SEI messages giving the top field
+    /// first, the bottom field first, then top, bottom, top.
+    pub(crate) const H264_INTERLACED: [u8; 159] = [
+        0x00, 0x00, 0x00, 0x01, 0x09, 0x10, 0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x28, 0xac,
+        0xe5, 0x01, 0xe0, 0x11, 0x3f, 0x78, 0x0b, 0x50, 0x10, 0x10, 0x14, 0x00, 0x00, 0x0f, 0xa4,
+        0x00, 0x03, 0xa9, 0x83, 0x81, 0x00, 0x01, 0x86, 0xa0, 0x00, 0x0c, 0x35, 0x0b, 0xde, 0xe0,
+        0x3e, 0x30, 0x63, 0x2c, 0x00, 0x00, 0x00, 0x01, 0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0, 0x00,
+        0x00, 0x00, 0x01, 0x06, 0x00, 0x07, 0x80, 0x57, 0xe4, 0x00, 0x00, 0x03, 0x00, 0x40, 0x01,
+        0x07, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x04, 0x32, 0x80, 0x00, 0x00, 0x00, 0x01,
+        0x65, 0x88, 0x80, 0x2c, 0xf1, 0x60, 0x00, 0x00, 0x00, 0x01, 0x09, 0x30, 0x00, 0x00, 0x00,
+        0x01, 0x06, 0x01, 0x07, 0x00, 0x00, 0x03, 0x02, 0x00, 0x00, 0x04, 0x42, 0x80, 0x00, 0x00,
+        0x00, 0x01, 0x41, 0x9a, 0x22, 0xb3, 0xc5, 0x80, 0x00, 0x00, 0x00, 0x01, 0x09, 0x30, 0x00,
+        0x00, 0x00, 0x01, 0x06, 0x01, 0x07, 0x00, 0x00, 0x04, 0x00, 0x00, 0x04, 0x51, 0x80, 0x00,
+        0x00, 0x00, 0x01, 0x41, 0x9a, 0x44, 0xb3, 0xc5, 0x80,
+    ];
+
+    /// Main profile SPS of a 1920x1080 picture.
+    const HEVC_SPS: [u8; 30] = [
+        0x00, 0x00, 0x00, 0x01, 0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00,
+        0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x78, 0xa0, 0x03, 0xc0, 0x80, 0x11, 0x07, 0xcb, 0x96,
+    ];
+
+    fn dimensions(params: CodecParams) -> (usize, usize) {
+        match params.kind {
+            Some(MediaKind::Video(video)) => (video.width, video.height),
+            _ => panic!("Not a video stream"),
+        }
+    }
+
+    #[test]
+    fn units() {
+        let data = [
+            0, 0, 0, 1, 0x09, 0x10, 0, 0, 1, 0x41, 0, 0, 3, 1, 0, 0, 0, 1, 0x41,
+        ];
+        let units: Vec<_> = nal_units(&data).collect();
+        assert_eq!(
+            units,
+            [
+                (1, &[0x09, 0x10][..]),
+                (6, &[0x41, 0, 0, 3, 1][..]),
+                (15, &[0x41][..])
+            ]
+        );
+    }
+
+    #[test]
+    fn h264() {
+        assert_eq!(
+            dimensions(H264Parser::default().params(&H264_SPS).unwrap()),
+            (1920, 1080)
+        );
+        assert!(H264Parser::default().probe(&H264_SPS));
+        assert!(!HevcParser.probe(&H264_SPS));
+
+        // Two slices of a picture, then the first slice of the next one.
+        let data = [
+            0, 0, 1, 0x65, 0x88, 0, 0, 1, 0x65, 0x40, 0, 0, 1, 0x41, 0x9a,
+        ];
+        let frame = H264Parser::default().frame(&data, false).unwrap();
+        assert_eq!((frame.size, frame.is_key), (10, true));
+        assert!(matches!(
+            H264Parser::default().frame(&data[10..], false),
+            Err(Error::MoreDataNeeded(_))
+        ));
+        assert_eq!(
+            H264Parser::default().frame(&data[10..], true).unwrap().size,
+            5
+        );
+    }
+
+    #[test]
+    fn h264_fields() {
+        let (_, sps) = nal_units(&H264_INTERLACED).nth(1).unwrap();
+        assert_eq!(
+            h264_sps(sps).unwrap(),
+            H264Sps {
+                id: 0,
+                width: 1920,
+                height: 1080,
+                timing: H264Timing {
+                    delays: Some((24, 24)),
+                    pic_struct_present: true,
+                },
+            }
+        );
+
+        let mut parser = H264Parser::default();
+        let mut data = &H264_INTERLACED[..];
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let size = parser.frame(data, true).unwrap().size;
+            fields.push(parser.fields(&data[..size]));
+            data = &data[size..];
+        }
+        let interlaced = |first_field, repeat_first_field| FieldInfo {
+            interlaced: true,
+            first_field,
+            repeat_first_field,
+            ..Default::default()
+        };
+        assert_eq!(
+            fields,
+            [
+                Some(interlaced(Field::Top, false)),
+                Some(interlaced(Field::Bottom, false)),
+                Some(interlaced(Field::Top, true)),
+            ]
+        );
+
+        // Without the parameter sets, the SEI messages cannot be read.
+        let mut parser = H264Parser::default();
+        let size = parser.frame(&H264_INTERLACED, true).unwrap().size;
+        assert_eq!(parser.fields(&H264_INTERLACED[size..]), None);
+        // Nor without the picture structure flag.
+        let mut parser = H264Parser::default();
+        assert_eq!(parser.fields(&H264_SPS), None);
+    }
+
+    #[test]
+    fn hevc() {
+        assert_eq!(
+            dimensions(HevcParser.params(&HEVC_SPS).unwrap()),
+            (1920, 1080)
+        );
+        assert!(HevcParser.probe(&[0, 0, 0, 1, 0x40, 0x01, 0x0c]));
+        assert!(!H264Parser::default().probe(&[0, 0, 0, 1, 0x40, 0x01, 0x0c]));
+
+        // An IDR picture, its trailing SEI, then a VPS.
+        let data = [
+            0, 0, 1, 0x26, 0x01, 0xaf, 0, 0, 1, 0x50, 0x01, 0x05, 0, 0, 0, 1, 0x40, 0x01, 0x0c,
+        ];
+        let frame = HevcParser.frame(&data, false).unwrap();
+        assert_eq!((frame.size, frame.is_key), (12, true));
+    }
+}
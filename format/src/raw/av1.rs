@@ -0,0 +1,202 @@
+//!
+//! AV1 low overhead bitstream parser.
+//!
+//! The frames are the temporal units, each starting with a temporal
+//! delimiter OBU.
+//!
+
+use super::bits::Bits;
+use super::{video_params, Frame, Parser};
+use crate::data::params::CodecParams;
+use crate::demuxer::need;
+use crate::error::*;
+
+/// OBU types.
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+/// Reads an unsigned LEB128 value, returning it with its size.
+pub(crate) fn get_leb128(data: &[u8]) -> Result<(u64, usize)> {
+    let mut v = 0;
+    for i in 0..8 {
+        need(data, i + 1)?;
+        v |= u64::from(data[i] & 0x7f) << (i * 7);
+        if data[i] & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(Error::InvalidData)
+}
+
+/// Reads the OBU starting the data, returning its type, the offset of its
+/// payload and its size.
+pub(crate) fn read_obu(data: &[u8]) -> Result<(u8, usize, usize)> {
+    need(data, 1)?;
+    let header = data[0];
+    if header & 0x80 != 0 {
+        return Err(Error::InvalidData);
+    }
+    if header & 0x02 == 0 {
+        return Err(Error::Unsupported("OBUs without size field".to_owned()));
+    }
+    let start = if header & 0x04 != 0 { 2 } else { 1 };
+    need(data, start)?;
+    let (size, len) = get_leb128(&data[start..])?;
+    let start = start + len;
+    if size > (usize::MAX / 2) as u64 {
+        return Err(Error::InvalidData);
+    }
+    let end = start + size as usize;
+    need(data, end)?;
+
+    Ok(((header >> 3) & 0xf, start, end))
+}
+
+/// Reads a variable length code of a sequence header.
+fn get_uvlc(bits: &mut Bits) -> Result<u32> {
+    let mut zeros = 0;
+    while !bits.get_bit()? {
+        zeros += 1;
+        if zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+    }
+    Ok(((1u64 << zeros) - 1 + u64::from(bits.get(zeros)?)) as u32)
+}
+
+/// Returns the maximum frame dimensions of a sequence header.
+fn sequence_size(payload: &[u8]) -> Result<(usize, usize)> {
+    let mut bits = Bits::new(payload);
+    bits.skip(4)?;
+    if bits.get_bit()? {
+        // Reduced still picture header.
+        bits.skip(5)?;
+    } else {
+        let mut buffer_delay_size = 0;
+        let timing_info = bits.get_bit()?;
+        let decoder_model_info = if timing_info {
+            bits.skip(64)?;
+            if bits.get_bit()? {
+                get_uvlc(&mut bits)?;
+            }
+            let present = bits.get_bit()?;
+            if present {
+                buffer_delay_size = bits.get(5)? as usize + 1;
+                bits.skip(32 + 10)?;
+            }
+            present
+        } else {
+            false
+        };
+        let initial_display_delay = bits.get_bit()?;
+        for _ in 0..=bits.get(5)? {
+            bits.skip(12)?;
+            if bits.get(5)? > 7 {
+                bits.skip(1)?;
+            }
+            if decoder_model_info && bits.get_bit()? {
+                bits.skip(2 * buffer_delay_size + 1)?;
+            }
+            if initial_display_delay && bits.get_bit()? {
+                bits.skip(4)?;
+            }
+        }
+    }
+    let width_bits = bits.get(4)? as usize + 1;
+    let height_bits = bits.get(4)? as usize + 1;
+    let width = bits.get(width_bits)? as usize + 1;
+    let height = bits.get(height_bits)? as usize + 1;
+    Ok((width, height))
+}
+
+/// AV1 low overhead bitstream parser.
+pub struct Av1Parser;
+
+impl Parser for Av1Parser {
+    fn probe(&self, data: &[u8]) -> bool {
+        match read_obu(data) {
+            Ok((OBU_TEMPORAL_DELIMITER, _, end)) => {
+                end == 2 && read_obu(&data[end..]).map_or(true, |(kind, _, _)| kind != 0)
+            }
+            _ => false,
+        }
+    }
+    fn params(&self, data: &[u8]) -> Result<CodecParams> {
+        let mut size = None;
+        let mut pos = 0;
+        while let Ok((kind, start, end)) = read_obu(&data[pos..]) {
+            if kind == OBU_SEQUENCE_HEADER {
+                size = sequence_size(&data[pos + start..pos + end]).ok();
+                break;
+            }
+            pos += end;
+        }
+        let (width, height) = size.unwrap_or((0, 0));
+        Ok(video_params("av1", width, height))
+    }
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame> {
+        let mut pos = 0;
+        let mut is_key = false;
+        while pos < data.len() {
+            let (kind, _, end) = match read_obu(&data[pos..]) {
+                Err(Error::MoreDataNeeded(size)) if !eof => {
+                    return Err(Error::MoreDataNeeded(pos + size))
+                }
+                Err(Error::MoreDataNeeded(_)) => return Err(Error::InvalidData),
+                res => res?,
+            };
+            if kind == OBU_TEMPORAL_DELIMITER && pos > 0 {
+                break;
+            }
+            is_key |= kind == OBU_SEQUENCE_HEADER;
+            pos += end;
+        }
+
+        if pos == data.len() && !eof {
+            return Err(Error::MoreDataNeeded(pos + 1));
+        }
+        Ok(Frame {
+            size: pos,
+            is_key,
+            samples: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::params::MediaKind;
+
+    /// Sequence header of 640x360 frames, with timing and decoder model
+    /// information.
+    const SEQUENCE_HEADER: [u8; 27] = [
+        0x0a, 0x19, 0x04, 0x00, 0x00, 0x0f, 0xa4, 0x00, 0x01, 0xd4, 0xc3, 0xa4, 0x00, 0x00, 0x00,
+        0x05, 0x29, 0x00, 0x00, 0x10, 0x80, 0xa0, 0x32, 0x62, 0x7f, 0xb3, 0x80,
+    ];
+
+    #[test]
+    fn temporal_units() {
+        let mut data = vec![0x12, 0];
+        data.extend_from_slice(&SEQUENCE_HEADER);
+        data.extend_from_slice(&[0x32, 2, 0xaa, 0xbb]);
+        data.extend_from_slice(&[0x12, 0, 0x32, 1, 0xcc]);
+        assert!(Av1Parser.probe(&data));
+
+        match Av1Parser.params(&data).unwrap().kind {
+            Some(MediaKind::Video(video)) => assert_eq!((video.width, video.height), (640, 360)),
+            _ => panic!("Not a video stream"),
+        }
+
+        let frame = Av1Parser.frame(&data, false).unwrap();
+        assert_eq!((frame.size, frame.is_key), (33, true));
+        let rest = &data[33..];
+        assert!(matches!(
+            Av1Parser.frame(rest, false),
+            Err(Error::MoreDataNeeded(6))
+        ));
+        let frame = Av1Parser.frame(rest, true).unwrap();
+        assert_eq!((frame.size, frame.is_key), (5, false));
+        assert!(Av1Parser.frame(&rest[..4], true).is_err());
+    }
+}
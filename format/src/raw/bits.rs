@@ -0,0 +1,106 @@
+//!
+//! Bit reader for the headers of the elementary streams.
+//!
+
+use crate::error::*;
+
+/// Reads bits, most significant first.
+pub(crate) struct Bits {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Bits {
+    /// Creates a reader for plain data.
+    pub(crate) fn new(data: &[u8]) -> Self {
+        Bits {
+            data: data.to_vec(),
+            pos: 0,
+        }
+    }
+
+    /// Creates a reader for a NAL unit payload, dropping its emulation
+    /// prevention bytes.
+    pub(crate) fn from_nal(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeros = 0;
+        for &b in nal {
+            if zeros >= 2 && b == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if b == 0 { zeros + 1 } else { 0 };
+            data.push(b);
+        }
+        Bits { data, pos: 0 }
+    }
+
+    /// Reads up to 32 bits.
+    pub(crate) fn get(&mut self, n: usize) -> Result<u32> {
+        if self.pos + n > self.data.len() * 8 {
+            return Err(Error::InvalidData);
+        }
+        let mut v = 0u32;
+        for _ in 0..n {
+            let bit = (self.data[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            v = v << 1 | u32::from(bit);
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+
+    pub(crate) fn get_bit(&mut self) -> Result<bool> {
+        Ok(self.get(1)? == 1)
+    }
+
+    /// Returns the number of bits left.
+    pub(crate) fn left(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    pub(crate) fn skip(&mut self, n: usize) -> Result<()> {
+        if self.pos + n > self.data.len() * 8 {
+            return Err(Error::InvalidData);
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    /// Reads an unsigned Exp-Golomb code.
+    pub(crate) fn get_ue(&mut self) -> Result<u32> {
+        let mut zeros = 0;
+        while !self.get_bit()? {
+            zeros += 1;
+            if zeros > 31 {
+                return Err(Error::InvalidData);
+            }
+        }
+        Ok(((1u64 << zeros) - 1 + u64::from(self.get(zeros)?)) as u32)
+    }
+
+    /// Reads a signed Exp-Golomb code.
+    pub(crate) fn get_se(&mut self) -> Result<i32> {
+        let v = i64::from(self.get_ue()?);
+        Ok(if v & 1 == 1 { (v + 1) / 2 } else { -v / 2 } as i32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn golomb() {
+        // 1, 010, 011, 00100, 00111 and the emulation prevention byte.
+        let mut bits = Bits::from_nal(&[0b1010_0110, 0b0100_0011, 0b1000_0000, 0, 0, 3, 1]);
+        assert_eq!(bits.get_ue().unwrap(), 0);
+        assert_eq!(bits.get_ue().unwrap(), 1);
+        assert_eq!(bits.get_se().unwrap(), -1);
+        assert_eq!(bits.get_ue().unwrap(), 3);
+        assert_eq!(bits.get_se().unwrap(), -3);
+        assert_eq!(bits.get(7).unwrap(), 0);
+        assert_eq!(bits.get(16).unwrap(), 0);
+        assert_eq!(bits.get(8).unwrap(), 1);
+        assert!(bits.get_bit().is_err());
+    }
+}
@@ -0,0 +1,387 @@
+//!
+//! Raw elementary stream demuxers.
+//!
+//! The frames are delimited by the parser of the codec. The video streams
+//! carry no timing, their timestamps are generated from the frame rate set
+//! on the demuxer, 25 frames per second by default.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+pub mod ac3;
+pub mod adts;
+pub mod annexb;
+pub mod av1;
+mod bits;
+
+use std::io::SeekFrom;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::frame::FieldInfo;
+use crate::data::packet::Packet;
+use crate::data::params::{AudioInfo, CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::demuxer::{self, Demuxer, Event, PROBE_DATA, PROBE_SCORE_EXTENSION};
+use crate::error::*;
+use crate::pcm;
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Frame found by a parser.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    /// Size of the frame.
+    pub size: usize,
+    /// Tells if the frame can be decoded on its own.
+    pub is_key: bool,
+    /// Number of audio samples of the frame.
+    pub samples: Option<u64>,
+}
+
+/// Used to split an elementary stream into frames.
+pub trait Parser: Send {
+    /// Tells if the data looks like the start of a stream.
+    fn probe(&self, data: &[u8]) -> bool;
+    /// Returns the codec parameters found in the data starting the stream.
+    fn params(&self, data: &[u8]) -> Result<CodecParams>;
+    /// Returns the frame starting the data, `eof` telling that no data
+    /// follows.
+    ///
+    /// Returns `Error::MoreDataNeeded` if the end of the frame is not
+    /// buffered.
+    fn frame(&self, data: &[u8], eof: bool) -> Result<Frame>;
+    /// Returns the field structure of a frame, `None` if the stream does
+    /// not signal it.
+    ///
+    /// Called on every frame in order, letting the parser keep the
+    /// parameter sets it needs.
+    fn fields(&mut self, _data: &[u8]) -> Option<FieldInfo> {
+        None
+    }
+    /// Tells if the frames are stored out of presentation order.
+    fn reorders(&self) -> bool {
+        false
+    }
+}
+
+pub(crate) fn video_params(codec_id: &str, width: usize, height: usize) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Video(VideoInfo {
+            width,
+            height,
+            format: None,
+        })),
+        codec_id: Some(codec_id.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+pub(crate) fn audio_params(codec_id: &str, rate: usize, channels: usize) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Audio(AudioInfo {
+            rate,
+            map: pcm::channel_map(channels).ok(),
+            format: None,
+        })),
+        codec_id: Some(codec_id.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// Raw elementary stream demuxer.
+pub struct RawDemuxer {
+    parser: Box<dyn Parser>,
+    frame_rate: Rational64,
+    /// Timestamp of the next frame.
+    ts: Timestamp,
+}
+
+impl RawDemuxer {
+    /// Creates a new demuxer splitting the stream with a parser.
+    pub fn new(parser: Box<dyn Parser>) -> Self {
+        RawDemuxer {
+            parser,
+            frame_rate: Rational64::new(25, 1),
+            ts: Timestamp::new(0, Rational64::new(1, 25)),
+        }
+    }
+
+    /// Returns the frame rate of the video streams.
+    pub fn get_frame_rate(&self) -> Rational64 {
+        self.frame_rate
+    }
+
+    /// Sets the frame rate of the video streams.
+    pub fn set_frame_rate(&mut self, frame_rate: Rational64) -> Result<()> {
+        if *frame_rate.numer() <= 0 || *frame_rate.denom() <= 0 {
+            return Err(Error::InvalidData);
+        }
+        self.frame_rate = frame_rate;
+        Ok(())
+    }
+
+    fn packet(&mut self, data: &[u8], eof: bool) -> Result<(SeekFrom, Event)> {
+        let frame = self.parser.frame(data, eof)?;
+        if frame.size == 0 || frame.size > data.len() {
+            return Err(Error::InvalidData);
+        }
+
+        let mut pkt = Packet::with_capacity(frame.size);
+        pkt.data.extend_from_slice(&data[..frame.size]);
+        pkt.stream_index = 0;
+        pkt.is_key = frame.is_key;
+        pkt.fields = self.parser.fields(&data[..frame.size]);
+        let duration = Duration::new(frame.samples.unwrap_or(1), self.ts.get_timebase());
+        if !self.parser.reorders() {
+            pkt.t.set_pts(Some(self.ts))?;
+        }
+        pkt.t.set_dts(Some(self.ts))?;
+        pkt.t.set_duration(Some(duration))?;
+        self.ts = self.ts.checked_add(duration)?;
+
+        Ok((SeekFrom::Current(frame.size as i64), Event::NewPacket(pkt)))
+    }
+}
+
+impl Demuxer for RawDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let params = self.parser.params(buf.data())?;
+        let timebase = match params.kind {
+            Some(MediaKind::Audio(ref audio)) if audio.rate > 0 => {
+                Rational64::new(1, audio.rate as i64)
+            }
+            _ => self.frame_rate.recip(),
+        };
+        info.add_stream(Stream::from_params(&params, timebase));
+        self.ts = Timestamp::new(0, timebase);
+
+        Ok(SeekFrom::Current(0))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        self.packet(buf.data(), false)
+    }
+
+    fn read_eof(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if buf.data().is_empty() {
+            Ok((SeekFrom::Current(0), Event::Eof))
+        } else {
+            self.packet(buf.data(), true)
+        }
+    }
+}
+
+struct RawDescr {
+    d: demuxer::Descr,
+    parser: fn() -> Box<dyn Parser>,
+}
+
+impl demuxer::Descriptor for RawDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(RawDemuxer::new((self.parser)()))
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        // Just above the threshold, letting the containers win.
+        if (self.parser)().probe(data) {
+            PROBE_SCORE_EXTENSION + 1
+        } else {
+            0
+        }
+    }
+    fn probe_size(&self) -> usize {
+        // The parameter sets may follow other units.
+        8 * PROBE_DATA
+    }
+}
+
+/// Raw H.264 demuxer descriptor.
+pub const H264_DESCR: &dyn demuxer::Descriptor = &RawDescr {
+    d: demuxer::Descr {
+        name: "h264",
+        demuxer: "h264",
+        description: "Raw H.264 video",
+        extensions: &["h264", "264", "avc"],
+        mime: &["video/h264"],
+    },
+    parser: || Box::<annexb::H264Parser>::default(),
+};
+
+/// Raw HEVC demuxer descriptor.
+pub const HEVC_DESCR: &dyn demuxer::Descriptor = &RawDescr {
+    d: demuxer::Descr {
+        name: "hevc",
+        demuxer: "hevc",
+        description: "Raw HEVC video",
+        extensions: &["hevc", "h265", "265"],
+        mime: &["video/h265"],
+    },
+    parser: || Box::new(annexb::HevcParser),
+};
+
+/// Raw AV1 demuxer descriptor, for low overhead bitstreams.
+pub const AV1_DESCR: &dyn demuxer::Descriptor = &RawDescr {
+    d: demuxer::Descr {
+        name: "obu",
+        demuxer: "obu",
+        description: "AV1 low overhead OBU",
+        extensions: &["obu", "av1"],
+        mime: &["video/av1"],
+    },
+    parser: || Box::new(av1::Av1Parser),
+};
+
+/// Raw ADTS AAC demuxer descriptor.
+pub const AAC_DESCR: &dyn demuxer::Descriptor = &RawDescr {
+    d: demuxer::Descr {
+        name: "aac",
+        demuxer: "aac",
+        description: "Raw ADTS AAC",
+        extensions: &["aac"],
+        mime: &["audio/aac", "audio/aacp"],
+    },
+    parser: || Box::new(adts::AdtsParser),
+};
+
+/// Raw AC-3 and E-AC-3 demuxer descriptor.
+pub const AC3_DESCR: &dyn demuxer::Descriptor = &RawDescr {
+    d: demuxer::Descr {
+        name: "ac3",
+        demuxer: "ac3",
+        description: "Raw AC-3",
+        extensions: &["ac3", "eac3"],
+        mime: &["audio/ac3", "audio/eac3"],
+    },
+    parser: || Box::new(ac3::Ac3Parser),
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::frame::Field;
+    use crate::demuxer::{demux_file, Context, Probe};
+    use crate::limits::Limits;
+    use std::io::Cursor;
+
+    fn demux(descr: &'static dyn demuxer::Descriptor, file: Vec<u8>) -> (Context, Vec<Packet>) {
+        let descrs = [H264_DESCR, HEVC_DESCR, AV1_DESCR, AAC_DESCR, AC3_DESCR];
+        let found = descrs[..].probe(&file).unwrap();
+        assert_eq!(found.describe().name, descr.describe().name);
+
+        demux_file(descr, file, 16)
+    }
+
+    #[test]
+    fn h264() {
+        let mut file = Vec::new();
+        for i in 0..3 {
+            file.extend_from_slice(&[0, 0, 0, 1, 0x09, 0x10]);
+            if i == 0 {
+                file.extend_from_slice(&annexb::test::H264_SPS);
+                file.extend_from_slice(&[0, 0, 1, 0x68, 0xce, 0x38, 0x80]);
+                file.extend_from_slice(&[0, 0, 1, 0x65, 0x88, 0x84, 0x00]);
+            } else {
+                file.extend_from_slice(&[0, 0, 1, 0x41, 0x9a, 0x02]);
+            }
+        }
+
+        let (c, packets) = demux(H264_DESCR, file);
+        let st = &c.info.streams[0];
+        assert_eq!(st.timebase, Rational64::new(1, 25));
+        assert_eq!(st.params.codec_id.as_deref(), Some("h264"));
+        let frames: Vec<_> = packets
+            .iter()
+            .map(|pkt| {
+                let pts = pkt.t.get_pts().map(Timestamp::get_value);
+                let dts = pkt.t.get_dts().map(Timestamp::get_value);
+                (pkt.data.len(), pkt.is_key, pts, dts)
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                (6 + annexb::test::H264_SPS.len() + 14, true, None, Some(0)),
+                (12, false, None, Some(1)),
+                (12, false, None, Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn h264_fields() {
+        let (_, packets) = demux(H264_DESCR, annexb::test::H264_INTERLACED.to_vec());
+        let fields: Vec<_> = packets
+            .iter()
+            .map(|pkt| {
+                pkt.fields
+                    .map(|info| (info.first_field, info.repeat_first_field))
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                Some((Field::Top, false)),
+                Some((Field::Bottom, false)),
+                Some((Field::Top, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn aac() {
+        let mut file = Vec::new();
+        for _ in 0..3 {
+            file.extend_from_slice(&adts::test::header(10));
+            file.extend_from_slice(&[0x21, 0x10, 0x04]);
+        }
+
+        let (c, packets) = demux(AAC_DESCR, file);
+        assert_eq!(c.info.streams[0].timebase, Rational64::new(1, 44100));
+        let ts: Vec<_> = packets
+            .iter()
+            .map(|pkt| pkt.t.get_pts().unwrap().get_value())
+            .collect();
+        assert_eq!(ts, [0, 1024, 2048]);
+    }
+
+    #[test]
+    fn limits() {
+        let mut file = Vec::new();
+        for _ in 0..2 {
+            file.extend_from_slice(&adts::test::header(10));
+            file.extend_from_slice(&[0x21, 0x10, 0x04]);
+        }
+
+        let r = AccReader::with_capacity(16, Cursor::new(file));
+        let mut c = Context::new(AAC_DESCR.create(), Box::new(r));
+        c.set_limits(Limits {
+            max_packet_size: 8,
+            ..Limits::default()
+        });
+        c.read_headers().unwrap();
+        assert!(matches!(
+            c.read_event(),
+            Err(Error::LimitExceeded("packet size"))
+        ));
+    }
+
+    #[test]
+    fn frame_rate() {
+        let mut demuxer = RawDemuxer::new(Box::new(av1::Av1Parser));
+        assert!(demuxer.set_frame_rate(Rational64::new(0, 1)).is_err());
+        demuxer
+            .set_frame_rate(Rational64::new(30000, 1001))
+            .unwrap();
+        assert_eq!(demuxer.get_frame_rate(), Rational64::new(30000, 1001));
+    }
+}
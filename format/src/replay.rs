@@ -0,0 +1,412 @@
+//!
+//! Recording and replaying of sources.
+//!
+//! A `Recorder` wraps the source of a demuxer, such as a network stream,
+//! and writes to a capture everything it returns: the bytes of each read
+//! along with the time it completed, the seeks and the errors, timeouts
+//! included. A `Replayer` reads a capture back as a source returning the
+//! same results in the same order, so the demuxer goes through the same
+//! states as when recording, e.g. to reproduce a bug report in a test.
+//!
+//! The datagram sources record each datagram as one read. The replay is
+//! immediate unless paced as the recording was.
+//!
+//! A capture starts with the `AVREPLAY` magic and a version byte, then
+//! holds a record for each operation:
+//!
+//! - `D`, the elapsed microseconds on 64 bits, the size on 32 bits and
+//!   the bytes read, a size of 0 being the end of the source;
+//! - `S`, the elapsed microseconds, the origin of the seek on 8 bits
+//!   (0 for the start, 1 for the end, 2 for the current position), its
+//!   offset on 64 bits and the resulting position on 64 bits;
+//! - `E`, the elapsed microseconds and the error kind on 8 bits.
+//!
+//! The integers are big-endian.
+//!
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"AVREPLAY";
+const VERSION: u8 = 1;
+
+const DATA: u8 = b'D';
+const SEEK: u8 = b'S';
+const ERROR: u8 = b'E';
+
+/// Error kinds kept by the captures, by their code.
+const ERROR_KINDS: [io::ErrorKind; 8] = [
+    io::ErrorKind::Other,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::BrokenPipe,
+];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn encode_seek(pos: SeekFrom) -> (u8, i64) {
+    match pos {
+        SeekFrom::Start(offset) => (0, offset as i64),
+        SeekFrom::End(offset) => (1, offset),
+        SeekFrom::Current(offset) => (2, offset),
+    }
+}
+
+/// Operation of a capture.
+#[derive(Debug)]
+enum Record {
+    Data(Vec<u8>),
+    Seek { origin: u8, offset: i64, pos: u64 },
+    Error(io::ErrorKind),
+}
+
+/// Source writing the results of its operations to a capture.
+pub struct Recorder<R, W: Write> {
+    inner: R,
+    capture: W,
+    start: Instant,
+}
+
+impl<R, W: Write> Recorder<R, W> {
+    /// Creates a new recorder, writing the capture header.
+    pub fn new(inner: R, mut capture: W) -> io::Result<Self> {
+        capture.write_all(MAGIC)?;
+        capture.write_all(&[VERSION])?;
+        Ok(Recorder {
+            inner,
+            capture,
+            start: Instant::now(),
+        })
+    }
+
+    /// Gets a reference to the recorded source.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Flushes the capture, returning the source and the capture.
+    pub fn finish(mut self) -> io::Result<(R, W)> {
+        self.capture.flush()?;
+        Ok((self.inner, self.capture))
+    }
+
+    fn record(&mut self, record: &Record) -> io::Result<()> {
+        let elapsed = self.start.elapsed().as_micros() as u64;
+        let mut out = Vec::new();
+        match record {
+            Record::Data(data) => {
+                out.push(DATA);
+                out.extend_from_slice(&elapsed.to_be_bytes());
+                out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                out.extend_from_slice(data);
+            }
+            Record::Seek {
+                origin,
+                offset,
+                pos,
+            } => {
+                out.push(SEEK);
+                out.extend_from_slice(&elapsed.to_be_bytes());
+                out.push(*origin);
+                out.extend_from_slice(&offset.to_be_bytes());
+                out.extend_from_slice(&pos.to_be_bytes());
+            }
+            Record::Error(kind) => {
+                let code = ERROR_KINDS.iter().position(|k| k == kind).unwrap_or(0);
+                out.push(ERROR);
+                out.extend_from_slice(&elapsed.to_be_bytes());
+                out.push(code as u8);
+            }
+        }
+        self.capture.write_all(&out)
+    }
+}
+
+impl<R: Read, W: Write> Read for Recorder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(len) => {
+                self.record(&Record::Data(buf[..len].to_vec()))?;
+                Ok(len)
+            }
+            Err(err) => {
+                self.record(&Record::Error(err.kind()))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<R: Seek, W: Write> Seek for Recorder<R, W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.inner.seek(pos) {
+            Ok(new) => {
+                let (origin, offset) = encode_seek(pos);
+                self.record(&Record::Seek {
+                    origin,
+                    offset,
+                    pos: new,
+                })?;
+                Ok(new)
+            }
+            Err(err) => {
+                self.record(&Record::Error(err.kind()))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Source replaying a capture.
+///
+/// Seeking differently from the recording fails with `InvalidData`.
+pub struct Replayer<R> {
+    capture: R,
+    /// Bytes of the current data record not read yet.
+    pending: Vec<u8>,
+    consumed: usize,
+    realtime: bool,
+    start: Instant,
+}
+
+impl<R: Read> Replayer<R> {
+    /// Creates a new replayer, checking the capture header.
+    pub fn new(mut capture: R) -> io::Result<Self> {
+        let mut header = [0; 9];
+        capture.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a capture"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid("unsupported capture version"));
+        }
+        Ok(Replayer {
+            capture,
+            pending: Vec::new(),
+            consumed: 0,
+            realtime: false,
+            start: Instant::now(),
+        })
+    }
+
+    /// Paces the replay as the recording, waiting until each operation
+    /// completes as late as it did.
+    pub fn set_realtime(&mut self, realtime: bool) {
+        self.realtime = realtime;
+        self.start = Instant::now();
+    }
+
+    /// Returns the next record, `None` at the end of the capture.
+    fn next(&mut self) -> io::Result<Option<Record>> {
+        let mut kind = [0; 1];
+        if self.capture.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0; 8];
+        self.capture.read_exact(&mut buf)?;
+        let elapsed = Duration::from_micros(u64::from_be_bytes(buf));
+
+        let record = match kind[0] {
+            DATA => {
+                let mut len = [0; 4];
+                self.capture.read_exact(&mut len)?;
+                let mut data = vec![0; u32::from_be_bytes(len) as usize];
+                self.capture.read_exact(&mut data)?;
+                Record::Data(data)
+            }
+            SEEK => {
+                let mut buf = [0; 17];
+                self.capture.read_exact(&mut buf)?;
+                let mut offset = [0; 8];
+                offset.copy_from_slice(&buf[1..9]);
+                let mut pos = [0; 8];
+                pos.copy_from_slice(&buf[9..]);
+                Record::Seek {
+                    origin: buf[0],
+                    offset: i64::from_be_bytes(offset),
+                    pos: u64::from_be_bytes(pos),
+                }
+            }
+            ERROR => {
+                let mut code = [0; 1];
+                self.capture.read_exact(&mut code)?;
+                let kind = *ERROR_KINDS
+                    .get(usize::from(code[0]))
+                    .ok_or_else(|| invalid("unknown error kind"))?;
+                Record::Error(kind)
+            }
+            _ => return Err(invalid("unknown capture record")),
+        };
+
+        if self.realtime {
+            let due = self.start + elapsed;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Read for Replayer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.pending.len() {
+            match self.next()? {
+                None => return Ok(0),
+                Some(Record::Data(data)) => {
+                    self.pending = data;
+                    self.consumed = 0;
+                }
+                Some(Record::Error(kind)) => {
+                    return Err(io::Error::new(kind, "replayed error"));
+                }
+                Some(Record::Seek { .. }) => {
+                    return Err(invalid("replay diverged: read instead of seek"))
+                }
+            }
+        }
+
+        let len = buf.len().min(self.pending.len() - self.consumed);
+        buf[..len].copy_from_slice(&self.pending[self.consumed..self.consumed + len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read> Seek for Replayer<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pending.clear();
+        self.consumed = 0;
+        let (origin, offset) = encode_seek(pos);
+        match self.next()? {
+            Some(Record::Seek {
+                origin: o,
+                offset: off,
+                pos,
+            }) if (o, off) == (origin, offset) => Ok(pos),
+            Some(Record::Error(kind)) => Err(io::Error::new(kind, "replayed error")),
+            _ => Err(invalid("replay diverged: unexpected seek")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::caf::CAF_DESCR;
+    use crate::cancel::test::Stalling;
+    use crate::cancel::{CancelToken, Interruptible};
+    use crate::data::timeinfo::Timestamp;
+    use crate::demuxer::{read_packets, Context};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn operations() {
+        let source = Stalling {
+            data: Cursor::new(vec![1, 2, 3, 4, 5]),
+            stalls: 1,
+            left: 1,
+        };
+        let mut recorder = Recorder::new(source, Vec::new()).unwrap();
+        let mut buf = [0; 3];
+        let err = recorder.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(recorder.read(&mut buf).unwrap(), 3);
+        assert_eq!(recorder.seek(SeekFrom::Current(-1)).unwrap(), 2);
+        recorder.read(&mut buf).unwrap_err();
+        assert_eq!(recorder.read(&mut buf).unwrap(), 3);
+        recorder.read(&mut buf).unwrap_err();
+        assert_eq!(recorder.read(&mut buf).unwrap(), 0);
+        let (_, capture) = recorder.finish().unwrap();
+
+        let mut replayer = Replayer::new(Cursor::new(&capture)).unwrap();
+        let err = replayer.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // Smaller reads split the recorded ones.
+        let mut small = [0; 2];
+        assert_eq!(replayer.read(&mut small).unwrap(), 2);
+        assert_eq!(replayer.read(&mut small).unwrap(), 1);
+        assert_eq!(small[0], 3);
+        assert_eq!(replayer.seek(SeekFrom::Current(-1)).unwrap(), 2);
+        replayer.read(&mut buf).unwrap_err();
+        assert_eq!(replayer.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf, [3, 4, 5]);
+
+        let mut diverged = Replayer::new(Cursor::new(&capture)).unwrap();
+        diverged.read(&mut buf).unwrap_err();
+        let err = diverged.seek(SeekFrom::Start(0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Replayer::new(Cursor::new(b"AVREPLAY\x02")).is_err());
+    }
+
+    /// Capture shared with the recorder.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn caf_file() -> Vec<u8> {
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        let mut desc = 48000f64.to_be_bytes().to_vec();
+        desc.extend_from_slice(b"lpcm");
+        for v in &[2u32, 4, 1, 2, 16] {
+            desc.extend_from_slice(&v.to_be_bytes());
+        }
+        let samples: Vec<u8> = (0..4 * 3000 + 4).map(|i| i as u8).collect();
+        for (id, data) in &[(b"desc", desc), (b"data", samples)] {
+            file.extend_from_slice(*id);
+            file.extend_from_slice(&(data.len() as i64).to_be_bytes());
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn demux<R: Read + Seek + Send + 'static>(source: R) -> Vec<(Option<i64>, Vec<u8>)> {
+        let r = AccReader::with_capacity(64, Interruptible::new(source, CancelToken::new()));
+        let mut c = Context::new(CAF_DESCR.create(), Box::new(r));
+        c.read_headers().unwrap();
+
+        read_packets(&mut c)
+            .into_iter()
+            .map(|pkt| (pkt.t.get_pts().map(Timestamp::get_value), pkt.data))
+            .collect()
+    }
+
+    #[test]
+    fn demuxing() {
+        let expected = demux(Cursor::new(caf_file()));
+        assert!(expected.len() > 1);
+
+        let capture = Capture::default();
+        let source = Stalling {
+            data: Cursor::new(caf_file()),
+            stalls: 2,
+            left: 0,
+        };
+        let recorder = Recorder::new(source, capture.clone()).unwrap();
+        assert_eq!(demux(recorder), expected);
+
+        let capture = capture.0.lock().unwrap().clone();
+        let mut replayer = Replayer::new(Cursor::new(capture)).unwrap();
+        replayer.set_realtime(true);
+        assert_eq!(demux(replayer), expected);
+    }
+}
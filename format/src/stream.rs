@@ -52,16 +52,27 @@ impl Stream {
     }
 }
 
+/// Relationship between the streams of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKind {
+    /// Tiles of a picture, the stream of the base picture first if any.
+    Tiles,
+    /// Stereoscopic 3D pair, the left view first.
+    Stereo,
+    /// Alternatives to one another, a single one being presented.
+    Alternatives,
+    /// Audio description, the described audio streams first and the
+    /// description last.
+    AudioDescription,
+}
+
 /// Group of streams.
-pub struct StreamGroup<'a> {
-    /// Stream group ID.
-    ///
-    /// Must be unique for each group of stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamGroup {
+    /// Format-specific group identifier.
     pub id: usize,
-    /// Start position of the stream group.
-    pub start: u64,
-    /// End position of the stream group.
-    pub end: u64,
-    /// Streams of the group.
-    pub streams: &'a [Stream],
+    /// Relationship between the streams.
+    pub kind: GroupKind,
+    /// Indices of the streams of the group.
+    pub streams: Vec<usize>,
 }
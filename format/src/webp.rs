@@ -0,0 +1,391 @@
+//!
+//! WebP still image demuxer and muxer.
+//!
+//! The image is exposed as a single frame video stream, `vp8` for lossy
+//! images and `vp8l` for lossless ones. The alpha plane of lossy images,
+//! stored apart, is not exposed and animated files are not supported.
+//!
+
+#![allow(clippy::borrowed_box)]
+
+use std::io::{SeekFrom, Write};
+use std::sync::Arc;
+
+use av_bitstream::byteread::*;
+
+use crate::buffer::Buffered;
+use crate::common::*;
+use crate::data::packet::Packet;
+use crate::data::params::{CodecParams, MediaKind, VideoInfo};
+use crate::data::timeinfo::Timestamp;
+use crate::data::value::Value;
+use crate::demuxer::{self, need, Demuxer, Event};
+use crate::error::*;
+use crate::muxer::{self, Muxer};
+use crate::rational::Rational64;
+use crate::stream::Stream;
+
+/// Size of the RIFF header.
+const HEADER_SIZE: usize = 12;
+/// Size of a chunk header.
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Animation flag of the extended header.
+const VP8X_ANIMATION: u8 = 0x02;
+
+fn is_webp(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP"
+}
+
+/// Reads the chunk starting at `pos`, returning its type, its data and
+/// the position following it, padding included.
+fn read_chunk(data: &[u8], pos: usize) -> Result<([u8; 4], &[u8], usize)> {
+    need(data, pos + CHUNK_HEADER_SIZE)?;
+    let kind = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+    let len = get_u32l(&data[pos + 4..]) as usize;
+    let start = pos + CHUNK_HEADER_SIZE;
+    need(data, start + len)?;
+    // The padding of the last chunk may be missing.
+    let end = (start + len + (len & 1)).min(data.len());
+
+    Ok((kind, &data[start..start + len], end))
+}
+
+/// Returns the codec and the dimensions of an image chunk.
+fn image_info(kind: &[u8; 4], data: &[u8]) -> Result<(&'static str, usize, usize)> {
+    match kind {
+        b"VP8 " => {
+            // Only key frames carry the dimensions, after their start code.
+            if data.len() < 10 || data[0] & 1 != 0 || data[3..6] != [0x9d, 0x01, 0x2a] {
+                return Err(Error::InvalidData);
+            }
+            let width = get_u16l(&data[6..]) & 0x3fff;
+            let height = get_u16l(&data[8..]) & 0x3fff;
+            Ok(("vp8", width as usize, height as usize))
+        }
+        b"VP8L" => {
+            if data.len() < 5 || data[0] != 0x2f {
+                return Err(Error::InvalidData);
+            }
+            let bits = get_u32l(&data[1..]);
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+            Ok(("vp8l", width as usize, height as usize))
+        }
+        _ => Err(Error::InvalidData),
+    }
+}
+
+/// WebP demuxer.
+#[derive(Default)]
+pub struct WebpDemuxer {
+    done: bool,
+}
+
+impl WebpDemuxer {
+    /// Creates a new demuxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Demuxer for WebpDemuxer {
+    fn read_headers(&mut self, buf: &Box<dyn Buffered>, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let data = buf.data();
+        need(data, HEADER_SIZE)?;
+        if !is_webp(data) {
+            return Err(Error::InvalidData);
+        }
+
+        let mut pos = HEADER_SIZE;
+        let (codec_id, width, height) = loop {
+            let (kind, chunk, end) = read_chunk(data, pos)?;
+            match &kind {
+                b"VP8X" if chunk.first().is_some_and(|f| f & VP8X_ANIMATION != 0) => {
+                    return Err(Error::Unsupported("animated WebP".to_owned()));
+                }
+                b"VP8 " | b"VP8L" => break image_info(&kind, chunk)?,
+                _ => {}
+            }
+            pos = end;
+        };
+
+        let params = CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width,
+                height,
+                format: None,
+            })),
+            codec_id: Some(codec_id.to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+        info.add_stream(Stream::from_params(&params, Rational64::new(1, 1)));
+        self.done = false;
+
+        Ok(SeekFrom::Current(pos as i64))
+    }
+
+    fn read_event(&mut self, buf: &Box<dyn Buffered>) -> Result<(SeekFrom, Event)> {
+        if self.done {
+            return Ok((SeekFrom::Current(0), Event::Eof));
+        }
+        let (_, chunk, end) = read_chunk(buf.data(), 0)?;
+
+        let mut pkt = Packet::with_capacity(chunk.len());
+        pkt.data.extend_from_slice(chunk);
+        pkt.stream_index = 0;
+        pkt.is_key = true;
+        let ts = Timestamp::new(0, Rational64::new(1, 1));
+        pkt.t.set_pts(Some(ts))?;
+        pkt.t.set_dts(Some(ts))?;
+        self.done = true;
+
+        Ok((SeekFrom::Current(end as i64), Event::NewPacket(pkt)))
+    }
+}
+
+struct WebpDemuxerDescr {
+    d: demuxer::Descr,
+}
+
+impl demuxer::Descriptor for WebpDemuxerDescr {
+    fn create(&self) -> Box<dyn Demuxer> {
+        Box::new(WebpDemuxer::new())
+    }
+    fn describe(&self) -> &demuxer::Descr {
+        &self.d
+    }
+    fn probe(&self, data: &[u8]) -> u8 {
+        if is_webp(data) {
+            100
+        } else {
+            0
+        }
+    }
+}
+
+/// WebP demuxer descriptor.
+pub const WEBP_DEMUXER_DESCR: &dyn demuxer::Descriptor = &WebpDemuxerDescr {
+    d: demuxer::Descr {
+        name: "webp",
+        demuxer: "webp",
+        description: "WebP",
+        extensions: &["webp"],
+        mime: &["image/webp"],
+    },
+};
+
+/// WebP muxer.
+///
+/// The first packet is written as a simple format file, without extended
+/// header.
+#[derive(Default)]
+pub struct WebpMuxer {
+    kind: Option<&'static [u8; 4]>,
+    written: bool,
+    info: Option<GlobalInfo>,
+}
+
+impl WebpMuxer {
+    /// Creates a new muxer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Muxer for WebpMuxer {
+    fn configure(&mut self) -> Result<()> {
+        let info = self.info.as_ref().ok_or(Error::InvalidData)?;
+        let st = match info.streams.as_slice() {
+            [st] => st,
+            _ => return Err(Error::Unsupported("WebP with several streams".to_owned())),
+        };
+        self.kind = match st.params.codec_id.as_deref() {
+            Some("vp8") => Some(b"VP8 "),
+            Some("vp8l") => Some(b"VP8L"),
+            codec_id => return Err(Error::Unsupported(format!("codec {:?} in WebP", codec_id))),
+        };
+        Ok(())
+    }
+
+    fn write_header(&mut self, _out: &mut dyn Write) -> Result<()> {
+        self.written = false;
+        Ok(())
+    }
+
+    fn write_packet(&mut self, out: &mut dyn Write, pkt: Arc<Packet>) -> Result<()> {
+        let kind = self.kind.ok_or(Error::InvalidData)?;
+        if self.written {
+            return Err(Error::Unsupported("more than one WebP image".to_owned()));
+        }
+        image_info(kind, &pkt.data)?;
+
+        let len = pkt.data.len();
+        let padded = len + (len & 1);
+        let mut file = Vec::with_capacity(HEADER_SIZE + CHUNK_HEADER_SIZE + padded);
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&((4 + CHUNK_HEADER_SIZE + padded) as u32).to_le_bytes());
+        file.extend_from_slice(b"WEBP");
+        file.extend_from_slice(kind);
+        file.extend_from_slice(&(len as u32).to_le_bytes());
+        file.extend_from_slice(&pkt.data);
+        file.resize(HEADER_SIZE + CHUNK_HEADER_SIZE + padded, 0);
+
+        out.write_all(&file)?;
+        self.written = true;
+        Ok(())
+    }
+
+    fn write_trailer(&mut self, _out: &mut dyn Write) -> Result<()> {
+        if self.written {
+            Ok(())
+        } else {
+            Err(Error::InvalidData)
+        }
+    }
+
+    fn set_global_info(&mut self, info: GlobalInfo) -> Result<()> {
+        self.info = Some(info);
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, _val: Value<'a>) -> Result<()> {
+        Err(Error::Unsupported(format!("{} key", key)))
+    }
+}
+
+struct WebpMuxerDescr {
+    d: muxer::Descr,
+    caps: muxer::Capabilities,
+}
+
+impl muxer::Descriptor for WebpMuxerDescr {
+    fn create(&self) -> Box<dyn Muxer> {
+        Box::new(WebpMuxer::new())
+    }
+    fn describe(&self) -> &muxer::Descr {
+        &self.d
+    }
+    fn capabilities(&self) -> &muxer::Capabilities {
+        &self.caps
+    }
+}
+
+/// WebP muxer descriptor.
+pub const WEBP_MUXER_DESCR: &dyn muxer::Descriptor = &WebpMuxerDescr {
+    d: muxer::Descr {
+        name: "webp",
+        demuxer: "webp",
+        description: "WebP",
+        extensions: &["webp"],
+        mime: &["image/webp"],
+    },
+    caps: muxer::Capabilities {
+        codecs: Some(&["vp8", "vp8l"]),
+        max_streams: Some(1),
+        parameter_sets: muxer::ParameterSets::Any,
+        variable_frame_rate: false,
+        seekable_output: false,
+    },
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::AccReader;
+    use crate::data::metadata::Metadata;
+    use crate::demuxer::{demux_file, Context};
+    use std::io::Cursor;
+
+    fn info(codec_id: &str) -> GlobalInfo {
+        let params = CodecParams {
+            kind: Some(MediaKind::Video(VideoInfo {
+                width: 0,
+                height: 0,
+                format: None,
+            })),
+            codec_id: Some(codec_id.to_owned()),
+            extradata: None,
+            bit_rate: 0,
+            convergence_window: 0,
+            delay: 0,
+        };
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+            metadata: Metadata::new(),
+            groups: Vec::new(),
+        };
+        info.add_stream(Stream::from_params(&params, Rational64::new(1, 1)));
+        info
+    }
+
+    #[test]
+    fn roundtrip() {
+        // Key frame of 320x240, the partition being left out.
+        let mut frame = vec![0x50, 0x02, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+        frame.extend_from_slice(&[0; 23]);
+
+        let mut muxer = WebpMuxer::new();
+        muxer.set_global_info(info("vp8")).unwrap();
+        muxer.configure().unwrap();
+        let mut file = Vec::new();
+        muxer.write_header(&mut file).unwrap();
+        let pkt = Arc::new(Packet {
+            data: frame.clone(),
+            ..Packet::new()
+        });
+        muxer.write_packet(&mut file, pkt.clone()).unwrap();
+        assert!(muxer.write_packet(&mut file, pkt).is_err());
+        muxer.write_trailer(&mut file).unwrap();
+        assert_eq!(file.len(), 12 + 8 + 34);
+        assert_eq!(get_u32l(&file[4..]) as usize, file.len() - 8);
+        assert_eq!(WEBP_DEMUXER_DESCR.probe(&file), 100);
+
+        let (c, packets) = demux_file(WEBP_DEMUXER_DESCR, file, 16);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("vp8"));
+        match st.params.kind {
+            Some(MediaKind::Video(ref video)) => {
+                assert_eq!((video.width, video.height), (320, 240))
+            }
+            _ => panic!("Not a video stream"),
+        }
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, frame);
+    }
+
+    #[test]
+    fn extended() {
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"VP8X\x0a\0\0\0");
+        chunks.extend_from_slice(&[0x10, 0, 0, 0, 1, 0, 0, 2, 0, 0]);
+        chunks.extend_from_slice(b"ALPH\x01\0\0\0\0\0");
+        // Lossless image of 2x3.
+        chunks.extend_from_slice(b"VP8L\x06\0\0\0");
+        chunks.extend_from_slice(&[0x2f, 0x01, 0x80, 0x00, 0x10, 0x00]);
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        file.extend_from_slice(b"WEBP");
+        file.extend_from_slice(&chunks);
+
+        let (c, packets) = demux_file(WEBP_DEMUXER_DESCR, file.clone(), 16);
+        let st = &c.info.streams[0];
+        assert_eq!(st.params.codec_id.as_deref(), Some("vp8l"));
+        match st.params.kind {
+            Some(MediaKind::Video(ref video)) => assert_eq!((video.width, video.height), (2, 3)),
+            _ => panic!("Not a video stream"),
+        }
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data.len(), 6);
+
+        file[20] |= VP8X_ANIMATION;
+        let r = AccReader::with_capacity(16, Cursor::new(file));
+        let mut c = Context::new(WEBP_DEMUXER_DESCR.create(), Box::new(r));
+        assert!(c.read_headers().is_err());
+    }
+}
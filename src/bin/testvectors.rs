@@ -0,0 +1,38 @@
+//!
+//! Writes the test vectors of the muxers to a directory, the current one
+//! by default.
+//!
+//! Usage: testvectors [DIRECTORY]
+//!
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use av::vectors::{generate, MUXERS};
+
+fn main() {
+    let dir = env::args_os()
+        .nth(1)
+        .map_or_else(|| PathBuf::from("."), PathBuf::from);
+
+    for &descr in MUXERS {
+        let d = descr.describe();
+        let data = match generate(descr) {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("skipping {}: {}", d.name, err);
+                continue;
+            }
+        };
+
+        let ext = d.extensions.first().unwrap_or(&d.name);
+        let path = dir.join(format!("{}.{}", d.name, ext));
+        if let Err(err) = fs::write(&path, data) {
+            eprintln!("cannot write {}: {}", path.display(), err);
+            process::exit(1);
+        }
+        println!("{}", path.display());
+    }
+}
@@ -0,0 +1,291 @@
+//!
+//! Example pipelines.
+//!
+//! Complete uses of the API written as functions, their documentation
+//! tests running them so the behavior they show stays true.
+//!
+
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+
+use crate::codec::{decoder, encoder};
+use crate::data::metadata::Metadata;
+use crate::data::options::{Options, OptionsError};
+use crate::data::packet::Packet;
+use crate::format::buffer::AccReader;
+use crate::format::common::GlobalInfo;
+use crate::format::demuxer::{self, Event, Probe, ProbeOptions};
+use crate::format::muxer;
+use crate::format::parsers::{Parser, Step};
+use crate::format::stream::Stream;
+use crate::format::{aiff, apng, avif, caf, dv, gif, gxf, mpegps, mxf, nut, raw, webp};
+
+/// Errors of the example pipelines.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// No demuxer recognizes the input.
+    #[error("Unknown format")]
+    UnknownFormat,
+    /// No codec of the given name is available.
+    #[error("Unknown codec {0}")]
+    UnknownCodec(String),
+    /// The input has no stream.
+    #[error("No stream")]
+    NoStream,
+    /// The options cannot be parsed.
+    #[error(transparent)]
+    Options(#[from] OptionsError),
+    /// A demuxing or muxing error.
+    #[error(transparent)]
+    Format(#[from] crate::format::error::Error),
+    /// A decoding or encoding error.
+    #[error(transparent)]
+    Codec(#[from] crate::codec::error::Error),
+}
+
+/// A specialised `Result` type for the example pipelines.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// The demuxers of the crate.
+pub const DEMUXERS: &[&dyn demuxer::Descriptor] = &[
+    aiff::AIFF_DEMUXER_DESCR,
+    apng::APNG_DESCR,
+    avif::AVIF_DEMUXER_DESCR,
+    caf::CAF_DESCR,
+    dv::DV_DESCR,
+    gif::GIF_DESCR,
+    gxf::GXF_DESCR,
+    mpegps::PS_DESCR,
+    mxf::MXF_DESCR,
+    nut::demuxer::NUT_DEMUXER_DESCR,
+    webp::WEBP_DEMUXER_DESCR,
+    raw::H264_DESCR,
+    raw::HEVC_DESCR,
+    raw::AV1_DESCR,
+    raw::AAC_DESCR,
+    raw::AC3_DESCR,
+];
+
+/// Muxer output kept in memory, its clones sharing the data.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryOutput(Arc<Mutex<Vec<u8>>>);
+
+impl MemoryOutput {
+    /// Returns a copy of the data written so far.
+    pub fn get_data(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for MemoryOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Probes the format of the input and reads its headers.
+///
+/// ```
+/// # let file = av::examples::test::caf();
+/// let demuxer = av::examples::open(file)?;
+/// let st = &demuxer.info.streams[0];
+/// assert_eq!(st.params.codec_id.as_deref(), Some("pcm_s16le"));
+/// # Ok::<(), av::examples::Error>(())
+/// ```
+pub fn open(input: Vec<u8>) -> Result<demuxer::Context> {
+    let mut reader = AccReader::new(Cursor::new(input));
+    let descr = demuxer::probe_source(DEMUXERS, &mut reader, &ProbeOptions::default())?
+        .ok_or(Error::UnknownFormat)?;
+    let mut demuxer = demuxer::Context::new(descr.create(), Box::new(reader));
+    demuxer.read_headers()?;
+    Ok(demuxer)
+}
+
+/// Copies the streams of the input to another format, the muxer options
+/// given as `key=value:key=value`.
+///
+/// ```
+/// use av::examples::remux;
+/// use av::format::aiff::AIFF_MUXER_DESCR;
+///
+/// # let file = av::examples::test::caf;
+/// let aiff = remux(file(), AIFF_MUXER_DESCR, "")?;
+/// assert_eq!(&aiff[..4], b"FORM");
+///
+/// // The AIFF muxer has no option.
+/// assert!(remux(file(), AIFF_MUXER_DESCR, "title=x").is_err());
+/// # Ok::<(), av::examples::Error>(())
+/// ```
+pub fn remux(input: Vec<u8>, descr: &dyn muxer::Descriptor, options: &str) -> Result<Vec<u8>> {
+    let mut demuxer = open(input)?;
+
+    let output = MemoryOutput::default();
+    let mut muxer = muxer::Context::from_descriptor(descr, Box::new(output.clone()));
+    muxer.set_options(&options.parse::<Options>()?)?;
+    muxer.set_global_info(demuxer.info.clone())?;
+    muxer.configure()?;
+    muxer.write_header()?;
+
+    loop {
+        match demuxer.read_event()? {
+            Event::NewPacket(pkt) => {
+                muxer.write_packet(Arc::new(pkt))?;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    muxer.write_trailer()?;
+
+    Ok(output.get_data())
+}
+
+/// Splits the input into packets with a sans-IO parser, handing it the
+/// data only when asked to.
+///
+/// ```
+/// # let file = av::examples::test::caf();
+/// let packets = av::examples::parse(&file)?;
+/// assert_eq!(packets.iter().map(|pkt| pkt.data.len()).sum::<usize>(), 400);
+/// # Ok::<(), av::examples::Error>(())
+/// ```
+pub fn parse(input: &[u8]) -> Result<Vec<Packet>> {
+    let descr = DEMUXERS.probe(input).ok_or(Error::UnknownFormat)?;
+    let mut parser = Parser::from_descriptor(descr);
+
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    let mut len = 0;
+    loop {
+        let end = (pos + len).min(input.len());
+        let (consumed, step) = parser.parse(&input[pos..end], end == input.len())?;
+        pos += consumed;
+        match step {
+            Step::NeedMore(size) => len = size,
+            Step::Event(Event::NewPacket(pkt)) => packets.push(pkt),
+            Step::Event(Event::Eof) => break,
+            _ => {}
+        }
+    }
+
+    Ok(packets)
+}
+
+/// Decodes the first stream of the input and encodes it again with the
+/// encoder of the given name.
+///
+/// ```no_run
+/// use av::codec::common::CodecList;
+/// use av::codec::{decoder, encoder};
+/// use av::format::nut::muxer::NUT_MUXER_DESCR;
+///
+/// // The codecs are provided by other crates.
+/// let decoders = decoder::Codecs::from_list(&[]);
+/// let encoders = encoder::Codecs::from_list(&[]);
+/// # let input = av::examples::test::caf();
+/// let nut = av::examples::transcode(input, &decoders, &encoders, "pcm_s16be", NUT_MUXER_DESCR)?;
+/// # Ok::<(), av::examples::Error>(())
+/// ```
+pub fn transcode(
+    input: Vec<u8>,
+    decoders: &decoder::Codecs,
+    encoders: &encoder::Codecs,
+    encoder: &str,
+    descr: &dyn muxer::Descriptor,
+) -> Result<Vec<u8>> {
+    let mut demuxer = open(input)?;
+    let st = demuxer.info.streams.first().ok_or(Error::NoStream)?.clone();
+
+    let codec_id = st.params.codec_id.clone().unwrap_or_default();
+    let mut dec =
+        decoder::Context::by_name(decoders, &codec_id).ok_or(Error::UnknownCodec(codec_id))?;
+    if let Some(ref extradata) = st.params.extradata {
+        dec.set_extradata(extradata);
+    }
+    dec.configure()?;
+
+    let mut enc = encoder::Context::by_name(encoders, encoder)
+        .ok_or_else(|| Error::UnknownCodec(encoder.to_owned()))?;
+    enc.set_params(&st.params)?;
+    enc.configure()?;
+
+    let mut info = GlobalInfo {
+        duration: st.duration,
+        timebase: None,
+        streams: Vec::new(),
+        metadata: Metadata::new(),
+        groups: Vec::new(),
+    };
+    info.add_stream(Stream::from_params(&enc.get_params()?, st.timebase));
+
+    let output = MemoryOutput::default();
+    let mut muxer = muxer::Context::from_descriptor(descr, Box::new(output.clone()));
+    muxer.set_global_info(info)?;
+    muxer.configure()?;
+    muxer.write_header()?;
+
+    // Moves the frames decoded to the encoder and its packets to the muxer.
+    let mut drain = |dec: &mut decoder::Context, enc: &mut encoder::Context| -> Result<()> {
+        loop {
+            match dec.receive_frame() {
+                Ok(frame) => enc.send_frame(&frame)?,
+                Err(crate::codec::error::Error::MoreDataNeeded) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        loop {
+            match enc.receive_packet() {
+                Ok(mut pkt) => {
+                    pkt.stream_index = 0;
+                    muxer.write_packet(Arc::new(pkt))?;
+                }
+                Err(crate::codec::error::Error::MoreDataNeeded) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    };
+
+    loop {
+        match demuxer.read_event()? {
+            Event::NewPacket(pkt) if pkt.stream_index == st.index as isize => {
+                dec.send_packet(&pkt)?;
+                drain(&mut dec, &mut enc)?;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    dec.flush()?;
+    enc.flush()?;
+    drain(&mut dec, &mut enc)?;
+    muxer.write_trailer()?;
+
+    Ok(output.get_data())
+}
+
+/// Inputs of the documentation tests.
+#[doc(hidden)]
+pub mod test {
+    /// Returns a CAF file of 100 stereo 16 bit samples.
+    pub fn caf() -> Vec<u8> {
+        let mut desc = 48000f64.to_be_bytes().to_vec();
+        desc.extend_from_slice(b"lpcm");
+        for v in &[2u32, 4, 1, 2, 16] {
+            desc.extend_from_slice(&v.to_be_bytes());
+        }
+        let data = vec![0; 4 + 400];
+
+        let mut file = b"caff\x00\x01\x00\x00".to_vec();
+        for (id, chunk) in [(b"desc", desc), (b"data", data)].iter() {
+            file.extend_from_slice(&id[..]);
+            file.extend_from_slice(&(chunk.len() as i64).to_be_bytes());
+            file.extend_from_slice(chunk);
+        }
+        file
+    }
+}
@@ -0,0 +1,279 @@
+//!
+//! Colorspace conversion helpers shared by the filters.
+//!
+
+use crate::data::frame::{FrameBuffer, VideoInfo};
+use crate::data::imgutils;
+use crate::data::pixel::{
+    ColorModel, Formaton, MatrixCoefficients, TrichromaticEncodingSystem, YUVRange, YUVSystem,
+};
+use crate::filter::{Error, Result};
+
+/// BT.601 luma coefficients.
+pub(crate) const BT601_LUMA: (f64, f64) = (0.299, 0.114);
+
+/// BT.709 luma coefficients.
+pub(crate) const BT709_LUMA: (f64, f64) = (0.2126, 0.0722);
+
+/// BT.2020 luma coefficients.
+pub(crate) const BT2020_LUMA: (f64, f64) = (0.2627, 0.0593);
+
+/// Returns the red and blue luma coefficients of a matrix, using
+/// `unspecified` if the matrix is not known.
+pub(crate) fn luma_coefficients(
+    matrix: MatrixCoefficients,
+    unspecified: (f64, f64),
+) -> Option<(f64, f64)> {
+    match matrix {
+        MatrixCoefficients::Unspecified => Some(unspecified),
+        MatrixCoefficients::BT2020NonConstantLuminance => Some(BT2020_LUMA),
+        MatrixCoefficients::BT709 => Some(BT709_LUMA),
+        MatrixCoefficients::BT470BG | MatrixCoefficients::ST170M => Some(BT601_LUMA),
+        _ => None,
+    }
+}
+
+/// Conversion between normalized Y'CbCr and R'G'B' values.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct YuvMatrix {
+    pub kr: f64,
+    pub kb: f64,
+}
+
+impl YuvMatrix {
+    pub fn new((kr, kb): (f64, f64)) -> Self {
+        YuvMatrix { kr, kb }
+    }
+
+    pub fn to_rgb(self, y: f64, cb: f64, cr: f64) -> [f64; 3] {
+        let r = y + 2.0 * (1.0 - self.kr) * cr;
+        let b = y + 2.0 * (1.0 - self.kb) * cb;
+        let g = (y - self.kr * r - self.kb * b) / (1.0 - self.kr - self.kb);
+        [r, g, b]
+    }
+
+    pub fn to_yuv(self, rgb: [f64; 3]) -> (f64, f64, f64) {
+        let y = self.kr * rgb[0] + (1.0 - self.kr - self.kb) * rgb[1] + self.kb * rgb[2];
+        let cb = (rgb[2] - y) / (2.0 * (1.0 - self.kb));
+        let cr = (rgb[0] - y) / (2.0 * (1.0 - self.kr));
+        (y, cb, cr)
+    }
+}
+
+/// Conversion between sample values and normalized values.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Quantizer {
+    pub luma_offset: f64,
+    pub luma_range: f64,
+    pub chroma_range: f64,
+    pub chroma_offset: f64,
+    pub max: f64,
+}
+
+impl Quantizer {
+    pub fn new(depth: u8, range: YUVRange) -> Self {
+        let max = f64::from((1u32 << depth) - 1);
+        let scale = f64::from(1u32 << (depth - 8));
+        let chroma_offset = f64::from(1u32 << (depth - 1));
+
+        match range {
+            YUVRange::Limited => Quantizer {
+                luma_offset: 16.0 * scale,
+                luma_range: 219.0 * scale,
+                chroma_range: 224.0 * scale,
+                chroma_offset,
+                max,
+            },
+            YUVRange::Full => Quantizer {
+                luma_offset: 0.0,
+                luma_range: max,
+                chroma_range: max,
+                chroma_offset,
+                max,
+            },
+        }
+    }
+
+    pub fn luma(&self, v: u16) -> f64 {
+        (f64::from(v) - self.luma_offset) / self.luma_range
+    }
+
+    pub fn chroma(&self, v: u16) -> f64 {
+        (f64::from(v) - self.chroma_offset) / self.chroma_range
+    }
+
+    pub fn quantize_luma(&self, y: f64) -> u16 {
+        (y * self.luma_range + self.luma_offset)
+            .round()
+            .clamp(0.0, self.max) as u16
+    }
+
+    pub fn quantize_chroma(&self, c: f64) -> u16 {
+        (c * self.chroma_range + self.chroma_offset)
+            .round()
+            .clamp(0.0, self.max) as u16
+    }
+}
+
+/// Plane sample accessor.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Samples {
+    pub wide: bool,
+    pub be: bool,
+}
+
+impl Samples {
+    pub fn get(self, data: &[u8], idx: usize) -> u16 {
+        if self.wide {
+            let b = [data[idx * 2], data[idx * 2 + 1]];
+            if self.be {
+                u16::from_be_bytes(b)
+            } else {
+                u16::from_le_bytes(b)
+            }
+        } else {
+            u16::from(data[idx])
+        }
+    }
+
+    pub fn put(self, data: &mut [u8], idx: usize, v: u16) {
+        if self.wide {
+            let b = if self.be {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            };
+            data[idx * 2..idx * 2 + 2].copy_from_slice(&b);
+        } else {
+            data[idx] = v as u8;
+        }
+    }
+}
+
+/// Storage of a planar Y'CbCr image.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct YuvLayout {
+    pub quant: Quantizer,
+    pub samples: Samples,
+}
+
+impl YuvLayout {
+    /// Checks that a format is planar Y'CbCr with the same depth, between 8
+    /// and 16 bits, for all the color components.
+    pub fn new(fmt: &Formaton) -> Result<Self> {
+        let unsupported = || Error::Unsupported(format!("format {}", fmt));
+
+        let range = match fmt.get_model() {
+            ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(YUVSystem::YCbCr(r))) => r,
+            _ => return Err(unsupported()),
+        };
+        let depth = fmt.get_chromaton(0).ok_or_else(unsupported)?.get_depth();
+        if fmt.is_paletted()
+            || fmt.get_num_comp() < 3
+            || imgutils::get_num_planes(fmt) != fmt.get_num_comp()
+            || !(8..=16).contains(&depth)
+            || fmt.iter().take(3).flatten().any(|c| c.get_depth() != depth)
+        {
+            return Err(unsupported());
+        }
+
+        Ok(YuvLayout {
+            quant: Quantizer::new(depth, range),
+            samples: Samples {
+                wide: depth > 8,
+                be: fmt.is_be(),
+            },
+        })
+    }
+}
+
+/// Maps every pixel of a planar Y'CbCr image through `f`, working on
+/// normalized values.
+///
+/// The images share the plane geometry of `info`, their samples being
+/// stored as described by `src_layout` and `dst_layout`. Pixels sharing
+/// a chroma sample are converted with the same chroma values and their
+/// output chroma values are averaged.
+pub(crate) fn map_yuv<F>(
+    src_layout: &YuvLayout,
+    dst_layout: &YuvLayout,
+    src: &dyn FrameBuffer,
+    dst: &mut dyn FrameBuffer,
+    info: &VideoInfo,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(f64, f64, f64) -> (f64, f64, f64),
+{
+    let fmt = &info.format;
+    let (width, height) = (info.width, info.height);
+    let cw = imgutils::get_plane_width(fmt, 1, width);
+    let chh = imgutils::get_plane_height(fmt, 1, height);
+    let bw = width.div_ceil(cw.max(1));
+    let bh = height.div_ceil(chh.max(1));
+
+    let step = |layout: &YuvLayout| if layout.samples.wide { 2 } else { 1 };
+    let mut linesizes = [0; 3];
+    let mut dst_linesizes = [0; 3];
+    for plane in 0..3 {
+        linesizes[plane] = src.linesize(plane).map_err(|_| Error::InvalidData)? / step(src_layout);
+        dst_linesizes[plane] =
+            dst.linesize(plane).map_err(|_| Error::InvalidData)? / step(dst_layout);
+    }
+
+    let src_y = src.as_slice_inner(0).map_err(|_| Error::InvalidData)?;
+    let src_u = src.as_slice_inner(1).map_err(|_| Error::InvalidData)?;
+    let src_v = src.as_slice_inner(2).map_err(|_| Error::InvalidData)?;
+
+    let mut luma = vec![0u16; width * height];
+    let mut chroma = vec![(0u16, 0u16); cw * chh];
+    let (s, ds) = (src_layout.samples, dst_layout.samples);
+    let (q, dq) = (&src_layout.quant, &dst_layout.quant);
+
+    for cy in 0..chh {
+        for cx in 0..cw {
+            let cb = q.chroma(s.get(src_u, cy * linesizes[1] + cx));
+            let cr = q.chroma(s.get(src_v, cy * linesizes[2] + cx));
+            let (mut sum_cb, mut sum_cr, mut n) = (0.0, 0.0, 0.0);
+
+            for y in cy * bh..((cy + 1) * bh).min(height) {
+                for x in cx * bw..((cx + 1) * bw).min(width) {
+                    let yv = q.luma(s.get(src_y, y * linesizes[0] + x));
+                    let (out_y, out_cb, out_cr) = f(yv, cb, cr);
+                    luma[y * width + x] = dq.quantize_luma(out_y);
+                    sum_cb += out_cb;
+                    sum_cr += out_cr;
+                    n += 1.0;
+                }
+            }
+
+            if n > 0.0 {
+                chroma[cy * cw + cx] = (
+                    dq.quantize_chroma(sum_cb / n),
+                    dq.quantize_chroma(sum_cr / n),
+                );
+            }
+        }
+    }
+
+    let dst_y = dst.as_mut_slice_inner(0).map_err(|_| Error::InvalidData)?;
+    for y in 0..height {
+        for x in 0..width {
+            ds.put(dst_y, y * dst_linesizes[0] + x, luma[y * width + x]);
+        }
+    }
+    for (plane, pick) in [(1, false), (2, true)] {
+        let data = dst
+            .as_mut_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+        for cy in 0..chh {
+            for cx in 0..cw {
+                let (u, v) = chroma[cy * cw + cx];
+                let val = if pick { v } else { u };
+                ds.put(data, cy * dst_linesizes[plane] + cx, val);
+            }
+        }
+    }
+
+    Ok(())
+}
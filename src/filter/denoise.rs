@@ -0,0 +1,602 @@
+//!
+//! Spatio-temporal denoiser.
+//!
+//! A port of the hqdn3d algorithm: every sample goes through a horizontal,
+//! a vertical and a temporal low-pass step, each one weighting the
+//! difference with the previous sample through a lookup table so that
+//! small differences (noise) are smoothed and large ones (edges, motion)
+//! are preserved.
+//!
+//! Samples are processed with 16 bits of precision, so 8-bit and
+//! high bit depth planes share the same kernels. The horizontal step is
+//! sequential, the vertical and temporal steps run on 8 samples at a time
+//! with AVX2 when the CPU supports it.
+//!
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::data::frame::{ArcFrame, FrameBuffer, VideoInfo};
+use crate::data::imgutils;
+use crate::data::pixel::{ColorModel, Formaton, TrichromaticEncodingSystem};
+use crate::data::value::Value;
+use crate::filter::*;
+
+/// Fractional bits of the lookup table index.
+const LUT_BITS: u32 = 4;
+
+/// Number of entries of a lookup table, covering every 16-bit difference.
+const LUT_SIZE: usize = 512 << LUT_BITS;
+
+/// Default luma spatial strength.
+const DEFAULT_LUMA_SPATIAL: f64 = 4.0;
+
+/// Builds the lookup table for a filtering strength.
+fn coefficients(strength: f64) -> Vec<i32> {
+    let gamma = 0.25f64.ln() / (1.0 - strength.min(252.0) / 255.0 - 0.00001).ln();
+
+    (0..LUT_SIZE as i64)
+        .map(|i| {
+            let d = i - (256 << LUT_BITS);
+            let f = ((d << (9 - LUT_BITS)) + (1 << (8 - LUT_BITS)) - 1) as f64 / 512.0;
+            let simil = (1.0 - f.abs() / 255.0).max(0.0);
+            (simil.powf(gamma) * 256.0 * f).round() as i32
+        })
+        .collect()
+}
+
+#[inline(always)]
+fn lowpass(prev: u16, cur: u16, coef: &[i32]) -> u16 {
+    let d = (i32::from(prev) - i32::from(cur)) >> (8 - LUT_BITS);
+    let idx = (d + (256 << LUT_BITS)) as usize;
+
+    (i32::from(cur) + coef[idx]).clamp(0, i32::from(u16::MAX)) as u16
+}
+
+/// Filters a row of samples in place.
+///
+/// `line` holds the previous filtered row, `prev` the same row of the
+/// previous filtered frame.
+fn denoise_row(
+    row: &mut [u16],
+    line: &mut [u16],
+    prev: &mut [u16],
+    first_line: bool,
+    spatial: &[i32],
+    temporal: &[i32],
+) {
+    let mut pixel = row[0];
+    for cur in row.iter_mut() {
+        pixel = lowpass(pixel, *cur, spatial);
+        *cur = pixel;
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the CPU supports AVX2.
+            unsafe { x86::vertical_temporal(row, line, prev, first_line, spatial, temporal) };
+            return;
+        }
+    }
+
+    vertical_temporal(row, line, prev, first_line, spatial, temporal);
+}
+
+/// Runs the vertical and temporal steps on a horizontally filtered row.
+fn vertical_temporal(
+    row: &mut [u16],
+    line: &mut [u16],
+    prev: &mut [u16],
+    first_line: bool,
+    spatial: &[i32],
+    temporal: &[i32],
+) {
+    for ((cur, line), prev) in row.iter_mut().zip(line.iter_mut()).zip(prev.iter_mut()) {
+        *line = if first_line {
+            *cur
+        } else {
+            lowpass(*line, *cur, spatial)
+        };
+        *prev = lowpass(*prev, *line, temporal);
+        *cur = *prev;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::LUT_BITS;
+
+    /// `lowpass` on 8 samples widened to 32 bits.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn lowpass(prev: __m256i, cur: __m256i, coef: &[i32]) -> __m256i {
+        let d = _mm256_srai_epi32::<{ 8 - LUT_BITS as i32 }>(_mm256_sub_epi32(prev, cur));
+        let idx = _mm256_add_epi32(d, _mm256_set1_epi32(256 << LUT_BITS));
+        // The differences of 16-bit samples index within the table.
+        let c = _mm256_i32gather_epi32::<4>(coef.as_ptr(), idx);
+        let v = _mm256_add_epi32(cur, c);
+        _mm256_min_epi32(
+            _mm256_max_epi32(v, _mm256_setzero_si256()),
+            _mm256_set1_epi32(i32::from(u16::MAX)),
+        )
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn load(src: &[u16]) -> __m256i {
+        _mm256_cvtepu16_epi32(_mm_loadu_si128(src.as_ptr() as *const __m128i))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn store(dst: &mut [u16], v: __m256i) {
+        let packed =
+            _mm256_permute4x64_epi64::<0b1000>(_mm256_packus_epi32(v, _mm256_setzero_si256()));
+        _mm_storeu_si128(
+            dst.as_mut_ptr() as *mut __m128i,
+            _mm256_castsi256_si128(packed),
+        );
+    }
+
+    /// AVX2 version of `super::vertical_temporal`.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn vertical_temporal(
+        row: &mut [u16],
+        line: &mut [u16],
+        prev: &mut [u16],
+        first_line: bool,
+        spatial: &[i32],
+        temporal: &[i32],
+    ) {
+        assert!(spatial.len() >= super::LUT_SIZE && temporal.len() >= super::LUT_SIZE);
+        let len = row.len().min(line.len()).min(prev.len());
+        let body = len - len % 8;
+
+        for x in (0..body).step_by(8) {
+            let cur = load(&row[x..x + 8]);
+            let l = if first_line {
+                cur
+            } else {
+                lowpass(load(&line[x..x + 8]), cur, spatial)
+            };
+            let p = lowpass(load(&prev[x..x + 8]), l, temporal);
+            store(&mut line[x..x + 8], l);
+            store(&mut prev[x..x + 8], p);
+            store(&mut row[x..x + 8], p);
+        }
+
+        super::vertical_temporal(
+            &mut row[body..len],
+            &mut line[body..len],
+            &mut prev[body..len],
+            first_line,
+            spatial,
+            temporal,
+        );
+    }
+}
+
+fn read_row(src: &[u8], row: &mut [u16], sample_size: usize, be: bool, shift: u32) {
+    if sample_size == 1 {
+        for (dst, &src) in row.iter_mut().zip(src) {
+            *dst = u16::from(src) << shift;
+        }
+    } else {
+        for (dst, src) in row.iter_mut().zip(src.chunks_exact(2)) {
+            let v = if be {
+                u16::from_be_bytes([src[0], src[1]])
+            } else {
+                u16::from_le_bytes([src[0], src[1]])
+            };
+            *dst = v << shift;
+        }
+    }
+}
+
+fn write_row(row: &[u16], dst: &mut [u8], sample_size: usize, be: bool, shift: u32) {
+    let round = (1u32 << shift) >> 1;
+    let max = u32::from(u16::MAX) >> shift;
+
+    if sample_size == 1 {
+        for (dst, &src) in dst.iter_mut().zip(row) {
+            *dst = ((u32::from(src) + round) >> shift).min(max) as u8;
+        }
+    } else {
+        for (dst, &src) in dst.chunks_exact_mut(2).zip(row) {
+            let v = ((u32::from(src) + round) >> shift).min(max) as u16;
+            let bytes = if be { v.to_be_bytes() } else { v.to_le_bytes() };
+            dst.copy_from_slice(&bytes);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlaneKind {
+    Luma,
+    Chroma,
+    Alpha,
+}
+
+fn plane_kind(fmt: &Formaton, plane: usize) -> PlaneKind {
+    let yuv = matches!(
+        fmt.get_model(),
+        ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(_))
+    );
+
+    if fmt.has_alpha() && plane == fmt.get_num_comp() - 1 {
+        PlaneKind::Alpha
+    } else if yuv && plane > 0 {
+        PlaneKind::Chroma
+    } else {
+        PlaneKind::Luma
+    }
+}
+
+struct Tables {
+    luma_spatial: Vec<i32>,
+    luma_temporal: Vec<i32>,
+    chroma_spatial: Vec<i32>,
+    chroma_temporal: Vec<i32>,
+}
+
+#[derive(Default)]
+struct PlaneState {
+    line: Vec<u16>,
+    prev: Vec<u16>,
+}
+
+/// Spatio-temporal denoising filter.
+///
+/// The strengths are set through the `luma_spatial`, `chroma_spatial`,
+/// `luma_temporal` and `chroma_temporal` options. The ones not set are
+/// derived from the others.
+#[derive(Default)]
+pub struct Denoise {
+    luma_spatial: Option<f64>,
+    chroma_spatial: Option<f64>,
+    luma_temporal: Option<f64>,
+    chroma_temporal: Option<f64>,
+    tables: Option<Tables>,
+    info: Option<VideoInfo>,
+    planes: Vec<PlaneState>,
+    out: VecDeque<ArcFrame>,
+}
+
+fn strength(val: Value) -> Result<f64> {
+    let v = match val {
+        Value::U64(v) => v as f64,
+        Value::I64(v) => v as f64,
+        Value::Str(s) => s.parse().map_err(|_| Error::ConfigurationInvalid)?,
+        _ => return Err(Error::ConfigurationInvalid),
+    };
+
+    if v.is_finite() && v >= 0.0 {
+        Ok(v)
+    } else {
+        Err(Error::ConfigurationInvalid)
+    }
+}
+
+impl Denoise {
+    /// Creates a new denoising filter with the default strengths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_format(fmt: &Formaton) -> Result<()> {
+        if fmt.is_paletted()
+            || imgutils::get_num_planes(fmt) != fmt.get_num_comp()
+            || fmt.iter().flatten().any(|c| c.get_depth() > 16)
+        {
+            return Err(Error::Unsupported(format!("format {}", fmt)));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, info: &VideoInfo) {
+        self.planes = (0..imgutils::get_num_planes(&info.format))
+            .map(|_| PlaneState::default())
+            .collect();
+        self.info = Some(info.clone());
+    }
+
+    fn denoise_plane(
+        &mut self,
+        src: &dyn FrameBuffer,
+        dst: &mut dyn FrameBuffer,
+        info: &VideoInfo,
+        plane: usize,
+    ) -> Result<()> {
+        let tables = self.tables.as_ref().ok_or(Error::ConfigurationInvalid)?;
+        let (spatial, temporal) = match plane_kind(&info.format, plane) {
+            PlaneKind::Luma => (&tables.luma_spatial, &tables.luma_temporal),
+            PlaneKind::Chroma => (&tables.chroma_spatial, &tables.chroma_temporal),
+            PlaneKind::Alpha => return copy_frame_plane(src, dst, info, plane),
+        };
+
+        let fmt = &info.format;
+        let width = imgutils::get_plane_width(fmt, plane, info.width);
+        let height = imgutils::get_plane_height(fmt, plane, info.height);
+        let sample_size = imgutils::get_plane_sample_size(fmt, plane);
+        let depth = fmt
+            .get_chromaton(plane)
+            .map(|c| u32::from(c.get_depth()))
+            .ok_or(Error::InvalidData)?;
+        let shift = 16 - depth;
+        let be = fmt.is_be();
+
+        let src_linesize = src.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let dst_linesize = dst.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let src_data = src.as_slice_inner(plane).map_err(|_| Error::InvalidData)?;
+        let dst_data = dst
+            .as_mut_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+
+        let state = &mut self.planes[plane];
+        let first_frame = state.prev.is_empty();
+        if first_frame {
+            state.prev = vec![0; width * height];
+            state.line = vec![0; width];
+        }
+
+        let mut row = vec![0u16; width];
+        for y in 0..height {
+            read_row(
+                &src_data[y * src_linesize..],
+                &mut row,
+                sample_size,
+                be,
+                shift,
+            );
+
+            let prev = &mut state.prev[y * width..][..width];
+            if first_frame {
+                prev.copy_from_slice(&row);
+            }
+
+            denoise_row(&mut row, &mut state.line, prev, y == 0, spatial, temporal);
+
+            write_row(
+                &row,
+                &mut dst_data[y * dst_linesize..],
+                sample_size,
+                be,
+                shift,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Filter for Denoise {
+    fn configure(&mut self) -> Result<()> {
+        let luma_spatial = self.luma_spatial.unwrap_or(DEFAULT_LUMA_SPATIAL);
+        let chroma_spatial = self.chroma_spatial.unwrap_or(luma_spatial * 0.75);
+        let luma_temporal = self.luma_temporal.unwrap_or(luma_spatial * 1.5);
+        let chroma_temporal = self.chroma_temporal.unwrap_or_else(|| {
+            if luma_spatial > 0.0 {
+                luma_temporal * chroma_spatial / luma_spatial
+            } else {
+                chroma_spatial * 1.5
+            }
+        });
+
+        self.tables = Some(Tables {
+            luma_spatial: coefficients(luma_spatial),
+            luma_temporal: coefficients(luma_temporal),
+            chroma_spatial: coefficients(chroma_spatial),
+            chroma_temporal: coefficients(chroma_temporal),
+        });
+
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match key {
+            "luma_spatial" => self.luma_spatial = Some(strength(val)?),
+            "chroma_spatial" => self.chroma_spatial = Some(strength(val)?),
+            "luma_temporal" => self.luma_temporal = Some(strength(val)?),
+            "chroma_temporal" => self.chroma_temporal = Some(strength(val)?),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        self.tables = None;
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let info = video_info(&frame)?.clone();
+        Self::check_format(&info.format)?;
+
+        if self.tables.is_none() {
+            self.configure()?;
+        }
+        if self.info.as_ref() != Some(&info) {
+            self.reset(&info);
+        }
+
+        let mut out = new_frame_like(&frame, info.clone());
+        for plane in 0..frame.buf.count() {
+            self.denoise_plane(&*frame.buf, &mut *out.buf, &info, plane)?;
+        }
+
+        self.out.push_back(Arc::new(out));
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{Frame, FrameType};
+    use crate::data::pixel::formats::{YUV420, YUV420_10};
+
+    const W: usize = 32;
+    const H: usize = 32;
+
+    fn noise(seed: &mut u32) -> i32 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        ((*seed >> 16) % 9) as i32 - 4
+    }
+
+    fn frame(fmt: &Formaton, base: u16, seed: &mut u32) -> ArcFrame {
+        let info = VideoInfo::new(W, H, false, FrameType::I, Arc::new(*fmt));
+        let mut f = Frame::new_default_frame(info, None);
+        let wide = imgutils::get_plane_sample_size(fmt, 0) == 2;
+
+        for plane in 0..f.buf.count() {
+            let linesize = f.buf.linesize(plane).unwrap();
+            let width = imgutils::get_plane_width(fmt, plane, W);
+            let height = imgutils::get_plane_height(fmt, plane, H);
+            let data = f.buf.as_mut_slice_inner(plane).unwrap();
+            for line in data.chunks_mut(linesize).take(height) {
+                for x in 0..width {
+                    let scale = if wide { 4 } else { 1 };
+                    let v = (i32::from(base) + noise(seed) * scale) as u16;
+                    if wide {
+                        line[x * 2..x * 2 + 2].copy_from_slice(&v.to_le_bytes());
+                    } else {
+                        line[x] = v as u8;
+                    }
+                }
+            }
+        }
+
+        Arc::new(f)
+    }
+
+    fn samples(frame: &Frame, wide: bool) -> Vec<i32> {
+        let linesize = frame.buf.linesize(0).unwrap();
+        let data = frame.buf.as_slice_inner(0).unwrap();
+        data.chunks(linesize)
+            .take(H)
+            .flat_map(|line| {
+                (0..W).map(move |x| {
+                    if wide {
+                        i32::from(u16::from_le_bytes([line[x * 2], line[x * 2 + 1]]))
+                    } else {
+                        i32::from(line[x])
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn deviation(frame: &Frame, wide: bool, base: i32) -> i32 {
+        samples(frame, wide).iter().map(|v| (v - base).abs()).sum()
+    }
+
+    #[test]
+    fn reduce_noise() {
+        for &(fmt, base, wide) in &[(YUV420, 128, false), (YUV420_10, 512, true)] {
+            let mut filter = Denoise::new();
+            filter.set_option("luma_spatial", Value::U64(8)).unwrap();
+            let mut seed = 1;
+            let mut last = 0;
+
+            for i in 0..4 {
+                let input = frame(fmt, base, &mut seed);
+                filter.send_frame(input.clone()).unwrap();
+                let output = filter.receive_frame().unwrap();
+
+                let before = deviation(&input, wide, i32::from(base));
+                let after = deviation(&output, wide, i32::from(base));
+                assert!(after < before);
+                if i > 0 {
+                    assert!(after <= last);
+                }
+                last = after;
+            }
+            assert!(last * 2 < deviation(&frame(fmt, base, &mut seed), wide, i32::from(base)));
+            assert!(filter.receive_frame().is_err());
+        }
+    }
+
+    #[test]
+    fn zero_strength() {
+        let mut filter = Denoise::new();
+        for key in &[
+            "luma_spatial",
+            "chroma_spatial",
+            "luma_temporal",
+            "chroma_temporal",
+        ] {
+            filter.set_option(key, Value::Str("0")).unwrap();
+        }
+
+        let mut seed = 7;
+        let input = frame(YUV420_10, 300, &mut seed);
+        filter.send_frame(input.clone()).unwrap();
+        let output = filter.receive_frame().unwrap();
+
+        assert_eq!(samples(&input, true), samples(&output, true));
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn avx2() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+
+        let spatial = coefficients(6.0);
+        let temporal = coefficients(9.0);
+        let mut seed = 3u32;
+        let mut sample = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            match seed >> 28 {
+                0 => 0,
+                1 => u16::MAX,
+                _ => (seed >> 8) as u16,
+            }
+        };
+
+        for &width in &[1, 8, 13, 64] {
+            let mut rows: Vec<Vec<u16>> = (0..3)
+                .map(|_| (0..width).map(|_| sample()).collect())
+                .collect();
+            let mut expected = rows.clone();
+
+            for &first_line in &[true, false] {
+                if let [row, line, prev] = &mut expected[..] {
+                    vertical_temporal(row, line, prev, first_line, &spatial, &temporal);
+                }
+                if let [row, line, prev] = &mut rows[..] {
+                    unsafe {
+                        x86::vertical_temporal(row, line, prev, first_line, &spatial, &temporal)
+                    };
+                }
+                assert_eq!(rows, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut filter = Denoise::new();
+
+        filter
+            .set_option("luma_spatial", Value::Str("2.5"))
+            .unwrap();
+        assert!(filter.set_option("luma_spatial", Value::I64(-1)).is_err());
+        assert!(filter.set_option("luma_spatial", Value::Str("x")).is_err());
+        assert!(filter.set_option("unknown", Value::U64(1)).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//!
+//! GPU scaling filter.
+//!
+//! Frames are kept on the GPU between the components sharing a wgpu
+//! device: the frames produced hold a `GpuFrame`, and the frames received
+//! are used without copies if they already are on the device of the filter.
+//!
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::data::frame::ArcFrame;
+use crate::data::value::Value;
+use crate::filter::*;
+use crate::scale::gpu::GpuBackend;
+use crate::scale::{self, PlaneInfo};
+
+fn scale_error(err: scale::Error) -> Error {
+    match err {
+        scale::Error::InvalidData => Error::InvalidData,
+        scale::Error::Unsupported(feature) => Error::Unsupported(feature),
+        scale::Error::Backend(err) => Error::Unsupported(format!("GPU operation: {}", err)),
+    }
+}
+
+/// Scaling filter producing frames stored on the GPU.
+pub struct GpuScale {
+    backend: GpuBackend,
+    width: usize,
+    height: usize,
+    out: VecDeque<ArcFrame>,
+}
+
+impl GpuScale {
+    /// Creates a new filter producing frames of the given size.
+    pub fn new(backend: GpuBackend, width: usize, height: usize) -> Self {
+        GpuScale {
+            backend,
+            width,
+            height,
+            out: VecDeque::new(),
+        }
+    }
+
+    /// Returns the backend of the filter.
+    pub fn get_backend(&self) -> &GpuBackend {
+        &self.backend
+    }
+}
+
+impl Filter for GpuScale {
+    fn configure(&mut self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::ConfigurationInvalid);
+        }
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("width", Value::U64(v)) => self.width = v as usize,
+            ("height", Value::U64(v)) => self.height = v as usize,
+            ("width", _) | ("height", _) => return Err(Error::ConfigurationInvalid),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let info = video_info(&frame)?;
+        let src = self.backend.import(&frame).map_err(scale_error)?;
+
+        let mut out_info = info.clone();
+        out_info.width = self.width;
+        out_info.height = self.height;
+        let planes = PlaneInfo::from_video_info(&out_info).map_err(scale_error)?;
+        let scaled = self
+            .backend
+            .scale_frame(&src, &planes)
+            .map_err(scale_error)?;
+
+        let mut out = scaled.into_frame(out_info, frame.t.clone());
+        out.metadata = frame.metadata.clone();
+        self.out.push_back(Arc::new(out));
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{Frame, FrameType, VideoInfo};
+    use crate::data::pixel::formats::YUV420;
+    use crate::scale::Scaler;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn chain() {
+        let backend = GpuBackend::new().unwrap();
+        let device = backend.get_hw_device().clone();
+
+        let info = VideoInfo::new(16, 16, false, FrameType::I, Arc::new(*YUV420));
+        let mut frame = Frame::new_default_frame(info, None);
+        for plane in 0..3 {
+            let data = frame.buf.as_mut_slice_inner(plane).unwrap();
+            for (i, s) in data.iter_mut().enumerate() {
+                *s = (i * 7 % 256) as u8;
+            }
+        }
+        let expected = Scaler::new(8, 8).scale(&frame).unwrap();
+
+        let mut first = GpuScale::new(backend, 16, 8);
+        let mut second = GpuScale::new(GpuBackend::from_hw_device(&device).unwrap(), 0, 0);
+        second.set_option("width", Value::U64(8)).unwrap();
+        assert!(second.configure().is_err());
+        second.set_option("height", Value::U64(8)).unwrap();
+        second.configure().unwrap();
+
+        first.send_frame(Arc::new(frame)).unwrap();
+        let scaled = first.receive_frame().unwrap();
+        assert!(scaled.buf.as_hw_frame().is_some());
+        second.send_frame(scaled).unwrap();
+        let scaled = second.receive_frame().unwrap();
+        assert!(matches!(second.receive_frame(), Err(Error::MoreDataNeeded)));
+
+        let gpu = second.get_backend().import(&scaled).unwrap();
+        let mut out = Frame::new_default_frame(scaled.kind.clone(), None);
+        second.get_backend().download(&gpu, &mut out).unwrap();
+        let (a, b) = (
+            out.buf.as_slice_inner(0).unwrap(),
+            expected.buf.as_slice_inner(0).unwrap(),
+        );
+        let linesize = out.buf.linesize(0).unwrap();
+        for (la, lb) in a.chunks(linesize).zip(b.chunks(linesize)).take(8) {
+            assert!(la[..8]
+                .iter()
+                .zip(&lb[..8])
+                .all(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() <= 2));
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//!
+//! 3D LUT application.
+//!
+//! Applies color transforms stored as `.cube` 3D lookup tables to R'G'B'
+//! values, using tetrahedral interpolation between the table entries.
+//!
+//! Packed 8-bit RGB frames are transformed directly, planar Y'CbCr frames
+//! are converted to R'G'B' and back to the same format.
+//!
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::data::frame::{ArcFrame, FrameBuffer, VideoInfo};
+use crate::data::imgutils;
+use crate::data::pixel::{ColorModel, Formaton, TrichromaticEncodingSystem};
+use crate::data::value::Value;
+use crate::filter::colorspace::*;
+use crate::filter::*;
+
+/// Largest table size accepted, as in the Adobe specification.
+const MAX_SIZE: usize = 256;
+
+/// A 3D lookup table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cube {
+    /// Table title, if any.
+    pub title: Option<String>,
+    /// Number of entries for each dimension.
+    pub size: usize,
+    /// Input values mapped to the first entry of each dimension.
+    pub domain_min: [f64; 3],
+    /// Input values mapped to the last entry of each dimension.
+    pub domain_max: [f64; 3],
+    /// Table entries, red index changing the fastest.
+    pub table: Vec<[f64; 3]>,
+}
+
+fn parse_triplet<'a, I: Iterator<Item = &'a str>>(mut tokens: I) -> Result<[f64; 3]> {
+    let mut v = [0.0; 3];
+    for c in v.iter_mut() {
+        *c = tokens
+            .next()
+            .and_then(|t| t.parse().ok())
+            .ok_or(Error::InvalidData)?;
+    }
+    if tokens.next().is_some() {
+        return Err(Error::InvalidData);
+    }
+    Ok(v)
+}
+
+impl Cube {
+    /// Parses the content of a `.cube` file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap_or_default();
+            match keyword {
+                "TITLE" => {
+                    let t = line["TITLE".len()..].trim().trim_matches('"');
+                    title = Some(t.to_owned());
+                }
+                "LUT_3D_SIZE" => {
+                    let n = tokens
+                        .next()
+                        .and_then(|t| t.parse::<usize>().ok())
+                        .filter(|n| (2..=MAX_SIZE).contains(n))
+                        .ok_or(Error::InvalidData)?;
+                    size = Some(n);
+                }
+                "LUT_1D_SIZE" => return Err(Error::Unsupported("1D LUT".to_owned())),
+                "DOMAIN_MIN" => domain_min = parse_triplet(tokens)?,
+                "DOMAIN_MAX" => domain_max = parse_triplet(tokens)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let range = tokens
+                        .map(|t| t.parse::<f64>().map_err(|_| Error::InvalidData))
+                        .collect::<Result<Vec<_>>>()?;
+                    if range.len() != 2 {
+                        return Err(Error::InvalidData);
+                    }
+                    domain_min = [range[0]; 3];
+                    domain_max = [range[1]; 3];
+                }
+                _ => table.push(parse_triplet(line.split_whitespace())?),
+            }
+        }
+
+        let size = size.ok_or(Error::InvalidData)?;
+        if table.len() != size * size * size || (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Cube {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Loads a `.cube` file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|_| Error::InvalidData)?;
+        Self::parse(&text)
+    }
+
+    #[inline(always)]
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f64; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    /// Transforms an R'G'B' triplet.
+    pub fn apply(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let last = (self.size - 1) as f64;
+        let mut i0 = [0; 3];
+        let mut i1 = [0; 3];
+        let mut f = [0.0; 3];
+
+        for c in 0..3 {
+            let x = (rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]);
+            let x = (x * last).clamp(0.0, last);
+            let i = x.floor();
+            i0[c] = i as usize;
+            i1[c] = (i0[c] + 1).min(self.size - 1);
+            f[c] = x - i;
+        }
+
+        let c000 = self.entry(i0[0], i0[1], i0[2]);
+        let c111 = self.entry(i1[0], i1[1], i1[2]);
+        let (fr, fg, fb) = (f[0], f[1], f[2]);
+
+        // Each ordering of the fractional parts selects one of the six
+        // tetrahedra splitting the cube cell.
+        let (w, ca, cb) = if fr > fg {
+            if fg > fb {
+                (
+                    [1.0 - fr, fr - fg, fg - fb, fb],
+                    self.entry(i1[0], i0[1], i0[2]),
+                    self.entry(i1[0], i1[1], i0[2]),
+                )
+            } else if fr > fb {
+                (
+                    [1.0 - fr, fr - fb, fb - fg, fg],
+                    self.entry(i1[0], i0[1], i0[2]),
+                    self.entry(i1[0], i0[1], i1[2]),
+                )
+            } else {
+                (
+                    [1.0 - fb, fb - fr, fr - fg, fg],
+                    self.entry(i0[0], i0[1], i1[2]),
+                    self.entry(i1[0], i0[1], i1[2]),
+                )
+            }
+        } else if fb > fg {
+            (
+                [1.0 - fb, fb - fg, fg - fr, fr],
+                self.entry(i0[0], i0[1], i1[2]),
+                self.entry(i0[0], i1[1], i1[2]),
+            )
+        } else if fb > fr {
+            (
+                [1.0 - fg, fg - fb, fb - fr, fr],
+                self.entry(i0[0], i1[1], i0[2]),
+                self.entry(i0[0], i1[1], i1[2]),
+            )
+        } else {
+            (
+                [1.0 - fg, fg - fr, fr - fb, fb],
+                self.entry(i0[0], i1[1], i0[2]),
+                self.entry(i1[0], i1[1], i0[2]),
+            )
+        };
+
+        let mut out = [0.0; 3];
+        for (c, o) in out.iter_mut().enumerate() {
+            *o = w[0] * c000[c] + w[1] * ca[c] + w[2] * cb[c] + w[3] * c111[c];
+        }
+        out
+    }
+}
+
+/// Returns the byte offsets of the red, green and blue components of a
+/// packed 8-bit RGB format.
+fn packed_rgb_offsets(fmt: &Formaton) -> Option<[usize; 3]> {
+    if fmt.get_model() != ColorModel::Trichromatic(TrichromaticEncodingSystem::RGB)
+        || fmt.is_paletted()
+        || imgutils::get_num_planes(fmt) != 1
+    {
+        return None;
+    }
+
+    let mut offs = [0; 3];
+    for (c, off) in offs.iter_mut().enumerate() {
+        let chr = fmt.get_chromaton(c)?;
+        if chr.get_depth() != 8 || chr.get_shift() != 0 {
+            return None;
+        }
+        *off = chr.get_offset() as usize;
+    }
+    Some(offs)
+}
+
+/// 3D LUT filter.
+///
+/// The table is loaded from the file set through the `file` option or
+/// given on creation.
+#[derive(Default)]
+pub struct Lut3d {
+    cube: Option<Arc<Cube>>,
+    out: VecDeque<ArcFrame>,
+}
+
+impl Lut3d {
+    /// Creates a new filter without a table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new filter applying `cube`.
+    pub fn with_cube(cube: Cube) -> Self {
+        Lut3d {
+            cube: Some(Arc::new(cube)),
+            out: VecDeque::new(),
+        }
+    }
+
+    fn apply_rgb(
+        cube: &Cube,
+        offs: [usize; 3],
+        dst: &mut dyn FrameBuffer,
+        info: &VideoInfo,
+    ) -> Result<()> {
+        let step = info.format.get_elem_size() as usize;
+        let linesize = dst.linesize(0).map_err(|_| Error::InvalidData)?;
+        let data = dst.as_mut_slice_inner(0).map_err(|_| Error::InvalidData)?;
+
+        for line in data.chunks_mut(linesize).take(info.height) {
+            for px in line.chunks_exact_mut(step).take(info.width) {
+                let rgb = [
+                    f64::from(px[offs[0]]) / 255.0,
+                    f64::from(px[offs[1]]) / 255.0,
+                    f64::from(px[offs[2]]) / 255.0,
+                ];
+                let out = cube.apply(rgb);
+                for (c, &off) in offs.iter().enumerate() {
+                    px[off] = (out[c] * 255.0).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Filter for Lut3d {
+    fn configure(&mut self) -> Result<()> {
+        if self.cube.is_none() {
+            return Err(Error::ConfigurationInvalid);
+        }
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("file", Value::Str(path)) => self.cube = Some(Arc::new(Cube::open(path)?)),
+            ("file", _) => return Err(Error::ConfigurationInvalid),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let cube = self.cube.clone().ok_or(Error::ConfigurationInvalid)?;
+        let info = video_info(&frame)?.clone();
+        let fmt = &info.format;
+
+        let out = if let Some(offs) = packed_rgb_offsets(fmt) {
+            let mut out = copy_frame(&frame, info.clone())?;
+            Self::apply_rgb(&cube, offs, &mut *out.buf, &info)?;
+            out
+        } else {
+            let layout = YuvLayout::new(fmt)?;
+            let matrix = luma_coefficients(fmt.get_matrix(), BT709_LUMA)
+                .map(YuvMatrix::new)
+                .ok_or_else(|| Error::Unsupported(format!("matrix {}", fmt.get_matrix())))?;
+
+            let mut out = new_frame_like(&frame, info.clone());
+            map_yuv(
+                &layout,
+                &layout,
+                &*frame.buf,
+                &mut *out.buf,
+                &info,
+                |y, cb, cr| {
+                    let rgb = matrix.to_rgb(y, cb, cr);
+                    let rgb = cube.apply([
+                        rgb[0].clamp(0.0, 1.0),
+                        rgb[1].clamp(0.0, 1.0),
+                        rgb[2].clamp(0.0, 1.0),
+                    ]);
+                    matrix.to_yuv(rgb)
+                },
+            )?;
+            for plane in 3..frame.buf.count() {
+                copy_frame_plane(&*frame.buf, &mut *out.buf, &info, plane)?;
+            }
+            out
+        };
+
+        self.out.push_back(Arc::new(out));
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{Frame, FrameType};
+    use crate::data::pixel::formats::{RGB24, YUV420};
+
+    fn cube_text<F: Fn([f64; 3]) -> [f64; 3]>(size: usize, f: F) -> String {
+        let mut text = format!("# test\nTITLE \"test\"\nLUT_3D_SIZE {}\n", size);
+        let last = (size - 1) as f64;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let out = f([r as f64 / last, g as f64 / last, b as f64 / last]);
+                    text += &format!("{} {} {}\n", out[0], out[1], out[2]);
+                }
+            }
+        }
+        text
+    }
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        (0..3).all(|c| (a[c] - b[c]).abs() < 1e-9)
+    }
+
+    #[test]
+    fn parse() {
+        let cube = Cube::parse(&cube_text(2, |rgb| rgb)).unwrap();
+        assert_eq!(cube.title.as_deref(), Some("test"));
+        assert_eq!(cube.size, 2);
+        assert_eq!(cube.table.len(), 8);
+        assert_eq!(cube.table[1], [1.0, 0.0, 0.0]);
+
+        let text =
+            "LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n".to_owned() + &"0 0 0\n".repeat(8);
+        assert_eq!(Cube::parse(&text).unwrap().domain_max, [2.0; 3]);
+
+        assert!(Cube::parse("0 0 0\n").is_err());
+        assert!(Cube::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Cube::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(Cube::parse("LUT_3D_SIZE 2\n0 0\n").is_err());
+    }
+
+    #[test]
+    fn tetrahedral() {
+        let swap = |rgb: [f64; 3]| [rgb[2], rgb[1], rgb[0]];
+        let cube = Cube::parse(&cube_text(5, swap)).unwrap();
+
+        for &rgb in &[
+            [0.1, 0.5, 0.9],
+            [0.9, 0.5, 0.1],
+            [0.3, 0.3, 0.3],
+            [0.6, 0.2, 0.4],
+            [0.2, 0.7, 0.45],
+            [1.0, 0.0, 1.0],
+        ] {
+            assert!(close(cube.apply(rgb), swap(rgb)));
+        }
+
+        // Out of domain values are clamped.
+        assert!(close(cube.apply([-1.0, 2.0, 0.5]), [0.5, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn rgb_frame() {
+        let cube = Cube::parse(&cube_text(3, |rgb| [rgb[2], rgb[1], rgb[0]])).unwrap();
+        let mut filter = Lut3d::with_cube(cube);
+
+        let info = VideoInfo::new(4, 2, false, FrameType::I, Arc::new(*RGB24));
+        let mut frame = Frame::new_default_frame(info, None);
+        let offs = packed_rgb_offsets(RGB24).unwrap();
+        let linesize = frame.buf.linesize(0).unwrap();
+        let data = frame.buf.as_mut_slice_inner(0).unwrap();
+        for line in data.chunks_mut(linesize).take(2) {
+            for px in line.chunks_exact_mut(3).take(4) {
+                px[offs[0]] = 255;
+                px[offs[1]] = 51;
+                px[offs[2]] = 0;
+            }
+        }
+
+        filter.send_frame(Arc::new(frame)).unwrap();
+        let out = filter.receive_frame().unwrap();
+        let data = out.buf.as_slice_inner(0).unwrap();
+        assert_eq!([data[offs[0]], data[offs[1]], data[offs[2]]], [0, 51, 255]);
+    }
+
+    #[test]
+    fn yuv_frame() {
+        let cube = Cube::parse(&cube_text(2, |rgb| rgb)).unwrap();
+        let mut filter = Lut3d::with_cube(cube);
+
+        let info = VideoInfo::new(4, 4, false, FrameType::I, Arc::new(*YUV420));
+        let mut frame = Frame::new_default_frame(info, None);
+        for (plane, v) in [(0, 120u8), (1, 100), (2, 150)] {
+            frame
+                .buf
+                .as_mut_slice_inner(plane)
+                .unwrap()
+                .iter_mut()
+                .for_each(|s| *s = v);
+        }
+
+        filter.send_frame(Arc::new(frame)).unwrap();
+        let out = filter.receive_frame().unwrap();
+        for (plane, v) in [(0, 120i32), (1, 100), (2, 150)] {
+            let s = out.buf.as_slice_inner(plane).unwrap()[0];
+            assert!((i32::from(s) - v).abs() <= 1);
+        }
+
+        assert!(Lut3d::new().configure().is_err());
+        assert!(Lut3d::new()
+            .set_option("file", Value::Str("/nonexistent.cube"))
+            .is_err());
+    }
+}
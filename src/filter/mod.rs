@@ -1 +1,124 @@
+//!
+//! Frame filters.
+//!
+//! Filters consume frames and produce frames, possibly with a different
+//! timing (e.g. dropping or merging frames).
+//!
 
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::data::frame::{ArcFrame, Frame, FrameBuffer, MediaKind, VideoInfo};
+use crate::data::imgutils;
+use crate::data::value::Value;
+
+pub(crate) mod colorspace;
+pub mod denoise;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod lut3d;
+pub mod pullup;
+pub mod remix;
+pub mod tonemap;
+
+/// General filtering errors.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid input data.
+    #[error("Invalid Data")]
+    InvalidData,
+    /// The filter needs more input frames to produce an output frame.
+    #[error("Additional data needed")]
+    MoreDataNeeded,
+    /// Invalid input configuration.
+    #[error("Configuration Invalid")]
+    ConfigurationInvalid,
+    /// Unsupported requested feature.
+    #[error("Unsupported feature {0}")]
+    Unsupported(String),
+}
+
+/// A specialised `Result` type for filtering operations.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Used to interact with a filter.
+pub trait Filter: Send {
+    /// Configures the filter.
+    fn configure(&mut self) -> Result<()>;
+    /// Sets a filter option.
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()>;
+    /// Sends to the filter a frame to be processed.
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()>;
+    /// Returns a processed frame.
+    ///
+    /// `Error::MoreDataNeeded` is returned if no frame is available yet.
+    fn receive_frame(&mut self) -> Result<ArcFrame>;
+    /// Tells the filter no more frames will be sent, so that all the
+    /// frames it holds can be received.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// Returns the video information of a frame.
+pub(crate) fn video_info(frame: &Frame) -> Result<&VideoInfo> {
+    match frame.kind {
+        MediaKind::Video(ref info) => Ok(info),
+        MediaKind::Audio(_) => Err(Error::Unsupported("audio frames".to_owned())),
+    }
+}
+
+/// Creates a new frame sharing timestamps and metadata with `src`.
+pub(crate) fn new_frame_like(src: &Frame, info: VideoInfo) -> Frame {
+    let mut frame = Frame::new_default_frame(info, Some(src.t.clone()));
+    frame.metadata = src.metadata.clone();
+    frame
+}
+
+/// Copies the content of `src` into a new frame described by `info`.
+///
+/// `info` must describe an image with the same size and format as `src`.
+pub(crate) fn copy_frame(src: &Frame, info: VideoInfo) -> Result<Frame> {
+    let src_info = video_info(src)?;
+    let mut frame = new_frame_like(src, info);
+
+    for plane in 0..src.buf.count() {
+        copy_frame_plane(&*src.buf, &mut *frame.buf, src_info, plane)?;
+    }
+
+    Ok(frame)
+}
+
+/// Returns an owned version of a frame, copying it if it is shared.
+pub(crate) fn make_owned(frame: ArcFrame) -> Result<Frame> {
+    match Arc::try_unwrap(frame) {
+        Ok(frame) => Ok(frame),
+        Err(shared) => {
+            let info = video_info(&shared)?.clone();
+            copy_frame(&shared, info)
+        }
+    }
+}
+
+/// Copies a whole plane between two frame buffers holding images of the
+/// same size and format.
+pub(crate) fn copy_frame_plane(
+    src: &dyn FrameBuffer,
+    dst: &mut dyn FrameBuffer,
+    info: &VideoInfo,
+    plane: usize,
+) -> Result<()> {
+    let src_linesize = src.linesize(plane).map_err(|_| Error::InvalidData)?;
+    let dst_linesize = dst.linesize(plane).map_err(|_| Error::InvalidData)?;
+
+    imgutils::copy_plane(
+        dst.as_mut_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?,
+        dst_linesize,
+        src.as_slice_inner(plane).map_err(|_| Error::InvalidData)?,
+        src_linesize,
+        imgutils::get_plane_bytewidth(&info.format, plane, info.width),
+        imgutils::get_plane_height(&info.format, plane, info.height),
+    );
+
+    Ok(())
+}
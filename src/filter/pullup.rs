@@ -0,0 +1,459 @@
+//!
+//! Inverse telecine.
+//!
+//! Reconstructs the progressive frames of film content converted to
+//! 29.97 fps video through 3:2 pulldown.
+//!
+//! Soft telecined frames, flagged to repeat fields on display, are
+//! returned as they are with the repetition flags cleared.
+//!
+//! Hard telecined frames are matched with the opposite field of the
+//! previous frame whenever that produces less combing, then the frame
+//! most similar to its predecessor is dropped every five frames and the
+//! remaining ones are evenly retimed.
+//!
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::data::frame::{ArcFrame, Field, FieldInfo, Frame, FrameBuffer, VideoInfo};
+use crate::data::imgutils;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::data::value::Value;
+use crate::filter::*;
+
+/// Number of frames in a 3:2 pulldown pattern.
+const CYCLE: usize = 5;
+
+/// Number of frames without repeat flags that can follow a frame with
+/// them while still being considered part of a soft telecined sequence.
+const SOFT_HOLD: usize = 4;
+
+/// Default minimum difference between a line and both its neighbours for
+/// a pixel to be considered combed.
+const DEFAULT_COMB_THRESHOLD: u8 = 10;
+
+struct Candidate {
+    frame: ArcFrame,
+    diff: u64,
+}
+
+/// Inverse telecine filter.
+pub struct Pullup {
+    comb_threshold: u8,
+    prev: Option<ArcFrame>,
+    last: Option<ArcFrame>,
+    window: VecDeque<Candidate>,
+    out: VecDeque<ArcFrame>,
+    soft: usize,
+}
+
+impl Default for Pullup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts the pixels of the luma plane differing from both the line above
+/// and the line below in the same direction.
+fn comb_score(buf: &dyn FrameBuffer, info: &VideoInfo, threshold: u8) -> Result<u64> {
+    let data = buf.as_slice_inner(0).map_err(|_| Error::InvalidData)?;
+    let linesize = buf.linesize(0).map_err(|_| Error::InvalidData)?;
+    let threshold = i16::from(threshold);
+    let mut score = 0;
+
+    for y in 1..info.height.saturating_sub(1) {
+        let above = &data[(y - 1) * linesize..][..info.width];
+        let line = &data[y * linesize..][..info.width];
+        let below = &data[(y + 1) * linesize..][..info.width];
+
+        for ((&a, &b), &c) in above.iter().zip(line).zip(below) {
+            let d1 = i16::from(a) - i16::from(b);
+            let d2 = i16::from(c) - i16::from(b);
+            if (d1 > threshold && d2 > threshold) || (d1 < -threshold && d2 < -threshold) {
+                score += 1;
+            }
+        }
+    }
+
+    Ok(score)
+}
+
+/// Sum of absolute differences of the luma planes of two frames.
+fn frame_diff(a: &Frame, b: &Frame, info: &VideoInfo) -> Result<u64> {
+    let a_data = a.buf.as_slice_inner(0).map_err(|_| Error::InvalidData)?;
+    let b_data = b.buf.as_slice_inner(0).map_err(|_| Error::InvalidData)?;
+    let a_linesize = a.buf.linesize(0).map_err(|_| Error::InvalidData)?;
+    let b_linesize = b.buf.linesize(0).map_err(|_| Error::InvalidData)?;
+
+    let diff = a_data
+        .chunks(a_linesize)
+        .zip(b_data.chunks(b_linesize))
+        .take(info.height)
+        .map(|(la, lb)| {
+            la[..info.width]
+                .iter()
+                .zip(&lb[..info.width])
+                .map(|(&pa, &pb)| u64::from(pa.abs_diff(pb)))
+                .sum::<u64>()
+        })
+        .sum();
+
+    Ok(diff)
+}
+
+/// Builds a frame made of the `field` lines of `cur` and the opposite
+/// field lines of `prev`.
+fn weave(cur: &Frame, prev: &Frame, info: &VideoInfo, field: Field) -> Result<Frame> {
+    let mut out_info = info.clone();
+    out_info.fields = FieldInfo::default();
+    let mut frame = new_frame_like(cur, out_info);
+    let keep_even = field == Field::Top;
+
+    for plane in 0..frame.buf.count() {
+        let bytewidth = imgutils::get_plane_bytewidth(&info.format, plane, info.width);
+        let height = imgutils::get_plane_height(&info.format, plane, info.height);
+        let cur_linesize = cur.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let prev_linesize = prev.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let dst_linesize = frame.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let cur_data = cur
+            .buf
+            .as_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+        let prev_data = prev
+            .buf
+            .as_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+        let dst = frame
+            .buf
+            .as_mut_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+
+        for (y, line) in dst.chunks_mut(dst_linesize).take(height).enumerate() {
+            let src = if (y % 2 == 0) == keep_even {
+                &cur_data[y * cur_linesize..]
+            } else {
+                &prev_data[y * prev_linesize..]
+            };
+            line[..bytewidth].copy_from_slice(&src[..bytewidth]);
+        }
+    }
+
+    Ok(frame)
+}
+
+/// Returns a frame with the same content and progressive field information.
+fn progressive(frame: ArcFrame) -> Result<ArcFrame> {
+    let info = video_info(&frame)?;
+    if info.fields == FieldInfo::default() {
+        return Ok(frame);
+    }
+
+    let mut info = info.clone();
+    info.fields = FieldInfo::default();
+    let mut frame = make_owned(frame)?;
+    frame.kind = info.into();
+
+    Ok(Arc::new(frame))
+}
+
+impl Pullup {
+    /// Creates a new inverse telecine filter.
+    pub fn new() -> Self {
+        Pullup {
+            comb_threshold: DEFAULT_COMB_THRESHOLD,
+            prev: None,
+            last: None,
+            window: VecDeque::with_capacity(CYCLE),
+            out: VecDeque::new(),
+            soft: 0,
+        }
+    }
+
+    fn check_format(info: &VideoInfo) -> Result<()> {
+        let fmt = &info.format;
+        if fmt.is_paletted()
+            || imgutils::get_num_planes(fmt) != fmt.get_num_comp()
+            || imgutils::get_plane_sample_size(fmt, 0) != 1
+        {
+            return Err(Error::Unsupported(format!("format {}", fmt)));
+        }
+        Ok(())
+    }
+
+    fn field_match(&self, frame: ArcFrame, info: &VideoInfo) -> Result<ArcFrame> {
+        let prev = match self.prev {
+            Some(ref prev) => prev,
+            None => return progressive(frame),
+        };
+        if *video_info(prev)? != *info {
+            return progressive(frame);
+        }
+
+        let c_score = comb_score(&*frame.buf, info, self.comb_threshold)?;
+        if c_score == 0 {
+            return progressive(frame);
+        }
+
+        let woven = weave(&frame, prev, info, info.fields.first_field)?;
+        let p_score = comb_score(&*woven.buf, info, self.comb_threshold)?;
+
+        if p_score < c_score {
+            Ok(Arc::new(woven))
+        } else {
+            progressive(frame)
+        }
+    }
+
+    fn drain_window(&mut self) {
+        self.out.extend(self.window.drain(..).map(|c| c.frame));
+        self.last = None;
+    }
+
+    fn decimate(&mut self) -> Result<()> {
+        let drop = self
+            .window
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| c.diff)
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+
+        let first = self.window.front().and_then(|c| c.frame.t.get_pts());
+        let last = self.window.back().and_then(|c| c.frame.t.get_pts());
+        let timing = match (first, last) {
+            (Some(first), Some(last)) if last > first => {
+                let elapsed = last.duration_since(first).ok().map(|d| d.get_value());
+                elapsed.map(|elapsed| (first, elapsed as i64 * CYCLE as i64 / (CYCLE as i64 - 1)))
+            }
+            _ => None,
+        };
+
+        self.window.remove(drop);
+
+        let kept = self.window.len() as i64;
+        for (k, c) in self.window.drain(..).enumerate() {
+            let frame = match timing {
+                Some((first, span)) => {
+                    let mut frame = make_owned(c.frame)?;
+                    let timebase = first.get_timebase();
+                    let pts = first.get_value() + k as i64 * span / kept;
+                    let duration = Duration::new((span / kept) as u64, timebase);
+                    frame
+                        .t
+                        .set_pts(Some(Timestamp::new(pts, timebase)))
+                        .and_then(|_| frame.t.set_duration(Some(duration)))
+                        .map_err(|_| Error::InvalidData)?;
+                    Arc::new(frame)
+                }
+                None => c.frame,
+            };
+            self.last = Some(frame.clone());
+            self.out.push_back(frame);
+        }
+
+        Ok(())
+    }
+}
+
+impl Filter for Pullup {
+    fn configure(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("comb_threshold", Value::U64(v)) if v <= 255 => self.comb_threshold = v as u8,
+            ("comb_threshold", Value::I64(v)) if (0..=255).contains(&v) => {
+                self.comb_threshold = v as u8
+            }
+            ("comb_threshold", _) => return Err(Error::ConfigurationInvalid),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let info = video_info(&frame)?.clone();
+        Self::check_format(&info)?;
+
+        let flagged = info.fields.repeat_first_field || info.fields.repeat_frame > 0;
+        if flagged {
+            self.soft = SOFT_HOLD;
+        } else {
+            self.soft = self.soft.saturating_sub(1);
+        }
+
+        if flagged || self.soft > 0 {
+            self.drain_window();
+            self.prev = Some(frame.clone());
+            self.out.push_back(progressive(frame)?);
+            return Ok(());
+        }
+
+        let matched = self.field_match(frame.clone(), &info)?;
+        self.prev = Some(frame);
+
+        let diff = match self.last {
+            Some(ref last) => frame_diff(last, &matched, &info)?,
+            None => u64::MAX,
+        };
+        self.last = Some(matched.clone());
+        self.window.push_back(Candidate {
+            frame: matched,
+            diff,
+        });
+
+        if self.window.len() == CYCLE {
+            self.last = None;
+            self.decimate()?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.drain_window();
+        self.prev = None;
+        self.soft = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{FrameType, MediaKind};
+    use crate::data::pixel::formats::YUV420;
+    use crate::data::timeinfo::TimeInfo;
+    use crate::rational::Rational64;
+
+    const W: usize = 16;
+    const H: usize = 16;
+
+    fn frame(top: u8, bottom: u8, pts: i64, fields: FieldInfo) -> ArcFrame {
+        let mut info = VideoInfo::new(W, H, false, FrameType::I, Arc::new(*YUV420));
+        info.fields = fields;
+        let mut t = TimeInfo::default();
+        t.set_pts(Some(Timestamp::new(pts, Rational64::new(1, 90000))))
+            .unwrap();
+        let mut f = Frame::new_default_frame(info, Some(t));
+        for plane in 0..f.buf.count() {
+            let linesize = f.buf.linesize(plane).unwrap();
+            let data = f.buf.as_mut_slice_inner(plane).unwrap();
+            for (y, line) in data.chunks_mut(linesize).enumerate() {
+                let v = if y % 2 == 0 { top } else { bottom };
+                line.iter_mut().for_each(|p| *p = v);
+            }
+        }
+        Arc::new(f)
+    }
+
+    fn level(frame: &Frame) -> (u8, u8) {
+        let data = frame.buf.as_slice_inner(0).unwrap();
+        let linesize = frame.buf.linesize(0).unwrap();
+        (data[0], data[linesize])
+    }
+
+    fn drain(filter: &mut Pullup) -> Vec<ArcFrame> {
+        let mut out = Vec::new();
+        while let Ok(f) = filter.receive_frame() {
+            out.push(f);
+        }
+        out
+    }
+
+    #[test]
+    fn hard_telecine() {
+        let film: Vec<u8> = (0..8).map(|i| 40 + i * 20).collect();
+        let interlaced = FieldInfo {
+            interlaced: true,
+            ..Default::default()
+        };
+        let mut filter = Pullup::new();
+
+        // Top field first 3:2 pulldown: AA BB BC CD DD
+        let mut pts = 0;
+        for quad in film.chunks(4) {
+            let (a, b, c, d) = (quad[0], quad[1], quad[2], quad[3]);
+            for &(top, bottom) in &[(a, a), (b, b), (b, c), (c, d), (d, d)] {
+                filter
+                    .send_frame(frame(top, bottom, pts, interlaced))
+                    .unwrap();
+                pts += 1001;
+            }
+        }
+        filter.flush().unwrap();
+
+        let out = drain(&mut filter);
+        let levels: Vec<_> = out.iter().map(|f| level(f)).collect();
+        let expected: Vec<_> = film.iter().map(|&v| (v, v)).collect();
+        assert_eq!(levels, expected);
+
+        let pts: Vec<_> = out
+            .iter()
+            .map(|f| f.t.get_pts().unwrap().get_value())
+            .collect();
+        assert_eq!(&pts[..4], &[0, 1251, 2502, 3753]);
+        assert_eq!(out[0].t.get_duration().map(Duration::get_value), Some(1251));
+        assert!(out
+            .iter()
+            .all(|f| *video_info(f).unwrap().get_field_info() == FieldInfo::default()));
+    }
+
+    #[test]
+    fn soft_telecine() {
+        let rff = FieldInfo {
+            repeat_first_field: true,
+            ..Default::default()
+        };
+        let mut filter = Pullup::new();
+
+        for i in 0..8u8 {
+            let fields = if i % 2 == 1 {
+                rff
+            } else {
+                FieldInfo::default()
+            };
+            filter
+                .send_frame(frame(i * 10, i * 10, i as i64, fields))
+                .unwrap();
+        }
+        filter.flush().unwrap();
+
+        let out = drain(&mut filter);
+        assert_eq!(out.len(), 8);
+        for (i, f) in out.iter().enumerate() {
+            assert_eq!(level(f), (i as u8 * 10, i as u8 * 10));
+            assert!(!video_info(f).unwrap().fields.repeat_first_field);
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut filter = Pullup::new();
+
+        filter.set_option("comb_threshold", Value::U64(20)).unwrap();
+        assert!(filter
+            .set_option("comb_threshold", Value::U64(300))
+            .is_err());
+        assert!(filter.set_option("unknown", Value::U64(1)).is_err());
+
+        let audio = Frame::new_default_frame(
+            MediaKind::Audio(crate::data::frame::AudioInfo::new(
+                4,
+                48000,
+                crate::data::audiosample::ChannelMap::default_map(1),
+                Arc::new(crate::data::audiosample::formats::S16),
+                None,
+            )),
+            None,
+        );
+        assert!(filter.send_frame(Arc::new(audio)).is_err());
+    }
+}
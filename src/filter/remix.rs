@@ -0,0 +1,375 @@
+//!
+//! Channel remixing.
+//!
+//! Converts audio frames to a channel layout. The channels present in both
+//! layouts are copied, the others are folded into their nearest output
+//! channels: the centre channels into the left and right ones at -3 dB,
+//! the surround channels into the side ones or the front ones, and so on.
+//! The LFE channels are dropped when the output has none.
+//!
+//! The 16 bit integer and 32 bit float samples are remixed, either planar
+//! or interleaved.
+//!
+
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::sync::Arc;
+
+use crate::data::audiosample::{ChannelLayout, ChannelMap, ChannelType, Soniton};
+use crate::data::frame::{ArcFrame, AudioInfo, Frame, MediaKind};
+use crate::data::value::Value;
+use crate::filter::*;
+
+/// Maximum number of fallbacks followed to route a channel.
+const MAX_DEPTH: usize = 4;
+
+/// Returns the alternative channels a channel is folded into, by
+/// preference, along with their gains.
+fn fallbacks(ch: ChannelType) -> &'static [&'static [(ChannelType, f32)]] {
+    use crate::data::audiosample::ChannelType::*;
+    const H: f32 = FRAC_1_SQRT_2;
+    match ch {
+        C => &[&[(L, H), (R, H)]],
+        L | R => &[&[(C, H)]],
+        Ls => &[&[(Lss, 1.0)], &[(L, H)]],
+        Rs => &[&[(Rss, 1.0)], &[(R, H)]],
+        Lss => &[&[(Ls, 1.0)], &[(L, H)]],
+        Rss => &[&[(Rs, 1.0)], &[(R, H)]],
+        Cs => &[&[(Ls, H), (Rs, H)]],
+        LFE => &[&[(LFE2, 1.0)]],
+        LFE2 => &[&[(LFE, 1.0)]],
+        ch if ch.is_left() => &[&[(L, H)]],
+        ch if ch.is_right() => &[&[(R, H)]],
+        _ => &[&[(C, H)]],
+    }
+}
+
+/// Adds the gains of a channel to the output channels it is routed to,
+/// returning whether it was routed.
+fn route(ch: ChannelType, gain: f32, output: &ChannelMap, depth: usize, gains: &mut [f32]) -> bool {
+    if let Some(idx) = output.find_channel_id(ch) {
+        gains[idx as usize] += gain;
+        return true;
+    }
+    if depth == MAX_DEPTH {
+        return false;
+    }
+    for targets in fallbacks(ch) {
+        let mut routed = false;
+        for &(target, g) in targets.iter() {
+            routed |= route(target, gain * g, output, depth + 1, gains);
+        }
+        if routed {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the gains of the input channels in each output channel, if both
+/// layouts have named channels.
+pub fn mix_matrix(input: &ChannelLayout, output: &ChannelLayout) -> Option<Vec<Vec<f32>>> {
+    let input = input.to_map()?;
+    let output = output.to_map()?;
+    let mut matrix = vec![vec![0.0; input.len()]; output.len()];
+    let mut gains = vec![0.0; output.len()];
+
+    for i in 0..input.len() {
+        gains.iter_mut().for_each(|g| *g = 0.0);
+        route(input.get_channel(i), 1.0, &output, 0, &mut gains);
+        for (row, &g) in matrix.iter_mut().zip(&gains) {
+            row[i] = g;
+        }
+    }
+
+    Some(matrix)
+}
+
+fn check_format(fmt: &Soniton) -> Result<()> {
+    let supported = !fmt.be
+        && !fmt.packed
+        && fmt.signed
+        && ((fmt.float && fmt.bits == 32) || (!fmt.float && fmt.bits == 16));
+    if !supported {
+        return Err(Error::Unsupported(format!("format {}", fmt)));
+    }
+    Ok(())
+}
+
+/// Position of a sample in the frame buffer, as its plane and its index.
+fn position(fmt: &Soniton, channels: usize, ch: usize, sample: usize) -> (usize, usize) {
+    if fmt.planar {
+        (ch, sample)
+    } else {
+        (0, sample * channels + ch)
+    }
+}
+
+fn get_sample(data: &[u8], fmt: &Soniton, idx: usize) -> f32 {
+    if fmt.float {
+        let b = &data[idx * 4..idx * 4 + 4];
+        f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    } else {
+        let b = &data[idx * 2..idx * 2 + 2];
+        f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0
+    }
+}
+
+fn put_sample(data: &mut [u8], fmt: &Soniton, idx: usize, v: f32) {
+    if fmt.float {
+        data[idx * 4..idx * 4 + 4].copy_from_slice(&v.to_le_bytes());
+    } else {
+        let v = (v * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+        data[idx * 2..idx * 2 + 2].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// Channel remixing filter.
+pub struct Remix {
+    layout: ChannelLayout,
+    map: Option<ChannelMap>,
+    /// Input channels and mix matrix of the last frame.
+    matrix: Option<(ChannelMap, Vec<Vec<f32>>)>,
+    out: VecDeque<ArcFrame>,
+}
+
+impl Remix {
+    /// Creates a new remixing filter producing frames of a layout.
+    pub fn new(layout: ChannelLayout) -> Self {
+        Remix {
+            layout,
+            map: None,
+            matrix: None,
+            out: VecDeque::new(),
+        }
+    }
+
+    fn remix(
+        &self,
+        src: &Frame,
+        info: &AudioInfo,
+        map: &ChannelMap,
+        matrix: &[Vec<f32>],
+    ) -> Result<Frame> {
+        let fmt = *info.format;
+        let out_info = AudioInfo::new(
+            info.samples,
+            info.sample_rate,
+            map.clone(),
+            info.format.clone(),
+            info.block_len,
+        );
+        let mut frame = Frame::new_default_frame(out_info, Some(src.t.clone()));
+        frame.metadata = src.metadata.clone();
+
+        let in_channels = info.map.len();
+        let out_channels = map.len();
+        let mut input = vec![0.0; in_channels];
+        for s in 0..info.samples {
+            for (ch, v) in input.iter_mut().enumerate() {
+                let (plane, idx) = position(&fmt, in_channels, ch, s);
+                let data = src
+                    .buf
+                    .as_slice_inner(plane)
+                    .map_err(|_| Error::InvalidData)?;
+                if data.len() < (idx + 1) * usize::from(fmt.bits / 8) {
+                    return Err(Error::InvalidData);
+                }
+                *v = get_sample(data, &fmt, idx);
+            }
+            for (ch, gains) in matrix.iter().enumerate() {
+                let v = gains.iter().zip(&input).map(|(g, v)| g * v).sum();
+                let (plane, idx) = position(&fmt, out_channels, ch, s);
+                let data = frame
+                    .buf
+                    .as_mut_slice_inner(plane)
+                    .map_err(|_| Error::InvalidData)?;
+                put_sample(data, &fmt, idx, v);
+            }
+        }
+
+        Ok(frame)
+    }
+}
+
+impl Filter for Remix {
+    fn configure(&mut self) -> Result<()> {
+        let map = self.layout.to_map().ok_or(Error::ConfigurationInvalid)?;
+        if map.is_empty() {
+            return Err(Error::ConfigurationInvalid);
+        }
+        self.map = Some(map);
+        self.matrix = None;
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("mask", Value::U64(mask)) if mask <= u64::from(u32::MAX) => {
+                self.layout = ChannelLayout::Mask(mask as u32)
+            }
+            ("channels", Value::U64(count)) => {
+                self.layout = ChannelLayout::default_for(count as usize)
+            }
+            ("mask", _) | ("channels", _) => return Err(Error::ConfigurationInvalid),
+            (key, _) => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+        self.map = None;
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let info = match frame.kind {
+            MediaKind::Audio(ref info) => info.clone(),
+            MediaKind::Video(_) => return Err(Error::Unsupported("video frames".to_owned())),
+        };
+        check_format(&info.format)?;
+        if self.map.is_none() {
+            self.configure()?;
+        }
+        let map = self.map.clone().ok_or(Error::ConfigurationInvalid)?;
+
+        if info.map == map {
+            self.out.push_back(frame);
+            return Ok(());
+        }
+        if self.matrix.as_ref().map(|(input, _)| input) != Some(&info.map) {
+            let matrix = mix_matrix(&ChannelLayout::Custom(info.map.clone()), &self.layout)
+                .ok_or(Error::ConfigurationInvalid)?;
+            self.matrix = Some((info.map.clone(), matrix));
+        }
+
+        let matrix = self
+            .matrix
+            .as_ref()
+            .map(|(_, m)| m.as_slice())
+            .unwrap_or(&[]);
+        let out = self.remix(&frame, &info, &map, matrix)?;
+        self.out.push_back(Arc::new(out));
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::audiosample::formats::{F32, S16};
+
+    const H: f32 = FRAC_1_SQRT_2;
+
+    fn frame(layout: &ChannelLayout, fmt: Soniton, samples: &[&[f32]]) -> ArcFrame {
+        let map = layout.to_map().unwrap();
+        let info = AudioInfo::new(samples[0].len(), 48000, map.clone(), Arc::new(fmt), None);
+        let mut f = Frame::new_default_frame(info, None);
+        for (ch, values) in samples.iter().enumerate() {
+            for (s, &v) in values.iter().enumerate() {
+                let (plane, idx) = position(&fmt, map.len(), ch, s);
+                put_sample(f.buf.as_mut_slice_inner(plane).unwrap(), &fmt, idx, v);
+            }
+        }
+        Arc::new(f)
+    }
+
+    fn channel(frame: &Frame, ch: usize) -> Vec<f32> {
+        let info = frame.kind.get_audio_info().unwrap();
+        (0..info.samples)
+            .map(|s| {
+                let (plane, idx) = position(&info.format, info.map.len(), ch, s);
+                get_sample(frame.buf.as_slice_inner(plane).unwrap(), &info.format, idx)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matrix() {
+        let matrix = mix_matrix(&ChannelLayout::SURROUND_5_1, &ChannelLayout::STEREO).unwrap();
+        // L R C LFE Ls Rs.
+        assert_eq!(matrix[0], [1.0, 0.0, H, 0.0, H, 0.0]);
+        assert_eq!(matrix[1], [0.0, 1.0, H, 0.0, 0.0, H]);
+
+        let matrix = mix_matrix(&ChannelLayout::STEREO, &ChannelLayout::MONO).unwrap();
+        assert_eq!(matrix, [[H, H]]);
+        let matrix = mix_matrix(&ChannelLayout::MONO, &ChannelLayout::SURROUND_7_1).unwrap();
+        assert_eq!(matrix[2], [1.0]);
+        assert_eq!(matrix.iter().map(|row| row[0]).sum::<f32>(), 1.0);
+
+        let matrix =
+            mix_matrix(&ChannelLayout::SURROUND_7_1, &ChannelLayout::SURROUND_5_1).unwrap();
+        // Lss into Ls.
+        assert_eq!(matrix[4][6], 1.0);
+        assert!(mix_matrix(&ChannelLayout::Unspecified(2), &ChannelLayout::MONO).is_none());
+    }
+
+    #[test]
+    fn downmix() {
+        let mut remix = Remix::new(ChannelLayout::STEREO);
+        let input: [&[f32]; 6] = [
+            &[0.5, 0.0],
+            &[0.0, -0.5],
+            &[0.25, 0.0],
+            &[1.0, 1.0],
+            &[0.0, 0.0],
+            &[0.0, 0.25],
+        ];
+        for &fmt in &[
+            F32,
+            Soniton {
+                planar: true,
+                ..S16
+            },
+        ] {
+            remix
+                .send_frame(frame(&ChannelLayout::SURROUND_5_1, fmt, &input))
+                .unwrap();
+            let out = remix.receive_frame().unwrap();
+            let info = out.kind.get_audio_info().unwrap();
+            assert_eq!(ChannelLayout::from_map(&info.map), ChannelLayout::STEREO);
+            assert_eq!((info.samples, info.get_format()), (2, fmt));
+
+            let expected = [[0.5 + 0.25 * H, 0.0], [0.25 * H, -0.5 + 0.25 * H]];
+            for (ch, expected) in expected.iter().enumerate() {
+                for (v, e) in channel(&out, ch).iter().zip(expected) {
+                    assert!((v - e).abs() < 1e-4, "{} {}", v, e);
+                }
+            }
+        }
+        assert!(remix.receive_frame().is_err());
+    }
+
+    #[test]
+    fn options() {
+        let mut remix = Remix::new(ChannelLayout::STEREO);
+        let stereo = frame(&ChannelLayout::STEREO, F32, &[&[0.5], &[0.5]]);
+        remix.send_frame(stereo.clone()).unwrap();
+        assert!(Arc::ptr_eq(&remix.receive_frame().unwrap(), &stereo));
+
+        remix.set_option("channels", Value::U64(1)).unwrap();
+        remix.send_frame(stereo).unwrap();
+        let out = remix.receive_frame().unwrap();
+        assert!((channel(&out, 0)[0] - H).abs() < 1e-6);
+
+        assert!(remix.set_option("mask", Value::I64(3)).is_err());
+        remix.set_option("channels", Value::U64(9)).unwrap();
+        assert!(remix.configure().is_err());
+
+        let mut remix = Remix::new(ChannelLayout::MONO);
+        let s32 = Soniton { bits: 32, ..S16 };
+        let frame = frame(&ChannelLayout::STEREO, F32, &[&[0.0], &[0.0]]);
+        let mut info = frame.kind.get_audio_info().unwrap();
+        info.format = Arc::new(s32);
+        let unsupported = Frame::new_default_frame(info, None);
+        assert!(matches!(
+            remix.send_frame(Arc::new(unsupported)),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,510 @@
+//!
+//! Tone mapping.
+//!
+//! Converts BT.2020 PQ or HLG high dynamic range frames to BT.709 standard
+//! dynamic range frames with the same pixel layout.
+//!
+//! The peak luminance of the content is taken from the frame HDR metadata
+//! (content light level first, then mastering display), unless it is set
+//! through the `peak` option.
+//!
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::data::frame::{ArcFrame, VideoInfo};
+use crate::data::hdr::HdrMetadata;
+use crate::data::pixel::{ColorPrimaries, Formaton, MatrixCoefficients, TransferCharacteristic};
+use crate::data::value::Value;
+use crate::filter::colorspace::*;
+use crate::filter::*;
+
+/// Luminance of the SDR reference white in cd/m².
+const REFERENCE_WHITE: f64 = 100.0;
+
+/// Luminance represented by the PQ signal peak in cd/m².
+const PQ_PEAK: f64 = 10000.0;
+
+/// Nominal peak luminance of HLG displays in cd/m².
+const HLG_PEAK: f64 = 1000.0;
+
+/// Linear BT.2020 to BT.709 RGB conversion.
+const BT2020_TO_BT709: [[f64; 3]; 3] = [
+    [1.660_491, -0.587_641, -0.072_850],
+    [-0.124_550, 1.132_900, -0.008_349],
+    [-0.018_151, -0.100_579, 1.118_730],
+];
+
+/// Tone mapping curve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    /// Filmic curve from Uncharted 2, preserving dark details at the
+    /// expense of contrast in bright areas.
+    Hable,
+    /// ITU-R BT.2390 EETF, a knee function applied in the PQ domain
+    /// leaving the luminance below the knee untouched.
+    Bt2390,
+}
+
+mod pq {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+
+    /// Converts a PQ signal into linear light, 1.0 being 10000 cd/m².
+    pub fn eotf(e: f64) -> f64 {
+        let p = e.max(0.0).powf(1.0 / M2);
+        ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+    }
+
+    /// Converts linear light, 1.0 being 10000 cd/m², into a PQ signal.
+    pub fn inverse_eotf(y: f64) -> f64 {
+        let p = y.max(0.0).powf(M1);
+        ((C1 + C2 * p) / (1.0 + C3 * p)).powf(M2)
+    }
+}
+
+mod hlg {
+    const A: f64 = 0.178_832_77;
+    const B: f64 = 0.284_668_92;
+    const C: f64 = 0.559_910_73;
+
+    /// Converts a HLG signal into normalized scene linear light.
+    pub fn inverse_oetf(e: f64) -> f64 {
+        if e <= 0.5 {
+            e * e / 3.0
+        } else {
+            (((e - C) / A).exp() + B) / 12.0
+        }
+    }
+
+    /// Returns the system gamma for a display of the given peak luminance.
+    pub fn gamma(peak: f64) -> f64 {
+        1.2 + 0.42 * (peak / super::HLG_PEAK).log10()
+    }
+}
+
+/// BT.709 opto-electronic transfer function.
+fn bt709_oetf(l: f64) -> f64 {
+    if l < 0.018 {
+        4.5 * l
+    } else {
+        1.099 * l.powf(0.45) - 0.099
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+impl Curve {
+    /// Maps a luminance relative to the reference white of a signal
+    /// peaking at `peak` into the [0, 1] range.
+    pub fn map(self, sig: f64, peak: f64) -> f64 {
+        if peak <= 1.0 {
+            return sig.min(1.0);
+        }
+
+        match self {
+            Curve::Hable => hable(sig) / hable(peak),
+            Curve::Bt2390 => {
+                let scale = REFERENCE_WHITE / PQ_PEAK;
+                let src_max = pq::inverse_eotf(peak * scale);
+                let max_lum = pq::inverse_eotf(scale) / src_max;
+                let ks = 1.5 * max_lum - 0.5;
+                let e1 = pq::inverse_eotf(sig * scale) / src_max;
+
+                let e2 = if e1 < ks {
+                    e1
+                } else {
+                    let t = (e1 - ks) / (1.0 - ks);
+                    let (t2, t3) = (t * t, t * t * t);
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * ks
+                        + (t3 - 2.0 * t2 + t) * (1.0 - ks)
+                        + (-2.0 * t3 + 3.0 * t2) * max_lum
+                };
+
+                (pq::eotf(e2 * src_max) / scale).min(1.0)
+            }
+        }
+    }
+}
+
+/// Per frame conversion parameters.
+#[derive(Clone, Copy)]
+pub(crate) struct Conversion {
+    pub xfer: TransferCharacteristic,
+    pub src: YuvMatrix,
+    pub dst: YuvMatrix,
+    pub primaries: Option<[[f64; 3]; 3]>,
+    pub layout: YuvLayout,
+    pub curve: Curve,
+    /// Content peak relative to the reference white.
+    pub peak: f64,
+    pub hlg_gamma: f64,
+}
+
+impl Conversion {
+    /// Returns the conversion of the frames described by `info` to BT.709
+    /// SDR, `peak` overriding the peak luminance of their metadata.
+    pub fn new(info: &VideoInfo, curve: Curve, peak: Option<f64>) -> Result<Self> {
+        let fmt = &info.format;
+        let layout = YuvLayout::new(fmt)?;
+
+        let xfer = fmt.get_xfer();
+        let default_peak = match xfer {
+            TransferCharacteristic::PerceptualQuantizer => PQ_PEAK,
+            TransferCharacteristic::HybridLogGamma => HLG_PEAK,
+            _ => return Err(Error::Unsupported(format!("transfer {}", xfer))),
+        };
+        let primaries = match fmt.get_primaries() {
+            ColorPrimaries::Unspecified | ColorPrimaries::BT2020 => Some(BT2020_TO_BT709),
+            ColorPrimaries::BT709 => None,
+            p => return Err(Error::Unsupported(format!("primaries {}", p))),
+        };
+        let (kr, kb) = luma_coefficients(fmt.get_matrix(), BT2020_LUMA)
+            .ok_or_else(|| Error::Unsupported(format!("matrix {}", fmt.get_matrix())))?;
+
+        let peak = peak
+            .or_else(|| info.hdr.get_peak_luminance())
+            .unwrap_or(default_peak);
+
+        Ok(Conversion {
+            xfer,
+            src: YuvMatrix::new((kr, kb)),
+            dst: YuvMatrix::new(BT709_LUMA),
+            primaries,
+            layout,
+            curve,
+            peak: peak / REFERENCE_WHITE,
+            hlg_gamma: hlg::gamma(peak),
+        })
+    }
+
+    /// Converts a non-linear R'G'B' triplet into linear light relative to
+    /// the reference white.
+    fn linearize(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let rgb = [
+            rgb[0].clamp(0.0, 1.0),
+            rgb[1].clamp(0.0, 1.0),
+            rgb[2].clamp(0.0, 1.0),
+        ];
+
+        if self.xfer == TransferCharacteristic::PerceptualQuantizer {
+            let scale = PQ_PEAK / REFERENCE_WHITE;
+            [
+                pq::eotf(rgb[0]) * scale,
+                pq::eotf(rgb[1]) * scale,
+                pq::eotf(rgb[2]) * scale,
+            ]
+        } else {
+            let scene = [
+                hlg::inverse_oetf(rgb[0]),
+                hlg::inverse_oetf(rgb[1]),
+                hlg::inverse_oetf(rgb[2]),
+            ];
+            let (kr, kb) = BT2020_LUMA;
+            let ys = kr * scene[0] + (1.0 - kr - kb) * scene[1] + kb * scene[2];
+            let gain = self.peak * ys.max(0.0).powf(self.hlg_gamma - 1.0);
+            [scene[0] * gain, scene[1] * gain, scene[2] * gain]
+        }
+    }
+
+    pub fn convert(&self, y: f64, cb: f64, cr: f64) -> (f64, f64, f64) {
+        let mut rgb = self.linearize(self.src.to_rgb(y, cb, cr));
+
+        let sig = rgb[0].max(rgb[1]).max(rgb[2]);
+        if sig > 0.0 {
+            let gain = self.curve.map(sig, self.peak) / sig;
+            rgb.iter_mut().for_each(|c| *c *= gain);
+        }
+
+        if let Some(m) = self.primaries {
+            rgb = [
+                m[0][0] * rgb[0] + m[0][1] * rgb[1] + m[0][2] * rgb[2],
+                m[1][0] * rgb[0] + m[1][1] * rgb[1] + m[1][2] * rgb[2],
+                m[2][0] * rgb[0] + m[2][1] * rgb[1] + m[2][2] * rgb[2],
+            ];
+        }
+
+        let rgb = [
+            bt709_oetf(rgb[0].clamp(0.0, 1.0)),
+            bt709_oetf(rgb[1].clamp(0.0, 1.0)),
+            bt709_oetf(rgb[2].clamp(0.0, 1.0)),
+        ];
+
+        self.dst.to_yuv(rgb)
+    }
+}
+
+/// HDR to SDR tone mapping filter.
+pub struct Tonemap {
+    curve: Curve,
+    peak: Option<f64>,
+    out: VecDeque<ArcFrame>,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tonemap {
+    /// Creates a new tone mapping filter using the BT.2390 curve.
+    pub fn new() -> Self {
+        Tonemap {
+            curve: Curve::Bt2390,
+            peak: None,
+            out: VecDeque::new(),
+        }
+    }
+}
+
+fn number(val: Value) -> Result<f64> {
+    let v = match val {
+        Value::U64(v) => v as f64,
+        Value::I64(v) => v as f64,
+        Value::Str(s) => s.parse().map_err(|_| Error::ConfigurationInvalid)?,
+        _ => return Err(Error::ConfigurationInvalid),
+    };
+
+    if v.is_finite() && v > 0.0 {
+        Ok(v)
+    } else {
+        Err(Error::ConfigurationInvalid)
+    }
+}
+
+impl Filter for Tonemap {
+    fn configure(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+        match (key, val) {
+            ("curve", Value::Str("hable")) => self.curve = Curve::Hable,
+            ("curve", Value::Str("bt2390")) => self.curve = Curve::Bt2390,
+            ("curve", _) => return Err(Error::ConfigurationInvalid),
+            ("peak", val) => self.peak = Some(number(val)?),
+            _ => return Err(Error::Unsupported(format!("{} key", key))),
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&mut self, frame: ArcFrame) -> Result<()> {
+        let info = video_info(&frame)?;
+        let conv = Conversion::new(info, self.curve, self.peak)?;
+
+        let format = Formaton {
+            primaries: ColorPrimaries::BT709,
+            xfer: TransferCharacteristic::BT1886,
+            matrix: MatrixCoefficients::BT709,
+            ..*info.format
+        };
+        let mut out_info = info.clone();
+        out_info.format = Arc::new(format);
+        out_info.hdr = HdrMetadata::default();
+
+        let mut out = new_frame_like(&frame, out_info);
+        map_yuv(
+            &conv.layout,
+            &conv.layout,
+            &*frame.buf,
+            &mut *out.buf,
+            info,
+            |y, cb, cr| conv.convert(y, cb, cr),
+        )?;
+        for plane in 3..frame.buf.count() {
+            copy_frame_plane(&*frame.buf, &mut *out.buf, info, plane)?;
+        }
+
+        self.out.push_back(Arc::new(out));
+
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<ArcFrame> {
+        self.out.pop_front().ok_or(Error::MoreDataNeeded)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{Frame, FrameType};
+    use crate::data::hdr::ContentLightLevel;
+    use crate::data::pixel::formats::YUV420_10;
+
+    const W: usize = 8;
+    const H: usize = 8;
+
+    fn frame(xfer: TransferCharacteristic, y: u16, hdr: HdrMetadata) -> ArcFrame {
+        let format = Formaton {
+            primaries: ColorPrimaries::BT2020,
+            xfer,
+            matrix: MatrixCoefficients::BT2020NonConstantLuminance,
+            ..*YUV420_10
+        };
+        let mut info = VideoInfo::new(W, H, false, FrameType::I, Arc::new(format));
+        info.hdr = hdr;
+        let mut f = Frame::new_default_frame(info, None);
+
+        for (plane, v) in [(0, y), (1, 512), (2, 512)] {
+            let data = f.buf.as_mut_slice_inner(plane).unwrap();
+            for s in data.chunks_exact_mut(2) {
+                s.copy_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        Arc::new(f)
+    }
+
+    fn sample(frame: &Frame, plane: usize) -> u16 {
+        let data = frame.buf.as_slice_inner(plane).unwrap();
+        u16::from_le_bytes([data[0], data[1]])
+    }
+
+    fn run(filter: &mut Tonemap, frame: ArcFrame) -> ArcFrame {
+        filter.send_frame(frame).unwrap();
+        filter.receive_frame().unwrap()
+    }
+
+    /// Returns the 10-bit limited range PQ code of a luminance.
+    fn pq_code(nits: f64) -> u16 {
+        (64.0 + pq::inverse_eotf(nits / PQ_PEAK) * 876.0).round() as u16
+    }
+
+    #[test]
+    fn transfer_functions() {
+        for &l in &[0.0, 0.001, 0.01, 0.1, 0.5, 1.0] {
+            assert!((pq::eotf(pq::inverse_eotf(l)) - l).abs() < 1e-9);
+        }
+        assert!((hlg::inverse_oetf(0.5) - 1.0 / 12.0).abs() < 1e-9);
+        assert!((hlg::inverse_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((hlg::gamma(HLG_PEAK) - 1.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn curves() {
+        for &curve in &[Curve::Hable, Curve::Bt2390] {
+            let mut last = 0.0;
+            for i in 1..=100 {
+                let v = curve.map(f64::from(i) * 0.1, 10.0);
+                assert!(v > last);
+                last = v;
+            }
+            assert!((curve.map(10.0, 10.0) - 1.0).abs() < 1e-6);
+            assert_eq!(curve.map(0.5, 1.0), 0.5);
+        }
+        assert!((Curve::Bt2390.map(0.1, 10.0) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pq_to_sdr() {
+        let mut filter = Tonemap::new();
+
+        let out = run(
+            &mut filter,
+            frame(
+                TransferCharacteristic::PerceptualQuantizer,
+                64,
+                HdrMetadata::default(),
+            ),
+        );
+        assert_eq!((sample(&out, 0), sample(&out, 1)), (64, 512));
+
+        let fmt = video_info(&out).unwrap().format.clone();
+        assert_eq!(fmt.get_primaries(), ColorPrimaries::BT709);
+        assert_eq!(fmt.get_xfer(), TransferCharacteristic::BT1886);
+        assert_eq!(fmt.get_matrix(), MatrixCoefficients::BT709);
+
+        // Reference white of a frame not exceeding it is preserved.
+        filter.set_option("peak", Value::U64(100)).unwrap();
+        let out = run(
+            &mut filter,
+            frame(
+                TransferCharacteristic::PerceptualQuantizer,
+                pq_code(100.0),
+                HdrMetadata::default(),
+            ),
+        );
+        assert!((i32::from(sample(&out, 0)) - 940).abs() <= 1);
+        assert!((i32::from(sample(&out, 1)) - 512).abs() <= 1);
+        assert!((i32::from(sample(&out, 2)) - 512).abs() <= 1);
+
+        // The content peak is mapped to the SDR peak.
+        let mut filter = Tonemap::new();
+        filter.set_option("curve", Value::Str("hable")).unwrap();
+        let hdr = HdrMetadata {
+            content_light: Some(ContentLightLevel {
+                max_cll: 1000,
+                max_fall: 400,
+            }),
+            ..Default::default()
+        };
+        let out = run(
+            &mut filter,
+            frame(
+                TransferCharacteristic::PerceptualQuantizer,
+                pq_code(1000.0),
+                hdr,
+            ),
+        );
+        assert!((i32::from(sample(&out, 0)) - 940).abs() <= 1);
+        assert!(video_info(&out).unwrap().hdr.is_empty());
+    }
+
+    #[test]
+    fn hlg_to_sdr() {
+        let mut filter = Tonemap::new();
+        let out = run(
+            &mut filter,
+            frame(
+                TransferCharacteristic::HybridLogGamma,
+                940,
+                HdrMetadata::default(),
+            ),
+        );
+        assert!((i32::from(sample(&out, 0)) - 940).abs() <= 1);
+
+        let out = run(
+            &mut filter,
+            frame(
+                TransferCharacteristic::HybridLogGamma,
+                500,
+                HdrMetadata::default(),
+            ),
+        );
+        let y = sample(&out, 0);
+        assert!(y > 64 && y < 940);
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut filter = Tonemap::new();
+        assert!(filter
+            .send_frame(frame(
+                TransferCharacteristic::BT1886,
+                64,
+                HdrMetadata::default()
+            ))
+            .is_err());
+        assert!(filter.set_option("curve", Value::Str("none")).is_err());
+        assert!(filter.set_option("peak", Value::I64(0)).is_err());
+    }
+}
@@ -5,6 +5,7 @@
 
 // local crates
 extern crate av_bitstream;
+extern crate av_codec;
 extern crate av_data;
 extern crate av_format;
 
@@ -20,13 +21,21 @@ pub mod bitstream {
     pub use av_bitstream::*;
 }
 
+pub mod codec {
+    pub use av_codec::*;
+}
+
 pub use av_data::rational;
 
 // core functionalities
 mod entropy;
 mod io;
 
+// usage examples
+pub mod examples;
+pub mod vectors;
+
 // raw multimedia data manipulation
-mod filter;
+pub mod filter;
 mod resample;
-mod scale;
+pub mod scale;
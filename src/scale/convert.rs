@@ -0,0 +1,160 @@
+//!
+//! Pixel format conversion.
+//!
+//! Planar Y'CbCr frames are converted to another depth, range, chroma
+//! subsampling or matrix, PQ and HLG frames being tone mapped when the
+//! target format has a standard dynamic range transfer.
+//!
+
+use std::sync::Arc;
+
+use crate::data::frame::{FrameBuffer, VideoInfo};
+use crate::data::pixel::{ColorPrimaries, Formaton, MatrixCoefficients, TransferCharacteristic};
+use crate::filter;
+use crate::filter::colorspace::{luma_coefficients, map_yuv, YuvLayout, YuvMatrix, BT709_LUMA};
+use crate::filter::tonemap::{self, Curve};
+use crate::scale::{Error, Result};
+
+/// Per pixel color transform.
+#[derive(Clone, Copy)]
+pub(crate) enum Transform {
+    /// The values are only quantized again.
+    Identity,
+    /// The Y'CbCr matrix changes.
+    Matrix(YuvMatrix, YuvMatrix),
+    /// HDR to SDR tone mapping.
+    Tonemap(tonemap::Conversion),
+}
+
+fn is_hdr(xfer: TransferCharacteristic) -> bool {
+    matches!(
+        xfer,
+        TransferCharacteristic::PerceptualQuantizer | TransferCharacteristic::HybridLogGamma
+    )
+}
+
+fn filter_error(err: filter::Error) -> Error {
+    match err {
+        filter::Error::Unsupported(what) => Error::Unsupported(what),
+        _ => Error::InvalidData,
+    }
+}
+
+/// Conversion between two planar Y'CbCr formats.
+pub struct Conversion {
+    pub(crate) src: YuvLayout,
+    pub(crate) dst: YuvLayout,
+    pub(crate) transform: Transform,
+    mid: Arc<Formaton>,
+}
+
+impl Conversion {
+    /// Returns the conversion of the frames described by `info` to the
+    /// format `dst`, tone mapping them with `curve` if needed.
+    pub fn new(info: &VideoInfo, dst: &Formaton, curve: Curve) -> Result<Self> {
+        let src = &info.format;
+        let unsupported = |what: String| Err(Error::Unsupported(what));
+
+        let src_layout = YuvLayout::new(src).map_err(filter_error)?;
+        let dst_layout = YuvLayout::new(dst).map_err(filter_error)?;
+        if src.get_num_comp() != dst.get_num_comp()
+            || src
+                .iter()
+                .skip(3)
+                .zip(dst.iter().skip(3))
+                .any(|(a, b)| a.map(|c| c.get_depth()) != b.map(|c| c.get_depth()))
+        {
+            return unsupported(format!("conversion from {} to {}", src, dst));
+        }
+
+        let transform = if is_hdr(src.get_xfer()) && !is_hdr(dst.get_xfer()) {
+            match dst.get_primaries() {
+                ColorPrimaries::Unspecified | ColorPrimaries::BT709 => {}
+                p => return unsupported(format!("tone mapping to primaries {}", p)),
+            }
+            match dst.get_matrix() {
+                MatrixCoefficients::Unspecified | MatrixCoefficients::BT709 => {}
+                m => return unsupported(format!("tone mapping to matrix {}", m)),
+            }
+            Transform::Tonemap(tonemap::Conversion::new(info, curve, None).map_err(filter_error)?)
+        } else {
+            let (sp, dp) = (src.get_primaries(), dst.get_primaries());
+            if sp != dp && sp != ColorPrimaries::Unspecified && dp != ColorPrimaries::Unspecified {
+                return unsupported(format!("primaries {}", dp));
+            }
+            if !is_hdr(src.get_xfer()) && is_hdr(dst.get_xfer()) {
+                return unsupported(format!("transfer {}", dst.get_xfer()));
+            }
+
+            let (sm, dm) = (src.get_matrix(), dst.get_matrix());
+            if sm == dm
+                || sm == MatrixCoefficients::Unspecified
+                || dm == MatrixCoefficients::Unspecified
+            {
+                Transform::Identity
+            } else {
+                let matrix = |m| {
+                    luma_coefficients(m, BT709_LUMA)
+                        .map(YuvMatrix::new)
+                        .ok_or_else(|| Error::Unsupported(format!("matrix {}", m)))
+                };
+                Transform::Matrix(matrix(sm)?, matrix(dm)?)
+            }
+        };
+
+        // The frames are scaled to the chroma subsampling of the target
+        // before the conversion of their samples.
+        let mut mid = **src;
+        for (comp, target) in mid.comp_info.iter_mut().zip(dst.iter()) {
+            if let (Some(c), Some(t)) = (comp.as_mut(), target) {
+                c.h_ss = t.h_ss;
+                c.v_ss = t.v_ss;
+            }
+        }
+
+        Ok(Conversion {
+            src: src_layout,
+            dst: dst_layout,
+            transform,
+            mid: Arc::new(mid),
+        })
+    }
+
+    /// Returns the format of the source frames once scaled, with the
+    /// chroma subsampling of the target.
+    pub fn get_scaled_format(&self) -> &Arc<Formaton> {
+        &self.mid
+    }
+
+    /// Tells if the samples are kept as they are, only the plane geometry
+    /// changing.
+    pub fn is_passthrough(&self) -> bool {
+        matches!(self.transform, Transform::Identity) && self.src == self.dst
+    }
+
+    /// Converts a pixel of normalized values.
+    pub(crate) fn apply(&self, y: f64, cb: f64, cr: f64) -> (f64, f64, f64) {
+        match self.transform {
+            Transform::Identity => (y, cb, cr),
+            Transform::Matrix(src, dst) => dst.to_yuv(src.to_rgb(y, cb, cr)),
+            Transform::Tonemap(ref conv) => conv.convert(y, cb, cr),
+        }
+    }
+
+    /// Converts the color planes of an image in the scaled format.
+    ///
+    /// `info` describes the geometry of both images.
+    pub fn map(
+        &self,
+        src: &dyn FrameBuffer,
+        dst: &mut dyn FrameBuffer,
+        info: &VideoInfo,
+    ) -> Result<()> {
+        let mut info = info.clone();
+        info.format = self.mid.clone();
+        map_yuv(&self.src, &self.dst, src, dst, &info, |y, cb, cr| {
+            self.apply(y, cb, cr)
+        })
+        .map_err(filter_error)
+    }
+}
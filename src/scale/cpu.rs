@@ -0,0 +1,209 @@
+//!
+//! CPU scaling backend.
+//!
+
+use crate::data::frame::{Frame, MediaKind};
+use crate::scale::{Backend, Conversion, Error, PlaneInfo, Result};
+
+/// Fractional bits of the interpolation weights.
+const FRAC_BITS: u32 = 8;
+const FRAC_ONE: u64 = 1 << FRAC_BITS;
+
+/// Returns, for each output position, the first input position to
+/// interpolate from and the weight of the following one.
+fn positions(src: usize, dst: usize) -> Vec<(usize, u64)> {
+    let last = src - 1;
+    let ratio = src as f64 / dst as f64;
+
+    (0..dst)
+        .map(|i| {
+            let pos = ((i as f64 + 0.5) * ratio - 0.5).clamp(0.0, last as f64);
+            let base = pos.floor();
+            let frac = ((pos - base) * FRAC_ONE as f64).round() as u64;
+            let base = base as usize;
+
+            if frac == FRAC_ONE {
+                ((base + 1).min(last), 0)
+            } else {
+                (base, frac)
+            }
+        })
+        .collect()
+}
+
+#[inline(always)]
+fn get(line: &[u8], x: usize, info: &PlaneInfo) -> u64 {
+    if info.sample_size == 1 {
+        u64::from(line[x])
+    } else {
+        let b = [line[x * 2], line[x * 2 + 1]];
+        u64::from(if info.be {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+}
+
+#[inline(always)]
+fn put(line: &mut [u8], x: usize, info: &PlaneInfo, v: u64) {
+    if info.sample_size == 1 {
+        line[x] = v as u8;
+    } else {
+        let b = if info.be {
+            (v as u16).to_be_bytes()
+        } else {
+            (v as u16).to_le_bytes()
+        };
+        line[x * 2..x * 2 + 2].copy_from_slice(&b);
+    }
+}
+
+/// Scales a plane with bilinear interpolation.
+pub(crate) fn scale_plane(
+    src: &[u8],
+    src_linesize: usize,
+    sp: &PlaneInfo,
+    dst: &mut [u8],
+    dst_linesize: usize,
+    dp: &PlaneInfo,
+) {
+    if sp.width == 0 || sp.height == 0 || dp.width == 0 || dp.height == 0 {
+        return;
+    }
+
+    let xs = positions(sp.width, dp.width);
+    let ys = positions(sp.height, dp.height);
+    let round = FRAC_ONE * FRAC_ONE / 2;
+
+    for (line, &(y0, fy)) in dst.chunks_mut(dst_linesize).zip(ys.iter()) {
+        let y1 = (y0 + 1).min(sp.height - 1);
+        let top = &src[y0 * src_linesize..];
+        let bottom = &src[y1 * src_linesize..];
+
+        for (x, &(x0, fx)) in xs.iter().enumerate() {
+            let x1 = (x0 + 1).min(sp.width - 1);
+            let t = get(top, x0, sp) * (FRAC_ONE - fx) + get(top, x1, sp) * fx;
+            let b = get(bottom, x0, sp) * (FRAC_ONE - fx) + get(bottom, x1, sp) * fx;
+            let v = (t * (FRAC_ONE - fy) + b * fy + round) >> (2 * FRAC_BITS);
+            put(line, x, dp, v);
+        }
+    }
+}
+
+/// Scales the planes of `src` into `dst`, starting from plane `first`.
+fn scale_planes(
+    src: &Frame,
+    src_planes: &[PlaneInfo],
+    dst: &mut Frame,
+    dst_planes: &[PlaneInfo],
+    first: usize,
+) -> Result<()> {
+    for (plane, (sp, dp)) in src_planes.iter().zip(dst_planes).enumerate().skip(first) {
+        let src_linesize = src.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let dst_linesize = dst.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+        let src_data = src
+            .buf
+            .as_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+        let dst_data = dst
+            .buf
+            .as_mut_slice_inner(plane)
+            .map_err(|_| Error::InvalidData)?;
+
+        scale_plane(src_data, src_linesize, sp, dst_data, dst_linesize, dp);
+    }
+
+    Ok(())
+}
+
+/// Scaling backend running on the CPU.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuBackend;
+
+impl Backend for CpuBackend {
+    fn scale(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+    ) -> Result<()> {
+        scale_planes(src, src_planes, dst, dst_planes, 0)
+    }
+
+    fn convert(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+        conv: &Conversion,
+    ) -> Result<()> {
+        let mut info = match dst.kind {
+            MediaKind::Video(ref info) => info.clone(),
+            MediaKind::Audio(_) => return Err(Error::InvalidData),
+        };
+        info.format = conv.get_scaled_format().clone();
+        let mid_planes = PlaneInfo::from_video_info(&info)?;
+        let mut mid = Frame::new_default_frame(info.clone(), None);
+        scale_planes(src, src_planes, &mut mid, &mid_planes, 0)?;
+
+        conv.map(&*mid.buf, &mut *dst.buf, &info)?;
+        // The planes past the color ones have the same format in both.
+        scale_planes(&mid, &mid_planes, dst, dst_planes, 3)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plane(width: usize, height: usize) -> PlaneInfo {
+        PlaneInfo {
+            width,
+            height,
+            sample_size: 1,
+            be: false,
+        }
+    }
+
+    #[test]
+    fn upscale() {
+        let src = [0u8, 100, 200, 100];
+        let mut dst = [0u8; 16];
+
+        scale_plane(&src, 2, &plane(2, 2), &mut dst, 4, &plane(4, 4));
+
+        assert_eq!(&dst[..4], &[0, 25, 75, 100]);
+        assert_eq!(&dst[12..], &[200, 175, 125, 100]);
+    }
+
+    #[test]
+    fn downscale() {
+        let src: Vec<u8> = (0..16).map(|i| i * 10).collect();
+        let mut dst = [0u8; 4];
+
+        scale_plane(&src, 4, &plane(4, 4), &mut dst, 2, &plane(2, 2));
+
+        assert_eq!(dst, [25, 45, 105, 125]);
+    }
+
+    #[test]
+    fn wide_samples() {
+        let info = PlaneInfo {
+            width: 2,
+            height: 1,
+            sample_size: 2,
+            be: false,
+        };
+        let dst_info = PlaneInfo { width: 3, ..info };
+        let src = [0u8, 0, 0xe8, 0x03];
+        let mut dst = [0u8; 6];
+
+        scale_plane(&src, 4, &info, &mut dst, 6, &dst_info);
+
+        assert_eq!(u16::from_le_bytes([dst[2], dst[3]]), 500);
+        assert_eq!(u16::from_le_bytes([dst[4], dst[5]]), 1000);
+    }
+}
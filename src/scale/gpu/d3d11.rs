@@ -0,0 +1,227 @@
+//!
+//! Direct3D 11 devices and shared textures.
+//!
+//! Decoders share their NV12 and P010 textures through NT handles, opened
+//! as Vulkan images by the GPU backend on Windows.
+//!
+
+use std::any::Any;
+use std::fmt;
+use std::os::raw::c_void;
+
+use crate::data::frame::{FrameBuffer, FrameError, VideoInfo};
+use crate::data::hwdevice::*;
+use crate::scale::{Error, Result};
+
+use super::{GpuBackend, GpuFrame, SurfaceFormat};
+
+/// A Direct3D 11 device.
+pub struct D3d11Context {
+    device: *mut c_void,
+}
+
+// Direct3D 11 devices are free threaded.
+unsafe impl Send for D3d11Context {}
+unsafe impl Sync for D3d11Context {}
+
+impl D3d11Context {
+    /// Wraps an `ID3D11Device` pointer.
+    ///
+    /// # Safety
+    ///
+    /// The device must outlive the context.
+    pub unsafe fn from_raw(device: *mut c_void) -> Self {
+        D3d11Context { device }
+    }
+
+    /// Returns the `ID3D11Device` pointer.
+    pub fn get_handle(&self) -> *mut c_void {
+        self.device
+    }
+}
+
+impl fmt::Debug for D3d11Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("D3d11Context")
+            .field("device", &self.device)
+            .finish()
+    }
+}
+
+impl HwDeviceContext for D3d11Context {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::D3d11va
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A frame in a shared Direct3D 11 texture.
+///
+/// The texture is owned by the producer of the frame, which has to keep it
+/// and its handle alive while the frame is in use.
+#[derive(Clone, Debug)]
+pub struct D3d11Frame {
+    device: HwDevice,
+    handle: *mut c_void,
+    format: SurfaceFormat,
+    width: usize,
+    height: usize,
+}
+
+// NT handles can be used from any thread.
+unsafe impl Send for D3d11Frame {}
+unsafe impl Sync for D3d11Frame {}
+
+impl D3d11Frame {
+    /// Wraps the NT handle of a texture of `device` created with
+    /// `D3D11_RESOURCE_MISC_SHARED_NTHANDLE`, `width` and `height` being
+    /// the size of the texture.
+    ///
+    /// # Safety
+    ///
+    /// The handle must refer to a texture of the given layout and size,
+    /// holding a frame of the size of the `VideoInfo` of the frame using
+    /// it, and outlive it.
+    pub unsafe fn new(
+        device: HwDevice,
+        handle: *mut c_void,
+        format: SurfaceFormat,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        D3d11Frame {
+            device,
+            handle,
+            format,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the shared handle of the texture.
+    pub fn get_handle(&self) -> *mut c_void {
+        self.handle
+    }
+
+    /// Returns the layout of the texture.
+    pub fn get_format(&self) -> SurfaceFormat {
+        self.format
+    }
+
+    /// Returns the size of the texture in pixels.
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+impl HwFrame for D3d11Frame {
+    fn get_device(&self) -> &HwDevice {
+        &self.device
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The planes of a `D3d11Frame` are not accessible from the CPU.
+impl FrameBuffer for D3d11Frame {
+    fn linesize(&self, _: usize) -> ::std::result::Result<usize, FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn count(&self) -> usize {
+        2
+    }
+    fn as_slice_inner(&self, _: usize) -> ::std::result::Result<&[u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_mut_slice_inner(&mut self, _: usize) -> ::std::result::Result<&mut [u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+        Some(self)
+    }
+}
+
+impl GpuBackend {
+    /// Opens the shared texture of a Direct3D 11 frame as a Vulkan image
+    /// and copies its planes to a `GpuFrame`.
+    #[cfg(windows)]
+    pub(super) fn import_d3d11(
+        &self,
+        frame: &D3d11Frame,
+        info: &VideoInfo,
+        depth: u8,
+    ) -> Result<GpuFrame> {
+        use super::SurfaceLayout;
+        use wgpu::hal;
+        use windows::Win32::Foundation::HANDLE;
+
+        if info.width > frame.width || info.height > frame.height {
+            return Err(Error::InvalidData);
+        }
+        let layout = SurfaceLayout::new(info.width, info.height, frame.format);
+        let format = match frame.format {
+            SurfaceFormat::Nv12 => wgpu::TextureFormat::NV12,
+            SurfaceFormat::P010 => wgpu::TextureFormat::P010,
+        };
+        let size = wgpu::Extent3d {
+            width: frame.width as u32,
+            height: frame.height as u32,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = {
+            let hal_device = unsafe { self.device.as_hal::<hal::api::Vulkan>() }
+                .ok_or_else(|| Error::Unsupported("D3D11 frames without Vulkan".to_owned()))?;
+            let desc = hal::TextureDescriptor {
+                label: Some("d3d11"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUses::COPY_SRC,
+                memory_flags: hal::MemoryFlags::empty(),
+                view_formats: Vec::new(),
+            };
+            let raw =
+                unsafe { hal_device.texture_from_d3d11_shared_handle(HANDLE(frame.handle), &desc) }
+                    .map_err(super::backend_error)?;
+
+            unsafe {
+                self.device.create_texture_from_hal::<hal::api::Vulkan>(
+                    raw,
+                    &wgpu::TextureDescriptor {
+                        label: Some("d3d11"),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format,
+                        usage: wgpu::TextureUsages::COPY_SRC,
+                        view_formats: &[],
+                    },
+                    wgpu::TextureUses::UNINITIALIZED,
+                )
+            }
+        };
+
+        self.import_textures(
+            [
+                (&texture, wgpu::TextureAspect::Plane0),
+                (&texture, wgpu::TextureAspect::Plane1),
+            ],
+            &layout,
+            depth,
+        )
+    }
+
+    #[cfg(not(windows))]
+    pub(super) fn import_d3d11(&self, _: &D3d11Frame, _: &VideoInfo, _: u8) -> Result<GpuFrame> {
+        Err(Error::Unsupported(
+            "D3D11 frames outside of Windows".to_owned(),
+        ))
+    }
+}
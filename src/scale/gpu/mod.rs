@@ -0,0 +1,1659 @@
+//!
+//! GPU scaling backend.
+//!
+//! Planes are uploaded to storage buffers holding one 32-bit value per
+//! sample, scaled by a compute shader, converted to another pixel format
+//! by a second one if needed, and then either read back into a frame or
+//! kept on the GPU as a `GpuFrame` for further processing.
+//!
+//! A `GpuFrame` is a hardware frame of the wgpu device: frames carrying
+//! one are handed to the filters and imported back without copies by the
+//! backends sharing the device, and packed into the `EncoderSurface`
+//! layout expected by hardware encoders on the GPU as well.
+//!
+//! The frames of hardware decoders are imported on the GPU through the
+//! Vulkan backend of wgpu: VAAPI surfaces as DMA-BUF objects on Linux,
+//! shared Direct3D 11 textures on Windows.
+//!
+
+mod d3d11;
+#[cfg(target_os = "linux")]
+mod vaapi;
+
+pub use self::d3d11::*;
+#[cfg(target_os = "linux")]
+pub use self::vaapi::*;
+
+use std::any::Any;
+use std::sync::mpsc;
+
+use wgpu::util::DeviceExt;
+
+use crate::data::frame::{Frame, FrameBuffer, FrameError, MediaKind, VideoInfo};
+use crate::data::hwdevice::{
+    DeviceSelector, HwDevice, HwDeviceBackend, HwDeviceContext, HwDeviceError, HwDeviceType,
+    HwFrame,
+};
+use crate::data::pixel::TransferCharacteristic;
+use crate::data::timeinfo::TimeInfo;
+use crate::filter::tonemap::Curve;
+use crate::scale::convert::Transform;
+use crate::scale::{Backend, Conversion, Error, PlaneInfo, Result};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+const SHADER: &str = r#"
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn sample(x: u32, y: u32) -> f32 {
+    return f32(src[y * params.src_width + x]);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_width || id.y >= params.dst_height) {
+        return;
+    }
+
+    let ratio = vec2<f32>(
+        f32(params.src_width) / f32(params.dst_width),
+        f32(params.src_height) / f32(params.dst_height),
+    );
+    let last = vec2<f32>(f32(params.src_width - 1u), f32(params.src_height - 1u));
+    let pos = clamp((vec2<f32>(id.xy) + 0.5) * ratio - 0.5, vec2<f32>(0.0), last);
+    let p0 = vec2<u32>(floor(pos));
+    let p1 = min(p0 + 1u, vec2<u32>(last));
+    let f = pos - floor(pos);
+
+    let top = mix(sample(p0.x, p0.y), sample(p1.x, p0.y), f.x);
+    let bottom = mix(sample(p0.x, p1.y), sample(p1.x, p1.y), f.x);
+    dst[id.y * params.dst_width + id.x] = u32(round(mix(top, bottom, f.y)));
+}
+"#;
+
+/// Packs 4:2:0 planes into a luma plane followed by interleaved chroma,
+/// each invocation writing one 32-bit word of a line.
+const PACK_SHADER: &str = r#"
+struct Params {
+    width: u32,
+    height: u32,
+    chroma_width: u32,
+    chroma_height: u32,
+    pitch: u32,
+    wide: u32,
+    shift: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> luma: array<u32>;
+@group(0) @binding(2) var<storage, read> cb: array<u32>;
+@group(0) @binding(3) var<storage, read> cr: array<u32>;
+@group(0) @binding(4) var<storage, read_write> dst: array<u32>;
+
+fn luma_at(x: u32, y: u32) -> u32 {
+    if (x >= params.width) {
+        return 0u;
+    }
+    return luma[y * params.width + x] << params.shift;
+}
+
+fn chroma_at(x: u32, y: u32, red: bool) -> u32 {
+    if (x >= params.chroma_width) {
+        return 0u;
+    }
+    let idx = y * params.chroma_width + x;
+    if (red) {
+        return cr[idx] << params.shift;
+    }
+    return cb[idx] << params.shift;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.pitch || id.y >= params.height + params.chroma_height) {
+        return;
+    }
+
+    var word = 0u;
+    if (params.wide != 0u) {
+        var a = 0u;
+        var b = 0u;
+        if (id.y < params.height) {
+            a = luma_at(id.x * 2u, id.y);
+            b = luma_at(id.x * 2u + 1u, id.y);
+        } else {
+            a = chroma_at(id.x, id.y - params.height, false);
+            b = chroma_at(id.x, id.y - params.height, true);
+        }
+        word = (a & 0xffffu) | ((b & 0xffffu) << 16u);
+    } else {
+        for (var i = 0u; i < 4u; i++) {
+            var v = 0u;
+            if (id.y < params.height) {
+                v = luma_at(id.x * 4u + i, id.y);
+            } else {
+                v = chroma_at(id.x * 2u + i / 2u, id.y - params.height, i % 2u == 1u);
+            }
+            word |= (v & 0xffu) << (8u * i);
+        }
+    }
+    dst[id.y * params.pitch + id.x] = word;
+}
+"#;
+
+/// Unpacks 4:2:0 planes laid out as `PACK_SHADER` writes them, each
+/// invocation reading one sample of the luma plane or one pair of chroma
+/// samples.
+const UNPACK_SHADER: &str = r#"
+struct Params {
+    width: u32,
+    height: u32,
+    chroma_width: u32,
+    chroma_height: u32,
+    pitch: u32,
+    chroma_offset: u32,
+    wide: u32,
+    shift: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> luma: array<u32>;
+@group(0) @binding(3) var<storage, read_write> cb: array<u32>;
+@group(0) @binding(4) var<storage, read_write> cr: array<u32>;
+
+fn sample_at(offset: u32) -> u32 {
+    let v = src[offset / 4u] >> (8u * (offset % 4u));
+    if (params.wide != 0u) {
+        return (v & 0xffffu) >> params.shift;
+    }
+    return v & 0xffu;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = 1u + params.wide;
+    if (id.y < params.height) {
+        if (id.x < params.width) {
+            luma[id.y * params.width + id.x] = sample_at(id.y * params.pitch + id.x * size);
+        }
+        return;
+    }
+
+    let y = id.y - params.height;
+    if (y < params.chroma_height && id.x < params.chroma_width) {
+        let offset = params.chroma_offset + y * params.pitch + id.x * 2u * size;
+        let idx = y * params.chroma_width + id.x;
+        cb[idx] = sample_at(offset);
+        cr[idx] = sample_at(offset + size);
+    }
+}
+"#;
+
+/// Converts the samples of Y'CbCr planes sharing the chroma subsampling of
+/// the target, each invocation handling the pixels of a chroma sample as
+/// `filter::colorspace::map_yuv` does.
+const CONVERT_SHADER: &str = r#"
+struct Params {
+    // Linear conversion of the primaries, by rows.
+    m0: vec4<f32>,
+    m1: vec4<f32>,
+    m2: vec4<f32>,
+    // Luma offset and range, chroma offset and range of the input.
+    src_q: vec4<f32>,
+    // Luma offset and range, chroma offset and range of the output.
+    dst_q: vec4<f32>,
+    // Red and blue luma coefficients of the input and output matrices.
+    coeffs: vec4<f32>,
+    // Content peak relative to the reference white, HLG system gamma and
+    // maximum output sample value.
+    tone: vec4<f32>,
+    width: u32,
+    height: u32,
+    chroma_width: u32,
+    chroma_height: u32,
+    block_width: u32,
+    block_height: u32,
+    // 0: requantization, 1: matrix, 2: PQ tone mapping, 3: HLG tone mapping.
+    mode: u32,
+    // 0: Hable, 1: BT.2390.
+    curve: u32,
+    primaries: u32,
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src_y: array<u32>;
+@group(0) @binding(2) var<storage, read> src_cb: array<u32>;
+@group(0) @binding(3) var<storage, read> src_cr: array<u32>;
+@group(0) @binding(4) var<storage, read_write> dst_y: array<u32>;
+@group(0) @binding(5) var<storage, read_write> dst_cb: array<u32>;
+@group(0) @binding(6) var<storage, read_write> dst_cr: array<u32>;
+
+const REFERENCE_WHITE: f32 = 100.0;
+const PQ_PEAK: f32 = 10000.0;
+
+const PQ_M1: f32 = 0.1593017578125;
+const PQ_M2: f32 = 78.84375;
+const PQ_C1: f32 = 0.8359375;
+const PQ_C2: f32 = 18.8515625;
+const PQ_C3: f32 = 18.6875;
+
+fn pq_eotf(e: f32) -> f32 {
+    let p = pow(max(e, 0.0), 1.0 / PQ_M2);
+    return pow(max(p - PQ_C1, 0.0) / (PQ_C2 - PQ_C3 * p), 1.0 / PQ_M1);
+}
+
+fn pq_inverse_eotf(y: f32) -> f32 {
+    let p = pow(max(y, 0.0), PQ_M1);
+    return pow((PQ_C1 + PQ_C2 * p) / (1.0 + PQ_C3 * p), PQ_M2);
+}
+
+fn hlg_inverse_oetf(e: f32) -> f32 {
+    if (e <= 0.5) {
+        return e * e / 3.0;
+    }
+    return (exp((e - 0.55991073) / 0.17883277) + 0.28466892) / 12.0;
+}
+
+fn bt709_oetf(l: f32) -> f32 {
+    if (l < 0.018) {
+        return 4.5 * l;
+    }
+    return 1.099 * pow(l, 0.45) - 0.099;
+}
+
+fn hable(x: f32) -> f32 {
+    return (x * (0.15 * x + 0.05) + 0.004) / (x * (0.15 * x + 0.5) + 0.06) - 0.02 / 0.3;
+}
+
+fn curve_map(sig: f32, peak: f32) -> f32 {
+    if (peak <= 1.0) {
+        return min(sig, 1.0);
+    }
+    if (params.curve == 0u) {
+        return hable(sig) / hable(peak);
+    }
+
+    let scale = REFERENCE_WHITE / PQ_PEAK;
+    let src_max = pq_inverse_eotf(peak * scale);
+    let max_lum = pq_inverse_eotf(scale) / src_max;
+    let ks = 1.5 * max_lum - 0.5;
+    let e1 = pq_inverse_eotf(sig * scale) / src_max;
+    var e2 = e1;
+    if (e1 >= ks) {
+        let t = (e1 - ks) / (1.0 - ks);
+        let t2 = t * t;
+        let t3 = t2 * t;
+        e2 = (2.0 * t3 - 3.0 * t2 + 1.0) * ks
+            + (t3 - 2.0 * t2 + t) * (1.0 - ks)
+            + (-2.0 * t3 + 3.0 * t2) * max_lum;
+    }
+    return min(pq_eotf(e2 * src_max) / scale, 1.0);
+}
+
+fn to_rgb(v: vec3<f32>, kr: f32, kb: f32) -> vec3<f32> {
+    let r = v.x + 2.0 * (1.0 - kr) * v.z;
+    let b = v.x + 2.0 * (1.0 - kb) * v.y;
+    let g = (v.x - kr * r - kb * b) / (1.0 - kr - kb);
+    return vec3<f32>(r, g, b);
+}
+
+fn to_yuv(rgb: vec3<f32>, kr: f32, kb: f32) -> vec3<f32> {
+    let y = dot(rgb, vec3<f32>(kr, 1.0 - kr - kb, kb));
+    return vec3<f32>(y, (rgb.z - y) / (2.0 * (1.0 - kb)), (rgb.x - y) / (2.0 * (1.0 - kr)));
+}
+
+fn linearize(rgb: vec3<f32>) -> vec3<f32> {
+    let c = clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (params.mode == 2u) {
+        return vec3<f32>(pq_eotf(c.x), pq_eotf(c.y), pq_eotf(c.z)) * (PQ_PEAK / REFERENCE_WHITE);
+    }
+    let scene = vec3<f32>(hlg_inverse_oetf(c.x), hlg_inverse_oetf(c.y), hlg_inverse_oetf(c.z));
+    let ys = dot(scene, vec3<f32>(0.2627, 0.678, 0.0593));
+    return scene * params.tone.x * pow(max(ys, 0.0), params.tone.y - 1.0);
+}
+
+fn convert(v: vec3<f32>) -> vec3<f32> {
+    if (params.mode == 0u) {
+        return v;
+    }
+    var rgb = to_rgb(v, params.coeffs.x, params.coeffs.y);
+    if (params.mode == 1u) {
+        return to_yuv(rgb, params.coeffs.z, params.coeffs.w);
+    }
+
+    rgb = linearize(rgb);
+    let sig = max(rgb.x, max(rgb.y, rgb.z));
+    if (sig > 0.0) {
+        rgb = rgb * (curve_map(sig, params.tone.x) / sig);
+    }
+    if (params.primaries != 0u) {
+        rgb = vec3<f32>(dot(params.m0.xyz, rgb), dot(params.m1.xyz, rgb), dot(params.m2.xyz, rgb));
+    }
+    let c = clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    return to_yuv(
+        vec3<f32>(bt709_oetf(c.x), bt709_oetf(c.y), bt709_oetf(c.z)),
+        params.coeffs.z,
+        params.coeffs.w,
+    );
+}
+
+fn quantize(v: f32, offset: f32, range: f32) -> u32 {
+    return u32(clamp(round(v * range + offset), 0.0, params.tone.z));
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.chroma_width || id.y >= params.chroma_height) {
+        return;
+    }
+
+    let idx = id.y * params.chroma_width + id.x;
+    let cb = (f32(src_cb[idx]) - params.src_q.z) / params.src_q.w;
+    let cr = (f32(src_cr[idx]) - params.src_q.z) / params.src_q.w;
+    var sum = vec2<f32>(0.0);
+    var n = 0.0;
+
+    let y_end = min((id.y + 1u) * params.block_height, params.height);
+    let x_end = min((id.x + 1u) * params.block_width, params.width);
+    for (var y = id.y * params.block_height; y < y_end; y++) {
+        for (var x = id.x * params.block_width; x < x_end; x++) {
+            let pos = y * params.width + x;
+            let luma = (f32(src_y[pos]) - params.src_q.x) / params.src_q.y;
+            let out = convert(vec3<f32>(luma, cb, cr));
+            dst_y[pos] = quantize(out.x, params.dst_q.x, params.dst_q.y);
+            sum += out.yz;
+            n += 1.0;
+        }
+    }
+
+    if (n > 0.0) {
+        dst_cb[idx] = quantize(sum.x / n, params.dst_q.z, params.dst_q.w);
+        dst_cr[idx] = quantize(sum.y / n, params.dst_q.z, params.dst_q.w);
+    }
+}
+"#;
+
+/// A plane stored on the GPU.
+#[derive(Clone, Debug)]
+pub struct GpuPlane {
+    /// Storage buffer holding one `u32` per sample, line after line.
+    pub buffer: wgpu::Buffer,
+    /// Plane geometry.
+    pub info: PlaneInfo,
+}
+
+/// A frame stored on the GPU.
+///
+/// Cloning a `GpuFrame` shares its buffers.
+#[derive(Clone, Debug)]
+pub struct GpuFrame {
+    /// Frame planes.
+    pub planes: Vec<GpuPlane>,
+    device: HwDevice,
+}
+
+impl GpuFrame {
+    /// Wraps the frame into a `Frame` described by `info`, to hand it to
+    /// the filters and encoders sharing the device.
+    pub fn into_frame(self, info: VideoInfo, t: TimeInfo) -> Frame {
+        let mut frame = Frame::new_default_frame(info, Some(t));
+        frame.buf = Box::new(self);
+        frame
+    }
+}
+
+impl HwFrame for GpuFrame {
+    fn get_device(&self) -> &HwDevice {
+        &self.device
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The planes of a `GpuFrame` are not accessible from the CPU, they are
+/// read back through `GpuBackend::download`.
+impl FrameBuffer for GpuFrame {
+    fn linesize(&self, _: usize) -> ::std::result::Result<usize, FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn count(&self) -> usize {
+        self.planes.len()
+    }
+    fn as_slice_inner(&self, _: usize) -> ::std::result::Result<&[u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_mut_slice_inner(&mut self, _: usize) -> ::std::result::Result<&mut [u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+        Some(self)
+    }
+}
+
+/// Layouts of the frames handed to hardware encoders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    /// 8-bit 4:2:0, a luma plane followed by a plane of interleaved Cb and
+    /// Cr samples.
+    Nv12,
+    /// 4:2:0 laid out as `Nv12` with 16-bit little-endian samples, the
+    /// significant bits in the high bits.
+    P010,
+}
+
+impl SurfaceFormat {
+    fn get_sample_size(self) -> usize {
+        match self {
+            SurfaceFormat::Nv12 => 1,
+            SurfaceFormat::P010 => 2,
+        }
+    }
+}
+
+/// Placement of a 4:2:0 image in a buffer, the chroma plane following
+/// the luma plane with the same pitch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceLayout {
+    format: SurfaceFormat,
+    width: usize,
+    height: usize,
+    pitch: usize,
+}
+
+impl SurfaceLayout {
+    /// Returns the layout of an image of the given size, its lines being
+    /// aligned as buffer to texture copies require.
+    pub fn new(width: usize, height: usize, format: SurfaceFormat) -> Self {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+        let pitch = (width.div_ceil(2) * 2 * format.get_sample_size()).div_ceil(align) * align;
+
+        SurfaceLayout {
+            format,
+            width,
+            height,
+            pitch,
+        }
+    }
+
+    /// Returns the sample layout.
+    pub fn get_format(&self) -> SurfaceFormat {
+        self.format
+    }
+
+    /// Returns the size of the image in pixels.
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the size of the chroma planes in samples.
+    pub fn get_chroma_size(&self) -> (usize, usize) {
+        (self.width.div_ceil(2), self.height.div_ceil(2))
+    }
+
+    /// Returns the size of a line in bytes.
+    pub fn get_pitch(&self) -> usize {
+        self.pitch
+    }
+
+    /// Returns the offset of the chroma plane in bytes.
+    pub fn get_chroma_offset(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    /// Returns the size of the image in bytes.
+    pub fn get_len(&self) -> usize {
+        self.get_chroma_offset() + self.pitch * self.get_chroma_size().1
+    }
+}
+
+/// A frame in the layout of a hardware encoder input, on the GPU.
+#[derive(Debug)]
+pub struct EncoderSurface {
+    buffer: wgpu::Buffer,
+    layout: SurfaceLayout,
+}
+
+impl EncoderSurface {
+    /// Returns the buffer holding the surface, for the encoders sharing
+    /// the device.
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Returns the placement of the image in the buffer.
+    pub fn get_layout(&self) -> &SurfaceLayout {
+        &self.layout
+    }
+
+    /// Returns the layout of the surface.
+    pub fn get_format(&self) -> SurfaceFormat {
+        self.layout.get_format()
+    }
+
+    /// Returns the size of the image in pixels.
+    pub fn get_size(&self) -> (usize, usize) {
+        self.layout.get_size()
+    }
+
+    /// Returns the size of a line in bytes.
+    pub fn get_pitch(&self) -> usize {
+        self.layout.get_pitch()
+    }
+
+    /// Returns the offset of the chroma plane in bytes.
+    pub fn get_chroma_offset(&self) -> usize {
+        self.layout.get_chroma_offset()
+    }
+}
+
+/// wgpu device context.
+#[derive(Clone, Debug)]
+pub struct WgpuContext {
+    /// Logical device.
+    pub device: wgpu::Device,
+    /// Queue of the device.
+    pub queue: wgpu::Queue,
+}
+
+impl HwDeviceContext for WgpuContext {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Wgpu
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Features used to import the frames of hardware decoders, enabled on
+/// the devices supporting them.
+const INTEROP_FEATURES: wgpu::Features = wgpu::Features::VULKAN_EXTERNAL_MEMORY_DMA_BUF
+    .union(wgpu::Features::VULKAN_EXTERNAL_MEMORY_WIN32)
+    .union(wgpu::Features::TEXTURE_FORMAT_NV12)
+    .union(wgpu::Features::TEXTURE_FORMAT_P010);
+
+/// Opens wgpu devices, on the default adapter, by adapter index or on the
+/// Vulkan adapter of a DRM render node.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WgpuDeviceBackend;
+
+impl HwDeviceBackend for WgpuDeviceBackend {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Wgpu
+    }
+
+    fn create(
+        &self,
+        selector: &DeviceSelector,
+    ) -> ::std::result::Result<Box<dyn HwDeviceContext>, HwDeviceError> {
+        let creation = |err: &dyn std::fmt::Display| HwDeviceError::Creation(err.to_string());
+
+        pollster::block_on(async {
+            let instance = wgpu::Instance::new(
+                wgpu::InstanceDescriptor::new_without_display_handle_from_env(),
+            );
+            let adapter = match *selector {
+                DeviceSelector::Default => instance
+                    .request_adapter(&wgpu::RequestAdapterOptions::default())
+                    .await
+                    .map_err(|e| creation(&e))?,
+                DeviceSelector::AdapterIndex(idx) => instance
+                    .enumerate_adapters(wgpu::Backends::all())
+                    .await
+                    .into_iter()
+                    .nth(idx as usize)
+                    .ok_or_else(|| creation(&format!("no adapter {}", idx)))?,
+                #[cfg(target_os = "linux")]
+                DeviceSelector::DrmNode(ref node) => {
+                    let (vendor, device) = vaapi::pci_ids(node).map_err(|e| creation(&e))?;
+                    instance
+                        .enumerate_adapters(wgpu::Backends::VULKAN)
+                        .await
+                        .into_iter()
+                        .find(|adapter| {
+                            let info = adapter.get_info();
+                            (info.vendor, info.device) == (vendor, device)
+                        })
+                        .ok_or_else(|| creation(&format!("no adapter for {}", node.display())))?
+                }
+                #[cfg(not(target_os = "linux"))]
+                DeviceSelector::DrmNode(_) => {
+                    return Err(HwDeviceError::Unsupported("DRM node selection".to_owned()))
+                }
+            };
+            let (device, queue) = adapter
+                .request_device(&wgpu::DeviceDescriptor {
+                    required_features: adapter.features() & INTEROP_FEATURES,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| creation(&e))?;
+
+            Ok(Box::new(WgpuContext { device, queue }) as Box<dyn HwDeviceContext>)
+        })
+    }
+}
+
+/// Scaling backend running compute shaders through wgpu.
+pub struct GpuBackend {
+    hw_device: HwDevice,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    convert_pipeline: wgpu::ComputePipeline,
+    pack_pipeline: wgpu::ComputePipeline,
+    unpack_pipeline: wgpu::ComputePipeline,
+}
+
+fn backend_error<E: std::fmt::Display>(err: E) -> Error {
+    Error::Backend(err.to_string())
+}
+
+fn buffer_size(info: &PlaneInfo) -> u64 {
+    (info.width * info.height * 4) as u64
+}
+
+/// Returns whether the samples of a surface take 16 bits and the shift
+/// of their significant bits.
+fn sample_shift(format: SurfaceFormat, depth: u8) -> Result<(u32, u32)> {
+    match (format, depth) {
+        (SurfaceFormat::Nv12, 8) => Ok((0, 0)),
+        (SurfaceFormat::P010, 9..=16) => Ok((1, 16 - u32::from(depth))),
+        _ => Err(Error::Unsupported(format!("{} bits samples", depth))),
+    }
+}
+
+impl GpuBackend {
+    /// Creates a new backend on the default adapter.
+    pub fn new() -> Result<Self> {
+        let device =
+            HwDevice::create(&WgpuDeviceBackend, DeviceSelector::Default).map_err(backend_error)?;
+        Self::from_hw_device(&device)
+    }
+
+    /// Creates a new backend on a shared hardware device, deriving a wgpu
+    /// context from it if needed.
+    pub fn from_hw_device(device: &HwDevice) -> Result<Self> {
+        let hw_device = device.derive(HwDeviceType::Wgpu).map_err(backend_error)?;
+        let ctx = hw_device
+            .downcast_ref::<WgpuContext>()
+            .ok_or_else(|| Error::Backend("not a wgpu context".to_owned()))?;
+        let (device, queue) = (ctx.device.clone(), ctx.queue.clone());
+
+        Ok(Self::with_hw_device(hw_device, device, queue))
+    }
+
+    /// Creates a new backend sharing a device with other GPU users.
+    pub fn from_device(device: wgpu::Device, queue: wgpu::Queue) -> Self {
+        let hw_device = HwDevice::from_context(Box::new(WgpuContext {
+            device: device.clone(),
+            queue: queue.clone(),
+        }));
+
+        Self::with_hw_device(hw_device, device, queue)
+    }
+
+    fn with_hw_device(hw_device: HwDevice, device: wgpu::Device, queue: wgpu::Queue) -> Self {
+        let pipeline = |label, source: &str| {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &module,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let (pipeline, convert_pipeline, pack_pipeline, unpack_pipeline) = (
+            pipeline("scale", SHADER),
+            pipeline("convert", CONVERT_SHADER),
+            pipeline("pack", PACK_SHADER),
+            pipeline("unpack", UNPACK_SHADER),
+        );
+
+        GpuBackend {
+            hw_device,
+            device,
+            queue,
+            pipeline,
+            convert_pipeline,
+            pack_pipeline,
+            unpack_pipeline,
+        }
+    }
+
+    /// Returns the hardware device used by the backend.
+    pub fn get_hw_device(&self) -> &HwDevice {
+        &self.hw_device
+    }
+
+    /// Returns the device used by the backend.
+    pub fn get_device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    /// Returns the queue used by the backend.
+    pub fn get_queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Uploads the planes of a frame.
+    pub fn upload(&self, frame: &Frame, planes: &[PlaneInfo]) -> Result<GpuFrame> {
+        let mut out = Vec::with_capacity(planes.len());
+
+        for (plane, info) in planes.iter().enumerate() {
+            let linesize = frame.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+            let data = frame
+                .buf
+                .as_slice_inner(plane)
+                .map_err(|_| Error::InvalidData)?;
+
+            let mut contents = Vec::with_capacity(buffer_size(info) as usize);
+            for line in data.chunks(linesize).take(info.height) {
+                for x in 0..info.width {
+                    let v = if info.sample_size == 1 {
+                        u32::from(line[x])
+                    } else {
+                        let b = [line[x * 2], line[x * 2 + 1]];
+                        u32::from(if info.be {
+                            u16::from_be_bytes(b)
+                        } else {
+                            u16::from_le_bytes(b)
+                        })
+                    };
+                    contents.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+
+            let buffer = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("plane"),
+                    contents: &contents,
+                    usage: wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST,
+                });
+            out.push(GpuPlane {
+                buffer,
+                info: *info,
+            });
+        }
+
+        Ok(self.gpu_frame(out))
+    }
+
+    fn gpu_frame(&self, planes: Vec<GpuPlane>) -> GpuFrame {
+        GpuFrame {
+            planes,
+            device: self.hw_device.clone(),
+        }
+    }
+
+    /// Tells if a hardware frame is stored on the device of the backend.
+    fn is_local(&self, frame: &dyn HwFrame) -> bool {
+        frame.get_device().ptr_eq(&self.hw_device)
+            || frame
+                .get_device()
+                .downcast_ref::<WgpuContext>()
+                .is_some_and(|ctx| ctx.device == self.device)
+    }
+
+    /// Returns the planes of a frame on the GPU.
+    ///
+    /// The frames already stored on the device of the backend are shared
+    /// without copies, the frames in system memory are uploaded.
+    ///
+    /// `VaapiFrame`s and `D3d11Frame`s are opened as Vulkan images and
+    /// copied to the planes of a `GpuFrame` on the GPU, which requires the
+    /// Vulkan backend and the external memory features of the device.
+    pub fn import(&self, frame: &Frame) -> Result<GpuFrame> {
+        let info = match frame.kind {
+            MediaKind::Video(ref info) => info,
+            MediaKind::Audio(_) => return Err(Error::Unsupported("audio frames".to_owned())),
+        };
+
+        if let Some(hw) = frame.buf.as_hw_frame() {
+            let depth = info.format.get_chromaton(0).map_or(8, |c| c.get_depth());
+            let any = hw.as_any();
+
+            if let Some(gpu) = any.downcast_ref::<GpuFrame>() {
+                if self.is_local(hw) {
+                    return Ok(gpu.clone());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(va) = any.downcast_ref::<VaapiFrame>() {
+                return self.import_vaapi(va, info, depth);
+            }
+            if let Some(d3d) = any.downcast_ref::<D3d11Frame>() {
+                return self.import_d3d11(d3d, info, depth);
+            }
+            return Err(Error::Unsupported(format!(
+                "{} frames of another device",
+                hw.get_device().get_type()
+            )));
+        }
+
+        self.upload(frame, &PlaneInfo::from_video_info(info)?)
+    }
+
+    /// Unpacks a 4:2:0 frame of `depth` bits per sample from an encoder
+    /// surface, the counterpart of `export`.
+    pub fn import_surface(&self, surface: &EncoderSurface, depth: u8) -> Result<GpuFrame> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("unpack"),
+            });
+        let planes = self.unpack(&mut encoder, &surface.buffer, &surface.layout, depth)?;
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(self.gpu_frame(planes))
+    }
+
+    /// Copies the luma and chroma planes of a 4:2:0 frame from textures
+    /// and unpacks them.
+    #[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+    fn import_textures(
+        &self,
+        textures: [(&wgpu::Texture, wgpu::TextureAspect); 2],
+        layout: &SurfaceLayout,
+        depth: u8,
+    ) -> Result<GpuFrame> {
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("surface"),
+            size: layout.get_len() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("import"),
+            });
+
+        let sizes = [layout.get_size(), layout.get_chroma_size()];
+        let offsets = [0, layout.get_chroma_offset()];
+        for (((texture, aspect), (width, height)), offset) in
+            textures.iter().zip(sizes).zip(offsets)
+        {
+            encoder.copy_texture_to_buffer(
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: *aspect,
+                },
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset: offset as u64,
+                        bytes_per_row: Some(layout.get_pitch() as u32),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: width as u32,
+                    height: height as u32,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let planes = self.unpack(&mut encoder, &buffer, layout, depth)?;
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(self.gpu_frame(planes))
+    }
+
+    /// Records the unpacking of a 4:2:0 frame laid out in a buffer.
+    fn unpack(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &wgpu::Buffer,
+        layout: &SurfaceLayout,
+        depth: u8,
+    ) -> Result<Vec<GpuPlane>> {
+        let (wide, shift) = sample_shift(layout.get_format(), depth)?;
+        let (width, height) = layout.get_size();
+        let chroma = layout.get_chroma_size();
+        let params: Vec<u8> = [
+            width,
+            height,
+            chroma.0,
+            chroma.1,
+            layout.get_pitch(),
+            layout.get_chroma_offset(),
+            wide as usize,
+            shift as usize,
+        ]
+        .iter()
+        .flat_map(|&v| (v as u32).to_le_bytes())
+        .collect();
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: &params,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let sample_size = layout.get_format().get_sample_size();
+        let planes: Vec<_> = [(width, height), chroma, chroma]
+            .iter()
+            .map(|&(width, height)| {
+                let info = PlaneInfo {
+                    width,
+                    height,
+                    sample_size,
+                    be: false,
+                };
+                let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("plane"),
+                    size: buffer_size(&info).max(4),
+                    usage: wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_SRC
+                        | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                GpuPlane { buffer, info }
+            })
+            .collect();
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: src.as_entire_binding(),
+            },
+        ];
+        for (binding, plane) in (2..).zip(&planes) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: plane.buffer.as_entire_binding(),
+            });
+        }
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("unpack"),
+            layout: &self.unpack_pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("unpack"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.unpack_pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                (width as u32).div_ceil(WORKGROUP_SIZE),
+                ((height + chroma.1) as u32).div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        Ok(planes)
+    }
+
+    /// Scales a frame stored on the GPU, the result staying on the GPU.
+    pub fn scale_frame(&self, src: &GpuFrame, dst_planes: &[PlaneInfo]) -> Result<GpuFrame> {
+        if src.planes.len() != dst_planes.len() {
+            return Err(Error::InvalidData);
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("scale"),
+            });
+        let layout = self.pipeline.get_bind_group_layout(0);
+        let mut planes = Vec::with_capacity(dst_planes.len());
+
+        for (sp, dp) in src.planes.iter().zip(dst_planes) {
+            let params: Vec<u8> = [sp.info.width, sp.info.height, dp.width, dp.height]
+                .iter()
+                .flat_map(|&v| (v as u32).to_le_bytes())
+                .collect();
+            let params = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("params"),
+                    contents: &params,
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("plane"),
+                size: buffer_size(dp).max(4),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("scale"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: sp.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("scale"),
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(
+                    (dp.width as u32).div_ceil(WORKGROUP_SIZE),
+                    (dp.height as u32).div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+
+            planes.push(GpuPlane { buffer, info: *dp });
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(self.gpu_frame(planes))
+    }
+
+    /// Converts the samples of a frame stored on the GPU, the result
+    /// staying on the GPU.
+    ///
+    /// The frame has the plane geometry of the target format of `conv`,
+    /// as produced by `scale_frame`.
+    pub fn convert_frame(&self, src: &GpuFrame, conv: &Conversion) -> Result<GpuFrame> {
+        if src.planes.len() < 3 {
+            return Err(Error::InvalidData);
+        }
+        let (luma, chroma) = (&src.planes[0].info, &src.planes[1].info);
+        if chroma.width == 0 || chroma.height == 0 || src.planes[2].info != *chroma {
+            return Err(Error::InvalidData);
+        }
+
+        let mut matrix = [[0.0; 3]; 3];
+        let (mode, curve, primaries, coeffs, tone) = match conv.transform {
+            Transform::Identity => (0, 0, 0, [0.0; 4], [0.0; 2]),
+            Transform::Matrix(src, dst) => (1, 0, 0, [src.kr, src.kb, dst.kr, dst.kb], [0.0; 2]),
+            Transform::Tonemap(ref tm) => {
+                let mode = if tm.xfer == TransferCharacteristic::PerceptualQuantizer {
+                    2
+                } else {
+                    3
+                };
+                let curve = match tm.curve {
+                    Curve::Hable => 0,
+                    Curve::Bt2390 => 1,
+                };
+                if let Some(m) = tm.primaries {
+                    matrix = m;
+                }
+                (
+                    mode,
+                    curve,
+                    tm.primaries.is_some() as u32,
+                    [tm.src.kr, tm.src.kb, tm.dst.kr, tm.dst.kb],
+                    [tm.peak, tm.hlg_gamma],
+                )
+            }
+        };
+        let (sq, dq) = (&conv.src.quant, &conv.dst.quant);
+
+        let mut floats = Vec::with_capacity(28);
+        for row in &matrix {
+            floats.extend_from_slice(&[row[0], row[1], row[2], 0.0]);
+        }
+        floats.extend_from_slice(&[
+            sq.luma_offset,
+            sq.luma_range,
+            sq.chroma_offset,
+            sq.chroma_range,
+            dq.luma_offset,
+            dq.luma_range,
+            dq.chroma_offset,
+            dq.chroma_range,
+        ]);
+        floats.extend_from_slice(&coeffs);
+        floats.extend_from_slice(&[tone[0], tone[1], dq.max, 0.0]);
+        let ints = [
+            luma.width,
+            luma.height,
+            chroma.width,
+            chroma.height,
+            luma.width.div_ceil(chroma.width),
+            luma.height.div_ceil(chroma.height),
+            mode,
+            curve,
+            primaries as usize,
+            0,
+            0,
+            0,
+        ];
+        let params: Vec<u8> = floats
+            .iter()
+            .flat_map(|&v| (v as f32).to_le_bytes())
+            .chain(ints.iter().flat_map(|&v| (v as u32).to_le_bytes()))
+            .collect();
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: &params,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let mut planes = src.planes.clone();
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: params.as_entire_binding(),
+        }];
+        for (binding, plane) in (1..).zip(&src.planes[..3]) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: plane.buffer.as_entire_binding(),
+            });
+        }
+        for plane in planes.iter_mut().take(3) {
+            plane.buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("plane"),
+                size: buffer_size(&plane.info).max(4),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+        for (binding, plane) in (4..).zip(&planes[..3]) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: plane.buffer.as_entire_binding(),
+            });
+        }
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("convert"),
+            layout: &self.convert_pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("convert"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("convert"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.convert_pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                (chroma.width as u32).div_ceil(WORKGROUP_SIZE),
+                (chroma.height as u32).div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(self.gpu_frame(planes))
+    }
+
+    /// Creates a surface to hand frames of the given size to an encoder.
+    pub fn create_surface(
+        &self,
+        width: usize,
+        height: usize,
+        format: SurfaceFormat,
+    ) -> Result<EncoderSurface> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidData);
+        }
+        let layout = SurfaceLayout::new(width, height, format);
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("surface"),
+            size: layout.get_len() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Ok(EncoderSurface { buffer, layout })
+    }
+
+    /// Packs a 4:2:0 frame of `depth` bits per sample into an encoder
+    /// surface of the same size, on the GPU.
+    pub fn export(&self, src: &GpuFrame, depth: u8, surface: &EncoderSurface) -> Result<()> {
+        let layout = surface.get_layout();
+        let (width, height) = layout.get_size();
+        let chroma = layout.get_chroma_size();
+        let geometry: Vec<_> = src
+            .planes
+            .iter()
+            .map(|plane| (plane.info.width, plane.info.height))
+            .collect();
+        if geometry != [(width, height), chroma, chroma] {
+            return Err(Error::InvalidData);
+        }
+        let (wide, shift) = sample_shift(layout.get_format(), depth)?;
+        if !self.is_local(src) {
+            return Err(Error::Unsupported("frames of another device".to_owned()));
+        }
+
+        let words = layout.get_pitch() / 4;
+        let params: Vec<u8> = [
+            width as u32,
+            height as u32,
+            chroma.0 as u32,
+            chroma.1 as u32,
+            words as u32,
+            wide,
+            shift,
+            0,
+        ]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: &params,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: params.as_entire_binding(),
+        }];
+        for (binding, plane) in (1..).zip(&src.planes) {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: plane.buffer.as_entire_binding(),
+            });
+        }
+        entries.push(wgpu::BindGroupEntry {
+            binding: 4,
+            resource: surface.buffer.as_entire_binding(),
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pack"),
+            layout: &self.pack_pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("pack"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("pack"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pack_pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                (words as u32).div_ceil(WORKGROUP_SIZE),
+                ((height + chroma.1) as u32).div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        self.queue.submit(Some(encoder.finish()));
+
+        Ok(())
+    }
+
+    /// Reads back the content of buffers.
+    fn read_buffers(&self, buffers: &[(&wgpu::Buffer, u64)]) -> Result<Vec<Vec<u8>>> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("download"),
+            });
+        let staging: Vec<_> = buffers
+            .iter()
+            .map(|&(src, size)| {
+                let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("staging"),
+                    size: size.max(4),
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                encoder.copy_buffer_to_buffer(src, 0, &buffer, 0, size);
+                buffer
+            })
+            .collect();
+        self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        for (idx, buffer) in staging.iter().enumerate() {
+            let sender = sender.clone();
+            buffer.map_async(wgpu::MapMode::Read, .., move |res| {
+                let _ = sender.send((idx, res));
+            });
+        }
+        drop(sender);
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(backend_error)?;
+        for (_, res) in receiver.iter() {
+            res.map_err(backend_error)?;
+        }
+
+        staging
+            .iter()
+            .zip(buffers)
+            .map(|(buffer, &(_, size))| {
+                let data =
+                    buffer.get_mapped_range(..).map_err(backend_error)?[..size as usize].to_vec();
+                buffer.unmap();
+                Ok(data)
+            })
+            .collect()
+    }
+
+    /// Reads back the content of an encoder surface, for the encoders not
+    /// sharing the device.
+    pub fn read_surface(&self, surface: &EncoderSurface) -> Result<Vec<u8>> {
+        let len = surface.get_layout().get_len() as u64;
+        let mut data = self.read_buffers(&[(&surface.buffer, len)])?;
+        Ok(data.remove(0))
+    }
+
+    /// Reads back a frame stored on the GPU.
+    pub fn download(&self, src: &GpuFrame, dst: &mut Frame) -> Result<()> {
+        let buffers: Vec<_> = src
+            .planes
+            .iter()
+            .map(|plane| (&plane.buffer, buffer_size(&plane.info)))
+            .collect();
+        let contents = self.read_buffers(&buffers)?;
+
+        for (plane, (gp, content)) in src.planes.iter().zip(&contents).enumerate() {
+            let info = &gp.info;
+            let linesize = dst.buf.linesize(plane).map_err(|_| Error::InvalidData)?;
+            let data = dst
+                .buf
+                .as_mut_slice_inner(plane)
+                .map_err(|_| Error::InvalidData)?;
+            let mut samples = content
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+            for line in data.chunks_mut(linesize).take(info.height) {
+                for x in 0..info.width {
+                    let v = samples.next().ok_or(Error::InvalidData)?;
+                    if info.sample_size == 1 {
+                        line[x] = v as u8;
+                    } else {
+                        let b = if info.be {
+                            (v as u16).to_be_bytes()
+                        } else {
+                            (v as u16).to_le_bytes()
+                        };
+                        line[x * 2..x * 2 + 2].copy_from_slice(&b);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend for GpuBackend {
+    fn scale(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+    ) -> Result<()> {
+        let uploaded = self.upload(src, src_planes)?;
+        let scaled = self.scale_frame(&uploaded, dst_planes)?;
+        self.download(&scaled, dst)
+    }
+
+    fn convert(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+        conv: &Conversion,
+    ) -> Result<()> {
+        let uploaded = self.upload(src, src_planes)?;
+        let scaled = self.scale_frame(&uploaded, dst_planes)?;
+        let converted = self.convert_frame(&scaled, conv)?;
+        self.download(&converted, dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::{FrameType, VideoInfo};
+    use crate::data::pixel::formats::{YUV420, YUV420_10};
+    use crate::scale::Scaler;
+    use std::sync::Arc;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn matches_cpu() {
+        let backend = GpuBackend::new().unwrap();
+
+        let info = VideoInfo::new(16, 16, false, FrameType::I, Arc::new(*YUV420));
+        let mut frame = Frame::new_default_frame(info, None);
+        for plane in 0..3 {
+            let data = frame.buf.as_mut_slice_inner(plane).unwrap();
+            for (i, s) in data.iter_mut().enumerate() {
+                *s = (i * 7 % 256) as u8;
+            }
+        }
+
+        let mut gpu = Scaler::with_backend(24, 10, Box::new(backend));
+        let mut cpu = Scaler::new(24, 10);
+        let a = gpu.scale(&frame).unwrap();
+        let b = cpu.scale(&frame).unwrap();
+
+        let planes = PlaneInfo::from_video_info(&a.kind.get_video_info().unwrap()).unwrap();
+        for (plane, info) in planes.iter().enumerate() {
+            let linesize = a.buf.linesize(plane).unwrap();
+            let a = a.buf.as_slice_inner(plane).unwrap();
+            let b = b.buf.as_slice_inner(plane).unwrap();
+            for (la, lb) in a.chunks(linesize).zip(b.chunks(linesize)).take(info.height) {
+                assert!(la[..info.width]
+                    .iter()
+                    .zip(&lb[..info.width])
+                    .all(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() <= 1));
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn convert_matches_cpu() {
+        use crate::data::pixel::formats::YUV444;
+        use crate::data::pixel::*;
+        use crate::scale::test::{first, solid};
+
+        let backend = GpuBackend::new().unwrap();
+
+        let pq = Formaton {
+            primaries: ColorPrimaries::BT2020,
+            xfer: TransferCharacteristic::PerceptualQuantizer,
+            matrix: MatrixCoefficients::BT2020NonConstantLuminance,
+            ..*YUV420_10
+        };
+        let sdr = Formaton {
+            primaries: ColorPrimaries::BT709,
+            xfer: TransferCharacteristic::BT1886,
+            matrix: MatrixCoefficients::BT709,
+            ..*YUV444
+        };
+
+        let mut gpu = Scaler::with_backend(6, 6, Box::new(backend));
+        let mut cpu = Scaler::new(6, 6);
+        for scaler in [&mut gpu, &mut cpu] {
+            scaler.set_format(Arc::new(sdr));
+        }
+        for &y in &[64, 400, 700, 940] {
+            let src = solid(pq, 8, 8, [y, 480, 560]);
+            let a = first(&gpu.scale(&src).unwrap());
+            let b = first(&cpu.scale(&src).unwrap());
+            assert!(a
+                .iter()
+                .zip(&b)
+                .all(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() <= 1));
+        }
+    }
+
+    #[test]
+    fn shaders() {
+        use wgpu::naga;
+
+        for source in [SHADER, CONVERT_SHADER, PACK_SHADER, UNPACK_SHADER] {
+            let module = naga::front::wgsl::parse_str(source).unwrap();
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::empty(),
+            )
+            .validate(&module)
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn surface_layout() {
+        let nv12 = SurfaceLayout::new(6, 2, SurfaceFormat::Nv12);
+        assert_eq!(nv12.get_pitch(), 256);
+        assert_eq!(nv12.get_chroma_size(), (3, 1));
+        assert_eq!(nv12.get_chroma_offset(), 512);
+        assert_eq!(nv12.get_len(), 768);
+
+        // Odd sizes round the chroma planes up.
+        let nv12 = SurfaceLayout::new(1279, 719, SurfaceFormat::Nv12);
+        assert_eq!(nv12.get_pitch(), 1280);
+        assert_eq!(nv12.get_chroma_size(), (640, 360));
+        assert_eq!(nv12.get_chroma_offset(), 1280 * 719);
+        assert_eq!(nv12.get_len(), 1280 * (719 + 360));
+
+        let p010 = SurfaceLayout::new(1920, 1080, SurfaceFormat::P010);
+        assert_eq!(p010.get_pitch(), 3840);
+        assert_eq!(p010.get_chroma_offset(), 3840 * 1080);
+        assert_eq!(p010.get_len(), 3840 * 1620);
+
+        let p010 = SurfaceLayout::new(7, 3, SurfaceFormat::P010);
+        assert_eq!(p010.get_pitch(), 256);
+        assert_eq!(p010.get_chroma_size(), (4, 2));
+        assert_eq!(p010.get_len(), 256 * 5);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn encoder_surface() {
+        let backend = GpuBackend::new().unwrap();
+
+        let info = VideoInfo::new(6, 2, false, FrameType::I, Arc::new(*YUV420));
+        let mut frame = Frame::new_default_frame(info.clone(), None);
+        for plane in 0..3 {
+            let linesize = frame.buf.linesize(plane).unwrap();
+            let data = frame.buf.as_mut_slice_inner(plane).unwrap();
+            for (y, line) in data.chunks_mut(linesize).enumerate() {
+                for (x, s) in line.iter_mut().enumerate() {
+                    *s = (plane * 100 + y * 10 + x) as u8;
+                }
+            }
+        }
+
+        let gpu = backend.import(&frame).unwrap();
+        let frame = gpu.into_frame(info.clone(), TimeInfo::default());
+        // Frames of the device are shared, not uploaded again.
+        let shared = backend.import(&frame).unwrap();
+        let hw = frame.buf.as_hw_frame().unwrap();
+        assert!(hw.get_device().ptr_eq(backend.get_hw_device()));
+        assert!(frame.buf.as_slice_inner(0).is_err());
+
+        let surface = backend.create_surface(6, 2, SurfaceFormat::Nv12).unwrap();
+        backend.export(&shared, 8, &surface).unwrap();
+        let data = backend.read_surface(&surface).unwrap();
+        let pitch = surface.get_pitch();
+        assert_eq!(pitch % 256, 0);
+        assert_eq!(&data[..6], &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(&data[pitch..pitch + 6], &[10, 11, 12, 13, 14, 15]);
+        let chroma = &data[surface.get_chroma_offset()..];
+        assert_eq!(&chroma[..6], &[100, 200, 101, 201, 102, 202]);
+
+        // Unpacking the surface gives the planes back.
+        let unpacked = backend.import_surface(&surface, 8).unwrap();
+        let mut out = Frame::new_default_frame(info.clone(), None);
+        backend.download(&unpacked, &mut out).unwrap();
+        assert_eq!(
+            &out.buf.as_slice_inner(0).unwrap()[..6],
+            &[0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(&out.buf.as_slice_inner(2).unwrap()[..3], &[200, 201, 202]);
+
+        let surface = backend.create_surface(6, 2, SurfaceFormat::P010).unwrap();
+        assert!(backend.export(&shared, 8, &surface).is_err());
+        backend.export(&shared, 10, &surface).unwrap();
+        let data = backend.read_surface(&surface).unwrap();
+        assert_eq!(&data[..4], &[0, 0, 1 << 6, 0]);
+
+        let unpacked = backend.import_surface(&surface, 10).unwrap();
+        let info = VideoInfo::new(6, 2, false, FrameType::I, Arc::new(*YUV420_10));
+        let mut out = Frame::new_default_frame(info, None);
+        backend.download(&unpacked, &mut out).unwrap();
+        assert_eq!(&out.buf.as_slice_inner(1).unwrap()[..4], &[100, 0, 101, 0]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn shared_device() {
+        let device = HwDevice::create(&WgpuDeviceBackend, DeviceSelector::Default).unwrap();
+
+        assert_eq!(device.get_type(), HwDeviceType::Wgpu);
+        assert!(device.derive(HwDeviceType::Wgpu).unwrap().ptr_eq(&device));
+        assert!(GpuBackend::from_hw_device(&device).is_ok());
+        assert!(GpuBackend::from_hw_device(&device.clone()).is_ok());
+    }
+}
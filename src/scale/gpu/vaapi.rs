@@ -0,0 +1,590 @@
+//!
+//! VAAPI devices and surfaces.
+//!
+//! libva is loaded when a device is opened. Surfaces are exported as
+//! DMA-BUF objects, one per plane, and imported as Vulkan images by the
+//! GPU backend.
+//!
+
+use std::any::Any;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use libloading::Library;
+use wgpu::hal;
+
+use crate::data::frame::{FrameBuffer, FrameError, VideoInfo};
+use crate::data::hwdevice::*;
+use crate::scale::{Error, Result};
+
+use super::{backend_error, GpuBackend, GpuFrame, SurfaceFormat, SurfaceLayout, WgpuDeviceBackend};
+
+const LIBRARY: &str = "libva.so.2";
+const DRM_LIBRARY: &str = "libva-drm.so.2";
+
+const VA_SURFACE_ATTRIB_MEM_TYPE_DRM_PRIME_2: u32 = 0x4000_0000;
+const VA_EXPORT_SURFACE_READ_ONLY: u32 = 0x0001;
+const VA_EXPORT_SURFACE_SEPARATE_LAYERS: u32 = 0x0004;
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+const FOURCC_NV12: u32 = fourcc(b"NV12");
+const FOURCC_P010: u32 = fourcc(b"P010");
+const DRM_FORMAT_R8: u32 = fourcc(b"R8  ");
+const DRM_FORMAT_GR88: u32 = fourcc(b"GR88");
+const DRM_FORMAT_R16: u32 = fourcc(b"R16 ");
+const DRM_FORMAT_GR1616: u32 = fourcc(b"GR32");
+
+type VaDisplay = *mut c_void;
+type VaStatus = c_int;
+
+struct Api {
+    terminate: unsafe extern "C" fn(VaDisplay) -> VaStatus,
+    sync_surface: unsafe extern "C" fn(VaDisplay, u32) -> VaStatus,
+    export_surface_handle: unsafe extern "C" fn(VaDisplay, u32, u32, u32, *mut c_void) -> VaStatus,
+    _drm: Library,
+    _lib: Library,
+}
+
+fn failure(what: &str, res: VaStatus) -> HwDeviceError {
+    HwDeviceError::Creation(format!("{} failing with VA status {}", what, res))
+}
+
+impl Api {
+    fn open(file: &File) -> ::std::result::Result<(Api, VaDisplay), HwDeviceError> {
+        unsafe {
+            let creation = |e: libloading::Error| HwDeviceError::Creation(e.to_string());
+            let lib = Library::new(LIBRARY).map_err(creation)?;
+            let drm = Library::new(DRM_LIBRARY).map_err(creation)?;
+
+            let get_display = *drm
+                .get::<unsafe extern "C" fn(c_int) -> VaDisplay>(b"vaGetDisplayDRM\0")
+                .map_err(creation)?;
+            let initialize = *lib
+                .get::<unsafe extern "C" fn(VaDisplay, *mut c_int, *mut c_int) -> VaStatus>(
+                    b"vaInitialize\0",
+                )
+                .map_err(creation)?;
+            let terminate = *lib
+                .get::<unsafe extern "C" fn(VaDisplay) -> VaStatus>(b"vaTerminate\0")
+                .map_err(creation)?;
+            let sync_surface = *lib
+                .get::<unsafe extern "C" fn(VaDisplay, u32) -> VaStatus>(b"vaSyncSurface\0")
+                .map_err(creation)?;
+            let export_surface_handle = *lib
+                .get::<unsafe extern "C" fn(VaDisplay, u32, u32, u32, *mut c_void) -> VaStatus>(
+                    b"vaExportSurfaceHandle\0",
+                )
+                .map_err(creation)?;
+
+            let display = get_display(file.as_raw_fd());
+            if display.is_null() {
+                return Err(HwDeviceError::Creation(
+                    "vaGetDisplayDRM failing".to_owned(),
+                ));
+            }
+            let (mut major, mut minor) = (0, 0);
+            let res = initialize(display, &mut major, &mut minor);
+            if res != 0 {
+                return Err(failure("vaInitialize", res));
+            }
+
+            Ok((
+                Api {
+                    terminate,
+                    sync_surface,
+                    export_surface_handle,
+                    _drm: drm,
+                    _lib: lib,
+                },
+                display,
+            ))
+        }
+    }
+}
+
+/// A VAAPI display, opened on a DRM render node.
+pub struct VaapiContext {
+    display: VaDisplay,
+    node: PathBuf,
+    api: Arc<Api>,
+    _file: File,
+}
+
+// libva serializes the calls made on a display.
+unsafe impl Send for VaapiContext {}
+unsafe impl Sync for VaapiContext {}
+
+impl VaapiContext {
+    /// Returns the `VADisplay` handle.
+    pub fn get_handle(&self) -> *mut c_void {
+        self.display
+    }
+
+    /// Returns the render node the display is opened on.
+    pub fn get_node(&self) -> &Path {
+        &self.node
+    }
+}
+
+impl fmt::Debug for VaapiContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VaapiContext")
+            .field("display", &self.display)
+            .field("node", &self.node)
+            .finish()
+    }
+}
+
+impl Drop for VaapiContext {
+    fn drop(&mut self) {
+        unsafe {
+            (self.api.terminate)(self.display);
+        }
+    }
+}
+
+impl HwDeviceContext for VaapiContext {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Vaapi
+    }
+    /// A wgpu device is opened on the Vulkan adapter of the render node.
+    fn derive(&self, ty: HwDeviceType) -> Option<Box<dyn HwDeviceContext>> {
+        match ty {
+            HwDeviceType::Wgpu => WgpuDeviceBackend
+                .create(&DeviceSelector::DrmNode(self.node.clone()))
+                .ok(),
+            _ => None,
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Opens VAAPI displays on DRM render nodes, through libva loaded at run
+/// time.
+///
+/// The default device is the first render node, adapter indices count
+/// the render nodes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VaapiDeviceBackend;
+
+impl HwDeviceBackend for VaapiDeviceBackend {
+    fn device_type(&self) -> HwDeviceType {
+        HwDeviceType::Vaapi
+    }
+
+    fn create(
+        &self,
+        selector: &DeviceSelector,
+    ) -> ::std::result::Result<Box<dyn HwDeviceContext>, HwDeviceError> {
+        let node = match *selector {
+            DeviceSelector::Default => PathBuf::from("/dev/dri/renderD128"),
+            DeviceSelector::DrmNode(ref node) => node.clone(),
+            DeviceSelector::AdapterIndex(idx) => {
+                PathBuf::from(format!("/dev/dri/renderD{}", 128 + idx))
+            }
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&node)
+            .map_err(|e| HwDeviceError::Creation(format!("{}: {}", node.display(), e)))?;
+        let (api, display) = Api::open(&file)?;
+
+        Ok(Box::new(VaapiContext {
+            display,
+            node,
+            api: Arc::new(api),
+            _file: file,
+        }))
+    }
+}
+
+/// Returns the PCI vendor and device identifiers of a DRM node.
+pub(super) fn pci_ids(node: &Path) -> io::Result<(u32, u32)> {
+    let node = fs::canonicalize(node)?;
+    let name = node
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a DRM node"))?;
+    let device = Path::new("/sys/class/drm").join(name).join("device");
+    let read = |file: &str| -> io::Result<u32> {
+        let id = fs::read_to_string(device.join(file))?;
+        u32::from_str_radix(id.trim().trim_start_matches("0x"), 16)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    Ok((read("vendor")?, read("device")?))
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct DrmObject {
+    fd: c_int,
+    size: u32,
+    modifier: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct DrmLayer {
+    drm_format: u32,
+    num_planes: u32,
+    object_index: [u32; 4],
+    offset: [u32; 4],
+    pitch: [u32; 4],
+}
+
+/// `VADRMPRIMESurfaceDescriptor`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct DrmPrimeDescriptor {
+    fourcc: u32,
+    width: u32,
+    height: u32,
+    num_objects: u32,
+    objects: [DrmObject; 4],
+    num_layers: u32,
+    layers: [DrmLayer; 4],
+}
+
+/// A layer of an exported surface, imported as a texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Layer {
+    object: usize,
+    offset: u64,
+    pitch: u64,
+    modifier: u64,
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+}
+
+impl DrmPrimeDescriptor {
+    /// Returns the layout of the surface and its luma and chroma layers.
+    fn layers(&self) -> Result<(SurfaceFormat, [Layer; 2])> {
+        let (format, layers) = match self.fourcc {
+            FOURCC_NV12 => (
+                SurfaceFormat::Nv12,
+                [
+                    (DRM_FORMAT_R8, wgpu::TextureFormat::R8Uint),
+                    (DRM_FORMAT_GR88, wgpu::TextureFormat::Rg8Uint),
+                ],
+            ),
+            FOURCC_P010 => (
+                SurfaceFormat::P010,
+                [
+                    (DRM_FORMAT_R16, wgpu::TextureFormat::R16Uint),
+                    (DRM_FORMAT_GR1616, wgpu::TextureFormat::Rg16Uint),
+                ],
+            ),
+            fourcc => {
+                return Err(Error::Unsupported(format!(
+                    "VAAPI surfaces of fourcc {:#010x}",
+                    fourcc
+                )))
+            }
+        };
+        if self.num_layers != 2 {
+            return Err(Error::InvalidData);
+        }
+
+        let mut out = [None; 2];
+        for (idx, ((drm_format, tex_format), layer)) in layers.iter().zip(&self.layers).enumerate()
+        {
+            let object = layer.object_index[0] as usize;
+            if layer.drm_format != *drm_format
+                || layer.num_planes != 1
+                || object >= (self.num_objects as usize).min(self.objects.len())
+            {
+                return Err(Error::InvalidData);
+            }
+            let sub = idx as u32;
+            out[idx] = Some(Layer {
+                object,
+                offset: u64::from(layer.offset[0]),
+                pitch: u64::from(layer.pitch[0]),
+                modifier: self.objects[object].modifier,
+                format: *tex_format,
+                size: wgpu::Extent3d {
+                    width: (self.width + sub) >> sub,
+                    height: (self.height + sub) >> sub,
+                    depth_or_array_layers: 1,
+                },
+            });
+        }
+
+        Ok((format, [out[0].unwrap(), out[1].unwrap()]))
+    }
+}
+
+/// A surface exported as DMA-BUF objects.
+struct Exported {
+    desc: DrmPrimeDescriptor,
+    fds: Vec<OwnedFd>,
+}
+
+/// A frame in a surface of a VAAPI display.
+///
+/// The surface is owned by the producer of the frame, which has to keep it
+/// alive while the frame is in use.
+#[derive(Clone, Debug)]
+pub struct VaapiFrame {
+    device: HwDevice,
+    surface: u32,
+}
+
+impl VaapiFrame {
+    /// Wraps a `VASurfaceID` of the display of `device`.
+    ///
+    /// # Safety
+    ///
+    /// The surface must hold a frame of the size of the `VideoInfo` of the
+    /// frame using it and outlive it.
+    pub unsafe fn new(device: HwDevice, surface: u32) -> Self {
+        VaapiFrame { device, surface }
+    }
+
+    /// Returns the `VASurfaceID` of the frame.
+    pub fn get_surface(&self) -> u32 {
+        self.surface
+    }
+
+    /// Exports the surface once the operations rendering it are complete.
+    fn export(&self) -> Result<Exported> {
+        let ctx = self
+            .device
+            .downcast_ref::<VaapiContext>()
+            .ok_or_else(|| Error::Backend("not a VAAPI context".to_owned()))?;
+        let mut desc = DrmPrimeDescriptor::default();
+
+        unsafe {
+            let res = (ctx.api.sync_surface)(ctx.display, self.surface);
+            if res != 0 {
+                return Err(Error::Backend(format!(
+                    "vaSyncSurface failing with {}",
+                    res
+                )));
+            }
+            let res = (ctx.api.export_surface_handle)(
+                ctx.display,
+                self.surface,
+                VA_SURFACE_ATTRIB_MEM_TYPE_DRM_PRIME_2,
+                VA_EXPORT_SURFACE_READ_ONLY | VA_EXPORT_SURFACE_SEPARATE_LAYERS,
+                &mut desc as *mut DrmPrimeDescriptor as *mut c_void,
+            );
+            if res != 0 {
+                return Err(Error::Backend(format!(
+                    "vaExportSurfaceHandle failing with {}",
+                    res
+                )));
+            }
+        }
+
+        let count = (desc.num_objects as usize).min(desc.objects.len());
+        let fds = desc.objects[..count]
+            .iter()
+            .map(|object| unsafe { OwnedFd::from_raw_fd(object.fd) })
+            .collect();
+
+        Ok(Exported { desc, fds })
+    }
+}
+
+impl HwFrame for VaapiFrame {
+    fn get_device(&self) -> &HwDevice {
+        &self.device
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The planes of a `VaapiFrame` are not accessible from the CPU.
+impl FrameBuffer for VaapiFrame {
+    fn linesize(&self, _: usize) -> ::std::result::Result<usize, FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn count(&self) -> usize {
+        2
+    }
+    fn as_slice_inner(&self, _: usize) -> ::std::result::Result<&[u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_mut_slice_inner(&mut self, _: usize) -> ::std::result::Result<&mut [u8], FrameError> {
+        Err(FrameError::InvalidConversion)
+    }
+    fn as_hw_frame(&self) -> Option<&dyn HwFrame> {
+        Some(self)
+    }
+}
+
+impl GpuBackend {
+    /// Imports the layers of a VAAPI surface as Vulkan images and copies
+    /// them to the planes of a `GpuFrame`.
+    pub(super) fn import_vaapi(
+        &self,
+        frame: &VaapiFrame,
+        info: &VideoInfo,
+        depth: u8,
+    ) -> Result<GpuFrame> {
+        let exported = frame.export()?;
+        let (format, layers) = exported.desc.layers()?;
+        let layout = SurfaceLayout::new(info.width, info.height, format);
+        let (width, height) = (info.width as u32, info.height as u32);
+        if width > layers[0].size.width || height > layers[0].size.height {
+            return Err(Error::InvalidData);
+        }
+
+        let textures = {
+            let hal_device = unsafe { self.device.as_hal::<hal::api::Vulkan>() }
+                .ok_or_else(|| Error::Unsupported("VAAPI frames without Vulkan".to_owned()))?;
+
+            layers
+                .iter()
+                .map(|layer| {
+                    let fd = exported.fds[layer.object]
+                        .try_clone()
+                        .map_err(backend_error)?;
+                    let desc = hal::TextureDescriptor {
+                        label: Some("vaapi"),
+                        size: layer.size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: layer.format,
+                        usage: wgpu::TextureUses::COPY_SRC,
+                        memory_flags: hal::MemoryFlags::empty(),
+                        view_formats: Vec::new(),
+                    };
+                    let raw = unsafe {
+                        hal_device.texture_from_dmabuf_fd(
+                            fd,
+                            &desc,
+                            layer.modifier,
+                            layer.pitch,
+                            layer.offset,
+                        )
+                    }
+                    .map_err(backend_error)?;
+
+                    // The image is created in the undefined layout, its
+                    // content being left untouched by the first barrier.
+                    Ok(unsafe {
+                        self.device.create_texture_from_hal::<hal::api::Vulkan>(
+                            raw,
+                            &wgpu::TextureDescriptor {
+                                label: Some("vaapi"),
+                                size: layer.size,
+                                mip_level_count: 1,
+                                sample_count: 1,
+                                dimension: wgpu::TextureDimension::D2,
+                                format: layer.format,
+                                usage: wgpu::TextureUsages::COPY_SRC,
+                                view_formats: &[],
+                            },
+                            wgpu::TextureUses::UNINITIALIZED,
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        self.import_textures(
+            [
+                (&textures[0], wgpu::TextureAspect::All),
+                (&textures[1], wgpu::TextureAspect::All),
+            ],
+            &layout,
+            depth,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(fourcc: u32, formats: [u32; 2], pitch: u32) -> DrmPrimeDescriptor {
+        let mut desc = DrmPrimeDescriptor {
+            fourcc,
+            width: 1920,
+            height: 1080,
+            num_objects: 1,
+            num_layers: 2,
+            ..Default::default()
+        };
+        desc.objects[0].modifier = 0x0100_0000_0000_0002;
+        for (layer, &format) in desc.layers.iter_mut().zip(&formats) {
+            layer.drm_format = format;
+            layer.num_planes = 1;
+            layer.pitch[0] = pitch;
+        }
+        desc.layers[1].offset[0] = pitch * 1088;
+        desc
+    }
+
+    #[test]
+    fn nv12_layers() {
+        let desc = descriptor(FOURCC_NV12, [DRM_FORMAT_R8, DRM_FORMAT_GR88], 2048);
+        let (format, [luma, chroma]) = desc.layers().unwrap();
+
+        assert_eq!(format, SurfaceFormat::Nv12);
+        assert_eq!(luma.format, wgpu::TextureFormat::R8Uint);
+        assert_eq!((luma.size.width, luma.size.height), (1920, 1080));
+        assert_eq!((luma.offset, luma.pitch), (0, 2048));
+        assert_eq!(chroma.format, wgpu::TextureFormat::Rg8Uint);
+        assert_eq!((chroma.size.width, chroma.size.height), (960, 540));
+        assert_eq!((chroma.offset, chroma.pitch), (2048 * 1088, 2048));
+        assert_eq!(chroma.object, 0);
+        assert_eq!(chroma.modifier, 0x0100_0000_0000_0002);
+    }
+
+    #[test]
+    fn p010_layers() {
+        let mut desc = descriptor(FOURCC_P010, [DRM_FORMAT_R16, DRM_FORMAT_GR1616], 4096);
+        desc.width = 1279;
+        desc.height = 719;
+        desc.num_objects = 2;
+        desc.objects[1].fd = 4;
+        desc.layers[1].object_index[0] = 1;
+        desc.layers[1].offset[0] = 0;
+        let (format, [luma, chroma]) = desc.layers().unwrap();
+
+        assert_eq!(format, SurfaceFormat::P010);
+        assert_eq!(luma.format, wgpu::TextureFormat::R16Uint);
+        assert_eq!(chroma.format, wgpu::TextureFormat::Rg16Uint);
+        assert_eq!((chroma.size.width, chroma.size.height), (640, 360));
+        assert_eq!((chroma.object, chroma.offset), (1, 0));
+    }
+
+    #[test]
+    fn invalid_layers() {
+        let nv12 = descriptor(FOURCC_NV12, [DRM_FORMAT_R8, DRM_FORMAT_GR88], 2048);
+
+        let mut desc = nv12;
+        desc.fourcc = fourcc(b"YUYV");
+        assert!(matches!(desc.layers(), Err(Error::Unsupported(_))));
+
+        // Composed layers are not requested.
+        let mut desc = nv12;
+        desc.num_layers = 1;
+        desc.layers[0].num_planes = 2;
+        assert!(desc.layers().is_err());
+
+        let mut desc = nv12;
+        desc.layers[1].drm_format = DRM_FORMAT_GR1616;
+        assert!(desc.layers().is_err());
+
+        let mut desc = nv12;
+        desc.layers[1].object_index[0] = 1;
+        assert!(desc.layers().is_err());
+    }
+}
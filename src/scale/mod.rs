@@ -1 +1,360 @@
+//!
+//! Image scaling.
+//!
+//! A `Scaler` resizes video frames through a `Backend`, keeping their
+//! pixel format or converting them to another one. The CPU backend is
+//! always available, the GPU one is built with the `gpu` feature.
+//!
 
+mod convert;
+pub mod cpu;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+
+pub use self::convert::Conversion;
+
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::data::frame::{Frame, MediaKind, VideoInfo};
+use crate::data::imgutils;
+use crate::data::pixel::Formaton;
+use crate::filter::tonemap::Curve;
+
+/// General scaling errors.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid input data.
+    #[error("Invalid Data")]
+    InvalidData,
+    /// Unsupported requested feature.
+    #[error("Unsupported feature {0}")]
+    Unsupported(String),
+    /// The backend failed to perform the operation.
+    #[error("Backend error: {0}")]
+    Backend(String),
+}
+
+/// A specialised `Result` type for scaling operations.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Geometry of an image plane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaneInfo {
+    /// Width in samples.
+    pub width: usize,
+    /// Height in lines.
+    pub height: usize,
+    /// Size of a sample in bytes, either 1 or 2.
+    pub sample_size: usize,
+    /// Tells if 2-byte samples are stored as big-endian.
+    pub be: bool,
+}
+
+impl PlaneInfo {
+    /// Returns the geometry of the planes of a planar image.
+    pub fn from_video_info(info: &VideoInfo) -> Result<Vec<PlaneInfo>> {
+        let fmt = &info.format;
+        check_format(fmt)?;
+
+        Ok((0..imgutils::get_num_planes(fmt))
+            .map(|plane| PlaneInfo {
+                width: imgutils::get_plane_width(fmt, plane, info.width),
+                height: imgutils::get_plane_height(fmt, plane, info.height),
+                sample_size: imgutils::get_plane_sample_size(fmt, plane),
+                be: fmt.is_be(),
+            })
+            .collect())
+    }
+}
+
+fn check_format(fmt: &Formaton) -> Result<()> {
+    let planar = imgutils::get_num_planes(fmt) == fmt.get_num_comp();
+    let sample_sizes =
+        (0..fmt.get_num_comp()).all(|plane| imgutils::get_plane_sample_size(fmt, plane) <= 2);
+
+    if fmt.is_paletted() || !planar || !sample_sizes {
+        return Err(Error::Unsupported(format!("format {}", fmt)));
+    }
+    Ok(())
+}
+
+/// Used to implement a scaling backend.
+pub trait Backend: Send {
+    /// Scales every plane of `src` into `dst`.
+    ///
+    /// The frames share the same pixel format, `src_planes` and
+    /// `dst_planes` describe their plane geometry.
+    fn scale(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+    ) -> Result<()>;
+    /// Scales every plane of `src` into `dst`, converting the samples to
+    /// the pixel format of `dst` as described by `conv`.
+    ///
+    /// `src_planes` and `dst_planes` describe the plane geometry of the
+    /// frames.
+    fn convert(
+        &mut self,
+        src: &Frame,
+        src_planes: &[PlaneInfo],
+        dst: &mut Frame,
+        dst_planes: &[PlaneInfo],
+        conv: &Conversion,
+    ) -> Result<()>;
+}
+
+/// Frame scaler.
+///
+/// Planar formats of up to 16 bits per sample are supported, samples are
+/// interpolated bilinearly. Planar Y'CbCr frames may be converted to
+/// another planar Y'CbCr format.
+pub struct Scaler {
+    width: usize,
+    height: usize,
+    format: Option<Arc<Formaton>>,
+    curve: Curve,
+    backend: Box<dyn Backend>,
+}
+
+impl Scaler {
+    /// Creates a new scaler producing frames of the given size on the CPU.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_backend(width, height, Box::new(cpu::CpuBackend))
+    }
+
+    /// Creates a new scaler producing frames of the given size with the
+    /// given backend.
+    pub fn with_backend(width: usize, height: usize, backend: Box<dyn Backend>) -> Self {
+        Scaler {
+            width,
+            height,
+            format: None,
+            curve: Curve::Bt2390,
+            backend,
+        }
+    }
+
+    /// Returns the size of the output frames.
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Sets the pixel format of the output frames.
+    ///
+    /// By default the frames keep their pixel format.
+    pub fn set_format(&mut self, format: Arc<Formaton>) {
+        self.format = Some(format);
+    }
+
+    /// Returns the pixel format of the output frames, if set.
+    pub fn get_format(&self) -> Option<&Arc<Formaton>> {
+        self.format.as_ref()
+    }
+
+    /// Sets the curve tone mapping HDR frames converted to an SDR format.
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.curve = curve;
+    }
+
+    /// Scales a frame.
+    pub fn scale(&mut self, src: &Frame) -> Result<Frame> {
+        let src_info = match src.kind {
+            MediaKind::Video(ref info) => info,
+            MediaKind::Audio(_) => return Err(Error::Unsupported("audio frames".to_owned())),
+        };
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::InvalidData);
+        }
+
+        let mut dst_info = src_info.clone();
+        dst_info.width = self.width;
+        dst_info.height = self.height;
+
+        let conv = match self.format {
+            Some(ref format) if **format != *src_info.format => {
+                let conv = Conversion::new(src_info, format, self.curve)?;
+                dst_info.format = format.clone();
+                dst_info.bits = format.get_total_depth();
+                if matches!(conv.transform, convert::Transform::Tonemap(_)) {
+                    dst_info.hdr = Default::default();
+                }
+                Some(conv)
+            }
+            _ => None,
+        };
+
+        let src_planes = PlaneInfo::from_video_info(src_info)?;
+        let dst_planes = PlaneInfo::from_video_info(&dst_info)?;
+
+        let mut dst = Frame::new_default_frame(dst_info, Some(src.t.clone()));
+        dst.metadata = src.metadata.clone();
+
+        match conv {
+            Some(ref conv) if !conv.is_passthrough() => {
+                self.backend
+                    .convert(src, &src_planes, &mut dst, &dst_planes, conv)?
+            }
+            _ => self
+                .backend
+                .scale(src, &src_planes, &mut dst, &dst_planes)?,
+        }
+
+        Ok(dst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::frame::FrameType;
+    use crate::data::pixel::formats::{RGB24, YUV420, YUV420_10, YUV444, YUV444_10};
+    use crate::data::pixel::*;
+    use std::sync::Arc;
+
+    /// Returns a frame of the given format filled with a single color.
+    pub(crate) fn solid(fmt: Formaton, w: usize, h: usize, color: [u16; 3]) -> Frame {
+        let info = VideoInfo::new(w, h, false, FrameType::I, Arc::new(fmt));
+        let mut frame = Frame::new_default_frame(info, None);
+        let wide = fmt.get_chromaton(0).unwrap().get_depth() > 8;
+        for (plane, &v) in color.iter().enumerate() {
+            let data = frame.buf.as_mut_slice_inner(plane).unwrap();
+            if wide {
+                for s in data.chunks_exact_mut(2) {
+                    s.copy_from_slice(&v.to_le_bytes());
+                }
+            } else {
+                data.iter_mut().for_each(|s| *s = v as u8);
+            }
+        }
+        frame
+    }
+
+    /// Returns the first sample of each color plane.
+    pub(crate) fn first(frame: &Frame) -> [u16; 3] {
+        let info = frame.kind.get_video_info().unwrap();
+        let wide = info.format.get_chromaton(0).unwrap().get_depth() > 8;
+        let mut out = [0; 3];
+        for (plane, v) in out.iter_mut().enumerate() {
+            let data = frame.buf.as_slice_inner(plane).unwrap();
+            *v = if wide {
+                u16::from_le_bytes([data[0], data[1]])
+            } else {
+                u16::from(data[0])
+            };
+        }
+        out
+    }
+
+    fn close(a: [u16; 3], b: [u16; 3]) -> bool {
+        a.iter()
+            .zip(&b)
+            .all(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() <= 1)
+    }
+
+    #[test]
+    fn planes() {
+        let info = VideoInfo::new(16, 8, false, FrameType::I, Arc::new(*YUV420));
+        let planes = PlaneInfo::from_video_info(&info).unwrap();
+        assert_eq!(planes.len(), 3);
+        assert_eq!((planes[0].width, planes[0].height), (16, 8));
+        assert_eq!((planes[1].width, planes[1].height), (8, 4));
+
+        let info = VideoInfo::new(16, 8, false, FrameType::I, Arc::new(*RGB24));
+        assert!(PlaneInfo::from_video_info(&info).is_err());
+    }
+
+    #[test]
+    fn convert_depth_and_range() {
+        let src = solid(*YUV420, 8, 8, [235, 128, 128]);
+
+        let mut scaler = Scaler::new(4, 4);
+        scaler.set_format(Arc::new(*YUV444_10));
+        let out = scaler.scale(&src).unwrap();
+        let info = out.kind.get_video_info().unwrap();
+        assert_eq!(*info.format, *YUV444_10);
+        assert_eq!(info.bits, YUV444_10.get_total_depth());
+        let planes = PlaneInfo::from_video_info(&info).unwrap();
+        assert_eq!((planes[1].width, planes[1].height), (4, 4));
+        assert_eq!(first(&out), [940, 512, 512]);
+
+        let full = Formaton {
+            model: ColorModel::Trichromatic(TrichromaticEncodingSystem::YUV(YUVSystem::YCbCr(
+                YUVRange::Full,
+            ))),
+            ..*YUV420
+        };
+        scaler.set_format(Arc::new(full));
+        assert_eq!(first(&scaler.scale(&src).unwrap()), [255, 128, 128]);
+        let black = solid(*YUV420, 8, 8, [16, 128, 128]);
+        assert_eq!(first(&scaler.scale(&black).unwrap()), [0, 128, 128]);
+    }
+
+    #[test]
+    fn convert_matrix() {
+        let bt601 = Formaton {
+            matrix: MatrixCoefficients::ST170M,
+            ..*YUV444
+        };
+        let bt709 = Formaton {
+            matrix: MatrixCoefficients::BT709,
+            ..*YUV444
+        };
+        let red = solid(bt601, 4, 4, [81, 90, 240]);
+
+        let mut scaler = Scaler::new(4, 4);
+        scaler.set_format(Arc::new(bt709));
+        assert!(close(first(&scaler.scale(&red).unwrap()), [63, 102, 240]));
+
+        // Unspecified matrices are taken as the same.
+        scaler.set_format(Arc::new(*YUV444));
+        assert_eq!(first(&scaler.scale(&red).unwrap()), [81, 90, 240]);
+    }
+
+    #[test]
+    fn convert_tonemap() {
+        let pq = Formaton {
+            primaries: ColorPrimaries::BT2020,
+            xfer: TransferCharacteristic::PerceptualQuantizer,
+            matrix: MatrixCoefficients::BT2020NonConstantLuminance,
+            ..*YUV420_10
+        };
+        let sdr = Formaton {
+            primaries: ColorPrimaries::BT709,
+            xfer: TransferCharacteristic::BT1886,
+            matrix: MatrixCoefficients::BT709,
+            ..*YUV420
+        };
+        let src = solid(pq, 8, 8, [64, 512, 512]);
+
+        let mut scaler = Scaler::new(8, 8);
+        scaler.set_format(Arc::new(sdr));
+        let out = scaler.scale(&src).unwrap();
+        assert_eq!(first(&out), [16, 128, 128]);
+        assert!(out.kind.get_video_info().unwrap().hdr.is_empty());
+
+        let bright = scaler.scale(&solid(pq, 8, 8, [700, 512, 512])).unwrap();
+        let y = first(&bright)[0];
+        assert!(y > 16 && y <= 235);
+    }
+
+    #[test]
+    fn convert_unsupported() {
+        let src = solid(*YUV420, 8, 8, [16, 128, 128]);
+        let mut scaler = Scaler::new(8, 8);
+
+        scaler.set_format(Arc::new(*RGB24));
+        assert!(scaler.scale(&src).is_err());
+
+        let pq = Formaton {
+            xfer: TransferCharacteristic::PerceptualQuantizer,
+            ..*YUV420
+        };
+        scaler.set_format(Arc::new(pq));
+        assert!(scaler.scale(&src).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//!
+//! Test vectors.
+//!
+//! Tiny sample files generated deterministically for the muxers storing a
+//! codec encoded here: one second of color bars, of a 1 kHz tone or of
+//! both. They exercise the muxers and demuxers without resorting to
+//! real-world samples, and let users check their players.
+//!
+//! The video is stored as packed RGB24 `rawvideo` or as a lossless `vp8l`
+//! still image, the audio as 16 bit PCM.
+//!
+
+use std::sync::Arc;
+
+use crate::data::audiosample::ChannelMap;
+use crate::data::metadata::Metadata;
+use crate::data::packet::Packet;
+use crate::data::params::{AudioInfo, CodecParams, MediaKind, VideoInfo};
+use crate::data::pixel::formats::RGB24;
+use crate::data::timeinfo::{Duration, Timestamp};
+use crate::examples::MemoryOutput;
+use crate::format::common::GlobalInfo;
+use crate::format::error::*;
+use crate::format::muxer::{self, Capabilities};
+use crate::format::stream::Stream;
+use crate::format::{aiff, avif, nut, ogg, webp};
+use crate::rational::Rational64;
+
+/// Width of the color bars.
+pub const WIDTH: usize = 32;
+/// Height of the color bars.
+pub const HEIGHT: usize = 24;
+/// Frame rate of the color bars.
+pub const FRAME_RATE: usize = 25;
+/// Sample rate of the tone.
+pub const SAMPLE_RATE: usize = 8000;
+
+/// The muxers of the crate.
+pub const MUXERS: &[&dyn muxer::Descriptor] = &[
+    aiff::AIFF_MUXER_DESCR,
+    avif::AVIF_MUXER_DESCR,
+    nut::muxer::NUT_MUXER_DESCR,
+    ogg::opus::OPUS_DESCR,
+    webp::WEBP_MUXER_DESCR,
+];
+
+/// Video codecs encoded here, by order of preference.
+const VIDEO_CODECS: &[&str] = &["rawvideo", "vp8l"];
+/// Audio codecs encoded here, by order of preference.
+const AUDIO_CODECS: &[&str] = &["pcm_s16le", "pcm_s16be"];
+
+/// The 75% color bars, from white to black.
+const BARS: [[u8; 3]; 8] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+    [0, 0, 0],
+];
+
+/// A period of a 1 kHz sine at 8 kHz, at half the full scale.
+const SINE: [i16; 8] = [0, 11585, 16384, 11585, 0, -11585, -16384, -11585];
+
+/// Returns packed RGB24 color bars.
+pub fn color_bars(width: usize, height: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width * height * 3);
+    for _ in 0..height {
+        for x in 0..width {
+            data.extend_from_slice(&BARS[x * BARS.len() / width]);
+        }
+    }
+    data
+}
+
+/// Returns samples of a 1 kHz tone at 8 kHz.
+pub fn tone(count: usize) -> Vec<i16> {
+    (0..count).map(|i| SINE[i % SINE.len()]).collect()
+}
+
+/// Writes bits from the least significant one, as VP8L reads them.
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, bits: u32) {
+        self.bits |= u64::from(value) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.data.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.data.push(self.bits as u8);
+        }
+        self.data
+    }
+}
+
+/// Writes a simple prefix code of one or two symbols, returning the code
+/// of each symbol.
+fn simple_code(w: &mut BitWriter, symbols: &[u8]) -> Vec<(u8, u32, u32)> {
+    w.put(1, 1);
+    w.put(symbols.len() as u32 - 1, 1);
+    let first = u32::from(symbols[0]);
+    if first < 2 {
+        w.put(0, 1);
+        w.put(first, 1);
+    } else {
+        w.put(1, 1);
+        w.put(first, 8);
+    }
+    match *symbols {
+        [symbol] => vec![(symbol, 0, 0)],
+        [first, second] => {
+            w.put(u32::from(second), 8);
+            // The canonical codes follow the order of the symbols.
+            vec![(first.min(second), 0, 1), (first.max(second), 1, 1)]
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Encodes an opaque RGB24 image as a VP8L bitstream.
+///
+/// Each of the channels may take two values at most, as in color bars.
+fn vp8l(width: usize, height: usize, rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut w = BitWriter::default();
+    w.put(0x2f, 8);
+    w.put(width as u32 - 1, 14);
+    w.put(height as u32 - 1, 14);
+    // No alpha, version 0, no transform, no color cache, no meta codes.
+    w.put(0, 1);
+    w.put(0, 3);
+    w.put(0, 1);
+    w.put(0, 1);
+    w.put(0, 1);
+
+    // The codes are written in the order green, red, blue, then alpha
+    // and distance, each taking a single value.
+    let mut codes = Vec::new();
+    for &channel in &[1, 0, 2] {
+        let mut symbols: Vec<u8> = rgb.iter().skip(channel).step_by(3).copied().collect();
+        symbols.sort_unstable();
+        symbols.dedup();
+        if symbols.len() > 2 {
+            return Err(Error::Unsupported("image with many colors".to_owned()));
+        }
+        codes.push(simple_code(&mut w, &symbols));
+    }
+    simple_code(&mut w, &[255]);
+    simple_code(&mut w, &[0]);
+
+    for pixel in rgb.chunks_exact(3) {
+        for (code, &channel) in codes.iter().zip(&[1, 0, 2]) {
+            let &(_, value, bits) = code
+                .iter()
+                .find(|&&(symbol, ..)| symbol == pixel[channel])
+                .unwrap();
+            w.put(value, bits);
+        }
+    }
+
+    Ok(w.finish())
+}
+
+fn video_params(codec_id: &str) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Video(VideoInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            format: Some(Arc::new(*RGB24)),
+        })),
+        codec_id: Some(codec_id.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+fn audio_params(codec_id: &str) -> CodecParams {
+    CodecParams {
+        kind: Some(MediaKind::Audio(AudioInfo {
+            rate: SAMPLE_RATE,
+            map: Some(ChannelMap::default_map(1)),
+            format: None,
+        })),
+        codec_id: Some(codec_id.to_owned()),
+        extradata: None,
+        bit_rate: 0,
+        convergence_window: 0,
+        delay: 0,
+    }
+}
+
+/// Returns the first codec encoded here the muxer can store.
+fn pick(caps: &Capabilities, codecs: &[&'static str]) -> Option<&'static str> {
+    codecs
+        .iter()
+        .copied()
+        .find(|&codec_id| caps.supports_codec(codec_id))
+}
+
+/// Generates the test vector of a muxer.
+///
+/// Returns `Error::Unsupported` if the muxer stores none of the codecs
+/// encoded here.
+pub fn generate(descr: &dyn muxer::Descriptor) -> Result<Vec<u8>> {
+    let caps = descr.capabilities();
+    let max_streams = caps.max_streams.unwrap_or(usize::MAX);
+    let video = pick(caps, VIDEO_CODECS);
+    let audio = pick(caps, AUDIO_CODECS).filter(|_| video.is_none() || max_streams > 1);
+    if video.is_none() && audio.is_none() {
+        return Err(Error::Unsupported(format!(
+            "test vector for {}",
+            descr.describe().name
+        )));
+    }
+
+    let mut info = GlobalInfo {
+        duration: None,
+        timebase: None,
+        streams: Vec::new(),
+        metadata: Metadata::new(),
+        groups: Vec::new(),
+    };
+    info.metadata.insert("title", "av test vector".to_owned());
+
+    let bars = color_bars(WIDTH, HEIGHT);
+    let samples = SAMPLE_RATE / FRAME_RATE;
+    let mut packets = Vec::new();
+    if let Some(codec_id) = video {
+        let timebase = Rational64::new(1, FRAME_RATE as i64);
+        let mut st = Stream::from_params(&video_params(codec_id), timebase);
+        // A still image is a single frame.
+        let (data, frames) = match codec_id {
+            "vp8l" => (vp8l(WIDTH, HEIGHT, &bars)?, 1),
+            _ => (bars, FRAME_RATE),
+        };
+        st.duration = Some(frames as u64);
+        let index = info.add_stream(st);
+        for i in 0..frames {
+            let mut pkt = Packet::new();
+            pkt.data = data.clone();
+            pkt.stream_index = index as isize;
+            pkt.t.set_pts(Some(Timestamp::new(i as i64, timebase)))?;
+            pkt.t.set_duration(Some(Duration::new(1, timebase)))?;
+            pkt.is_key = true;
+            packets.push((i * samples, pkt));
+        }
+    }
+    if let Some(codec_id) = audio {
+        let timebase = Rational64::new(1, SAMPLE_RATE as i64);
+        let mut st = Stream::from_params(&audio_params(codec_id), timebase);
+        st.duration = Some(SAMPLE_RATE as u64);
+        let index = info.add_stream(st);
+        let tone = tone(SAMPLE_RATE);
+        for (i, chunk) in tone.chunks(samples).enumerate() {
+            let mut pkt = Packet::with_capacity(chunk.len() * 2);
+            for sample in chunk {
+                match codec_id {
+                    "pcm_s16be" => pkt.data.extend_from_slice(&sample.to_be_bytes()),
+                    _ => pkt.data.extend_from_slice(&sample.to_le_bytes()),
+                }
+            }
+            pkt.stream_index = index as isize;
+            pkt.t
+                .set_pts(Some(Timestamp::new((i * samples) as i64, timebase)))?;
+            pkt.t
+                .set_duration(Some(Duration::new(chunk.len() as u64, timebase)))?;
+            pkt.is_key = true;
+            packets.push((i * samples, pkt));
+        }
+    }
+    // Interleaves the streams, by time then by index.
+    packets.sort_by_key(|(time, pkt)| (*time, pkt.stream_index));
+
+    let output = MemoryOutput::default();
+    let mut muxer = muxer::Context::from_descriptor(descr, Box::new(output.clone()));
+    muxer.set_global_info(info)?;
+    muxer.configure()?;
+    muxer.write_header()?;
+    for (_, pkt) in packets {
+        muxer.write_packet(Arc::new(pkt))?;
+    }
+    muxer.write_trailer()?;
+
+    Ok(output.get_data())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::buffer::AccReader;
+    use crate::format::demuxer::{self, Context, Event};
+    use std::io::Cursor;
+
+    fn demux(descr: &dyn demuxer::Descriptor, file: Vec<u8>) -> (GlobalInfo, Vec<Packet>) {
+        let r = AccReader::new(Cursor::new(file));
+        let mut c = Context::new(descr.create(), Box::new(r));
+        c.read_headers().unwrap();
+        let mut packets = Vec::new();
+        loop {
+            match c.read_event().unwrap() {
+                Event::NewPacket(pkt) => packets.push(pkt),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        (c.info, packets)
+    }
+
+    fn size(packets: &[Packet], index: isize) -> usize {
+        packets
+            .iter()
+            .filter(|pkt| pkt.stream_index == index)
+            .map(|pkt| pkt.data.len())
+            .sum()
+    }
+
+    #[test]
+    fn signals() {
+        let bars = color_bars(16, 1);
+        assert_eq!(&bars[..6], &[191, 191, 191, 191, 191, 191]);
+        assert_eq!(&bars[45..], &[0, 0, 0]);
+        assert_eq!(tone(10)[8..], [0, 11585]);
+    }
+
+    #[test]
+    fn vp8l_bitstream() {
+        let data = vp8l(8, 1, &color_bars(8, 1)).unwrap();
+        // Header, three codes of two symbols, the alpha and distance codes,
+        // then 3 bits a pixel: 118 bits.
+        assert_eq!(data.len(), 15);
+        assert_eq!(&data[..5], &[0x2f, 0x07, 0x00, 0x00, 0x00]);
+        assert!(vp8l(2, 1, &[0, 0, 0, 1, 2, 3]).is_ok());
+        assert!(vp8l(3, 1, &[0, 0, 0, 1, 1, 1, 2, 2, 2]).is_err());
+    }
+
+    #[test]
+    fn deterministic() {
+        for &descr in MUXERS {
+            let first = generate(descr).map_err(|err| err.to_string());
+            assert_eq!(first, generate(descr).map_err(|err| err.to_string()));
+        }
+    }
+
+    #[test]
+    fn aiff() {
+        let file = generate(aiff::AIFF_MUXER_DESCR).unwrap();
+        let (info, packets) = demux(aiff::AIFF_DEMUXER_DESCR, file);
+        assert_eq!(info.streams.len(), 1);
+        assert_eq!(size(&packets, 0), SAMPLE_RATE * 2);
+    }
+
+    #[test]
+    fn nut() {
+        let file = generate(nut::muxer::NUT_MUXER_DESCR).unwrap();
+        let (info, packets) = demux(nut::demuxer::NUT_DEMUXER_DESCR, file);
+        assert_eq!(info.streams.len(), 2);
+        let params = &info.streams[0].params;
+        assert_eq!(params.codec_id.as_deref(), Some("rawvideo"));
+        assert_eq!(size(&packets, 0), WIDTH * HEIGHT * 3 * FRAME_RATE);
+        let params = &info.streams[1].params;
+        assert_eq!(params.codec_id.as_deref(), Some("pcm_s16le"));
+        assert_eq!(size(&packets, 1), SAMPLE_RATE * 2);
+    }
+
+    #[test]
+    fn webp() {
+        let file = generate(webp::WEBP_MUXER_DESCR).unwrap();
+        let (info, packets) = demux(webp::WEBP_DEMUXER_DESCR, file);
+        match info.streams[0].params.kind {
+            Some(MediaKind::Video(ref video)) => {
+                assert_eq!((video.width, video.height), (WIDTH, HEIGHT))
+            }
+            _ => panic!("no video stream"),
+        }
+        assert_eq!(packets.len(), 1);
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(matches!(
+            generate(avif::AVIF_MUXER_DESCR),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            generate(ogg::opus::OPUS_DESCR),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}